@@ -12,6 +12,93 @@ export function set_frame_load_callback(data_url, in_msg2, jscallback) {
     };
 }
 
+// The video streams being played, keyed by the id of their canvas.
+let video_streams = {};
+
+export function set_video_chunk_callback(css_id, mime_type, data_base64, pts_secs, keyframe, in_msg2, jscallback) {
+    let stream = video_streams[css_id];
+    if (typeof stream == "undefined" || stream.mime_type != mime_type) {
+        // The video element is never shown. Its frames are drawn onto the
+        // canvas in do_frame_loaded().
+        let video = document.createElement("video");
+        video.muted = true;
+        let media_source = new MediaSource();
+        video.src = URL.createObjectURL(media_source);
+        stream = {
+            mime_type,
+            video,
+            source_buffer: null,
+            pending: [],
+            appended: null,
+            // Presentation times of the appended keyframes, oldest first.
+            keyframes: [],
+        };
+        media_source.addEventListener("sourceopen", function () {
+            stream.source_buffer = media_source.addSourceBuffer(mime_type);
+            stream.source_buffer.addEventListener("updateend", function () {
+                let item = stream.appended;
+                stream.appended = null;
+                if (item !== null) {
+                    show_video_frame(stream.video, item);
+                }
+                append_next_video_chunk(stream);
+            });
+            append_next_video_chunk(stream);
+        });
+        video_streams[css_id] = stream;
+    }
+
+    let data = Uint8Array.from(atob(data_base64), c => c.charCodeAt(0));
+    stream.pending.push({ data, pts_secs, keyframe, in_msg2, jscallback });
+    append_next_video_chunk(stream);
+}
+
+function append_next_video_chunk(stream) {
+    let source_buffer = stream.source_buffer;
+    if (source_buffer === null || source_buffer.updating || stream.pending.length == 0) {
+        return;
+    }
+    let item = stream.pending[0];
+
+    // Remove old video so that the buffer does not fill up. Appending
+    // continues once the removal is done. Only video before a keyframe is
+    // removed, as the frames after it cannot be decoded without it.
+    let buffered = source_buffer.buffered;
+    if (buffered.length > 0 && buffered.start(0) < item.pts_secs - 10.0) {
+        // The last keyframe at least 5 seconds before this frame.
+        let i = stream.keyframes.length - 1;
+        while (i >= 0 && stream.keyframes[i] > item.pts_secs - 5.0) {
+            i--;
+        }
+        if (i >= 0) {
+            let keep_from = stream.keyframes[i];
+            stream.keyframes.splice(0, i);
+            if (buffered.start(0) < keep_from) {
+                source_buffer.remove(0, keep_from);
+                return;
+            }
+        }
+    }
+
+    stream.pending.shift();
+    if (item.keyframe) {
+        stream.keyframes.push(item.pts_secs);
+    }
+    stream.appended = item;
+    source_buffer.appendBuffer(item.data);
+}
+
+function show_video_frame(video, item) {
+    video.addEventListener("seeked", function () {
+        let handle = {
+            img: video,
+            in_msg2: item.in_msg2,
+        };
+        item.jscallback(handle);
+    }, { once: true });
+    video.currentTime = item.pts_secs;
+}
+
 export function do_frame_loaded(max_framerate, css_id, last_frame_render_msec, handle) {
 
     // TODO:
@@ -145,6 +145,7 @@ impl Component for VideoField {
         self.measured_fps = props.measured_fps;
         if let Some(in_msg) = props.video_data.inner() {
             let data_url = in_msg.firehose_frame_data_url;
+            let video_chunk = in_msg.video_chunk;
             let mut draw_shapes = in_msg.annotations;
             if let Some(ref valid_display) = in_msg.valid_display {
                 let line_width = 5.0;
@@ -181,7 +182,18 @@ impl Component for VideoField {
             // img.set_src(&data_url);
             // img.set_onload(Some(callback2.as_ref().unchecked_ref()));
 
-            set_frame_load_callback(data_url, in_msg2, callback2);
+            match video_chunk {
+                Some(chunk) => set_video_chunk_callback(
+                    &self.css_id,
+                    chunk.mime_type,
+                    chunk.data_base64,
+                    chunk.pts_secs,
+                    chunk.keyframe,
+                    in_msg2,
+                    callback2,
+                ),
+                None => set_frame_load_callback(data_url, in_msg2, callback2),
+            }
 
             // js! {
             //     @(no_return)
@@ -247,6 +259,15 @@ impl VideoField {
 #[wasm_bindgen(module = "/src/components/video_field.js")]
 extern "C" {
     fn set_frame_load_callback(data_url: String, in_msg2: JsValue, jscallback: JsValue);
+    fn set_video_chunk_callback(
+        css_id: &str,
+        mime_type: String,
+        data_base64: String,
+        pts_secs: f64,
+        keyframe: bool,
+        in_msg2: JsValue,
+        jscallback: JsValue,
+    );
     fn do_frame_loaded(fps: f32, css_id: &str, last_frame_render_msec: f64, handle: JsValue)
         -> f64;
 }
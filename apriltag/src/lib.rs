@@ -1,5 +1,7 @@
 use std::{convert::TryInto, os::raw::c_char};
 
+pub mod pose;
+
 /// Associates array pointer destroy function to a Zarray.
 pub trait ArrayDealloc {
     // Call `apriltag_x_destroy()` for the correct array type.
@@ -382,6 +384,12 @@ impl Detection {
     pub fn center(&self) -> &[f64] {
         unsafe { &(*self.0).c }
     }
+    /// The corners of the tag in pixel coordinates.
+    ///
+    /// The corners wrap counter-clockwise around the tag.
+    pub fn corners(&self) -> &[[f64; 2]; 4] {
+        unsafe { &(*self.0).p }
+    }
 }
 
 impl std::fmt::Debug for Detection {
@@ -0,0 +1,249 @@
+//! Estimate the 6-DoF pose of a detected tag.
+//!
+//! The pose is found from the four corners of a tag with a known size and a
+//! pinhole camera model. An initial estimate is computed from the homography
+//! between the tag plane and the image, which is then refined by minimizing
+//! the reprojection error of the corners.
+//!
+//! The tag coordinate frame is the one of the apriltag library's
+//! `estimate_tag_pose()`: the origin is at the center of the tag and the
+//! corners, in the order returned by [`Detection::corners`](crate::Detection::corners),
+//! are at `(-s, s, 0)`, `(s, s, 0)`, `(s, -s, 0)` and `(-s, -s, 0)` where
+//! `s` is half the tag size.
+
+use nalgebra::{
+    Matrix2x3, Matrix2x6, Matrix3, Matrix6, Point3, Rotation3, SMatrix, SVector, UnitQuaternion,
+    Vector2, Vector3, Vector6,
+};
+
+const MAX_ITERATIONS: usize = 50;
+
+/// Parameters of a pinhole camera (in pixels).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CameraIntrinsics {
+    pub fx: f64,
+    pub fy: f64,
+    pub cx: f64,
+    pub cy: f64,
+}
+
+impl CameraIntrinsics {
+    fn project(&self, p: &Point3<f64>) -> Vector2<f64> {
+        Vector2::new(self.fx * p.x / p.z + self.cx, self.fy * p.y / p.z + self.cy)
+    }
+
+    /// Derivative of the projection with respect to the camera frame point.
+    fn project_jacobian(&self, p: &Point3<f64>) -> Matrix2x3<f64> {
+        let iz = 1.0 / p.z;
+        let iz2 = iz * iz;
+        Matrix2x3::new(
+            self.fx * iz,
+            0.0,
+            -self.fx * p.x * iz2,
+            0.0,
+            self.fy * iz,
+            -self.fy * p.y * iz2,
+        )
+    }
+}
+
+/// The pose of a tag in the camera frame.
+///
+/// A point `x` in the tag frame is at `rotation * x + translation` in the
+/// camera frame. The translation has the units of the tag size.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TagPose {
+    pub rotation: UnitQuaternion<f64>,
+    pub translation: Vector3<f64>,
+    /// Root mean square distance (in pixels) between the detected corners
+    /// and the corners projected with this pose.
+    pub reprojection_error: f64,
+}
+
+/// Estimate the pose of a tag from the (undistorted) pixel coordinates of its
+/// corners.
+///
+/// Returns `None` if the corners are degenerate, e.g. when the tag plane
+/// contains the camera center.
+pub fn estimate_tag_pose(
+    corners: &[[f64; 2]; 4],
+    tag_size: f64,
+    intrinsics: &CameraIntrinsics,
+) -> Option<TagPose> {
+    let object_points = object_points(tag_size);
+    let (rotation, translation) = initial_pose(corners, &object_points, intrinsics)?;
+    let (rotation, translation) =
+        refine_pose(corners, &object_points, intrinsics, rotation, translation);
+
+    let mut sum_sq = 0.0;
+    for (corner, pt) in corners.iter().zip(object_points.iter()) {
+        let p = Point3::from(rotation * pt.coords + translation);
+        if p.z <= 0.0 {
+            return None;
+        }
+        sum_sq += (intrinsics.project(&p) - Vector2::new(corner[0], corner[1])).norm_squared();
+    }
+    Some(TagPose {
+        rotation: UnitQuaternion::from_rotation_matrix(&rotation),
+        translation,
+        reprojection_error: (sum_sq / 4.0).sqrt(),
+    })
+}
+
+fn object_points(tag_size: f64) -> [Point3<f64>; 4] {
+    let s = tag_size / 2.0;
+    [
+        Point3::new(-s, s, 0.0),
+        Point3::new(s, s, 0.0),
+        Point3::new(s, -s, 0.0),
+        Point3::new(-s, -s, 0.0),
+    ]
+}
+
+/// Compute the pose from the homography between tag plane and image.
+fn initial_pose(
+    corners: &[[f64; 2]; 4],
+    object_points: &[Point3<f64>; 4],
+    intrinsics: &CameraIntrinsics,
+) -> Option<(Rotation3<f64>, Vector3<f64>)> {
+    // Solve for the homography (with h22 = 1) mapping tag coordinates to
+    // normalized image coordinates.
+    let mut a = SMatrix::<f64, 8, 8>::zeros();
+    let mut b = SVector::<f64, 8>::zeros();
+    for (i, (corner, pt)) in corners.iter().zip(object_points.iter()).enumerate() {
+        let x = (corner[0] - intrinsics.cx) / intrinsics.fx;
+        let y = (corner[1] - intrinsics.cy) / intrinsics.fy;
+        let (u, v) = (pt.x, pt.y);
+        let r0 = 2 * i;
+        let r1 = r0 + 1;
+        a.row_mut(r0)
+            .copy_from_slice(&[u, v, 1.0, 0.0, 0.0, 0.0, -x * u, -x * v]);
+        a.row_mut(r1)
+            .copy_from_slice(&[0.0, 0.0, 0.0, u, v, 1.0, -y * u, -y * v]);
+        b[r0] = x;
+        b[r1] = y;
+    }
+    let h = a.lu().solve(&b)?;
+    let h = Matrix3::new(h[0], h[1], h[2], h[3], h[4], h[5], h[6], h[7], 1.0);
+
+    // The homography is [r1 r2 t] up to scale.
+    let norm = h.column(0).norm() + h.column(1).norm();
+    if norm == 0.0 {
+        return None;
+    }
+    let mut scale = 2.0 / norm;
+    if h[(2, 2)] * scale < 0.0 {
+        // The tag must be in front of the camera.
+        scale = -scale;
+    }
+    let r1: Vector3<f64> = h.column(0) * scale;
+    let r2: Vector3<f64> = h.column(1) * scale;
+    let translation: Vector3<f64> = h.column(2) * scale;
+    let r = Matrix3::from_columns(&[r1, r2, r1.cross(&r2)]);
+
+    // Find the closest rotation matrix.
+    let svd = r.svd(true, true);
+    let mut u = svd.u?;
+    let v_t = svd.v_t?;
+    if (u * v_t).determinant() < 0.0 {
+        let last = -u.column(2).into_owned();
+        u.set_column(2, &last);
+    }
+    Some((Rotation3::from_matrix_unchecked(u * v_t), translation))
+}
+
+/// Minimize the reprojection error with the Levenberg-Marquardt algorithm.
+fn refine_pose(
+    corners: &[[f64; 2]; 4],
+    object_points: &[Point3<f64>; 4],
+    intrinsics: &CameraIntrinsics,
+    mut rotation: Rotation3<f64>,
+    mut translation: Vector3<f64>,
+) -> (Rotation3<f64>, Vector3<f64>) {
+    let residuals = |rotation: &Rotation3<f64>, translation: &Vector3<f64>| {
+        let mut sum_sq = 0.0;
+        let mut jtj = Matrix6::zeros();
+        let mut jtr = Vector6::zeros();
+        for (corner, pt) in corners.iter().zip(object_points.iter()) {
+            let rotated = rotation * pt.coords;
+            let p = Point3::from(rotated + translation);
+            let r = intrinsics.project(&p) - Vector2::new(corner[0], corner[1]);
+            sum_sq += r.norm_squared();
+
+            // The rotation is perturbed as exp(delta) * rotation.
+            let dp = intrinsics.project_jacobian(&p);
+            let mut j = Matrix2x6::zeros();
+            j.fixed_slice_mut::<2, 3>(0, 0)
+                .copy_from(&(dp * -rotated.cross_matrix()));
+            j.fixed_slice_mut::<2, 3>(0, 3).copy_from(&dp);
+            jtj += j.transpose() * j;
+            jtr += j.transpose() * r;
+        }
+        (sum_sq, jtj, jtr)
+    };
+
+    let mut lambda = 1e-3;
+    let (mut cost, mut jtj, mut jtr) = residuals(&rotation, &translation);
+    for _ in 0..MAX_ITERATIONS {
+        let mut damped = jtj;
+        for i in 0..6 {
+            damped[(i, i)] *= 1.0 + lambda;
+        }
+        let step = match damped.cholesky() {
+            Some(chol) => -chol.solve(&jtr),
+            None => break,
+        };
+        let delta_rotation = Rotation3::new(step.fixed_rows::<3>(0).into_owned());
+        let new_rotation = delta_rotation * rotation;
+        let new_translation = translation + step.fixed_rows::<3>(3);
+        let (new_cost, new_jtj, new_jtr) = residuals(&new_rotation, &new_translation);
+        if new_cost < cost {
+            let converged = cost - new_cost < 1e-12 * cost.max(1e-12);
+            rotation = new_rotation;
+            translation = new_translation;
+            cost = new_cost;
+            jtj = new_jtj;
+            jtr = new_jtr;
+            lambda *= 0.1;
+            if converged {
+                break;
+            }
+        } else {
+            lambda *= 10.0;
+            if lambda > 1e10 {
+                break;
+            }
+        }
+    }
+    (rotation, translation)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn synthetic_pose() {
+        let intrinsics = CameraIntrinsics {
+            fx: 800.0,
+            fy: 810.0,
+            cx: 320.0,
+            cy: 240.0,
+        };
+        let tag_size = 0.1;
+        let rotation = UnitQuaternion::from_euler_angles(0.3, -0.2, 0.4);
+        let translation = Vector3::new(0.05, -0.02, 0.6);
+
+        let mut corners = [[0.0; 2]; 4];
+        for (corner, pt) in corners.iter_mut().zip(object_points(tag_size).iter()) {
+            let p = Point3::from(rotation * pt.coords + translation);
+            let px = intrinsics.project(&p);
+            *corner = [px.x, px.y];
+        }
+
+        let pose = estimate_tag_pose(&corners, tag_size, &intrinsics).unwrap();
+        assert!(pose.reprojection_error < 1e-6);
+        assert!(pose.rotation.angle_to(&rotation) < 1e-6);
+        assert!((pose.translation - translation).norm() < 1e-6);
+    }
+}
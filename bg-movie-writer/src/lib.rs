@@ -77,9 +77,16 @@ impl BgMovieWriter {
         format_str_mkv: String,
         mkv_recording_config: ci2_remote_control::MkvRecordingConfig,
         queue_size: usize,
+        file_metadata: mkv_writer::FileMetadata,
     ) -> Self {
         let (err_tx, err_rx) = channellib::unbounded();
-        let tx = launch_runner(format_str_mkv, mkv_recording_config, queue_size, err_tx);
+        let tx = launch_runner(
+            format_str_mkv,
+            mkv_recording_config,
+            queue_size,
+            file_metadata,
+            err_tx,
+        );
         Self {
             tx,
             is_done: false,
@@ -91,6 +98,7 @@ impl BgMovieWriter {
         &mut self,
         frame: DynamicFrame,
         timestamp: chrono::DateTime<chrono::Utc>,
+        metadata: Option<mkv_writer::FrameMetadata>,
     ) -> Result<()> {
         async_err!(self.err_rx);
         if self.is_done {
@@ -99,7 +107,7 @@ impl BgMovieWriter {
                 Backtrace::capture(),
             ));
         }
-        let msg = Msg::Write((frame, timestamp));
+        let msg = Msg::Write((frame, timestamp, metadata));
         self.send(msg)
     }
 
@@ -116,7 +124,13 @@ impl BgMovieWriter {
 }
 
 enum Msg {
-    Write((DynamicFrame, chrono::DateTime<chrono::Utc>)),
+    Write(
+        (
+            DynamicFrame,
+            chrono::DateTime<chrono::Utc>,
+            Option<mkv_writer::FrameMetadata>,
+        ),
+    ),
     Finish,
 }
 
@@ -137,6 +151,7 @@ fn launch_runner(
     format_str_mkv: String,
     mkv_recording_config: ci2_remote_control::MkvRecordingConfig,
     size: usize,
+    file_metadata: mkv_writer::FileMetadata,
     err_tx: channellib::Sender<Error>,
 ) -> channellib::Sender<Msg> {
     let (tx, rx) = channellib::bounded::<Msg>(size);
@@ -150,7 +165,7 @@ fn launch_runner(
         loop {
             let msg = thread_try!(err_tx, rx.recv());
             match msg {
-                Msg::Write((frame, stamp)) => {
+                Msg::Write((frame, stamp, metadata)) => {
                     if raw.is_none() {
                         let local: chrono::DateTime<chrono::Local> =
                             stamp.with_timezone(&chrono::Local);
@@ -191,11 +206,20 @@ fn launch_runner(
 
                         raw = Some(thread_try!(
                             err_tx,
-                            mkv_writer::MkvWriter::new(f, mkv_recording_config.clone(), nv_enc)
+                            mkv_writer::MkvWriter::new_with_metadata(
+                                f,
+                                mkv_recording_config.clone(),
+                                nv_enc,
+                                file_metadata.clone(),
+                            )
                         ));
                     }
                     if let Some(ref mut r) = &mut raw {
-                        let result = match_all_dynamic_fmts!(&frame, x, r.write(x, stamp));
+                        let result = match_all_dynamic_fmts!(
+                            &frame,
+                            x,
+                            r.write_with_metadata(x, stamp, metadata)
+                        );
                         thread_try!(err_tx, result);
                     }
                 }
@@ -25,6 +25,9 @@ struct Opt {
     /// Tracking parameters TOML file.
     #[structopt(long = "tracking-params", parse(from_os_str))]
     tracking_params: Option<std::path::PathBuf>,
+    /// Also save smoothed (forward-backward) estimates
+    #[structopt(long = "smooth")]
+    smooth: bool,
 }
 
 #[tokio::main]
@@ -66,6 +69,7 @@ async fn main() -> anyhow::Result<()> {
     let mut opts = braid_offline::KalmanizeOptions::default();
     opts.start_frame = opt.start_frame;
     opts.stop_frame = opt.stop_frame;
+    opts.smooth = opt.smooth;
 
     // The user specifies an output .braidz file. But we will save initially to
     // a .braid directory. We here ensure the user's name had ".braidz"
@@ -91,6 +91,7 @@ fn to_point_info(row: &Data2dDistortedRow, idx: u8) -> NumberedRawUdpPoint {
             cur_val: row.cur_val,
             mean_val: row.mean_val,
             sumsqf_val: row.sumsqf_val,
+            blob_features: row.blob_features(),
         },
     }
 }
@@ -170,6 +171,10 @@ pub struct KalmanizeOptions {
     pub start_frame: Option<u64>,
     pub stop_frame: Option<u64>,
     pub model_server_addr: Option<String>,
+    /// If true, run a Rauch-Tung-Striebel smoother over each trajectory and
+    /// save the smoothed estimates (with covariances) to an additional
+    /// `kalman_estimates_smoothed` table.
+    pub smooth: bool,
 }
 
 impl Default for KalmanizeOptions {
@@ -178,6 +183,7 @@ impl Default for KalmanizeOptions {
             start_frame: None,
             stop_frame: None,
             model_server_addr: None,
+            smooth: false,
         }
     }
 }
@@ -263,6 +269,7 @@ where
         save_empty_data2d,
         ignore_latency,
     )?;
+    coord_processor.set_smooth_kalman_estimates(opt2.smooth)?;
 
     for cam_name in recon.cam_names() {
         let mut old_image_fname = data_src.path_starter();
@@ -431,7 +438,11 @@ where
                         trigger_timestamp,
                         cam_received_timestamp,
                     );
-                    let fdp = FrameDataAndPoints { frame_data, points };
+                    let fdp = FrameDataAndPoints {
+                        frame_data,
+                        points,
+                        apriltags: vec![],
+                    };
                     // block until sent
                     match futures::executor::block_on(futures::sink::SinkExt::send(
                         &mut frame_data_tx,
@@ -0,0 +1,145 @@
+//! Compare data association methods on two objects crossing each other.
+//!
+//! The test data contain a single object seen by a single camera and thus
+//! require flat-3d tracking. Run this with:
+//!
+//! ```text
+//! cargo test --no-default-features --features "flat-3d flydra2/bundle_files" \
+//!     --test test-data-association -- --nocapture
+//! ```
+#![cfg(feature = "flat-3d")]
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use flydra_types::{
+    Data2dDistortedRow, DataAssociationMethod, TrackingParamsInnerFlat3D,
+    DATA2D_DISTORTED_CSV_FNAME,
+};
+
+const SRC_DIR: &str = "test_data/20180330_113743.short";
+
+/// Copy the test data to `dest`, adding a second object which moves along
+/// the path of the first object in reverse so that the two paths cross.
+fn make_crossing_data(dest: &Path) {
+    std::fs::create_dir_all(dest).unwrap();
+    for fname in &["calibration.xml", "cam_info.csv", "textlog.csv"] {
+        std::fs::copy(Path::new(SRC_DIR).join(fname), dest.join(fname)).unwrap();
+    }
+
+    let mut rdr =
+        csv::Reader::from_path(Path::new(SRC_DIR).join(DATA2D_DISTORTED_CSV_FNAME)).unwrap();
+    let rows: Vec<Data2dDistortedRow> = rdr.deserialize().map(|row| row.unwrap()).collect();
+
+    let mut wtr = csv::Writer::from_path(dest.join(DATA2D_DISTORTED_CSV_FNAME)).unwrap();
+    for (row, reversed) in rows.iter().zip(rows.iter().rev()) {
+        wtr.serialize(row).unwrap();
+        let second = Data2dDistortedRow {
+            camn: row.camn,
+            frame: row.frame,
+            timestamp: row.timestamp.clone(),
+            cam_received_timestamp: row.cam_received_timestamp.clone(),
+            x: reversed.x,
+            y: reversed.y,
+            area: reversed.area,
+            slope: reversed.slope,
+            eccentricity: reversed.eccentricity,
+            frame_pt_idx: 1,
+            cur_val: reversed.cur_val,
+            mean_val: reversed.mean_val,
+            sumsqf_val: reversed.sumsqf_val,
+            ellipse_major: reversed.ellipse_major,
+            ellipse_minor: reversed.ellipse_minor,
+            ellipse_angle: reversed.ellipse_angle,
+            bbox_left: reversed.bbox_left,
+            bbox_bottom: reversed.bbox_bottom,
+            bbox_right: reversed.bbox_right,
+            bbox_top: reversed.bbox_top,
+            contour: reversed.contour.clone(),
+            patch_left: reversed.patch_left,
+            patch_bottom: reversed.patch_bottom,
+            patch: reversed.patch.clone(),
+        };
+        wtr.serialize(second).unwrap();
+    }
+    wtr.flush().unwrap();
+}
+
+/// Count the data association rows and how often the point used by each
+/// object changes.
+///
+/// In the crossing data, each object should keep using the same point index.
+fn count_identity_swaps(output_braidz: &Path) -> (usize, usize) {
+    let mut archive = braidz_parser::braidz_parse_path(output_braidz).unwrap();
+    let mut last_pt_idx: BTreeMap<u32, u8> = BTreeMap::new();
+    let mut n_rows = 0;
+    let mut n_swaps = 0;
+    for row in archive.data_association_rows(Default::default()).unwrap() {
+        let row = row.unwrap();
+        n_rows += 1;
+        if let Some(prev) = last_pt_idx.insert(row.obj_id, row.pt_idx) {
+            if prev != row.pt_idx {
+                n_swaps += 1;
+            }
+        }
+    }
+    (n_rows, n_swaps)
+}
+
+#[tokio::test]
+async fn test_identity_swaps_per_data_association_method() {
+    env_tracing_logger::init();
+
+    let root = tempfile::tempdir().unwrap(); // will cleanup on drop
+    let data_dir = root.path().join("crossing.braid");
+    make_crossing_data(&data_dir);
+
+    let mut n_swaps = BTreeMap::new();
+    for method in &[
+        DataAssociationMethod::Greedy,
+        DataAssociationMethod::Gnn,
+        DataAssociationMethod::Jpda,
+    ] {
+        let data_src =
+            braidz_parser::incremental_parser::IncrementalParser::open(&data_dir).unwrap();
+        let data_src = data_src.parse_basics().unwrap();
+
+        let output_braidz = root.path().join(format!("{:?}.braidz", method));
+
+        let tracking_params = TrackingParamsInnerFlat3D {
+            data_association: *method,
+            ..Default::default()
+        };
+
+        let opts = braid_offline::KalmanizeOptions::default();
+        let rt_handle = tokio::runtime::Handle::try_current().unwrap();
+
+        braid_offline::kalmanize(
+            data_src,
+            &output_braidz,
+            None,
+            tracking_params,
+            opts,
+            rt_handle,
+            false,
+        )
+        .await
+        .unwrap();
+
+        let (n_rows, method_swaps) = count_identity_swaps(&output_braidz);
+        println!(
+            "{:?}: {} identity swaps in {} associations",
+            method, method_swaps, n_rows
+        );
+
+        assert!(n_rows > 0);
+        n_swaps.insert(format!("{:?}", method), method_swaps);
+    }
+
+    // Greedy association lets the first object take the point of the second
+    // object where the paths cross. Global association avoids this.
+    let greedy_swaps = n_swaps["Greedy"];
+    assert!(greedy_swaps > 0);
+    assert!(n_swaps["Gnn"] < greedy_swaps);
+    assert!(n_swaps["Jpda"] < greedy_swaps);
+}
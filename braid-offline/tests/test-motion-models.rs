@@ -0,0 +1,78 @@
+use std::convert::TryInto;
+
+const FNAME: &str = "20201013_140707.braidz";
+const URL_BASE: &str = "https://strawlab-cdn.com/assets/";
+const SHA256SUM: &str = "500b235c321b81ca27a442801e716ec3dd1f12488a60cc9c7d5781855e8d4424";
+
+#[tokio::test]
+async fn test_imm_motion_model() {
+    env_tracing_logger::init();
+
+    download_verify::download_verify(
+        format!("{}/{}", URL_BASE, FNAME).as_str(),
+        FNAME,
+        &download_verify::Hash::Sha256(SHA256SUM.into()),
+    )
+    .unwrap();
+
+    let data_src =
+        braidz_parser::incremental_parser::IncrementalParser::open_braidz_file(FNAME).unwrap();
+    let data_src = data_src.parse_basics().unwrap();
+
+    let output_root = tempfile::tempdir().unwrap(); // will cleanup on drop
+    let output_braidz = output_root.path().join("output.braidz");
+
+    let tracking_params_parsed = data_src.basic_info().tracking_params.as_ref();
+
+    let mut tracking_params: flydra_types::TrackingParamsInner3D = tracking_params_parsed
+        .map(|p| p.try_into().unwrap())
+        .unwrap();
+    let motion_noise_scale = tracking_params.motion_noise_scale;
+    tracking_params.motion_model = "imm".to_string();
+    tracking_params.imm_params = Some(flydra_types::ImmParams {
+        models: vec![
+            flydra_types::ImmModelParams {
+                motion_model: "constant-velocity".to_string(),
+                motion_noise_scale,
+            },
+            flydra_types::ImmModelParams {
+                motion_model: "constant-acceleration".to_string(),
+                motion_noise_scale: 100.0 * motion_noise_scale,
+            },
+        ],
+        switch_probability: 0.05,
+    });
+
+    let opts = braid_offline::KalmanizeOptions::default();
+
+    let rt_handle = tokio::runtime::Handle::try_current().unwrap();
+
+    braid_offline::kalmanize(
+        data_src,
+        &output_braidz,
+        None,
+        tracking_params,
+        opts,
+        rt_handle,
+        false,
+    )
+    .await
+    .unwrap();
+
+    // The motion model is saved with the tracking parameters.
+    let parsed =
+        braidz_parser::incremental_parser::IncrementalParser::open_braidz_file(&output_braidz)
+            .unwrap();
+    let parsed = parsed.parse_basics().unwrap();
+    let saved_params = parsed.basic_info().tracking_params.as_ref().unwrap();
+    assert_eq!(saved_params.motion_model.as_deref(), Some("imm"));
+    assert_eq!(saved_params.imm_params.as_ref().unwrap().models.len(), 2);
+
+    let mut archive = braidz_parser::braidz_parse_path(&output_braidz).unwrap();
+    let n_rows = archive
+        .kalman_estimates_rows(Default::default())
+        .unwrap()
+        .map(|row| row.unwrap())
+        .count();
+    assert!(n_rows > 0);
+}
@@ -0,0 +1,84 @@
+use std::{collections::BTreeMap, convert::TryInto};
+
+use flydra_types::KalmanEstimatesRow;
+
+const FNAME: &str = "20201013_140707.braidz";
+const URL_BASE: &str = "https://strawlab-cdn.com/assets/";
+const SHA256SUM: &str = "500b235c321b81ca27a442801e716ec3dd1f12488a60cc9c7d5781855e8d4424";
+
+#[tokio::test]
+async fn test_smoothing() {
+    env_tracing_logger::init();
+
+    download_verify::download_verify(
+        format!("{}/{}", URL_BASE, FNAME).as_str(),
+        FNAME,
+        &download_verify::Hash::Sha256(SHA256SUM.into()),
+    )
+    .unwrap();
+
+    let data_src =
+        braidz_parser::incremental_parser::IncrementalParser::open_braidz_file(FNAME).unwrap();
+    let data_src = data_src.parse_basics().unwrap();
+
+    let output_root = tempfile::tempdir().unwrap(); // will cleanup on drop
+    let output_braidz = output_root.path().join("output.braidz");
+
+    let tracking_params_parsed = data_src.basic_info().tracking_params.as_ref();
+
+    let tracking_params: flydra_types::TrackingParamsInner3D = tracking_params_parsed
+        .map(|p| p.try_into().unwrap())
+        .unwrap();
+
+    let mut opts = braid_offline::KalmanizeOptions::default();
+    opts.smooth = true;
+
+    let rt_handle = tokio::runtime::Handle::try_current().unwrap();
+
+    braid_offline::kalmanize(
+        data_src,
+        &output_braidz,
+        None,
+        tracking_params,
+        opts,
+        rt_handle,
+        false,
+    )
+    .await
+    .unwrap();
+
+    let mut archive = braidz_parser::braidz_parse_path(&output_braidz).unwrap();
+    let forward: Vec<KalmanEstimatesRow> = archive
+        .kalman_estimates_rows(Default::default())
+        .unwrap()
+        .map(|row| row.unwrap())
+        .collect();
+    assert!(!forward.is_empty());
+
+    let mut zs = archive.zip_struct();
+    let mut fname = zs.path_starter();
+    fname.push(flydra_types::KALMAN_ESTIMATES_SMOOTHED_CSV_FNAME);
+    let rdr = braidz_parser::open_maybe_gzipped(&mut fname).unwrap();
+    let smoothed: BTreeMap<(u32, u64), KalmanEstimatesRow> = csv::Reader::from_reader(rdr)
+        .into_deserialize()
+        .map(|row| {
+            let row: KalmanEstimatesRow = row.unwrap();
+            ((row.obj_id, row.frame.0), row)
+        })
+        .collect();
+
+    // There is exactly one smoothed row for each forward row.
+    assert_eq!(smoothed.len(), forward.len());
+    let mut last_forward: BTreeMap<u32, &KalmanEstimatesRow> = BTreeMap::new();
+    for row in forward.iter() {
+        assert!(smoothed.contains_key(&(row.obj_id, row.frame.0)));
+        last_forward.insert(row.obj_id, row);
+    }
+
+    // The last smoothed estimate of a trajectory is the forward estimate.
+    for (obj_id, row) in last_forward.iter() {
+        let smoothed_row = &smoothed[&(*obj_id, row.frame.0)];
+        assert!((smoothed_row.x - row.x).abs() < 1e-6);
+        assert!((smoothed_row.P00 - row.P00).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,72 @@
+//! Detections from the data2d_distorted table of a braidz file.
+
+use anyhow::Context;
+use std::{collections::BTreeMap, path::Path};
+
+use braidz_parser::RowFilter;
+use flydra_types::CamNum;
+use mvg::DistortedPixel;
+use nalgebra::Point2;
+
+use crate::Detections;
+
+/// Read the frames on which cameras detected a single point.
+///
+/// Image sizes are taken from the calibration, if the file has one.
+pub(crate) fn read_detections(input: &Path) -> anyhow::Result<Detections> {
+    let mut archive = braidz_parser::braidz_parse_path(input)
+        .with_context(|| format!("Parsing file {}", input.display()))?;
+    let camn2camid = archive.cam_info.camn2camid.clone();
+
+    let mut image_sizes = BTreeMap::new();
+    for cam_id in camn2camid.values() {
+        let size = archive
+            .calibration_info
+            .as_ref()
+            .and_then(|cal| cal.cameras.cam_by_name(cam_id))
+            .map(|cam| (cam.width(), cam.height()));
+        image_sizes.insert(cam_id.clone(), size);
+    }
+
+    // The detections of each camera on each frame.
+    let mut frames: BTreeMap<i64, BTreeMap<CamNum, Vec<Point2<f64>>>> = BTreeMap::new();
+    for row in archive.data2d_distorted_rows(RowFilter::default())? {
+        let row = row?;
+        if row.x.is_nan() || row.y.is_nan() {
+            continue;
+        }
+        frames
+            .entry(row.frame)
+            .or_insert_with(BTreeMap::new)
+            .entry(row.camn)
+            .or_insert_with(Vec::new)
+            .push(Point2::new(row.x, row.y));
+    }
+
+    let mut points = Vec::new();
+    for by_camn in frames.values() {
+        let mut obs = Vec::with_capacity(by_camn.len());
+        for (camn, detections) in by_camn.iter() {
+            if detections.len() != 1 {
+                continue;
+            }
+            let cam_id = camn2camid
+                .get(camn)
+                .with_context(|| format!("unknown camera number {}", camn.0))?;
+            obs.push((
+                cam_id.clone(),
+                DistortedPixel {
+                    coords: detections[0],
+                },
+            ));
+        }
+        if obs.len() >= 2 {
+            points.push(obs);
+        }
+    }
+
+    Ok(Detections {
+        image_sizes,
+        points,
+    })
+}
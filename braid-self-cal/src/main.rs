@@ -0,0 +1,235 @@
+//! Calibrate a multi-camera system from a single moving point (e.g. an LED)
+//!
+//! The 2D detections are read from a braidz file (synchronized by frame
+//! number) or from the CSV files saved by strand-cam (one per camera,
+//! synchronized by timestamp). Only detections of a camera which saw a
+//! single point at that time are used.
+use anyhow::Context;
+use std::{collections::BTreeMap, path::PathBuf};
+use structopt::StructOpt;
+
+use mvg::self_calibration::{
+    align_to_known_points, self_calibrate, SelfCalibrationCamera, SelfCalibrationOptions,
+};
+use mvg::{DistortedPixel, PointWorldFrame};
+use nalgebra::{Point2, Point3};
+
+mod braidz_input;
+mod strand_cam_csv;
+
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "braid-self-cal",
+    about = "calibrate cameras from a single point moved through the volume"
+)]
+struct Opt {
+    /// Input braidz filename
+    #[structopt(
+        long = "braidz",
+        parse(from_os_str),
+        required_unless = "strand-cam-csv"
+    )]
+    braidz: Option<PathBuf>,
+
+    /// Input strand-cam CSV filenames (one per camera)
+    #[structopt(long = "strand-cam-csv", parse(from_os_str), conflicts_with = "braidz")]
+    strand_cam_csv: Vec<PathBuf>,
+
+    /// Output calibration (flydra XML) filename
+    #[structopt(long = "output", short = "o", parse(from_os_str))]
+    output: PathBuf,
+
+    /// Filename of the YAML reprojection error report (default: print)
+    #[structopt(long = "report", parse(from_os_str))]
+    report: Option<PathBuf>,
+
+    /// Known intrinsic parameters (ROS camera_info YAML, may be repeated)
+    #[structopt(long = "intrinsics", parse(from_os_str))]
+    intrinsics: Vec<PathBuf>,
+
+    /// Image size (e.g. 1280x1024) of cameras without known intrinsics
+    ///
+    /// Required for braidz input if neither intrinsics nor a calibration are
+    /// available. Strand-cam CSV files contain the image size.
+    #[structopt(long = "image-size")]
+    image_size: Option<ImageSize>,
+
+    /// YAML file with world coordinates of points to align the calibration
+    ///
+    /// This is a list of entries with `world: [x, y, z]` and
+    /// `observations: {cam_name: [x_px, y_px], ...}`.
+    #[structopt(long = "known-points", parse(from_os_str))]
+    known_points: Option<PathBuf>,
+
+    /// Maximum time difference between simultaneous detections in strand-cam
+    /// CSV files (in milliseconds)
+    #[structopt(long = "max-time-diff-msec", default_value = "5.0")]
+    max_time_diff_msec: f64,
+
+    /// Minimum number of cameras which must observe a point
+    #[structopt(long = "min-views", default_value = "2")]
+    min_views: usize,
+
+    /// Maximum number of points used
+    #[structopt(long = "max-points", default_value = "5000")]
+    max_points: usize,
+
+    /// Reprojection error (in pixels) above which observations are discarded
+    #[structopt(long = "outlier-threshold", default_value = "5.0")]
+    outlier_threshold: f64,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ImageSize {
+    width: usize,
+    height: usize,
+}
+
+impl std::str::FromStr for ImageSize {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let mut parts = s.splitn(2, 'x');
+        match (parts.next(), parts.next()) {
+            (Some(width), Some(height)) => Ok(Self {
+                width: width.parse()?,
+                height: height.parse()?,
+            }),
+            _ => anyhow::bail!("expected image size as WIDTHxHEIGHT, got \"{}\"", s),
+        }
+    }
+}
+
+/// The detections of all cameras.
+pub(crate) struct Detections {
+    /// The image size of each camera, if known.
+    pub(crate) image_sizes: BTreeMap<String, Option<(usize, usize)>>,
+    /// The simultaneous observations of the point.
+    pub(crate) points: Vec<Vec<(String, DistortedPixel<f64>)>>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct KnownPoint {
+    world: [f64; 3],
+    observations: BTreeMap<String, [f64; 2]>,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct Report {
+    n_points: usize,
+    n_outliers: usize,
+    reprojection_error: mvg::bundle_adjustment::ReprojectionReport<f64>,
+}
+
+fn main() -> anyhow::Result<()> {
+    if std::env::var_os("RUST_LOG").is_none() {
+        std::env::set_var("RUST_LOG", "info");
+    }
+    env_logger::init();
+    let opt = Opt::from_args();
+
+    let detections = match &opt.braidz {
+        Some(braidz) => braidz_input::read_detections(braidz)?,
+        None => strand_cam_csv::read_detections(&opt.strand_cam_csv, opt.max_time_diff_msec)?,
+    };
+
+    let mut intrinsics = BTreeMap::new();
+    for fname in opt.intrinsics.iter() {
+        let fd = std::fs::File::open(fname)
+            .with_context(|| format!("Opening file {}", fname.display()))?;
+        let named: opencv_ros_camera::NamedIntrinsicParameters<f64> =
+            opencv_ros_camera::from_ros_yaml(fd)
+                .with_context(|| format!("Parsing file {}", fname.display()))?;
+        intrinsics.insert(named.name.clone(), named);
+    }
+
+    let mut cameras = Vec::new();
+    for (name, image_size) in detections.image_sizes.iter() {
+        let cam = match intrinsics.get(name) {
+            Some(named) => SelfCalibrationCamera {
+                name: name.clone(),
+                width: named.width,
+                height: named.height,
+                intrinsics: Some(named.intrinsics.clone()),
+            },
+            None => {
+                let (width, height) = match (image_size, opt.image_size) {
+                    (Some(size), _) => *size,
+                    (None, Some(size)) => (size.width, size.height),
+                    (None, None) => {
+                        anyhow::bail!("image size of camera {} unknown (use --image-size)", name)
+                    }
+                };
+                SelfCalibrationCamera {
+                    name: name.clone(),
+                    width,
+                    height,
+                    intrinsics: None,
+                }
+            }
+        };
+        cameras.push(cam);
+    }
+
+    let points = detections.points;
+    let max_points = opt.max_points.max(1);
+    let step = (points.len() + max_points - 1) / max_points;
+    let points: Vec<_> = points.into_iter().step_by(step.max(1)).collect();
+    log::info!(
+        "calibrating {} cameras with {} points",
+        cameras.len(),
+        points.len()
+    );
+
+    let opts = SelfCalibrationOptions {
+        min_views: opt.min_views,
+        outlier_threshold: opt.outlier_threshold,
+        ..Default::default()
+    };
+    let result = self_calibrate(&cameras, &points, &opts)?;
+
+    let system = match &opt.known_points {
+        Some(fname) => {
+            let fd = std::fs::File::open(fname)
+                .with_context(|| format!("Opening file {}", fname.display()))?;
+            let known_points: Vec<KnownPoint> = serde_yaml::from_reader(fd)
+                .with_context(|| format!("Parsing file {}", fname.display()))?;
+            let known: Vec<_> = known_points
+                .into_iter()
+                .map(|pt| {
+                    let world = PointWorldFrame {
+                        coords: Point3::new(pt.world[0], pt.world[1], pt.world[2]),
+                    };
+                    let observations = pt
+                        .observations
+                        .into_iter()
+                        .map(|(name, xy)| {
+                            let coords = Point2::new(xy[0], xy[1]);
+                            (name, DistortedPixel { coords })
+                        })
+                        .collect();
+                    (world, observations)
+                })
+                .collect();
+            align_to_known_points(&result.system, &known)?
+        }
+        None => result.system,
+    };
+
+    let system = flydra_mvg::FlydraMultiCameraSystem::from_system(system, None);
+    let fd = std::fs::File::create(&opt.output)
+        .with_context(|| format!("Creating file {}", opt.output.display()))?;
+    system.to_flydra_xml(fd)?;
+
+    let report = Report {
+        n_points: result.n_points,
+        n_outliers: result.n_outliers,
+        reprojection_error: result.report,
+    };
+    let buf = serde_yaml::to_string(&report)?;
+    match &opt.report {
+        Some(fname) => std::fs::write(fname, buf)
+            .with_context(|| format!("Writing report {}", fname.display()))?,
+        None => println!("{}", buf),
+    }
+    Ok(())
+}
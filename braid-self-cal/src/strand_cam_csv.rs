@@ -0,0 +1,179 @@
+//! Detections from the CSV files saved by strand-cam.
+//!
+//! Each file contains the detections of a single camera, preceded by a
+//! commented YAML header with the camera configuration. The frame numbers of
+//! different cameras are unrelated, so simultaneous detections are found by
+//! their timestamps.
+
+use anyhow::Context;
+use std::{
+    collections::BTreeMap,
+    io::{BufRead, BufReader},
+    path::{Path, PathBuf},
+};
+
+use mvg::DistortedPixel;
+use nalgebra::Point2;
+use strand_cam_csv_config_types::FullCfgFview2_0_26;
+
+use crate::Detections;
+
+const HEADER_START: &str = "# -- start of yaml config --";
+const HEADER_END: &str = "# -- end of yaml config --";
+
+#[derive(Debug, serde::Deserialize)]
+struct Fview2CsvRecord {
+    time_microseconds: i64,
+    frame: i64,
+    x_px: f64,
+    y_px: f64,
+}
+
+/// A detection of a single point by a camera.
+struct Detection {
+    /// Microseconds since the epoch.
+    time: i64,
+    cam_idx: usize,
+    coords: Point2<f64>,
+}
+
+/// Read the frames on which cameras detected a single point and group them
+/// by time.
+///
+/// Detections within `max_time_diff_msec` of the first detection of a group
+/// are simultaneous.
+pub(crate) fn read_detections(
+    inputs: &[PathBuf],
+    max_time_diff_msec: f64,
+) -> anyhow::Result<Detections> {
+    let mut image_sizes = BTreeMap::new();
+    let mut names = Vec::with_capacity(inputs.len());
+    let mut detections = Vec::new();
+    for (cam_idx, input) in inputs.iter().enumerate() {
+        let (cfg, cam_detections) =
+            read_file(input, cam_idx).with_context(|| format!("Reading {}", input.display()))?;
+        let name = cfg.camera.serial.clone();
+        if image_sizes
+            .insert(
+                name.clone(),
+                Some((cfg.camera.width as usize, cfg.camera.height as usize)),
+            )
+            .is_some()
+        {
+            anyhow::bail!("camera {} given more than once", name);
+        }
+        names.push(name);
+        detections.extend(cam_detections);
+    }
+    detections.sort_by_key(|d| d.time);
+
+    let max_time_diff = (max_time_diff_msec * 1000.0) as i64;
+    let mut points = Vec::new();
+    let mut current: Vec<&Detection> = Vec::new();
+    for detection in detections.iter() {
+        let is_simultaneous = match current.first() {
+            Some(first) => detection.time - first.time <= max_time_diff,
+            None => true,
+        };
+        if !is_simultaneous {
+            push_point(&mut points, &current, &names);
+            current.clear();
+        }
+        current.push(detection);
+    }
+    push_point(&mut points, &current, &names);
+
+    Ok(Detections {
+        image_sizes,
+        points,
+    })
+}
+
+/// Add the detections of a group, unless a camera detected more than once.
+fn push_point(
+    points: &mut Vec<Vec<(String, DistortedPixel<f64>)>>,
+    group: &[&Detection],
+    names: &[String],
+) {
+    let mut by_cam = BTreeMap::new();
+    for detection in group.iter() {
+        if by_cam.insert(detection.cam_idx, detection.coords).is_some() {
+            return;
+        }
+    }
+    if by_cam.len() < 2 {
+        return;
+    }
+    points.push(
+        by_cam
+            .into_iter()
+            .map(|(cam_idx, coords)| (names[cam_idx].clone(), DistortedPixel { coords }))
+            .collect(),
+    );
+}
+
+/// Read the configuration and single point detections of a file.
+fn read_file(input: &Path, cam_idx: usize) -> anyhow::Result<(FullCfgFview2_0_26, Vec<Detection>)> {
+    let mut rdr = BufReader::new(std::fs::File::open(input)?);
+    let cfg: FullCfgFview2_0_26 = serde_yaml::from_str(&read_header(&mut rdr)?)?;
+    let t0 = cfg.created_at.timestamp_nanos() / 1000;
+
+    let rdr = csv::ReaderBuilder::new()
+        .comment(Some(b'#'))
+        .from_reader(rdr);
+    let mut by_frame: BTreeMap<i64, Vec<Fview2CsvRecord>> = BTreeMap::new();
+    for record in rdr.into_deserialize() {
+        let record: Fview2CsvRecord = record?;
+        by_frame
+            .entry(record.frame)
+            .or_insert_with(Vec::new)
+            .push(record);
+    }
+
+    let detections = by_frame
+        .into_iter()
+        .filter(|(_, records)| records.len() == 1)
+        .map(|(_, records)| {
+            let record = &records[0];
+            Detection {
+                time: t0 + record.time_microseconds,
+                cam_idx,
+                coords: Point2::new(record.x_px, record.y_px),
+            }
+        })
+        .collect();
+    Ok((cfg, detections))
+}
+
+/// Read the commented YAML header.
+///
+/// Reading stops after the end of the header, leaving the CSV data.
+fn read_header<R: BufRead>(rdr: &mut R) -> anyhow::Result<String> {
+    let mut line = String::new();
+    loop {
+        line.clear();
+        rdr.read_line(&mut line)?;
+        if line.trim_end() == HEADER_START {
+            break;
+        }
+        if !line.starts_with('#') {
+            anyhow::bail!("no YAML configuration header");
+        }
+    }
+    let mut header = Vec::new();
+    loop {
+        line.clear();
+        if rdr.read_line(&mut line)? == 0 {
+            anyhow::bail!("premature end of YAML configuration header");
+        }
+        let line = line.trim_end();
+        if line == HEADER_END {
+            break;
+        }
+        match line.strip_prefix("# ") {
+            Some(content) => header.push(content.to_string()),
+            None => anyhow::bail!("unexpected line prefix in YAML configuration header"),
+        }
+    }
+    Ok(header.join("\n"))
+}
@@ -11,9 +11,7 @@ use std::convert::TryInto;
 use anyhow::Result;
 use structopt::StructOpt;
 
-use flydra_types::{
-    AddrInfoIP, MainbrainBuiLocation, RawCamName, RealtimePointsDestAddr, TriggerType,
-};
+use flydra_types::{MainbrainBuiLocation, RawCamName, RealtimePointsDestAddr, TriggerType};
 use strand_cam::{ImPtDetectCfgSource, MyApp, NoisyDrop};
 
 use braid::{braid_start, parse_config_file, BraidCameraConfig};
@@ -39,6 +37,7 @@ fn launch_strand_cam(
     runtime: &tokio::runtime::Runtime,
     force_camera_sync_mode: bool,
     software_limit_framerate: strand_cam::StartSoftwareFrameRateLimit,
+    cal_fname: Option<std::path::PathBuf>,
 ) -> Result<StrandCamInstance> {
     let tracker_cfg_src =
         ImPtDetectCfgSource::ChangesNotSavedToDisk(camera.point_detection_config.clone());
@@ -58,6 +57,7 @@ fn launch_strand_cam(
         #[cfg(feature = "fiducial")]
         apriltag_csv_filename_template: strand_cam_storetype::APRILTAG_CSV_TEMPLATE_DEFAULT
             .to_string(),
+        camera_calibration_fname: cal_fname,
         ros_periodic_update_interval: std::time::Duration::from_millis(9999), // not actually used
         tracker_cfg_src,
         raise_grab_thread_priority: camera.raise_grab_thread_priority,
@@ -69,6 +69,8 @@ fn launch_strand_cam(
         show_url: false,
         force_camera_sync_mode,
         software_limit_framerate,
+        live_view_bitrate: camera.live_view_bitrate,
+        post_trigger_gpio: None,
     };
 
     let (_, _, fut, _my_app) = runtime.block_on(strand_cam::setup_app(handle.clone(), args))?;
@@ -99,6 +101,10 @@ fn main() -> Result<()> {
             false,
             strand_cam::StartSoftwareFrameRateLimit::Enable(cfg.fps),
         ),
+        TriggerType::SoftwareSync(cfg) => (
+            false,
+            strand_cam::StartSoftwareFrameRateLimit::Enable(cfg.fps),
+        ),
     };
     let show_tracking_params = false;
 
@@ -108,6 +114,7 @@ fn main() -> Result<()> {
         .iter()
         .map(|x| RawCamName::new(x.name.clone()).to_ros())
         .collect();
+    let cal_fname = cfg.mainbrain.cal_fname.clone();
     let phase1 = runtime.block_on(flydra2_mainbrain::pre_run(
         &handle,
         cfg.mainbrain.cal_fname,
@@ -125,21 +132,25 @@ fn main() -> Result<()> {
         cfg.mainbrain.save_empty_data2d,
         cfg.mainbrain.jwt_secret.map(|x| x.as_bytes().to_vec()),
         all_expected_cameras,
+        cfg.trigger_volumes,
+        cfg.mainbrain.camtrig_device_path,
     ))?;
 
     let mainbrain_server_info = MainbrainBuiLocation(phase1.mainbrain_server_info.clone());
 
+    // The mainbrain receives both UDP and TCP on this address.
     let camdata_addr = phase1.camdata_socket.local_addr()?;
 
-    let addr_info_ip = AddrInfoIP::from_socket_addr(&camdata_addr);
-
     let cfg_cameras = cfg.cameras;
     let handle = runtime.handle().clone();
     let _enter_guard = runtime.enter();
     let _strand_cams = cfg_cameras
         .into_iter()
         .map(|camera| {
-            let camdata_addr = Some(RealtimePointsDestAddr::IpAddr(addr_info_ip.clone()));
+            let camdata_addr = Some(RealtimePointsDestAddr::new(
+                &camdata_addr,
+                camera.camdata_transport,
+            ));
             launch_strand_cam(
                 camera,
                 camdata_addr,
@@ -148,6 +159,7 @@ fn main() -> Result<()> {
                 &runtime,
                 force_camera_sync_mode,
                 software_limit_framerate.clone(),
+                cal_fname.clone(),
             )
         })
         .collect::<Result<Vec<StrandCamInstance>>>()?;
@@ -2,7 +2,10 @@ use serde::{Deserialize, Serialize};
 
 use anyhow::Result;
 
-use flydra_types::{FakeSyncConfig, TriggerType, TriggerboxConfig};
+use flydra_types::{
+    CamdataTransport, FakeSyncConfig, TriggerType, TriggerVolumeConfig, TriggerVolumeShape,
+    TriggerboxConfig,
+};
 use image_tracker_types::ImPtDetectCfg;
 
 fn default_lowlatency_camdata_udp_addr() -> String {
@@ -64,7 +67,8 @@ pub struct MainbrainConfig {
     // Raising the mainbrain thread priority is currently disabled.
     // /// Parameters to potentially raise the mainbrain thread priority.
     // sched_policy_priority: Option<(i32, i32)>,
-    /// Address of UDP port to send low-latency detection data
+    /// Address of UDP port to send low-latency detection data. Cameras using
+    /// the TCP transport connect to the same port.
     #[serde(default = "default_lowlatency_camdata_udp_addr")]
     pub lowlatency_camdata_udp_addr: String,
     /// Address of HTTP port for control API
@@ -80,6 +84,9 @@ pub struct MainbrainConfig {
     pub save_empty_data2d: bool,
     /// Secret to use for JWT auth on HTTP port for control API
     pub jwt_secret: Option<String>,
+    /// Serial device of the camtrig device set by trigger volumes (e.g.
+    /// "/dev/ttyACM0")
+    pub camtrig_device_path: Option<String>,
 }
 
 impl std::default::Default for MainbrainConfig {
@@ -96,6 +103,7 @@ impl std::default::Default for MainbrainConfig {
             model_server_addr: default_model_server_addr(),
             save_empty_data2d: true,
             jwt_secret: None,
+            camtrig_device_path: None,
         }
     }
 }
@@ -137,6 +145,9 @@ pub struct BraidConfig2 {
     #[serde(default)]
     pub trigger: TriggerType,
     pub cameras: Vec<BraidCameraConfig>,
+    /// Regions emitting events when tracked objects enter or leave them.
+    #[serde(default)]
+    pub trigger_volumes: Vec<TriggerVolumeConfig>,
 }
 
 impl From<BraidConfig1> for BraidConfig2 {
@@ -149,6 +160,7 @@ impl From<BraidConfig1> for BraidConfig2 {
             mainbrain: orig.mainbrain,
             trigger,
             cameras: orig.cameras,
+            trigger_volumes: vec![],
         }
     }
 }
@@ -179,6 +191,13 @@ impl BraidConfig2 {
         // fixup self.mainbrain.output_base_dirname
         fixup_relative_path(&mut self.mainbrain.output_base_dirname, &dirname)?;
 
+        // fixup the filenames of the trigger volume meshes
+        for trigger_volume in self.trigger_volumes.iter_mut() {
+            if let TriggerVolumeShape::Mesh { filename } = &mut trigger_volume.shape {
+                fixup_relative_path(filename, &dirname)?;
+            }
+        }
+
         Ok(())
     }
 }
@@ -197,6 +216,7 @@ impl std::default::Default for BraidConfig2 {
                 BraidCameraConfig::default_absdiff_config("fake-camera-2".to_string()),
                 BraidCameraConfig::default_absdiff_config("fake-camera-3".to_string()),
             ],
+            trigger_volumes: vec![],
         }
     }
 }
@@ -218,6 +238,13 @@ pub struct BraidCameraConfig {
     /// Whether to raise the priority of the grab thread.
     #[serde(default = "return_false")]
     pub raise_grab_thread_priority: bool,
+    /// If set, stream the live view as VP8 video with this bitrate (in kbps)
+    /// rather than as JPEG images.
+    #[serde(default)]
+    pub live_view_bitrate: Option<u32>,
+    /// How to send detected points to the mainbrain ("Udp" or "Tcp").
+    #[serde(default)]
+    pub camdata_transport: CamdataTransport,
 }
 
 impl BraidCameraConfig {
@@ -227,6 +254,8 @@ impl BraidCameraConfig {
             pixel_format: None,
             point_detection_config: im_pt_detect_config::default_absdiff(),
             raise_grab_thread_priority: false,
+            live_view_bitrate: None,
+            camdata_transport: CamdataTransport::default(),
         }
     }
 }
@@ -0,0 +1,110 @@
+//! Refinement of the calibration of a braidz file from its 2D detections.
+//!
+//! The detections associated with the same object on the same frame are
+//! observations of a single 3D point. These are used to bundle adjust the
+//! calibration with [mvg::bundle_adjustment].
+
+use anyhow::Context;
+use std::{collections::BTreeMap, path::Path};
+
+use braidz_parser::RowFilter;
+use flydra_types::CamNum;
+use mvg::bundle_adjustment::{BundleAdjustmentOptions, ReprojectionReport};
+use mvg::DistortedPixel;
+use nalgebra::Point2;
+
+/// The before/after comparison written as report.
+#[derive(Debug, serde::Serialize)]
+struct Report {
+    n_points: usize,
+    n_iterations: usize,
+    before: ReprojectionReport<f64>,
+    after: ReprojectionReport<f64>,
+}
+
+/// Bundle adjust the calibration of `input` and save it to `output`.
+///
+/// At most `max_points` 3D points, evenly spread over the recording, are
+/// used. A YAML report of the reprojection errors before and after is
+/// written to `report` or, if not given, printed.
+pub(crate) fn bundle_adjust(
+    input: &Path,
+    output: &Path,
+    report: Option<&Path>,
+    max_points: usize,
+    opts: &BundleAdjustmentOptions,
+) -> anyhow::Result<()> {
+    let mut archive = braidz_parser::braidz_parse_path(input)
+        .with_context(|| format!("Parsing file {}", input.display()))?;
+    let calibration = archive
+        .calibration_info
+        .clone()
+        .with_context(|| format!("{} has no calibration", input.display()))?;
+    if calibration.water.is_some() {
+        anyhow::bail!("bundle adjustment with refraction is not supported");
+    }
+    let camn2camid = archive.cam_info.camn2camid.clone();
+
+    // The object of each associated detection.
+    let mut obj_ids: BTreeMap<(u64, CamNum, u8), u32> = BTreeMap::new();
+    for row in archive.data_association_rows(RowFilter::default())? {
+        let row = row?;
+        obj_ids.insert((row.frame.0, row.cam_num, row.pt_idx), row.obj_id);
+    }
+    if obj_ids.is_empty() {
+        anyhow::bail!("{} has no data association", input.display());
+    }
+
+    // The observations of each object on each frame.
+    let mut points: BTreeMap<(u32, u64), Vec<(String, DistortedPixel<f64>)>> = BTreeMap::new();
+    for row in archive.data2d_distorted_rows(RowFilter::default())? {
+        let row = row?;
+        if row.frame < 0 || row.x.is_nan() || row.y.is_nan() {
+            continue;
+        }
+        let frame = row.frame as u64;
+        let obj_id = match obj_ids.get(&(frame, row.camn, row.frame_pt_idx)) {
+            Some(obj_id) => *obj_id,
+            None => continue,
+        };
+        let cam_id = camn2camid
+            .get(&row.camn)
+            .with_context(|| format!("unknown camera number {}", row.camn.0))?;
+        points
+            .entry((obj_id, frame))
+            .or_insert_with(Vec::new)
+            .push((
+                cam_id.clone(),
+                DistortedPixel {
+                    coords: Point2::new(row.x, row.y),
+                },
+            ));
+    }
+
+    let points: Vec<_> = points.into_values().filter(|obs| obs.len() >= 2).collect();
+    let max_points = max_points.max(1);
+    let step = (points.len() + max_points - 1) / max_points;
+    let points: Vec<_> = points.into_iter().step_by(step.max(1)).collect();
+    log::info!("bundle adjusting with {} points", points.len());
+
+    let result = mvg::bundle_adjustment::bundle_adjust(&calibration.cameras, &points, opts)?;
+
+    let system = flydra_mvg::FlydraMultiCameraSystem::from_system(result.system, None);
+    let fd = std::fs::File::create(output)
+        .with_context(|| format!("Creating file {}", output.display()))?;
+    system.to_flydra_xml(fd)?;
+
+    let report_data = Report {
+        n_points: points.len(),
+        n_iterations: result.n_iterations,
+        before: result.before,
+        after: result.after,
+    };
+    let buf = serde_yaml::to_string(&report_data)?;
+    match report {
+        Some(report) => std::fs::write(report, buf)
+            .with_context(|| format!("Writing report {}", report.display()))?,
+        None => println!("{}", buf),
+    }
+    Ok(())
+}
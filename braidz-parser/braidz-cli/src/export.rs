@@ -0,0 +1,253 @@
+//! Export of the `kalman_estimates` table to formats readable without a
+//! braidz parser.
+
+use anyhow::Context;
+use std::{io::Write, path::Path};
+
+use braidz_parser::RowFilter;
+use flydra_types::KalmanEstimatesRow;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum ExportFormat {
+    Csv,
+    Npz,
+    Parquet,
+}
+
+impl std::str::FromStr for ExportFormat {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "csv" => Ok(Self::Csv),
+            "npz" => Ok(Self::Npz),
+            "parquet" => Ok(Self::Parquet),
+            _ => Err(anyhow::anyhow!(
+                "unknown export format \"{}\" (expected csv, npz or parquet)",
+                s
+            )),
+        }
+    }
+}
+
+impl ExportFormat {
+    /// Guess the format from the extension of the output filename.
+    pub(crate) fn from_path(path: &Path) -> anyhow::Result<Self> {
+        let ext = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .with_context(|| format!("no extension in output filename {}", path.display()))?;
+        ext.parse()
+    }
+}
+
+/// Names of the floating point columns, in the order of [f64_values].
+const F64_COLUMNS: [&str; 15] = [
+    "x", "y", "z", "xvel", "yvel", "zvel", "P00", "P01", "P02", "P11", "P12", "P22", "P33", "P44",
+    "P55",
+];
+
+fn f64_values(row: &KalmanEstimatesRow) -> [f64; 15] {
+    [
+        row.x, row.y, row.z, row.xvel, row.yvel, row.zvel, row.P00, row.P01, row.P02, row.P11,
+        row.P12, row.P22, row.P33, row.P44, row.P55,
+    ]
+}
+
+/// The `kalman_estimates` table, stored column-wise.
+struct Columns {
+    obj_id: Vec<u32>,
+    frame: Vec<u64>,
+    timestamp: Vec<Option<f64>>,
+    values: Vec<Vec<f64>>,
+}
+
+impl Columns {
+    fn new() -> Self {
+        Self {
+            obj_id: Vec::new(),
+            frame: Vec::new(),
+            timestamp: Vec::new(),
+            values: vec![Vec::new(); F64_COLUMNS.len()],
+        }
+    }
+
+    fn push(&mut self, row: &KalmanEstimatesRow) {
+        self.obj_id.push(row.obj_id);
+        self.frame.push(row.frame.0);
+        self.timestamp
+            .push(row.timestamp.as_ref().map(|t| t.as_f64()));
+        for (col, value) in self.values.iter_mut().zip(f64_values(row).iter()) {
+            col.push(*value);
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.obj_id.len()
+    }
+
+    fn f64_columns(&self) -> impl Iterator<Item = (&'static str, &[f64])> {
+        F64_COLUMNS
+            .iter()
+            .zip(self.values.iter())
+            .map(|(name, col)| (*name, col.as_slice()))
+    }
+}
+
+/// Export the `kalman_estimates` table of `input` to `output`.
+///
+/// Returns the number of rows written.
+pub(crate) fn export_kalman_estimates(
+    input: &Path,
+    output: &Path,
+    format: ExportFormat,
+) -> anyhow::Result<usize> {
+    let mut archive = braidz_parser::braidz_parse_path(input)
+        .with_context(|| format!("Parsing file {}", input.display()))?;
+    let rows = archive
+        .kalman_estimates_rows(RowFilter::default())
+        .with_context(|| format!("No kalman estimates in {}", input.display()))?;
+
+    if format == ExportFormat::Csv {
+        let fd = std::fs::File::create(output)
+            .with_context(|| format!("Creating file {}", output.display()))?;
+        let mut wtr = csv::Writer::from_writer(fd);
+        let mut n_rows = 0;
+        for row in rows {
+            wtr.serialize(row?)?;
+            n_rows += 1;
+        }
+        wtr.flush()?;
+        return Ok(n_rows);
+    }
+
+    let mut cols = Columns::new();
+    for row in rows {
+        cols.push(&row?);
+    }
+
+    match format {
+        ExportFormat::Csv => unreachable!(),
+        ExportFormat::Npz => write_npz(&cols, output)?,
+        ExportFormat::Parquet => write_parquet(&cols, output)?,
+    }
+    Ok(cols.len())
+}
+
+// NPZ ----------------------------------------------------------------------
+
+/// Write each column as an array in a `.npz` file (as read by `numpy.load()`).
+///
+/// A missing timestamp is stored as NaN.
+fn write_npz(cols: &Columns, output: &Path) -> anyhow::Result<()> {
+    let fd = std::fs::File::create(output)
+        .with_context(|| format!("Creating file {}", output.display()))?;
+    let mut zipw = zip::ZipWriter::new(fd);
+    let options =
+        zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+
+    zipw.start_file("obj_id.npy", options)?;
+    write_npy(&mut zipw, "<u4", cols.len(), |w| {
+        for v in cols.obj_id.iter() {
+            w.write_all(&v.to_le_bytes())?;
+        }
+        Ok(())
+    })?;
+
+    zipw.start_file("frame.npy", options)?;
+    write_npy(&mut zipw, "<u8", cols.len(), |w| {
+        for v in cols.frame.iter() {
+            w.write_all(&v.to_le_bytes())?;
+        }
+        Ok(())
+    })?;
+
+    zipw.start_file("timestamp.npy", options)?;
+    write_npy(&mut zipw, "<f8", cols.len(), |w| {
+        for v in cols.timestamp.iter() {
+            w.write_all(&v.unwrap_or(std::f64::NAN).to_le_bytes())?;
+        }
+        Ok(())
+    })?;
+
+    for (name, col) in cols.f64_columns() {
+        zipw.start_file(format!("{}.npy", name), options)?;
+        write_npy(&mut zipw, "<f8", col.len(), |w| {
+            for v in col.iter() {
+                w.write_all(&v.to_le_bytes())?;
+            }
+            Ok(())
+        })?;
+    }
+
+    zipw.finish()?;
+    Ok(())
+}
+
+/// Write a one dimensional array in `.npy` format version 1.0.
+///
+/// `descr` is the numpy dtype string (e.g. `<f8`) of the `len` elements
+/// written by `write_data`.
+fn write_npy<W, F>(w: &mut W, descr: &str, len: usize, write_data: F) -> std::io::Result<()>
+where
+    W: Write,
+    F: FnOnce(&mut W) -> std::io::Result<()>,
+{
+    const MAGIC: &[u8] = b"\x93NUMPY\x01\x00";
+    let mut header = format!(
+        "{{'descr': '{}', 'fortran_order': False, 'shape': ({},), }}",
+        descr, len
+    );
+    // The header (including magic and length) is padded with spaces to a
+    // multiple of 64 bytes and terminated by a newline.
+    let unpadded = MAGIC.len() + 2 + header.len() + 1;
+    let padding = (64 - unpadded % 64) % 64;
+    header.extend(std::iter::repeat(' ').take(padding));
+    header.push('\n');
+    let header_len: u16 = header.len() as u16;
+
+    w.write_all(MAGIC)?;
+    w.write_all(&header_len.to_le_bytes())?;
+    w.write_all(header.as_bytes())?;
+    write_data(w)
+}
+
+// Parquet ------------------------------------------------------------------
+
+#[cfg(feature = "export-parquet")]
+fn write_parquet(cols: &Columns, output: &Path) -> anyhow::Result<()> {
+    use arrow::{
+        array::{ArrayRef, Float64Array, UInt32Array, UInt64Array},
+        datatypes::{DataType, Field, Schema},
+        record_batch::RecordBatch,
+    };
+    use std::sync::Arc;
+
+    let mut fields = vec![
+        Field::new("obj_id", DataType::UInt32, false),
+        Field::new("frame", DataType::UInt64, false),
+        Field::new("timestamp", DataType::Float64, true),
+    ];
+    let mut arrays: Vec<ArrayRef> = vec![
+        Arc::new(UInt32Array::from(cols.obj_id.clone())),
+        Arc::new(UInt64Array::from(cols.frame.clone())),
+        Arc::new(Float64Array::from(cols.timestamp.clone())),
+    ];
+    for (name, col) in cols.f64_columns() {
+        fields.push(Field::new(name, DataType::Float64, false));
+        arrays.push(Arc::new(Float64Array::from(col.to_vec())));
+    }
+    let schema = Arc::new(Schema::new(fields));
+    let batch = RecordBatch::try_new(schema.clone(), arrays)?;
+
+    let fd = std::fs::File::create(output)
+        .with_context(|| format!("Creating file {}", output.display()))?;
+    let mut wtr = parquet::arrow::ArrowWriter::try_new(fd, schema, None)?;
+    wtr.write(&batch)?;
+    wtr.close()?;
+    Ok(())
+}
+
+#[cfg(not(feature = "export-parquet"))]
+fn write_parquet(_cols: &Columns, _output: &Path) -> anyhow::Result<()> {
+    anyhow::bail!("braidz-cli was compiled without the \"export-parquet\" feature")
+}
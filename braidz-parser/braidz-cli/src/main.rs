@@ -1,28 +1,590 @@
 use anyhow::Context;
-use std::path::PathBuf;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::{Path, PathBuf},
+};
 use structopt::StructOpt;
 
+use braidz_parser::RowFilter;
+
+mod bundle_adjust;
+
+mod export;
+use export::ExportFormat;
+
+mod rewrite;
+use rewrite::{BraidzWriter, RowEdits};
+
+mod stitch;
+use stitch::StitchParams;
+
 #[derive(Debug, StructOpt)]
-#[structopt(name = "braidz-cli")]
-struct Opt {
-    /// Input braidz filename
-    #[structopt(parse(from_os_str))]
-    input: PathBuf,
+#[structopt(name = "braidz-cli", about = "work with .braidz files")]
+enum Opt {
+    /// print a YAML summary of a braidz file
+    #[structopt(name = "summary")]
+    Summary {
+        /// Input braidz filename
+        #[structopt(parse(from_os_str))]
+        input: PathBuf,
+    },
+
+    /// export the kalman_estimates table to CSV, NPZ or Parquet
+    #[structopt(name = "export")]
+    Export {
+        /// Input braidz filename
+        #[structopt(parse(from_os_str))]
+        input: PathBuf,
+
+        /// Output filename
+        #[structopt(long = "output", short = "o", parse(from_os_str))]
+        output: PathBuf,
+
+        /// Output format (csv, npz or parquet, default: from output extension)
+        #[structopt(long = "format")]
+        format: Option<ExportFormat>,
+    },
+
+    /// copy a frame or time range into a new braidz file
+    #[structopt(name = "cut")]
+    Cut {
+        /// Input braidz filename
+        #[structopt(parse(from_os_str))]
+        input: PathBuf,
+
+        /// Output braidz filename
+        #[structopt(long = "output", short = "o", parse(from_os_str))]
+        output: PathBuf,
+
+        /// First frame to keep
+        #[structopt(long = "start-frame", conflicts_with_all = &["start-time", "stop-time"])]
+        start_frame: Option<u64>,
+
+        /// Last frame to keep
+        #[structopt(long = "stop-frame", conflicts_with_all = &["start-time", "stop-time"])]
+        stop_frame: Option<u64>,
+
+        /// First time to keep, in seconds since the start of the recording
+        #[structopt(long = "start-time")]
+        start_time: Option<f64>,
+
+        /// Last time to keep, in seconds since the start of the recording
+        #[structopt(long = "stop-time")]
+        stop_time: Option<f64>,
+    },
+
+    /// copy into a new braidz file without trajectories shorter than a minimum
+    #[structopt(name = "drop-short")]
+    DropShort {
+        /// Input braidz filename
+        #[structopt(parse(from_os_str))]
+        input: PathBuf,
+
+        /// Output braidz filename
+        #[structopt(long = "output", short = "o", parse(from_os_str))]
+        output: PathBuf,
+
+        /// Minimum number of frames in trajectories to keep
+        #[structopt(long = "min-frames")]
+        min_frames: usize,
+    },
+
+    /// concatenate consecutive recordings sharing a calibration
+    ///
+    /// Object ids and (if needed) frame numbers of each input are offset to
+    /// follow those of the previous input. The text log and other metadata
+    /// are taken from the first input.
+    #[structopt(name = "concat")]
+    Concat {
+        /// Input braidz filenames, in chronological order
+        #[structopt(parse(from_os_str), required = true, min_values = 2)]
+        inputs: Vec<PathBuf>,
+
+        /// Output braidz filename
+        #[structopt(long = "output", short = "o", parse(from_os_str))]
+        output: PathBuf,
+    },
+
+    /// refine the calibration by bundle adjustment of the 2D detections
+    ///
+    /// The detections associated with the same object on the same frame are
+    /// used as observations of a 3D point. The extrinsic parameters of all
+    /// cameras except the first are optimized, and optionally the intrinsic
+    /// parameters and distortion of all cameras. The refined calibration is
+    /// saved as flydra XML.
+    #[structopt(name = "bundle-adjust")]
+    BundleAdjust {
+        /// Input braidz filename
+        #[structopt(parse(from_os_str))]
+        input: PathBuf,
+
+        /// Output calibration (flydra XML) filename
+        #[structopt(long = "output", short = "o", parse(from_os_str))]
+        output: PathBuf,
+
+        /// Filename of the YAML reprojection error report (default: print)
+        #[structopt(long = "report", parse(from_os_str))]
+        report: Option<PathBuf>,
+
+        /// Also optimize focal lengths and principal points
+        #[structopt(long = "intrinsics")]
+        intrinsics: bool,
+
+        /// Also optimize lens distortion
+        #[structopt(long = "distortion")]
+        distortion: bool,
+
+        /// Maximum number of 3D points used
+        #[structopt(long = "max-points", default_value = "5000")]
+        max_points: usize,
+
+        /// Maximum number of iterations
+        #[structopt(long = "max-iterations", default_value = "100")]
+        max_iterations: usize,
+    },
+
+    /// join trajectories of objects which were briefly lost
+    ///
+    /// A trajectory is continued by a later one if the later one starts
+    /// within the given number of frames and close to the position
+    /// extrapolated from the end of the earlier one. The joined trajectories
+    /// take the object id of the first trajectory. The new object id of each
+    /// original object id is saved in the output.
+    #[structopt(name = "stitch")]
+    Stitch {
+        /// Input braidz filename
+        #[structopt(parse(from_os_str))]
+        input: PathBuf,
+
+        /// Output braidz filename
+        #[structopt(long = "output", short = "o", parse(from_os_str))]
+        output: PathBuf,
+
+        /// Maximum number of frames between the end of a trajectory and the
+        /// start of the next
+        #[structopt(long = "max-gap-frames", default_value = "20")]
+        max_gap_frames: u64,
+
+        /// Maximum distance (in meters) between the extrapolated end of a
+        /// trajectory and the start of the next
+        #[structopt(long = "max-distance", default_value = "0.02")]
+        max_distance: f64,
+    },
+
+    /// A braidz filename without a subcommand prints its summary, as
+    /// `braidz-cli summary` does.
+    #[structopt(external_subcommand)]
+    Input(Vec<std::ffi::OsString>),
 }
 
-fn main() -> anyhow::Result<()> {
-    env_logger::init();
-    let opt = Opt::from_args();
-    let attr = std::fs::metadata(&opt.input)
-        .with_context(|| format!("Getting file metadata for {}", opt.input.display()))?;
+fn summary(input: &Path) -> anyhow::Result<()> {
+    let attr = std::fs::metadata(input)
+        .with_context(|| format!("Getting file metadata for {}", input.display()))?;
 
-    let archive = braidz_parser::braidz_parse_path(&opt.input)
-        .with_context(|| format!("Parsing file {}", opt.input.display()))?;
+    let archive = braidz_parser::braidz_parse_path(input)
+        .with_context(|| format!("Parsing file {}", input.display()))?;
 
     let summary =
-        braidz_parser::summarize_braidz(&archive, opt.input.display().to_string(), attr.len());
+        braidz_parser::summarize_braidz(&archive, input.display().to_string(), attr.len());
 
     let yaml_buf = serde_yaml::to_string(&summary)?;
     println!("{}", yaml_buf);
     Ok(())
 }
+
+fn cut(
+    input: &Path,
+    output: &Path,
+    start_frame: Option<u64>,
+    stop_frame: Option<u64>,
+    start_time: Option<f64>,
+    stop_time: Option<f64>,
+) -> anyhow::Result<()> {
+    let mut archive = braidz_parser::braidz_parse_path(input)
+        .with_context(|| format!("Parsing file {}", input.display()))?;
+
+    let frames = if start_time.is_some() || stop_time.is_some() {
+        // Convert the time range to a frame range using the time of the
+        // first row in `data2d_distorted` as the start of the recording.
+        let start_time = start_time.unwrap_or(std::f64::NEG_INFINITY);
+        let stop_time = stop_time.unwrap_or(std::f64::INFINITY);
+        let mut t0 = None;
+        let mut limits: Option<(u64, u64)> = None;
+        for row in archive.data2d_distorted_rows(RowFilter::default())? {
+            let row = row?;
+            let t = row.cam_received_timestamp.as_f64();
+            let t0 = *t0.get_or_insert(t);
+            if row.frame < 0 || t - t0 < start_time || t - t0 > stop_time {
+                continue;
+            }
+            let frame = row.frame as u64;
+            limits = Some(match limits {
+                None => (frame, frame),
+                Some((lo, hi)) => (lo.min(frame), hi.max(frame)),
+            });
+        }
+        let (lo, hi) = limits.context("no data in time range")?;
+        log::info!("time range corresponds to frames {}-{}", lo, hi);
+        lo..=hi
+    } else {
+        start_frame.unwrap_or(0)..=stop_frame.unwrap_or(std::u64::MAX)
+    };
+
+    let edits = RowEdits {
+        frames: Some(frames),
+        ..Default::default()
+    };
+    let mut writer = BraidzWriter::new(output)?;
+    writer.append(&mut archive.zip_struct(), &edits)?;
+    writer.finish()
+}
+
+fn drop_short(input: &Path, output: &Path, min_frames: usize) -> anyhow::Result<()> {
+    let mut archive = braidz_parser::braidz_parse_path(input)
+        .with_context(|| format!("Parsing file {}", input.display()))?;
+
+    let mut n_frames: BTreeMap<u32, usize> = BTreeMap::new();
+    for row in archive.kalman_estimates_rows(RowFilter::default())? {
+        *n_frames.entry(row?.obj_id).or_insert(0) += 1;
+    }
+    let keep: BTreeSet<u32> = n_frames
+        .iter()
+        .filter(|(_, n)| **n >= min_frames)
+        .map(|(obj_id, _)| *obj_id)
+        .collect();
+    log::info!("keeping {} of {} trajectories", keep.len(), n_frames.len());
+
+    let edits = RowEdits {
+        obj_ids: Some(keep),
+        ..Default::default()
+    };
+    let mut writer = BraidzWriter::new(output)?;
+    writer.append(&mut archive.zip_struct(), &edits)?;
+    writer.finish()
+}
+
+fn concat(inputs: &[PathBuf], output: &Path) -> anyhow::Result<()> {
+    let mut writer = BraidzWriter::new(output)?;
+    let mut first: Option<(PathBuf, Option<Vec<u8>>, BTreeMap<_, _>)> = None;
+    let mut prev_stop_time = None;
+
+    for input in inputs.iter() {
+        let archive = braidz_parser::braidz_parse_path(input)
+            .with_context(|| format!("Parsing file {}", input.display()))?;
+        let camn2camid = archive.cam_info.camn2camid.clone();
+        let data2d_limits = archive
+            .data2d_distorted
+            .as_ref()
+            .map(|d2d| (d2d.frame_lim, d2d.time_limits));
+        let mut zs = archive.zip_struct();
+        let calibration = rewrite::read_optional(&mut zs, flydra_types::CALIBRATION_XML_FNAME)?;
+
+        match &first {
+            None => {
+                first = Some((input.clone(), calibration, camn2camid));
+            }
+            Some((first_input, first_calibration, first_camn2camid)) => {
+                if &calibration != first_calibration {
+                    anyhow::bail!(
+                        "calibration of {} differs from that of {}",
+                        input.display(),
+                        first_input.display()
+                    );
+                }
+                if &camn2camid != first_camn2camid {
+                    anyhow::bail!(
+                        "cameras of {} differ from those of {}",
+                        input.display(),
+                        first_input.display()
+                    );
+                }
+            }
+        }
+
+        let mut edits = RowEdits::default();
+        if let Some(max_obj_id) = writer.limits().max_obj_id {
+            edits.obj_id_offset = max_obj_id + 1;
+        }
+        if let Some(([start_frame, _], [start_time, stop_time])) = data2d_limits {
+            if let Some(prev_stop_time) = prev_stop_time {
+                if start_time < prev_stop_time {
+                    anyhow::bail!(
+                        "{} starts before the end of the previous recording",
+                        input.display()
+                    );
+                }
+            }
+            prev_stop_time = Some(stop_time);
+            if let Some(max_frame) = writer.limits().max_frame {
+                if start_frame <= max_frame {
+                    edits.frame_offset = max_frame + 1 - start_frame;
+                }
+            }
+        }
+
+        writer
+            .append(&mut zs, &edits)
+            .with_context(|| format!("Appending {}", input.display()))?;
+    }
+    writer.finish()
+}
+
+fn main() -> anyhow::Result<()> {
+    env_logger::init();
+    run(Opt::from_args())
+}
+
+fn run(opt: Opt) -> anyhow::Result<()> {
+    match opt {
+        Opt::Summary { input } => summary(&input),
+        Opt::Input(args) => {
+            if args.len() != 1 {
+                anyhow::bail!("expected a single braidz filename, found {:?}", args);
+            }
+            summary(Path::new(&args[0]))
+        }
+        Opt::Export {
+            input,
+            output,
+            format,
+        } => {
+            let format = match format {
+                Some(format) => format,
+                None => ExportFormat::from_path(&output)?,
+            };
+            let n_rows = export::export_kalman_estimates(&input, &output, format)?;
+            log::info!("wrote {} rows to {}", n_rows, output.display());
+            Ok(())
+        }
+        Opt::Cut {
+            input,
+            output,
+            start_frame,
+            stop_frame,
+            start_time,
+            stop_time,
+        } => cut(
+            &input,
+            &output,
+            start_frame,
+            stop_frame,
+            start_time,
+            stop_time,
+        ),
+        Opt::DropShort {
+            input,
+            output,
+            min_frames,
+        } => drop_short(&input, &output, min_frames),
+        Opt::Concat { inputs, output } => concat(&inputs, &output),
+        Opt::BundleAdjust {
+            input,
+            output,
+            report,
+            intrinsics,
+            distortion,
+            max_points,
+            max_iterations,
+        } => {
+            let opts = mvg::bundle_adjustment::BundleAdjustmentOptions {
+                optimize_intrinsics: intrinsics,
+                optimize_distortion: distortion,
+                max_iterations,
+                ..Default::default()
+            };
+            bundle_adjust::bundle_adjust(&input, &output, report.as_deref(), max_points, &opts)
+        }
+        Opt::Stitch {
+            input,
+            output,
+            max_gap_frames,
+            max_distance,
+        } => {
+            let params = StitchParams {
+                max_gap_frames,
+                max_distance,
+            };
+            let n_links = stitch::stitch(&input, &output, &params)?;
+            log::info!("joined {} pairs of trajectories", n_links);
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const URL_BASE: &str = "https://strawlab-cdn.com/assets/";
+    const FNAME: &str = "20201104_174158.braidz";
+    const SHA256SUM: &str = "d9e742336cf924f378e49055f3a709e52817ed90385c4f777f443952cf0557d6";
+
+    fn get_test_file() -> PathBuf {
+        let _ = env_logger::builder().is_test(true).try_init();
+        download_verify::download_verify(
+            format!("{}/{}", URL_BASE, FNAME).as_str(),
+            FNAME,
+            &download_verify::Hash::Sha256(SHA256SUM.into()),
+        )
+        .unwrap();
+        PathBuf::from(FNAME)
+    }
+
+    /// The number of frames of each trajectory.
+    fn trajectory_lengths(path: &Path) -> BTreeMap<u32, usize> {
+        let mut archive = braidz_parser::braidz_parse_path(path).unwrap();
+        let mut n_frames = BTreeMap::new();
+        for row in archive.kalman_estimates_rows(RowFilter::default()).unwrap() {
+            *n_frames.entry(row.unwrap().obj_id).or_insert(0) += 1;
+        }
+        n_frames
+    }
+
+    fn frame_limits(path: &Path) -> (u64, u64) {
+        let archive = braidz_parser::braidz_parse_path(path).unwrap();
+        let frame_lim = archive.data2d_distorted.as_ref().unwrap().frame_lim;
+        (frame_lim[0], frame_lim[1])
+    }
+
+    #[test]
+    fn test_filename_without_subcommand() {
+        match Opt::from_iter_safe(&["braidz-cli", "file.braidz"]).unwrap() {
+            Opt::Input(args) => assert_eq!(args, vec![std::ffi::OsString::from("file.braidz")]),
+            opt => panic!("unexpected {:?}", opt),
+        }
+        match Opt::from_iter_safe(&["braidz-cli", "summary", "file.braidz"]).unwrap() {
+            Opt::Summary { input } => assert_eq!(input, PathBuf::from("file.braidz")),
+            opt => panic!("unexpected {:?}", opt),
+        }
+    }
+
+    #[test]
+    fn test_export() {
+        let input = get_test_file();
+        let n_expected: usize = trajectory_lengths(&input).values().sum();
+        let tmpdir = tempfile::tempdir().unwrap();
+
+        let output = tmpdir.path().join("kest.csv");
+        let n_rows = export::export_kalman_estimates(&input, &output, ExportFormat::Csv).unwrap();
+        assert_eq!(n_rows, n_expected);
+        let mut rdr = csv::Reader::from_path(&output).unwrap();
+        assert_eq!(rdr.records().count(), n_expected);
+
+        let output = tmpdir.path().join("kest.npz");
+        let n_rows = export::export_kalman_estimates(&input, &output, ExportFormat::Npz).unwrap();
+        assert_eq!(n_rows, n_expected);
+        let mut npz = zip::ZipArchive::new(std::fs::File::open(&output).unwrap()).unwrap();
+        assert!(npz.by_name("obj_id.npy").is_ok());
+        assert!(npz.by_name("x.npy").is_ok());
+    }
+
+    #[test]
+    fn test_cut() {
+        let input = get_test_file();
+        let (lo, hi) = frame_limits(&input);
+        let start = lo + (hi - lo) / 4;
+        let stop = lo + (hi - lo) / 2;
+        let tmpdir = tempfile::tempdir().unwrap();
+        let output = tmpdir.path().join("cut.braidz");
+        cut(&input, &output, Some(start), Some(stop), None, None).unwrap();
+
+        let (out_lo, out_hi) = frame_limits(&output);
+        assert!(start <= out_lo && out_hi <= stop);
+        let mut archive = braidz_parser::braidz_parse_path(&output).unwrap();
+        for row in archive.kalman_estimates_rows(RowFilter::default()).unwrap() {
+            let frame = row.unwrap().frame.0;
+            assert!(start <= frame && frame <= stop);
+        }
+    }
+
+    #[test]
+    fn test_drop_short() {
+        let input = get_test_file();
+        let lengths = trajectory_lengths(&input);
+        let mut sorted: Vec<usize> = lengths.values().copied().collect();
+        sorted.sort_unstable();
+        let min_frames = sorted[sorted.len() / 2];
+
+        let tmpdir = tempfile::tempdir().unwrap();
+        let output = tmpdir.path().join("long.braidz");
+        drop_short(&input, &output, min_frames).unwrap();
+
+        let expected: BTreeMap<u32, usize> = lengths
+            .into_iter()
+            .filter(|(_, n)| *n >= min_frames)
+            .collect();
+        assert_eq!(trajectory_lengths(&output), expected);
+    }
+
+    #[test]
+    fn test_concat() {
+        let input = get_test_file();
+        let (lo, hi) = frame_limits(&input);
+        let mid = lo + (hi - lo) / 2;
+        let tmpdir = tempfile::tempdir().unwrap();
+        let first = tmpdir.path().join("first.braidz");
+        let second = tmpdir.path().join("second.braidz");
+        cut(&input, &first, None, Some(mid), None, None).unwrap();
+        // Leave a gap so that no 2D data of the second part was received
+        // before the end of the first.
+        cut(&input, &second, Some(mid + 100), None, None, None).unwrap();
+
+        let output = tmpdir.path().join("concat.braidz");
+        concat(&[first.clone(), second.clone()], &output).unwrap();
+
+        // The object ids of the second input follow those of the first.
+        let first_lengths = trajectory_lengths(&first);
+        let second_lengths = trajectory_lengths(&second);
+        let obj_id_offset = first_lengths.keys().last().map(|id| id + 1).unwrap_or(0);
+        let mut expected = first_lengths;
+        expected.extend(
+            second_lengths
+                .into_iter()
+                .map(|(obj_id, n)| (obj_id + obj_id_offset, n)),
+        );
+        assert_eq!(trajectory_lengths(&output), expected);
+        assert_eq!(
+            frame_limits(&output),
+            (frame_limits(&first).0, frame_limits(&second).1)
+        );
+
+        // The second input does not start after the first.
+        assert!(concat(&[second, first], &output).is_err());
+    }
+
+    #[test]
+    fn test_bundle_adjust() {
+        let input = get_test_file();
+        let tmpdir = tempfile::tempdir().unwrap();
+        let output = tmpdir.path().join("calibration.xml");
+        let report = tmpdir.path().join("report.yaml");
+        let opt = Opt::from_iter_safe(&[
+            "braidz-cli",
+            "bundle-adjust",
+            input.to_str().unwrap(),
+            "--output",
+            output.to_str().unwrap(),
+            "--report",
+            report.to_str().unwrap(),
+            "--max-points",
+            "200",
+            "--max-iterations",
+            "5",
+        ])
+        .unwrap();
+        run(opt).unwrap();
+
+        let archive = braidz_parser::braidz_parse_path(&input).unwrap();
+        let orig = archive.calibration_info.unwrap().cameras;
+        let refined = flydra_mvg::FlydraMultiCameraSystem::<f64>::from_flydra_xml(
+            std::fs::File::open(&output).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(refined.len(), orig.cams().len());
+
+        let report: serde_yaml::Value =
+            serde_yaml::from_str(&std::fs::read_to_string(&report).unwrap()).unwrap();
+        let rms = |when: &str| report[when]["all_cameras"]["rms"].as_f64().unwrap();
+        assert!(rms("after") <= rms("before"));
+    }
+}
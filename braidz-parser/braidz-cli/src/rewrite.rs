@@ -0,0 +1,319 @@
+//! Writing a new braidz file from selected rows of existing braid archives.
+//!
+//! The tables with per-frame data are streamed row by row (so that
+//! many-gigabyte archives are never held in memory) and may be filtered and
+//! renumbered on the way. All other files, such as the calibration, camera
+//! info and the text log, are copied unchanged from the first input.
+
+use anyhow::Context;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs::File,
+    io::{BufWriter, Read, Seek, Write},
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+};
+
+use csv_eof::EarlyEofOk;
+use libflate::{finish::AutoFinishUnchecked, gzip::Encoder};
+use zip_or_dir::ZipDirArchive;
+
+/// The tables which are rewritten row by row.
+///
+/// `camdata_stats` has no frame column, so all of its rows are kept.
+const ROW_TABLES: [&str; 8] = [
+    flydra_types::KALMAN_ESTIMATES_CSV_FNAME,
+    flydra_types::KALMAN_ESTIMATES_SMOOTHED_CSV_FNAME,
+    flydra_types::DATA_ASSOCIATE_CSV_FNAME,
+    flydra_types::DATA2D_DISTORTED_CSV_FNAME,
+    flydra_types::TRIGGER_CLOCK_INFO_CSV_FNAME,
+    flydra_types::APRILTAG_3D_CSV_FNAME,
+    flydra_types::TRIGGER_VOLUME_EVENTS_CSV_FNAME,
+    flydra_types::CAMDATA_STATS_CSV_FNAME,
+];
+
+/// Changes applied to each row while appending an input.
+///
+/// Filters apply only to tables with the relevant column, e.g. `obj_ids` does
+/// not remove any rows from `data2d_distorted`.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct RowEdits {
+    /// Keep only rows with a frame number in this range.
+    pub(crate) frames: Option<RangeInclusive<u64>>,
+    /// Keep only rows with one of these object ids.
+    pub(crate) obj_ids: Option<BTreeSet<u32>>,
+    /// Replaces the object id of each row kept. Object ids not in the map
+    /// are unchanged.
+    pub(crate) obj_id_map: Option<BTreeMap<u32, u32>>,
+    /// Added to the object id of each row kept.
+    pub(crate) obj_id_offset: u32,
+    /// Added to the frame number of each row kept.
+    pub(crate) frame_offset: u64,
+}
+
+/// The largest object id and frame number written so far.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct WrittenLimits {
+    pub(crate) max_obj_id: Option<u32>,
+    pub(crate) max_frame: Option<u64>,
+}
+
+struct TableWriter {
+    header: csv::StringRecord,
+    frame_col: Option<usize>,
+    obj_id_col: Option<usize>,
+    wtr: csv::Writer<Box<dyn Write>>,
+}
+
+/// Writes a new `.braidz` file.
+///
+/// The contents are first written to a `.braid` directory next to the output
+/// file which is zipped and removed by [BraidzWriter::finish].
+pub(crate) struct BraidzWriter {
+    output: PathBuf,
+    dirname: PathBuf,
+    tables: BTreeMap<&'static str, TableWriter>,
+    n_inputs: usize,
+    limits: WrittenLimits,
+}
+
+impl BraidzWriter {
+    pub(crate) fn new<P: AsRef<Path>>(output: P) -> anyhow::Result<Self> {
+        let output = output.as_ref().to_path_buf();
+        if output.extension().and_then(|e| e.to_str()) != Some("braidz") {
+            anyhow::bail!("output filename {} must end with .braidz", output.display());
+        }
+        if output.exists() {
+            anyhow::bail!("output {} already exists", output.display());
+        }
+        let dirname = output.with_extension("braid");
+        if dirname.exists() {
+            anyhow::bail!("temporary directory {} already exists", dirname.display());
+        }
+        std::fs::create_dir_all(&dirname)
+            .with_context(|| format!("Creating directory {}", dirname.display()))?;
+        Ok(Self {
+            output,
+            dirname,
+            tables: BTreeMap::new(),
+            n_inputs: 0,
+            limits: WrittenLimits::default(),
+        })
+    }
+
+    pub(crate) fn limits(&self) -> &WrittenLimits {
+        &self.limits
+    }
+
+    /// Append the rows of `input` (after applying `edits`) to the output.
+    ///
+    /// All tables of the inputs must have identical columns.
+    pub(crate) fn append<R: Read + Seek>(
+        &mut self,
+        input: &mut ZipDirArchive<R>,
+        edits: &RowEdits,
+    ) -> anyhow::Result<()> {
+        if self.n_inputs == 0 {
+            self.copy_other_files(input, None)?;
+        }
+        for table in ROW_TABLES.iter() {
+            self.append_table(input, table, edits)
+                .with_context(|| format!("Copying {} from {}", table, input.display()))?;
+        }
+        self.n_inputs += 1;
+        Ok(())
+    }
+
+    /// Recursively copy all files which are not in [ROW_TABLES].
+    fn copy_other_files<R: Read + Seek>(
+        &mut self,
+        input: &mut ZipDirArchive<R>,
+        relname: Option<&Path>,
+    ) -> anyhow::Result<()> {
+        let parent = relname.map(PathBuf::from).unwrap_or_default();
+        let paths = input.list_paths(relname)?;
+        for entry in paths.iter() {
+            let full_entry = parent.join(entry);
+            if input.is_file(&full_entry) {
+                if relname.is_none() && is_row_table(&full_entry) {
+                    continue;
+                }
+                let dest = self.dirname.join(&full_entry);
+                if let Some(dest_dir) = dest.parent() {
+                    std::fs::create_dir_all(dest_dir)?;
+                }
+                let mut rdr = input.open(&full_entry)?;
+                let mut wtr = File::create(&dest)
+                    .with_context(|| format!("Creating file {}", dest.display()))?;
+                std::io::copy(&mut rdr, &mut wtr)?;
+            } else {
+                self.copy_other_files(input, Some(&full_entry))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn append_table<R: Read + Seek>(
+        &mut self,
+        input: &mut ZipDirArchive<R>,
+        table: &'static str,
+        edits: &RowEdits,
+    ) -> anyhow::Result<()> {
+        let gz_fname = format!("{}.gz", table);
+        if !input.exists(Path::new(table)) && !input.exists(Path::new(&gz_fname)) {
+            // Optional tables (e.g. `kalman_estimates` without 3D tracking)
+            // may be missing.
+            return Ok(());
+        }
+
+        let mut path = input.path_starter();
+        path.push(table);
+        let rdr = braidz_parser::open_maybe_gzipped(&mut path)?;
+        let mut rdr = csv::Reader::from_reader(rdr);
+        let header = rdr.headers()?.clone();
+
+        if !self.tables.contains_key(table) {
+            let mut wtr = create_gz_csv(&self.dirname.join(&gz_fname))?;
+            wtr.write_record(&header)?;
+            let frame_col = header.iter().position(|name| name == "frame");
+            let obj_id_col = header.iter().position(|name| name == "obj_id");
+            self.tables.insert(
+                table,
+                TableWriter {
+                    header: header.clone(),
+                    frame_col,
+                    obj_id_col,
+                    wtr,
+                },
+            );
+        }
+        let tw = self.tables.get_mut(table).unwrap();
+        if tw.header != header {
+            anyhow::bail!("columns differ from those of the first input");
+        }
+
+        for record in rdr.into_records().early_eof_ok() {
+            let record = record?;
+
+            let frame: Option<i64> = match tw.frame_col {
+                Some(i) => Some(record[i].parse()?),
+                None => None,
+            };
+            let obj_id: Option<u32> = match tw.obj_id_col {
+                Some(i) => Some(record[i].parse()?),
+                None => None,
+            };
+
+            if let (Some(frames), Some(frame)) = (&edits.frames, frame) {
+                if frame < 0 || !frames.contains(&(frame as u64)) {
+                    continue;
+                }
+            }
+            if let (Some(obj_ids), Some(obj_id)) = (&edits.obj_ids, obj_id) {
+                if !obj_ids.contains(&obj_id) {
+                    continue;
+                }
+            }
+
+            let frame = frame.map(|f| f + edits.frame_offset as i64);
+            let obj_id = obj_id.map(|o| {
+                let o = match &edits.obj_id_map {
+                    Some(map) => map.get(&o).copied().unwrap_or(o),
+                    None => o,
+                };
+                o + edits.obj_id_offset
+            });
+            if let Some(frame) = frame {
+                if frame >= 0 {
+                    let frame = frame as u64;
+                    self.limits.max_frame =
+                        Some(self.limits.max_frame.map_or(frame, |m| m.max(frame)));
+                }
+            }
+            if let Some(obj_id) = obj_id {
+                self.limits.max_obj_id =
+                    Some(self.limits.max_obj_id.map_or(obj_id, |m| m.max(obj_id)));
+            }
+
+            if edits.frame_offset == 0 && edits.obj_id_offset == 0 && edits.obj_id_map.is_none() {
+                tw.wtr.write_record(&record)?;
+            } else {
+                let edited: csv::StringRecord = record
+                    .iter()
+                    .enumerate()
+                    .map(|(i, field)| {
+                        if Some(i) == tw.frame_col {
+                            frame.unwrap().to_string()
+                        } else if Some(i) == tw.obj_id_col {
+                            obj_id.unwrap().to_string()
+                        } else {
+                            field.to_string()
+                        }
+                    })
+                    .collect();
+                tw.wtr.write_record(&edited)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the additional table `table` (which must not be in [ROW_TABLES]).
+    ///
+    /// This replaces a file of the same name copied from the first input.
+    pub(crate) fn write_table<T: serde::Serialize>(
+        &mut self,
+        table: &str,
+        rows: &[T],
+    ) -> anyhow::Result<()> {
+        debug_assert!(!is_row_table(Path::new(table)));
+        let mut wtr = create_gz_csv(&self.dirname.join(format!("{}.gz", table)))?;
+        for row in rows.iter() {
+            wtr.serialize(row)?;
+        }
+        wtr.flush()?;
+        Ok(())
+    }
+
+    /// Finish writing, zip the output and check that it can be parsed.
+    pub(crate) fn finish(self) -> anyhow::Result<()> {
+        for (_table, mut tw) in self.tables.into_iter() {
+            // The gzip stream is finished when the writer is dropped.
+            tw.wtr.flush()?;
+        }
+
+        zip_or_dir::copy_to_zip(&self.dirname, &self.output)
+            .with_context(|| format!("Creating {}", self.output.display()))?;
+        std::fs::remove_dir_all(&self.dirname)
+            .with_context(|| format!("Removing directory {}", self.dirname.display()))?;
+
+        braidz_parser::braidz_parse_path(&self.output)
+            .with_context(|| format!("Parsing newly written {}", self.output.display()))?;
+        Ok(())
+    }
+}
+
+/// Create a gzipped CSV file at `dest`.
+fn create_gz_csv(dest: &Path) -> anyhow::Result<csv::Writer<Box<dyn Write>>> {
+    let fd = File::create(dest).with_context(|| format!("Creating file {}", dest.display()))?;
+    let fd: Box<dyn Write> = Box::new(AutoFinishUnchecked::new(Encoder::new(BufWriter::new(fd))?));
+    Ok(csv::Writer::from_writer(fd))
+}
+
+fn is_row_table(relname: &Path) -> bool {
+    ROW_TABLES
+        .iter()
+        .any(|table| relname == Path::new(table) || relname == Path::new(&format!("{}.gz", table)))
+}
+
+/// Read the file `relname` from `archive` if it exists.
+pub(crate) fn read_optional<R: Read + Seek>(
+    archive: &mut ZipDirArchive<R>,
+    relname: &str,
+) -> anyhow::Result<Option<Vec<u8>>> {
+    if !archive.exists(Path::new(relname)) {
+        return Ok(None);
+    }
+    let mut buf = Vec::new();
+    archive.open(relname)?.read_to_end(&mut buf)?;
+    Ok(Some(buf))
+}
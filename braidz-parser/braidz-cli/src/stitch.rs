@@ -0,0 +1,301 @@
+//! Joining trajectories of objects which were briefly lost.
+//!
+//! When an object is lost during tracking, its model is killed and a new
+//! object id is born once it is found again. Here, the end of each trajectory
+//! is extrapolated with its last velocity estimate and linked to a later
+//! birth close to the extrapolated position.
+
+use anyhow::Context;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io::{Read, Seek},
+    path::Path,
+};
+
+use braidz_parser::RowFilter;
+use csv_eof::EarlyEofOk;
+use flydra_types::{ObjIdRemappingRow, OBJ_ID_REMAPPING_CSV_FNAME};
+use zip_or_dir::ZipDirArchive;
+
+use crate::rewrite::{BraidzWriter, RowEdits};
+
+/// Limits on which trajectories may be joined.
+#[derive(Debug, Clone)]
+pub(crate) struct StitchParams {
+    /// Maximum number of frames between the end of a trajectory and the
+    /// start of the next.
+    pub(crate) max_gap_frames: u64,
+    /// Maximum distance (in meters) between the extrapolated end of a
+    /// trajectory and the start of the next.
+    pub(crate) max_distance: f64,
+}
+
+/// A position (and velocity) at a frame.
+#[derive(Debug, Clone)]
+struct Sample {
+    frame: u64,
+    pos: [f64; 3],
+    vel: [f64; 3],
+}
+
+/// The first and last samples of a trajectory.
+#[derive(Debug, Clone)]
+struct TrajectoryEnds {
+    first: Sample,
+    last: Sample,
+}
+
+/// Join trajectories of `input` and write the result to `output`.
+///
+/// Returns the number of links between trajectories.
+pub(crate) fn stitch(input: &Path, output: &Path, params: &StitchParams) -> anyhow::Result<usize> {
+    let mut archive = braidz_parser::braidz_parse_path(input)
+        .with_context(|| format!("Parsing file {}", input.display()))?;
+    let fps = archive.expected_fps;
+    if !fps.is_finite() || fps <= 0.0 {
+        anyhow::bail!("frame rate of {} unknown", input.display());
+    }
+
+    let mut ends: BTreeMap<u32, TrajectoryEnds> = BTreeMap::new();
+    for row in archive.kalman_estimates_rows(RowFilter::default())? {
+        let row = row?;
+        let sample = Sample {
+            frame: row.frame.0,
+            pos: [row.x, row.y, row.z],
+            vel: [row.xvel, row.yvel, row.zvel],
+        };
+        match ends.get_mut(&row.obj_id) {
+            Some(e) => {
+                if sample.frame < e.first.frame {
+                    e.first = sample;
+                } else if sample.frame > e.last.frame {
+                    e.last = sample;
+                }
+            }
+            None => {
+                ends.insert(
+                    row.obj_id,
+                    TrajectoryEnds {
+                        first: sample.clone(),
+                        last: sample,
+                    },
+                );
+            }
+        }
+    }
+
+    let links = find_links(&ends, fps, params);
+    let obj_id_map = chain_roots(&links);
+
+    let mut zs = archive.zip_struct();
+
+    // Compose with the remapping of an input which was already stitched.
+    let previous = read_remapping(&mut zs)?;
+    let mut remapping: Vec<ObjIdRemappingRow> = match previous {
+        Some(previous) => previous
+            .into_iter()
+            .map(|row| ObjIdRemappingRow {
+                orig_obj_id: row.orig_obj_id,
+                obj_id: obj_id_map.get(&row.obj_id).copied().unwrap_or(row.obj_id),
+            })
+            .collect(),
+        None => ends
+            .keys()
+            .map(|obj_id| ObjIdRemappingRow {
+                orig_obj_id: *obj_id,
+                obj_id: obj_id_map.get(obj_id).copied().unwrap_or(*obj_id),
+            })
+            .collect(),
+    };
+    remapping.sort_by_key(|row| row.orig_obj_id);
+
+    let edits = RowEdits {
+        obj_id_map: Some(obj_id_map),
+        ..Default::default()
+    };
+    let mut writer = BraidzWriter::new(output)?;
+    writer.append(&mut zs, &edits)?;
+    writer.write_table(OBJ_ID_REMAPPING_CSV_FNAME, &remapping)?;
+    writer.finish()?;
+    Ok(links.len())
+}
+
+/// Find which trajectory continues which other trajectory.
+///
+/// Returns a map from the object id of the later trajectory to the object id
+/// of the earlier one. Candidate links are accepted in order of increasing
+/// distance, such that each trajectory has at most one predecessor and one
+/// successor.
+fn find_links(
+    ends: &BTreeMap<u32, TrajectoryEnds>,
+    fps: f64,
+    params: &StitchParams,
+) -> BTreeMap<u32, u32> {
+    let mut candidates = Vec::new();
+    for (prev_id, prev) in ends.iter() {
+        for (next_id, next) in ends.iter() {
+            if next.first.frame <= prev.last.frame {
+                continue;
+            }
+            let gap = next.first.frame - prev.last.frame;
+            if gap > params.max_gap_frames {
+                continue;
+            }
+            let dt = gap as f64 / fps;
+            let dist_sq: f64 = (0..3)
+                .map(|i| {
+                    let predicted = prev.last.pos[i] + prev.last.vel[i] * dt;
+                    (predicted - next.first.pos[i]).powi(2)
+                })
+                .sum();
+            let dist = dist_sq.sqrt();
+            if dist <= params.max_distance {
+                candidates.push((dist, *prev_id, *next_id));
+            }
+        }
+    }
+    candidates.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+    let mut has_successor = BTreeSet::new();
+    let mut links = BTreeMap::new();
+    for (dist, prev_id, next_id) in candidates.into_iter() {
+        if has_successor.contains(&prev_id) || links.contains_key(&next_id) {
+            continue;
+        }
+        log::debug!("linking obj_id {} to {} ({:.4} m)", next_id, prev_id, dist);
+        has_successor.insert(prev_id);
+        links.insert(next_id, prev_id);
+    }
+    links
+}
+
+/// Map each object id in `links` to the first object id of its chain.
+fn chain_roots(links: &BTreeMap<u32, u32>) -> BTreeMap<u32, u32> {
+    let mut result = BTreeMap::new();
+    for next_id in links.keys() {
+        // Links always go back in time, so there are no cycles.
+        let mut root = *next_id;
+        while let Some(prev_id) = links.get(&root) {
+            root = *prev_id;
+        }
+        result.insert(*next_id, root);
+    }
+    result
+}
+
+/// Read the remapping table of `archive` if it exists.
+fn read_remapping<R: Read + Seek>(
+    archive: &mut ZipDirArchive<R>,
+) -> anyhow::Result<Option<Vec<ObjIdRemappingRow>>> {
+    let gz_fname = format!("{}.gz", OBJ_ID_REMAPPING_CSV_FNAME);
+    if !archive.exists(Path::new(OBJ_ID_REMAPPING_CSV_FNAME))
+        && !archive.exists(Path::new(&gz_fname))
+    {
+        return Ok(None);
+    }
+    let mut path = archive.path_starter();
+    path.push(OBJ_ID_REMAPPING_CSV_FNAME);
+    let rdr = braidz_parser::open_maybe_gzipped(&mut path)?;
+    let rdr = csv::Reader::from_reader(rdr);
+    let mut rows = Vec::new();
+    for row in rdr.into_deserialize().early_eof_ok() {
+        rows.push(row?);
+    }
+    Ok(Some(rows))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const FPS: f64 = 100.0;
+
+    fn params() -> StitchParams {
+        StitchParams {
+            max_gap_frames: 10,
+            max_distance: 0.01,
+        }
+    }
+
+    /// A trajectory from `first_frame` to `last_frame` moving along x at 1 m/s
+    /// and ending at `last_x`.
+    fn trajectory(first_frame: u64, last_frame: u64, last_x: f64) -> TrajectoryEnds {
+        let vel = [1.0, 0.0, 0.0];
+        let first_x = last_x - (last_frame - first_frame) as f64 / FPS;
+        TrajectoryEnds {
+            first: Sample {
+                frame: first_frame,
+                pos: [first_x, 0.0, 0.0],
+                vel,
+            },
+            last: Sample {
+                frame: last_frame,
+                pos: [last_x, 0.0, 0.0],
+                vel,
+            },
+        }
+    }
+
+    #[test]
+    fn test_find_links_extrapolates() {
+        let mut ends = BTreeMap::new();
+        ends.insert(1, trajectory(0, 100, 1.0));
+        // Starts 5 frames later where object 1 is expected.
+        ends.insert(2, trajectory(105, 200, 2.0));
+        // Starts close to the end of object 2, but too late.
+        ends.insert(3, trajectory(211, 300, 3.11));
+        // Starts where object 2 is expected, but before object 2 ends.
+        ends.insert(4, trajectory(150, 250, 2.5));
+
+        let links = find_links(&ends, FPS, &params());
+        let expected: BTreeMap<u32, u32> = vec![(2, 1)].into_iter().collect();
+        assert_eq!(links, expected);
+    }
+
+    #[test]
+    fn test_find_links_branching() {
+        let mut ends = BTreeMap::new();
+        // Two candidate successors of object 1. Object 3 is closer to where
+        // object 1 is expected.
+        ends.insert(1, trajectory(0, 100, 1.0));
+        ends.insert(2, trajectory(102, 200, 2.0 + 0.005));
+        ends.insert(3, trajectory(102, 200, 2.0 + 0.001));
+        // Two candidate predecessors of object 6. Object 5 is closer.
+        ends.insert(4, trajectory(300, 400, 4.0 - 0.004));
+        ends.insert(5, trajectory(300, 400, 4.0 - 0.002));
+        ends.insert(6, trajectory(402, 500, 5.0));
+
+        let links = find_links(&ends, FPS, &params());
+        let expected: BTreeMap<u32, u32> = vec![(3, 1), (6, 5)].into_iter().collect();
+        assert_eq!(links, expected);
+    }
+
+    #[test]
+    fn test_find_links_chain() {
+        let mut ends = BTreeMap::new();
+        ends.insert(1, trajectory(0, 100, 1.0));
+        ends.insert(2, trajectory(102, 200, 2.0));
+        ends.insert(3, trajectory(202, 300, 3.0));
+
+        let links = find_links(&ends, FPS, &params());
+        let expected: BTreeMap<u32, u32> = vec![(2, 1), (3, 2)].into_iter().collect();
+        assert_eq!(links, expected);
+
+        let roots = chain_roots(&links);
+        let expected: BTreeMap<u32, u32> = vec![(2, 1), (3, 1)].into_iter().collect();
+        assert_eq!(roots, expected);
+    }
+
+    #[test]
+    fn test_chain_roots() {
+        let links: BTreeMap<u32, u32> = vec![(2, 1), (3, 2), (4, 3), (7, 5), (9, 8)]
+            .into_iter()
+            .collect();
+        let roots = chain_roots(&links);
+        let expected: BTreeMap<u32, u32> = vec![(2, 1), (3, 1), (4, 1), (7, 5), (9, 8)]
+            .into_iter()
+            .collect();
+        assert_eq!(roots, expected);
+        assert!(chain_roots(&BTreeMap::new()).is_empty());
+    }
+}
@@ -409,4 +409,34 @@ impl<R: Read + Seek, S: ParseState> IncrementalParser<R, S> {
     pub fn path_starter(&mut self) -> zip_or_dir::PathLike<R> {
         self.archive.path_starter()
     }
+
+    /// Iterate over the rows of the `kalman_estimates` table.
+    ///
+    /// This does not require the archive to have been parsed.
+    pub fn kalman_estimates_rows(
+        &mut self,
+        filter: RowFilter,
+    ) -> Result<RowIter<'_, KalmanEstimatesRow>, Error> {
+        crate::rows::iter_rows(&mut self.archive, filter)
+    }
+
+    /// Iterate over the rows of the `data2d_distorted` table.
+    ///
+    /// This does not require the archive to have been parsed.
+    pub fn data2d_distorted_rows(
+        &mut self,
+        filter: RowFilter,
+    ) -> Result<RowIter<'_, Data2dDistortedRow>, Error> {
+        crate::rows::iter_rows(&mut self.archive, filter)
+    }
+
+    /// Iterate over the rows of the `data_association` table.
+    ///
+    /// This does not require the archive to have been parsed.
+    pub fn data_association_rows(
+        &mut self,
+        filter: RowFilter,
+    ) -> Result<RowIter<'_, DataAssocRow>, Error> {
+        crate::rows::iter_rows(&mut self.archive, filter)
+    }
 }
@@ -12,16 +12,21 @@ use std::{
 
 use hdrhistogram::serialization::interval_log;
 
-use flydra_types::{FlydraFloatTimestampLocal, HostClock, TextlogRow, TrackingParams};
+use flydra_types::{
+    AprilTag3dRow, CamdataStatsRow, FlydraFloatTimestampLocal, HostClock, TextlogRow,
+    TrackingParams,
+};
 
 use braidz_types::{
     BraidMetadata, BraidzSummary, CalibrationInfo, CamInfo, CamInfoRow, CamNum, Data2dDistortedRow,
-    Data2dSummary, HistogramSummary, KalmanEstimatesRow, KalmanEstimatesSummary,
+    Data2dSummary, DataAssocRow, HistogramSummary, KalmanEstimatesRow, KalmanEstimatesSummary,
 };
 
 use csv_eof::EarlyEofOk;
 
 pub mod incremental_parser;
+pub mod rows;
+pub use rows::{RowFilter, RowIter};
 
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
@@ -156,6 +161,48 @@ impl<R: Read + Seek> BraidzArchive<R> {
     pub fn zip_struct(self) -> zip_or_dir::ZipDirArchive<R> {
         self.archive
     }
+
+    /// Iterate over the rows of the `kalman_estimates` table.
+    pub fn kalman_estimates_rows(
+        &mut self,
+        filter: RowFilter,
+    ) -> Result<RowIter<'_, KalmanEstimatesRow>, Error> {
+        rows::iter_rows(&mut self.archive, filter)
+    }
+
+    /// Iterate over the rows of the `data2d_distorted` table.
+    pub fn data2d_distorted_rows(
+        &mut self,
+        filter: RowFilter,
+    ) -> Result<RowIter<'_, Data2dDistortedRow>, Error> {
+        rows::iter_rows(&mut self.archive, filter)
+    }
+
+    /// Iterate over the rows of the `data_association` table.
+    pub fn data_association_rows(
+        &mut self,
+        filter: RowFilter,
+    ) -> Result<RowIter<'_, DataAssocRow>, Error> {
+        rows::iter_rows(&mut self.archive, filter)
+    }
+
+    /// Iterate over the rows of the `apriltag_3d` table.
+    pub fn apriltag_3d_rows(
+        &mut self,
+        filter: RowFilter,
+    ) -> Result<RowIter<'_, AprilTag3dRow>, Error> {
+        rows::iter_rows(&mut self.archive, filter)
+    }
+
+    /// Iterate over the rows of the `camdata_stats` table.
+    ///
+    /// This table has no frame numbers, so [RowFilter::frames] is ignored.
+    pub fn camdata_stats_rows(
+        &mut self,
+        filter: RowFilter,
+    ) -> Result<RowIter<'_, CamdataStatsRow>, Error> {
+        rows::iter_rows(&mut self.archive, filter)
+    }
 }
 
 pub struct D2DInfo {
@@ -0,0 +1,308 @@
+//! Lazy, typed iterators over the tables stored in a braid archive.
+//!
+//! The tables are read directly from the archive, whether plain `.csv` or
+//! compressed `.csv.gz` and whether the archive is a `.braidz` zip file or a
+//! `.braid` directory. Nothing is unpacked to disk and rows are deserialized
+//! only as the iterator advances.
+
+use std::{
+    collections::BTreeSet,
+    io::{Read, Seek},
+    ops::RangeInclusive,
+    path::Path,
+};
+
+use csv_eof::{EarlyEofOk, TerminateEarlyOnUnexpectedEof};
+use serde::de::DeserializeOwned;
+
+use flydra_types::{
+    AprilTag3dRow, CamNum, CamdataStatsRow, Data2dDistortedRow, DataAssocRow, KalmanEstimatesRow,
+};
+
+use crate::Error;
+
+/// Selects which rows are returned by a [RowIter].
+///
+/// Each criterion which is `None` does not restrict the rows returned. A
+/// criterion is ignored for tables which do not contain the relevant column
+/// (e.g. `obj_ids` for the `data2d_distorted` table).
+#[derive(Debug, Clone, Default)]
+pub struct RowFilter {
+    /// Only return rows with one of these object ids.
+    pub obj_ids: Option<BTreeSet<u32>>,
+    /// Only return rows with a frame number in this range.
+    pub frames: Option<RangeInclusive<u64>>,
+    /// Only return rows from one of these cameras.
+    pub cameras: Option<BTreeSet<CamNum>>,
+}
+
+impl RowFilter {
+    fn accept<T: FilterableRow>(&self, row: &T) -> bool {
+        if let (Some(obj_ids), Some(obj_id)) = (&self.obj_ids, row.obj_id()) {
+            if !obj_ids.contains(&obj_id) {
+                return false;
+            }
+        }
+        if let (Some(frames), true) = (&self.frames, T::HAS_FRAME) {
+            // Negative frame numbers (which should not exist) never match.
+            match row.frame() {
+                Some(frame) if frames.contains(&frame) => {}
+                _ => return false,
+            }
+        }
+        if let (Some(cameras), Some(camn)) = (&self.cameras, row.camn()) {
+            if !cameras.contains(&camn) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// A row of a braid table which can be selected with a [RowFilter].
+pub trait FilterableRow: DeserializeOwned {
+    /// The name of the table in the archive, without `.gz` suffix.
+    const CSV_FNAME: &'static str;
+    /// Whether the table has a frame column.
+    const HAS_FRAME: bool = true;
+    fn obj_id(&self) -> Option<u32>;
+    fn frame(&self) -> Option<u64>;
+    fn camn(&self) -> Option<CamNum>;
+}
+
+impl FilterableRow for KalmanEstimatesRow {
+    const CSV_FNAME: &'static str = flydra_types::KALMAN_ESTIMATES_CSV_FNAME;
+    fn obj_id(&self) -> Option<u32> {
+        Some(self.obj_id)
+    }
+    fn frame(&self) -> Option<u64> {
+        Some(self.frame.0)
+    }
+    fn camn(&self) -> Option<CamNum> {
+        None
+    }
+}
+
+impl FilterableRow for Data2dDistortedRow {
+    const CSV_FNAME: &'static str = flydra_types::DATA2D_DISTORTED_CSV_FNAME;
+    fn obj_id(&self) -> Option<u32> {
+        None
+    }
+    fn frame(&self) -> Option<u64> {
+        if self.frame < 0 {
+            None
+        } else {
+            Some(self.frame as u64)
+        }
+    }
+    fn camn(&self) -> Option<CamNum> {
+        Some(self.camn)
+    }
+}
+
+impl FilterableRow for DataAssocRow {
+    const CSV_FNAME: &'static str = flydra_types::DATA_ASSOCIATE_CSV_FNAME;
+    fn obj_id(&self) -> Option<u32> {
+        Some(self.obj_id)
+    }
+    fn frame(&self) -> Option<u64> {
+        Some(self.frame.0)
+    }
+    fn camn(&self) -> Option<CamNum> {
+        Some(self.cam_num)
+    }
+}
+
+impl FilterableRow for AprilTag3dRow {
+    const CSV_FNAME: &'static str = flydra_types::APRILTAG_3D_CSV_FNAME;
+    fn obj_id(&self) -> Option<u32> {
+        None
+    }
+    fn frame(&self) -> Option<u64> {
+        Some(self.frame.0)
+    }
+    fn camn(&self) -> Option<CamNum> {
+        None
+    }
+}
+
+impl FilterableRow for CamdataStatsRow {
+    const CSV_FNAME: &'static str = flydra_types::CAMDATA_STATS_CSV_FNAME;
+    const HAS_FRAME: bool = false;
+    fn obj_id(&self) -> Option<u32> {
+        None
+    }
+    fn frame(&self) -> Option<u64> {
+        None
+    }
+    fn camn(&self) -> Option<CamNum> {
+        Some(self.camn)
+    }
+}
+
+type CsvRows<'a, T> =
+    TerminateEarlyOnUnexpectedEof<csv::DeserializeRecordsIntoIter<Box<dyn Read + 'a>, T>, T>;
+
+/// A lazy iterator over the rows of a table in a braid archive.
+///
+/// A truncated final row (e.g. because braid quit unexpectedly) ends the
+/// iteration without error.
+pub struct RowIter<'a, T: FilterableRow> {
+    rows: CsvRows<'a, T>,
+    filter: RowFilter,
+}
+
+impl<'a, T: FilterableRow> Iterator for RowIter<'a, T> {
+    type Item = Result<T, Error>;
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.rows.next()? {
+                Ok(row) => {
+                    if self.filter.accept(&row) {
+                        return Some(Ok(row));
+                    }
+                }
+                Err(e) => return Some(Err(e.into())),
+            }
+        }
+    }
+}
+
+/// Iterate over the rows of a table in `archive`.
+///
+/// Returns an error of [Error::ZipOrDir] with source
+/// [zip_or_dir::Error::FileNotFound] if the table does not exist.
+pub fn iter_rows<R: Read + Seek, T: FilterableRow>(
+    archive: &mut zip_or_dir::ZipDirArchive<R>,
+    filter: RowFilter,
+) -> Result<RowIter<'_, T>, Error> {
+    let rdr = open_table(archive, T::CSV_FNAME)?;
+    let rows = csv::Reader::from_reader(rdr)
+        .into_deserialize()
+        .early_eof_ok();
+    Ok(RowIter { rows, filter })
+}
+
+/// Open the `.csv` file `fname` (if it exists), else the `.csv.gz` file.
+///
+/// Unlike [crate::open_maybe_gzipped], the returned reader borrows only the
+/// archive and thus can outlive the caller.
+fn open_table<'a, R: Read + Seek>(
+    archive: &'a mut zip_or_dir::ZipDirArchive<R>,
+    fname: &str,
+) -> Result<Box<dyn Read + 'a>, Error> {
+    let gz_fname = format!("{}.gz", fname);
+    let have_plain = archive.exists(Path::new(fname));
+    let have_gz = archive.exists(Path::new(&gz_fname));
+    match (have_plain, have_gz) {
+        (true, true) => Err(Error::DualData),
+        (true, false) => Ok(Box::new(archive.open(fname)?)),
+        (false, true) => {
+            let gz_fd = archive.open(&gz_fname)?;
+            Ok(Box::new(libflate::gzip::Decoder::new(gz_fd)?))
+        }
+        (false, false) => Err(zip_or_dir::Error::FileNotFound.into()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn tag_row(frame: u64, tag_id: i32) -> AprilTag3dRow {
+        AprilTag3dRow {
+            frame: flydra_types::SyncFno(frame),
+            timestamp: None,
+            family: "36h11".to_string(),
+            tag_id,
+            x: 0.1,
+            y: 0.2,
+            z: 0.3,
+            qw: 1.0,
+            qx: 0.0,
+            qy: 0.0,
+            qz: 0.0,
+            corner0_x: 0.0,
+            corner0_y: 0.0,
+            corner0_z: 0.0,
+            corner1_x: 0.0,
+            corner1_y: 0.0,
+            corner1_z: 0.0,
+            corner2_x: 0.0,
+            corner2_y: 0.0,
+            corner2_z: 0.0,
+            corner3_x: 0.0,
+            corner3_y: 0.0,
+            corner3_z: 0.0,
+            n_cams: 2,
+            mean_reproj_dist: 0.5,
+        }
+    }
+
+    #[test]
+    fn test_apriltag_3d_rows() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        {
+            let path = tmpdir.path().join(flydra_types::APRILTAG_3D_CSV_FNAME);
+            let mut wtr = csv::Writer::from_path(path).unwrap();
+            for frame in 10..15 {
+                wtr.serialize(tag_row(frame, 3)).unwrap();
+            }
+            wtr.flush().unwrap();
+        }
+
+        let mut archive =
+            zip_or_dir::ZipDirArchive::<std::fs::File>::from_dir(tmpdir.path().to_path_buf())
+                .unwrap();
+
+        let filter = RowFilter {
+            frames: Some(11..=12),
+            ..Default::default()
+        };
+        let rows: Vec<AprilTag3dRow> = iter_rows(&mut archive, filter)
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].frame.0, 11);
+        assert_eq!(rows[0].tag_id, 3);
+        assert_eq!(rows[1].x, 0.1);
+    }
+
+    #[test]
+    fn test_camdata_stats_rows() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        {
+            let path = tmpdir.path().join(flydra_types::CAMDATA_STATS_CSV_FNAME);
+            let mut wtr = csv::Writer::from_path(path).unwrap();
+            for i in 0..6 {
+                wtr.serialize(CamdataStatsRow {
+                    timestamp: flydra_types::FlydraFloatTimestampLocal::from_f64(i as f64),
+                    camn: CamNum(i % 2),
+                    frames_received: 100,
+                    frames_lost: i as usize,
+                    sync_jitter: None,
+                })
+                .unwrap();
+            }
+            wtr.flush().unwrap();
+        }
+
+        let mut archive =
+            zip_or_dir::ZipDirArchive::<std::fs::File>::from_dir(tmpdir.path().to_path_buf())
+                .unwrap();
+
+        // The table has no frames, so this part of the filter is ignored.
+        let filter = RowFilter {
+            frames: Some(11..=12),
+            cameras: Some(std::iter::once(CamNum(1)).collect()),
+            ..Default::default()
+        };
+        let rows: Vec<CamdataStatsRow> = iter_rows(&mut archive, filter)
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        let lost: Vec<usize> = rows.iter().map(|row| row.frames_lost).collect();
+        assert_eq!(lost, vec![1, 3, 5]);
+    }
+}
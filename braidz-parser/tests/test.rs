@@ -45,3 +45,66 @@ fn test_20191125_104254() {
     let archive = braidz_parser::braidz_parse_path(&FILE2_FNAME).unwrap();
     let _summary = braidz_parser::summarize_braidz(&archive, FILE2_FNAME.to_string(), attr.len());
 }
+
+#[test]
+fn test_row_iterators() {
+    const FILE1_FNAME: &str = "20201104_174158.braidz";
+    const FILE1_SHA256SUM: &str =
+        "d9e742336cf924f378e49055f3a709e52817ed90385c4f777f443952cf0557d6";
+
+    init();
+
+    download_verify::download_verify(
+        format!("{}/{}", URL_BASE, FILE1_FNAME).as_str(),
+        FILE1_FNAME,
+        &download_verify::Hash::Sha256(FILE1_SHA256SUM.into()),
+    )
+    .unwrap();
+
+    let mut archive = braidz_parser::braidz_parse_path(&FILE1_FNAME).unwrap();
+
+    let (num_rows, first_traj) = {
+        let kest = archive.kalman_estimates_info.as_ref().unwrap();
+        let (obj_id, traj) = kest.trajectories.iter().next().unwrap();
+        (
+            kest.num_rows,
+            (*obj_id, traj.position.len(), traj.start_frame),
+        )
+    };
+
+    // All rows are returned without a filter.
+    let all_rows = archive
+        .kalman_estimates_rows(braidz_parser::RowFilter::default())
+        .unwrap();
+    assert_eq!(all_rows.count() as u64, num_rows);
+
+    // Filter by obj_id.
+    let (obj_id, traj_len, start_frame) = first_traj;
+    let mut filter = braidz_parser::RowFilter::default();
+    filter.obj_ids = Some(std::iter::once(obj_id).collect());
+    let rows: Vec<_> = archive
+        .kalman_estimates_rows(filter)
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(rows.len(), traj_len);
+    assert!(rows.iter().all(|row| row.obj_id == obj_id));
+
+    // Filter by frame.
+    let mut filter = braidz_parser::RowFilter::default();
+    filter.frames = Some(start_frame..=start_frame);
+    for row in archive.kalman_estimates_rows(filter).unwrap() {
+        assert_eq!(row.unwrap().frame.0, start_frame);
+    }
+
+    // Filter 2D data by camera.
+    let camn = *archive.cam_info.camn2camid.keys().next().unwrap();
+    let mut filter = braidz_parser::RowFilter::default();
+    filter.cameras = Some(std::iter::once(camn).collect());
+    let mut n_2d = 0;
+    for row in archive.data2d_distorted_rows(filter).unwrap() {
+        assert_eq!(row.unwrap().camn, camn);
+        n_2d += 1;
+    }
+    assert!(n_2d > 0);
+}
@@ -3,7 +3,7 @@ use std::collections::BTreeMap;
 use serde::{Deserialize, Serialize};
 
 pub use flydra_types::{
-    CamInfoRow, CamNum, Data2dDistortedRow, KalmanEstimatesRow, TrackingParams,
+    CamInfoRow, CamNum, Data2dDistortedRow, DataAssocRow, KalmanEstimatesRow, TrackingParams,
 };
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
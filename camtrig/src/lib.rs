@@ -1,4 +1,5 @@
 use bytes::buf::Buf;
+use std::io::{Read, Write};
 use tokio_util::codec::{Decoder, Encoder};
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -62,3 +63,52 @@ impl Encoder<camtrig_comms::ToDevice> for CamtrigCodec {
         Ok(())
     }
 }
+
+/// Read messages from the camtrig device at `port` and pass each to `on_msg`.
+///
+/// Reading continues while `is_alive` returns true. Read timeouts are ignored,
+/// so `port` should have a timeout for `is_alive` to be checked regularly.
+/// Returns on the first read or decoding error.
+pub fn read_device_messages<R, A, F>(mut port: R, is_alive: A, mut on_msg: F) -> Result<()>
+where
+    R: Read,
+    A: Fn() -> bool,
+    F: FnMut(camtrig_comms::FromDevice),
+{
+    let mut codec = CamtrigCodec::new();
+    let mut buf = bytes::BytesMut::with_capacity(1000);
+    let mut read_buf = [0; 100];
+    while is_alive() {
+        match port.read(&mut read_buf[..]) {
+            Ok(n_bytes) => {
+                buf.extend_from_slice(&read_buf[..n_bytes]);
+                while let Some(msg) = codec.decode(&mut buf)? {
+                    on_msg(msg);
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::TimedOut => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(())
+}
+
+/// Write the messages returned by `next_msg` to the camtrig device at `port`.
+///
+/// Writing continues until `next_msg` returns `None`. Returns on the first
+/// write error.
+pub fn write_device_messages<W, F>(mut port: W, mut next_msg: F) -> Result<()>
+where
+    W: Write,
+    F: FnMut() -> Option<camtrig_comms::ToDevice>,
+{
+    let mut codec = CamtrigCodec::new();
+    let mut buf = bytes::BytesMut::with_capacity(1000);
+    while let Some(msg) = next_msg() {
+        log::debug!("sending message to camtrig device: {:?}", msg);
+        codec.encode(msg, &mut buf)?;
+        port.write_all(&buf)?;
+        buf.clear();
+    }
+    Ok(())
+}
@@ -7,8 +7,12 @@ extern crate ci2_aravis as backend;
 extern crate ci2_dc1394 as backend;
 #[cfg(feature = "backend_flycap2")]
 extern crate ci2_flycap2 as backend;
+#[cfg(feature = "backend_playback")]
+extern crate ci2_playback as backend;
 #[cfg(feature = "backend_pyloncxx")]
 extern crate ci2_pyloncxx as backend;
+#[cfg(feature = "backend_sim")]
+extern crate ci2_sim as backend;
 
 use structopt::StructOpt;
 
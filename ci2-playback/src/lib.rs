@@ -0,0 +1,646 @@
+//! Playback of recordings as ci2 cameras.
+//!
+//! Each recording (an FMF, ufmf or MKV file or a directory of PNG or JPEG
+//! images) is made available as a camera named after the file. This allows
+//! running the live tracking of strand-cam on recorded data. The recordings
+//! are given by environment variables:
+//!
+//! - `CI2_PLAYBACK_FILES`: the paths of the recordings, separated like the
+//!   paths of `PATH` (required).
+//! - `CI2_PLAYBACK_SPEED`: the playback speed relative to real time (default
+//!   `1`). With `0`, frames are delivered as fast as possible.
+//! - `CI2_PLAYBACK_LOOP`: if `1` (or `true`), restart at the end of the
+//!   recording.
+//! - `CI2_PLAYBACK_FRAME_RATE`: the frame rate of image directories, which
+//!   have no timestamps (default `30`).
+//!
+//! The recorded timestamps are used as host timestamps. When looping, the
+//! timestamps of later passes are shifted to continue after the end of the
+//! previous pass. MKV files are decoded with `ffmpeg`, which must be
+//! installed.
+#![cfg_attr(feature = "backtrace", feature(backtrace))]
+
+extern crate machine_vision_formats as formats;
+
+#[cfg(feature = "backtrace")]
+use std::backtrace::Backtrace;
+
+use chrono::{DateTime, Utc};
+use std::path::PathBuf;
+use std::time::Instant;
+
+use basic_frame::DynamicFrame;
+use ci2::{AcquisitionMode, AutoMode, TriggerMode, TriggerSelector};
+use timestamped_frame::HostTimeData;
+
+mod source;
+
+use source::{FrameSource, RecordedFrame};
+
+pub const FILES_ENV_VAR: &str = "CI2_PLAYBACK_FILES";
+pub const SPEED_ENV_VAR: &str = "CI2_PLAYBACK_SPEED";
+pub const LOOP_ENV_VAR: &str = "CI2_PLAYBACK_LOOP";
+pub const FRAME_RATE_ENV_VAR: &str = "CI2_PLAYBACK_FRAME_RATE";
+
+const DEFAULT_IMAGE_FRAME_RATE: f64 = 30.0;
+
+pub type Result<M> = std::result::Result<M, Error>;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("io error: {source}")]
+    IoError {
+        #[from]
+        source: std::io::Error,
+        #[cfg(feature = "backtrace")]
+        backtrace: Backtrace,
+    },
+    #[error("FMF error: {source}")]
+    FmfError {
+        #[from]
+        source: fmf::FMFError,
+        #[cfg(feature = "backtrace")]
+        backtrace: Backtrace,
+    },
+    #[error("ufmf error: {source}")]
+    UfmfError {
+        #[from]
+        source: ufmf::UFMFError,
+        #[cfg(feature = "backtrace")]
+        backtrace: Backtrace,
+    },
+    #[error("image error: {source}")]
+    ImageError {
+        #[from]
+        source: image::ImageError,
+        #[cfg(feature = "backtrace")]
+        backtrace: Backtrace,
+    },
+    #[error("JSON error: {source}")]
+    JsonError {
+        #[from]
+        source: serde_json::Error,
+        #[cfg(feature = "backtrace")]
+        backtrace: Backtrace,
+    },
+    #[error("ffmpeg error: {0}")]
+    FfmpegError(String),
+    #[error("unknown type of file {0}")]
+    UnknownFileType(String),
+    #[error("recording {0} is empty")]
+    EmptyRecording(String),
+    #[error("image size or format changed at {0}")]
+    FormatChanged(String),
+    #[error("unsupported image format {0}")]
+    UnsupportedImage(String),
+    #[error("other error: {msg}")]
+    OtherError {
+        msg: String,
+        #[cfg(feature = "backtrace")]
+        backtrace: Backtrace,
+    },
+}
+
+impl From<Error> for ci2::Error {
+    fn from(orig: Error) -> ci2::Error {
+        ci2::Error::BackendError(orig.into())
+    }
+}
+
+/// How fast frames are delivered.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Pacing {
+    /// Deliver frames according to their timestamps, with the given speed
+    /// relative to real time.
+    RealTime { speed: f64 },
+    /// Deliver frames without waiting.
+    AsFastAsPossible,
+}
+
+/// The recordings and how to play them.
+#[derive(Debug, Clone)]
+pub struct PlaybackConfig {
+    pub files: Vec<PathBuf>,
+    pub pacing: Pacing,
+    pub looping: bool,
+    /// The frame rate of image directories.
+    pub image_frame_rate: f64,
+}
+
+fn other_error(msg: String) -> Error {
+    Error::OtherError {
+        msg,
+        #[cfg(feature = "backtrace")]
+        backtrace: Backtrace::capture(),
+    }
+}
+
+fn parse_env<T: std::str::FromStr>(name: &str) -> Result<Option<T>> {
+    match std::env::var(name) {
+        Ok(value) => match value.parse() {
+            Ok(value) => Ok(Some(value)),
+            Err(_) => Err(other_error(format!("cannot parse {}=\"{}\"", name, value))),
+        },
+        Err(_) => Ok(None),
+    }
+}
+
+impl PlaybackConfig {
+    /// Read the configuration from the environment variables.
+    pub fn from_env() -> Result<Self> {
+        let files: Vec<PathBuf> = match std::env::var_os(FILES_ENV_VAR) {
+            Some(paths) => std::env::split_paths(&paths).collect(),
+            None => Vec::new(),
+        };
+        if files.is_empty() {
+            return Err(other_error(format!(
+                "no recordings given (set {})",
+                FILES_ENV_VAR
+            )));
+        }
+        let speed = parse_env::<f64>(SPEED_ENV_VAR)?.unwrap_or(1.0);
+        let pacing = if speed > 0.0 {
+            Pacing::RealTime { speed }
+        } else {
+            Pacing::AsFastAsPossible
+        };
+        let looping = match std::env::var(LOOP_ENV_VAR) {
+            Ok(value) => matches!(value.to_lowercase().as_str(), "1" | "true" | "yes"),
+            Err(_) => false,
+        };
+        let image_frame_rate =
+            parse_env::<f64>(FRAME_RATE_ENV_VAR)?.unwrap_or(DEFAULT_IMAGE_FRAME_RATE);
+        Ok(Self {
+            files,
+            pacing,
+            looping,
+            image_frame_rate,
+        })
+    }
+}
+
+/// The camera name of a recording.
+fn camera_name(path: &std::path::Path) -> String {
+    let name = if path.is_dir() {
+        path.file_name()
+    } else {
+        path.file_stem()
+    };
+    name.map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| path.display().to_string())
+}
+
+pub struct WrappedModule {
+    cfg: PlaybackConfig,
+    names: Vec<String>,
+}
+
+pub fn new_module() -> ci2::Result<WrappedModule> {
+    Ok(WrappedModule::new(PlaybackConfig::from_env()?)?)
+}
+
+impl WrappedModule {
+    pub fn new(cfg: PlaybackConfig) -> Result<Self> {
+        let mut names: Vec<String> = Vec::with_capacity(cfg.files.len());
+        for path in cfg.files.iter() {
+            let name = camera_name(path);
+            if names.contains(&name) {
+                return Err(other_error(format!(
+                    "more than one recording named {}",
+                    name
+                )));
+            }
+            names.push(name);
+        }
+        Ok(Self { cfg, names })
+    }
+}
+
+impl ci2::CameraModule for WrappedModule {
+    type CameraType = WrappedCamera;
+
+    fn name(&self) -> &str {
+        "playback"
+    }
+    fn camera_infos(&self) -> ci2::Result<Vec<Box<dyn ci2::CameraInfo>>> {
+        let infos = self
+            .names
+            .iter()
+            .map(|name| {
+                let pci = Box::new(PlaybackCameraInfo { name: name.clone() });
+                let ci: Box<dyn ci2::CameraInfo> = pci; // explicitly perform type erasure
+                ci
+            })
+            .collect();
+        Ok(infos)
+    }
+    fn camera(&mut self, name: &str) -> ci2::Result<Self::CameraType> {
+        let idx = self
+            .names
+            .iter()
+            .position(|n| n == name)
+            .ok_or_else(|| other_error(format!("requested camera '{}' was not found", name)))?;
+        let path = &self.cfg.files[idx];
+        let source = source::open(path, self.cfg.image_frame_rate)?;
+        log::info!("playing {} as camera {}", path.display(), name);
+        Ok(WrappedCamera::new(name.to_string(), source, &self.cfg))
+    }
+}
+
+#[derive(Debug)]
+struct PlaybackCameraInfo {
+    name: String,
+}
+
+impl ci2::CameraInfo for PlaybackCameraInfo {
+    fn name(&self) -> &str {
+        &self.name
+    }
+    fn serial(&self) -> &str {
+        &self.name
+    }
+    fn model(&self) -> &str {
+        "playback"
+    }
+    fn vendor(&self) -> &str {
+        "ci2-playback"
+    }
+}
+
+/// The reference for pacing frames during an acquisition.
+struct Acquisition {
+    /// When the first frame was delivered and its timestamp.
+    first: Option<(Instant, DateTime<Utc>)>,
+}
+
+pub struct WrappedCamera {
+    name: String,
+    source: Box<dyn FrameSource>,
+    pacing: Pacing,
+    looping: bool,
+    exposure_time: f64,
+    exposure_auto: AutoMode,
+    gain: f64,
+    gain_auto: AutoMode,
+    trigger_mode: TriggerMode,
+    trigger_selector: TriggerSelector,
+    acquisition_mode: AcquisitionMode,
+    acquisition: Option<Acquisition>,
+    next_framenumber: usize,
+    /// Added to the recorded timestamps of the current pass.
+    loop_offset: chrono::Duration,
+    last_timestamp: Option<DateTime<Utc>>,
+    /// The latest interval between frames.
+    interval: chrono::Duration,
+}
+
+fn _test_camera_is_send() {
+    // Compile-time test to ensure WrappedCamera implements Send trait.
+    fn implements<T: Send>() {}
+    implements::<WrappedCamera>();
+}
+
+impl WrappedCamera {
+    fn new(name: String, source: Box<dyn FrameSource>, cfg: &PlaybackConfig) -> Self {
+        let interval = chrono::Duration::nanoseconds((1e9 / cfg.image_frame_rate) as i64);
+        Self {
+            name,
+            source,
+            pacing: cfg.pacing,
+            looping: cfg.looping,
+            exposure_time: 10_000.0,
+            exposure_auto: AutoMode::Off,
+            gain: 0.0,
+            gain_auto: AutoMode::Off,
+            trigger_mode: TriggerMode::Off,
+            trigger_selector: TriggerSelector::FrameStart,
+            acquisition_mode: AcquisitionMode::Continuous,
+            acquisition: None,
+            next_framenumber: 0,
+            loop_offset: chrono::Duration::zero(),
+            last_timestamp: None,
+            interval,
+        }
+    }
+
+    /// Read the next frame, restarting the recording if looping.
+    ///
+    /// Returns the frame and its (shifted) timestamp.
+    fn read_frame(&mut self) -> ci2::Result<(RecordedFrame, DateTime<Utc>)> {
+        let mut rewound = false;
+        loop {
+            match self.source.next_frame()? {
+                Some(frame) => {
+                    if rewound {
+                        if let Some(last) = self.last_timestamp {
+                            self.loop_offset = last + self.interval - frame.timestamp;
+                        }
+                    }
+                    let timestamp = frame.timestamp + self.loop_offset;
+                    if let Some(last) = self.last_timestamp {
+                        if timestamp > last {
+                            self.interval = timestamp - last;
+                        }
+                    }
+                    self.last_timestamp = Some(timestamp);
+                    return Ok((frame, timestamp));
+                }
+                None => {
+                    if !self.looping || rewound {
+                        return Err(ci2::Error::from(format!("end of recording {}", self.name)));
+                    }
+                    log::debug!("{}: restarting recording", self.name);
+                    self.source.rewind()?;
+                    rewound = true;
+                }
+            }
+        }
+    }
+}
+
+impl ci2::CameraInfo for WrappedCamera {
+    fn name(&self) -> &str {
+        &self.name
+    }
+    fn serial(&self) -> &str {
+        &self.name
+    }
+    fn model(&self) -> &str {
+        "playback"
+    }
+    fn vendor(&self) -> &str {
+        "ci2-playback"
+    }
+}
+
+impl ci2::Camera for WrappedCamera {
+    /// Return the sensor width in pixels
+    fn width(&self) -> ci2::Result<u32> {
+        Ok(self.source.width())
+    }
+    /// Return the sensor height in pixels
+    fn height(&self) -> ci2::Result<u32> {
+        Ok(self.source.height())
+    }
+
+    // Settings: PixFmt ----------------------------
+    fn pixel_format(&self) -> ci2::Result<formats::PixFmt> {
+        Ok(self.source.pixel_format())
+    }
+    fn possible_pixel_formats(&self) -> ci2::Result<Vec<formats::PixFmt>> {
+        Ok(vec![self.source.pixel_format()])
+    }
+    fn set_pixel_format(&mut self, pixel_format: formats::PixFmt) -> ci2::Result<()> {
+        if pixel_format != self.source.pixel_format() {
+            return Err(ci2::Error::from(format!(
+                "recording {} has pixel format {}",
+                self.name,
+                self.source.pixel_format()
+            )));
+        }
+        Ok(())
+    }
+
+    // The following settings are stored but have no effect on the recorded
+    // images.
+
+    // Settings: Exposure Time ----------------------------
+    /// value given in microseconds
+    fn exposure_time(&self) -> ci2::Result<f64> {
+        Ok(self.exposure_time)
+    }
+    /// value given in microseconds
+    fn exposure_time_range(&self) -> ci2::Result<(f64, f64)> {
+        Ok((self.exposure_time, self.exposure_time))
+    }
+    /// value given in microseconds
+    fn set_exposure_time(&mut self, value: f64) -> ci2::Result<()> {
+        self.exposure_time = value;
+        Ok(())
+    }
+
+    // Settings: Exposure Time Auto Mode ----------------------------
+    fn exposure_auto(&self) -> ci2::Result<AutoMode> {
+        Ok(self.exposure_auto)
+    }
+    fn set_exposure_auto(&mut self, value: AutoMode) -> ci2::Result<()> {
+        self.exposure_auto = value;
+        Ok(())
+    }
+
+    // Settings: Gain ----------------------------
+    /// value given in dB
+    fn gain(&self) -> ci2::Result<f64> {
+        Ok(self.gain)
+    }
+    /// value given in dB
+    fn gain_range(&self) -> ci2::Result<(f64, f64)> {
+        Ok((self.gain, self.gain))
+    }
+    /// value given in dB
+    fn set_gain(&mut self, value: f64) -> ci2::Result<()> {
+        self.gain = value;
+        Ok(())
+    }
+
+    // Settings: Gain Auto Mode ----------------------------
+    fn gain_auto(&self) -> ci2::Result<AutoMode> {
+        Ok(self.gain_auto)
+    }
+    fn set_gain_auto(&mut self, value: AutoMode) -> ci2::Result<()> {
+        self.gain_auto = value;
+        Ok(())
+    }
+
+    // Settings: TriggerMode ----------------------------
+    fn trigger_mode(&self) -> ci2::Result<TriggerMode> {
+        Ok(self.trigger_mode)
+    }
+    fn set_trigger_mode(&mut self, value: TriggerMode) -> ci2::Result<()> {
+        self.trigger_mode = value;
+        Ok(())
+    }
+
+    // Settings: AcquisitionFrameRateEnable ----------------------------
+    fn acquisition_frame_rate_enable(&self) -> ci2::Result<bool> {
+        Ok(false)
+    }
+    fn set_acquisition_frame_rate_enable(&mut self, value: bool) -> ci2::Result<()> {
+        if value {
+            return Err(ci2::Error::from(
+                "frame rate is given by recording, cannot limit",
+            ));
+        }
+        Ok(())
+    }
+
+    // Settings: AcquisitionFrameRate ----------------------------
+    fn acquisition_frame_rate(&self) -> ci2::Result<f64> {
+        Err(ci2::Error::from("frame rate is given by recording"))
+    }
+    fn acquisition_frame_rate_range(&self) -> ci2::Result<(f64, f64)> {
+        Err(ci2::Error::from("frame rate is given by recording"))
+    }
+    fn set_acquisition_frame_rate(&mut self, _value: f64) -> ci2::Result<()> {
+        Err(ci2::Error::from("frame rate is given by recording"))
+    }
+
+    // Settings: TriggerSelector ----------------------------
+    fn trigger_selector(&self) -> ci2::Result<TriggerSelector> {
+        Ok(self.trigger_selector)
+    }
+    fn set_trigger_selector(&mut self, value: TriggerSelector) -> ci2::Result<()> {
+        self.trigger_selector = value;
+        Ok(())
+    }
+
+    // Settings: AcquisitionMode ----------------------------
+    fn acquisition_mode(&self) -> ci2::Result<AcquisitionMode> {
+        Ok(self.acquisition_mode)
+    }
+    fn set_acquisition_mode(&mut self, value: AcquisitionMode) -> ci2::Result<()> {
+        self.acquisition_mode = value;
+        Ok(())
+    }
+
+    // Acquisition ----------------------------
+    fn acquisition_start(&mut self) -> ci2::Result<()> {
+        self.acquisition = Some(Acquisition { first: None });
+        Ok(())
+    }
+    fn acquisition_stop(&mut self) -> ci2::Result<()> {
+        self.acquisition = None;
+        Ok(())
+    }
+
+    /// synchronous (blocking) frame acquisition
+    fn next_frame(&mut self) -> ci2::Result<DynamicFrame> {
+        if self.acquisition.is_none() {
+            return Err(ci2::Error::from("acquisition not started"));
+        }
+        let (frame, timestamp) = self.read_frame()?;
+
+        if let Some(acquisition) = self.acquisition.as_mut() {
+            match (self.pacing, acquisition.first) {
+                (Pacing::RealTime { speed }, Some((instant0, timestamp0))) => {
+                    let dt = (timestamp - timestamp0).to_std().unwrap_or_default();
+                    let deadline = instant0 + dt.div_f64(speed);
+                    let now = Instant::now();
+                    if deadline > now {
+                        std::thread::sleep(deadline - now);
+                    }
+                }
+                (_, None) => {
+                    acquisition.first = Some((Instant::now(), timestamp));
+                }
+                (Pacing::AsFastAsPossible, Some(_)) => {}
+            }
+        }
+        if self.acquisition_mode == AcquisitionMode::SingleFrame {
+            self.acquisition = None;
+        }
+
+        let host_framenumber = self.next_framenumber;
+        self.next_framenumber += 1;
+
+        let pixel_format = self.source.pixel_format();
+        let extra = Box::new(PlaybackExtra {
+            host_timestamp: timestamp,
+            host_framenumber,
+            pixel_format,
+        });
+        Ok(DynamicFrame::new(
+            self.source.width(),
+            self.source.height(),
+            frame.stride,
+            extra,
+            frame.image_data,
+            pixel_format,
+        ))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct PlaybackExtra {
+    host_timestamp: DateTime<Utc>,
+    host_framenumber: usize,
+    pub pixel_format: formats::PixFmt,
+}
+
+impl HostTimeData for PlaybackExtra {
+    fn host_framenumber(&self) -> usize {
+        self.host_framenumber
+    }
+    fn host_timestamp(&self) -> DateTime<Utc> {
+        self.host_timestamp
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use basic_frame::{BasicExtra, BasicFrame};
+    use ci2::{Camera, CameraModule};
+    use formats::pixel_format::Mono8;
+    use timestamped_frame::ExtraTimeData;
+
+    fn frame(value: u8, host_timestamp: DateTime<Utc>) -> BasicFrame<Mono8> {
+        BasicFrame {
+            width: 4,
+            height: 2,
+            stride: 4,
+            image_data: vec![value; 8],
+            extra: Box::new(BasicExtra {
+                host_timestamp,
+                host_framenumber: 0,
+            }),
+            pixel_format: std::marker::PhantomData,
+        }
+    }
+
+    #[test]
+    fn test_fmf_playback_looping() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cam1.fmf");
+        let t0 = Utc::now();
+        let dt = chrono::Duration::milliseconds(10);
+        {
+            let fd = std::fs::File::create(&path).unwrap();
+            let mut writer = fmf::FMFWriter::new(fd).unwrap();
+            for i in 0u8..3 {
+                writer.write(&frame(i, t0), t0 + dt * i as i32).unwrap();
+            }
+            writer.close().unwrap();
+        }
+
+        let cfg = PlaybackConfig {
+            files: vec![path],
+            pacing: Pacing::AsFastAsPossible,
+            looping: true,
+            image_frame_rate: DEFAULT_IMAGE_FRAME_RATE,
+        };
+        let mut module = WrappedModule::new(cfg).unwrap();
+        let infos = module.camera_infos().unwrap();
+        assert_eq!(infos.len(), 1);
+        assert_eq!(infos[0].name(), "cam1");
+
+        let mut cam = module.camera("cam1").unwrap();
+        assert_eq!(cam.width().unwrap(), 4);
+        assert!(cam.next_frame().is_err());
+        cam.acquisition_start().unwrap();
+
+        let mut previous: Option<DateTime<Utc>> = None;
+        for i in 0..7 {
+            let frame = cam.next_frame().unwrap();
+            assert_eq!(frame.extra().host_framenumber(), i);
+            assert_eq!(frame.image_data_without_format()[0], (i % 3) as u8);
+            let timestamp = frame.extra().host_timestamp();
+            if let Some(previous) = previous {
+                // Recorded timestamps are stored with microsecond precision.
+                let diff = (timestamp - previous - dt).num_microseconds().unwrap();
+                assert!(diff.abs() <= 1, "diff {}", diff);
+            }
+            previous = Some(timestamp);
+        }
+    }
+}
@@ -0,0 +1,500 @@
+//! Readers of the supported recording formats.
+
+use std::{
+    io::Read,
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+};
+
+use chrono::{DateTime, Utc};
+use formats::PixFmt;
+
+use basic_frame::DynamicFrame;
+use timestamped_frame::ExtraTimeData;
+
+use crate::{Error, Result};
+
+/// A frame read from a recording.
+pub(crate) struct RecordedFrame {
+    pub(crate) image_data: Vec<u8>,
+    pub(crate) stride: u32,
+    pub(crate) timestamp: DateTime<Utc>,
+}
+
+/// A recording which is read frame by frame.
+pub(crate) trait FrameSource: Send {
+    fn width(&self) -> u32;
+    fn height(&self) -> u32;
+    fn pixel_format(&self) -> PixFmt;
+    /// Read the next frame, returning `None` at the end of the recording.
+    fn next_frame(&mut self) -> Result<Option<RecordedFrame>>;
+    /// Continue reading with the first frame.
+    fn rewind(&mut self) -> Result<()>;
+}
+
+/// Open the recording at `path`, choosing the reader by the file extension.
+///
+/// A directory is read as a sequence of images, in the order of their
+/// filenames, at `image_frame_rate`.
+pub(crate) fn open(path: &Path, image_frame_rate: f64) -> Result<Box<dyn FrameSource>> {
+    if path.is_dir() {
+        return Ok(Box::new(ImageDirSource::new(path, image_frame_rate)?));
+    }
+    let extension = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_lowercase());
+    match extension.as_deref() {
+        Some("fmf") => Ok(Box::new(FmfSource::new(path)?)),
+        Some("ufmf") => Ok(Box::new(UfmfSource::new(path)?)),
+        Some("mkv") | Some("webm") => Ok(Box::new(MkvSource::new(path)?)),
+        _ => Err(Error::UnknownFileType(path.display().to_string())),
+    }
+}
+
+fn to_recorded(frame: DynamicFrame) -> RecordedFrame {
+    let timestamp = frame.extra().host_timestamp();
+    let height = frame.height();
+    let (image_data, _) = frame.into_data_extra();
+    let stride = if height > 0 {
+        image_data.len() as u32 / height
+    } else {
+        0
+    };
+    RecordedFrame {
+        image_data,
+        stride,
+        timestamp,
+    }
+}
+
+struct FmfSource {
+    reader: fmf::FMFReader,
+    next: usize,
+}
+
+impl FmfSource {
+    fn new(path: &Path) -> Result<Self> {
+        Ok(Self {
+            reader: fmf::FMFReader::new(path)?,
+            next: 0,
+        })
+    }
+}
+
+impl FrameSource for FmfSource {
+    fn width(&self) -> u32 {
+        self.reader.width()
+    }
+    fn height(&self) -> u32 {
+        self.reader.height()
+    }
+    fn pixel_format(&self) -> PixFmt {
+        self.reader.format()
+    }
+    fn next_frame(&mut self) -> Result<Option<RecordedFrame>> {
+        if self.next >= self.reader.frame_count() {
+            return Ok(None);
+        }
+        let frame = self.reader.get_frame(self.next)?;
+        self.next += 1;
+        Ok(Some(to_recorded(frame)))
+    }
+    fn rewind(&mut self) -> Result<()> {
+        self.next = 0;
+        Ok(())
+    }
+}
+
+struct UfmfSource {
+    reader: ufmf::UFMFReader<std::io::BufReader<std::fs::File>>,
+    next: usize,
+}
+
+impl UfmfSource {
+    fn new(path: &Path) -> Result<Self> {
+        Ok(Self {
+            reader: ufmf::UFMFReader::open(path)?,
+            next: 0,
+        })
+    }
+}
+
+impl FrameSource for UfmfSource {
+    fn width(&self) -> u32 {
+        self.reader.width()
+    }
+    fn height(&self) -> u32 {
+        self.reader.height()
+    }
+    fn pixel_format(&self) -> PixFmt {
+        self.reader.format()
+    }
+    fn next_frame(&mut self) -> Result<Option<RecordedFrame>> {
+        if self.next >= self.reader.frame_count() {
+            return Ok(None);
+        }
+        let frame = self.reader.get_frame(self.next)?;
+        self.next += 1;
+        Ok(Some(to_recorded(frame)))
+    }
+    fn rewind(&mut self) -> Result<()> {
+        self.next = 0;
+        Ok(())
+    }
+}
+
+/// A directory of images (PNG or JPEG).
+///
+/// The images have no timestamps, so these are computed from the time the
+/// directory was opened and the frame rate.
+struct ImageDirSource {
+    paths: Vec<PathBuf>,
+    width: u32,
+    height: u32,
+    pixel_format: PixFmt,
+    start: DateTime<Utc>,
+    frame_rate: f64,
+    next: usize,
+}
+
+impl ImageDirSource {
+    fn new(dir: &Path, frame_rate: f64) -> Result<Self> {
+        let mut paths = Vec::new();
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            let is_image = path
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .map(|ext| {
+                    let ext = ext.to_lowercase();
+                    ext == "png" || ext == "jpg" || ext == "jpeg"
+                })
+                .unwrap_or(false);
+            if is_image {
+                paths.push(path);
+            }
+        }
+        paths.sort();
+        let first = paths
+            .first()
+            .ok_or_else(|| Error::EmptyRecording(dir.display().to_string()))?;
+        let (width, height, pixel_format, _) = read_image(first)?;
+        Ok(Self {
+            paths,
+            width,
+            height,
+            pixel_format,
+            start: Utc::now(),
+            frame_rate,
+            next: 0,
+        })
+    }
+}
+
+/// Read an image as Mono8 (if it is grayscale) or RGB8.
+///
+/// Images with more than 8 bits per sample are not supported.
+fn read_image(path: &Path) -> Result<(u32, u32, PixFmt, Vec<u8>)> {
+    use image::DynamicImage::*;
+    let image = image::open(path)?;
+    Ok(match image {
+        ImageLuma8(_) | ImageLumaA8(_) => {
+            let gray = image.to_luma8();
+            let (width, height) = gray.dimensions();
+            (width, height, PixFmt::Mono8, gray.into_vec())
+        }
+        ImageRgb8(_) | ImageRgba8(_) | ImageBgr8(_) | ImageBgra8(_) => {
+            let rgb = image.to_rgb8();
+            let (width, height) = rgb.dimensions();
+            (width, height, PixFmt::RGB8, rgb.into_vec())
+        }
+        image => {
+            return Err(Error::UnsupportedImage(format!(
+                "{} ({:?})",
+                path.display(),
+                image.color()
+            )))
+        }
+    })
+}
+
+impl FrameSource for ImageDirSource {
+    fn width(&self) -> u32 {
+        self.width
+    }
+    fn height(&self) -> u32 {
+        self.height
+    }
+    fn pixel_format(&self) -> PixFmt {
+        self.pixel_format
+    }
+    fn next_frame(&mut self) -> Result<Option<RecordedFrame>> {
+        let path = match self.paths.get(self.next) {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+        let (width, height, pixel_format, image_data) = read_image(path)?;
+        if (width, height, pixel_format) != (self.width, self.height, self.pixel_format) {
+            return Err(Error::FormatChanged(path.display().to_string()));
+        }
+        let t = self.next as f64 / self.frame_rate;
+        let timestamp = self.start + chrono::Duration::nanoseconds((t * 1e9).round() as i64);
+        self.next += 1;
+        let stride = image_data.len() as u32 / height;
+        Ok(Some(RecordedFrame {
+            image_data,
+            stride,
+            timestamp,
+        }))
+    }
+    fn rewind(&mut self) -> Result<()> {
+        self.next = 0;
+        Ok(())
+    }
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct ProbeOutput {
+    #[serde(default)]
+    packets: Vec<ProbePacket>,
+    #[serde(default)]
+    streams: Vec<ProbeStream>,
+    format: Option<ProbeFormat>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct ProbePacket {
+    pts_time: Option<String>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct ProbeStream {
+    width: u32,
+    height: u32,
+    pix_fmt: String,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct ProbeFormat {
+    #[serde(default)]
+    tags: std::collections::BTreeMap<String, String>,
+}
+
+/// A matroska (MKV) file decoded by `ffmpeg`.
+///
+/// The timestamp of a frame is the time of the start of the recording (which
+/// strand-cam saves as `DateUTC`) plus the presentation time of the frame.
+struct MkvSource {
+    path: PathBuf,
+    width: u32,
+    height: u32,
+    pixel_format: PixFmt,
+    timestamps: Vec<DateTime<Utc>>,
+    decoder: Option<Child>,
+    next: usize,
+}
+
+impl MkvSource {
+    fn new(path: &Path) -> Result<Self> {
+        let output = Command::new("ffprobe")
+            .args(&["-v", "error", "-select_streams", "v:0", "-show_entries"])
+            .arg("stream=width,height,pix_fmt:format_tags=creation_time:packet=pts_time")
+            .args(&["-of", "json"])
+            .arg(path)
+            .output()
+            .map_err(|e| Error::FfmpegError(format!("running ffprobe: {}", e)))?;
+        if !output.status.success() {
+            return Err(Error::FfmpegError(
+                String::from_utf8_lossy(&output.stderr).into_owned(),
+            ));
+        }
+        let probe: ProbeOutput = serde_json::from_slice(&output.stdout)?;
+        let stream = probe
+            .streams
+            .first()
+            .ok_or_else(|| Error::EmptyRecording(path.display().to_string()))?;
+        let pixel_format = if stream.pix_fmt.starts_with("gray") {
+            PixFmt::Mono8
+        } else {
+            PixFmt::RGB8
+        };
+
+        let creation_time = probe
+            .format
+            .as_ref()
+            .and_then(|format| format.tags.get("creation_time"))
+            .and_then(|t| DateTime::parse_from_rfc3339(t).ok())
+            .map(|t| t.with_timezone(&Utc));
+        let start = match creation_time {
+            Some(start) => start,
+            None => {
+                log::warn!(
+                    "{} has no creation time, timestamps start now",
+                    path.display()
+                );
+                Utc::now()
+            }
+        };
+        // Packets are in decoding order, frames in presentation order.
+        let mut pts: Vec<f64> = probe
+            .packets
+            .iter()
+            .filter_map(|packet| packet.pts_time.as_ref())
+            .filter_map(|t| t.parse().ok())
+            .collect();
+        let n_packets = pts.len();
+        pts.retain(|t| t.is_finite());
+        if pts.len() < n_packets {
+            log::warn!(
+                "{}: ignoring {} packets with invalid timestamps",
+                path.display(),
+                n_packets - pts.len()
+            );
+        }
+        pts.sort_by(|a, b| a.partial_cmp(b).expect("finite timestamps"));
+        let timestamps = pts
+            .into_iter()
+            .map(|t| start + chrono::Duration::nanoseconds((t * 1e9).round() as i64))
+            .collect();
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            width: stream.width,
+            height: stream.height,
+            pixel_format,
+            timestamps,
+            decoder: None,
+            next: 0,
+        })
+    }
+
+    fn bytes_per_pixel(&self) -> u32 {
+        match self.pixel_format {
+            PixFmt::Mono8 => 1,
+            _ => 3,
+        }
+    }
+
+    fn spawn_decoder(&self) -> Result<Child> {
+        let pix_fmt = match self.pixel_format {
+            PixFmt::Mono8 => "gray",
+            _ => "rgb24",
+        };
+        Command::new("ffmpeg")
+            .args(&["-v", "error", "-i"])
+            .arg(&self.path)
+            .args(&[
+                "-vsync",
+                "passthrough",
+                "-f",
+                "rawvideo",
+                "-pix_fmt",
+                pix_fmt,
+                "-",
+            ])
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| Error::FfmpegError(format!("running ffmpeg: {}", e)))
+    }
+
+    fn stop_decoder(&mut self) {
+        if let Some(mut decoder) = self.decoder.take() {
+            decoder.kill().ok();
+            decoder.wait().ok();
+        }
+    }
+}
+
+impl Drop for MkvSource {
+    fn drop(&mut self) {
+        self.stop_decoder();
+    }
+}
+
+impl FrameSource for MkvSource {
+    fn width(&self) -> u32 {
+        self.width
+    }
+    fn height(&self) -> u32 {
+        self.height
+    }
+    fn pixel_format(&self) -> PixFmt {
+        self.pixel_format
+    }
+    fn next_frame(&mut self) -> Result<Option<RecordedFrame>> {
+        let timestamp = match self.timestamps.get(self.next) {
+            Some(timestamp) => *timestamp,
+            None => return Ok(None),
+        };
+        if self.decoder.is_none() {
+            self.decoder = Some(self.spawn_decoder()?);
+        }
+        let stride = self.width * self.bytes_per_pixel();
+        let mut image_data = vec![0; (stride * self.height) as usize];
+        let stdout = self
+            .decoder
+            .as_mut()
+            .and_then(|decoder| decoder.stdout.as_mut());
+        let result = match stdout {
+            Some(stdout) => stdout.read_exact(&mut image_data),
+            None => return Err(Error::FfmpegError("no ffmpeg output".into())),
+        };
+        match result {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                log::warn!(
+                    "{}: decoded {} of {} frames",
+                    self.path.display(),
+                    self.next,
+                    self.timestamps.len()
+                );
+                self.stop_decoder();
+                return Ok(None);
+            }
+            Err(e) => return Err(e.into()),
+        }
+        self.next += 1;
+        Ok(Some(RecordedFrame {
+            image_data,
+            stride,
+            timestamp,
+        }))
+    }
+    fn rewind(&mut self) -> Result<()> {
+        self.stop_decoder();
+        self.next = 0;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_image_depth() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let path = dir.path().join("mono8.png");
+        image::GrayImage::from_raw(4, 2, vec![7; 8])
+            .unwrap()
+            .save(&path)
+            .unwrap();
+        let (width, height, pixel_format, image_data) = read_image(&path).unwrap();
+        assert_eq!((width, height, pixel_format), (4, 2, PixFmt::Mono8));
+        assert_eq!(image_data, vec![7; 8]);
+
+        // 16-bit grayscale is not silently converted to RGB8.
+        let path = dir.path().join("mono16.png");
+        image::ImageBuffer::<image::Luma<u16>, Vec<u16>>::from_raw(4, 2, vec![1000; 8])
+            .unwrap()
+            .save(&path)
+            .unwrap();
+        match read_image(&path) {
+            Err(Error::UnsupportedImage(_)) => {}
+            other => panic!("unexpected result {:?}", other.map(|r| r.2)),
+        }
+    }
+}
@@ -95,6 +95,8 @@ pub enum MkvCodec {
     VP8(VP8Options),
     VP9(VP9Options),
     H264(H264Options),
+    /// Lossless FFV1 encoding, keeping Bayer images undemosaiced.
+    FFV1,
 }
 
 impl Default for MkvCodec {
@@ -147,6 +149,12 @@ pub struct MkvRecordingConfig {
     pub codec: MkvCodec,
     pub max_framerate: RecordingFrameRate,
     pub writing_application: Option<String>,
+    /// Periodically update the seek index and duration while recording.
+    ///
+    /// This keeps files seekable even if recording is interrupted (e.g. by a
+    /// crash or power cut) before the file is finished.
+    #[serde(default)]
+    pub crash_safe: bool,
 }
 
 impl Default for MkvRecordingConfig {
@@ -155,10 +163,62 @@ impl Default for MkvRecordingConfig {
             codec: MkvCodec::default(),
             max_framerate: RecordingFrameRate::Fps25,
             writing_application: None,
+            crash_safe: false,
         }
     }
 }
 
+/// Configuration of recordings started by a trigger.
+///
+/// Frames are continuously kept in a buffer so that a recording can include
+/// the video from before the trigger.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct PostTriggerConfig {
+    /// Duration of video before the trigger to save, in seconds.
+    pub pre_trigger_secs: f64,
+    /// Duration of video after the trigger to save, in seconds. If zero,
+    /// recording continues until it is stopped.
+    pub post_trigger_secs: f64,
+    /// Maximum size of the buffer in megabytes. If full, the oldest frames
+    /// are dropped even if they are within `pre_trigger_secs`.
+    pub max_buffer_mbytes: u32,
+    /// Keep the frames in the buffer losslessly compressed.
+    ///
+    /// This uses less memory at the cost of CPU time.
+    pub compress: bool,
+    /// If set, the buffer holds this many frames rather than the frames of
+    /// the last `pre_trigger_secs` seconds. This is set by
+    /// `CamArg::SetPostTriggerBufferSize`.
+    #[serde(default)]
+    pub max_frames: Option<usize>,
+}
+
+impl Default for PostTriggerConfig {
+    fn default() -> Self {
+        Self {
+            pre_trigger_secs: 0.0,
+            post_trigger_secs: 0.0,
+            max_buffer_mbytes: 1000,
+            compress: false,
+            max_frames: None,
+        }
+    }
+}
+
+/// The file format of a recording started by a trigger.
+#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
+pub enum PostTriggerFormat {
+    /// MKV file with the current MKV recording configuration.
+    Mkv,
+    /// FMF file.
+    Fmf,
+    /// µFMF file. Used only with image-tracker crate.
+    ///
+    /// As µFMF files store only the regions around detected points, frames
+    /// from before the trigger are not saved.
+    Ufmf,
+}
+
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub enum CsvSaveConfig {
     /// Do not save CSV
@@ -261,11 +321,19 @@ pub enum CamArg {
     ClearCheckerboards,
     PerformCheckerboardCalibration,
     DoQuit,
-    PostTrigger(MkvRecordingConfig),
+    /// Start a recording including the video from before the trigger.
+    PostTrigger(PostTriggerFormat),
+    /// Keep this many frames from before the trigger.
+    ///
+    /// Kept for compatibility, this sets `PostTriggerConfig::max_frames`. Use
+    /// `SetPostTriggerConfig` instead.
     SetPostTriggerBufferSize(usize),
+    SetPostTriggerConfig(PostTriggerConfig),
     ToggleAprilTagFamily(TagFamily),
     ToggleAprilTagDetection(bool),
     SetIsRecordingAprilTagCsv(bool),
+    /// Set the edge length of the April Tags in meters.
+    SetAprilTagSize(f64),
     ToggleImOpsDetection(bool),
     SetImOpsDestination(std::net::SocketAddr),
     SetImOpsSource(std::net::IpAddr),
@@ -0,0 +1,614 @@
+//! Simulated cameras for ci2.
+//!
+//! The cameras render a synthetic scene (see [world]) at the set frame rate,
+//! which allows running strand-cam and braid without camera hardware. The
+//! scene is configured with a YAML file given by the environment variable
+//! `CI2_SIM_CONFIG`, for example:
+//!
+//! ```yaml
+//! trigger_rate: 100.0
+//! calibration: cal.xml
+//! dropped_frame_probability: 0.001
+//! timestamp_jitter_usec: 200.0
+//! cameras:
+//!   - name: cam1
+//!     width: 640
+//!     height: 480
+//!   - name: cam2
+//!     blobs:
+//!       - x: {center: 100.0, amplitude: 50.0, period: 2.0}
+//!         y: {center: 100.0}
+//!         radius: 4.0
+//!         intensity: 200
+//! objects:
+//!   - x: {amplitude: 0.2, period: 4.0}
+//!     y: {amplitude: 0.2, period: 4.0, phase: 0.25}
+//!     z: {center: 0.1}
+//!     radius: 5.0
+//!     intensity: 255
+//! ```
+//!
+//! All cameras of a process share one world. When triggered, the cameras
+//! acquire on the same (simulated) trigger pulses and thus have the same frame
+//! numbers. Otherwise, each camera runs at its own frame rate.
+#![cfg_attr(feature = "backtrace", feature(backtrace))]
+
+extern crate machine_vision_formats as formats;
+
+#[cfg(feature = "backtrace")]
+use std::backtrace::Backtrace;
+
+use chrono::{DateTime, Utc};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::sync::Arc;
+use std::time::Duration;
+
+use basic_frame::DynamicFrame;
+use ci2::{AcquisitionMode, AutoMode, TriggerMode, TriggerSelector};
+use timestamped_frame::HostTimeData;
+
+pub mod world;
+
+use world::{CameraConfig, World};
+
+/// The exposure time (in microseconds) at which objects have their
+/// configured intensity (at zero gain).
+const REFERENCE_EXPOSURE_TIME: f64 = 10_000.0;
+const EXPOSURE_TIME_RANGE: (f64, f64) = (10.0, 1_000_000.0);
+const GAIN_RANGE: (f64, f64) = (0.0, 24.0);
+const MIN_FRAME_RATE: f64 = 1.0;
+
+pub type Result<M> = std::result::Result<M, Error>;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("io error: {source}")]
+    IoError {
+        #[from]
+        source: std::io::Error,
+        #[cfg(feature = "backtrace")]
+        backtrace: Backtrace,
+    },
+    #[error("YAML error: {source}")]
+    YamlError {
+        #[from]
+        source: serde_yaml::Error,
+        #[cfg(feature = "backtrace")]
+        backtrace: Backtrace,
+    },
+    #[error("mvg error: {source}")]
+    MvgError {
+        #[from]
+        source: mvg::MvgError,
+        #[cfg(feature = "backtrace")]
+        backtrace: Backtrace,
+    },
+    #[error("other error: {msg}")]
+    OtherError {
+        msg: String,
+        #[cfg(feature = "backtrace")]
+        backtrace: Backtrace,
+    },
+}
+
+impl From<Error> for ci2::Error {
+    fn from(orig: Error) -> ci2::Error {
+        ci2::Error::BackendError(orig.into())
+    }
+}
+
+pub struct WrappedModule {
+    world: Arc<World>,
+}
+
+pub fn new_module() -> ci2::Result<WrappedModule> {
+    Ok(WrappedModule {
+        world: World::shared()?,
+    })
+}
+
+impl WrappedModule {
+    /// Create a module with its own world (e.g. for testing).
+    pub fn with_world(world: Arc<World>) -> Self {
+        Self { world }
+    }
+}
+
+impl ci2::CameraModule for WrappedModule {
+    type CameraType = WrappedCamera;
+
+    fn name(&self) -> &str {
+        "sim"
+    }
+    fn camera_infos(&self) -> ci2::Result<Vec<Box<dyn ci2::CameraInfo>>> {
+        let infos = self
+            .world
+            .config()
+            .cameras
+            .iter()
+            .map(|cam| {
+                let sci = Box::new(SimCameraInfo {
+                    name: cam.name.clone(),
+                });
+                let ci: Box<dyn ci2::CameraInfo> = sci; // explicitly perform type erasure
+                ci
+            })
+            .collect();
+        Ok(infos)
+    }
+    fn camera(&mut self, name: &str) -> ci2::Result<Self::CameraType> {
+        WrappedCamera::new(self.world.clone(), name)
+    }
+}
+
+#[derive(Debug)]
+struct SimCameraInfo {
+    name: String,
+}
+
+impl ci2::CameraInfo for SimCameraInfo {
+    fn name(&self) -> &str {
+        &self.name
+    }
+    fn serial(&self) -> &str {
+        &self.name
+    }
+    fn model(&self) -> &str {
+        "simulated camera"
+    }
+    fn vendor(&self) -> &str {
+        "ci2-sim"
+    }
+}
+
+/// The state of a running acquisition.
+struct Acquisition {
+    /// Time of the next frame since trigger pulse zero.
+    next_time: Duration,
+    next_framenumber: usize,
+}
+
+pub struct WrappedCamera {
+    world: Arc<World>,
+    cfg: CameraConfig,
+    pixel_format: formats::PixFmt,
+    exposure_time: f64,
+    exposure_auto: AutoMode,
+    gain: f64,
+    gain_auto: AutoMode,
+    trigger_mode: TriggerMode,
+    trigger_selector: TriggerSelector,
+    acquisition_frame_rate_enable: bool,
+    acquisition_frame_rate: f64,
+    acquisition_mode: AcquisitionMode,
+    acquisition: Option<Acquisition>,
+    rng: StdRng,
+}
+
+fn _test_camera_is_send() {
+    // Compile-time test to ensure WrappedCamera implements Send trait.
+    fn implements<T: Send>() {}
+    implements::<WrappedCamera>();
+}
+
+impl WrappedCamera {
+    fn new(world: Arc<World>, name: &str) -> ci2::Result<Self> {
+        let cameras = &world.config().cameras;
+        let idx = cameras
+            .iter()
+            .position(|cam| cam.name == name)
+            .ok_or_else(|| Error::OtherError {
+                msg: format!("requested camera '{}' was not found", name),
+                #[cfg(feature = "backtrace")]
+                backtrace: std::backtrace::Backtrace::capture(),
+            })?;
+        let cfg = cameras[idx].clone();
+        let rng = StdRng::seed_from_u64(world.config().seed + idx as u64);
+        let acquisition_frame_rate = cfg.max_frame_rate;
+        Ok(Self {
+            world,
+            cfg,
+            pixel_format: formats::PixFmt::Mono8,
+            exposure_time: REFERENCE_EXPOSURE_TIME,
+            exposure_auto: AutoMode::Off,
+            gain: 0.0,
+            gain_auto: AutoMode::Off,
+            trigger_mode: TriggerMode::Off,
+            trigger_selector: TriggerSelector::FrameStart,
+            acquisition_frame_rate_enable: false,
+            acquisition_frame_rate,
+            acquisition_mode: AcquisitionMode::Continuous,
+            acquisition: None,
+            rng,
+        })
+    }
+
+    /// The interval between frames.
+    ///
+    /// When not triggered, the frame rate is limited by the exposure time.
+    fn frame_period(&self) -> Duration {
+        match self.trigger_mode {
+            TriggerMode::On => self.world.trigger_period(),
+            TriggerMode::Off => {
+                let rate = if self.acquisition_frame_rate_enable {
+                    self.acquisition_frame_rate
+                } else {
+                    self.cfg.max_frame_rate
+                };
+                Duration::from_secs_f64((1.0 / rate).max(self.exposure_time * 1e-6))
+            }
+        }
+    }
+
+    /// Start acquiring on the next trigger pulse or immediately.
+    fn start_acquisition(&mut self) {
+        let now = self.world.elapsed();
+        let acquisition = match self.trigger_mode {
+            TriggerMode::On => {
+                let period = self.world.trigger_period();
+                let pulse = (now.as_secs_f64() / period.as_secs_f64()).ceil() as u32;
+                Acquisition {
+                    next_time: period * pulse,
+                    next_framenumber: pulse as usize,
+                }
+            }
+            TriggerMode::Off => Acquisition {
+                next_time: now,
+                next_framenumber: 0,
+            },
+        };
+        self.acquisition = Some(acquisition);
+    }
+
+    fn brightness(&self) -> f64 {
+        self.exposure_time / REFERENCE_EXPOSURE_TIME * 10.0f64.powf(self.gain / 20.0)
+    }
+}
+
+impl ci2::CameraInfo for WrappedCamera {
+    fn name(&self) -> &str {
+        &self.cfg.name
+    }
+    fn serial(&self) -> &str {
+        &self.cfg.name
+    }
+    fn model(&self) -> &str {
+        "simulated camera"
+    }
+    fn vendor(&self) -> &str {
+        "ci2-sim"
+    }
+}
+
+fn check_range(name: &str, value: f64, range: (f64, f64)) -> ci2::Result<()> {
+    if value < range.0 || value > range.1 {
+        return Err(ci2::Error::from(format!(
+            "{} {} out of range {:?}",
+            name, value, range
+        )));
+    }
+    Ok(())
+}
+
+impl ci2::Camera for WrappedCamera {
+    /// Return the sensor width in pixels
+    fn width(&self) -> ci2::Result<u32> {
+        Ok(self.cfg.width)
+    }
+    /// Return the sensor height in pixels
+    fn height(&self) -> ci2::Result<u32> {
+        Ok(self.cfg.height)
+    }
+
+    // Settings: PixFmt ----------------------------
+    fn pixel_format(&self) -> ci2::Result<formats::PixFmt> {
+        Ok(self.pixel_format)
+    }
+    fn possible_pixel_formats(&self) -> ci2::Result<Vec<formats::PixFmt>> {
+        Ok(vec![formats::PixFmt::Mono8, formats::PixFmt::RGB8])
+    }
+    fn set_pixel_format(&mut self, pixel_format: formats::PixFmt) -> ci2::Result<()> {
+        if !self.possible_pixel_formats()?.contains(&pixel_format) {
+            return Err(ci2::Error::from(format!(
+                "Unsuppored PixFmt {}",
+                pixel_format
+            )));
+        }
+        self.pixel_format = pixel_format;
+        Ok(())
+    }
+
+    // Settings: Exposure Time ----------------------------
+    /// value given in microseconds
+    fn exposure_time(&self) -> ci2::Result<f64> {
+        Ok(self.exposure_time)
+    }
+    /// value given in microseconds
+    fn exposure_time_range(&self) -> ci2::Result<(f64, f64)> {
+        Ok(EXPOSURE_TIME_RANGE)
+    }
+    /// value given in microseconds
+    fn set_exposure_time(&mut self, value: f64) -> ci2::Result<()> {
+        check_range("exposure time", value, EXPOSURE_TIME_RANGE)?;
+        self.exposure_time = value;
+        Ok(())
+    }
+
+    // Settings: Exposure Time Auto Mode ----------------------------
+    fn exposure_auto(&self) -> ci2::Result<AutoMode> {
+        Ok(self.exposure_auto)
+    }
+    fn set_exposure_auto(&mut self, value: AutoMode) -> ci2::Result<()> {
+        self.exposure_auto = value;
+        Ok(())
+    }
+
+    // Settings: Gain ----------------------------
+    /// value given in dB
+    fn gain(&self) -> ci2::Result<f64> {
+        Ok(self.gain)
+    }
+    /// value given in dB
+    fn gain_range(&self) -> ci2::Result<(f64, f64)> {
+        Ok(GAIN_RANGE)
+    }
+    /// value given in dB
+    fn set_gain(&mut self, value: f64) -> ci2::Result<()> {
+        check_range("gain", value, GAIN_RANGE)?;
+        self.gain = value;
+        Ok(())
+    }
+
+    // Settings: Gain Auto Mode ----------------------------
+    fn gain_auto(&self) -> ci2::Result<AutoMode> {
+        Ok(self.gain_auto)
+    }
+    fn set_gain_auto(&mut self, value: AutoMode) -> ci2::Result<()> {
+        self.gain_auto = value;
+        Ok(())
+    }
+
+    // Settings: TriggerMode ----------------------------
+    fn trigger_mode(&self) -> ci2::Result<TriggerMode> {
+        Ok(self.trigger_mode)
+    }
+    fn set_trigger_mode(&mut self, value: TriggerMode) -> ci2::Result<()> {
+        self.trigger_mode = value;
+        if self.acquisition.is_some() {
+            // Realign to the trigger pulses (or the free running clock).
+            self.start_acquisition();
+        }
+        Ok(())
+    }
+
+    // Settings: AcquisitionFrameRateEnable ----------------------------
+    fn acquisition_frame_rate_enable(&self) -> ci2::Result<bool> {
+        Ok(self.acquisition_frame_rate_enable)
+    }
+    fn set_acquisition_frame_rate_enable(&mut self, value: bool) -> ci2::Result<()> {
+        self.acquisition_frame_rate_enable = value;
+        Ok(())
+    }
+
+    // Settings: AcquisitionFrameRate ----------------------------
+    fn acquisition_frame_rate(&self) -> ci2::Result<f64> {
+        Ok(self.acquisition_frame_rate)
+    }
+    fn acquisition_frame_rate_range(&self) -> ci2::Result<(f64, f64)> {
+        Ok((MIN_FRAME_RATE, self.cfg.max_frame_rate))
+    }
+    fn set_acquisition_frame_rate(&mut self, value: f64) -> ci2::Result<()> {
+        check_range(
+            "frame rate",
+            value,
+            (MIN_FRAME_RATE, self.cfg.max_frame_rate),
+        )?;
+        self.acquisition_frame_rate = value;
+        Ok(())
+    }
+
+    // Settings: TriggerSelector ----------------------------
+    fn trigger_selector(&self) -> ci2::Result<TriggerSelector> {
+        Ok(self.trigger_selector)
+    }
+    fn set_trigger_selector(&mut self, value: TriggerSelector) -> ci2::Result<()> {
+        self.trigger_selector = value;
+        Ok(())
+    }
+
+    // Settings: AcquisitionMode ----------------------------
+    fn acquisition_mode(&self) -> ci2::Result<AcquisitionMode> {
+        Ok(self.acquisition_mode)
+    }
+    fn set_acquisition_mode(&mut self, value: AcquisitionMode) -> ci2::Result<()> {
+        self.acquisition_mode = value;
+        Ok(())
+    }
+
+    // Acquisition ----------------------------
+    fn acquisition_start(&mut self) -> ci2::Result<()> {
+        self.start_acquisition();
+        Ok(())
+    }
+    fn acquisition_stop(&mut self) -> ci2::Result<()> {
+        self.acquisition = None;
+        Ok(())
+    }
+
+    /// synchronous (blocking) frame acquisition
+    fn next_frame(&mut self) -> ci2::Result<DynamicFrame> {
+        let period = self.frame_period();
+        let cfg = self.world.config();
+        let drop_probability = cfg.dropped_frame_probability;
+        let jitter = cfg.timestamp_jitter_usec * 1e-6;
+
+        let (t, framenumber) = loop {
+            let acquisition = self
+                .acquisition
+                .as_mut()
+                .ok_or_else(|| ci2::Error::from("acquisition not started"))?;
+
+            // Skip frames which were missed because we fell behind.
+            let now = self.world.elapsed();
+            if now > acquisition.next_time + period {
+                let missed = (now - acquisition.next_time).as_secs_f64() / period.as_secs_f64();
+                let missed = missed.floor() as u32;
+                acquisition.next_time += period * missed;
+                acquisition.next_framenumber += missed as usize;
+            }
+
+            let t = acquisition.next_time;
+            let framenumber = acquisition.next_framenumber;
+            acquisition.next_time += period;
+            acquisition.next_framenumber += 1;
+
+            self.world.wait_until(t);
+
+            if drop_probability > 0.0 && self.rng.gen::<f64>() < drop_probability {
+                log::debug!("{}: dropping frame {}", self.cfg.name, framenumber);
+                continue;
+            }
+            break (t.as_secs_f64(), framenumber);
+        };
+
+        if self.acquisition_mode == AcquisitionMode::SingleFrame {
+            self.acquisition = None;
+        }
+
+        let mono = self.world.render(&self.cfg, t, self.brightness());
+        let (image_data, bytes_per_pixel) = match self.pixel_format {
+            formats::PixFmt::RGB8 => (
+                mono.iter()
+                    .flat_map(|v| std::iter::repeat(*v).take(3))
+                    .collect(),
+                3,
+            ),
+            _ => (mono, 1),
+        };
+
+        let host_t = if jitter > 0.0 {
+            t + self.rng.gen_range(-jitter..=jitter)
+        } else {
+            t
+        };
+        let extra = Box::new(SimExtra {
+            host_timestamp: self.world.timestamp(host_t),
+            host_framenumber: framenumber,
+            sim_time: t,
+            pixel_format: self.pixel_format,
+        });
+        Ok(DynamicFrame::new(
+            self.cfg.width,
+            self.cfg.height,
+            self.cfg.width * bytes_per_pixel,
+            extra,
+            image_data,
+            self.pixel_format,
+        ))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SimExtra {
+    host_timestamp: DateTime<Utc>,
+    host_framenumber: usize,
+    /// The time of the rendered scene (in seconds since trigger pulse zero).
+    pub sim_time: f64,
+    pub pixel_format: formats::PixFmt,
+}
+
+impl HostTimeData for SimExtra {
+    fn host_framenumber(&self) -> usize {
+        self.host_framenumber
+    }
+    fn host_timestamp(&self) -> DateTime<Utc> {
+        self.host_timestamp
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ci2::{Camera, CameraModule};
+    use timestamped_frame::ExtraTimeData;
+    use world::{Blob, Oscillation, SimConfig};
+
+    fn still_blob(x: f64, y: f64) -> Blob {
+        let at = |center| Oscillation {
+            center,
+            ..Default::default()
+        };
+        Blob {
+            x: at(x),
+            y: at(y),
+            radius: 2.0,
+            intensity: 100,
+        }
+    }
+
+    #[test]
+    fn test_triggered_cameras() {
+        let cameras = vec![
+            CameraConfig {
+                name: "a".to_string(),
+                width: 32,
+                height: 16,
+                blobs: vec![still_blob(10.0, 5.0)],
+                ..Default::default()
+            },
+            CameraConfig {
+                name: "b".to_string(),
+                width: 32,
+                height: 16,
+                ..Default::default()
+            },
+        ];
+        let cfg = SimConfig {
+            trigger_rate: 500.0,
+            cameras,
+            ..Default::default()
+        };
+        let world = Arc::new(World::with_manual_clock(cfg, None).unwrap());
+        let mut module = WrappedModule::with_world(world);
+        assert_eq!(module.camera_infos().unwrap().len(), 2);
+
+        let mut cam_a = module.camera("a").unwrap();
+        let mut cam_b = module.camera("b").unwrap();
+        assert!(module.camera("c").is_err());
+        for cam in [&mut cam_a, &mut cam_b].iter_mut() {
+            cam.set_trigger_mode(TriggerMode::On).unwrap();
+            cam.acquisition_start().unwrap();
+        }
+
+        let frame_a = cam_a.next_frame().unwrap();
+        let frame_b = cam_b.next_frame().unwrap();
+        let fno_a = frame_a.extra().host_framenumber();
+        let fno_b = frame_b.extra().host_framenumber();
+        // Both cameras acquire on the same trigger pulse.
+        assert_eq!(fno_a, fno_b);
+        assert_eq!(
+            frame_a.extra().host_timestamp(),
+            frame_b.extra().host_timestamp()
+        );
+
+        let image = frame_a.image_data_without_format();
+        assert_eq!(image.len(), 32 * 16);
+        assert_eq!(image[5 * 32 + 10], 100);
+        assert_eq!(image[0], 20);
+
+        // Doubling the exposure time doubles the intensities.
+        cam_a
+            .set_exposure_time(2.0 * REFERENCE_EXPOSURE_TIME)
+            .unwrap();
+        cam_a.set_pixel_format(formats::PixFmt::RGB8).unwrap();
+        let frame = cam_a.next_frame().unwrap();
+        assert_eq!(frame.extra().host_framenumber(), fno_a + 1);
+        let image = frame.image_data_without_format();
+        assert_eq!(image.len(), 32 * 16 * 3);
+        assert_eq!(
+            &image[(5 * 32 + 10) * 3..(5 * 32 + 11) * 3],
+            &[200, 200, 200]
+        );
+        assert_eq!(image[0], 40);
+    }
+}
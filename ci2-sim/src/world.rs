@@ -0,0 +1,400 @@
+//! The simulated world seen by all cameras of the process.
+//!
+//! The world consists of 2D blobs which are drawn directly onto the image of
+//! a camera and 3D objects which are projected into every calibrated camera.
+//! All positions are periodic functions of time so that every camera renders
+//! the same scene at the same instant.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, Weak},
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, Utc};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+use mvg::{MultiCameraSystem, PointWorldFrame};
+use nalgebra::Point3;
+
+use crate::{Error, Result};
+
+/// Name of the environment variable with the filename of the YAML
+/// configuration.
+pub const CONFIG_ENV_VAR: &str = "CI2_SIM_CONFIG";
+
+lazy_static::lazy_static! {
+    static ref SHARED_WORLD: Mutex<Weak<World>> = Mutex::new(Weak::new());
+}
+
+/// Configuration of the simulation.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SimConfig {
+    /// Rate of the (simulated) external trigger in frames per second.
+    pub trigger_rate: f64,
+    /// Filename of a calibration (flydra XML or pymvg JSON) of the cameras.
+    ///
+    /// This is required to render `objects`. A relative filename is relative
+    /// to the configuration file.
+    pub calibration: Option<PathBuf>,
+    /// The cameras.
+    pub cameras: Vec<CameraConfig>,
+    /// Objects moving in 3D and seen by every calibrated camera.
+    pub objects: Vec<Object3d>,
+    /// Probability that a frame is dropped.
+    pub dropped_frame_probability: f64,
+    /// Maximum random error of the host timestamps (in microseconds).
+    pub timestamp_jitter_usec: f64,
+    /// Seed of the random number generator of the first camera.
+    pub seed: u64,
+}
+
+impl Default for SimConfig {
+    fn default() -> Self {
+        let blob = |phase| Blob {
+            x: Oscillation {
+                center: 320.0,
+                amplitude: 200.0,
+                period: 5.0,
+                phase,
+            },
+            y: Oscillation {
+                center: 240.0,
+                amplitude: 150.0,
+                period: 5.0,
+                phase: phase + 0.25,
+            },
+            radius: 5.0,
+            intensity: 255,
+        };
+        let cameras = (1..=2)
+            .map(|i| CameraConfig {
+                name: format!("sim-cam-{}", i),
+                blobs: vec![blob(0.1 * i as f64)],
+                ..Default::default()
+            })
+            .collect();
+        Self {
+            trigger_rate: 100.0,
+            calibration: None,
+            cameras,
+            objects: Vec::new(),
+            dropped_frame_probability: 0.0,
+            timestamp_jitter_usec: 0.0,
+            seed: 0,
+        }
+    }
+}
+
+/// Configuration of a single camera.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CameraConfig {
+    /// The name of the camera (and of its calibration).
+    pub name: String,
+    pub width: u32,
+    pub height: u32,
+    /// Maximum frame rate when not triggered.
+    pub max_frame_rate: f64,
+    /// Intensity of the background.
+    pub background: u8,
+    /// Blobs moving in the image of this camera only.
+    pub blobs: Vec<Blob>,
+}
+
+impl Default for CameraConfig {
+    fn default() -> Self {
+        Self {
+            name: "sim-cam".to_string(),
+            width: 640,
+            height: 480,
+            max_frame_rate: 200.0,
+            background: 20,
+            blobs: Vec::new(),
+        }
+    }
+}
+
+/// A sinusoidal motion along one axis.
+///
+/// The position at time `t` (in seconds) is
+/// `center + amplitude * sin(2π (t / period + phase))`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Oscillation {
+    pub center: f64,
+    pub amplitude: f64,
+    /// Period in seconds.
+    pub period: f64,
+    /// Phase as fraction of the period.
+    pub phase: f64,
+}
+
+impl Default for Oscillation {
+    fn default() -> Self {
+        Self {
+            center: 0.0,
+            amplitude: 0.0,
+            period: 1.0,
+            phase: 0.0,
+        }
+    }
+}
+
+impl Oscillation {
+    fn at(&self, t: f64) -> f64 {
+        let angle = 2.0 * std::f64::consts::PI * (t / self.period + self.phase);
+        self.center + self.amplitude * angle.sin()
+    }
+}
+
+/// A bright disc moving in the image (coordinates in pixels).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Blob {
+    pub x: Oscillation,
+    pub y: Oscillation,
+    /// Radius in pixels.
+    pub radius: f64,
+    pub intensity: u8,
+}
+
+/// A bright point moving in 3D (coordinates in the calibration frame).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Object3d {
+    pub x: Oscillation,
+    pub y: Oscillation,
+    pub z: Oscillation,
+    /// Radius of the rendered disc in pixels.
+    pub radius: f64,
+    pub intensity: u8,
+}
+
+impl Object3d {
+    /// The position at time `t`.
+    pub fn position(&self, t: f64) -> PointWorldFrame<f64> {
+        PointWorldFrame {
+            coords: Point3::new(self.x.at(t), self.y.at(t), self.z.at(t)),
+        }
+    }
+}
+
+/// The state shared by all simulated cameras.
+pub struct World {
+    cfg: SimConfig,
+    calibration: Option<MultiCameraSystem<f64>>,
+    /// The time of trigger pulse zero.
+    epoch: DateTime<Utc>,
+    clock: Clock,
+}
+
+/// The source of the time since trigger pulse zero.
+enum Clock {
+    /// The time passes in real time since `epoch_instant`.
+    Wall { epoch_instant: Instant },
+    /// The time only advances when waiting for a later time.
+    Manual { now: Mutex<Duration> },
+}
+
+impl World {
+    /// Create a world from a configuration.
+    ///
+    /// A relative calibration filename is relative to `base_dir`.
+    pub fn new(mut cfg: SimConfig, base_dir: Option<&Path>) -> Result<Self> {
+        if !cfg.trigger_rate.is_finite() || cfg.trigger_rate <= 0.0 {
+            return Err(Error::OtherError {
+                msg: format!("invalid trigger rate {}", cfg.trigger_rate),
+                #[cfg(feature = "backtrace")]
+                backtrace: std::backtrace::Backtrace::capture(),
+            });
+        }
+        let calibration = match &cfg.calibration {
+            Some(fname) => {
+                let fname = match base_dir {
+                    Some(base_dir) => base_dir.join(fname),
+                    None => fname.clone(),
+                };
+                let system = read_calibration(&fname)?;
+                cfg.calibration = Some(fname);
+                Some(system)
+            }
+            None => None,
+        };
+        if !cfg.objects.is_empty() && calibration.is_none() {
+            log::warn!("ci2-sim: 3D objects are not rendered without calibration");
+        }
+
+        // Start on a whole second so that worlds of different processes
+        // share their trigger pulses.
+        let now = Utc::now();
+        let epoch_instant =
+            Instant::now() - Duration::from_nanos(now.timestamp_subsec_nanos().into());
+        let epoch = now - chrono::Duration::nanoseconds(now.timestamp_subsec_nanos().into());
+
+        Ok(Self {
+            cfg,
+            calibration,
+            epoch,
+            clock: Clock::Wall { epoch_instant },
+        })
+    }
+
+    /// Create a world with a manual clock (e.g. for testing).
+    ///
+    /// The time starts at trigger pulse zero and only advances when a camera
+    /// waits for its next frame, which then returns immediately. Thus, the
+    /// frames do not depend on timing.
+    pub fn with_manual_clock(cfg: SimConfig, base_dir: Option<&Path>) -> Result<Self> {
+        let mut world = Self::new(cfg, base_dir)?;
+        world.clock = Clock::Manual {
+            now: Mutex::new(Duration::from_secs(0)),
+        };
+        Ok(world)
+    }
+
+    /// Return the world of this process, creating it if needed.
+    ///
+    /// The configuration is read from the file given by the environment
+    /// variable `CI2_SIM_CONFIG`. Without it, the default configuration is
+    /// used.
+    pub fn shared() -> Result<Arc<Self>> {
+        let mut shared = SHARED_WORLD.lock();
+        if let Some(world) = shared.upgrade() {
+            return Ok(world);
+        }
+        let world = Arc::new(match std::env::var_os(CONFIG_ENV_VAR) {
+            Some(fname) => {
+                let fname = PathBuf::from(fname);
+                let rdr = std::fs::File::open(&fname)?;
+                let cfg: SimConfig = serde_yaml::from_reader(rdr)?;
+                World::new(cfg, fname.parent())?
+            }
+            None => World::new(SimConfig::default(), None)?,
+        });
+        *shared = Arc::downgrade(&world);
+        Ok(world)
+    }
+
+    #[inline]
+    pub fn config(&self) -> &SimConfig {
+        &self.cfg
+    }
+
+    /// The interval between trigger pulses.
+    pub fn trigger_period(&self) -> Duration {
+        Duration::from_secs_f64(1.0 / self.cfg.trigger_rate)
+    }
+
+    /// The time since trigger pulse zero.
+    pub fn elapsed(&self) -> Duration {
+        match &self.clock {
+            Clock::Wall { epoch_instant } => epoch_instant.elapsed(),
+            Clock::Manual { now } => *now.lock(),
+        }
+    }
+
+    /// Wait until time `t` since trigger pulse zero.
+    pub fn wait_until(&self, t: Duration) {
+        match &self.clock {
+            Clock::Wall { epoch_instant } => {
+                let deadline = *epoch_instant + t;
+                let now = Instant::now();
+                if deadline > now {
+                    std::thread::sleep(deadline - now);
+                }
+            }
+            Clock::Manual { now } => {
+                let mut now = now.lock();
+                if t > *now {
+                    *now = t;
+                }
+            }
+        }
+    }
+
+    /// The wall clock time at `t` seconds since trigger pulse zero.
+    pub fn timestamp(&self, t: f64) -> DateTime<Utc> {
+        self.epoch + chrono::Duration::nanoseconds((t * 1e9).round() as i64)
+    }
+
+    /// Render the image (Mono8, without padding) of a camera at time `t`.
+    ///
+    /// All intensities are multiplied by `brightness` to simulate exposure
+    /// time and gain.
+    pub fn render(&self, cam: &CameraConfig, t: f64, brightness: f64) -> Vec<u8> {
+        let scale = |value: u8| (value as f64 * brightness).round().min(255.0) as u8;
+        let width = cam.width as usize;
+        let height = cam.height as usize;
+        let mut image = vec![scale(cam.background); width * height];
+
+        for blob in cam.blobs.iter() {
+            let (x, y) = (blob.x.at(t), blob.y.at(t));
+            draw_disc(
+                &mut image,
+                width,
+                height,
+                x,
+                y,
+                blob.radius,
+                scale(blob.intensity),
+            );
+        }
+
+        let mvg_cam = self
+            .calibration
+            .as_ref()
+            .and_then(|system| system.cam_by_name(&cam.name));
+        if let Some(mvg_cam) = mvg_cam {
+            for obj in self.cfg.objects.iter() {
+                let pt = obj.position(t);
+                let extrinsics = mvg_cam.extrinsics();
+                let cam_frame = extrinsics.rotation() * (pt.coords - extrinsics.camcenter());
+                if cam_frame.z <= 0.0 {
+                    // behind the camera
+                    continue;
+                }
+                let px = mvg_cam.project_3d_to_distorted_pixel(&pt);
+                draw_disc(
+                    &mut image,
+                    width,
+                    height,
+                    px.coords.x,
+                    px.coords.y,
+                    obj.radius,
+                    scale(obj.intensity),
+                );
+            }
+        }
+        image
+    }
+}
+
+fn read_calibration(fname: &Path) -> Result<MultiCameraSystem<f64>> {
+    let rdr = std::fs::File::open(fname)?;
+    let is_xml = fname.extension().map(|ext| ext == "xml").unwrap_or(false);
+    let system = if is_xml {
+        flydra_mvg::FlydraMultiCameraSystem::<f64>::from_flydra_xml(rdr)?.to_system()
+    } else {
+        MultiCameraSystem::from_pymvg_file_json(rdr)?
+    };
+    Ok(system)
+}
+
+fn draw_disc(image: &mut [u8], width: usize, height: usize, x: f64, y: f64, r: f64, value: u8) {
+    let r2 = r * r;
+    let y0 = (y - r).floor().max(0.0) as usize;
+    let y1 = ((y + r).ceil().max(0.0) as usize).min(height);
+    let x0 = (x - r).floor().max(0.0) as usize;
+    let x1 = ((x + r).ceil().max(0.0) as usize).min(width);
+    for row in y0..y1 {
+        let dy = row as f64 - y;
+        let image_row = &mut image[row * width..(row + 1) * width];
+        for (col, pixel) in image_row.iter_mut().enumerate().take(x1).skip(x0) {
+            let dx = col as f64 - x;
+            if dx * dx + dy * dy <= r2 {
+                *pixel = value;
+            }
+        }
+    }
+}
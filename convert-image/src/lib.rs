@@ -48,6 +48,10 @@ pub enum Error {
     },
     #[error("unimplemented conversion {0} -> {1}")]
     UnimplementedConversion(PixFmt, PixFmt),
+    #[error("unsupported number of bits per sample: {0}")]
+    UnsupportedBitsPerSample(u8),
+    #[error("sample value {value} exceeds {bits_per_sample} bits")]
+    SampleOutOfRange { value: u16, bits_per_sample: u8 },
 }
 
 #[allow(non_camel_case_types)]
@@ -863,6 +867,48 @@ mod tests {
         }
     }
 
+    #[test]
+    fn check_lossless_bayer_is_not_demosaiced() {
+        // Create an image where stride is larger than width.
+        const STRIDE: usize = 6;
+        const W: u32 = 4;
+        const H: u32 = 2;
+        let image_data: Vec<u8> = (0..(H as usize * STRIDE) as u8).collect();
+        let frame: SimpleFrame<formats::pixel_format::BayerRG8> = SimpleFrame {
+            width: W,
+            height: H,
+            stride: STRIDE as u32,
+            image_data,
+            fmt: std::marker::PhantomData,
+        };
+        let lossless = encode_lossless_frame(&frame).unwrap();
+        assert_eq!(lossless.colorspace, LosslessColorspace::Gray);
+        assert_eq!(lossless.bits_per_sample, 8);
+        assert_eq!(lossless.samples, vec![0, 1, 2, 3, 6, 7, 8, 9]);
+    }
+
+    #[test]
+    fn check_lossless_mono16() {
+        // Two rows of 12 bit samples with padding at the end of each row.
+        let rows: [[u16; 3]; 2] = [[0, 1, 0x0FFF], [0x0123, 0x0800, 0x0FFE]];
+        let mut data = Vec::new();
+        for row in rows.iter() {
+            for value in row.iter() {
+                data.extend_from_slice(&value.to_le_bytes());
+            }
+            data.extend_from_slice(&[0xFF; 2]);
+        }
+        let lossless = lossless_frame_from_mono16(3, 2, 8, &data, 12).unwrap();
+        assert_eq!(lossless.colorspace, LosslessColorspace::Gray);
+        assert_eq!(lossless.bits_per_sample, 12);
+        assert_eq!(lossless.samples, vec![0, 1, 0x0FFF, 0x0123, 0x0800, 0x0FFE]);
+
+        // A value which does not fit into 10 bits.
+        assert!(lossless_frame_from_mono16(3, 2, 8, &data, 10).is_err());
+        // The last row is incomplete.
+        assert!(lossless_frame_from_mono16(3, 2, 8, &data[..12], 12).is_err());
+    }
+
     #[test]
     fn check_bayer_conversion_to_jpg() {
         // Create an image where stride is larger than width.
@@ -1216,3 +1262,126 @@ where
 {
     convert_into(frame, dest, dest_stride)
 }
+
+/// The color channels of a [LosslessFrame].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LosslessColorspace {
+    /// One luminance (or raw Bayer) sample per pixel.
+    Gray,
+    /// Red, green and blue samples per pixel.
+    Rgb,
+}
+
+/// Image samples for lossless encoding.
+///
+/// Samples are stored as `u16` so that data with more than 8 bits per sample
+/// is not truncated.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LosslessFrame {
+    pub width: u32,
+    pub height: u32,
+    pub colorspace: LosslessColorspace,
+    /// Number of significant bits in each sample.
+    pub bits_per_sample: u8,
+    /// Samples in row-major order without padding. Color samples of a pixel
+    /// are consecutive.
+    pub samples: Vec<u16>,
+}
+
+/// Copy any type implementing `ImageStride<FMT>` into a [LosslessFrame].
+///
+/// Mono and Bayer images are kept as gray samples (Bayer images are not
+/// demosaiced) and RGB8 images as RGB samples, so no information is lost.
+/// Other 8 bit formats are converted to RGB8 first. Floating point formats
+/// cannot be represented and return an error.
+pub fn encode_lossless_frame<FMT>(frame: &dyn ImageStride<FMT>) -> Result<LosslessFrame>
+where
+    FMT: PixelFormat,
+{
+    let src_fmt = machine_vision_formats::pixel_format::pixfmt::<FMT>().unwrap();
+    let width = frame.width() as usize;
+    let (colorspace, samples) = match src_fmt {
+        PixFmt::Mono8
+        | PixFmt::BayerRG8
+        | PixFmt::BayerGB8
+        | PixFmt::BayerGR8
+        | PixFmt::BayerBG8 => {
+            let samples =
+                lossless_samples(frame.image_data(), frame.stride(), width, frame.height());
+            (LosslessColorspace::Gray, samples)
+        }
+        PixFmt::Mono32f
+        | PixFmt::BayerRG32f
+        | PixFmt::BayerGB32f
+        | PixFmt::BayerGR32f
+        | PixFmt::BayerBG32f => {
+            return Err(Error::UnimplementedPixelFormat(src_fmt));
+        }
+        _ => {
+            let rgb = convert::<_, RGB8>(frame)?;
+            let samples = lossless_samples(rgb.image_data(), rgb.stride(), width * 3, rgb.height());
+            (LosslessColorspace::Rgb, samples)
+        }
+    };
+    Ok(LosslessFrame {
+        width: frame.width(),
+        height: frame.height(),
+        colorspace,
+        bits_per_sample: 8,
+        samples,
+    })
+}
+
+/// Copy unpacked 16 bit mono samples into a [LosslessFrame].
+///
+/// This is for images with more than 8 bits per pixel (e.g. the Mono12 and
+/// Mono16 formats of machine vision cameras), for which there is no
+/// [PixelFormat]. Each sample is stored as little endian `u16` with the
+/// `bits_per_sample` significant bits in the least significant bits. Rows are
+/// `stride` bytes apart.
+pub fn lossless_frame_from_mono16(
+    width: u32,
+    height: u32,
+    stride: usize,
+    data: &[u8],
+    bits_per_sample: u8,
+) -> Result<LosslessFrame> {
+    if !(1..=16).contains(&bits_per_sample) {
+        return Err(Error::UnsupportedBitsPerSample(bits_per_sample));
+    }
+    let row_len = width as usize * 2;
+    // The last row need not be padded.
+    let min_len = (stride * height as usize).saturating_sub(stride - row_len.min(stride));
+    if stride < row_len || data.len() < min_len {
+        return Err(Error::InvalidAllocatedBufferSize);
+    }
+    let max = ((1u32 << bits_per_sample) - 1) as u16;
+    let mut samples = Vec::with_capacity(width as usize * height as usize);
+    for row in data.chunks(stride).take(height as usize) {
+        for sample in row[..row_len].chunks_exact(2) {
+            let value = u16::from_le_bytes([sample[0], sample[1]]);
+            if value > max {
+                return Err(Error::SampleOutOfRange {
+                    value,
+                    bits_per_sample,
+                });
+            }
+            samples.push(value);
+        }
+    }
+    Ok(LosslessFrame {
+        width,
+        height,
+        colorspace: LosslessColorspace::Gray,
+        bits_per_sample,
+        samples,
+    })
+}
+
+/// Copy 8 bit samples from rows of `row_len` bytes, dropping the padding.
+fn lossless_samples(data: &[u8], stride: usize, row_len: usize, height: u32) -> Vec<u16> {
+    data.chunks(stride)
+        .take(height as usize)
+        .flat_map(|row| row[..row_len].iter().map(|v| *v as u16))
+        .collect()
+}
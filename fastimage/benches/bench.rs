@@ -48,6 +48,7 @@ fn bench_abs_diff_8u_c1r(c: &mut Criterion) {
     });
 }
 
+#[cfg(any(feature = "simd-sse2", feature = "simd-avx2"))]
 fn bench_abs_diff_simd(c: &mut Criterion) {
     #[cfg(feature = "simd-avx2")]
     use fastimage::simd_avx2 as simd;
@@ -65,6 +66,9 @@ fn bench_abs_diff_simd(c: &mut Criterion) {
     });
 }
 
+#[cfg(not(any(feature = "simd-sse2", feature = "simd-avx2")))]
+fn bench_abs_diff_simd(_c: &mut Criterion) {}
+
 fn bench_abs_diff_naive_v2(c: &mut Criterion) {
     const W: usize = 1280;
     const H: usize = 1024;
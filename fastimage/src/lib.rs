@@ -1,5 +1,11 @@
-//! Provides interface for Intel IPP
-
+//! Provides fast image operations, optionally using Intel IPP
+//!
+//! The image operations in [ripp] are implemented with Intel IPP when the `ipp`
+//! feature is enabled (the default). Otherwise, or if the `portable` feature is
+//! enabled, a pure Rust implementation is used. This can be built without IPP
+//! installed and on platforms not supported by IPP.
+
+#[cfg(feature = "ipp")]
 extern crate ipp_sys as ipp;
 extern crate num_traits;
 
@@ -8,12 +14,24 @@ extern crate core;
 use std::marker::PhantomData;
 pub use std::os::raw as ipp_ctypes;
 
+#[cfg(all(feature = "ipp", not(feature = "portable")))]
+mod ipp_backend;
+#[cfg(all(feature = "ipp", not(feature = "portable")))]
+pub use ipp_backend::{ripp, MomentState};
+
+#[cfg(any(feature = "portable", not(feature = "ipp")))]
+mod portable;
+#[cfg(any(feature = "portable", not(feature = "ipp")))]
+pub use portable::{ripp, MomentState};
+
 pub type IppStatusType = ipp_ctypes::c_int;
+#[cfg(feature = "ipp")]
 pub const NO_IPP_ERR: IppStatusType = ipp::ippStsNoErr as IppStatusType;
 
 // ---------------------------
 // errors
 
+#[cfg(feature = "ipp")]
 pub fn ipp_status_string(status: IppStatusType) -> &'static str {
     // Intel manual says this is a "pointer to internal static buffer,
     // need not be released".
@@ -45,6 +63,8 @@ pub enum Error {
     FailedAlloc,
     #[error("MismatchedCompileRuntimeVersions (compiled: {0}, runtime: {1}, level: {2}")]
     MismatchedCompileRuntimeVersions(ipp_ctypes::c_int, ipp_ctypes::c_int, &'static str),
+    #[error("UnsupportedMomentOrder")]
+    UnsupportedMomentOrder,
 }
 
 // ---------------------------
@@ -149,18 +169,6 @@ pub mod simd_avx2 {
     }
 }
 
-macro_rules! itry {
-    ($x:expr) => {
-        match unsafe { $x } {
-            NO_IPP_ERR => {}
-            e => {
-                let s = ipp_status_string(e);
-                return Err(Error::IppStatusError(e, s));
-            }
-        }
-    };
-}
-
 pub enum Chan1 {}
 pub enum Chan3 {}
 pub enum AChan4 {}
@@ -741,25 +749,21 @@ pub trait MutableFastImage: FastImage {
 /// Size (in pixels) of a region
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub struct FastImageSize {
-    inner: ipp::IppiSize,
+    width: ipp_ctypes::c_int,
+    height: ipp_ctypes::c_int,
 }
 
 impl FastImageSize {
     pub fn new(width: ipp_ctypes::c_int, height: ipp_ctypes::c_int) -> FastImageSize {
-        FastImageSize {
-            inner: ipp::IppiSize {
-                width: width,
-                height: height,
-            },
-        }
+        FastImageSize { width, height }
     }
     #[inline]
     pub fn width(&self) -> ipp_ctypes::c_int {
-        self.inner.width
+        self.width
     }
     #[inline]
     pub fn height(&self) -> ipp_ctypes::c_int {
-        self.inner.height
+        self.height
     }
 }
 
@@ -813,23 +817,22 @@ impl FastImageRegion {
 
 #[derive(Debug, Clone)]
 pub struct Point {
-    inner: ipp::IppiPoint,
+    x: ipp_ctypes::c_int,
+    y: ipp_ctypes::c_int,
 }
 
 impl Point {
     #[inline]
     pub fn new(x: ipp_ctypes::c_int, y: ipp_ctypes::c_int) -> Self {
-        Self {
-            inner: ipp::IppiPoint { x, y },
-        }
+        Self { x, y }
     }
     #[inline]
     pub fn x(&self) -> ipp_ctypes::c_int {
-        self.inner.x
+        self.x
     }
     #[inline]
     pub fn y(&self) -> ipp_ctypes::c_int {
-        self.inner.y
+        self.y
     }
 }
 
@@ -841,418 +844,6 @@ pub enum RoundMode {
     HintAccurate,
 }
 
-fn round_mode_to_ipp(round_mode: RoundMode) -> ipp::IppRoundMode::Type {
-    match round_mode {
-        RoundMode::Zero => ipp::IppRoundMode::ippRndZero,
-        RoundMode::Near => ipp::IppRoundMode::ippRndNear,
-        RoundMode::Financial => ipp::IppRoundMode::ippRndFinancial,
-        RoundMode::HintAccurate => ipp::IppRoundMode::ippRndHintAccurate,
-    }
-}
-
-macro_rules! version_assert {
-    ($compiled:expr, $runtime:expr, $level:expr) => {{
-        if $compiled != $runtime {
-            return Err(Error::MismatchedCompileRuntimeVersions(
-                $compiled, $runtime, $level,
-            ));
-        }
-    }};
-}
-
-pub mod ripp {
-    use *;
-
-    pub fn init() -> Result<()> {
-        itry!(ipp::ippInit());
-        // check that compile-time headers match runtime version
-        let version = IppVersion::new();
-        version_assert!(
-            ipp::IPP_VERSION_MAJOR as ipp_ctypes::c_int,
-            version.major(),
-            "major"
-        );
-        version_assert!(
-            ipp::IPP_VERSION_MINOR as ipp_ctypes::c_int,
-            version.minor(),
-            "minor"
-        );
-        // version_assert!(ipp::IPP_VERSION_UPDATE as ipp_ctypes::c_int, version.major_build(), "build");
-        Ok(())
-    }
-
-    pub fn copy_8u_c1r<S, D>(src: &S, dest: &mut D, size: &FastImageSize) -> Result<()>
-    where
-        S: FastImage<D = u8, C = Chan1>,
-        D: MutableFastImage<D = u8, C = Chan1>,
-    {
-        itry!(ipp::ippiCopy_8u_C1R(
-            src.raw_ptr(),
-            src.stride(),
-            dest.raw_mut_ptr(),
-            dest.stride(),
-            size.inner
-        ));
-        Ok(())
-    }
-
-    pub fn copy_32f_c1r<S, D>(src: &S, dest: &mut D, size: &FastImageSize) -> Result<()>
-    where
-        S: FastImage<D = f32, C = Chan1>,
-        D: MutableFastImage<D = f32, C = Chan1>,
-    {
-        itry!(ipp::ippiCopy_32f_C1R(
-            src.raw_ptr(),
-            src.stride(),
-            dest.raw_mut_ptr(),
-            dest.stride(),
-            size.inner
-        ));
-        Ok(())
-    }
-
-    pub fn convert_8u32f_c1r<S, D>(src: &S, dest: &mut D, size: &FastImageSize) -> Result<()>
-    where
-        S: FastImage<D = u8, C = Chan1>,
-        D: MutableFastImage<D = f32, C = Chan1>,
-    {
-        itry!(ipp::ippiConvert_8u32f_C1R(
-            src.raw_ptr(),
-            src.stride(),
-            dest.raw_mut_ptr(),
-            dest.stride(),
-            size.inner
-        ));
-        Ok(())
-    }
-
-    pub fn convert_32f8u_c1r<S, D>(
-        src: &S,
-        dest: &mut D,
-        size: &FastImageSize,
-        round_mode: RoundMode,
-    ) -> Result<()>
-    where
-        S: FastImage<D = f32, C = Chan1>,
-        D: MutableFastImage<D = u8, C = Chan1>,
-    {
-        itry!(ipp::ippiConvert_32f8u_C1R(
-            src.raw_ptr(),
-            src.stride(),
-            dest.raw_mut_ptr(),
-            dest.stride(),
-            size.inner,
-            round_mode_to_ipp(round_mode)
-        ));
-        Ok(())
-    }
-
-    pub fn compare_c_8u_c1r<S, D>(
-        src: &S,
-        value: u8,
-        dest: &mut D,
-        size: &FastImageSize,
-        cmp_op: CompareOp,
-    ) -> Result<()>
-    where
-        S: FastImage<D = u8, C = Chan1>,
-        D: MutableFastImage<D = u8, C = Chan1>,
-    {
-        itry!(ipp::ippiCompareC_8u_C1R(
-            src.raw_ptr(),
-            src.stride(),
-            value,
-            dest.raw_mut_ptr(),
-            dest.stride(),
-            size.inner,
-            get_compare_op(cmp_op)
-        ));
-        Ok(())
-    }
-
-    pub fn min_indx_8u_c1r<S>(src: &S, size: &FastImageSize) -> Result<(u8, Point)>
-    where
-        S: FastImage<D = u8, C = Chan1>,
-    {
-        let mut value = 0;
-        let mut loc = Point::new(-1, -1);
-
-        itry!(ipp::ippiMinIndx_8u_C1R(
-            src.raw_ptr(),
-            src.stride(),
-            size.inner,
-            &mut value,
-            &mut loc.inner.x,
-            &mut loc.inner.y,
-        ));
-        Ok((value, loc))
-    }
-
-    pub fn max_indx_8u_c1r<S>(src: &S, size: &FastImageSize) -> Result<(u8, Point)>
-    where
-        S: FastImage<D = u8, C = Chan1>,
-    {
-        let mut value = 0;
-        let mut loc = Point::new(-1, -1);
-
-        itry!(ipp::ippiMaxIndx_8u_C1R(
-            src.raw_ptr(),
-            src.stride(),
-            size.inner,
-            &mut value,
-            &mut loc.inner.x,
-            &mut loc.inner.y,
-        ));
-        Ok((value, loc))
-    }
-
-    pub fn threshold_val_8u_c1ir<SD>(
-        src_dest: &mut SD,
-        size: &FastImageSize,
-        threshold: u8,
-        value: u8,
-        cmp_op: CompareOp,
-    ) -> Result<()>
-    where
-        SD: MutableFastImage<D = u8, C = Chan1>,
-    {
-        itry!(ipp::ippiThreshold_Val_8u_C1IR(
-            src_dest.raw_mut_ptr(),
-            src_dest.stride(),
-            size.inner,
-            threshold,
-            value,
-            get_compare_op(cmp_op)
-        ));
-        Ok(())
-    }
-
-    /// Subtract `src1` from `src2` and put results in `dest`.
-    /// In other words, `dest = src2 - src` for each pixel.
-    pub fn sub_8u_c1rsfs<S1, S2, D>(
-        src1: &S1,
-        src2: &S2,
-        dest: &mut D,
-        size: &FastImageSize,
-        scale_factor: ipp_ctypes::c_int,
-    ) -> Result<()>
-    where
-        S1: FastImage<D = u8, C = Chan1>,
-        S2: FastImage<D = u8, C = Chan1>,
-        D: MutableFastImage<D = u8, C = Chan1>,
-    {
-        itry!(ipp::ippiSub_8u_C1RSfs(
-            src1.raw_ptr(),
-            src1.stride(),
-            src2.raw_ptr(),
-            src2.stride(),
-            dest.raw_mut_ptr(),
-            dest.stride(),
-            size.inner,
-            scale_factor
-        ));
-        Ok(())
-    }
-
-    /// Subtract `src1` from `src2` and put results in `dest`.
-    /// In other words, `dest = src2 - src` for each pixel.
-    pub fn sub_32f_c1r<S1, S2, D>(
-        src1: &S1,
-        src2: &S2,
-        dest: &mut D,
-        size: &FastImageSize,
-    ) -> Result<()>
-    where
-        S1: FastImage<D = f32, C = Chan1>,
-        S2: FastImage<D = f32, C = Chan1>,
-        D: MutableFastImage<D = f32, C = Chan1>,
-    {
-        itry!(ipp::ippiSub_32f_C1R(
-            src1.raw_ptr(),
-            src1.stride(),
-            src2.raw_ptr(),
-            src2.stride(),
-            dest.raw_mut_ptr(),
-            dest.stride(),
-            size.inner
-        ));
-        Ok(())
-    }
-
-    pub fn abs_32f_c1r<S, D>(src: &S, dest: &mut D, size: &FastImageSize) -> Result<()>
-    where
-        S: FastImage<D = f32, C = Chan1>,
-        D: MutableFastImage<D = f32, C = Chan1>,
-    {
-        itry!(ipp::ippiAbs_32f_C1R(
-            src.raw_ptr(),
-            src.stride(),
-            dest.raw_mut_ptr(),
-            dest.stride(),
-            size.inner
-        ));
-        Ok(())
-    }
-
-    pub fn sqrt_32f_c1ir<SD>(src_dest: &mut SD, size: &FastImageSize) -> Result<()>
-    where
-        SD: MutableFastImage<D = f32, C = Chan1>,
-    {
-        itry!(ipp::ippiSqrt_32f_C1IR(
-            src_dest.raw_mut_ptr(),
-            src_dest.stride(),
-            size.inner
-        ));
-        Ok(())
-    }
-
-    pub fn mul_c_32f_c1ir<SD>(k: f32, src_dest: &mut SD, size: &FastImageSize) -> Result<()>
-    where
-        SD: MutableFastImage<D = f32, C = Chan1>,
-    {
-        itry!(ipp::ippiMulC_32f_C1IR(
-            k,
-            src_dest.raw_mut_ptr(),
-            src_dest.stride(),
-            size.inner
-        ));
-        Ok(())
-    }
-
-    pub fn abs_diff_8u_c1r<S1, S2, D>(
-        src1: &S1,
-        src2: &S2,
-        dest: &mut D,
-        size: &FastImageSize,
-    ) -> Result<()>
-    where
-        S1: FastImage<D = u8, C = Chan1>,
-        S2: FastImage<D = u8, C = Chan1>,
-        D: MutableFastImage<D = u8, C = Chan1>,
-    {
-        itry!(ipp::ippiAbsDiff_8u_C1R(
-            src1.raw_ptr(),
-            src1.stride(),
-            src2.raw_ptr(),
-            src2.stride(),
-            dest.raw_mut_ptr(),
-            dest.stride(),
-            size.inner
-        ));
-        Ok(())
-    }
-
-    pub fn add_weighted_8u32f_c1ir<S, D>(
-        src: &S,
-        src_dst: &mut D,
-        size: &FastImageSize,
-        alpha: f32,
-    ) -> Result<()>
-    where
-        S: FastImage<D = u8, C = Chan1>,
-        D: MutableFastImage<D = f32, C = Chan1>,
-    {
-        itry!(ipp::ippiAddWeighted_8u32f_C1IR(
-            src.raw_ptr(),
-            src.stride(),
-            src_dst.raw_mut_ptr(),
-            src_dst.stride(),
-            size.inner,
-            alpha
-        ));
-        Ok(())
-    }
-
-    pub fn add_weighted_32f_c1ir<S, D>(
-        src: &S,
-        src_dst: &mut D,
-        size: &FastImageSize,
-        alpha: f32,
-    ) -> Result<()>
-    where
-        S: FastImage<D = f32, C = Chan1>,
-        D: MutableFastImage<D = f32, C = Chan1>,
-    {
-        itry!(ipp::ippiAddWeighted_32f_C1IR(
-            src.raw_ptr(),
-            src.stride(),
-            src_dst.raw_mut_ptr(),
-            src_dst.stride(),
-            size.inner,
-            alpha
-        ));
-        Ok(())
-    }
-
-    pub fn moments_8u_c1r<S>(src: &S, size: &FastImageSize, result: &mut MomentState) -> Result<()>
-    where
-        S: FastImage<D = u8, C = Chan1>,
-    {
-        itry!(ipp::ippiMoments64f_8u_C1R(
-            src.raw_ptr(),
-            src.stride(),
-            size.inner,
-            result.as_mut_ptr()
-        ));
-        result.valid = true;
-        Ok(())
-    }
-
-    pub fn set_8u_c1r<D>(value: u8, dest: &mut D, size: &FastImageSize) -> Result<()>
-    where
-        D: MutableFastImage<D = u8, C = Chan1>,
-    {
-        itry!(ipp::ippiSet_8u_C1R(
-            value,
-            dest.raw_mut_ptr(),
-            dest.stride(),
-            size.inner
-        ));
-        Ok(())
-    }
-
-    pub fn set_32f_c1r<D>(value: f32, dest: &mut D, size: &FastImageSize) -> Result<()>
-    where
-        D: MutableFastImage<D = f32, C = Chan1>,
-    {
-        itry!(ipp::ippiSet_32f_C1R(
-            value,
-            dest.raw_mut_ptr(),
-            dest.stride(),
-            size.inner
-        ));
-        Ok(())
-    }
-
-    pub fn set_8u_c1mr<D, M>(value: u8, dest: &mut D, size: &FastImageSize, mask: &M) -> Result<()>
-    where
-        D: MutableFastImage<D = u8, C = Chan1>,
-        M: FastImage<D = u8, C = Chan1>,
-    {
-        itry!(ipp::ippiSet_8u_C1MR(
-            value,
-            dest.raw_mut_ptr(),
-            dest.stride(),
-            size.inner,
-            mask.raw_ptr(),
-            mask.stride()
-        ));
-        Ok(())
-    }
-
-    pub fn sqr_32f_c1ir<SD>(src_dest: &mut SD, size: &FastImageSize) -> Result<()>
-    where
-        SD: MutableFastImage<D = f32, C = Chan1>,
-    {
-        itry!(ipp::ippiSqr_32f_C1IR(
-            src_dest.raw_mut_ptr(),
-            src_dest.stride(),
-            size.inner
-        ));
-        Ok(())
-    }
-}
-
 #[derive(Copy, Clone, Debug)]
 pub enum AlgorithmHint {
     NoHint,
@@ -1269,100 +860,12 @@ pub enum CompareOp {
     Greater,
 }
 
-#[inline]
-fn hint_to_ipp(hint: AlgorithmHint) -> ipp::IppHintAlgorithm::Type {
-    match hint {
-        AlgorithmHint::NoHint => ipp::IppHintAlgorithm::ippAlgHintNone,
-        AlgorithmHint::Fast => ipp::IppHintAlgorithm::ippAlgHintFast,
-        AlgorithmHint::Accurate => ipp::IppHintAlgorithm::ippAlgHintAccurate,
-    }
-}
-
-#[inline]
-fn get_compare_op(cmp: CompareOp) -> ipp::IppCmpOp::Type {
-    match cmp {
-        CompareOp::Less => ipp::IppCmpOp::ippCmpLess,
-        CompareOp::LessEqual => ipp::IppCmpOp::ippCmpLessEq,
-        CompareOp::Equal => ipp::IppCmpOp::ippCmpEq,
-        CompareOp::GreaterEqual => ipp::IppCmpOp::ippCmpGreaterEq,
-        CompareOp::Greater => ipp::IppCmpOp::ippCmpGreater,
-    }
-}
-
-pub struct MomentState {
-    data: Box<[u8]>,
-    valid: bool,
-}
-
-impl MomentState {
-    pub fn new(hint_algorithm: AlgorithmHint) -> Result<MomentState> {
-        let mut size = -1;
-        itry!(ipp::ippiMomentGetStateSize_64f(
-            hint_to_ipp(hint_algorithm),
-            &mut size
-        ));
-        let mut data = vec![0; size as usize].into_boxed_slice();
-        itry!(ipp::ippiMomentInit_64f(
-            data.as_mut_ptr() as *mut ipp::MomentState64f,
-            hint_to_ipp(hint_algorithm)
-        ));
-        Ok(MomentState {
-            data: data,
-            valid: false,
-        })
-    }
-    fn as_mut_ptr(&mut self) -> *mut ipp::MomentState64f {
-        self.data.as_mut_ptr() as *mut ipp::MomentState64f
-    }
-    fn as_ptr(&self) -> *const ipp::MomentState64f {
-        self.data.as_ptr() as *const ipp::MomentState64f
-    }
-    pub fn spatial(
-        &self,
-        m_ord: ipp_ctypes::c_int,
-        n_ord: ipp_ctypes::c_int,
-        n_channel: ipp_ctypes::c_int,
-        roi_offset: &Point,
-    ) -> Result<f64> {
-        if !self.valid {
-            return Err(Error::MomentStateNotInitialized);
-        }
-        let mut result = 0.0;
-        itry!(ipp::ippiGetSpatialMoment_64f(
-            self.as_ptr(),
-            m_ord,
-            n_ord,
-            n_channel,
-            roi_offset.inner,
-            &mut result
-        ));
-        Ok(result)
-    }
-    pub fn central(
-        &self,
-        m_ord: ipp_ctypes::c_int,
-        n_ord: ipp_ctypes::c_int,
-        n_channel: ipp_ctypes::c_int,
-    ) -> Result<f64> {
-        if !self.valid {
-            return Err(Error::MomentStateNotInitialized);
-        }
-        let mut result = 0.0;
-        itry!(ipp::ippiGetCentralMoment_64f(
-            self.as_ptr(),
-            m_ord,
-            n_ord,
-            n_channel,
-            &mut result
-        ));
-        Ok(result)
-    }
-}
-
+#[cfg(feature = "ipp")]
 pub struct IppVersion {
     version: *const ipp::IppLibraryVersion,
 }
 
+#[cfg(feature = "ipp")]
 impl IppVersion {
     pub fn new() -> IppVersion {
         let mut version: *const ipp::IppLibraryVersion = std::ptr::null_mut();
@@ -1413,6 +916,7 @@ impl IppVersion {
     }
 }
 
+#[cfg(feature = "ipp")]
 impl std::fmt::Debug for IppVersion {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         let inner: &ipp::IppLibraryVersion = unsafe { &*self.version };
@@ -0,0 +1,532 @@
+//! Implementation of the image operations in pure Rust.
+
+use *;
+
+/// The highest order of moments computed.
+const MAX_MOMENT_ORDER: usize = 3;
+
+#[inline]
+fn compare<T: PartialOrd>(a: T, b: T, cmp_op: CompareOp) -> bool {
+    match cmp_op {
+        CompareOp::Less => a < b,
+        CompareOp::LessEqual => a <= b,
+        CompareOp::Equal => a == b,
+        CompareOp::GreaterEqual => a >= b,
+        CompareOp::Greater => a > b,
+    }
+}
+
+/// Round to the nearest integer, with ties rounded to the even integer.
+#[inline]
+fn round_half_even(x: f32) -> f32 {
+    if (x - x.trunc()).abs() == 0.5 {
+        2.0 * (x / 2.0).round()
+    } else {
+        x.round()
+    }
+}
+
+#[inline]
+fn saturate_8u(x: f32) -> u8 {
+    if x.is_nan() {
+        0
+    } else {
+        x.clamp(0.0, 255.0) as u8
+    }
+}
+
+fn abs_diff_row(src1: &[u8], src2: &[u8], dest: &mut [u8]) {
+    #[cfg(all(
+        feature = "simd-avx2",
+        any(target_arch = "x86", target_arch = "x86_64")
+    ))]
+    {
+        if is_x86_feature_detected!("avx2") {
+            unsafe { simd_avx2::abs_diff_8u_c1r(src1, src2, dest) };
+            return;
+        }
+    }
+    #[cfg(all(
+        feature = "simd-sse2",
+        any(target_arch = "x86", target_arch = "x86_64")
+    ))]
+    {
+        if is_x86_feature_detected!("sse2") {
+            unsafe { simd_sse2::abs_diff_8u_c1r(src1, src2, dest) };
+            return;
+        }
+    }
+    for ((a, b), d) in src1.iter().zip(src2.iter()).zip(dest.iter_mut()) {
+        *d = std::cmp::max(*a, *b) - std::cmp::min(*a, *b);
+    }
+}
+
+pub mod ripp {
+    use super::*;
+
+    /// Apply `f` to each pixel of `src` with the corresponding pixel of `dest`.
+    #[inline]
+    fn for_each2<S, D, F>(src: &S, dest: &mut D, size: &FastImageSize, mut f: F)
+    where
+        S: FastImage<C = Chan1>,
+        D: MutableFastImage<C = Chan1>,
+        F: FnMut(&S::D, &mut D::D),
+    {
+        let w = size.width() as usize;
+        for row in 0..size.height() as usize {
+            let src_row = &src.row_slice(row)[..w];
+            let dest_row = &mut dest.row_slice_mut(row)[..w];
+            for (s, d) in src_row.iter().zip(dest_row.iter_mut()) {
+                f(s, d);
+            }
+        }
+    }
+
+    /// Apply `f` to each pixel of `src_dest`.
+    #[inline]
+    fn for_each_mut<SD, F>(src_dest: &mut SD, size: &FastImageSize, mut f: F)
+    where
+        SD: MutableFastImage<C = Chan1>,
+        F: FnMut(&mut SD::D),
+    {
+        let w = size.width() as usize;
+        for row in 0..size.height() as usize {
+            for d in src_dest.row_slice_mut(row)[..w].iter_mut() {
+                f(d);
+            }
+        }
+    }
+
+    /// Apply `f` to each pixel of `src1` with the corresponding pixels of
+    /// `src2` and `dest`.
+    #[inline]
+    fn for_each3<S1, S2, D, F>(src1: &S1, src2: &S2, dest: &mut D, size: &FastImageSize, mut f: F)
+    where
+        S1: FastImage<C = Chan1>,
+        S2: FastImage<C = Chan1>,
+        D: MutableFastImage<C = Chan1>,
+        F: FnMut(&S1::D, &S2::D, &mut D::D),
+    {
+        let w = size.width() as usize;
+        for row in 0..size.height() as usize {
+            let src1_row = &src1.row_slice(row)[..w];
+            let src2_row = &src2.row_slice(row)[..w];
+            let dest_row = &mut dest.row_slice_mut(row)[..w];
+            for ((s1, s2), d) in src1_row
+                .iter()
+                .zip(src2_row.iter())
+                .zip(dest_row.iter_mut())
+            {
+                f(s1, s2, d);
+            }
+        }
+    }
+
+    /// Find the first pixel, in row-major order, for which `is_better` is true
+    /// when compared with all previous pixels.
+    fn find_indx<S, F>(src: &S, size: &FastImageSize, is_better: F) -> Result<(u8, Point)>
+    where
+        S: FastImage<D = u8, C = Chan1>,
+        F: Fn(u8, u8) -> bool,
+    {
+        let w = size.width() as usize;
+        let mut best: Option<(u8, Point)> = None;
+        for row in 0..size.height() as usize {
+            for (col, value) in src.row_slice(row)[..w].iter().enumerate() {
+                let replace = match best {
+                    None => true,
+                    Some((best_value, _)) => is_better(*value, best_value),
+                };
+                if replace {
+                    best = Some((
+                        *value,
+                        Point::new(col as ipp_ctypes::c_int, row as ipp_ctypes::c_int),
+                    ));
+                }
+            }
+        }
+        Ok(best.unwrap_or((0, Point::new(-1, -1))))
+    }
+
+    pub fn init() -> Result<()> {
+        Ok(())
+    }
+
+    pub fn copy_8u_c1r<S, D>(src: &S, dest: &mut D, size: &FastImageSize) -> Result<()>
+    where
+        S: FastImage<D = u8, C = Chan1>,
+        D: MutableFastImage<D = u8, C = Chan1>,
+    {
+        let w = size.width() as usize;
+        for row in 0..size.height() as usize {
+            dest.row_slice_mut(row)[..w].copy_from_slice(&src.row_slice(row)[..w]);
+        }
+        Ok(())
+    }
+
+    pub fn copy_32f_c1r<S, D>(src: &S, dest: &mut D, size: &FastImageSize) -> Result<()>
+    where
+        S: FastImage<D = f32, C = Chan1>,
+        D: MutableFastImage<D = f32, C = Chan1>,
+    {
+        let w = size.width() as usize;
+        for row in 0..size.height() as usize {
+            dest.row_slice_mut(row)[..w].copy_from_slice(&src.row_slice(row)[..w]);
+        }
+        Ok(())
+    }
+
+    pub fn convert_8u32f_c1r<S, D>(src: &S, dest: &mut D, size: &FastImageSize) -> Result<()>
+    where
+        S: FastImage<D = u8, C = Chan1>,
+        D: MutableFastImage<D = f32, C = Chan1>,
+    {
+        for_each2(src, dest, size, |s, d| *d = *s as f32);
+        Ok(())
+    }
+
+    pub fn convert_32f8u_c1r<S, D>(
+        src: &S,
+        dest: &mut D,
+        size: &FastImageSize,
+        round_mode: RoundMode,
+    ) -> Result<()>
+    where
+        S: FastImage<D = f32, C = Chan1>,
+        D: MutableFastImage<D = u8, C = Chan1>,
+    {
+        let round: fn(f32) -> f32 = match round_mode {
+            RoundMode::Zero => f32::trunc,
+            RoundMode::Near | RoundMode::HintAccurate => round_half_even,
+            RoundMode::Financial => f32::round,
+        };
+        for_each2(src, dest, size, |s, d| *d = saturate_8u(round(*s)));
+        Ok(())
+    }
+
+    pub fn compare_c_8u_c1r<S, D>(
+        src: &S,
+        value: u8,
+        dest: &mut D,
+        size: &FastImageSize,
+        cmp_op: CompareOp,
+    ) -> Result<()>
+    where
+        S: FastImage<D = u8, C = Chan1>,
+        D: MutableFastImage<D = u8, C = Chan1>,
+    {
+        for_each2(src, dest, size, |s, d| {
+            *d = if compare(*s, value, cmp_op) { 255 } else { 0 }
+        });
+        Ok(())
+    }
+
+    pub fn min_indx_8u_c1r<S>(src: &S, size: &FastImageSize) -> Result<(u8, Point)>
+    where
+        S: FastImage<D = u8, C = Chan1>,
+    {
+        find_indx(src, size, |value, best| value < best)
+    }
+
+    pub fn max_indx_8u_c1r<S>(src: &S, size: &FastImageSize) -> Result<(u8, Point)>
+    where
+        S: FastImage<D = u8, C = Chan1>,
+    {
+        find_indx(src, size, |value, best| value > best)
+    }
+
+    pub fn threshold_val_8u_c1ir<SD>(
+        src_dest: &mut SD,
+        size: &FastImageSize,
+        threshold: u8,
+        value: u8,
+        cmp_op: CompareOp,
+    ) -> Result<()>
+    where
+        SD: MutableFastImage<D = u8, C = Chan1>,
+    {
+        for_each_mut(src_dest, size, |d| {
+            if compare(*d, threshold, cmp_op) {
+                *d = value;
+            }
+        });
+        Ok(())
+    }
+
+    /// Subtract `src1` from `src2` and put results in `dest`.
+    /// In other words, `dest = src2 - src` for each pixel.
+    pub fn sub_8u_c1rsfs<S1, S2, D>(
+        src1: &S1,
+        src2: &S2,
+        dest: &mut D,
+        size: &FastImageSize,
+        scale_factor: ipp_ctypes::c_int,
+    ) -> Result<()>
+    where
+        S1: FastImage<D = u8, C = Chan1>,
+        S2: FastImage<D = u8, C = Chan1>,
+        D: MutableFastImage<D = u8, C = Chan1>,
+    {
+        if scale_factor == 0 {
+            for_each3(src1, src2, dest, size, |s1, s2, d| {
+                *d = s2.saturating_sub(*s1)
+            });
+        } else {
+            let scale = 2.0f32.powi(-scale_factor);
+            for_each3(src1, src2, dest, size, |s1, s2, d| {
+                let diff = *s2 as f32 - *s1 as f32;
+                *d = saturate_8u(round_half_even(diff * scale));
+            });
+        }
+        Ok(())
+    }
+
+    /// Subtract `src1` from `src2` and put results in `dest`.
+    /// In other words, `dest = src2 - src` for each pixel.
+    pub fn sub_32f_c1r<S1, S2, D>(
+        src1: &S1,
+        src2: &S2,
+        dest: &mut D,
+        size: &FastImageSize,
+    ) -> Result<()>
+    where
+        S1: FastImage<D = f32, C = Chan1>,
+        S2: FastImage<D = f32, C = Chan1>,
+        D: MutableFastImage<D = f32, C = Chan1>,
+    {
+        for_each3(src1, src2, dest, size, |s1, s2, d| *d = s2 - s1);
+        Ok(())
+    }
+
+    pub fn abs_32f_c1r<S, D>(src: &S, dest: &mut D, size: &FastImageSize) -> Result<()>
+    where
+        S: FastImage<D = f32, C = Chan1>,
+        D: MutableFastImage<D = f32, C = Chan1>,
+    {
+        for_each2(src, dest, size, |s, d| *d = s.abs());
+        Ok(())
+    }
+
+    pub fn sqrt_32f_c1ir<SD>(src_dest: &mut SD, size: &FastImageSize) -> Result<()>
+    where
+        SD: MutableFastImage<D = f32, C = Chan1>,
+    {
+        for_each_mut(src_dest, size, |d| *d = d.sqrt());
+        Ok(())
+    }
+
+    pub fn mul_c_32f_c1ir<SD>(k: f32, src_dest: &mut SD, size: &FastImageSize) -> Result<()>
+    where
+        SD: MutableFastImage<D = f32, C = Chan1>,
+    {
+        for_each_mut(src_dest, size, |d| *d *= k);
+        Ok(())
+    }
+
+    pub fn abs_diff_8u_c1r<S1, S2, D>(
+        src1: &S1,
+        src2: &S2,
+        dest: &mut D,
+        size: &FastImageSize,
+    ) -> Result<()>
+    where
+        S1: FastImage<D = u8, C = Chan1>,
+        S2: FastImage<D = u8, C = Chan1>,
+        D: MutableFastImage<D = u8, C = Chan1>,
+    {
+        let w = size.width() as usize;
+        for row in 0..size.height() as usize {
+            abs_diff_row(
+                &src1.row_slice(row)[..w],
+                &src2.row_slice(row)[..w],
+                &mut dest.row_slice_mut(row)[..w],
+            );
+        }
+        Ok(())
+    }
+
+    pub fn add_weighted_8u32f_c1ir<S, D>(
+        src: &S,
+        src_dst: &mut D,
+        size: &FastImageSize,
+        alpha: f32,
+    ) -> Result<()>
+    where
+        S: FastImage<D = u8, C = Chan1>,
+        D: MutableFastImage<D = f32, C = Chan1>,
+    {
+        let beta = 1.0 - alpha;
+        for_each2(src, src_dst, size, |s, d| {
+            *d = *d * beta + *s as f32 * alpha
+        });
+        Ok(())
+    }
+
+    pub fn add_weighted_32f_c1ir<S, D>(
+        src: &S,
+        src_dst: &mut D,
+        size: &FastImageSize,
+        alpha: f32,
+    ) -> Result<()>
+    where
+        S: FastImage<D = f32, C = Chan1>,
+        D: MutableFastImage<D = f32, C = Chan1>,
+    {
+        let beta = 1.0 - alpha;
+        for_each2(src, src_dst, size, |s, d| *d = *d * beta + *s * alpha);
+        Ok(())
+    }
+
+    pub fn moments_8u_c1r<S>(src: &S, size: &FastImageSize, result: &mut MomentState) -> Result<()>
+    where
+        S: FastImage<D = u8, C = Chan1>,
+    {
+        let w = size.width() as usize;
+        let mut raw = [[0.0; MAX_MOMENT_ORDER + 1]; MAX_MOMENT_ORDER + 1];
+        for row in 0..size.height() as usize {
+            let y = row as f64;
+            // Moments of this row in x, multiplied by y^n below.
+            let mut row_raw = [0.0; MAX_MOMENT_ORDER + 1];
+            for (col, value) in src.row_slice(row)[..w].iter().enumerate() {
+                if *value == 0 {
+                    continue;
+                }
+                let x = col as f64;
+                let mut xm = *value as f64;
+                for m in row_raw.iter_mut() {
+                    *m += xm;
+                    xm *= x;
+                }
+            }
+            for (raw_m, row_raw_m) in raw.iter_mut().zip(row_raw.iter()) {
+                let mut yn = 1.0;
+                for raw_mn in raw_m.iter_mut() {
+                    *raw_mn += row_raw_m * yn;
+                    yn *= y;
+                }
+            }
+        }
+        result.raw = raw;
+        result.valid = true;
+        Ok(())
+    }
+
+    pub fn set_8u_c1r<D>(value: u8, dest: &mut D, size: &FastImageSize) -> Result<()>
+    where
+        D: MutableFastImage<D = u8, C = Chan1>,
+    {
+        for_each_mut(dest, size, |d| *d = value);
+        Ok(())
+    }
+
+    pub fn set_32f_c1r<D>(value: f32, dest: &mut D, size: &FastImageSize) -> Result<()>
+    where
+        D: MutableFastImage<D = f32, C = Chan1>,
+    {
+        for_each_mut(dest, size, |d| *d = value);
+        Ok(())
+    }
+
+    pub fn set_8u_c1mr<D, M>(value: u8, dest: &mut D, size: &FastImageSize, mask: &M) -> Result<()>
+    where
+        D: MutableFastImage<D = u8, C = Chan1>,
+        M: FastImage<D = u8, C = Chan1>,
+    {
+        for_each2(mask, dest, size, |m, d| {
+            if *m != 0 {
+                *d = value;
+            }
+        });
+        Ok(())
+    }
+
+    pub fn sqr_32f_c1ir<SD>(src_dest: &mut SD, size: &FastImageSize) -> Result<()>
+    where
+        SD: MutableFastImage<D = f32, C = Chan1>,
+    {
+        for_each_mut(src_dest, size, |d| *d *= *d);
+        Ok(())
+    }
+}
+
+#[inline]
+fn binomial(n: usize, k: usize) -> f64 {
+    (0..k).fold(1.0, |acc, i| acc * (n - i) as f64 / (i + 1) as f64)
+}
+
+pub struct MomentState {
+    /// Spatial moments about the ROI origin. `raw[m][n]` is the sum of
+    /// `value * x^m * y^n` over all pixels.
+    raw: [[f64; MAX_MOMENT_ORDER + 1]; MAX_MOMENT_ORDER + 1],
+    valid: bool,
+}
+
+impl MomentState {
+    pub fn new(_hint_algorithm: AlgorithmHint) -> Result<MomentState> {
+        Ok(MomentState {
+            raw: [[0.0; MAX_MOMENT_ORDER + 1]; MAX_MOMENT_ORDER + 1],
+            valid: false,
+        })
+    }
+
+    /// The moment of order (`m_ord`, `n_ord`) with the origin moved to
+    /// (`-dx`, `-dy`).
+    fn shifted(&self, m_ord: usize, n_ord: usize, dx: f64, dy: f64) -> f64 {
+        let mut result = 0.0;
+        for i in 0..=m_ord {
+            for j in 0..=n_ord {
+                result += binomial(m_ord, i)
+                    * binomial(n_ord, j)
+                    * dx.powi((m_ord - i) as i32)
+                    * dy.powi((n_ord - j) as i32)
+                    * self.raw[i][j];
+            }
+        }
+        result
+    }
+
+    fn check_args(
+        &self,
+        m_ord: ipp_ctypes::c_int,
+        n_ord: ipp_ctypes::c_int,
+        n_channel: ipp_ctypes::c_int,
+    ) -> Result<(usize, usize)> {
+        if !self.valid {
+            return Err(Error::MomentStateNotInitialized);
+        }
+        if n_channel != 0 {
+            return Err(Error::UnsupportedChannelType);
+        }
+        if m_ord < 0 || n_ord < 0 || (m_ord + n_ord) as usize > MAX_MOMENT_ORDER {
+            return Err(Error::UnsupportedMomentOrder);
+        }
+        Ok((m_ord as usize, n_ord as usize))
+    }
+
+    pub fn spatial(
+        &self,
+        m_ord: ipp_ctypes::c_int,
+        n_ord: ipp_ctypes::c_int,
+        n_channel: ipp_ctypes::c_int,
+        roi_offset: &Point,
+    ) -> Result<f64> {
+        let (m_ord, n_ord) = self.check_args(m_ord, n_ord, n_channel)?;
+        Ok(self.shifted(m_ord, n_ord, roi_offset.x() as f64, roi_offset.y() as f64))
+    }
+
+    pub fn central(
+        &self,
+        m_ord: ipp_ctypes::c_int,
+        n_ord: ipp_ctypes::c_int,
+        n_channel: ipp_ctypes::c_int,
+    ) -> Result<f64> {
+        let (m_ord, n_ord) = self.check_args(m_ord, n_ord, n_channel)?;
+        let m00 = self.raw[0][0];
+        if m00 == 0.0 {
+            return Ok(0.0);
+        }
+        let x_mean = self.raw[1][0] / m00;
+        let y_mean = self.raw[0][1] / m00;
+        Ok(self.shifted(m_ord, n_ord, -x_mean, -y_mean))
+    }
+}
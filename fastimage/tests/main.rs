@@ -1,15 +1,21 @@
 extern crate fastimage;
+#[cfg(feature = "ipp")]
 extern crate ipp_sys;
 extern crate itertools;
 
+#[cfg(feature = "ipp")]
 use std::os::raw as ipp_ctypes;
 
 use fastimage::{
-    ripp, Chan1, ChanTrait, CompareOp, FastImage, FastImageData, FastImageView, IppStatusType,
-    IppVersion, MomentState, MutableFastImage, MutableFastImageView,
+    ripp, Chan1, ChanTrait, CompareOp, FastImage, FastImageData, FastImageView, MomentState,
+    MutableFastImage, MutableFastImageView, RoundMode,
 };
+#[cfg(feature = "ipp")]
+use fastimage::{IppStatusType, IppVersion};
 
 #[test]
+#[cfg(any(feature = "simd-sse2", feature = "simd-avx2"))]
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
 fn test_simd_absdiff() {
     // const W: usize = 1280;
     // const H: usize = 1024;
@@ -45,11 +51,13 @@ fn test_simd_absdiff() {
 }
 
 #[test]
+#[cfg(feature = "ipp")]
 fn test_version() {
     let _version = IppVersion::new();
 }
 
 #[test]
+#[cfg(feature = "ipp")]
 fn test_check_version() {
     let version = IppVersion::new();
     // check that compile-time headers match runtime version
@@ -59,6 +67,7 @@ fn test_check_version() {
 }
 
 #[test]
+#[cfg(feature = "ipp")]
 fn test_status_string() {
     // ippStsNormErr has value -229 and bindgen might give it type i32
     let es = fastimage::ipp_status_string(ipp_sys::ippStsNormErr as IppStatusType);
@@ -238,6 +247,24 @@ fn test_compare() {
     }
 }
 
+#[test]
+fn test_convert_32f8u() {
+    ripp::init().unwrap();
+
+    let mut im = FastImageData::<Chan1, f32>::new(5, 1, 0.0).unwrap();
+    im.pixel_slice_mut(0, 0)[0] = 1.5;
+    im.pixel_slice_mut(0, 1)[0] = 2.5;
+    im.pixel_slice_mut(0, 2)[0] = 2.7;
+    im.pixel_slice_mut(0, 3)[0] = -1.0;
+    im.pixel_slice_mut(0, 4)[0] = 300.0;
+
+    let result = FastImageData::copy_from_32f8u_c1(&im, RoundMode::Zero).unwrap();
+    assert_eq!(result.row_slice(0), &[1, 2, 2, 0, 255]);
+
+    let result = FastImageData::copy_from_32f8u_c1(&im, RoundMode::Near).unwrap();
+    assert_eq!(result.row_slice(0), &[2, 2, 3, 0, 255]);
+}
+
 #[test]
 fn test_abs_diff() {
     ripp::init().unwrap();
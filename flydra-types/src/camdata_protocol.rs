@@ -0,0 +1,92 @@
+//! Versioning of the camera data sent from the cameras to braid.
+//!
+//! When sent as CBOR, each [FlydraRawUdpPacket] is wrapped in a
+//! [CamdataEnvelope] holding the [CamdataProtocolInfo] of the sender. The same
+//! info is sent when the camera registers with braid. Braid rejects cameras
+//! and packets with an incompatible protocol.
+
+use serde::{Deserialize, Serialize};
+
+use crate::{FlydraRawUdpPacket, FlydraTypesError, Result};
+
+/// Version of the protocol used to send camera data to braid.
+///
+/// Increment this with any incompatible change to [FlydraRawUdpPacket] or the
+/// types within it.
+pub const CAMDATA_PROTOCOL_VERSION: u16 = 2;
+
+bitflags! {
+    /// Optional data which may be sent by a camera.
+    #[derive(Serialize, Deserialize)]
+    pub struct CamdataCapabilities: u32 {
+        /// Points may have blob features.
+        const BLOB_FEATURES = 0b00000001;
+        /// Packets may have April Tag detections.
+        const APRILTAGS     = 0b00000010;
+    }
+}
+
+/// Describes the camera data protocol of a sender.
+///
+/// The order of the first two fields must never change so that any version
+/// can be read.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct CamdataProtocolInfo {
+    /// The protocol version, [CAMDATA_PROTOCOL_VERSION] when sent by this
+    /// build.
+    pub version: u16,
+    /// Name, version and revision of the sending program.
+    pub build_id: String,
+    pub capabilities: CamdataCapabilities,
+}
+
+impl CamdataProtocolInfo {
+    /// The protocol info of this build.
+    pub fn new(build_id: String, capabilities: CamdataCapabilities) -> Self {
+        Self {
+            version: CAMDATA_PROTOCOL_VERSION,
+            build_id,
+            capabilities,
+        }
+    }
+
+    /// Check if data from a sender with this protocol can be received.
+    pub fn check_compatible(&self) -> Result<()> {
+        if self.version != CAMDATA_PROTOCOL_VERSION {
+            return Err(FlydraTypesError::CamdataProtocolMismatch {
+                version: self.version,
+                expected: CAMDATA_PROTOCOL_VERSION,
+                build_id: self.build_id.clone(),
+            });
+        }
+        let unsupported = self.capabilities.bits() & !CamdataCapabilities::all().bits();
+        if unsupported != 0 {
+            return Err(FlydraTypesError::UnsupportedCamdataCapabilities {
+                bits: unsupported,
+                build_id: self.build_id.clone(),
+            });
+        }
+        Ok(())
+    }
+}
+
+/// A packet of camera data together with the protocol of its sender.
+///
+/// For sending, `P` can be `&FlydraRawUdpPacket` to avoid a copy.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct CamdataEnvelope<P = FlydraRawUdpPacket> {
+    pub protocol: CamdataProtocolInfo,
+    pub packet: P,
+}
+
+/// The part of a [CamdataEnvelope] which can be read regardless of version.
+#[derive(Deserialize)]
+pub(crate) struct CamdataEnvelopeVersion {
+    pub(crate) protocol: CamdataProtocolVersion,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct CamdataProtocolVersion {
+    pub(crate) version: u16,
+    pub(crate) build_id: String,
+}
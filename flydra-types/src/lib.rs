@@ -19,16 +19,21 @@ pub const DEFAULT_MODEL_SERVER_ADDR: &str = "0.0.0.0:8397";
 //
 // Any changes to these names, including additions and removes, should update
 // BraidMetadataSchemaTag.
-pub const BRAID_SCHEMA: u16 = 2; // BraidMetadataSchemaTag
+pub const BRAID_SCHEMA: u16 = 9; // BraidMetadataSchemaTag
 
 // CSV files. (These may also exist as .csv.gz)
 pub const KALMAN_ESTIMATES_CSV_FNAME: &str = "kalman_estimates.csv";
+pub const KALMAN_ESTIMATES_SMOOTHED_CSV_FNAME: &str = "kalman_estimates_smoothed.csv";
 pub const DATA_ASSOCIATE_CSV_FNAME: &str = "data_association.csv";
 pub const DATA2D_DISTORTED_CSV_FNAME: &str = "data2d_distorted.csv";
 pub const CAM_INFO_CSV_FNAME: &str = "cam_info.csv";
 pub const TRIGGER_CLOCK_INFO_CSV_FNAME: &str = "trigger_clock_info.csv";
 pub const EXPERIMENT_INFO_CSV_FNAME: &str = "experiment_info.csv";
 pub const TEXTLOG_CSV_FNAME: &str = "textlog.csv";
+pub const OBJ_ID_REMAPPING_CSV_FNAME: &str = "obj_id_remapping.csv";
+pub const APRILTAG_3D_CSV_FNAME: &str = "apriltag_3d.csv";
+pub const TRIGGER_VOLUME_EVENTS_CSV_FNAME: &str = "trigger_volume_events.csv";
+pub const CAMDATA_STATS_CSV_FNAME: &str = "camdata_stats.csv";
 
 // Other files
 pub const CALIBRATION_XML_FNAME: &str = "calibration.xml";
@@ -83,6 +88,114 @@ impl WithKey<SyncFno> for KalmanEstimatesRow {
     }
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DataAssocRow {
+    // changes to this struct should update BraidMetadataSchemaTag
+    pub obj_id: u32,
+    pub frame: SyncFno,
+    pub cam_num: CamNum,
+    pub pt_idx: u8,
+}
+impl WithKey<SyncFno> for DataAssocRow {
+    fn key(&self) -> SyncFno {
+        self.frame
+    }
+}
+
+/// The new object id of an object from the original recording.
+///
+/// Written when trajectories of the same object are joined after tracking.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ObjIdRemappingRow {
+    // changes to this struct should update BraidMetadataSchemaTag
+    pub orig_obj_id: u32,
+    pub obj_id: u32,
+}
+
+/// The 3D pose of an April Tag triangulated from multiple cameras.
+///
+/// The tag frame is centered on the tag with the x axis pointing from corner 0
+/// to corner 1, the y axis from corner 3 to corner 0 and the z axis normal to
+/// the tag.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AprilTag3dRow {
+    // changes to this struct should update BraidMetadataSchemaTag
+    pub frame: SyncFno,
+    #[serde(with = "crate::timestamp_opt_f64")]
+    pub timestamp: Option<FlydraFloatTimestampLocal<Triggerbox>>,
+    pub family: String,
+    pub tag_id: i32,
+    /// The tag center.
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+    /// The rotation from the tag frame to the world frame as quaternion.
+    pub qw: f64,
+    pub qx: f64,
+    pub qy: f64,
+    pub qz: f64,
+    pub corner0_x: f64,
+    pub corner0_y: f64,
+    pub corner0_z: f64,
+    pub corner1_x: f64,
+    pub corner1_y: f64,
+    pub corner1_z: f64,
+    pub corner2_x: f64,
+    pub corner2_y: f64,
+    pub corner2_z: f64,
+    pub corner3_x: f64,
+    pub corner3_y: f64,
+    pub corner3_z: f64,
+    /// The number of cameras which detected the tag.
+    pub n_cams: u8,
+    /// The mean reprojection distance (in pixels) of the center and corners.
+    pub mean_reproj_dist: f64,
+}
+impl WithKey<SyncFno> for AprilTag3dRow {
+    fn key(&self) -> SyncFno {
+        self.frame
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
+pub enum TriggerVolumeEventKind {
+    Enter,
+    Leave,
+}
+
+/// A tracked object entering or leaving a trigger volume.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TriggerVolumeEventRow {
+    // changes to this struct should update BraidMetadataSchemaTag
+    pub frame: SyncFno,
+    #[serde(with = "crate::timestamp_opt_f64")]
+    pub timestamp: Option<FlydraFloatTimestampLocal<Triggerbox>>,
+    pub obj_id: u32,
+    /// The name of the trigger volume.
+    pub volume: String,
+    pub event: TriggerVolumeEventKind,
+}
+impl WithKey<SyncFno> for TriggerVolumeEventRow {
+    fn key(&self) -> SyncFno {
+        self.frame
+    }
+}
+
+/// The camera data received from one camera during an interval.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CamdataStatsRow {
+    // changes to this struct should update BraidMetadataSchemaTag
+    /// The time at the end of the interval.
+    #[serde(with = "crate::timestamp_f64")]
+    pub timestamp: FlydraFloatTimestampLocal<HostClock>,
+    pub camn: CamNum,
+    pub frames_received: usize,
+    /// The number of frames sent by the camera but not received.
+    pub frames_lost: usize,
+    /// See [RecentStats::sync_jitter].
+    pub sync_jitter: Option<f64>,
+}
+
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub struct FlydraRawUdpPoint {
     pub x0_abs: f64,
@@ -92,6 +205,97 @@ pub struct FlydraRawUdpPoint {
     pub cur_val: u8,
     pub mean_val: f64,
     pub sumsqf_val: f64,
+    /// Extended features of the detected blob, if enabled in the image tracker.
+    ///
+    /// This is always `None` for flydra1 custom serialized packets.
+    #[serde(default)]
+    pub blob_features: Option<BlobFeatures>,
+}
+
+/// Extended features of a blob detected by the image tracker.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct BlobFeatures {
+    /// The ellipse with the same second moments as the blob.
+    ///
+    /// This is `None` if the ellipse is degenerate (e.g. a single pixel).
+    pub ellipse: Option<BlobEllipse>,
+    /// The bounding box of the blob pixels in (distorted) pixel coordinates.
+    pub bbox: BlobBoundingBox,
+    /// The convex hull of the blob pixels, simplified to a small polygon.
+    ///
+    /// The vertices are (distorted) pixel coordinates. This is empty if the
+    /// contour was not requested.
+    pub contour: Vec<[f32; 2]>,
+    /// The raw image around the blob, if requested.
+    pub patch: Option<ImagePatch>,
+}
+
+impl BlobFeatures {
+    /// The contour vertices as space-separated `x y` pairs.
+    pub fn contour_string(&self) -> String {
+        let coords: Vec<String> = self
+            .contour
+            .iter()
+            .map(|xy| format!("{} {}", xy[0], xy[1]))
+            .collect();
+        coords.join(" ")
+    }
+}
+
+/// An ellipse describing the second moments of a blob.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct BlobEllipse {
+    /// The length of the semi-major axis, in pixels.
+    pub major_axis: f64,
+    /// The length of the semi-minor axis, in pixels.
+    pub minor_axis: f64,
+    /// The angle of the major axis from the x axis towards the y axis, in
+    /// radians. The range is -pi/2 to pi/2.
+    pub angle: f64,
+}
+
+/// A bounding box in pixel coordinates. All values are inclusive.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct BlobBoundingBox {
+    pub left: u32,
+    pub bottom: u32,
+    pub right: u32,
+    pub top: u32,
+}
+
+/// A small square of an 8 bit image.
+///
+/// Pixels outside the camera image are 0.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct ImagePatch {
+    /// The pixel coordinate of the first column.
+    pub left: i32,
+    /// The pixel coordinate of the first row.
+    pub bottom: i32,
+    /// The width and height of the patch.
+    pub size: u16,
+    /// The pixel values in row-major order.
+    pub data: Vec<u8>,
+}
+
+impl ImagePatch {
+    /// The pixel values as hexadecimal bytes.
+    pub fn data_hex(&self) -> String {
+        self.data.iter().map(|x| format!("{:02x}", x)).collect()
+    }
+}
+
+/// An April Tag detected by a camera.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct FlydraRawUdpAprilTag {
+    pub family: String,
+    pub id: i32,
+    /// The tag center in (distorted) pixel coordinates.
+    pub center: [f64; 2],
+    /// The tag corners in (distorted) pixel coordinates.
+    ///
+    /// The corners wrap counter-clockwise around the tag.
+    pub corners: [[f64; 2]; 4],
 }
 
 /// The original camera name from the driver.
@@ -139,6 +343,12 @@ pub struct RegisterNewCamera {
     pub ros_cam_name: RosCamName,
     /// Location of the camera control HTTP server.
     pub http_camserver_info: CamHttpServerInfo,
+    /// The protocol of the camera data sent by the camera.
+    ///
+    /// This is `None` when registered by releases which predate versioned
+    /// camera data. Such cameras are rejected.
+    #[serde(default)]
+    pub camdata_protocol: Option<CamdataProtocolInfo>,
 }
 
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
@@ -170,6 +380,8 @@ impl ConnectedCameraSyncState {
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub struct HttpApiShared {
     pub fake_sync: bool,
+    /// The cameras are synchronized from their host timestamps.
+    pub software_sync: bool,
     pub clock_model_copy: Option<ClockModel>,
     pub csv_tables_dirname: Option<RecordingPath>,
     pub calibration_filename: Option<String>,
@@ -177,6 +389,24 @@ pub struct HttpApiShared {
     pub model_server_addr: Option<std::net::SocketAddr>,
     pub flydra_app_name: String,
     pub all_expected_cameras_are_synced: bool,
+    /// The objects currently within each trigger volume.
+    pub trigger_volumes: Vec<TriggerVolumeStatus>,
+    /// Cameras which tried to register but were rejected.
+    pub rejected_cameras: Vec<RejectedCamera>,
+}
+
+/// A camera which braid did not accept.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct RejectedCamera {
+    pub name: RosCamName,
+    /// Why the camera was rejected.
+    pub reason: String,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct TriggerVolumeStatus {
+    pub name: String,
+    pub obj_ids: Vec<u32>,
 }
 
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
@@ -184,6 +414,29 @@ pub struct RecentStats {
     pub total_frames_collected: usize,
     pub frames_collected: usize,
     pub points_detected: usize,
+    /// The number of frames sent by the camera but not received.
+    ///
+    /// This is always 0 for cameras sending flydra1 custom serialized packets,
+    /// which have no sequence number.
+    pub frames_lost: usize,
+    pub total_frames_lost: usize,
+    /// The RMS difference, in seconds, between the host timestamps of the
+    /// frames and the clock model of the camera.
+    ///
+    /// This is only set with software synchronization.
+    pub sync_jitter: Option<f64>,
+}
+
+impl RecentStats {
+    /// The fraction of recently sent frames which were not received.
+    pub fn loss_rate(&self) -> f64 {
+        let n_sent = self.frames_collected + self.frames_lost;
+        if n_sent == 0 {
+            0.0
+        } else {
+            self.frames_lost as f64 / n_sent as f64
+        }
+    }
 }
 
 impl Default for RecentStats {
@@ -192,6 +445,9 @@ impl Default for RecentStats {
             total_frames_collected: 0,
             frames_collected: 0,
             points_detected: 0,
+            frames_lost: 0,
+            total_frames_lost: 0,
+            sync_jitter: None,
         }
     }
 }
@@ -283,6 +539,58 @@ pub struct TrackingParams {
     /// minimum number of observations before object becomes visible
     #[serde(default = "default_num_observations_to_visibility")]
     pub num_observations_to_visibility: u8,
+    /// kalman filter motion model
+    ///
+    /// The name of a model in the motion model registry of the `tracking`
+    /// crate: "constant-velocity", "flat-z-zero", "constant-acceleration" or
+    /// "imm". If not given, "constant-velocity" is used for 3D tracking and
+    /// "flat-z-zero" for flat-3d tracking.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub motion_model: Option<String>,
+    /// parameters of the "imm" motion model
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub imm_params: Option<ImmParams>,
+    /// data association method
+    #[serde(default)]
+    pub data_association: DataAssociationMethod,
+}
+
+/// How observations are associated with tracked objects
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DataAssociationMethod {
+    /// Each object, in turn, takes its most likely observation.
+    Greedy,
+    /// Global nearest neighbour: the assignment of observations to objects
+    /// with the highest joint likelihood.
+    Gnn,
+    /// Joint probabilistic data association: each object is updated with all
+    /// nearby observations, weighted by their association probability.
+    Jpda,
+}
+
+impl Default for DataAssociationMethod {
+    fn default() -> Self {
+        DataAssociationMethod::Greedy
+    }
+}
+
+/// Parameters of the interacting multiple model (IMM) motion model
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImmParams {
+    /// the motion models to mix
+    pub models: Vec<ImmModelParams>,
+    /// probability of switching from one model to another on each frame
+    pub switch_probability: f64,
+}
+
+/// A motion model mixed by the IMM motion model
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImmModelParams {
+    /// name of the motion model (any model except "imm")
+    pub motion_model: String,
+    /// kalman filter parameter
+    pub motion_noise_scale: f64,
 }
 
 fn default_num_observations_to_visibility() -> u8 {
@@ -291,6 +599,12 @@ fn default_num_observations_to_visibility() -> u8 {
     3
 }
 
+/// The motion model used for 3D tracking if not specified.
+const DEFAULT_MOTION_MODEL_3D: &str = "constant-velocity";
+
+/// The motion model used for flat-3d tracking if not specified.
+const DEFAULT_MOTION_MODEL_FLAT_3D: &str = "flat-z-zero";
+
 pub type MyFloat = f64;
 
 /// Tracking parameters actually used for tracking.
@@ -312,6 +626,12 @@ pub struct TrackingParamsInner3D {
     pub hypothesis_test_params: HypothesisTestParams,
     /// minimum number of observations before object becomes visible
     pub num_observations_to_visibility: u8,
+    /// kalman filter motion model
+    pub motion_model: String,
+    /// parameters of the "imm" motion model
+    pub imm_params: Option<ImmParams>,
+    /// data association method
+    pub data_association: DataAssociationMethod,
 }
 
 impl Into<TrackingParams> for TrackingParamsInner3D {
@@ -327,6 +647,9 @@ impl Into<TrackingParams> for TrackingParamsInner3D {
             max_position_std_meters: self.max_position_std_meters,
             hypothesis_test_params,
             num_observations_to_visibility: self.num_observations_to_visibility,
+            motion_model: Some(self.motion_model),
+            imm_params: self.imm_params,
+            data_association: self.data_association,
         }
     }
 }
@@ -357,6 +680,12 @@ impl TryFrom<&TrackingParams> for TrackingParamsInner3D {
             max_position_std_meters: orig.max_position_std_meters,
             num_observations_to_visibility: orig.num_observations_to_visibility,
             hypothesis_test_params,
+            motion_model: orig
+                .motion_model
+                .clone()
+                .unwrap_or_else(|| DEFAULT_MOTION_MODEL_3D.to_string()),
+            imm_params: orig.imm_params.clone(),
+            data_association: orig.data_association,
         })
     }
 }
@@ -372,6 +701,9 @@ impl Default for TrackingParamsInner3D {
             max_position_std_meters: 0.01212,
             hypothesis_test_params: make_hypothesis_test_full3d_default(),
             num_observations_to_visibility: default_num_observations_to_visibility(),
+            motion_model: DEFAULT_MOTION_MODEL_3D.to_string(),
+            imm_params: None,
+            data_association: DataAssociationMethod::default(),
         }
     }
 }
@@ -393,6 +725,12 @@ pub struct TrackingParamsInnerFlat3D {
     pub max_position_std_meters: f32,
     /// minimum number of observations before object becomes visible
     pub num_observations_to_visibility: u8,
+    /// kalman filter motion model
+    pub motion_model: String,
+    /// parameters of the "imm" motion model
+    pub imm_params: Option<ImmParams>,
+    /// data association method
+    pub data_association: DataAssociationMethod,
 }
 
 impl Into<TrackingParams> for TrackingParamsInnerFlat3D {
@@ -408,6 +746,9 @@ impl Into<TrackingParams> for TrackingParamsInnerFlat3D {
             max_position_std_meters: self.max_position_std_meters,
             hypothesis_test_params,
             num_observations_to_visibility: self.num_observations_to_visibility,
+            motion_model: Some(self.motion_model),
+            imm_params: self.imm_params,
+            data_association: self.data_association,
         }
     }
 }
@@ -428,6 +769,11 @@ impl TryFrom<TrackingParams> for TrackingParamsInnerFlat3D {
             accept_observation_min_likelihood: orig.accept_observation_min_likelihood,
             max_position_std_meters: orig.max_position_std_meters,
             num_observations_to_visibility: orig.num_observations_to_visibility,
+            motion_model: orig
+                .motion_model
+                .unwrap_or_else(|| DEFAULT_MOTION_MODEL_FLAT_3D.to_string()),
+            imm_params: orig.imm_params,
+            data_association: orig.data_association,
         })
     }
 }
@@ -442,6 +788,9 @@ impl Default for TrackingParamsInnerFlat3D {
             ekf_observation_covariance_pixels: 10.0,
             max_position_std_meters: 0.2,
             num_observations_to_visibility: default_num_observations_to_visibility(),
+            motion_model: DEFAULT_MOTION_MODEL_FLAT_3D.to_string(),
+            imm_params: None,
+            data_association: DataAssociationMethod::default(),
         }
     }
 }
@@ -501,6 +850,17 @@ pub struct FlydraRawUdpPacket {
     /// this will always be 0 for flydra1 custom serialized packets
     pub image_processing_steps: ImageProcessingSteps,
     pub points: Vec<FlydraRawUdpPoint>,
+    /// April Tags detected on this frame.
+    ///
+    /// This is always empty for flydra1 custom serialized packets.
+    #[serde(default)]
+    pub apriltags: Vec<FlydraRawUdpAprilTag>,
+    /// Sequence number of the packets sent by a camera, to detect lost
+    /// packets. This starts at 0 and increments with each packet sent.
+    ///
+    /// This is always None for flydra1 custom serialized packets.
+    #[serde(default)]
+    pub seq: Option<u64>,
 }
 
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
@@ -539,6 +899,8 @@ impl FlydraRawUdpPacket {
             preprocess_stamp: header.preprocess_stamp,
             image_processing_steps: header.image_processing_steps,
             points,
+            apriltags: Vec::new(),
+            seq: None,
         }
     }
 }
@@ -555,6 +917,11 @@ pub use crate::timestamp::{FlydraFloatTimestampLocal, HostClock, Source, Trigger
 pub mod timestamp_f64;
 pub mod timestamp_opt_f64;
 
+mod camdata_protocol;
+pub use crate::camdata_protocol::{
+    CamdataCapabilities, CamdataEnvelope, CamdataProtocolInfo, CAMDATA_PROTOCOL_VERSION,
+};
+
 mod serialize;
 pub use crate::serialize::{
     deserialize_packet, deserialize_point, serialize_packet, serialize_point, ReadFlydraExt,
@@ -589,6 +956,22 @@ pub enum FlydraTypesError {
     IoError(#[from] std::io::Error),
     #[error("{0}")]
     Utf8Error(#[from] std::str::Utf8Error),
+    #[error(
+        "camera data protocol version {version} of {build_id} is incompatible with \
+        version {expected} used here"
+    )]
+    CamdataProtocolMismatch {
+        version: u16,
+        expected: u16,
+        build_id: String,
+    },
+    #[error("{build_id} sends camera data with unsupported capabilities (bits {bits:#x})")]
+    UnsupportedCamdataCapabilities { bits: u32, build_id: String },
+    #[error(
+        "camera \"{cam_name}\" does not send a camera data protocol version. \
+        It is probably from an older release and must be upgraded."
+    )]
+    UnversionedCamdata { cam_name: String },
 }
 
 #[derive(Deserialize, Serialize, Debug)]
@@ -619,18 +1002,47 @@ impl AddrInfoIP {
 #[derive(Debug)]
 pub enum RealtimePointsDestAddr {
     UnixDomainSocket(AddrInfoUnixDomainSocket),
+    /// Send UDP datagrams.
     IpAddr(AddrInfoIP),
+    /// Send over a TCP connection. Packets are always CBOR encoded.
+    TcpStream(AddrInfoIP),
 }
 
 impl RealtimePointsDestAddr {
+    pub fn new(addr: &std::net::SocketAddr, transport: CamdataTransport) -> Self {
+        let addr = AddrInfoIP::from_socket_addr(addr);
+        match transport {
+            CamdataTransport::Udp => RealtimePointsDestAddr::IpAddr(addr),
+            CamdataTransport::Tcp => RealtimePointsDestAddr::TcpStream(addr),
+        }
+    }
+
     pub fn into_string(self) -> String {
         match self {
             RealtimePointsDestAddr::UnixDomainSocket(uds) => format!("file://{}", uds.filename),
             RealtimePointsDestAddr::IpAddr(ip) => format!("http://{}:{}", ip.ip(), ip.port()),
+            RealtimePointsDestAddr::TcpStream(ip) => format!("tcp://{}:{}", ip.ip(), ip.port()),
         }
     }
 }
 
+/// How a camera sends its data to braid.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+pub enum CamdataTransport {
+    /// UDP datagrams. Lost packets are not resent, so this has the lowest
+    /// latency.
+    Udp,
+    /// A TCP connection. No packets are lost, but a lossy network increases
+    /// the latency.
+    Tcp,
+}
+
+impl Default for CamdataTransport {
+    fn default() -> Self {
+        CamdataTransport::Udp
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct MainbrainBuiLocation(pub BuiServerInfo);
 
@@ -692,12 +1104,48 @@ impl Default for FakeSyncConfig {
     }
 }
 
+/// Synchronization of free-running cameras from the host timestamps of their
+/// frames.
+///
+/// The clocks of all computers with cameras must be synchronized, for example
+/// with PTP.
+///
+/// The host timestamp of a frame is taken when the frame arrives at the host,
+/// not when it is exposed. Differences between cameras in the latency from
+/// exposure to arrival (e.g. due to exposure time, interface or driver) are not
+/// corrected and offset the synchronized frame numbers of the cameras.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SoftwareSyncConfig {
+    /// The frame rate of the synchronized frame numbers. The cameras should be
+    /// set to this frame rate.
+    pub fps: f64,
+    /// The duration, in seconds, of the recent frames used to estimate the
+    /// offset and drift of each camera.
+    #[serde(default = "default_sync_fit_window_sec")]
+    pub fit_window_sec: f64,
+}
+
+fn default_sync_fit_window_sec() -> f64 {
+    10.0
+}
+
+impl Default for SoftwareSyncConfig {
+    fn default() -> Self {
+        Self {
+            fps: 95.0,
+            fit_window_sec: default_sync_fit_window_sec(),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
 #[serde(tag = "trigger_type")]
 pub enum TriggerType {
     TriggerboxV1(TriggerboxConfig),
     FakeSync(FakeSyncConfig),
+    SoftwareSync(SoftwareSyncConfig),
 }
 
 impl Default for TriggerType {
@@ -706,6 +1154,60 @@ impl Default for TriggerType {
     }
 }
 
+/// A region of 3D space emitting events when tracked objects enter or leave.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TriggerVolumeConfig {
+    /// The name of the volume, used in the enter and leave events.
+    pub name: String,
+    pub shape: TriggerVolumeShape,
+    /// The camtrig channel (1-4) to switch on while any object is inside.
+    #[serde(default)]
+    pub camtrig_channel: Option<u8>,
+    /// The triggerbox analog output to set while any object is inside.
+    #[serde(default)]
+    pub triggerbox_aout: Option<TriggerboxAout>,
+    /// Start a recording in this format on all cameras when an object enters,
+    /// using the post trigger configuration of each camera.
+    #[serde(default)]
+    pub post_trigger: Option<ci2_remote_control::PostTriggerFormat>,
+}
+
+/// The shape of a trigger volume. All coordinates are in world units.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+#[serde(tag = "type")]
+pub enum TriggerVolumeShape {
+    /// An axis-aligned box.
+    Box { min: [f64; 3], max: [f64; 3] },
+    /// A cylinder with its axis parallel to the z axis.
+    Cylinder {
+        /// The center of the bottom face.
+        base_center: [f64; 3],
+        radius: f64,
+        height: f64,
+    },
+    /// The space enclosed by the closed triangle meshes in a Wavefront .obj
+    /// file.
+    ///
+    /// The file must contain texture coordinates.
+    Mesh { filename: std::path::PathBuf },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TriggerboxAout {
+    pub channel: AoutChannel,
+    /// The voltage while an object is inside. (The output is 0 V otherwise.)
+    pub volts: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum AoutChannel {
+    A,
+    B,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Data2dDistortedRow {
     // changes to this should update BraidMetadataSchemaTag
@@ -733,6 +1235,32 @@ pub struct Data2dDistortedRow {
     pub mean_val: f64,
     #[serde(deserialize_with = "invalid_nan")]
     pub sumsqf_val: f64,
+    // The following columns hold the optional `BlobFeatures`. They are
+    // missing in files saved prior to schema 7.
+    #[serde(default = "nan", deserialize_with = "invalid_nan")]
+    pub ellipse_major: f64,
+    #[serde(default = "nan", deserialize_with = "invalid_nan")]
+    pub ellipse_minor: f64,
+    #[serde(default = "nan", deserialize_with = "invalid_nan")]
+    pub ellipse_angle: f64,
+    #[serde(default)]
+    pub bbox_left: Option<u32>,
+    #[serde(default)]
+    pub bbox_bottom: Option<u32>,
+    #[serde(default)]
+    pub bbox_right: Option<u32>,
+    #[serde(default)]
+    pub bbox_top: Option<u32>,
+    /// The contour vertices as space-separated `x y` pairs.
+    #[serde(default)]
+    pub contour: String,
+    #[serde(default)]
+    pub patch_left: Option<i32>,
+    #[serde(default)]
+    pub patch_bottom: Option<i32>,
+    /// The image patch as hexadecimal bytes in row-major order.
+    #[serde(default)]
+    pub patch: String,
 }
 
 // Lower precision version of the above for saving to disk.
@@ -757,6 +1285,72 @@ pub struct Data2dDistortedRowF32 {
     pub cur_val: u8,
     pub mean_val: f32,
     pub sumsqf_val: f32,
+    pub ellipse_major: f32,
+    pub ellipse_minor: f32,
+    pub ellipse_angle: f32,
+    pub bbox_left: Option<u32>,
+    pub bbox_bottom: Option<u32>,
+    pub bbox_right: Option<u32>,
+    pub bbox_top: Option<u32>,
+    pub contour: String,
+    pub patch_left: Option<i32>,
+    pub patch_bottom: Option<i32>,
+    pub patch: String,
+}
+
+impl Data2dDistortedRow {
+    /// The extended blob features saved in this row, if any.
+    pub fn blob_features(&self) -> Option<BlobFeatures> {
+        let bbox = BlobBoundingBox {
+            left: self.bbox_left?,
+            bottom: self.bbox_bottom?,
+            right: self.bbox_right?,
+            top: self.bbox_top?,
+        };
+        let ellipse = if self.ellipse_major.is_nan() {
+            None
+        } else {
+            Some(BlobEllipse {
+                major_axis: self.ellipse_major,
+                minor_axis: self.ellipse_minor,
+                angle: self.ellipse_angle,
+            })
+        };
+        let coords: Vec<f32> = self
+            .contour
+            .split_whitespace()
+            .filter_map(|x| x.parse().ok())
+            .collect();
+        let contour = coords.chunks_exact(2).map(|xy| [xy[0], xy[1]]).collect();
+        let patch = match (self.patch_left, self.patch_bottom) {
+            (Some(left), Some(bottom)) => {
+                let data: Vec<u8> = self
+                    .patch
+                    .as_bytes()
+                    .chunks_exact(2)
+                    .filter_map(|hex| {
+                        std::str::from_utf8(hex)
+                            .ok()
+                            .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                    })
+                    .collect();
+                let size = (data.len() as f64).sqrt().round() as u16;
+                Some(ImagePatch {
+                    left,
+                    bottom,
+                    size,
+                    data,
+                })
+            }
+            _ => None,
+        };
+        Some(BlobFeatures {
+            ellipse,
+            bbox,
+            contour,
+            patch,
+        })
+    }
 }
 
 impl WithKey<i64> for Data2dDistortedRow {
@@ -765,6 +1359,10 @@ impl WithKey<i64> for Data2dDistortedRow {
     }
 }
 
+fn nan() -> f64 {
+    std::f64::NAN
+}
+
 fn invalid_nan<'de, D>(de: D) -> std::result::Result<f64, D::Error>
 where
     D: Deserializer<'de>,
@@ -92,6 +92,7 @@ pub trait ReadFlydraExt: std::io::Read {
             cur_val,
             mean_val,
             sumsqf_val,
+            blob_features: None,
         })
     }
     fn read_pascal_string(&mut self, buflen: usize) -> Result<String> {
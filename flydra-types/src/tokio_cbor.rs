@@ -1,8 +1,10 @@
 #[cfg(feature = "with-tokio-codec")]
 use tokio_util::codec::{Decoder, Encoder};
 
+use crate::camdata_protocol::CamdataEnvelopeVersion;
 use crate::{
-    FlydraFloatTimestampLocal, FlydraRawUdpPacket, FlydraRawUdpPoint, HostClock, Triggerbox,
+    CamdataEnvelope, FlydraFloatTimestampLocal, FlydraRawUdpPacket, FlydraRawUdpPoint,
+    FlydraTypesError, HostClock, Triggerbox, CAMDATA_PROTOCOL_VERSION,
 };
 
 pub struct CborPacketCodec {
@@ -32,38 +34,78 @@ impl Decoder for CborPacketCodec {
 
         // Parse all available input data.
         let available = buf.split();
-        let deserializer = serde_cbor::Deserializer::from_slice(&available[..]);
-        let new_results: Vec<Result<FlydraRawUdpPacket, serde_cbor::error::Error>> =
-            deserializer.into_iter().collect();
 
-        // early return on error
-        let new_results: Result<Vec<FlydraRawUdpPacket>, serde_cbor::error::Error> =
-            new_results.into_iter().collect();
-        let new_results = match new_results {
+        // Check the protocol versions before parsing the packets, which may be
+        // incompatible.
+        let versions: Result<Vec<CamdataEnvelopeVersion>, serde_cbor::error::Error> =
+            serde_cbor::Deserializer::from_slice(&available[..])
+                .into_iter::<CamdataEnvelopeVersion>()
+                .collect();
+        let versions = match versions {
             Ok(v) => v,
             Err(e) => {
-                return Err(std::io::Error::new(
-                    std::io::ErrorKind::Other,
-                    format!("serde_cbor::error::Error {:?}", e),
-                ));
+                // Packets sent before the introduction of `CamdataEnvelope`
+                // have no version.
+                let unversioned = serde_cbor::Deserializer::from_slice(&available[..])
+                    .into_iter::<FlydraRawUdpPacket>()
+                    .next();
+                if let Some(Ok(packet)) = unversioned {
+                    return Err(protocol_error(FlydraTypesError::UnversionedCamdata {
+                        cam_name: packet.cam_name,
+                    }));
+                }
+                return Err(cbor_error(e));
             }
         };
+        for v in versions.into_iter() {
+            if v.protocol.version != CAMDATA_PROTOCOL_VERSION {
+                return Err(protocol_error(FlydraTypesError::CamdataProtocolMismatch {
+                    version: v.protocol.version,
+                    expected: CAMDATA_PROTOCOL_VERSION,
+                    build_id: v.protocol.build_id,
+                }));
+            }
+        }
+
+        let deserializer = serde_cbor::Deserializer::from_slice(&available[..]);
+        let new_results: Vec<Result<CamdataEnvelope, serde_cbor::error::Error>> =
+            deserializer.into_iter().collect();
 
-        self.buffered_results.extend(new_results.into_iter());
+        // early return on error
+        let new_results: Result<Vec<CamdataEnvelope>, serde_cbor::error::Error> =
+            new_results.into_iter().collect();
+        let new_results = new_results.map_err(cbor_error)?;
+
+        for envelope in new_results.into_iter() {
+            envelope
+                .protocol
+                .check_compatible()
+                .map_err(protocol_error)?;
+            self.buffered_results.push_back(envelope.packet);
+        }
 
         Ok(self.buffered_results.pop_front())
     }
 }
 
+fn cbor_error(e: serde_cbor::error::Error) -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::Other,
+        format!("serde_cbor::error::Error {:?}", e),
+    )
+}
+
+/// Wrap a protocol error such that it can be recovered with
+/// `std::io::Error::get_ref()`.
+fn protocol_error(e: FlydraTypesError) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, e)
+}
+
 #[cfg(feature = "with-tokio-codec")]
-impl Encoder<FlydraRawUdpPacket> for CborPacketCodec {
+impl Encoder<CamdataEnvelope> for CborPacketCodec {
     type Error = std::io::Error;
 
-    fn encode(
-        &mut self,
-        item: FlydraRawUdpPacket,
-        dest: &mut bytes::BytesMut,
-    ) -> std::io::Result<()> {
+    fn encode(&mut self, item: CamdataEnvelope, dest: &mut bytes::BytesMut) -> std::io::Result<()> {
         let item_bytes = serde_cbor::to_vec(&item).unwrap();
         dest.extend(item_bytes); // If dest does not have enough capacity, it is resized first.
         Ok(())
@@ -77,13 +119,13 @@ fn cbor_decoder() {
     use bytes::{BufMut, BytesMut};
 
     let p1 = make_test_packet(1);
-    let p1_bytes = serde_cbor::to_vec(&p1).unwrap();
+    let p1_bytes = serde_cbor::to_vec(&make_test_envelope(&p1)).unwrap();
 
     let p2 = make_test_packet(2);
-    let p2_bytes = serde_cbor::to_vec(&p2).unwrap();
+    let p2_bytes = serde_cbor::to_vec(&make_test_envelope(&p2)).unwrap();
 
     let p1234 = make_test_packet(1234);
-    let p1234_bytes = serde_cbor::to_vec(&p1234).unwrap();
+    let p1234_bytes = serde_cbor::ser::to_vec_packed_sd(&make_test_envelope(&p1234)).unwrap();
 
     let mut codec = CborPacketCodec::default();
     let buf = &mut BytesMut::new();
@@ -97,7 +139,7 @@ fn cbor_decoder() {
     assert_eq!(p1234, codec.decode(buf).unwrap().unwrap());
     assert_eq!(None, codec.decode(buf).unwrap());
     assert_eq!(None, codec.decode_eof(buf).unwrap());
-    let p2_bytes = serde_cbor::to_vec(&p2).unwrap();
+    let p2_bytes = serde_cbor::to_vec(&make_test_envelope(&p2)).unwrap();
     buf.put_slice(&p2_bytes);
     assert_eq!(p2, codec.decode(buf).unwrap().unwrap());
     assert_eq!(None, codec.decode(buf).unwrap());
@@ -113,10 +155,57 @@ fn cbor_roundtrip() {
     let mut codec = CborPacketCodec::default();
     let mut buf = BytesMut::new();
 
-    codec.encode(p1234.clone(), &mut buf).unwrap();
+    let envelope = CamdataEnvelope {
+        protocol: make_test_envelope(&p1234).protocol,
+        packet: p1234.clone(),
+    };
+    codec.encode(envelope, &mut buf).unwrap();
     assert_eq!(p1234, codec.decode(&mut buf).unwrap().unwrap());
 }
 
+#[test]
+fn cbor_rejects_incompatible() {
+    use bytes::{BufMut, BytesMut};
+
+    let p1 = make_test_packet(1);
+    let mut codec = CborPacketCodec::default();
+    let mut buf = BytesMut::new();
+
+    // A packet without envelope, as sent by old releases.
+    buf.put_slice(&serde_cbor::ser::to_vec_packed_sd(&p1).unwrap());
+    let err = codec.decode(&mut buf).unwrap_err();
+    match err.get_ref().unwrap().downcast_ref::<FlydraTypesError>() {
+        Some(FlydraTypesError::UnversionedCamdata { cam_name }) => assert_eq!(cam_name, "cam_id"),
+        other => panic!("unexpected error {:?}", other),
+    }
+
+    // An envelope from another protocol version.
+    let mut envelope = make_test_envelope(&p1);
+    envelope.protocol.version = CAMDATA_PROTOCOL_VERSION + 1;
+    buf.put_slice(&serde_cbor::ser::to_vec_packed_sd(&envelope).unwrap());
+    let err = codec.decode(&mut buf).unwrap_err();
+    match err.get_ref().unwrap().downcast_ref::<FlydraTypesError>() {
+        Some(FlydraTypesError::CamdataProtocolMismatch { version, .. }) => {
+            assert_eq!(*version, CAMDATA_PROTOCOL_VERSION + 1)
+        }
+        other => panic!("unexpected error {:?}", other),
+    }
+
+    // Decoding continues after errors.
+    buf.put_slice(&serde_cbor::ser::to_vec_packed_sd(&make_test_envelope(&p1)).unwrap());
+    assert_eq!(p1, codec.decode(&mut buf).unwrap().unwrap());
+}
+
+#[allow(dead_code)]
+fn make_test_envelope(packet: &FlydraRawUdpPacket) -> CamdataEnvelope<&FlydraRawUdpPacket> {
+    use crate::{CamdataCapabilities, CamdataProtocolInfo};
+
+    CamdataEnvelope {
+        protocol: CamdataProtocolInfo::new("test".to_string(), CamdataCapabilities::all()),
+        packet,
+    }
+}
+
 #[allow(dead_code)]
 fn make_test_packet(framenumber: i32) -> FlydraRawUdpPacket {
     use crate::ImageProcessingSteps;
@@ -140,5 +229,7 @@ fn make_test_packet(framenumber: i32) -> FlydraRawUdpPacket {
         preprocess_stamp: 0.0,
         image_processing_steps: ImageProcessingSteps::empty(),
         points,
+        apriltags: vec![],
+        seq: Some(framenumber as u64),
     }
 }
@@ -54,6 +54,8 @@ fn make_test_packet() -> FlydraRawUdpPacket {
         preprocess_stamp: 0.0,
         image_processing_steps: ImageProcessingSteps::empty(),
         points,
+        apriltags: vec![],
+        seq: None,
     }
 }
 
@@ -88,6 +90,7 @@ fn make_test_point() -> FlydraRawUdpPoint {
         cur_val: 13,
         mean_val: 12345.0,
         sumsqf_val: 55.5,
+        blob_features: None,
     }
 }
 
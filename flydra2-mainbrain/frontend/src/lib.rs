@@ -2,7 +2,9 @@ use serde::{Deserialize, Serialize};
 
 use wasm_bindgen::prelude::*;
 
-use flydra_types::{CamHttpServerInfo, CamInfo, HttpApiCallback, HttpApiShared};
+use flydra_types::{
+    CamHttpServerInfo, CamInfo, HttpApiCallback, HttpApiShared, RejectedCamera, TriggerVolumeStatus,
+};
 use rust_cam_bui_types::{ClockModel, RecordingPath};
 
 use yew::format::Json;
@@ -223,6 +225,12 @@ impl Model {
                         {"⚠ Emulating synchronization because no trigger box in use. Data will not be perfectly synchronized. ⚠"}
                     </div>
                 }
+            } else if value.software_sync {
+                html! {
+                    <div>
+                        {"Cameras synchronized from host timestamps. Check the sync jitter of each camera."}
+                    </div>
+                }
             } else {
                 html! {
                     <></>
@@ -236,7 +244,9 @@ impl Model {
                         {view_clock_model(&value.clock_model_copy)}
                         {view_calibration(&value.calibration_filename)}
                         {view_cam_list(&value.connected_cameras)}
+                        {view_rejected_cameras(&value.rejected_cameras)}
                         {view_model_server_link(&value.model_server_addr)}
+                        {view_trigger_volumes(&value.trigger_volumes)}
                     </div>
                 </div>
             }
@@ -322,7 +332,14 @@ fn view_cam_list(cams: &Vec<CamInfo>) -> Html {
                 CamHttpServerInfo::Server(ref details) => details.guess_base_url_with_token(),
             };
             let state = format!("{:?}", cci.state);
-            let stats = format!("{:?}", cci.recent_stats);
+            let mut stats = format!(
+                "{:?} (lost {:.1}%)",
+                cci.recent_stats,
+                cci.recent_stats.loss_rate() * 100.0
+            );
+            if let Some(sync_jitter) = cci.recent_stats.sync_jitter {
+                stats.push_str(&format!(" (sync jitter {:.2} msec)", sync_jitter * 1000.0));
+            }
             html! {
                 <li>
                     <a href=cam_url>{cci.name.as_str()}</a>
@@ -346,6 +363,32 @@ fn view_cam_list(cams: &Vec<CamInfo>) -> Html {
     }
 }
 
+fn view_rejected_cameras(cams: &[RejectedCamera]) -> Html {
+    if cams.is_empty() {
+        return html! {
+            <></>
+        };
+    }
+    let all_rendered: Vec<Html> = cams
+        .iter()
+        .map(|cam| {
+            html! {
+                <li>
+                    {format!("⚠ {}: {}", cam.name.as_str(), cam.reason)}
+                </li>
+            }
+        })
+        .collect();
+    html! {
+        <div>
+            {"Rejected cameras:"}
+            <ul>
+                {all_rendered}
+            </ul>
+        </div>
+    }
+}
+
 fn view_model_server_link(opt_addr: &Option<std::net::SocketAddr>) -> Html {
     use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 
@@ -375,6 +418,38 @@ fn view_model_server_link(opt_addr: &Option<std::net::SocketAddr>) -> Html {
     }
 }
 
+fn view_trigger_volumes(volumes: &[TriggerVolumeStatus]) -> Html {
+    if volumes.is_empty() {
+        return html! {
+            <></>
+        };
+    }
+    let all_rendered: Vec<Html> = volumes
+        .iter()
+        .map(|vol| {
+            let objs = if vol.obj_ids.is_empty() {
+                "empty".to_string()
+            } else {
+                let ids: Vec<String> = vol.obj_ids.iter().map(|x| x.to_string()).collect();
+                format!("objects {}", ids.join(", "))
+            };
+            html! {
+                <li>
+                    {format!("{}: {}", vol.name, objs)}
+                </li>
+            }
+        })
+        .collect();
+    html! {
+        <div>
+            {"Trigger volumes:"}
+            <ul>
+                {all_rendered}
+            </ul>
+        </div>
+    }
+}
+
 // -----------------------------------------------------------------------------
 
 #[wasm_bindgen(start)]
@@ -10,7 +10,8 @@ use std::sync::{
 
 use parking_lot::RwLock;
 
-use tokio::net::UdpSocket;
+use tokio::net::{TcpListener, UdpSocket};
+use tokio_util::codec::{Decoder, FramedRead, LengthDelimitedCodec};
 use tokio_util::udp::UdpFramed;
 
 use structopt::StructOpt;
@@ -22,8 +23,9 @@ use bui_backend_types::CallbackDataAndSession;
 
 use flydra2::{CoordProcessor, FrameDataAndPoints, MyFloat, StreamItem};
 use flydra_types::{
-    BuiServerInfo, CamInfo, CborPacketCodec, FlydraFloatTimestampLocal, FlydraPacketCodec,
-    HttpApiCallback, HttpApiShared, RosCamName, SyncFno, TriggerType, Triggerbox,
+    BuiServerInfo, CamInfo, CamNum, CborPacketCodec, FlydraFloatTimestampLocal, FlydraPacketCodec,
+    HttpApiCallback, HttpApiShared, RejectedCamera, RosCamName, SyncFno, TriggerType,
+    TriggerVolumeConfig, TriggerVolumeStatus, Triggerbox,
 };
 use rust_cam_bui_types::ClockModel;
 use rust_cam_bui_types::RecordingPath;
@@ -43,10 +45,29 @@ use anyhow::Result;
 
 const SYNCHRONIZE_DURATION_SEC: u8 = 3;
 
+/// Number of packets received over TCP which may wait to be processed.
+const TCP_CAMDATA_QUEUE_SIZE: usize = 100;
+
+/// How often the recent stats of each camera are updated.
+const LIVE_STATS_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// A sequence number this much lower than the highest one received means the
+/// camera restarted rather than a packet arriving late.
+const MAX_SEQ_REORDER: u64 = 100;
+
 #[derive(thiserror::Error, Debug)]
 enum MainbrainError {
     #[error("The --jwt-secret argument must be passed or the JWT_SECRET environment variable must be set.")]
     JwtError,
+    #[error("Trigger volume \"{0}\" sets a triggerbox output, but no triggerbox is used.")]
+    NoTriggerbox(String),
+    #[error("Trigger volume \"{0}\" sets a camtrig channel, but no camtrig device is configured.")]
+    NoCamtrigDevice(String),
+    #[error("Trigger volume \"{0}\" sets camtrig channel {1}, but only channels 1-4 exist.")]
+    InvalidCamtrigChannel(String, u8),
+    #[cfg(not(feature = "with_camtrig"))]
+    #[error("A camtrig device is configured, but camtrig support was not compiled.")]
+    CamtrigNotCompiled,
 }
 
 /// The structure that holds our app data
@@ -118,12 +139,36 @@ async fn new_http_api_app(
             match msg.payload {
                 NewCamera(cam_info) => {
                     debug!("got NewCamera {:?}", cam_info);
-                    let mut cam_manager3 = cam_manager2.clone();
-                    cam_manager3.register_new_camera(
-                        &cam_info.orig_cam_name,
-                        &cam_info.http_camserver_info,
-                        &cam_info.ros_cam_name,
-                    );
+                    let compatible = match &cam_info.camdata_protocol {
+                        Some(protocol) => protocol.check_compatible(),
+                        None => Err(flydra_types::FlydraTypesError::UnversionedCamdata {
+                            cam_name: cam_info.ros_cam_name.as_str().to_string(),
+                        }),
+                    };
+                    match compatible {
+                        Ok(()) => {
+                            set_camera_rejected(&shared_data, &cam_info.ros_cam_name, None);
+                            let mut cam_manager3 = cam_manager2.clone();
+                            cam_manager3.register_new_camera(
+                                &cam_info.orig_cam_name,
+                                &cam_info.http_camserver_info,
+                                &cam_info.ros_cam_name,
+                            );
+                        }
+                        Err(e) => {
+                            error!("rejecting camera {}: {}", cam_info.ros_cam_name, e);
+                            let reason = e.to_string();
+                            set_camera_rejected(
+                                &shared_data,
+                                &cam_info.ros_cam_name,
+                                Some(reason.clone()),
+                            );
+                            // Fail the registration request so that the camera
+                            // gets the reason, too.
+                            let e = std::io::Error::new(std::io::ErrorKind::InvalidData, reason);
+                            return futures::future::err(e.into());
+                        }
+                    }
                 }
                 UpdateCurrentImage(image_info) => {
                     // new image from camera
@@ -177,6 +222,69 @@ fn compute_trigger_timestamp(
     }
 }
 
+/// Stream of the camera data received by the mainbrain.
+pub type CamdataStream = Box<
+    dyn futures::stream::Stream<
+            Item = std::result::Result<
+                (flydra_types::FlydraRawUdpPacket, std::net::SocketAddr),
+                std::io::Error,
+            >,
+        > + Send
+        + Unpin,
+>;
+
+/// Receive camera data on `camdata_socket`.
+///
+/// With `flydra1`, the legacy flydra1 packet format is expected. Otherwise
+/// packets are versioned CBOR envelopes. Packets from senders with an
+/// incompatible protocol produce errors which wrap a
+/// [flydra_types::FlydraTypesError].
+pub fn camdata_stream(camdata_socket: UdpSocket, flydra1: bool) -> CamdataStream {
+    match flydra1 {
+        true => Box::new(UdpFramed::new(camdata_socket, FlydraPacketCodec::default())),
+        false => Box::new(UdpFramed::new(camdata_socket, CborPacketCodec::default())),
+    }
+}
+
+/// Receive camera data from cameras connecting to `listener`.
+///
+/// Each packet is a versioned CBOR envelope prefixed by its length as a 32 bit
+/// big-endian integer. If the packets are not processed fast enough, reading
+/// from the connections stops until they are.
+pub fn tcp_camdata_stream(listener: TcpListener) -> CamdataStream {
+    let (tx, rx) = futures::channel::mpsc::channel(TCP_CAMDATA_QUEUE_SIZE);
+    tokio::spawn(async move {
+        loop {
+            let (socket, addr) = match listener.accept().await {
+                Ok(x) => x,
+                Err(e) => {
+                    error!("accepting camera data connection: {}", e);
+                    continue;
+                }
+            };
+            debug!("camera data connection from {}", addr);
+            let mut tx = tx.clone();
+            tokio::spawn(async move {
+                let mut frames = FramedRead::new(socket, LengthDelimitedCodec::new());
+                let mut codec = CborPacketCodec::default();
+                while let Some(frame) = futures::stream::StreamExt::next(&mut frames).await {
+                    let result = match frame.and_then(|mut frame| codec.decode(&mut frame)) {
+                        Ok(Some(packet)) => Ok((packet, addr)),
+                        Ok(None) => continue,
+                        Err(e) => Err(e),
+                    };
+                    if futures::SinkExt::send(&mut tx, result).await.is_err() {
+                        // The receiver is gone.
+                        break;
+                    }
+                }
+                debug!("camera data connection from {} closed", addr);
+            });
+        }
+    });
+    Box::new(rx)
+}
+
 /// Convert the address we are listening on to a string.
 ///
 /// We can strings over the network, but not binary representations of
@@ -197,6 +305,28 @@ impl flydra2::ConnectedCamCallback for SendConnectedCamToBuiBackend {
     }
 }
 
+/// Update the list of rejected cameras shown in the browser.
+///
+/// If `reason` is `None`, the camera is removed from the list.
+fn set_camera_rejected(
+    shared_store: &Arc<RwLock<ChangeTracker<HttpApiShared>>>,
+    name: &RosCamName,
+    reason: Option<String>,
+) {
+    let mut tracker = shared_store.write();
+    tracker.modify(|shared| {
+        shared
+            .rejected_cameras
+            .retain(|rejected| &rejected.name != name);
+        if let Some(reason) = &reason {
+            shared.rejected_cameras.push(RejectedCamera {
+                name: name.clone(),
+                reason: reason.clone(),
+            });
+        }
+    });
+}
+
 fn display_qr_url(url: &str) {
     use qrcodegen::{QrCode, QrCodeEcc};
     use std::io::{stdout, Write};
@@ -223,6 +353,7 @@ fn display_qr_url(url: &str) {
 
 pub struct StartupPhase1 {
     pub camdata_socket: UdpSocket,
+    camdata_tcp_listener: TcpListener,
     my_app: HttpApiApp,
     pub mainbrain_server_info: BuiServerInfo,
     cam_manager: flydra2::ConnectedCamerasManager,
@@ -237,6 +368,7 @@ pub struct StartupPhase1 {
     model_server_shutdown_rx: tokio::sync::oneshot::Receiver<()>,
     signal_all_cams_present: Arc<AtomicBool>,
     signal_all_cams_synced: Arc<AtomicBool>,
+    trigger_volumes: Option<flydra2::TriggerVolumes>,
 }
 
 pub async fn pre_run(
@@ -255,6 +387,8 @@ pub async fn pre_run(
     save_empty_data2d: bool,
     jwt_secret: Option<Vec<u8>>,
     all_expected_cameras: std::collections::BTreeSet<RosCamName>,
+    trigger_volumes: Vec<TriggerVolumeConfig>,
+    camtrig_device_path: Option<String>,
 ) -> Result<StartupPhase1> {
     info!("saving to directory: {}", output_base_dirname.display());
 
@@ -398,6 +532,7 @@ pub async fn pre_run(
             (Some(tx), Some(rx), false)
         }
         TriggerType::FakeSync(_) => (None, None, true),
+        TriggerType::SoftwareSync(_) => (None, None, false),
     };
 
     let sync_pulse_pause_started: Option<std::time::Instant> = None;
@@ -407,6 +542,7 @@ pub async fn pre_run(
 
     let shared = HttpApiShared {
         fake_sync,
+        software_sync: matches!(trigger_cfg, TriggerType::SoftwareSync(_)),
         csv_tables_dirname: None,
         clock_model_copy: None,
         calibration_filename: cal_fname.map(|x| x.into_os_string().into_string().unwrap()),
@@ -414,6 +550,14 @@ pub async fn pre_run(
         model_server_addr: None,
         flydra_app_name,
         all_expected_cameras_are_synced: false,
+        trigger_volumes: trigger_volumes
+            .iter()
+            .map(|cfg| TriggerVolumeStatus {
+                name: cfg.name.clone(),
+                obj_ids: vec![],
+            })
+            .collect(),
+        rejected_cameras: Vec::new(),
     };
 
     let expected_framerate_arc = Arc::new(RwLock::new(None));
@@ -457,6 +601,24 @@ pub async fn pre_run(
     )
     .await?;
 
+    let trigger_volumes = if trigger_volumes.is_empty() {
+        None
+    } else {
+        let outputs = TriggerVolumeOutputs::new(
+            trigger_volumes.clone(),
+            my_app.inner.shared_arc().clone(),
+            my_app.triggerbox_cmd.clone(),
+            camtrig_device_path,
+            http_session_handler.clone(),
+            handle.clone(),
+        )?;
+        Some(flydra2::TriggerVolumes::new(
+            &trigger_volumes,
+            coord_processor.save_data_tx.clone(),
+            Some(Box::new(outputs)),
+        )?)
+    };
+
     let is_loopback = my_app.inner.local_addr().ip().is_loopback();
     let mainbrain_server_info =
         flydra_types::BuiServerInfo::new(my_app.inner.local_addr().clone(), my_app.inner.token());
@@ -478,8 +640,12 @@ pub async fn pre_run(
     let camdata_socket_fut = UdpSocket::bind(&camdata_addr);
     let camdata_socket = camdata_socket_fut.await?;
 
+    // Cameras may also send their data over TCP to the same port.
+    let camdata_tcp_listener = TcpListener::bind(camdata_socket.local_addr()?).await?;
+
     Ok(StartupPhase1 {
         camdata_socket,
+        camdata_tcp_listener,
         my_app,
         mainbrain_server_info,
         cam_manager,
@@ -494,11 +660,13 @@ pub async fn pre_run(
         model_server_shutdown_rx,
         signal_all_cams_present,
         signal_all_cams_synced,
+        trigger_volumes,
     })
 }
 
 pub async fn run(phase1: StartupPhase1) -> Result<()> {
     let camdata_socket = phase1.camdata_socket;
+    let camdata_tcp_listener = phase1.camdata_tcp_listener;
     let my_app = phase1.my_app;
 
     let mainbrain_server_info = phase1.mainbrain_server_info;
@@ -517,6 +685,7 @@ pub async fn run(phase1: StartupPhase1) -> Result<()> {
     let model_server_shutdown_rx = phase1.model_server_shutdown_rx;
     let signal_all_cams_present = phase1.signal_all_cams_present;
     let signal_all_cams_synced = phase1.signal_all_cams_synced;
+    let trigger_volumes = phase1.trigger_volumes;
 
     let signal_triggerbox_connected = Arc::new(AtomicBool::new(false));
     let triggerbox_cmd = my_app.triggerbox_cmd.clone();
@@ -618,6 +787,8 @@ pub async fn run(phase1: StartupPhase1) -> Result<()> {
         })
     };
 
+    let mut software_sync = None;
+
     // if let Some(ref cfg) = trigger_cfg {
     match &trigger_cfg {
         TriggerType::TriggerboxV1(cfg) => {
@@ -671,49 +842,58 @@ pub async fn run(phase1: StartupPhase1) -> Result<()> {
                 residuals: 0.0,
             }));
         }
+        TriggerType::SoftwareSync(cfg) => {
+            info!("No triggerbox configuration. Synchronizing cameras from host timestamps.");
+
+            signal_triggerbox_connected.store(true, Ordering::SeqCst);
+
+            let mut expected_framerate = expected_framerate_arc.write();
+            *expected_framerate = Some(cfg.fps as f32);
+
+            // Synchronized frame 0 is now.
+            let now: chrono::DateTime<chrono::Utc> = chrono::Utc::now();
+            let t0 = datetime_conversion::datetime_to_f64(&now);
+
+            software_sync = Some(Arc::new(flydra2::SoftwareSync::new(cfg, t0)));
+
+            (on_new_clock_model)(Some(braid_triggerbox::ClockModel {
+                gain: 1.0 / cfg.fps,
+                n_measurements: 0,
+                offset: t0,
+                residuals: 0.0,
+            }));
+        }
     };
 
     let expected_framerate_arc9 = expected_framerate_arc.clone();
 
-    let live_stats_collector = LiveStatsCollector::new(tracker.clone());
+    let live_stats_collector = LiveStatsCollector::new(
+        tracker.clone(),
+        coord_processor.get_write_controller(),
+        software_sync.clone(),
+    );
     let tracker2 = tracker.clone();
 
-    let raw_cam_data_stream: Box<
-        dyn futures::stream::Stream<
-                Item = std::result::Result<
-                    (flydra_types::FlydraRawUdpPacket, std::net::SocketAddr),
-                    std::io::Error,
-                >,
-            > + Send
-            + Unpin,
-    > = match flydra1 {
-        true => {
-            let codec = FlydraPacketCodec::default();
-            // let (_sink, stream) = tokio::codec::Framed::new( camdata_socket, codec ).split();
-
-            // let (_sink, stream) = UdpFramed::new( camdata_socket, codec ).split();
-            let stream = UdpFramed::new(camdata_socket, codec);
-
-            // let (_sink, stream) = UdpFramed::new(camdata_socket, FlydraPacketCodec::default()).split();
-            // let stream = futures::compat::Compat01As03::new(stream);
-            Box::new(stream)
-        }
-        false => {
-            let codec = CborPacketCodec::default();
-            // let (_sink, stream) = tokio::codec::Framed::new( camdata_socket, codec ).split();
-            // let (_sink, stream) = UdpFramed::new( camdata_socket, codec ).split();
-            let stream = UdpFramed::new(camdata_socket, codec);
-
-            // let (_sink, stream) = UdpFramed::new(camdata_socket, CborPacketCodec::default()).split();
-            // let stream = futures::compat::Compat01As03::new(stream);
-            Box::new(stream)
+    // Update the stats of cameras which stopped sending data.
+    let live_stats_collector3 = live_stats_collector.clone();
+    rt_handle3.spawn(async move {
+        let mut interval = tokio::time::interval(LIVE_STATS_INTERVAL);
+        loop {
+            let _now = interval.tick().await;
+            live_stats_collector3.flush();
         }
-    };
+    });
+
+    let raw_cam_data_stream = futures::stream::select(
+        camdata_stream(camdata_socket, flydra1),
+        tcp_camdata_stream(camdata_tcp_listener),
+    );
 
     // Initiate camera synchronization on startup
     let sync_pulse_pause_started_arc2 = sync_pulse_pause_started_arc.clone();
     let time_model_arc2 = time_model_arc.clone();
     let cam_manager2 = cam_manager.clone();
+    let software_sync2 = software_sync.clone();
     let sync_start_jh = rt_handle3.spawn(async move {
         let mut interval = tokio::time::interval(std::time::Duration::from_secs(1));
 
@@ -729,6 +909,7 @@ pub async fn run(phase1: StartupPhase1) -> Result<()> {
                     sync_pulse_pause_started_arc2.clone(),
                     cam_manager2.clone(),
                     time_model_arc2.clone(),
+                    software_sync2.clone(),
                 );
                 break;
             }
@@ -759,6 +940,8 @@ pub async fn run(phase1: StartupPhase1) -> Result<()> {
     let cam_manager2 = cam_manager.clone();
     let live_stats_collector2 = live_stats_collector.clone();
 
+    let mut reported_camdata_errors = std::collections::BTreeSet::new();
+
     let flydra2_stream = futures::stream::StreamExt::filter_map(raw_cam_data_stream, move |r| {
         // vvvvvvvvvvvvvvvvvvvvvvvvvvvvvvvvvvvvvvvvvvvvvvvvvvvvvv
         // Start of closure for on each incoming packet.
@@ -777,13 +960,39 @@ pub async fn run(phase1: StartupPhase1) -> Result<()> {
         let (packet, _addr) = match r {
             Ok(r) => r,
             Err(e) => {
+                if let Some(e) = e
+                    .get_ref()
+                    .and_then(|inner| inner.downcast_ref::<flydra_types::FlydraTypesError>())
+                {
+                    // Data from an incompatible camera. Drop it, but do not
+                    // report the same problem for every frame.
+                    let msg = e.to_string();
+                    if !reported_camdata_errors.contains(&msg) {
+                        error!("dropping camera data: {}", msg);
+                        reported_camdata_errors.insert(msg);
+                    }
+                    return futures::future::ready(None);
+                }
                 error!("{}", e);
                 return futures::future::ready(Some(StreamItem::EOF));
             }
         };
 
         let ros_cam_name = RosCamName::new(packet.cam_name.clone());
-        live_stats_collector2.register_new_frame_data(&ros_cam_name, packet.points.len());
+        let cam_num = match cam_manager2.cam_num(&ros_cam_name) {
+            Some(cam_num) => cam_num,
+            None => {
+                // Not registered, e.g. because it was rejected.
+                debug!("dropping data from unregistered camera {}", ros_cam_name);
+                return futures::future::ready(None);
+            }
+        };
+        live_stats_collector2.register_new_frame_data(
+            &ros_cam_name,
+            cam_num,
+            packet.points.len(),
+            packet.seq,
+        );
 
         let http_session_handler3 = http_session_handler2.clone();
 
@@ -792,7 +1001,7 @@ pub async fn run(phase1: StartupPhase1) -> Result<()> {
                 // Using trigger box
                 std::time::Duration::from_secs(SYNCHRONIZE_DURATION_SEC as u64)
             }
-            TriggerType::FakeSync(_) => {
+            TriggerType::FakeSync(_) | TriggerType::SoftwareSync(_) => {
                 // Using fake trigger
                 std::time::Duration::from_secs(0)
             }
@@ -823,6 +1032,18 @@ pub async fn run(phase1: StartupPhase1) -> Result<()> {
             } // cannot compute synced_frame number, drop this data
         };
 
+        let synced_frame = match &software_sync {
+            // The frame number relative to synchronization is replaced by the
+            // one computed from the host timestamp.
+            Some(software_sync) => match software_sync.synced_frame(&packet) {
+                Some(v) => v,
+                None => {
+                    return futures::future::ready(None);
+                }
+            },
+            None => synced_frame,
+        };
+
         let trigger_timestamp = {
             let time_model = time_model_arc.read();
             compute_trigger_timestamp(&time_model, synced_frame)
@@ -855,7 +1076,11 @@ pub async fn run(phase1: StartupPhase1) -> Result<()> {
             })
             .collect();
 
-        let fdp = FrameDataAndPoints { frame_data, points };
+        let fdp = FrameDataAndPoints {
+            frame_data,
+            points,
+            apriltags: packet.apriltags,
+        };
         futures::future::ready(Some(StreamItem::Packet(fdp)))
         // This is the end of closure for each incoming packet.
         // ^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^
@@ -878,7 +1103,13 @@ pub async fn run(phase1: StartupPhase1) -> Result<()> {
     let expected_framerate: Option<f32> = *expected_framerate_arc9.read();
     info!("expected_framerate: {:?}", expected_framerate);
 
-    coord_processor.add_listener(Box::new(ms));
+    coord_processor.add_listener(Box::new(ms.clone()));
+    if let Some(mut trigger_volumes) = trigger_volumes {
+        // Added after the model server so that enter and leave events follow
+        // the update causing them.
+        trigger_volumes.add_listener(Box::new(ms));
+        coord_processor.add_listener(Box::new(trigger_volumes));
+    }
     let consume_future =
         coord_processor.consume_stream(valve.wrap(flydra2_stream), expected_framerate);
 
@@ -903,79 +1134,402 @@ pub async fn run(phase1: StartupPhase1) -> Result<()> {
 struct LiveStatsCollector {
     shared: Arc<RwLock<ChangeTracker<HttpApiShared>>>,
     collected: Arc<RwLock<BTreeMap<RosCamName, LiveStatsAccum>>>,
+    write_controller: flydra2::CoordProcessorControl,
+    software_sync: Option<Arc<flydra2::SoftwareSync>>,
 }
 
 #[derive(Debug)]
 struct LiveStatsAccum {
     start: std::time::Instant,
+    cam_num: CamNum,
     n_frames: usize,
     n_points: usize,
+    n_lost: usize,
+    /// The highest sequence number received.
+    last_seq: Option<u64>,
 }
 
 impl LiveStatsAccum {
-    fn new() -> Self {
+    fn new(cam_num: CamNum) -> Self {
         Self {
             start: std::time::Instant::now(),
+            cam_num,
             n_frames: 0,
             n_points: 0,
+            n_lost: 0,
+            last_seq: None,
         }
     }
-    fn update(&mut self, n_points: usize) {
+    fn update(&mut self, n_points: usize, seq: Option<u64>) {
         self.n_frames += 1;
         self.n_points += n_points;
+        if let Some(seq) = seq {
+            match self.last_seq {
+                Some(last_seq) if seq > last_seq => {
+                    self.n_lost += (seq - last_seq - 1) as usize;
+                    self.last_seq = Some(seq);
+                }
+                // The camera restarted and counts from zero again.
+                Some(last_seq) if seq + MAX_SEQ_REORDER < last_seq => {
+                    self.last_seq = Some(seq);
+                }
+                // A packet arriving after a later packet. It was counted as
+                // lost.
+                Some(_) => {}
+                None => {
+                    self.last_seq = Some(seq);
+                }
+            }
+        }
     }
     fn get_results_and_reset(&mut self) -> flydra_types::RecentStats {
         let recent = flydra_types::RecentStats {
             total_frames_collected: 0,
             frames_collected: self.n_frames,
             points_detected: self.n_points,
+            frames_lost: self.n_lost,
+            total_frames_lost: 0,
+            sync_jitter: None,
         };
         self.start = std::time::Instant::now();
         self.n_frames = 0;
         self.n_points = 0;
+        self.n_lost = 0;
         recent
     }
 }
 
 impl LiveStatsCollector {
-    fn new(shared: Arc<RwLock<ChangeTracker<HttpApiShared>>>) -> Self {
+    fn new(
+        shared: Arc<RwLock<ChangeTracker<HttpApiShared>>>,
+        write_controller: flydra2::CoordProcessorControl,
+        software_sync: Option<Arc<flydra2::SoftwareSync>>,
+    ) -> Self {
         let collected = Arc::new(RwLock::new(BTreeMap::new()));
-        Self { shared, collected }
+        Self {
+            shared,
+            collected,
+            write_controller,
+            software_sync,
+        }
     }
 
-    fn register_new_frame_data(&self, name: &RosCamName, n_points: usize) {
+    fn register_new_frame_data(
+        &self,
+        name: &RosCamName,
+        cam_num: CamNum,
+        n_points: usize,
+        seq: Option<u64>,
+    ) {
         let to_send = {
             // scope for lock on self.collected
             let mut collected = self.collected.write();
             let entry = collected
                 .entry(name.clone())
-                .or_insert_with(|| LiveStatsAccum::new());
-            entry.update(n_points);
+                .or_insert_with(|| LiveStatsAccum::new(cam_num));
+            entry.cam_num = cam_num;
+            entry.update(n_points, seq);
 
-            if entry.start.elapsed() > std::time::Duration::from_secs(1) {
-                Some((name.clone(), entry.get_results_and_reset()))
+            if entry.start.elapsed() > LIVE_STATS_INTERVAL {
+                Some(entry.get_results_and_reset())
             } else {
                 None
             }
         };
-        if let Some((name, recent_stats)) = to_send {
-            // scope for shared scope
-            let mut tracker = self.shared.write();
-            tracker.modify(|shared| {
-                for cc in shared.connected_cameras.iter_mut() {
-                    if cc.name == name {
-                        let old_total = cc.recent_stats.total_frames_collected;
-                        cc.recent_stats = recent_stats.clone();
-                        cc.recent_stats.total_frames_collected =
-                            old_total + recent_stats.frames_collected;
-                        break;
-                    }
-                }
+        if let Some(recent_stats) = to_send {
+            self.send_stats(name, cam_num, recent_stats);
+        }
+    }
+
+    /// Send the stats of cameras from which no frames arrived recently.
+    ///
+    /// This is called periodically so that cameras which stopped sending data
+    /// do not keep their old stats.
+    fn flush(&self) {
+        let to_send: Vec<_> = {
+            // scope for lock on self.collected
+            let mut collected = self.collected.write();
+            collected
+                .iter_mut()
+                .filter(|(_, entry)| entry.start.elapsed() > LIVE_STATS_INTERVAL)
+                .map(|(name, entry)| (name.clone(), entry.cam_num, entry.get_results_and_reset()))
+                .collect()
+        };
+        for (name, cam_num, recent_stats) in to_send {
+            self.send_stats(&name, cam_num, recent_stats);
+        }
+    }
+
+    fn send_stats(
+        &self,
+        name: &RosCamName,
+        cam_num: CamNum,
+        mut recent_stats: flydra_types::RecentStats,
+    ) {
+        if let Some(software_sync) = &self.software_sync {
+            recent_stats.sync_jitter = software_sync.camera_stats(name).map(|s| s.jitter);
+        }
+        if recent_stats.frames_lost > 0 {
+            warn!(
+                "camera {}: {} of {} frames lost",
+                name,
+                recent_stats.frames_lost,
+                recent_stats.frames_collected + recent_stats.frames_lost
+            );
+        }
+        self.write_controller
+            .append_camdata_stats(flydra_types::CamdataStatsRow {
+                timestamp: FlydraFloatTimestampLocal::from_dt(&chrono::Local::now()),
+                camn: cam_num,
+                frames_received: recent_stats.frames_collected,
+                frames_lost: recent_stats.frames_lost,
+                sync_jitter: recent_stats.sync_jitter,
             });
+
+        // scope for shared scope
+        let mut tracker = self.shared.write();
+        tracker.modify(|shared| {
+            for cc in shared.connected_cameras.iter_mut() {
+                if &cc.name == name {
+                    let old_total = cc.recent_stats.total_frames_collected;
+                    let old_total_lost = cc.recent_stats.total_frames_lost;
+                    cc.recent_stats = recent_stats.clone();
+                    cc.recent_stats.total_frames_collected =
+                        old_total + recent_stats.frames_collected;
+                    cc.recent_stats.total_frames_lost = old_total_lost + recent_stats.frames_lost;
+                    break;
+                }
+            }
+        });
+    }
+}
+
+/// Shows trigger volume events in the HTTP API, sets the configured outputs
+/// and starts the configured recordings.
+struct TriggerVolumeOutputs {
+    cfgs: Vec<TriggerVolumeConfig>,
+    shared_store: Arc<RwLock<ChangeTracker<HttpApiShared>>>,
+    triggerbox_cmd: Option<channellib::Sender<braid_triggerbox::Cmd>>,
+    http_session_handler: HttpSessionHandler,
+    handle: tokio::runtime::Handle,
+    #[cfg(feature = "with_camtrig")]
+    camtrig_tx: Option<channellib::Sender<camtrig_comms::DeviceState>>,
+    /// The state last sent to the camtrig device.
+    #[cfg(feature = "with_camtrig")]
+    camtrig_state: parking_lot::Mutex<camtrig_comms::DeviceState>,
+}
+
+impl TriggerVolumeOutputs {
+    fn new(
+        cfgs: Vec<TriggerVolumeConfig>,
+        shared_store: Arc<RwLock<ChangeTracker<HttpApiShared>>>,
+        triggerbox_cmd: Option<channellib::Sender<braid_triggerbox::Cmd>>,
+        camtrig_device_path: Option<String>,
+        http_session_handler: HttpSessionHandler,
+        handle: tokio::runtime::Handle,
+    ) -> Result<Self> {
+        for cfg in cfgs.iter() {
+            if cfg.triggerbox_aout.is_some() && triggerbox_cmd.is_none() {
+                return Err(MainbrainError::NoTriggerbox(cfg.name.clone()).into());
+            }
+            if let Some(channel) = cfg.camtrig_channel {
+                if camtrig_device_path.is_none() {
+                    return Err(MainbrainError::NoCamtrigDevice(cfg.name.clone()).into());
+                }
+                if channel < 1 || channel > 4 {
+                    return Err(
+                        MainbrainError::InvalidCamtrigChannel(cfg.name.clone(), channel).into(),
+                    );
+                }
+            }
+        }
+
+        #[cfg(feature = "with_camtrig")]
+        let camtrig_tx = match camtrig_device_path {
+            Some(ref path) => Some(launch_camtrig_threads(path)?),
+            None => None,
+        };
+        #[cfg(not(feature = "with_camtrig"))]
+        if camtrig_device_path.is_some() {
+            return Err(MainbrainError::CamtrigNotCompiled.into());
+        }
+
+        Ok(Self {
+            cfgs,
+            shared_store,
+            triggerbox_cmd,
+            http_session_handler,
+            handle,
+            #[cfg(feature = "with_camtrig")]
+            camtrig_tx,
+            // Nothing is sent to the device until a volume changes its
+            // output, so it is in its power-on state.
+            #[cfg(feature = "with_camtrig")]
+            camtrig_state: parking_lot::Mutex::new(camtrig_comms::DeviceState::default()),
+        })
+    }
+
+    fn set_triggerbox_outputs(&self, occupied: &[(&TriggerVolumeConfig, bool)]) {
+        let tx = match self.triggerbox_cmd {
+            Some(ref tx) => tx,
+            None => return,
+        };
+        let mut volts = [0.0, 0.0];
+        let mut has_aout = false;
+        for (cfg, is_occupied) in occupied.iter() {
+            if let Some(ref aout) = cfg.triggerbox_aout {
+                has_aout = true;
+                if *is_occupied {
+                    let idx = match aout.channel {
+                        flydra_types::AoutChannel::A => 0,
+                        flydra_types::AoutChannel::B => 1,
+                    };
+                    volts[idx] = aout.volts;
+                }
+            }
+        }
+        if has_aout {
+            tx.send(braid_triggerbox::Cmd::SetAOut((volts[0], volts[1])))
+                .cb_ok();
+        }
+    }
+
+    #[cfg(feature = "with_camtrig")]
+    fn set_camtrig_outputs(&self, occupied: &[(&TriggerVolumeConfig, bool)]) {
+        let tx = match self.camtrig_tx {
+            Some(ref tx) => tx,
+            None => return,
+        };
+        // Only the channels of the volumes are changed. A channel is on if
+        // any volume using it is occupied.
+        let mut channels_on: BTreeMap<u8, bool> = BTreeMap::new();
+        for (cfg, is_occupied) in occupied.iter() {
+            if let Some(channel) = cfg.camtrig_channel {
+                *channels_on.entry(channel).or_insert(false) |= *is_occupied;
+            }
+        }
+        let mut state = self.camtrig_state.lock();
+        let mut next_state = *state;
+        for (channel, is_on) in channels_on.into_iter() {
+            let ch = match channel {
+                1 => &mut next_state.ch1,
+                2 => &mut next_state.ch2,
+                3 => &mut next_state.ch3,
+                4 => &mut next_state.ch4,
+                _ => continue,
+            };
+            ch.on_state = if is_on {
+                camtrig_comms::OnState::ConstantOn
+            } else {
+                camtrig_comms::OnState::Off
+            };
+        }
+        if next_state != *state {
+            *state = next_state;
+            tx.send(next_state).cb_ok();
+        }
+    }
+
+    #[cfg(not(feature = "with_camtrig"))]
+    fn set_camtrig_outputs(&self, _occupied: &[(&TriggerVolumeConfig, bool)]) {}
+
+    /// Start the recordings of the volumes which were entered.
+    fn send_post_triggers(&self, events: &[flydra_types::TriggerVolumeEventRow]) {
+        for event in events.iter() {
+            if event.event != flydra_types::TriggerVolumeEventKind::Enter {
+                continue;
+            }
+            let format = self
+                .cfgs
+                .iter()
+                .find(|cfg| cfg.name == event.volume)
+                .and_then(|cfg| cfg.post_trigger);
+            if let Some(format) = format {
+                info!(
+                    "object {} entered trigger volume \"{}\", triggering {:?} recording",
+                    event.obj_id, event.volume, format
+                );
+                let mut http_session_handler = self.http_session_handler.clone();
+                self.handle.spawn(async move {
+                    http_session_handler.send_post_trigger_to_all(format).await;
+                });
+            }
         }
     }
 }
 
+impl flydra2::TriggerVolumeCallback for TriggerVolumeOutputs {
+    fn on_trigger_volume_events(
+        &self,
+        events: &[flydra_types::TriggerVolumeEventRow],
+        status: &[TriggerVolumeStatus],
+    ) {
+        {
+            let mut tracker = self.shared_store.write();
+            tracker.modify(|shared| shared.trigger_volumes = status.to_vec());
+        }
+
+        // `status` is in the same order as the configurations.
+        let occupied: Vec<(&TriggerVolumeConfig, bool)> = self
+            .cfgs
+            .iter()
+            .zip(status.iter())
+            .map(|(cfg, st)| (cfg, !st.obj_ids.is_empty()))
+            .collect();
+        self.set_triggerbox_outputs(&occupied);
+        self.set_camtrig_outputs(&occupied);
+        self.send_post_triggers(events);
+    }
+}
+
+/// Open the camtrig device and spawn threads to communicate with it.
+///
+/// Returns a sender for the device states to set.
+#[cfg(feature = "with_camtrig")]
+fn launch_camtrig_threads(
+    device_path: &str,
+) -> Result<channellib::Sender<camtrig_comms::DeviceState>> {
+    let settings = serialport::SerialPortSettings {
+        baud_rate: 9600,
+        data_bits: serialport::DataBits::Eight,
+        flow_control: serialport::FlowControl::None,
+        parity: serialport::Parity::None,
+        stop_bits: serialport::StopBits::One,
+        timeout: std::time::Duration::from_millis(10_000),
+    };
+    let writer_port = serialport::open_with_settings(device_path, &settings)?;
+    let reader_port = writer_port.try_clone()?;
+
+    std::thread::Builder::new()
+        .name("camtrig reader".to_string())
+        .spawn(move || {
+            // The device only answers requests, which we do not send, so
+            // messages are only logged.
+            let result = camtrig::read_device_messages(
+                reader_port,
+                || true,
+                |msg| debug!("read from camtrig device: {:?}", msg),
+            );
+            if let Err(e) = result {
+                error!("reading from camtrig device: {}", e);
+            }
+        })?;
+
+    let (tx, rx) = channellib::unbounded::<camtrig_comms::DeviceState>();
+    std::thread::Builder::new()
+        .name("camtrig writer".to_string())
+        .spawn(move || {
+            let result = camtrig::write_device_messages(writer_port, || {
+                rx.recv().ok().map(camtrig_comms::ToDevice::DeviceState)
+            });
+            if let Err(e) = result {
+                error!("writing to camtrig device: {}", e);
+            }
+        })?;
+
+    Ok(tx)
+}
+
 fn toggle_saving_csv_tables(
     start_saving: bool,
     expected_framerate_arc: Arc<RwLock<Option<f32>>>,
@@ -1048,6 +1602,7 @@ fn synchronize_cameras(
     sync_pulse_pause_started_arc: Arc<RwLock<Option<std::time::Instant>>>,
     mut cam_manager: flydra2::ConnectedCamerasManager,
     time_model_arc: Arc<RwLock<Option<rust_cam_bui_types::ClockModel>>>,
+    software_sync: Option<Arc<flydra2::SoftwareSync>>,
 ) {
     info!("preparing to synchronize cameras");
 
@@ -1060,7 +1615,10 @@ fn synchronize_cameras(
     // Now we can reset the sync data.
     cam_manager.reset_sync_data();
 
-    {
+    if let Some(software_sync) = software_sync {
+        // The clock model of software synchronization remains valid.
+        software_sync.reset();
+    } else {
         let mut guard = time_model_arc.write();
         *guard = None;
     }
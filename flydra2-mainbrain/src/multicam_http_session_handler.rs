@@ -146,6 +146,27 @@ impl HttpSessionHandler {
         self.post(cam_name, args).await
     }
 
+    /// Start a recording including the video from before the trigger on all
+    /// cameras.
+    ///
+    /// Errors are logged and do not prevent sending to the other cameras.
+    pub async fn send_post_trigger_to_all(
+        &mut self,
+        format: ci2_remote_control::PostTriggerFormat,
+    ) {
+        let cam_names = self.cam_manager.all_ros_cam_names();
+        for cam_name in cam_names.iter() {
+            info!(
+                "for cam {}, sending post trigger {:?}",
+                cam_name.as_str(),
+                format
+            );
+            let args = ci2_remote_control::CamArg::PostTrigger(format);
+            // An error was already logged by `post()`.
+            let _ = self.post(cam_name, args).await;
+        }
+    }
+
     pub async fn send_quit_all(&mut self) -> Result<(), hyper::Error> {
         let cam_names = self.cam_manager.all_ros_cam_names();
 
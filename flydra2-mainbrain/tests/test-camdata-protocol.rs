@@ -0,0 +1,133 @@
+use futures::stream::StreamExt;
+
+use flydra2_mainbrain::{camdata_stream, tcp_camdata_stream, CamdataStream};
+use flydra_types::{
+    serialize_packet, CamdataCapabilities, CamdataEnvelope, CamdataProtocolInfo,
+    FlydraFloatTimestampLocal, FlydraRawUdpPacket, FlydraTypesError, HostClock,
+    ImageProcessingSteps, Triggerbox, CAMDATA_PROTOCOL_VERSION,
+};
+
+fn make_test_packet(framenumber: i32) -> FlydraRawUdpPacket {
+    FlydraRawUdpPacket {
+        cam_name: "cam_id".to_string(),
+        timestamp: Some(FlydraFloatTimestampLocal::<Triggerbox>::from_f64(12.34)),
+        cam_received_time: FlydraFloatTimestampLocal::<HostClock>::from_f64(123.456),
+        framenumber,
+        n_frames_skipped: 0,
+        done_camnode_processing: 0.0,
+        preprocess_stamp: 0.0,
+        image_processing_steps: ImageProcessingSteps::empty(),
+        points: vec![],
+        apriltags: vec![],
+        seq: Some(framenumber as u64),
+    }
+}
+
+fn make_test_envelope(packet: &FlydraRawUdpPacket) -> Vec<u8> {
+    // Encoded as by strand-cam.
+    let envelope = CamdataEnvelope {
+        protocol: CamdataProtocolInfo::new("test".to_string(), CamdataCapabilities::all()),
+        packet,
+    };
+    serde_cbor::ser::to_vec_packed_sd(&envelope).unwrap()
+}
+
+/// Start a mainbrain receiver and return it with a socket to send to it.
+async fn start_receiver(flydra1: bool) -> (CamdataStream, std::net::UdpSocket) {
+    let socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let receiver_addr = socket.local_addr().unwrap();
+    let stream = camdata_stream(socket, flydra1);
+
+    let sender = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    sender.connect(receiver_addr).unwrap();
+    (stream, sender)
+}
+
+async fn expect_error(stream: &mut CamdataStream) -> FlydraTypesError {
+    let err = stream.next().await.unwrap().unwrap_err();
+    let inner = err.into_inner().unwrap();
+    *inner.downcast::<FlydraTypesError>().unwrap()
+}
+
+#[tokio::test]
+async fn test_flydra1_packets() {
+    let (mut stream, sender) = start_receiver(true).await;
+
+    let packet = make_test_packet(42);
+    sender
+        .send(&serialize_packet(&packet, None).unwrap())
+        .unwrap();
+    let (received, _addr) = stream.next().await.unwrap().unwrap();
+    assert_eq!(received.cam_name, packet.cam_name);
+    assert_eq!(received.framenumber, packet.framenumber);
+}
+
+#[tokio::test]
+async fn test_cbor_packets() {
+    let (mut stream, sender) = start_receiver(false).await;
+
+    let packet = make_test_packet(42);
+    sender.send(&make_test_envelope(&packet)).unwrap();
+    let (received, _addr) = stream.next().await.unwrap().unwrap();
+    assert_eq!(received, packet);
+
+    // A packet from a release without versioned packets.
+    let old = make_test_packet(43);
+    sender
+        .send(&serde_cbor::ser::to_vec_packed_sd(&old).unwrap())
+        .unwrap();
+    match expect_error(&mut stream).await {
+        FlydraTypesError::UnversionedCamdata { cam_name } => assert_eq!(cam_name, "cam_id"),
+        e => panic!("unexpected error: {}", e),
+    }
+
+    // A packet from a release with another protocol version.
+    let newer = CamdataEnvelope {
+        protocol: CamdataProtocolInfo {
+            version: CAMDATA_PROTOCOL_VERSION + 1,
+            build_id: "strand-cam-99.0.0".to_string(),
+            capabilities: CamdataCapabilities::empty(),
+        },
+        packet: make_test_packet(44),
+    };
+    sender
+        .send(&serde_cbor::ser::to_vec_packed_sd(&newer).unwrap())
+        .unwrap();
+    match expect_error(&mut stream).await {
+        FlydraTypesError::CamdataProtocolMismatch {
+            version, build_id, ..
+        } => {
+            assert_eq!(version, CAMDATA_PROTOCOL_VERSION + 1);
+            assert_eq!(build_id, "strand-cam-99.0.0");
+        }
+        e => panic!("unexpected error: {}", e),
+    }
+
+    // Receiving continues after rejected packets.
+    let packet = make_test_packet(45);
+    sender.send(&make_test_envelope(&packet)).unwrap();
+    let (received, _addr) = stream.next().await.unwrap().unwrap();
+    assert_eq!(received, packet);
+}
+
+#[tokio::test]
+async fn test_tcp_packets() {
+    use std::io::Write;
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let receiver_addr = listener.local_addr().unwrap();
+    let mut stream = tcp_camdata_stream(listener);
+
+    let mut sender = std::net::TcpStream::connect(receiver_addr).unwrap();
+    let packets: Vec<_> = (0..3).map(make_test_packet).collect();
+    for packet in packets.iter() {
+        // Framed as by strand-cam.
+        let buf = make_test_envelope(packet);
+        sender.write_all(&(buf.len() as u32).to_be_bytes()).unwrap();
+        sender.write_all(&buf).unwrap();
+    }
+    for packet in packets.iter() {
+        let (received, _addr) = stream.next().await.unwrap().unwrap();
+        assert_eq!(&received, packet);
+    }
+}
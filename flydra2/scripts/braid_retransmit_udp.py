@@ -7,7 +7,8 @@
 # the Braid pose API, in addition to the `Update` events, Braid also has `Birth`
 # and `Death` events. The `Birth` event returns the same data as an `Update`
 # event, whereas the `Death` event sends just `obj_id`. Version 2 added the
-# EndOfFrame message type.
+# EndOfFrame message type. Version 3 added the TriggerVolumeEnter and
+# TriggerVolumeLeave message types.
 
 from __future__ import print_function
 import argparse
@@ -38,7 +39,7 @@ class BraidProxy:
             data = parse_chunk(chunk)
             # print('chunk value: %r'%data)
             version = data.get("v", 1)  # default because missing in first release
-            assert version in (1, 2)  # check the data version
+            assert version in (1, 2, 3)  # check the data version
 
             try:
                 update_dict = data["Update"]
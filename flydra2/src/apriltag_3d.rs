@@ -0,0 +1,243 @@
+//! Triangulation of April Tags detected by multiple cameras.
+
+use std::collections::BTreeMap;
+
+use nalgebra::{Matrix3, Point2, Point3, Rotation3, UnitQuaternion, Vector3};
+
+use flydra_types::{AprilTag3dRow, FlydraRawUdpAprilTag};
+use mvg::DistortedPixel;
+
+use crate::{bundled_data::BundledAllCamsOneFrameDistorted, MyFloat};
+
+/// Distorted observations of a single tag, one entry per camera.
+type TagObservations<'a> = Vec<(String, &'a FlydraRawUdpAprilTag)>;
+
+/// Triangulate all April Tags seen by at least two calibrated cameras.
+pub(crate) fn triangulate_tags(
+    bundle: &BundledAllCamsOneFrameDistorted,
+    recon: &flydra_mvg::FlydraMultiCameraSystem<MyFloat>,
+) -> Vec<AprilTag3dRow> {
+    let mut by_tag: BTreeMap<(&str, i32), TagObservations> = BTreeMap::new();
+    for fdp in bundle.inner() {
+        let cam_name = fdp.frame_data.cam_name.as_str();
+        if recon.cam_by_name(cam_name).is_none() {
+            // no calibration for this camera - cannot contribute to 3D
+            continue;
+        }
+        for tag in fdp.apriltags.iter() {
+            let n_same = fdp
+                .apriltags
+                .iter()
+                .filter(|t| t.family == tag.family && t.id == tag.id)
+                .count();
+            if n_same != 1 {
+                // The same tag seen twice by one camera is ambiguous.
+                continue;
+            }
+            by_tag
+                .entry((tag.family.as_str(), tag.id))
+                .or_insert_with(Vec::new)
+                .push((cam_name.to_string(), tag));
+        }
+    }
+
+    let tdpt = bundle.tdpt();
+    by_tag
+        .into_iter()
+        .filter(|(_, obs)| obs.len() >= 2)
+        .filter_map(|((family, tag_id), obs)| {
+            let (center, mut sum_reproj_dist) = find3d(recon, &obs, |t| t.center)?;
+            let mut corners = [Point3::origin(); 4];
+            for (i, corner) in corners.iter_mut().enumerate() {
+                let (pt, reproj_dist) = find3d(recon, &obs, |t| t.corners[i])?;
+                *corner = pt;
+                sum_reproj_dist += reproj_dist;
+            }
+            let rotation = tag_orientation(&corners)?;
+            let q = UnitQuaternion::from_rotation_matrix(&rotation);
+            Some(AprilTag3dRow {
+                frame: tdpt.synced_frame(),
+                timestamp: tdpt.trigger_timestamp(),
+                family: family.to_string(),
+                tag_id,
+                x: center.x,
+                y: center.y,
+                z: center.z,
+                qw: q.w,
+                qx: q.i,
+                qy: q.j,
+                qz: q.k,
+                corner0_x: corners[0].x,
+                corner0_y: corners[0].y,
+                corner0_z: corners[0].z,
+                corner1_x: corners[1].x,
+                corner1_y: corners[1].y,
+                corner1_z: corners[1].z,
+                corner2_x: corners[2].x,
+                corner2_y: corners[2].y,
+                corner2_z: corners[2].z,
+                corner3_x: corners[3].x,
+                corner3_y: corners[3].y,
+                corner3_z: corners[3].z,
+                n_cams: obs.len().min(u8::MAX as usize) as u8,
+                mean_reproj_dist: sum_reproj_dist / 5.0,
+            })
+        })
+        .collect()
+}
+
+/// Triangulate one point of a tag, returning it with its mean reprojection
+/// distance.
+fn find3d<F>(
+    recon: &flydra_mvg::FlydraMultiCameraSystem<MyFloat>,
+    obs: &TagObservations,
+    get_pt: F,
+) -> Option<(Point3<MyFloat>, MyFloat)>
+where
+    F: Fn(&FlydraRawUdpAprilTag) -> [f64; 2],
+{
+    let points = obs
+        .iter()
+        .map(|(cam_name, tag)| {
+            let pt = get_pt(tag);
+            (
+                cam_name.clone(),
+                DistortedPixel {
+                    coords: Point2::new(pt[0], pt[1]),
+                },
+            )
+        })
+        .collect();
+    let result = recon.find3d_and_cum_reproj_dist_distorted(&points).ok()?;
+    Some((result.point.coords, result.mean_reproj_dist))
+}
+
+/// Compute the rotation from the tag frame to the world frame.
+///
+/// The x axis points from corner 0 to corner 1 and the y axis from corner 3 to
+/// corner 0, matching the tag frame used by the April Tag library.
+fn tag_orientation(corners: &[Point3<MyFloat>; 4]) -> Option<Rotation3<MyFloat>> {
+    let x = (corners[1] - corners[0]) + (corners[2] - corners[3]);
+    let y = (corners[0] - corners[3]) + (corners[1] - corners[2]);
+    let z = x.cross(&y);
+    let x = x.try_normalize(1e-12)?;
+    let z = z.try_normalize(1e-12)?;
+    let y: Vector3<MyFloat> = z.cross(&x);
+    Some(Rotation3::from_matrix_unchecked(Matrix3::from_columns(&[
+        x, y, z,
+    ])))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use flydra_types::{CamNum, FlydraFloatTimestampLocal, RosCamName, SyncFno};
+    use mvg::PointWorldFrame;
+    use nalgebra::Matrix3x4;
+
+    use crate::{FrameData, FrameDataAndPoints};
+
+    /// A pinhole camera with rotation `rot` (world to camera) and center `cc`.
+    fn pinhole(rot: Rotation3<f64>, cc: Vector3<f64>) -> mvg::Camera<f64> {
+        let k = Matrix3::new(500.0, 0.0, 320.0, 0.0, 500.0, 240.0, 0.0, 0.0, 1.0);
+        let t = -(rot * cc);
+        let mut rt = Matrix3x4::zeros();
+        rt.fixed_columns_mut::<3>(0).copy_from(rot.matrix());
+        rt.set_column(3, &t);
+        mvg::Camera::from_pmat(640, 480, &(k * rt)).unwrap()
+    }
+
+    #[test]
+    fn triangulate_tag_seen_by_two_cameras() {
+        let mut cams = BTreeMap::new();
+        cams.insert(
+            "cam1".to_string(),
+            pinhole(Rotation3::identity(), Vector3::new(0.0, 0.0, -2.0)),
+        );
+        cams.insert(
+            "cam2".to_string(),
+            pinhole(
+                Rotation3::from_euler_angles(0.0, 0.5, 0.0),
+                Vector3::new(-1.0, 0.0, -1.7),
+            ),
+        );
+        let recon = flydra_mvg::FlydraMultiCameraSystem::new(cams, None);
+
+        // A tag of 10 cm width, rotated and offset from the origin.
+        let rotation = Rotation3::from_euler_angles(0.2, -0.3, 0.4);
+        let offset = Vector3::new(0.05, -0.02, 0.1);
+        let s = 0.05;
+        let local = [(-s, s), (s, s), (s, -s), (-s, -s)];
+        let to_world = |x: f64, y: f64| PointWorldFrame {
+            coords: rotation * Point3::new(x, y, 0.0) + offset,
+        };
+
+        let mut bundle = None;
+        for (i, cam_name) in ["cam1", "cam2"].iter().enumerate() {
+            let cam = recon.cam_by_name(cam_name).unwrap();
+            let project = |pt: &PointWorldFrame<f64>| {
+                let px = cam.project_3d_to_distorted_pixel(pt);
+                [px.coords.x, px.coords.y]
+            };
+            let mut corners = [[0.0; 2]; 4];
+            for (corner, (x, y)) in corners.iter_mut().zip(local.iter()) {
+                *corner = project(&to_world(*x, *y));
+            }
+            let tag = FlydraRawUdpAprilTag {
+                family: "36h11".to_string(),
+                id: 7,
+                center: project(&to_world(0.0, 0.0)),
+                corners,
+            };
+            let fdp = FrameDataAndPoints {
+                frame_data: FrameData::new(
+                    RosCamName::new(cam_name.to_string()),
+                    CamNum(i as u8),
+                    SyncFno(42),
+                    None,
+                    FlydraFloatTimestampLocal::from_dt(&chrono::Local::now()),
+                ),
+                points: vec![],
+                apriltags: vec![tag],
+            };
+            match bundle.as_mut() {
+                None => bundle = Some(BundledAllCamsOneFrameDistorted::new(fdp)),
+                Some(bundle) => bundle.push(fdp),
+            }
+        }
+
+        let rows = triangulate_tags(&bundle.unwrap(), &recon);
+        assert_eq!(rows.len(), 1);
+        let row = &rows[0];
+        assert_eq!(row.frame, SyncFno(42));
+        assert_eq!(row.tag_id, 7);
+        assert_eq!(row.n_cams, 2);
+        assert!(row.mean_reproj_dist < 1e-6);
+
+        let center = Vector3::new(row.x, row.y, row.z);
+        assert!((center - offset).norm() < 1e-6);
+        let corner0 = Vector3::new(row.corner0_x, row.corner0_y, row.corner0_z);
+        assert!((corner0 - to_world(-s, s).coords.coords).norm() < 1e-6);
+
+        let q = UnitQuaternion::from_quaternion(nalgebra::Quaternion::new(
+            row.qw, row.qx, row.qy, row.qz,
+        ));
+        let found = q.to_rotation_matrix();
+        assert!(found.angle_to(&rotation) < 1e-6);
+    }
+
+    #[test]
+    fn orientation_of_rotated_tag() {
+        let rotation = Rotation3::from_euler_angles(0.1, -0.4, 1.2);
+        let offset = Vector3::new(0.3, -0.1, 0.5);
+        let s = 0.05;
+        let mut corners = [Point3::origin(); 4];
+        let local = [(-s, s), (s, s), (s, -s), (-s, -s)];
+        for (corner, (x, y)) in corners.iter_mut().zip(local.iter()) {
+            *corner = rotation * Point3::new(*x, *y, 0.0) + offset;
+        }
+        let found = tag_orientation(&corners).unwrap();
+        assert!(found.angle_to(&rotation) < 1e-9);
+    }
+}
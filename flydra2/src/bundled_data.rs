@@ -151,6 +151,16 @@ impl BundledAllCamsOneFrameDistorted {
         &self.cameras
     }
 
+    #[inline]
+    pub(crate) fn tdpt(&self) -> &TimeDataPassthrough {
+        &self.tdpt
+    }
+
+    /// The raw data from each camera.
+    pub(crate) fn inner(&self) -> &[FrameDataAndPoints] {
+        &self.inner
+    }
+
     pub(crate) fn undistort(
         self,
         recon: &flydra_mvg::FlydraMultiCameraSystem<MyFloat>,
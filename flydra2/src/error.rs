@@ -70,6 +70,13 @@ pub enum Error {
         #[cfg(feature = "backtrace")]
         backtrace: Backtrace,
     },
+    #[error("{source}")]
+    MotionModel {
+        #[from]
+        source: tracking::motion_model_registry::MotionModelError,
+        #[cfg(feature = "backtrace")]
+        backtrace: Backtrace,
+    },
     #[error("invalid hypothesis testing parameters")]
     InvalidHypothesisTestingParameters,
     #[error("insufficient data to calculate FPS")]
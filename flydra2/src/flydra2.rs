@@ -19,7 +19,7 @@ use hdrhistogram::{
 use libflate::finish::AutoFinishUnchecked;
 use libflate::gzip::Encoder;
 
-use nalgebra::core::dimension::{U1, U2, U3, U6};
+use nalgebra::core::dimension::{DimName, U1, U2, U3, U6, U9};
 use nalgebra::{OMatrix, OVector, Point3, Vector6};
 
 use nalgebra::allocator::Allocator;
@@ -33,12 +33,11 @@ pub use braidz_types::BraidMetadata;
 
 use crossbeam_ok::CrossbeamOk;
 use flydra_types::{
-    CamInfoRow, CamNum, ConnectedCameraSyncState, FlydraFloatTimestampLocal, HostClock,
-    KalmanEstimatesRow, RosCamName, SyncFno, TextlogRow, TriggerClockInfoRow, Triggerbox,
+    AprilTag3dRow, CamInfoRow, CamNum, CamdataStatsRow, ConnectedCameraSyncState,
+    FlydraFloatTimestampLocal, FlydraRawUdpAprilTag, HostClock, KalmanEstimatesRow, RosCamName,
+    SyncFno, TextlogRow, TriggerClockInfoRow, TriggerVolumeEventRow, Triggerbox,
 };
-pub use flydra_types::{Data2dDistortedRow, Data2dDistortedRowF32};
-
-use withkey::WithKey;
+pub use flydra_types::{Data2dDistortedRow, Data2dDistortedRowF32, DataAssocRow};
 
 mod connected_camera_manager;
 pub use connected_camera_manager::{ConnectedCamCallback, ConnectedCamerasManager};
@@ -46,9 +45,14 @@ pub use connected_camera_manager::{ConnectedCamCallback, ConnectedCamerasManager
 mod write_data;
 use write_data::writer_thread_main;
 
+mod apriltag_3d;
 mod bundled_data;
 mod contiguous_stream;
 mod frame_bundler;
+mod software_sync;
+pub use software_sync::{CameraSyncStats, SoftwareSync};
+mod trigger_volumes;
+pub use trigger_volumes::{TriggerVolumeCallback, TriggerVolumes};
 
 pub use flydra_types::{BRAID_SCHEMA, IMAGES_DIRNAME};
 use flydra_types::{RECONSTRUCT_LATENCY_HLOG_FNAME, REPROJECTION_DIST_HLOG_FNAME};
@@ -68,6 +72,7 @@ mod zip_dir;
 mod model_server;
 pub use crate::model_server::{
     new_model_server, GetsUpdates, ModelServer, SendKalmanEstimatesRow, SendType,
+    TriggerVolumeObject,
 };
 
 use crate::contiguous_stream::make_contiguous;
@@ -100,15 +105,19 @@ where
 }
 
 // We use a 6 dimensional state vector:
-// [x,y,z,xvel,yvel,zvel].
+// [x,y,z,xvel,yvel,zvel]
+// or, for motion models with acceleration, a 9 dimensional state vector:
+// [x,y,z,xvel,yvel,zvel,xaccel,yaccel,zaccel].
 #[derive(Debug)]
 struct CameraObservationModel<R>
 where
     R: RealField + Default + serde::Serialize,
 {
     cam: flydra_mvg::MultiCamera<R>,
-    observation_matrix: OMatrix<R, U2, U6>,
-    observation_matrix_transpose: OMatrix<R, U6, U2>,
+    observation_matrix6: OMatrix<R, U2, U6>,
+    observation_matrix6_transpose: OMatrix<R, U6, U2>,
+    observation_matrix9: OMatrix<R, U2, U9>,
+    observation_matrix9_transpose: OMatrix<R, U9, U2>,
     observation_noise_covariance: OMatrix<R, U2, U2>,
 }
 
@@ -121,23 +130,43 @@ where
         a: OMatrix<R, U2, U3>,
         ekf_observation_covariance_pixels: f32,
     ) -> Self {
-        let observation_matrix = {
+        let observation_matrix6 = {
             let mut o = OMatrix::<R, U2, U6>::zeros();
             o.fixed_columns_mut::<3>(0).copy_from(&a);
             o
         };
-        let observation_matrix_transpose = observation_matrix.transpose();
+        let observation_matrix6_transpose = observation_matrix6.transpose();
+        let observation_matrix9 = {
+            let mut o = OMatrix::<R, U2, U9>::zeros();
+            o.fixed_columns_mut::<3>(0).copy_from(&a);
+            o
+        };
+        let observation_matrix9_transpose = observation_matrix9.transpose();
 
         let r = nalgebra::convert(ekf_observation_covariance_pixels as f64);
         let zero = nalgebra::convert(0.0);
         let observation_noise_covariance = OMatrix::<R, U2, U2>::new(r, zero, zero, r);
         Self {
             cam,
-            observation_matrix,
-            observation_matrix_transpose,
+            observation_matrix6,
+            observation_matrix6_transpose,
+            observation_matrix9,
+            observation_matrix9_transpose,
             observation_noise_covariance,
         }
     }
+
+    fn project<SS: DimName>(&self, state: &OVector<R, SS>) -> OVector<R, U2>
+    where
+        DefaultAllocator: Allocator<R, SS>,
+    {
+        // TODO: update to handle water here. See tag "laksdfjasl".
+        let pt = to_world_point(state);
+        let undistored = self.cam.project_3d_to_pixel(&pt);
+        OMatrix::<R, U1, U2>::new(undistored.coords[0], undistored.coords[1]).transpose()
+        // This doesn't compile for some reason:
+        // OMatrix::<R, U2, U1>::new(undistored.coords[0], undistored.coords[1])
+    }
 }
 
 impl<R> adskalman::ObservationModel<R, U6, U2> for CameraObservationModel<R>
@@ -153,35 +182,42 @@ where
     R: RealField + Default + serde::Serialize,
 {
     fn H(&self) -> &OMatrix<R, U2, U6> {
-        &self.observation_matrix
+        &self.observation_matrix6
     }
     fn HT(&self) -> &OMatrix<R, U6, U2> {
-        &self.observation_matrix_transpose
+        &self.observation_matrix6_transpose
     }
     fn R(&self) -> &OMatrix<R, U2, U2> {
         &self.observation_noise_covariance
     }
     fn predict_observation(&self, state: &OVector<R, U6>) -> OVector<R, U2> {
-        // TODO: update to handle water here. See tag "laksdfjasl".
-        let pt = to_world_point(&state);
-        let undistored = self.cam.project_3d_to_pixel(&pt);
-        OMatrix::<R, U1, U2>::new(undistored.coords[0], undistored.coords[1]).transpose()
-        // This doesn't compile for some reason:
-        // OMatrix::<R, U2, U1>::new(undistored.coords[0], undistored.coords[1])
+        self.project(state)
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct DataAssocRow {
-    // changes to this struct should update BraidMetadataSchemaTag
-    pub obj_id: u32,
-    pub frame: SyncFno,
-    pub cam_num: CamNum,
-    pub pt_idx: u8,
-}
-impl WithKey<SyncFno> for DataAssocRow {
-    fn key(&self) -> SyncFno {
-        self.frame
+impl<R> adskalman::ObservationModel<R, U9, U2> for CameraObservationModel<R>
+where
+    DefaultAllocator: Allocator<R, U9, U9>,
+    DefaultAllocator: Allocator<R, U9>,
+    DefaultAllocator: Allocator<R, U2, U9>,
+    DefaultAllocator: Allocator<R, U9, U2>,
+    DefaultAllocator: Allocator<R, U2, U2>,
+    DefaultAllocator: Allocator<R, U2>,
+    DefaultAllocator: Allocator<(usize, usize), U2>,
+    U2: DimMin<U2, Output = U2>,
+    R: RealField + Default + serde::Serialize,
+{
+    fn H(&self) -> &OMatrix<R, U2, U9> {
+        &self.observation_matrix9
+    }
+    fn HT(&self) -> &OMatrix<R, U9, U2> {
+        &self.observation_matrix9_transpose
+    }
+    fn R(&self) -> &OMatrix<R, U2, U2> {
+        &self.observation_noise_covariance
+    }
+    fn predict_observation(&self, state: &OVector<R, U9>) -> OVector<R, U2> {
+        self.project(state)
     }
 }
 
@@ -324,10 +360,15 @@ impl std::cmp::PartialEq for TimeDataPassthrough {
     }
 }
 
-fn to_world_point<R: nalgebra::RealField>(vec6: &OVector<R, U6>) -> PointWorldFrame<R> {
+fn to_world_point<R, SS>(state: &OVector<R, SS>) -> PointWorldFrame<R>
+where
+    R: nalgebra::RealField,
+    SS: DimName,
+    DefaultAllocator: Allocator<R, SS>,
+{
     // TODO could we just borrow a pointer to data instead of copying it?
     PointWorldFrame {
-        coords: Point3::new(vec6.x, vec6.y, vec6.z),
+        coords: Point3::new(state[0], state[1], state[2]),
     }
 }
 
@@ -389,6 +430,7 @@ impl FrameData {
 pub struct FrameDataAndPoints {
     pub frame_data: FrameData,
     pub points: Vec<NumberedRawUdpPoint>,
+    pub apriltags: Vec<FlydraRawUdpAprilTag>,
 }
 
 fn safe_u8(val: usize) -> u8 {
@@ -404,6 +446,25 @@ fn convert_to_save(frame_data: &FrameData, input: &NumberedRawUdpPoint) -> Data2
         Some((s, e)) => (s as f32, e as f32),
     };
 
+    let mut row = convert_empty_to_save(frame_data);
+    if let Some(ref features) = input.pt.blob_features {
+        if let Some(ref ellipse) = features.ellipse {
+            row.ellipse_major = ellipse.major_axis as f32;
+            row.ellipse_minor = ellipse.minor_axis as f32;
+            row.ellipse_angle = ellipse.angle as f32;
+        }
+        row.bbox_left = Some(features.bbox.left);
+        row.bbox_bottom = Some(features.bbox.bottom);
+        row.bbox_right = Some(features.bbox.right);
+        row.bbox_top = Some(features.bbox.top);
+        row.contour = features.contour_string();
+        if let Some(ref patch) = features.patch {
+            row.patch_left = Some(patch.left);
+            row.patch_bottom = Some(patch.bottom);
+            row.patch = patch.data_hex();
+        }
+    }
+
     Data2dDistortedRowF32 {
         camn: frame_data.cam_num,
         frame: frame_data.synced_frame.0 as i64,
@@ -418,6 +479,7 @@ fn convert_to_save(frame_data: &FrameData, input: &NumberedRawUdpPoint) -> Data2
         cur_val: input.pt.cur_val,
         mean_val: input.pt.mean_val as f32,
         sumsqf_val: input.pt.sumsqf_val as f32,
+        ..row
     }
 }
 
@@ -436,6 +498,17 @@ fn convert_empty_to_save(frame_data: &FrameData) -> Data2dDistortedRowF32 {
         cur_val: 0,
         mean_val: std::f32::NAN,
         sumsqf_val: std::f32::NAN,
+        ellipse_major: std::f32::NAN,
+        ellipse_minor: std::f32::NAN,
+        ellipse_angle: std::f32::NAN,
+        bbox_left: None,
+        bbox_bottom: None,
+        bbox_right: None,
+        bbox_top: None,
+        contour: String::new(),
+        patch_left: None,
+        patch_bottom: None,
+        patch: String::new(),
     }
 }
 
@@ -502,8 +575,16 @@ pub struct KalmanEstimateRecord {
 pub enum SaveToDiskMsg {
     // birth?
     KalmanEstimate(KalmanEstimateRecord),
+    /// Smoothed estimates of an entire trajectory
+    SmoothedKalmanEstimates(Vec<KalmanEstimatesRow>),
     // death?
     Data2dDistorted(FrameDataAndPoints),
+    /// April Tags triangulated on a single frame
+    AprilTag3d(Vec<AprilTag3dRow>),
+    /// An object entering or leaving a trigger volume
+    TriggerVolumeEvent(TriggerVolumeEventRow),
+    /// Received and lost frames of a camera over a recent interval
+    CamdataStats(CamdataStatsRow),
     StartSavingCsv(StartSavingCsvConfig),
     StopSavingCsv,
     Textlog(TextlogRow),
@@ -758,6 +839,12 @@ impl CoordProcessorControl {
             .cb_ok();
     }
 
+    pub fn append_camdata_stats(&self, row: CamdataStatsRow) {
+        self.save_data_tx
+            .send(SaveToDiskMsg::CamdataStats(row))
+            .cb_ok();
+    }
+
     pub fn set_experiment_uuid(&self, uuid: String) {
         self.save_data_tx
             .send(SaveToDiskMsg::SetExperimentUuid(uuid))
@@ -772,6 +859,8 @@ pub struct CoordProcessor {
     pub writer_thread_handle: Option<std::thread::JoinHandle<()>>,
     model_servers: Vec<Box<dyn GetsUpdates>>,
     tracking_params: Arc<SwitchingTrackingParams>,
+    motion_model: tracking::motion_model_registry::RegisteredMotionModel3D<MyFloat>,
+    smooth_kalman_estimates: bool,
     mc2: Option<crate::tracking_core::ModelCollection<crate::tracking_core::CollectionFrameDone>>,
 }
 
@@ -801,6 +890,8 @@ impl CoordProcessor {
 
         info!("using SwitchingTrackingParams {:?}", tracking_params);
 
+        let motion_model = crate::tracking_core::motion_model_from_params(&tracking_params)?;
+
         let tracking_params = Arc::new(tracking_params);
         let tracking_params2 = tracking_params.clone();
         let writer_thread_builder = std::thread::Builder::new().name("writer_thread".to_string());
@@ -824,6 +915,8 @@ impl CoordProcessor {
             save_data_tx,
             writer_thread_handle,
             tracking_params,
+            motion_model,
+            smooth_kalman_estimates: false,
             model_servers: vec![],
             mc2: None,
         })
@@ -836,10 +929,12 @@ impl CoordProcessor {
     ) -> crate::tracking_core::ModelCollection<crate::tracking_core::CollectionFrameDone> {
         crate::tracking_core::initialize_model_collection(
             self.tracking_params.clone(),
+            &self.motion_model,
             recon.clone(),
             fps,
             self.cam_manager.clone(),
             self.save_data_tx.clone(),
+            self.smooth_kalman_estimates,
         )
     }
 
@@ -853,6 +948,22 @@ impl CoordProcessor {
         self.model_servers.push(model_server);
     }
 
+    /// Also save smoothed estimates of each trajectory when it ends.
+    ///
+    /// The forward Kalman estimates are saved in any case. The smoothed
+    /// estimates are saved to a separate table with rows grouped by object.
+    /// Since the smoothing is done at the end of each trajectory, smoothed
+    /// estimates are not available in realtime.
+    ///
+    /// Returns an error if smoothing is not implemented for the motion model.
+    pub fn set_smooth_kalman_estimates(&mut self, value: bool) -> Result<()> {
+        if value {
+            self.motion_model.check_smoothing()?;
+        }
+        self.smooth_kalman_estimates = value;
+        Ok(())
+    }
+
     /// Consume the CoordProcessor and the input stream.
     ///
     /// Returns a future that completes when done. The vast majority
@@ -940,6 +1051,15 @@ impl CoordProcessor {
             }
             prev_frame = bundle.frame();
 
+            if let Some(ref recon) = self.recon {
+                let tags = apriltag_3d::triangulate_tags(&bundle, recon);
+                if !tags.is_empty() {
+                    self.save_data_tx
+                        .send(SaveToDiskMsg::AprilTag3d(tags))
+                        .cb_ok();
+                }
+            }
+
             if let Some(model_collection) = self.mc2.take() {
                 // undistort all observations
                 let undistorted = bundle.undistort(&model_collection.mcinner.recon);
@@ -958,6 +1078,10 @@ impl CoordProcessor {
         }
         info!("contiguous_stream is done.");
 
+        if let Some(model_collection) = self.mc2.take() {
+            model_collection.finish_all();
+        }
+
         debug!("consume_stream future done");
         let writer_thread_handle = self.writer_thread_handle.take();
         writer_thread_handle
@@ -1014,6 +1138,17 @@ fn test_csv_nan() {
         cur_val: 5,
         mean_val: 6.0,
         sumsqf_val: 7.0,
+        ellipse_major: std::f32::NAN,
+        ellipse_minor: std::f32::NAN,
+        ellipse_angle: std::f32::NAN,
+        bbox_left: None,
+        bbox_bottom: None,
+        bbox_right: None,
+        bbox_top: None,
+        contour: String::new(),
+        patch_left: None,
+        patch_bottom: None,
+        patch: String::new(),
     };
 
     let mut csv_buf = Vec::<u8>::new();
@@ -1041,3 +1176,68 @@ fn test_csv_nan() {
         assert_eq!(count, 1);
     }
 }
+
+#[test]
+fn test_csv_blob_features() {
+    let save_row_data = Data2dDistortedRowF32 {
+        camn: CamNum(1),
+        frame: 2,
+        timestamp: None,
+        cam_received_timestamp: FlydraFloatTimestampLocal::from_dt(&chrono::Local::now()),
+        x: 10.5,
+        y: 20.5,
+        area: 1.0,
+        slope: 2.0,
+        eccentricity: 3.0,
+        frame_pt_idx: 4,
+        cur_val: 5,
+        mean_val: 6.0,
+        sumsqf_val: 7.0,
+        ellipse_major: 4.0,
+        ellipse_minor: 2.0,
+        ellipse_angle: 0.5,
+        bbox_left: Some(8),
+        bbox_bottom: Some(18),
+        bbox_right: Some(13),
+        bbox_top: Some(23),
+        contour: "8 18 13 18.5 10 23".to_string(),
+        patch_left: Some(9),
+        patch_bottom: Some(19),
+        patch: "00ff10a0".to_string(),
+    };
+
+    let mut csv_buf = Vec::<u8>::new();
+    {
+        let mut wtr = csv::Writer::from_writer(&mut csv_buf);
+        wtr.serialize(&save_row_data).unwrap();
+    }
+
+    let rdr = csv::Reader::from_reader(csv_buf.as_slice());
+    let rows: Vec<Data2dDistortedRow> = rdr.into_deserialize().map(|r| r.unwrap()).collect();
+    assert_eq!(rows.len(), 1);
+    let features = rows[0].blob_features().unwrap();
+    assert_eq!(
+        features.ellipse,
+        Some(flydra_types::BlobEllipse {
+            major_axis: 4.0,
+            minor_axis: 2.0,
+            angle: 0.5f32 as f64,
+        })
+    );
+    assert_eq!(
+        features.bbox,
+        flydra_types::BlobBoundingBox {
+            left: 8,
+            bottom: 18,
+            right: 13,
+            top: 23,
+        }
+    );
+    assert_eq!(
+        features.contour,
+        vec![[8.0, 18.0], [13.0, 18.5], [10.0, 23.0]]
+    );
+    let patch = features.patch.unwrap();
+    assert_eq!((patch.left, patch.bottom, patch.size), (9, 19, 2));
+    assert_eq!(patch.data, vec![0x00, 0xff, 0x10, 0xa0]);
+}
@@ -154,6 +154,7 @@ fn test_frame_bundler() {
             FlydraFloatTimestampLocal::from_f64(0.0),
         ),
         points: Vec::new(),
+        apriltags: Vec::new(),
     };
 
     let packet2_frame1_cam2 = FrameDataAndPoints {
@@ -165,6 +166,7 @@ fn test_frame_bundler() {
             FlydraFloatTimestampLocal::from_f64(0.0),
         ),
         points: Vec::new(),
+        apriltags: Vec::new(),
     };
 
     let packet2_frame0_cam2 = FrameDataAndPoints {
@@ -176,6 +178,7 @@ fn test_frame_bundler() {
             FlydraFloatTimestampLocal::from_f64(0.0),
         ),
         points: Vec::new(),
+        apriltags: Vec::new(),
     };
 
     let packet2_frame2_cam2 = FrameDataAndPoints {
@@ -187,6 +190,7 @@ fn test_frame_bundler() {
             FlydraFloatTimestampLocal::from_f64(0.0),
         ),
         points: Vec::new(),
+        apriltags: Vec::new(),
     };
 
     let packet2_frame3_cam2 = FrameDataAndPoints {
@@ -198,6 +202,7 @@ fn test_frame_bundler() {
             FlydraFloatTimestampLocal::from_f64(0.0),
         ),
         points: Vec::new(),
+        apriltags: Vec::new(),
     };
 
     // with zero packets
@@ -293,6 +293,14 @@ impl From<flydra_types::KalmanEstimatesRow> for SendKalmanEstimatesRow {
     }
 }
 
+/// An object entering or leaving a trigger volume.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TriggerVolumeObject {
+    pub obj_id: u32,
+    /// The name of the trigger volume.
+    pub volume: String,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub enum SendType {
     // IMPORTANT NOTE: if you change this type, be sure to change the version
@@ -302,6 +310,9 @@ pub enum SendType {
     Death(u32), // obj_id
 
     EndOfFrame(SyncFno),
+
+    TriggerVolumeEnter(TriggerVolumeObject),
+    TriggerVolumeLeave(TriggerVolumeObject),
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -428,7 +439,7 @@ impl GetsUpdates for ModelServer {
         // Send updates after each observation for lowest-possible latency.
         let data = ToListener {
             /// Braid pose API
-            v: 2, // <- Bump when ToListener or SendType definition changes ZP4q
+            v: 3, // <- Bump when ToListener or SendType definition changes ZP4q
             msg,
             latency,
             synced_frame: tdpt.synced_frame(),
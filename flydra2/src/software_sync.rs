@@ -0,0 +1,290 @@
+//! Synchronization of free-running cameras without a triggerbox.
+//!
+//! For each camera, a linear model of the host timestamp of a frame as a
+//! function of its frame number is fit to recent frames. This estimates the
+//! offset and the drift of the camera clock. The synchronized frame number of
+//! each frame is then computed from the time given by the model, which is
+//! common to all cameras.
+
+use std::collections::BTreeMap;
+
+use log::{debug, info, warn};
+use parking_lot::Mutex;
+
+use flydra_types::{FlydraRawUdpPacket, RosCamName, SoftwareSyncConfig, SyncFno};
+
+/// The offset and drift estimated for a camera.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CameraSyncStats {
+    /// The frame rate of the camera as measured by the host clock.
+    pub fps: f64,
+    /// The RMS difference, in seconds, between the host timestamps of the
+    /// frames and the model.
+    pub jitter: f64,
+}
+
+/// Linear fit of host time against frame number, weighting recent frames
+/// more.
+struct CameraClockFit {
+    /// Subtracted from all frame numbers and times for numerical precision.
+    frame_ref: u64,
+    time_ref: f64,
+    n_frames: u64,
+    mean_x: f64,
+    mean_y: f64,
+    cov_xx: f64,
+    cov_xy: f64,
+    mean_sq_resid: f64,
+    /// The number of consecutive frames not matching the model.
+    n_outliers: u64,
+    last_synced_frame: Option<u64>,
+}
+
+impl CameraClockFit {
+    fn new(frame: u64, time: f64) -> Self {
+        Self {
+            frame_ref: frame,
+            time_ref: time,
+            n_frames: 0,
+            mean_x: 0.0,
+            mean_y: 0.0,
+            cov_xx: 0.0,
+            cov_xy: 0.0,
+            mean_sq_resid: 0.0,
+            n_outliers: 0,
+            last_synced_frame: None,
+        }
+    }
+
+    /// Seconds per frame.
+    fn slope(&self) -> Option<f64> {
+        if self.n_frames >= 2 && self.cov_xx > 0.0 {
+            Some(self.cov_xy / self.cov_xx)
+        } else {
+            None
+        }
+    }
+
+    /// The time of `x` relative to `time_ref` given by the model.
+    fn predict(&self, x: f64) -> Option<f64> {
+        self.slope()
+            .map(|slope| self.mean_y + slope * (x - self.mean_x))
+    }
+
+    fn update(&mut self, x: f64, y: f64, window: f64) {
+        if let Some(predicted) = self.predict(x) {
+            let resid = y - predicted;
+            let alpha = (1.0 / self.n_frames as f64).max(1.0 / window);
+            self.mean_sq_resid += alpha * (resid * resid - self.mean_sq_resid);
+        }
+        self.n_frames += 1;
+        // Exponentially weighted mean and covariance. Until the window is
+        // full, this is the ordinary least squares fit.
+        let alpha = (1.0 / self.n_frames as f64).max(1.0 / window);
+        let dx = x - self.mean_x;
+        let dy = y - self.mean_y;
+        self.mean_x += alpha * dx;
+        self.mean_y += alpha * dy;
+        self.cov_xx = (1.0 - alpha) * (self.cov_xx + alpha * dx * dx);
+        self.cov_xy = (1.0 - alpha) * (self.cov_xy + alpha * dx * dy);
+    }
+}
+
+/// Computes synchronized frame numbers from host timestamps.
+///
+/// Synchronized frame 0 is at host time `t0` and frames are `1/fps` apart.
+pub struct SoftwareSync {
+    fps: f64,
+    t0: f64,
+    /// The number of frames in the fit window.
+    window: f64,
+    /// The number of frames fit before frame numbers are assigned.
+    min_frames: u64,
+    cams: Mutex<BTreeMap<RosCamName, CameraClockFit>>,
+}
+
+impl SoftwareSync {
+    pub fn new(cfg: &SoftwareSyncConfig, t0: f64) -> Self {
+        Self {
+            fps: cfg.fps,
+            t0,
+            window: (cfg.fit_window_sec * cfg.fps).max(1.0),
+            min_frames: (cfg.fps.ceil() as u64).max(10),
+            cams: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Forget the models of all cameras.
+    pub fn reset(&self) {
+        self.cams.lock().clear();
+    }
+
+    /// Compute the synchronized frame number of a packet.
+    ///
+    /// Returns `None` while the model of the camera is not yet established,
+    /// if the frame maps to the same synchronized frame as an earlier frame or
+    /// if the frame number is invalid.
+    pub fn synced_frame(&self, packet: &FlydraRawUdpPacket) -> Option<SyncFno> {
+        if packet.framenumber < 0 {
+            warn!(
+                "camera {} sent invalid frame number {}",
+                packet.cam_name, packet.framenumber
+            );
+            return None;
+        }
+        let frame = packet.framenumber as u64;
+        let time = packet.cam_received_time.as_f64();
+
+        let mut cams = self.cams.lock();
+        let ros_cam_name = RosCamName::new(packet.cam_name.clone());
+        let fit = cams
+            .entry(ros_cam_name.clone())
+            .or_insert_with(|| CameraClockFit::new(frame, time));
+
+        let x = frame as f64 - fit.frame_ref as f64;
+        let y = time - fit.time_ref;
+
+        if fit.n_frames >= self.min_frames {
+            let predicted = fit.predict(x).unwrap();
+            if (y - predicted).abs() > 0.5 / self.fps {
+                // More than half a frame off. Ignore this timestamp, unless
+                // the model no longer describes the camera.
+                fit.n_outliers += 1;
+                if fit.n_outliers as f64 > self.fps {
+                    warn!(
+                        "camera {} no longer matches its clock model. Restarting software \
+                        synchronization of this camera.",
+                        ros_cam_name
+                    );
+                    *fit = CameraClockFit::new(frame, time);
+                    fit.update(0.0, 0.0, self.window);
+                    return None;
+                }
+            } else {
+                fit.n_outliers = 0;
+                fit.update(x, y, self.window);
+            }
+        } else {
+            fit.update(x, y, self.window);
+            if fit.n_frames == self.min_frames {
+                if let Some(slope) = fit.slope() {
+                    info!(
+                        "camera {} software synchronized: {:.3} fps, jitter {:.2} msec",
+                        ros_cam_name,
+                        1.0 / slope,
+                        fit.mean_sq_resid.sqrt() * 1000.0
+                    );
+                }
+            }
+            return None;
+        }
+
+        let fit_time = fit.time_ref - self.t0 + fit.predict(x)?;
+        let synced_frame = (fit_time * self.fps).round();
+        if synced_frame < 0.0 {
+            return None;
+        }
+        let synced_frame = synced_frame as u64;
+        if let Some(last) = fit.last_synced_frame {
+            if synced_frame <= last {
+                // The camera is faster than `fps` or this frame arrived late.
+                debug!(
+                    "camera {} frame {} maps to already used synchronized frame {}",
+                    ros_cam_name, frame, synced_frame
+                );
+                return None;
+            }
+        }
+        fit.last_synced_frame = Some(synced_frame);
+        Some(SyncFno(synced_frame))
+    }
+
+    /// The current model of a camera.
+    pub fn camera_stats(&self, ros_cam_name: &RosCamName) -> Option<CameraSyncStats> {
+        let cams = self.cams.lock();
+        let fit = cams.get(ros_cam_name)?;
+        if fit.n_frames < self.min_frames {
+            return None;
+        }
+        Some(CameraSyncStats {
+            fps: 1.0 / fit.slope()?,
+            jitter: fit.mean_sq_resid.sqrt(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use flydra_types::{FlydraFloatTimestampLocal, ImageProcessingSteps};
+
+    fn packet(cam_name: &str, framenumber: i32, time: f64) -> FlydraRawUdpPacket {
+        FlydraRawUdpPacket {
+            cam_name: cam_name.to_string(),
+            timestamp: None,
+            cam_received_time: FlydraFloatTimestampLocal::from_f64(time),
+            framenumber,
+            n_frames_skipped: 0,
+            done_camnode_processing: 0.0,
+            preprocess_stamp: 0.0,
+            image_processing_steps: ImageProcessingSteps::empty(),
+            points: vec![],
+            apriltags: vec![],
+            seq: None,
+        }
+    }
+
+    #[test]
+    fn drifting_cameras() {
+        let fps = 100.0;
+        let t0 = 1_600_000_000.0;
+        let cfg = SoftwareSyncConfig {
+            fps,
+            fit_window_sec: 10.0,
+        };
+        let sync = SoftwareSync::new(&cfg, t0);
+
+        // Two cameras with different frame numbers, phases and clock rates,
+        // and with timestamp jitter of up to 2 msec.
+        let cams = [("cam1", 1234, 0.0021, 1.0001), ("cam2", 7, 0.0025, 0.9999)];
+        let mut synced = vec![vec![]; cams.len()];
+        for i in 0..2000 {
+            for (j, (name, frame0, phase, rate)) in cams.iter().enumerate() {
+                let jitter = 0.002 * (((i * 7919 + j * 104729) % 1000) as f64 / 1000.0 - 0.5);
+                let time = t0 + phase + i as f64 / (fps * rate) + jitter;
+                if let Some(fno) = sync.synced_frame(&packet(name, frame0 + i as i32, time)) {
+                    synced[j].push((i, fno.0));
+                }
+            }
+        }
+
+        for (j, (name, _, phase, rate)) in cams.iter().enumerate() {
+            let stats = sync
+                .camera_stats(&RosCamName::new(name.to_string()))
+                .unwrap();
+            assert!((stats.fps - fps * rate).abs() < 0.01);
+            assert!(stats.jitter < 0.001);
+
+            // Frame numbers are strictly increasing and match the true
+            // acquisition time.
+            assert!(synced[j].len() > 1800);
+            for w in synced[j].windows(2) {
+                assert!(w[0].1 < w[1].1);
+            }
+            for (i, fno) in synced[j].iter() {
+                let true_time = phase + *i as f64 / (fps * rate);
+                assert_eq!(*fno, (true_time * fps).round() as u64);
+            }
+        }
+    }
+
+    #[test]
+    fn negative_frame_number() {
+        let cfg = SoftwareSyncConfig::default();
+        let sync = SoftwareSync::new(&cfg, 0.0);
+        assert_eq!(sync.synced_frame(&packet("cam1", -1, 1.0)), None);
+        assert!(sync
+            .camera_stats(&RosCamName::new("cam1".to_string()))
+            .is_none());
+    }
+}
@@ -1,4 +1,4 @@
-use log::{log_enabled, trace, Level::Trace};
+use log::{log_enabled, trace, warn, Level::Trace};
 use std::{collections::BTreeMap, sync::Arc};
 
 use nalgebra::core::dimension::{U2, U6};
@@ -8,21 +8,21 @@ use nalgebra_mvn::MultivariateNormal;
 
 use pretty_print_nalgebra::pretty_print;
 
-use tracking::motion_model_3d_fixed_dt::{MotionModel3D, MotionModel3DFixedDt};
+use tracking::data_association::{
+    gnn_assignment, greedy_assignment, jpda_consumed_observations, jpda_probabilities,
+};
+use tracking::motion_model_registry::{
+    MotionModelEstimate, MotionModelFixedDt, RegisteredMotionModel3D, IMM,
+};
 
-#[cfg(feature = "flat-3d")]
-use tracking::flat_motion_model_3d::FlatZZero3DModel;
-#[cfg(feature = "full-3d")]
-use tracking::motion_model_3d::ConstantVelocity3DModel;
 #[cfg(not(any(feature = "full-3d", feature = "flat-3d")))]
 compile_error!("must either have feature full-3d or flat-3d");
 
 use adskalman::ObservationModel as ObservationModelTrait;
-use adskalman::{StateAndCovariance, TransitionModelLinearNoControl};
+use adskalman::StateAndCovariance;
 
 use flydra_types::{
-    CamNum, FlydraFloatTimestampLocal, FlydraRawUdpPoint, KalmanEstimatesRow, RosCamName, SyncFno,
-    Triggerbox,
+    CamNum, DataAssociationMethod, FlydraRawUdpPoint, KalmanEstimatesRow, RosCamName, SyncFno,
 };
 
 use crate::{
@@ -54,7 +54,7 @@ struct ModelFrameDone {}
 /// motion model has updated prior
 #[derive(Debug)]
 struct ModelFrameStarted {
-    prior: StateAndCovariance<MyFloat, U6>,
+    prior: MotionModelEstimate<MyFloat>,
 }
 
 #[derive(Debug)]
@@ -77,7 +77,7 @@ struct ModelFrameWithObservationLikes {
     /// Vec with one element per camera.
     obs_models_and_likelihoods: Vec<ObservationModel>,
     /// The estimate prior to update from observation.
-    prior: StateAndCovariance<MyFloat, U6>,
+    prior: MotionModelEstimate<MyFloat>,
 }
 
 #[derive(Debug)]
@@ -98,7 +98,7 @@ struct ModelFramePosteriors {
 
 impl ModelFramePosteriors {
     fn covariance_size(&self) -> MyFloat {
-        covariance_size(self.posterior.estimate.covariance())
+        covariance_size(self.posterior.estimate.position_velocity().covariance())
     }
 }
 
@@ -128,12 +128,15 @@ struct LivingModel<S: ModelState> {
     posteriors: Vec<StampedEstimate>,
     /// The number of frames (since start_frame) that an observation was made.
     last_observation_offset: usize,
+    /// The number of frames (since start_frame) of the first estimate saved
+    /// to disk. This is `None` until the model becomes visible.
+    first_saved_offset: Option<usize>,
     lmi: LMInner,
 }
 
 #[derive(Debug, Clone)]
 struct StampedEstimate {
-    estimate: StateAndCovariance<MyFloat, U6>,
+    estimate: MotionModelEstimate<MyFloat>,
     tdpt: TimeDataPassthrough,
 }
 
@@ -142,11 +145,6 @@ impl StampedEstimate {
     fn frame(&self) -> SyncFno {
         self.tdpt.synced_frame()
     }
-
-    #[inline]
-    fn trigger_timestamp(&self) -> Option<FlydraFloatTimestampLocal<Triggerbox>> {
-        self.tdpt.trigger_timestamp()
-    }
 }
 
 /// Inner data for `LivingModel`
@@ -171,7 +169,7 @@ impl LivingModel<ModelFrameStarted> {
     ) {
         use adskalman::ObservationModel;
 
-        let prior = &self.state.prior;
+        let prior = self.state.prior.position_velocity();
 
         // TODO: update to handle water here. See tag "laksdfjasl".
         let undistorted = camera.project_3d_to_pixel(&to_world_point(prior.state()));
@@ -186,8 +184,8 @@ impl LivingModel<ModelFrameStarted> {
 
         //  - compute expected observation through `frame_data.camera` given prior
         let projected_covariance = {
-            let h = obs_model.H();
-            let ht = obs_model.HT();
+            let h: &OMatrix<MyFloat, U2, U6> = obs_model.H();
+            let ht: &OMatrix<MyFloat, U6, U2> = obs_model.HT();
             let p = prior.covariance();
             (h * p) * ht
         };
@@ -290,6 +288,7 @@ impl LivingModel<ModelFrameStarted> {
             },
             posteriors: self.posteriors,
             last_observation_offset: self.last_observation_offset,
+            first_saved_offset: self.first_saved_offset,
             lmi: self.lmi,
         }
     }
@@ -297,13 +296,25 @@ impl LivingModel<ModelFrameStarted> {
 
 #[inline]
 fn get_kalman_estimates_row(obj_id: u32, posterior: &StampedEstimate) -> KalmanEstimatesRow {
-    let state = posterior.estimate.state();
-    let p = posterior.estimate.covariance();
-    let timestamp = posterior.trigger_timestamp().clone();
+    to_kalman_estimates_row(
+        obj_id,
+        &posterior.tdpt,
+        &posterior.estimate.position_velocity(),
+    )
+}
+
+fn to_kalman_estimates_row(
+    obj_id: u32,
+    tdpt: &TimeDataPassthrough,
+    estimate: &StateAndCovariance<MyFloat, U6>,
+) -> KalmanEstimatesRow {
+    let state = estimate.state();
+    let p = estimate.covariance();
+    let timestamp = tdpt.trigger_timestamp();
 
     KalmanEstimatesRow {
         obj_id,
-        frame: posterior.frame().clone(),
+        frame: tdpt.synced_frame(),
         timestamp,
         x: state[0],
         y: state[1],
@@ -323,6 +334,51 @@ fn get_kalman_estimates_row(obj_id: u32, posterior: &StampedEstimate) -> KalmanE
     }
 }
 
+impl<S: ModelState> LivingModel<S> {
+    /// Smooth all saved estimates of this model and send them to be saved.
+    ///
+    /// This should be called once the model will receive no further
+    /// observations. The smoothed rows cover the same frames as saved in the
+    /// forward estimates: from the first saved frame until the last frame with
+    /// an observation.
+    ///
+    /// Smoothing is only implemented for a single motion model, not for a
+    /// mixture of several models. This is checked when smoothing is enabled,
+    /// see [crate::CoordProcessor::set_smooth_kalman_estimates].
+    fn save_smoothed(
+        &self,
+        motion_model: &MotionModelFixedDt<MyFloat>,
+        save_data_tx: &mut channellib::Sender<SaveToDiskMsg>,
+    ) {
+        let first = match self.first_saved_offset {
+            Some(first) => first,
+            None => {
+                // Never became visible, so nothing was saved.
+                return;
+            }
+        };
+        let saved = &self.posteriors[first..=self.last_observation_offset];
+        let filtered: Vec<_> = saved.iter().map(|p| p.estimate.clone()).collect();
+        match motion_model.rts_smooth(&filtered) {
+            Some(smoothed) => {
+                let rows = saved
+                    .iter()
+                    .zip(smoothed.iter())
+                    .map(|(posterior, estimate)| {
+                        to_kalman_estimates_row(self.lmi.obj_id, &posterior.tdpt, estimate)
+                    })
+                    .collect();
+                save_data_tx
+                    .send(SaveToDiskMsg::SmoothedKalmanEstimates(rows))
+                    .cb_ok();
+            }
+            None => {
+                warn!("could not smooth trajectory of object {}", self.lmi.obj_id);
+            }
+        }
+    }
+}
+
 impl LivingModel<ModelFramePosteriors> {
     fn finish_frame(
         mut self,
@@ -420,6 +476,9 @@ impl LivingModel<ModelFramePosteriors> {
                 // Calculate backlog of posterior estimates not yet saved to disk.
                 let start_idx = self.last_observation_offset + 1;
                 let end_idx = self.posteriors.len();
+                if self.first_saved_offset.is_none() {
+                    self.first_saved_offset = Some(std::cmp::min(start_idx, end_idx));
+                }
                 for idx in start_idx..end_idx {
                     let posterior = &self.posteriors[idx];
 
@@ -475,6 +534,7 @@ impl LivingModel<ModelFramePosteriors> {
             state: ModelFrameDone {},
             posteriors,
             last_observation_offset: self.last_observation_offset,
+            first_saved_offset: self.first_saved_offset,
             lmi: self.lmi,
         }
     }
@@ -502,25 +562,51 @@ impl CollectionState for CollectionFrameStarted {}
 impl CollectionState for CollectionFrameWithObservationLikes {}
 impl CollectionState for CollectionFramePosteriors {}
 
+/// Create the motion model selected in the tracking parameters.
+pub(crate) fn motion_model_from_params(
+    params: &SwitchingTrackingParams,
+) -> crate::Result<RegisteredMotionModel3D<MyFloat>> {
+    if params.motion_model == IMM {
+        let imm_params = params
+            .imm_params
+            .as_ref()
+            .ok_or(tracking::motion_model_registry::MotionModelError::ImmModelsRequired)?;
+        let models = imm_params
+            .models
+            .iter()
+            .map(|m| RegisteredMotionModel3D::new(&m.motion_model, m.motion_noise_scale))
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        Ok(RegisteredMotionModel3D::new_imm(
+            models,
+            imm_params.switch_probability,
+        )?)
+    } else {
+        if params.imm_params.is_some() {
+            warn!(
+                "ignoring IMM parameters for motion model \"{}\"",
+                params.motion_model
+            );
+        }
+        Ok(RegisteredMotionModel3D::new(
+            &params.motion_model,
+            params.motion_noise_scale,
+        )?)
+    }
+}
+
 pub(crate) fn initialize_model_collection(
     params: Arc<SwitchingTrackingParams>,
+    motion_model: &RegisteredMotionModel3D<MyFloat>,
     recon: flydra_mvg::FlydraMultiCameraSystem<MyFloat>,
     fps: f32,
     cam_manager: ConnectedCamerasManager,
     save_data_tx: channellib::Sender<SaveToDiskMsg>,
+    smooth: bool,
 ) -> ModelCollection<CollectionFrameDone> {
     let new_obj = NewObjectTest::new(recon.clone(), params.clone());
 
-    let motion_noise_scale = params.motion_noise_scale;
-
-    #[cfg(feature = "full-3d")]
-    let motion_model_generator = ConstantVelocity3DModel::new(motion_noise_scale);
-
-    #[cfg(feature = "flat-3d")]
-    let motion_model_generator = FlatZZero3DModel::new(motion_noise_scale);
-
     let dt = 1.0 / fps as f64;
-    let motion_model = motion_model_generator.calc_for_dt(dt);
+    let motion_model = motion_model.calc_for_dt(dt);
 
     ModelCollection {
         state: CollectionFrameDone { models: vec![] },
@@ -532,6 +618,7 @@ pub(crate) fn initialize_model_collection(
             cam_manager,
             next_obj_id: 0,
             save_data_tx,
+            smooth,
             // model_sender,
         },
     }
@@ -546,13 +633,24 @@ pub(crate) struct MCInner {
     params: Arc<SwitchingTrackingParams>,
     pub(crate) recon: flydra_mvg::FlydraMultiCameraSystem<MyFloat>,
     new_obj: NewObjectTest,
-    motion_model: MotionModel3DFixedDt<MyFloat>,
+    motion_model: MotionModelFixedDt<MyFloat>,
     cam_manager: ConnectedCamerasManager,
     next_obj_id: u32,
     save_data_tx: channellib::Sender<SaveToDiskMsg>,
+    /// Whether to save smoothed estimates when a trajectory ends.
+    smooth: bool,
 }
 
 impl ModelCollection<CollectionFrameDone> {
+    /// End all live trajectories (e.g. because there is no further data).
+    pub(crate) fn finish_all(mut self) {
+        if self.mcinner.smooth {
+            for model in self.state.models.iter() {
+                model.save_smoothed(&self.mcinner.motion_model, &mut self.mcinner.save_data_tx);
+            }
+        }
+    }
+
     pub(crate) fn predict_motion(self) -> ModelCollection<CollectionFrameStarted> {
         let mcinner = self.mcinner;
         let models = self
@@ -567,6 +665,7 @@ impl ModelCollection<CollectionFrameDone> {
                     state: ModelFrameStarted { prior },
                     posteriors: x.posteriors,
                     last_observation_offset: x.last_observation_offset,
+                    first_saved_offset: x.first_saved_offset,
                     lmi: x.lmi,
                 }
             })
@@ -614,11 +713,9 @@ impl ModelCollection<CollectionFrameWithObservationLikes> {
     pub(crate) fn solve_data_association_and_update(
         self,
     ) -> (ModelCollection<CollectionFramePosteriors>, UnusedData) {
-        let zero = nalgebra::convert(0.0);
-
         // We have likelihoods for all objects on all cameras for each point.
         //
-        // Do something like the hungarian algorithm.
+        // Associate them according to `TrackingParams::data_association`.
 
         if self.state.models_with_obs_likes.len() == 0 {
             // Short-circuit stuff below when no data.
@@ -661,6 +758,7 @@ impl ModelCollection<CollectionFrameWithObservationLikes> {
                         },
                         posteriors: model.posteriors.clone(),
                         last_observation_offset: model.last_observation_offset.clone(),
+                        first_saved_offset: model.first_saved_offset,
                         lmi: model.lmi.clone(),
                     }
                 })
@@ -721,7 +819,7 @@ impl ModelCollection<CollectionFrameWithObservationLikes> {
 
                 // debug!("wantedness1 {:?}", wantedness);
 
-                let mut wantedness =
+                let wantedness =
                     nalgebra::OMatrix::<f64, nalgebra::Dynamic, nalgebra::Dynamic>::from_rows(
                         wantedness.as_slice(),
                     );
@@ -740,89 +838,147 @@ impl ModelCollection<CollectionFrameWithObservationLikes> {
                 let mut unused_col_idxs =
                     std::collections::BTreeSet::from_iter(0..wantedness.ncols());
 
+                let association = compute_association(
+                    &wantedness,
+                    self.mcinner.params.data_association,
+                    self.mcinner.params.accept_observation_min_likelihood,
+                );
+
+                // Points used by the models can no longer be used for new
+                // models. With JPDA, this includes all points with a non-zero
+                // association probability, as these update the models.
+                let used_col_idxs = match &association {
+                    Association::Assigned(assignment) => {
+                        assignment.iter().flatten().copied().collect()
+                    }
+                    Association::Probabilities(probs) => jpda_consumed_observations(probs),
+                };
+                for col_idx in used_col_idxs.iter() {
+                    unused_col_idxs.remove(col_idx);
+                }
+
                 // Iterate over the models
                 for (row_idx, next_model) in models_with_posteriors.iter_mut().enumerate() {
-                    // Each incoming point can only be assigned to a single
-                    // model, so iterate over columns and select the best row.
-                    // Also, each model can only get a single observation (from
-                    // this camera).
-                    let likelihoods = wantedness.row(row_idx); // extract likelihood for all points
-                    let best_col = arg_max_col(&likelihoods.iter().map(|x| *x).collect()); // select best point
-                    trace!("row_idx {}, best_col {:?}", row_idx, best_col);
-
-                    if let Some((best_idx, best_wantedness)) = best_col {
-                        if best_wantedness > self.mcinner.params.accept_observation_min_likelihood {
-                            // don't take unwanted point
-                            unused_col_idxs.remove(&best_idx);
-
-                            // this point can no longer be used for other models
-                            for tmp_i in 0..wantedness.nrows() {
-                                wantedness[(tmp_i, best_idx)] = zero;
-                            }
+                    // The points used by this model, with their association
+                    // probability. Each model can only get a single
+                    // observation (from this camera), but with JPDA this
+                    // observation is a weighted mixture of several points.
+                    let weighted_cols: Vec<(usize, f64)> = match &association {
+                        Association::Assigned(assignment) => assignment[row_idx]
+                            .map(|col_idx| (col_idx, 1.0))
+                            .into_iter()
+                            .collect(),
+                        Association::Probabilities(probs) => (0..probs.ncols())
+                            .filter(|col_idx| probs[(row_idx, *col_idx)] > 0.0)
+                            .map(|col_idx| (col_idx, probs[(row_idx, col_idx)]))
+                            .collect(),
+                    };
+                    trace!("row_idx {}, weighted_cols {:?}", row_idx, weighted_cols);
 
-                            let undist_pt = &frame_cam_points.undistorted[best_idx];
-                            trace!(
-                                "object {} is accepting undistorted point {:?}",
-                                next_model.lmi.obj_id,
-                                undist_pt
-                            );
-
-                            let observation_undistorted =
-                                OVector::<_, U2>::new(undist_pt.x, undist_pt.y);
-
-                            let model = &self.state.models_with_obs_likes[row_idx];
-                            let obs_model = match &model.state.obs_models_and_likelihoods[cam_idx] {
-                                ObservationModel::ObservationModelAndLikelihoods(oml) => {
-                                    &oml.observation_model
-                                }
-                                ObservationModel::NoObservations => {
-                                    // This should never happen.
-                                    panic!("non-zero wantedness for non-existent observation.");
-                                }
-                            };
-
-                            let estimate = &next_model.state.posterior;
-
-                            let form = adskalman::CovarianceUpdateMethod::JosephForm;
-                            let posterior = obs_model
-                                .update(&estimate.estimate, &observation_undistorted, form)
-                                .map_err(|e| {
-                                    format!(
-                                        "While computing posterior for frame {}, camera {}: {}.",
-                                        frame_cam_points.frame_data.synced_frame,
-                                        frame_cam_points.frame_data.cam_name,
-                                        e
-                                    )
-                                })
-                                .unwrap();
-
-                            trace!("previous estimate {:?}", estimate.estimate.state());
-                            trace!(" updated estimate {:?}", posterior.state());
-
-                            // Compute the coords of the estimated state.
-                            let reproj_undistorted =
-                                obs_model.predict_observation(posterior.state());
-                            let reproj_dist = ((reproj_undistorted.x - undist_pt.x).powi(2)
-                                + (reproj_undistorted.y - undist_pt.y).powi(2))
-                            .sqrt();
-
-                            next_model.state.posterior.estimate = posterior;
-                            let assoc = DataAssocInfo {
-                                pt_idx: undist_pt.idx,
-                                cam_num,
-                                reproj_dist,
-                            };
-
-                            trace!(
-                                "object {} at frame {} using: {:?}",
-                                next_model.lmi.obj_id,
-                                bundle.frame().0,
-                                assoc
-                            );
-
-                            next_model.state.data_assoc_this_timestamp.push(assoc);
+                    if weighted_cols.is_empty() {
+                        continue;
+                    }
+
+                    let model = &self.state.models_with_obs_likes[row_idx];
+                    let obs_model = match &model.state.obs_models_and_likelihoods[cam_idx] {
+                        ObservationModel::ObservationModelAndLikelihoods(oml) => {
+                            &oml.observation_model
+                        }
+                        ObservationModel::NoObservations => {
+                            // This should never happen.
+                            panic!("non-zero wantedness for non-existent observation.");
                         }
+                    };
+
+                    let estimate = &next_model.state.posterior;
+
+                    // The probability that none of the points is from this object.
+                    let miss_probability = weighted_cols
+                        .iter()
+                        .fold(1.0, |acc: f64, (_, weight)| acc - weight)
+                        .max(0.0);
+                    let mut components = Vec::with_capacity(weighted_cols.len() + 1);
+                    if miss_probability > 0.0 {
+                        components.push((miss_probability, estimate.estimate.clone()));
+                    }
+
+                    for (col_idx, weight) in weighted_cols.iter() {
+                        let undist_pt = &frame_cam_points.undistorted[*col_idx];
+                        trace!(
+                            "object {} is accepting undistorted point {:?} with weight {}",
+                            next_model.lmi.obj_id,
+                            undist_pt,
+                            weight
+                        );
+
+                        let observation_undistorted =
+                            OVector::<_, U2>::new(undist_pt.x, undist_pt.y);
+
+                        let posterior = estimate
+                            .estimate
+                            .update(obs_model, &observation_undistorted)
+                            .map_err(|e| {
+                                format!(
+                                    "While computing posterior for frame {}, camera {}: {}.",
+                                    frame_cam_points.frame_data.synced_frame,
+                                    frame_cam_points.frame_data.cam_name,
+                                    e
+                                )
+                            })
+                            .unwrap();
+                        components.push((*weight, posterior));
+                    }
+
+                    let posterior = if components.len() == 1 {
+                        components.pop().unwrap().1
+                    } else {
+                        MotionModelEstimate::mixture(&components)
+                    };
+
+                    let combined = posterior.position_velocity();
+                    trace!(
+                        "previous estimate {:?}",
+                        estimate.estimate.position_velocity().state()
+                    );
+                    trace!(" updated estimate {:?}", combined.state());
+
+                    next_model.state.posterior.estimate = posterior;
+
+                    // Record the most probable point, unless it is more
+                    // probable that none of the points is from this object.
+                    let (best_idx, best_weight) =
+                        weighted_cols.iter().fold(weighted_cols[0], |best, this| {
+                            if this.1 > best.1 {
+                                *this
+                            } else {
+                                best
+                            }
+                        });
+                    if best_weight <= miss_probability {
+                        continue;
                     }
+                    let undist_pt = &frame_cam_points.undistorted[best_idx];
+
+                    // Compute the coords of the estimated state.
+                    let reproj_undistorted = obs_model.predict_observation(combined.state());
+                    let reproj_dist = ((reproj_undistorted.x - undist_pt.x).powi(2)
+                        + (reproj_undistorted.y - undist_pt.y).powi(2))
+                    .sqrt();
+
+                    let assoc = DataAssocInfo {
+                        pt_idx: undist_pt.idx,
+                        cam_num,
+                        reproj_dist,
+                    };
+
+                    trace!(
+                        "object {} at frame {} using: {:?}",
+                        next_model.lmi.obj_id,
+                        bundle.frame().0,
+                        assoc
+                    );
+
+                    next_model.state.data_assoc_this_timestamp.push(assoc);
                 }
 
                 // we will fill this point-by-point
@@ -833,6 +989,7 @@ impl ModelCollection<CollectionFrameWithObservationLikes> {
                 let mut orig = FrameDataAndPoints {
                     frame_data: fdp.frame_data.clone(),
                     points: vec![],
+                    apriltags: vec![],
                 };
 
                 for col_idx in unused_col_idxs.into_iter() {
@@ -858,21 +1015,39 @@ impl ModelCollection<CollectionFrameWithObservationLikes> {
     }
 }
 
-fn arg_max_col(a: &Vec<f64>) -> Option<(usize, f64)> {
-    let mut r = None;
-    for (i, val) in a.iter().enumerate() {
-        r = match r {
-            None => Some((i, *val)),
-            Some(testr) => {
-                if *val > testr.1 {
-                    Some((i, *val))
-                } else {
-                    Some(testr)
-                }
+/// The association of the points of one camera with the models
+enum Association {
+    /// The point assigned to each model, if any.
+    Assigned(Vec<Option<usize>>),
+    /// The probability of each point (column) belonging to each model (row).
+    Probabilities(nalgebra::DMatrix<f64>),
+}
+
+fn compute_association(
+    wantedness: &nalgebra::DMatrix<f64>,
+    method: DataAssociationMethod,
+    min_likelihood: f64,
+) -> Association {
+    match method {
+        DataAssociationMethod::Greedy => {
+            Association::Assigned(greedy_assignment(wantedness, min_likelihood))
+        }
+        DataAssociationMethod::Gnn => {
+            Association::Assigned(gnn_assignment(wantedness, min_likelihood))
+        }
+        DataAssociationMethod::Jpda => match jpda_probabilities(wantedness, min_likelihood) {
+            Some(probs) => Association::Probabilities(probs),
+            None => {
+                warn!(
+                    "too many joint association events for {} objects and {} points, \
+                    using global nearest neighbour association",
+                    wantedness.nrows(),
+                    wantedness.ncols()
+                );
+                Association::Assigned(gnn_assignment(wantedness, min_likelihood))
             }
-        };
+        },
     }
-    r
 }
 
 fn to_bayesian_estimate(
@@ -991,7 +1166,10 @@ impl ModelCollection<CollectionFramePosteriors> {
                     })
                     .collect();
 
-                let estimate = to_bayesian_estimate(coords, &self.mcinner.params);
+                let estimate = self
+                    .mcinner
+                    .motion_model
+                    .initial_estimate(to_bayesian_estimate(coords, &self.mcinner.params));
 
                 let obj_id = self.next_obj_id();
                 trace!(
@@ -1012,6 +1190,7 @@ impl ModelCollection<CollectionFramePosteriors> {
                     },
                     posteriors: vec![],
                     last_observation_offset: 0,
+                    first_saved_offset: None,
                     lmi: LMInner {
                         obj_id,
                         start_frame: unused.0.tdpt.frame,
@@ -1024,6 +1203,12 @@ impl ModelCollection<CollectionFramePosteriors> {
             }
         }
 
+        if self.mcinner.smooth {
+            for model in &to_kill {
+                model.save_smoothed(&self.mcinner.motion_model, &mut self.mcinner.save_data_tx);
+            }
+        }
+
         if to_kill.len() > 0 {
             for ms in model_servers.iter() {
                 for model in &to_kill {
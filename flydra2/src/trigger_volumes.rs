@@ -0,0 +1,315 @@
+//! Trigger volumes: regions of 3D space which emit events when tracked objects
+//! enter or leave them.
+
+use std::collections::BTreeSet;
+
+use log::debug;
+use nalgebra::{Point3, Vector3};
+use parking_lot::Mutex;
+
+use crossbeam_ok::CrossbeamOk;
+use flydra_types::{
+    TriggerVolumeConfig, TriggerVolumeEventKind, TriggerVolumeEventRow, TriggerVolumeShape,
+    TriggerVolumeStatus,
+};
+
+use crate::model_server::{GetsUpdates, SendType, TriggerVolumeObject};
+use crate::{file_error, Result, SaveToDiskMsg, TimeDataPassthrough};
+
+/// Receives the events of [TriggerVolumes].
+pub trait TriggerVolumeCallback: Send {
+    /// Called with the events caused by a single update. `status` holds the
+    /// objects within each volume after these events.
+    fn on_trigger_volume_events(
+        &self,
+        events: &[TriggerVolumeEventRow],
+        status: &[TriggerVolumeStatus],
+    );
+}
+
+enum Geometry {
+    Box {
+        min: Point3<f64>,
+        max: Point3<f64>,
+    },
+    Cylinder {
+        base_center: Point3<f64>,
+        radius: f64,
+        height: f64,
+    },
+    /// The triangles of closed meshes.
+    Mesh(Vec<[Point3<f64>; 3]>),
+}
+
+fn to_point3(x: &[f64; 3]) -> Point3<f64> {
+    Point3::new(x[0], x[1], x[2])
+}
+
+impl Geometry {
+    fn new(shape: &TriggerVolumeShape) -> Result<Self> {
+        let geom = match shape {
+            TriggerVolumeShape::Box { min, max } => Geometry::Box {
+                min: to_point3(min),
+                max: to_point3(max),
+            },
+            TriggerVolumeShape::Cylinder {
+                base_center,
+                radius,
+                height,
+            } => Geometry::Cylinder {
+                base_center: to_point3(base_center),
+                radius: *radius,
+                height: *height,
+            },
+            TriggerVolumeShape::Mesh { filename } => {
+                let fname = filename.display().to_string();
+                let buf = std::fs::read(filename)
+                    .map_err(|e| file_error("trigger volume mesh", fname.clone(), e))?;
+                // Only the vertices and faces are used, so texture
+                // coordinates are not required.
+                let objects = simple_obj_parse::obj_parse_triangles(&buf)
+                    .map_err(|e| file_error("trigger volume mesh", fname, e))?;
+                let triangles = objects
+                    .into_iter()
+                    .flat_map(|(_name, triangles)| triangles.into_iter())
+                    .collect();
+                Geometry::Mesh(triangles)
+            }
+        };
+        Ok(geom)
+    }
+
+    fn contains(&self, pt: &Point3<f64>) -> bool {
+        match self {
+            Geometry::Box { min, max } => (0..3).all(|i| min[i] <= pt[i] && pt[i] <= max[i]),
+            Geometry::Cylinder {
+                base_center,
+                radius,
+                height,
+            } => {
+                let d = pt - base_center;
+                0.0 <= d.z && d.z <= *height && d.x * d.x + d.y * d.y <= radius * radius
+            }
+            Geometry::Mesh(triangles) => {
+                // A point is inside a closed mesh if a ray starting at the
+                // point crosses the surface an odd number of times. The
+                // direction is chosen to be unlikely to exactly hit an edge.
+                let dir = Vector3::new(0.5773, 0.5774, 0.5776);
+                let n_crossings = triangles
+                    .iter()
+                    .filter(|tri| ray_hits_triangle(pt, &dir, tri))
+                    .count();
+                n_crossings % 2 == 1
+            }
+        }
+    }
+}
+
+/// Möller-Trumbore ray-triangle intersection.
+fn ray_hits_triangle(origin: &Point3<f64>, dir: &Vector3<f64>, tri: &[Point3<f64>; 3]) -> bool {
+    let e1 = tri[1] - tri[0];
+    let e2 = tri[2] - tri[0];
+    let p = dir.cross(&e2);
+    let det = e1.dot(&p);
+    if det.abs() < 1e-12 {
+        // ray parallel to triangle
+        return false;
+    }
+    let s = origin - tri[0];
+    let u = s.dot(&p) / det;
+    if u < 0.0 || u > 1.0 {
+        return false;
+    }
+    let q = s.cross(&e1);
+    let v = dir.dot(&q) / det;
+    if v < 0.0 || u + v > 1.0 {
+        return false;
+    }
+    e2.dot(&q) / det > 0.0
+}
+
+struct Volume {
+    name: String,
+    geometry: Geometry,
+    /// The objects currently inside.
+    obj_ids: BTreeSet<u32>,
+}
+
+impl Volume {
+    fn status(&self) -> TriggerVolumeStatus {
+        TriggerVolumeStatus {
+            name: self.name.clone(),
+            obj_ids: self.obj_ids.iter().cloned().collect(),
+        }
+    }
+}
+
+/// Emits events when tracked objects enter or leave trigger volumes.
+///
+/// This is added as a listener to the `CoordProcessor`. The events are sent to
+/// the listeners of this struct (typically the model server), saved to disk and
+/// passed to the optional callback.
+pub struct TriggerVolumes {
+    volumes: Mutex<Vec<Volume>>,
+    listeners: Vec<Box<dyn GetsUpdates>>,
+    save_data_tx: channellib::Sender<SaveToDiskMsg>,
+    callback: Option<Box<dyn TriggerVolumeCallback>>,
+}
+
+impl TriggerVolumes {
+    /// Create the volumes, loading any meshes from disk.
+    pub fn new(
+        cfgs: &[TriggerVolumeConfig],
+        save_data_tx: channellib::Sender<SaveToDiskMsg>,
+        callback: Option<Box<dyn TriggerVolumeCallback>>,
+    ) -> Result<Self> {
+        let volumes = cfgs
+            .iter()
+            .map(|cfg| {
+                Ok(Volume {
+                    name: cfg.name.clone(),
+                    geometry: Geometry::new(&cfg.shape)?,
+                    obj_ids: BTreeSet::new(),
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self {
+            volumes: Mutex::new(volumes),
+            listeners: vec![],
+            save_data_tx,
+            callback,
+        })
+    }
+
+    pub fn add_listener(&mut self, listener: Box<dyn GetsUpdates>) {
+        self.listeners.push(listener);
+    }
+
+    /// The objects currently within each volume.
+    pub fn status(&self) -> Vec<TriggerVolumeStatus> {
+        self.volumes.lock().iter().map(Volume::status).collect()
+    }
+}
+
+impl GetsUpdates for TriggerVolumes {
+    fn send_update(&self, msg: SendType, tdpt: &TimeDataPassthrough) -> Result<()> {
+        use TriggerVolumeEventKind::*;
+
+        let mut events = Vec::new();
+        let status = {
+            let mut volumes = self.volumes.lock();
+            match &msg {
+                SendType::Birth(row) | SendType::Update(row) => {
+                    let pt = Point3::new(row.x, row.y, row.z);
+                    for vol in volumes.iter_mut() {
+                        let inside = vol.geometry.contains(&pt);
+                        if inside && vol.obj_ids.insert(row.obj_id) {
+                            events.push((row.obj_id, vol.name.clone(), Enter));
+                        } else if !inside && vol.obj_ids.remove(&row.obj_id) {
+                            events.push((row.obj_id, vol.name.clone(), Leave));
+                        }
+                    }
+                }
+                SendType::Death(obj_id) => {
+                    for vol in volumes.iter_mut() {
+                        if vol.obj_ids.remove(obj_id) {
+                            events.push((*obj_id, vol.name.clone(), Leave));
+                        }
+                    }
+                }
+                _ => {}
+            }
+            if events.is_empty() {
+                return Ok(());
+            }
+            volumes.iter().map(Volume::status).collect::<Vec<_>>()
+        };
+
+        let rows: Vec<TriggerVolumeEventRow> = events
+            .into_iter()
+            .map(|(obj_id, volume, event)| TriggerVolumeEventRow {
+                frame: tdpt.synced_frame(),
+                timestamp: tdpt.trigger_timestamp(),
+                obj_id,
+                volume,
+                event,
+            })
+            .collect();
+
+        for row in rows.iter() {
+            debug!(
+                "object {} trigger volume \"{}\": {:?}",
+                row.obj_id, row.volume, row.event
+            );
+            for listener in self.listeners.iter() {
+                let obj = TriggerVolumeObject {
+                    obj_id: row.obj_id,
+                    volume: row.volume.clone(),
+                };
+                let msg = match row.event {
+                    Enter => SendType::TriggerVolumeEnter(obj),
+                    Leave => SendType::TriggerVolumeLeave(obj),
+                };
+                listener.send_update(msg, tdpt)?;
+            }
+            self.save_data_tx
+                .send(SaveToDiskMsg::TriggerVolumeEvent(row.clone()))
+                .cb_ok();
+        }
+
+        if let Some(ref callback) = self.callback {
+            callback.on_trigger_volume_events(&rows, &status);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn box_and_cylinder() {
+        let bx = Geometry::Box {
+            min: Point3::new(0.0, 0.0, 0.0),
+            max: Point3::new(1.0, 2.0, 3.0),
+        };
+        assert!(bx.contains(&Point3::new(0.5, 1.5, 2.5)));
+        assert!(!bx.contains(&Point3::new(0.5, 2.5, 2.5)));
+
+        let cyl = Geometry::Cylinder {
+            base_center: Point3::new(1.0, 1.0, 0.0),
+            radius: 0.5,
+            height: 0.2,
+        };
+        assert!(cyl.contains(&Point3::new(1.4, 1.0, 0.1)));
+        assert!(!cyl.contains(&Point3::new(1.4, 1.4, 0.1)));
+        assert!(!cyl.contains(&Point3::new(1.0, 1.0, 0.3)));
+    }
+
+    #[test]
+    fn mesh_cube() {
+        // A unit cube made of 12 triangles.
+        let v: Vec<Point3<f64>> = (0..8)
+            .map(|i| Point3::new((i & 1) as f64, ((i >> 1) & 1) as f64, ((i >> 2) & 1) as f64))
+            .collect();
+        let faces = [
+            [0, 1, 3, 2],
+            [4, 5, 7, 6],
+            [0, 1, 5, 4],
+            [2, 3, 7, 6],
+            [0, 2, 6, 4],
+            [1, 3, 7, 5],
+        ];
+        let mut triangles = Vec::new();
+        for f in faces.iter() {
+            triangles.push([v[f[0]], v[f[1]], v[f[2]]]);
+            triangles.push([v[f[0]], v[f[2]], v[f[3]]]);
+        }
+        let mesh = Geometry::Mesh(triangles);
+        assert!(mesh.contains(&Point3::new(0.5, 0.5, 0.5)));
+        assert!(mesh.contains(&Point3::new(0.1, 0.9, 0.2)));
+        assert!(!mesh.contains(&Point3::new(1.5, 0.5, 0.5)));
+        assert!(!mesh.contains(&Point3::new(-0.5, 0.2, 0.4)));
+    }
+}
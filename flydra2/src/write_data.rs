@@ -18,7 +18,15 @@ struct WritingState {
     save_empty_data2d: bool,
     // kalman_estimates_wtr: Option<csv::Writer<Box<dyn std::io::Write>>>,
     kalman_estimates_wtr: Option<OrderingWriter>,
+    /// Opened upon receiving the first smoothed estimates.
+    kalman_estimates_smoothed_wtr: Option<csv::Writer<Box<dyn std::io::Write>>>,
     data_assoc_wtr: Option<csv::Writer<Box<dyn std::io::Write>>>,
+    /// Opened upon receiving the first triangulated April Tags.
+    apriltag_3d_wtr: Option<csv::Writer<Box<dyn std::io::Write>>>,
+    /// Opened upon receiving the first trigger volume event.
+    trigger_volume_events_wtr: Option<csv::Writer<Box<dyn std::io::Write>>>,
+    /// Opened upon receiving the first camera data statistics.
+    camdata_stats_wtr: Option<csv::Writer<Box<dyn std::io::Write>>>,
     data_2d_wtr: csv::Writer<Box<dyn std::io::Write>>,
     textlog_wtr: csv::Writer<Box<dyn std::io::Write>>,
     trigger_clock_info_wtr: csv::Writer<Box<dyn std::io::Write>>,
@@ -236,7 +244,11 @@ impl WritingState {
             readme_fd,
             save_empty_data2d,
             kalman_estimates_wtr,
+            kalman_estimates_smoothed_wtr: None,
             data_assoc_wtr,
+            apriltag_3d_wtr: None,
+            trigger_volume_events_wtr: None,
+            camdata_stats_wtr: None,
             data_2d_wtr,
             textlog_wtr,
             trigger_clock_info_wtr,
@@ -273,13 +285,87 @@ impl WritingState {
         Ok(())
     }
 
+    fn save_smoothed_kalman_estimates(&mut self, rows: Vec<KalmanEstimatesRow>) -> Result<()> {
+        if self.kalman_estimates_smoothed_wtr.is_none() {
+            let mut csv_path = self.output_dirname.clone();
+            csv_path.push(format!(
+                "{}.gz",
+                flydra_types::KALMAN_ESTIMATES_SMOOTHED_CSV_FNAME
+            ));
+            let fd = std::fs::File::create(&csv_path)?;
+            let fd: Box<dyn std::io::Write> = Box::new(AutoFinishUnchecked::new(Encoder::new(fd)?));
+            self.kalman_estimates_smoothed_wtr = Some(csv::Writer::from_writer(fd));
+        }
+        let wtr = self.kalman_estimates_smoothed_wtr.as_mut().unwrap();
+        for row in rows.iter() {
+            wtr.serialize(row)?;
+        }
+        Ok(())
+    }
+
+    fn save_apriltag_3d(&mut self, rows: Vec<AprilTag3dRow>) -> Result<()> {
+        if self.apriltag_3d_wtr.is_none() {
+            let mut csv_path = self.output_dirname.clone();
+            csv_path.push(format!("{}.gz", flydra_types::APRILTAG_3D_CSV_FNAME));
+            let fd = std::fs::File::create(&csv_path)?;
+            let fd: Box<dyn std::io::Write> = Box::new(AutoFinishUnchecked::new(Encoder::new(fd)?));
+            self.apriltag_3d_wtr = Some(csv::Writer::from_writer(fd));
+        }
+        let wtr = self.apriltag_3d_wtr.as_mut().unwrap();
+        for row in rows.iter() {
+            wtr.serialize(row)?;
+        }
+        Ok(())
+    }
+
+    fn save_trigger_volume_event(&mut self, row: TriggerVolumeEventRow) -> Result<()> {
+        if self.trigger_volume_events_wtr.is_none() {
+            let mut csv_path = self.output_dirname.clone();
+            csv_path.push(format!(
+                "{}.gz",
+                flydra_types::TRIGGER_VOLUME_EVENTS_CSV_FNAME
+            ));
+            let fd = std::fs::File::create(&csv_path)?;
+            let fd: Box<dyn std::io::Write> = Box::new(AutoFinishUnchecked::new(Encoder::new(fd)?));
+            self.trigger_volume_events_wtr = Some(csv::Writer::from_writer(fd));
+        }
+        let wtr = self.trigger_volume_events_wtr.as_mut().unwrap();
+        wtr.serialize(&row)?;
+        Ok(())
+    }
+
+    fn save_camdata_stats(&mut self, row: CamdataStatsRow) -> Result<()> {
+        if self.camdata_stats_wtr.is_none() {
+            let mut csv_path = self.output_dirname.clone();
+            csv_path.push(format!("{}.gz", flydra_types::CAMDATA_STATS_CSV_FNAME));
+            let fd = std::fs::File::create(&csv_path)?;
+            let fd: Box<dyn std::io::Write> = Box::new(AutoFinishUnchecked::new(Encoder::new(fd)?));
+            self.camdata_stats_wtr = Some(csv::Writer::from_writer(fd));
+        }
+        let wtr = self.camdata_stats_wtr.as_mut().unwrap();
+        wtr.serialize(&row)?;
+        Ok(())
+    }
+
     fn flush_all(&mut self) -> Result<()> {
         if let Some(ref mut kew) = self.kalman_estimates_wtr {
             kew.flush()?;
         }
+        if let Some(ref mut kesw) = self.kalman_estimates_smoothed_wtr {
+            kesw.flush()?;
+        }
         if let Some(ref mut daw) = self.data_assoc_wtr {
             daw.flush()?;
         }
+        if let Some(ref mut atw) = self.apriltag_3d_wtr {
+            atw.flush()?;
+        }
+        if let Some(ref mut tvw) = self.trigger_volume_events_wtr {
+            tvw.flush()?;
+        }
+        if let Some(ref mut csw) = self.camdata_stats_wtr {
+            csw.flush()?;
+        }
         self.data_2d_wtr.flush()?;
         self.textlog_wtr.flush()?;
         self.trigger_clock_info_wtr.flush()?;
@@ -302,7 +388,11 @@ impl Drop for WritingState {
         // Drop all CSV files, which closes them.
         {
             self.kalman_estimates_wtr.take();
+            self.kalman_estimates_smoothed_wtr.take();
             self.data_assoc_wtr.take();
+            self.apriltag_3d_wtr.take();
+            self.trigger_volume_events_wtr.take();
+            self.camdata_stats_wtr.take();
             // Could equivalently call `.flush()` on the writers?
             self.data_2d_wtr = dummy_csv();
             self.textlog_wtr = dummy_csv();
@@ -353,13 +443,6 @@ impl Drop for WritingState {
         }
 
         {
-            // TODO: read all the (forward) kalman estimates and smooth them to
-            // an additional file. If we do it here, it is done after the
-            // realtime tracking and thus does not interfere with recording
-            // data. On the other hand, if we smooth at the end of each
-            // trajectory, those smoothing costs are amortized throughout the
-            // experiment.
-
             let replace_extension = match output_dirname.extension() {
                 Some(ext) => ext == "braid",
                 None => false,
@@ -555,6 +638,30 @@ pub(crate) fn writer_thread_main(
 
                         // simply drop data if no file opened
                     }
+                    SmoothedKalmanEstimates(rows) => {
+                        if let Some(ref mut ws) = writing_state {
+                            ws.save_smoothed_kalman_estimates(rows)?;
+                        }
+                        // simply drop data if no file opened
+                    }
+                    AprilTag3d(rows) => {
+                        if let Some(ref mut ws) = writing_state {
+                            ws.save_apriltag_3d(rows)?;
+                        }
+                        // simply drop data if no file opened
+                    }
+                    TriggerVolumeEvent(row) => {
+                        if let Some(ref mut ws) = writing_state {
+                            ws.save_trigger_volume_event(row)?;
+                        }
+                        // simply drop data if no file opened
+                    }
+                    CamdataStats(row) => {
+                        if let Some(ref mut ws) = writing_state {
+                            ws.save_camdata_stats(row)?;
+                        }
+                        // simply drop data if no file opened
+                    }
                     Data2dDistorted(fdp) => {
                         if let Some(ref mut ws) = writing_state {
                             ws.save_data_2d_distorted(fdp)?;
@@ -105,6 +105,9 @@ enum Opt {
         /// Filename of output .fmf, "-" for stdout
         #[structopt(long = "output", short = "o", name = "OUTPUT-FMF", parse(from_os_str))]
         output: Option<PathBuf>,
+
+        #[structopt(flatten)]
+        range: FrameRange,
     },
 
     /// print information about an fmf file
@@ -125,6 +128,9 @@ enum Opt {
         /// Quality (1-100 where 1 is the worst and 100 is the best)
         #[structopt(name = "QUALITY", long = "quality", short = "q", default_value = "99")]
         quality: u8,
+
+        #[structopt(flatten)]
+        range: FrameRange,
     },
 
     /// export a sequence of png images
@@ -133,6 +139,9 @@ enum Opt {
         /// Filename of input fmf
         #[structopt(parse(from_os_str), name = "INPUT-FMF")]
         input: PathBuf,
+
+        #[structopt(flatten)]
+        range: FrameRange,
     },
 
     /// export to y4m (YUV4MPEG2) format
@@ -193,6 +202,9 @@ struct ExportY4m {
     /// aspect ratio denominator
     #[structopt(default_value = "1", long = "aspect-denominator")]
     aspect_denominator: u32,
+
+    #[structopt(flatten)]
+    range: FrameRange,
 }
 
 // #[derive(StructOpt, Debug)]
@@ -227,13 +239,96 @@ struct ExportMkv {
     #[structopt(long = "bitrate", short = "b", default_value = "1000")]
     bitrate: u32,
 
-    /// video codec
+    /// video codec (vp8, vp9, h264 or ffv1, which is lossless)
     #[structopt(long = "codec", default_value = "vp9")]
     codec: Codec,
 
     /// clip the width of the incoming frames to be divisible by this number
     #[structopt(long = "clip-divisible", default_value = "1")]
     clip_so_width_is_divisible_by: u8,
+
+    #[structopt(flatten)]
+    range: FrameRange,
+}
+
+/// Selects a range of frames from the input file.
+#[derive(StructOpt, Debug, Default)]
+struct FrameRange {
+    /// first frame to export, either a frame number or a time relative to the
+    /// first frame in seconds with an "s" suffix (e.g. "2.5s")
+    #[structopt(long = "start")]
+    start: Option<FramePos>,
+
+    /// stop exporting before this frame number, or after this time relative to
+    /// the first frame in seconds with an "s" suffix (e.g. "10s")
+    #[structopt(long = "stop")]
+    stop: Option<FramePos>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum FramePos {
+    Frame(usize),
+    Seconds(f64),
+}
+
+impl std::str::FromStr for FramePos {
+    type Err = String;
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        if let Some(secs) = s.strip_suffix('s') {
+            secs.parse()
+                .map(FramePos::Seconds)
+                .map_err(|e| format!("cannot parse time \"{}\": {}", s, e))
+        } else {
+            s.parse()
+                .map(FramePos::Frame)
+                .map_err(|e| format!("cannot parse frame number \"{}\": {}", s, e))
+        }
+    }
+}
+
+/// Which end of a [FrameRange] a timestamp is looked up for.
+#[derive(Debug, Clone, Copy)]
+enum FrameBound {
+    Start,
+    Stop,
+}
+
+impl FrameRange {
+    /// Compute the start (inclusive) and stop (exclusive) frame numbers.
+    ///
+    /// `t0` is the timestamp of the first frame. `frame_at(t, FrameBound::Start)`
+    /// returns the first frame with a timestamp not before `t` and
+    /// `frame_at(t, FrameBound::Stop)` the last frame with a timestamp not
+    /// after `t`.
+    fn resolve<F>(
+        &self,
+        n_frames: usize,
+        t0: Option<f64>,
+        mut frame_at: F,
+    ) -> Result<(usize, usize)>
+    where
+        F: FnMut(f64, FrameBound) -> Result<Option<usize>>,
+    {
+        let start = match (self.start, t0) {
+            (None, _) => 0,
+            (Some(FramePos::Frame(i)), _) => i,
+            (Some(FramePos::Seconds(secs)), Some(t0)) => {
+                frame_at(t0 + secs, FrameBound::Start)?.unwrap_or(n_frames)
+            }
+            (Some(FramePos::Seconds(_)), None) => 0,
+        };
+        let stop = match (self.stop, t0) {
+            (None, _) => n_frames,
+            (Some(FramePos::Frame(i)), _) => i,
+            (Some(FramePos::Seconds(secs)), Some(t0)) => frame_at(t0 + secs, FrameBound::Stop)?
+                .map(|i| i + 1)
+                .unwrap_or(0),
+            (Some(FramePos::Seconds(_)), None) => 0,
+        };
+        let stop = std::cmp::min(stop, n_frames);
+        let start = std::cmp::min(start, stop);
+        Ok((start, stop))
+    }
 }
 
 #[derive(Debug)]
@@ -242,6 +337,7 @@ enum Codec {
     Vp9,
     #[cfg(feature = "nv-h264")]
     H264,
+    Ffv1,
 }
 
 impl std::str::FromStr for Codec {
@@ -252,6 +348,7 @@ impl std::str::FromStr for Codec {
             "vp9" | "Vp9" | "VP9" => Ok(Codec::Vp9),
             #[cfg(feature = "nv-h264")]
             "h264" | "H264" => Ok(Codec::H264),
+            "ffv1" | "Ffv1" | "FFV1" => Ok(Codec::Ffv1),
             c => Err(format!("unknown codec: {}", c)),
         }
     }
@@ -313,6 +410,65 @@ fn display_filename(p: &Option<PathBuf>, default: &str) -> PathBuf {
     }
 }
 
+/// An opened input movie, either .fmf or .ufmf.
+struct InputMovie {
+    width: u32,
+    height: u32,
+    format: PixFmt,
+    frames: Box<dyn Iterator<Item = Result<DynamicFrame>>>,
+}
+
+impl InputMovie {
+    /// Open an input movie, choosing the reader by the file extension.
+    ///
+    /// Only frames within `range` will be returned.
+    fn open(path: &Path, range: &FrameRange) -> Result<Self> {
+        let is_ufmf = path
+            .extension()
+            .map(|ext| ext.to_ascii_lowercase() == "ufmf")
+            .unwrap_or(false);
+        if is_ufmf {
+            let mut reader = ufmf::UFMFReader::open(path)?;
+            let t0 = reader.frame_index().first().map(|entry| entry.timestamp);
+            let (start, stop) =
+                range.resolve(reader.frame_count(), t0, |t, bound| match bound {
+                    FrameBound::Start => Ok(reader.frame_from_timestamp(t)),
+                    FrameBound::Stop => Ok(reader.frame_at_timestamp(t)),
+                })?;
+            reader.seek_to_frame(start)?;
+            Ok(Self {
+                width: reader.width(),
+                height: reader.height(),
+                format: reader.format(),
+                frames: Box::new(
+                    reader
+                        .take(stop - start)
+                        .map(|r| r.map_err(anyhow::Error::from)),
+                ),
+            })
+        } else {
+            let mut reader = fmf::FMFReader::new(path)?;
+            let t0 = if reader.frame_count() > 0 {
+                Some(reader.get_timestamp(0)?)
+            } else {
+                None
+            };
+            let (start, stop) =
+                range.resolve(reader.frame_count(), t0, |t, bound| match bound {
+                    FrameBound::Start => Ok(reader.frame_from_timestamp(t)?),
+                    FrameBound::Stop => Ok(reader.frame_at_timestamp(t)?),
+                })?;
+            reader.seek_to_frame(start)?;
+            Ok(Self {
+                width: reader.width(),
+                height: reader.height(),
+                format: reader.format(),
+                frames: Box::new(reader.take(stop - start).map(Ok::<_, anyhow::Error>)),
+            })
+        }
+    }
+}
+
 fn info(path: PathBuf) -> Result<()> {
     #[derive(Debug)]
     struct Info {
@@ -321,8 +477,9 @@ fn info(path: PathBuf) -> Result<()> {
         stride: usize,
         pixel_format: PixFmt,
     }
-    let reader = fmf::FMFReader::new(&path)?;
+    let reader = InputMovie::open(&path, &FrameRange::default())?.frames;
     for (fno, frame) in reader.enumerate() {
+        let frame = frame?;
         let i = Info {
             width: frame.width(),
             stride: frame.stride(),
@@ -347,6 +504,7 @@ fn export_fmf(
     new_pixel_format: Option<PixFmt>,
     output: Option<PathBuf>,
     forced_input_pixel_format: Option<PixFmt>,
+    range: FrameRange,
 ) -> Result<()> {
     let output_fname = default_filename(&path, output, "fmf");
 
@@ -355,7 +513,7 @@ fn export_fmf(
         path.display(),
         display_filename(&output_fname, "<stdout>").display()
     );
-    let reader = fmf::FMFReader::new(&path)?;
+    let reader = InputMovie::open(&path, &range)?.frames;
 
     let output_fname = output_fname.unwrap(); // XXX temp hack FIXME
 
@@ -363,6 +521,7 @@ fn export_fmf(
     let mut writer = fmf::FMFWriter::new(f)?;
 
     for frame in reader {
+        let frame = frame?;
         let fts = frame.extra().host_timestamp();
         let frame: DynamicFrame = match forced_input_pixel_format {
             Some(forced_input_pixel_format) => frame.force_pixel_format(forced_input_pixel_format),
@@ -423,7 +582,7 @@ fn convert_to_rgb8(
     Ok(f)
 }
 
-fn export_images(path: PathBuf, opts: ImageOptions) -> Result<()> {
+fn export_images(path: PathBuf, opts: ImageOptions, range: FrameRange) -> Result<()> {
     use std::io::Write;
 
     let stem = path.file_stem().unwrap().to_os_string(); // strip extension
@@ -446,10 +605,11 @@ fn export_images(path: PathBuf, opts: ImageOptions) -> Result<()> {
         },
     }
 
-    let reader = fmf::FMFReader::new(&path)?;
+    let reader = InputMovie::open(&path, &range)?.frames;
 
-    for (i, frame) in reader.enumerate() {
-        let file = format!("frame{:05}.{}", i, ext);
+    for frame in reader {
+        let frame = frame?;
+        let file = format!("frame{:05}.{}", frame.extra().host_framenumber(), ext);
         let fname = dirname.join(&file);
         let frame = convert_to_rgb8(&frame)?;
         let buf = convert_image::frame_to_image(frame.as_ref(), opts)?;
@@ -556,7 +716,7 @@ fn export_mkv(x: ExportMkv) -> Result<()> {
         Some(path) => std::fs::File::create(&path)?,
     };
 
-    let reader = fmf::FMFReader::new(&x.input)?;
+    let reader = InputMovie::open(&x.input, &x.range)?.frames;
 
     let codec = match x.codec {
         Codec::Vp8 => {
@@ -575,12 +735,14 @@ fn export_mkv(x: ExportMkv) -> Result<()> {
             opts.bitrate = x.bitrate;
             ci2_remote_control::MkvCodec::H264(opts)
         }
+        Codec::Ffv1 => ci2_remote_control::MkvCodec::FFV1,
     };
 
     let cfg = MkvRecordingConfig {
         codec,
         max_framerate: ci2_remote_control::RecordingFrameRate::Unlimited,
         writing_application: Some("fmf-cli".to_string()),
+        crash_safe: false,
     };
 
     #[cfg(feature = "nv-h264")]
@@ -596,6 +758,7 @@ fn export_mkv(x: ExportMkv) -> Result<()> {
     let mut my_mkv_writer = mkv_writer::MkvWriter::new(out_fd, cfg, nv_enc)?;
 
     for (fno, fmf_frame) in reader.enumerate() {
+        let fmf_frame = fmf_frame?;
         debug!("saving frame {}", fno);
         let ts = fmf_frame.extra().host_timestamp();
         match fmf_frame {
@@ -693,15 +856,15 @@ fn export_y4m(x: ExportY4m) -> Result<()> {
         Some(path) => Box::new(std::fs::File::create(&path)?),
     };
 
-    let reader = fmf::FMFReader::new(&x.input)?;
-    let mut buffer_width = reader.width();
-    let buffer_height = reader.height();
+    let reader = InputMovie::open(&x.input, &x.range)?;
+    let mut buffer_width = reader.width;
+    let buffer_height = reader.height;
 
-    if reader.format() == PixFmt::RGB8 {
+    if reader.format == PixFmt::RGB8 {
         buffer_width *= 3;
     }
 
-    let final_width = match reader.format() {
+    let final_width = match reader.format {
         PixFmt::RGB8 => buffer_width / 3,
         _ => buffer_width,
     };
@@ -725,7 +888,8 @@ fn export_y4m(x: ExportY4m) -> Result<()> {
     );
     out_fd.write_all(buf.as_bytes())?;
 
-    for frame in reader {
+    for frame in reader.frames {
+        let frame = frame?;
         let buf = format!("{magic}\n", magic = Y4M_FRAME_MAGIC);
         out_fd.write_all(buf.as_bytes())?;
 
@@ -752,17 +916,28 @@ fn main() -> Result<()> {
             new_pixel_format,
             output,
             forced_input_pixel_format,
+            range,
         } => {
-            export_fmf(input, new_pixel_format, output, forced_input_pixel_format)?;
+            export_fmf(
+                input,
+                new_pixel_format,
+                output,
+                forced_input_pixel_format,
+                range,
+            )?;
         }
         Opt::Info { input } => {
             info(input)?;
         }
-        Opt::ExportJpeg { input, quality } => {
-            export_images(input, ImageOptions::Jpeg(quality))?;
+        Opt::ExportJpeg {
+            input,
+            quality,
+            range,
+        } => {
+            export_images(input, ImageOptions::Jpeg(quality), range)?;
         }
-        Opt::ExportPng { input } => {
-            export_images(input, ImageOptions::Png)?;
+        Opt::ExportPng { input, range } => {
+            export_images(input, ImageOptions::Png, range)?;
         }
         Opt::ExportY4m(x) => {
             export_y4m(x)?;
@@ -30,6 +30,8 @@ pub enum FMFError {
     UnimplementedVersion,
     #[error("premature file end")]
     PrematureFileEnd,
+    #[error("frame {0} out of range")]
+    FrameOutOfRange(usize),
     #[error("unknown format {0}")]
     UnknownFormat(String),
     #[error("inconsistent state")]
@@ -340,4 +342,59 @@ mod tests {
         let expected = [3, 0, 0, 0, 5, 0, 0, 0, 77, 79]; // TODO improve test
         assert_eq!(&buf[0..10], expected);
     }
+
+    #[test]
+    fn test_random_access() {
+        use crate::FMFReader;
+
+        let (w, h) = (32, 16);
+        let path = std::env::temp_dir().join(format!("fmf-test-{}.fmf", std::process::id()));
+        {
+            let f = std::fs::File::create(&path).unwrap();
+            let mut writer = FMFWriter::new(f).unwrap();
+            for i in 0..10u8 {
+                let mut frame = zeros(w, h);
+                frame.image_data.iter_mut().for_each(|x| *x = i);
+                let ts = datetime_conversion::f64_to_datetime(100.0 + i as f64);
+                writer.write(&frame, ts).unwrap();
+            }
+            writer.close().unwrap();
+        }
+
+        let mut reader = FMFReader::new(&path).unwrap();
+        assert_eq!(reader.frame_count(), 10);
+
+        let frame = reader.get_frame(7).unwrap();
+        assert_eq!(frame.extra().host_framenumber(), 7);
+        assert_eq!(frame.image_data_without_format()[0], 7);
+
+        // iteration continues after the frame just read
+        let next = reader.next().unwrap();
+        assert_eq!(next.image_data_without_format()[0], 8);
+
+        assert!(reader.get_frame(10).is_err());
+
+        assert_eq!(reader.frame_at_timestamp(99.0).unwrap(), None);
+        assert_eq!(reader.frame_at_timestamp(100.0).unwrap(), Some(0));
+        assert_eq!(reader.frame_at_timestamp(104.5).unwrap(), Some(4));
+        assert_eq!(reader.frame_at_timestamp(1000.0).unwrap(), Some(9));
+        assert_eq!(reader.frame_from_timestamp(99.0).unwrap(), Some(0));
+        assert_eq!(reader.frame_from_timestamp(104.0).unwrap(), Some(4));
+        assert_eq!(reader.frame_from_timestamp(104.5).unwrap(), Some(5));
+        assert_eq!(reader.frame_from_timestamp(1000.0).unwrap(), None);
+
+        // Searching by timestamp does not change the position of the iterator.
+        reader.seek_to_frame(3).unwrap();
+        assert_eq!(reader.get_timestamp(6).unwrap(), 106.0);
+        assert_eq!(reader.frame_at_timestamp(101.0).unwrap(), Some(1));
+        let next = reader.next().unwrap();
+        assert_eq!(next.extra().host_framenumber(), 3);
+        assert_eq!(next.image_data_without_format()[0], 3);
+
+        reader.seek_to_frame(2).unwrap();
+        let rest: Vec<_> = reader.collect();
+        assert_eq!(rest.len(), 8);
+
+        std::fs::remove_file(&path).unwrap();
+    }
 }
@@ -1,6 +1,6 @@
 use chrono;
 use std::fs::File;
-use std::io::Read;
+use std::io::{Read, Seek, SeekFrom};
 use std::path::Path;
 
 use byteorder::{LittleEndian, ReadBytesExt};
@@ -48,15 +48,19 @@ macro_rules! to_dynamic {
     }};
 }
 
+/// Reads FMF (fly movie format) movie files.
+///
+/// Because every frame is stored in a chunk of fixed size, frames can be
+/// accessed in any order with [FMFReader::get_frame] in addition to iterating
+/// over them in order.
 pub struct FMFReader {
     f: File,
     pixel_format: PixFmt,
     height: u32,
     width: u32,
     chunksize: u64,
-    // n_frames: u64,
-    // pos: usize,
-    // frame0_pos: usize,
+    n_frames: usize,
+    frame0_pos: u64,
     count: usize,
 }
 
@@ -73,32 +77,55 @@ impl FMFReader {
         let mut pos = 0;
         let version = f.read_u32::<LittleEndian>()?;
         pos += 4;
-        if version != 3 {
-            return Err(FMFError::UnimplementedVersion);
-        }
 
-        // format
-        let expected_format_len = f.read_u32::<LittleEndian>()? as usize;
-        pos += 4;
-        let mut format: Vec<u8> = vec![0; expected_format_len];
-        let actual_format_len = f.read(&mut format)?;
-        pos += actual_format_len;
-        if expected_format_len != actual_format_len {
-            return Err(FMFError::PrematureFileEnd);
-        }
-        let pixel_format = pixel_formats::get_pixel_format(&format)?;
+        let pixel_format = match version {
+            1 => PixFmt::Mono8,
+            3 => {
+                // format
+                let expected_format_len = f.read_u32::<LittleEndian>()? as usize;
+                pos += 4;
+                let mut format: Vec<u8> = vec![0; expected_format_len];
+                let actual_format_len = f.read(&mut format)?;
+                pos += actual_format_len;
+                if expected_format_len != actual_format_len {
+                    return Err(FMFError::PrematureFileEnd);
+                }
+                let pixel_format = pixel_formats::get_pixel_format(&format)?;
+
+                let _bpp = f.read_u32::<LittleEndian>()?;
+                pos += 4;
+                pixel_format
+            }
+            _ => {
+                return Err(FMFError::UnimplementedVersion);
+            }
+        };
 
-        let _bpp = f.read_u32::<LittleEndian>()?;
-        pos += 4;
         let height = f.read_u32::<LittleEndian>()?;
         pos += 4;
         let width = f.read_u32::<LittleEndian>()?;
         pos += 4;
         let chunksize = f.read_u64::<LittleEndian>()?;
         pos += 8;
-        let _n_frames = f.read_u64::<LittleEndian>()?;
+        let header_n_frames = f.read_u64::<LittleEndian>()?;
         pos += 8;
-        let _frame0_pos = pos;
+        let frame0_pos = pos as u64;
+
+        if chunksize < 8 {
+            return Err(FMFError::UnexpectedSize);
+        }
+
+        // The number of frames in the header is zero if the writer was not
+        // closed. In that case, and also to guard against a truncated file,
+        // compute the number of complete frames from the file size.
+        let file_len = f.metadata()?.len();
+        let n_frames_from_size = (file_len.saturating_sub(frame0_pos) / chunksize) as usize;
+        let n_frames = if header_n_frames == 0 {
+            n_frames_from_size
+        } else {
+            std::cmp::min(header_n_frames as usize, n_frames_from_size)
+        };
+
         let count = 0;
 
         Ok(Self {
@@ -107,7 +134,9 @@ impl FMFReader {
             height,
             width,
             chunksize,
-            /*n_frames, pos, frame0_pos,*/ count,
+            n_frames,
+            frame0_pos,
+            count,
         })
     }
 
@@ -126,7 +155,105 @@ impl FMFReader {
         self.pixel_format
     }
 
+    /// The number of frames in the file.
+    #[inline]
+    pub fn frame_count(&self) -> usize {
+        self.n_frames
+    }
+
+    /// Position the reader such that the next frame returned is frame `n`.
+    pub fn seek_to_frame(&mut self, n: usize) -> FMFResult<()> {
+        if n > self.n_frames {
+            return Err(FMFError::FrameOutOfRange(n));
+        }
+        self.f.seek(SeekFrom::Start(self.chunk_pos(n)))?;
+        self.count = n;
+        Ok(())
+    }
+
+    /// Read frame `n`.
+    ///
+    /// After this call, iteration continues with frame `n+1`.
+    pub fn get_frame(&mut self, n: usize) -> FMFResult<DynamicFrame> {
+        if n >= self.n_frames {
+            return Err(FMFError::FrameOutOfRange(n));
+        }
+        self.seek_to_frame(n)?;
+        self.next_frame()
+    }
+
+    /// Read the timestamp of frame `n` without reading its image data.
+    ///
+    /// The position of the iterator is not changed.
+    pub fn get_timestamp(&mut self, n: usize) -> FMFResult<f64> {
+        if n >= self.n_frames {
+            return Err(FMFError::FrameOutOfRange(n));
+        }
+        self.f.seek(SeekFrom::Start(self.chunk_pos(n)))?;
+        let timestamp = self.f.read_f64::<LittleEndian>();
+        // Restore the position for the next iteration, also if reading failed.
+        self.f.seek(SeekFrom::Start(self.chunk_pos(self.count)))?;
+        Ok(timestamp?)
+    }
+
+    /// Find the last frame with a timestamp not after `timestamp`.
+    ///
+    /// This performs a binary search and thus assumes that the timestamps in
+    /// the file increase monotonically. Returns `None` if all frames are after
+    /// `timestamp`.
+    ///
+    /// The position of the iterator is not changed.
+    pub fn frame_at_timestamp(&mut self, timestamp: f64) -> FMFResult<Option<usize>> {
+        Ok(self.count_frames_before(|t| t <= timestamp)?.checked_sub(1))
+    }
+
+    /// Find the first frame with a timestamp not before `timestamp`.
+    ///
+    /// Like [FMFReader::frame_at_timestamp], this assumes monotonically
+    /// increasing timestamps. Returns `None` if all frames are before
+    /// `timestamp`.
+    ///
+    /// The position of the iterator is not changed.
+    pub fn frame_from_timestamp(&mut self, timestamp: f64) -> FMFResult<Option<usize>> {
+        let n_before = self.count_frames_before(|t| t < timestamp)?;
+        Ok(if n_before < self.n_frames {
+            Some(n_before)
+        } else {
+            None
+        })
+    }
+
+    /// Binary search for the number of leading frames whose timestamp
+    /// satisfies `is_before`.
+    fn count_frames_before<F>(&mut self, is_before: F) -> FMFResult<usize>
+    where
+        F: Fn(f64) -> bool,
+    {
+        // Invariant: frames before `lo` satisfy `is_before`, frames at or
+        // after `hi` do not.
+        let mut lo = 0;
+        let mut hi = self.n_frames;
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            if is_before(self.get_timestamp(mid)?) {
+                lo = mid + 1;
+            } else {
+                hi = mid;
+            }
+        }
+        Ok(lo)
+    }
+
+    #[inline]
+    fn chunk_pos(&self, n: usize) -> u64 {
+        self.frame0_pos + n as u64 * self.chunksize
+    }
+
     fn next_frame(&mut self) -> FMFResult<DynamicFrame> {
+        if self.count >= self.n_frames {
+            return Err(FMFError::PrematureFileEnd);
+        }
+
         let f = &mut self.f;
 
         let timestamp_f64 = f.read_f64::<LittleEndian>()?;
@@ -134,11 +261,10 @@ impl FMFReader {
 
         let datasize = (self.chunksize - 8) as usize;
         let mut image_data: Vec<u8> = vec![0; datasize];
-        let actual_data_len = f.read(&mut image_data)?;
-
-        if actual_data_len < datasize {
-            return Err(FMFError::PrematureFileEnd);
-        }
+        f.read_exact(&mut image_data).map_err(|e| match e.kind() {
+            std::io::ErrorKind::UnexpectedEof => FMFError::PrematureFileEnd,
+            _ => FMFError::from(e),
+        })?;
 
         let width = self.width;
         let height = self.height;
@@ -158,12 +284,23 @@ impl FMFReader {
     }
 }
 
+/// Iterate over the frames in the file.
+///
+/// Iteration ends after the last frame. It also ends if a frame cannot be read,
+/// for example due to an I/O error. In that case, the error is logged. Use
+/// [FMFReader::get_frame] to handle errors.
 impl Iterator for FMFReader {
     type Item = DynamicFrame;
     fn next(&mut self) -> Option<Self::Item> {
+        if self.count >= self.n_frames {
+            return None;
+        }
         match self.next_frame() {
             Ok(f) => Some(f),
-            Err(_) => None,
+            Err(e) => {
+                log::error!("stopping iteration at frame {}: {}", self.count, e);
+                None
+            }
         }
     }
 }
@@ -12,7 +12,11 @@ pub struct Point {
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
 pub struct ToClient {
+    /// The frame as a JPEG data URL. Empty if `video_chunk` is set.
     pub firehose_frame_data_url: String,
+    /// The frame as part of an encoded video stream.
+    #[serde(default)]
+    pub video_chunk: Option<VideoChunk>,
     pub found_points: Vec<Point>,
     pub valid_display: Option<Shape>,
     pub annotations: Vec<DrawableShape>,
@@ -22,6 +26,26 @@ pub struct ToClient {
     pub name: Option<String>,
 }
 
+/// Part of an encoded video stream, to be played using the Media Source
+/// Extensions API of the browser.
+///
+/// The chunks of a connection form a single stream. Whenever the encoder is
+/// restarted, e.g. because the image size changed, a chunk starts with a new
+/// initialization segment.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct VideoChunk {
+    /// The MIME type of the stream, including the codecs parameter.
+    pub mime_type: String,
+    /// Base64 encoded data to append to the `SourceBuffer`.
+    pub data_base64: String,
+    /// The presentation time of the frame in this chunk, in seconds.
+    pub pts_secs: f64,
+    /// Whether the frame in this chunk is a keyframe. Video before a keyframe
+    /// can be removed from the `SourceBuffer` without breaking playback.
+    #[serde(default)]
+    pub keyframe: bool,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct CircleParams {
     pub center_x: i16,
@@ -16,7 +16,7 @@ use bui_backend_types::{ConnectionKey, SessionKey};
 use basic_frame::DynamicFrame;
 
 pub use http_video_streaming_types::{
-    CircleParams, DrawableShape, FirehoseCallbackInner, Point, Shape, ToClient,
+    CircleParams, DrawableShape, FirehoseCallbackInner, Point, Shape, ToClient, VideoChunk,
 };
 
 type Result<T> = std::result::Result<T, Error>;
@@ -40,9 +40,35 @@ pub enum Error {
     },
     #[error("callback sender disconnected")]
     CallbackSenderDisconnected(#[cfg(feature = "backtrace")] std::backtrace::Backtrace),
+    #[error("VPX Encoder Error")]
+    VpxEncoderError {
+        #[from]
+        #[cfg_attr(feature = "backtrace", backtrace)]
+        inner: vpx_encode::Error,
+    },
+}
+
+/// How frames are encoded for sending to the browser.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StreamEncoding {
+    /// Each frame is sent as a JPEG image.
+    Jpeg,
+    /// Frames are sent as a VP8 video stream in WebM format, to be played using
+    /// the Media Source Extensions API (https://w3c.github.io/media-source).
+    ///
+    /// Each connection has its own encoder, so clients which cannot keep up
+    /// are sent fewer frames rather than a stream of a lower quality.
+    Vp8 {
+        /// The target bitrate in kilobits per second.
+        bitrate: u32,
+    },
 }
 
-// future: use MediaSource API? https://w3c.github.io/media-source
+impl Default for StreamEncoding {
+    fn default() -> Self {
+        StreamEncoding::Jpeg
+    }
+}
 
 pub struct AnnotatedFrame {
     pub frame: DynamicFrame,
@@ -57,6 +83,24 @@ pub struct FirehoseCallback {
     pub inner: FirehoseCallbackInner,
 }
 
+/// The encoder and muxer for frames of one image size.
+struct VideoStream {
+    encoder: vpx_encode::Encoder,
+    muxer: mkv_mux::LiveMuxer,
+    width: u32,
+    height: u32,
+}
+
+/// The encoder of the video stream sent to a single connection.
+#[derive(Default)]
+struct VideoEncoder {
+    stream: Option<VideoStream>,
+    /// The time from which presentation timestamps are counted.
+    stream_start: Option<chrono::DateTime<chrono::Utc>>,
+    /// The presentation timestamp (in milliseconds) of the last encoded frame.
+    last_pts: Option<i64>,
+}
+
 struct PerSender {
     name_selector: NameSelector,
     out: EventChunkSender,
@@ -64,6 +108,8 @@ struct PerSender {
     ready_to_send: bool,
     conn_key: ConnectionKey,
     fno: u64,
+    encoding: StreamEncoding,
+    video_encoder: VideoEncoder,
 }
 
 #[derive(Debug)]
@@ -78,6 +124,7 @@ impl PerSender {
         out: EventChunkSender,
         conn_key: ConnectionKey,
         name_selector: NameSelector,
+        encoding: StreamEncoding,
     ) -> PerSender {
         PerSender {
             name_selector,
@@ -86,6 +133,8 @@ impl PerSender {
             ready_to_send: true,
             conn_key: conn_key,
             fno: 0,
+            encoding,
+            video_encoder: VideoEncoder::default(),
         }
     }
     fn push(&mut self, frame: Rc<AnnotatedFrame>) {
@@ -128,21 +177,41 @@ impl PerSender {
         // TODO make algorithm smarter to have more in-flight frames?
         // TODO include sent time in message to clients so we don't maintain that
 
-        match self.frame_lifo {
-            Some(ref most_recent_frame_data) => {
+        match self.frame_lifo.take() {
+            Some(most_recent_frame_data) => {
                 if self.ready_to_send {
                     // sent_time computed early so that latency includes duration to encode, etc.
                     let sent_time: chrono::DateTime<chrono::Utc> = chrono::Utc::now();
-                    let bytes = basic_frame::match_all_dynamic_fmts!(
-                        &most_recent_frame_data.frame,
-                        x,
-                        convert_image::frame_to_image(x, convert_image::ImageOptions::Jpeg(80),)
-                    )?;
-                    let firehose_frame_base64 = base64::encode(&bytes);
-                    let data_url = format!("data:image/jpeg;base64,{}", firehose_frame_base64);
+                    let (data_url, video_chunk) = match self.encoding {
+                        StreamEncoding::Jpeg => {
+                            let bytes = basic_frame::match_all_dynamic_fmts!(
+                                &most_recent_frame_data.frame,
+                                x,
+                                convert_image::frame_to_image(
+                                    x,
+                                    convert_image::ImageOptions::Jpeg(80),
+                                )
+                            )?;
+                            let firehose_frame_base64 = base64::encode(&bytes);
+                            let data_url =
+                                format!("data:image/jpeg;base64,{}", firehose_frame_base64);
+                            (data_url, None)
+                        }
+                        StreamEncoding::Vp8 { bitrate } => {
+                            let frame = &most_recent_frame_data.frame;
+                            match self.video_encoder.encode(frame, sent_time, bitrate)? {
+                                Some(chunk) => (String::new(), Some(chunk)),
+                                None => {
+                                    // The encoder dropped this frame.
+                                    return Ok(());
+                                }
+                            }
+                        }
+                    };
                     let found_points = most_recent_frame_data.found_points.clone();
                     let tc = ToClient {
                         firehose_frame_data_url: data_url,
+                        video_chunk,
                         found_points,
                         valid_display: most_recent_frame_data.valid_display.clone(),
                         annotations: most_recent_frame_data.annotations.clone(),
@@ -174,18 +243,93 @@ impl PerSender {
             None => {} // nothing to do, no frame in queue
         }
 
-        self.frame_lifo = None;
-
         Ok(())
     }
 }
 
+impl VideoEncoder {
+    /// Encode a frame into the video stream.
+    ///
+    /// A new stream, starting with an initialization segment, is started for
+    /// the first frame and whenever the image size changes. Returns `None` if
+    /// the encoder produced no data for this frame.
+    fn encode(
+        &mut self,
+        frame: &DynamicFrame,
+        now: chrono::DateTime<chrono::Utc>,
+        bitrate: u32,
+    ) -> Result<Option<VideoChunk>> {
+        let (width, height) = (frame.width(), frame.height());
+
+        let mut data = Vec::new();
+        let needs_new_stream = match &self.stream {
+            Some(vs) => vs.width != width || vs.height != height,
+            None => true,
+        };
+        if needs_new_stream {
+            let encoder = vpx_encode::Encoder::new(vpx_encode::Config {
+                width,
+                height,
+                timebase: [1, 1000], // millisecond time base
+                bitrate,
+                codec: vpx_encode::VideoCodecId::VP8,
+            })?;
+            let muxer = mkv_mux::LiveMuxer::new(width, height, mkv_mux::Codec::Vp8);
+            data.extend(muxer.init_segment());
+            self.stream = Some(VideoStream {
+                encoder,
+                muxer,
+                width,
+                height,
+            });
+        }
+        let vs = self.stream.as_mut().unwrap();
+
+        // Timestamps must increase, also across restarts of the encoder, so
+        // that the browser can append all chunks to the same buffer.
+        let stream_start = *self.stream_start.get_or_insert(now);
+        let mut pts = now.signed_duration_since(stream_start).num_milliseconds();
+        if let Some(last_pts) = self.last_pts {
+            pts = pts.max(last_pts + 1);
+        }
+        self.last_pts = Some(pts);
+
+        let yuv = basic_frame::match_all_dynamic_fmts!(
+            frame,
+            x,
+            convert_image::encode_y4m_frame(x, convert_image::Y4MColorspace::C420paldv)
+        )?;
+        let mut got_frame = false;
+        let mut keyframe = false;
+        for packet in vs.encoder.encode(pts, &yuv)? {
+            data.extend(vs.muxer.cluster(packet.data, packet.pts as u64, packet.key));
+            got_frame = true;
+            keyframe |= packet.key;
+        }
+        if !got_frame {
+            if needs_new_stream {
+                // Start again so that the initialization segment is not lost.
+                self.stream = None;
+            }
+            return Ok(None);
+        }
+
+        Ok(Some(VideoChunk {
+            mime_type: vs.muxer.mime_type().to_string(),
+            data_base64: base64::encode(&data),
+            pts_secs: pts as f64 / 1000.0,
+            keyframe,
+        }))
+    }
+}
+
 pub fn firehose_thread(
     sender_map_arc: Arc<RwLock<HashMap<ConnectionKey, (SessionKey, EventChunkSender, String)>>>,
     firehose_rx: channellib::Receiver<AnnotatedFrame>,
     firehose_callback_rx: channellib::Receiver<FirehoseCallback>,
     use_frame_selector: bool,
     events_prefix: &str,
+    encoding: StreamEncoding,
     flag: thread_control::Flag,
 ) -> Result<()> {
     // TODO switch this to a tokio core reactor based event loop and async processing.
@@ -240,7 +384,7 @@ pub fn firehose_thread(
                             let use_name = path[slash_idx..].to_string();
                             NameSelector::Name(use_name)
                         };
-                        let ps = PerSender::new(item.1.clone(), *conn_key, name_selector);
+                        let ps = PerSender::new(item.1.clone(), *conn_key, name_selector, encoding);
                         per_sender_map.insert(*conn_key, ps);
                     }
                     None => {
@@ -288,3 +432,71 @@ pub fn firehose_thread(
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use basic_frame::{BasicExtra, BasicFrame};
+
+    const EBML_ID: [u8; 4] = [0x1A, 0x45, 0xDF, 0xA3];
+    const CLUSTER_ID: [u8; 4] = [0x1F, 0x43, 0xB6, 0x75];
+
+    fn mono8_frame(width: u32, height: u32, value: u8) -> DynamicFrame {
+        DynamicFrame::Mono8(BasicFrame {
+            width,
+            height,
+            stride: width,
+            image_data: vec![value; (width * height) as usize],
+            pixel_format: std::marker::PhantomData,
+            extra: Box::new(BasicExtra {
+                host_timestamp: chrono::Utc::now(),
+                host_framenumber: 0,
+            }),
+        })
+    }
+
+    /// Encode frames until the encoder returns a chunk.
+    fn encode_next(
+        video_encoder: &mut VideoEncoder,
+        width: u32,
+        height: u32,
+        now: &mut chrono::DateTime<chrono::Utc>,
+    ) -> (VideoChunk, Vec<u8>) {
+        for i in 0..10 {
+            let frame = mono8_frame(width, height, i * 20);
+            *now = *now + chrono::Duration::milliseconds(40);
+            if let Some(chunk) = video_encoder.encode(&frame, *now, 100).unwrap() {
+                let data = base64::decode(&chunk.data_base64).unwrap();
+                return (chunk, data);
+            }
+        }
+        panic!("the encoder returned no data");
+    }
+
+    #[test]
+    fn encode_video() {
+        let mut video_encoder = VideoEncoder::default();
+        let mut now = chrono::Utc::now();
+
+        // The first chunk starts the stream with an initialization segment.
+        let (chunk, data) = encode_next(&mut video_encoder, 64, 32, &mut now);
+        assert_eq!(chunk.mime_type, "video/webm; codecs=\"vp8\"");
+        assert!(chunk.keyframe);
+        assert_eq!(&data[..4], &EBML_ID);
+        let mut last_pts = chunk.pts_secs;
+
+        // Further chunks only hold clusters, with increasing timestamps.
+        for _ in 0..3 {
+            let (chunk, data) = encode_next(&mut video_encoder, 64, 32, &mut now);
+            assert_eq!(&data[..4], &CLUSTER_ID);
+            assert!(chunk.pts_secs > last_pts);
+            last_pts = chunk.pts_secs;
+        }
+
+        // A new image size starts a new stream, continuing the timestamps.
+        let (chunk, data) = encode_next(&mut video_encoder, 32, 16, &mut now);
+        assert!(chunk.keyframe);
+        assert_eq!(&data[..4], &EBML_ID);
+        assert!(chunk.pts_secs > last_pts);
+    }
+}
@@ -17,6 +17,7 @@ fn my_default(polarity: ContrastPolarity, valid_region: Shape) -> ImPtDetectCfg
         clear_fraction: 0.3,
         despeckle_threshold: 5,
         valid_region,
+        blob_features: None,
     }
 }
 
@@ -61,4 +61,23 @@ pub struct ImPtDetectCfg {
     pub despeckle_threshold: u8,
     /// The shape of the reason over which detected points are checked.
     pub valid_region: Shape,
+    /// Which extended features to compute for each detected point.
+    ///
+    /// If this is `None`, no extended features are computed.
+    #[serde(default)]
+    pub blob_features: Option<BlobFeaturesCfg>,
+}
+
+/// Configuration of the extended features computed for each detected point.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BlobFeaturesCfg {
+    /// Maximum number of vertices of the contour polygon.
+    ///
+    /// 0 means no contour is computed.
+    pub max_contour_points: u16,
+    /// Width and height of the image patch saved around each point. In pixels.
+    ///
+    /// 0 means no image patch is saved.
+    pub patch_size: u16,
 }
@@ -0,0 +1,219 @@
+use fastimage::{ipp_ctypes, Chan1, FastImage, MomentState};
+use flydra_types::{BlobBoundingBox, BlobEllipse, BlobFeatures, ImagePatch};
+use image_tracker_types::BlobFeaturesCfg;
+
+use crate::Result;
+
+/// Compute the extended features of a detected blob.
+///
+/// `roi` is the thresholded difference image in which non-zero pixels belong to
+/// the blob and `moments` are the moments of `roi`. (`left`, `bottom`) is the
+/// location of `roi` and (`x0_abs`, `y0_abs`) the blob center in the full
+/// frame `raw_im_full`.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn compute_blob_features<S1, S2>(
+    cfg: &BlobFeaturesCfg,
+    moments: &MomentState,
+    roi: &S1,
+    left: ipp_ctypes::c_int,
+    bottom: ipp_ctypes::c_int,
+    raw_im_full: &S2,
+    x0_abs: f64,
+    y0_abs: f64,
+) -> Result<Option<BlobFeatures>>
+where
+    S1: FastImage<D = u8, C = Chan1>,
+    S2: FastImage<D = u8, C = Chan1>,
+{
+    // The leftmost and rightmost blob pixel of each row, in full frame
+    // coordinates. This is all that is needed for the bounding box and the
+    // convex hull.
+    let mut row_extents: Vec<(i32, i32, i32)> = Vec::new();
+    for row in 0..roi.height() {
+        let row_data = &roi.row_slice(row as usize)[..roi.width() as usize];
+        let first = row_data.iter().position(|x| *x != 0);
+        let last = row_data.iter().rposition(|x| *x != 0);
+        if let (Some(first), Some(last)) = (first, last) {
+            row_extents.push((
+                bottom + row,
+                left + first as ipp_ctypes::c_int,
+                left + last as ipp_ctypes::c_int,
+            ));
+        }
+    }
+    if row_extents.is_empty() {
+        return Ok(None);
+    }
+
+    let bbox = BlobBoundingBox {
+        left: row_extents.iter().map(|r| r.1).min().unwrap() as u32,
+        bottom: row_extents.first().unwrap().0 as u32,
+        right: row_extents.iter().map(|r| r.2).max().unwrap() as u32,
+        top: row_extents.last().unwrap().0 as u32,
+    };
+
+    let contour = if cfg.max_contour_points > 0 {
+        let mut points = Vec::with_capacity(row_extents.len() * 2);
+        for (y, x_first, x_last) in row_extents.iter() {
+            points.push([*x_first, *y]);
+            if x_last != x_first {
+                points.push([*x_last, *y]);
+            }
+        }
+        let hull = convex_hull(points);
+        simplify_polygon(hull, cfg.max_contour_points as usize)
+            .into_iter()
+            .map(|p| [p[0] as f32, p[1] as f32])
+            .collect()
+    } else {
+        Vec::new()
+    };
+
+    let patch = if cfg.patch_size > 0 {
+        Some(image_patch(raw_im_full, x0_abs, y0_abs, cfg.patch_size))
+    } else {
+        None
+    };
+
+    Ok(Some(BlobFeatures {
+        ellipse: compute_ellipse(moments)?,
+        bbox,
+        contour,
+        patch,
+    }))
+}
+
+/// The ellipse with the same second moments as the blob.
+fn compute_ellipse(moments: &MomentState) -> Result<Option<BlobEllipse>> {
+    let mu00 = moments.central(0, 0, 0)?;
+    if mu00 == 0.0 {
+        return Ok(None);
+    }
+    let a = moments.central(2, 0, 0)? / mu00;
+    let b = moments.central(1, 1, 0)? / mu00;
+    let c = moments.central(0, 2, 0)? / mu00;
+
+    // Eigenvalues of the covariance matrix [[a, b], [b, c]].
+    let mean = 0.5 * (a + c);
+    let dev = (0.25 * (a - c) * (a - c) + b * b).sqrt();
+    let (eval_major, eval_minor) = (mean + dev, (mean - dev).max(0.0));
+    if !(eval_major > 0.0) {
+        return Ok(None);
+    }
+
+    // A uniformly filled ellipse with semi-axis length `r` has a variance of
+    // `r^2/4` along that axis.
+    Ok(Some(BlobEllipse {
+        major_axis: 2.0 * eval_major.sqrt(),
+        minor_axis: 2.0 * eval_minor.sqrt(),
+        angle: 0.5 * (2.0 * b).atan2(a - c),
+    }))
+}
+
+#[inline]
+fn cross(o: &[i32; 2], a: &[i32; 2], b: &[i32; 2]) -> i64 {
+    (a[0] - o[0]) as i64 * (b[1] - o[1]) as i64 - (a[1] - o[1]) as i64 * (b[0] - o[0]) as i64
+}
+
+/// Convex hull with Andrew's monotone chain algorithm.
+///
+/// The vertices are returned counter-clockwise without collinear points.
+fn convex_hull(mut points: Vec<[i32; 2]>) -> Vec<[i32; 2]> {
+    points.sort_unstable();
+    points.dedup();
+    if points.len() < 3 {
+        return points;
+    }
+    let mut hull: Vec<[i32; 2]> = Vec::with_capacity(points.len() * 2);
+    for pass in 0..2 {
+        let start = hull.len();
+        let iter: Box<dyn Iterator<Item = &[i32; 2]>> = if pass == 0 {
+            Box::new(points.iter())
+        } else {
+            Box::new(points.iter().rev())
+        };
+        for p in iter {
+            while hull.len() >= start + 2
+                && cross(&hull[hull.len() - 2], &hull[hull.len() - 1], p) <= 0
+            {
+                hull.pop();
+            }
+            hull.push(*p);
+        }
+        // The last point of each chain is the first point of the other.
+        hull.pop();
+    }
+    hull
+}
+
+/// Reduce the number of vertices of a closed polygon.
+///
+/// Vertices are removed in order of the area of the triangle they form with
+/// their neighbors, smallest first (Visvalingam's algorithm).
+fn simplify_polygon(mut points: Vec<[i32; 2]>, max_points: usize) -> Vec<[i32; 2]> {
+    while points.len() > max_points && points.len() > 2 {
+        let n = points.len();
+        let (idx, _) = (0..n)
+            .map(|i| {
+                let area = cross(&points[(i + n - 1) % n], &points[i], &points[(i + 1) % n]).abs();
+                (i, area)
+            })
+            .min_by_key(|(_, area)| *area)
+            .unwrap();
+        points.remove(idx);
+    }
+    points.truncate(max_points);
+    points
+}
+
+/// The raw image around a point.
+fn image_patch<S>(raw_im_full: &S, x0_abs: f64, y0_abs: f64, size: u16) -> ImagePatch
+where
+    S: FastImage<D = u8, C = Chan1>,
+{
+    let half = size as i32 / 2;
+    let left = x0_abs.round() as i32 - half;
+    let bottom = y0_abs.round() as i32 - half;
+    let (w, h) = (raw_im_full.width(), raw_im_full.height());
+    let mut data = vec![0; size as usize * size as usize];
+    for (i, dest_row) in data.chunks_exact_mut(size as usize).enumerate() {
+        let y = bottom + i as i32;
+        if y < 0 || y >= h {
+            continue;
+        }
+        let src_row = raw_im_full.row_slice(y as usize);
+        for (j, dest) in dest_row.iter_mut().enumerate() {
+            let x = left + j as i32;
+            if x >= 0 && x < w {
+                *dest = src_row[x as usize];
+            }
+        }
+    }
+    ImagePatch {
+        left,
+        bottom,
+        size,
+        data,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_convex_hull() {
+        let points = vec![[0, 0], [2, 0], [1, 1], [2, 2], [0, 2], [1, 0]];
+        assert_eq!(convex_hull(points), vec![[0, 0], [2, 0], [2, 2], [0, 2]]);
+    }
+
+    #[test]
+    fn test_simplify_polygon() {
+        // A square with one vertex slightly off a straight edge.
+        let square = vec![[0, 0], [5, 0], [10, 1], [10, 10], [0, 10]];
+        assert_eq!(
+            simplify_polygon(square, 4),
+            vec![[0, 0], [10, 1], [10, 10], [0, 10]]
+        );
+    }
+}
@@ -70,4 +70,9 @@ pub enum Error {
     },
     #[error("{0}")]
     HyperError(#[from] hyper::Error),
+    #[error("braid rejected this camera ({status}): {reason}")]
+    RegistrationRejected {
+        status: hyper::StatusCode,
+        reason: String,
+    },
 }
@@ -31,7 +31,8 @@ use timestamped_frame::{ExtraTimeData, HostTimeData};
 
 use basic_frame::DynamicFrame;
 use flydra_types::{
-    serialize_packet, FlydraFloatTimestampLocal, FlydraRawUdpPacket, FlydraRawUdpPoint,
+    serialize_packet, CamdataCapabilities, CamdataEnvelope, CamdataProtocolInfo,
+    FlydraFloatTimestampLocal, FlydraRawUdpAprilTag, FlydraRawUdpPacket, FlydraRawUdpPoint,
     ImageProcessingSteps, MainbrainBuiLocation, RawCamName, RealtimePointsDestAddr, RosCamName,
     Triggerbox,
 };
@@ -49,12 +50,17 @@ use crate::borrow_fastimage::borrow_fi;
 mod background_model;
 use crate::background_model::{BackgroundModel, NUM_BG_START_IMAGES};
 
+mod blob_features;
+
 mod errors;
 pub use crate::errors::*;
 
 mod mainbrain_session;
 use mainbrain_session::mainbrain_future_session;
 
+mod tcp_camdata;
+use crate::tcp_camdata::TcpCamdataSender;
+
 #[cfg(feature = "debug-images")]
 thread_local!(
     static RT_IMAGE_VIEWER_SENDER: RefCell<rt_image_viewer::RtImageViewerSender> =
@@ -428,6 +434,20 @@ impl TrackingState {
                             .pixel_slice(max_loc.y() as usize, max_loc.x() as usize)[0]
                             as f64;
 
+                        let blob_features = match cfg.blob_features {
+                            Some(ref blob_features_cfg) => blob_features::compute_blob_features(
+                                blob_features_cfg,
+                                &self.moments,
+                                &absdiff_im_roi2_view,
+                                left2,
+                                bottom2,
+                                raw_im_full,
+                                x0_abs,
+                                y0_abs,
+                            )?,
+                            None => None,
+                        };
+
                         // qe9.push( dur_to_f64(q1.elapsed()) );
                         sample_vec.push((dur_to_f64(q1.elapsed()), line!() + 20000));
 
@@ -440,6 +460,7 @@ impl TrackingState {
                                 cur_val,
                                 mean_val,
                                 sumsqf_val,
+                                blob_features,
                             },
                             index_x,
                             index_y,
@@ -472,18 +493,20 @@ struct StartupState {
     mean_squared_im: FastImageData<Chan1, f32>, // "running_sumsq" in realtime_image_analysis
 }
 
-pub enum DatagramSocket {
+pub enum CamdataSocket {
     Udp(UdpSocket),
     #[cfg(feature = "flydra-uds")]
     Uds(unix_socket::UnixDatagram),
+    Tcp(TcpCamdataSender),
 }
 
-impl std::fmt::Debug for DatagramSocket {
+impl std::fmt::Debug for CamdataSocket {
     fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
-            DatagramSocket::Udp(s) => writeln!(fmt, "DatagramSocket::Udp({:?})", s),
+            CamdataSocket::Udp(s) => writeln!(fmt, "CamdataSocket::Udp({:?})", s),
             #[cfg(feature = "flydra-uds")]
-            DatagramSocket::Uds(s) => writeln!(fmt, "DatagramSocket::Uds({:?})", s),
+            CamdataSocket::Uds(s) => writeln!(fmt, "CamdataSocket::Uds({:?})", s),
+            CamdataSocket::Tcp(s) => writeln!(fmt, "CamdataSocket::Tcp({:?})", s),
         }
     }
 }
@@ -511,16 +534,22 @@ macro_rules! do_send {
     }};
 }
 
-impl DatagramSocket {
+impl CamdataSocket {
     fn send_complete(&self, x: &[u8]) -> Result<()> {
-        use DatagramSocket::*;
+        use CamdataSocket::*;
         match self {
             Udp(s) => do_send!(s, x),
             #[cfg(feature = "flydra-uds")]
             Uds(s) => do_send!(s, x),
+            Tcp(s) => s.send(x),
         }
         Ok(())
     }
+
+    /// Whether this is a stream, which always carries CBOR encoded packets.
+    fn is_stream(&self) -> bool {
+        matches!(self, CamdataSocket::Tcp(_))
+    }
 }
 
 #[inline]
@@ -559,8 +588,11 @@ pub struct FlyTracker {
     last_sent_raw_image_time: std::time::Instant,
     mask_image: Option<FastImageData<Chan1, u8>>,
     background_update_state: BackgroundAcquisitionState, // command from UI "take a new bg image"
-    coord_socket: Option<DatagramSocket>,
+    coord_socket: Option<CamdataSocket>,
     use_cbor_packets: bool,
+    camdata_protocol: CamdataProtocolInfo,
+    /// The sequence number of the next packet sent.
+    camdata_seq: u64,
     cam_args_tx: CamArgsTxType,
     clock_model: Option<ClockModel>,
     frame_offset: Option<u64>,
@@ -571,13 +603,63 @@ pub struct FlyTracker {
     debug_thread_cjh: (thread_control::Control, std::thread::JoinHandle<()>),
 }
 
-#[derive(Debug)]
 pub enum UfmfState {
     Starting(String),
+    /// Like `Starting`, but first save frames from before the start, e.g. from
+    /// a pre-trigger buffer.
+    ///
+    /// As no points were detected in these frames, each is saved completely.
+    StartingWithFrames(String, Vec<DynamicFrame>),
     Saving(UFMFWriter<File>),
     Stopped,
 }
 
+impl std::fmt::Debug for UfmfState {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            UfmfState::Starting(dest) => f.debug_tuple("Starting").field(dest).finish(),
+            UfmfState::StartingWithFrames(dest, frames) => f
+                .debug_tuple("StartingWithFrames")
+                .field(dest)
+                .field(&format!("{} frames", frames.len()))
+                .finish(),
+            UfmfState::Saving(writer) => f.debug_tuple("Saving").field(writer).finish(),
+            UfmfState::Stopped => f.write_str("Stopped"),
+        }
+    }
+}
+
+/// Create a UFMF file at `dest` and save `buffered` frames to it.
+///
+/// The first buffered frame, or `frame` if there are none, is saved as
+/// keyframe "frame0".
+fn start_ufmf(
+    dest: &str,
+    frame: &DynamicFrame,
+    buffered: &[DynamicFrame],
+) -> Result<UFMFWriter<File>> {
+    let path = std::path::Path::new(dest);
+    info!("saving UFMF to path {}", path.display());
+    let f = std::fs::File::create(&path)?;
+    let frame0 = buffered.first().unwrap_or(frame);
+    let mut ufmf_writer = UFMFWriter::new(
+        f,
+        cast::u16(frame.width())?,
+        cast::u16(frame.height())?,
+        frame.pixel_format(),
+        Some(frame0),
+    )?;
+    for buffered_frame in buffered.iter() {
+        let (w, h) = (
+            cast::u16(buffered_frame.width())?,
+            cast::u16(buffered_frame.height())?,
+        );
+        let full_frame = ufmf::RectFromCenter::from_xy_wh(w / 2, h / 2, w, h);
+        ufmf_writer.add_frame(buffered_frame, &vec![full_frame])?;
+    }
+    Ok(ufmf_writer)
+}
+
 const NUM_MSEC_BINS: usize = 100;
 const WARN_THRESH_MSEC: usize = 60;
 
@@ -730,6 +812,11 @@ impl FlyTracker {
 
         let orig_cam_name = cam_name.clone();
 
+        let camdata_protocol = CamdataProtocolInfo::new(
+            version_str.clone(),
+            CamdataCapabilities::BLOB_FEATURES | CamdataCapabilities::APRILTAGS,
+        );
+
         let (
             ros_cam_name,
             camdata_dest_addr,
@@ -762,6 +849,7 @@ impl FlyTracker {
                 orig_cam_name.clone(),
                 http_camserver_info,
                 ros_cam_name,
+                camdata_protocol.clone(),
                 transmit_current_image_rx,
             );
 
@@ -773,6 +861,13 @@ impl FlyTracker {
                 );
                 match result {
                     Ok(()) => {}
+                    Err(e @ Error::RegistrationRejected { .. }) => {
+                        error!(
+                            "camera '{}' cannot be used with braid: {}",
+                            orig_cam_name.as_str(),
+                            e
+                        );
+                    }
                     Err(e) => {
                         error!("error: {} ({}:{})", e, file!(), line!());
                     }
@@ -801,6 +896,8 @@ impl FlyTracker {
             background_update_state: BackgroundAcquisitionState::Initialization,
             coord_socket: None,
             use_cbor_packets,
+            camdata_protocol,
+            camdata_seq: 0,
             cam_args_tx,
             clock_model: None,
             frame_offset,
@@ -845,7 +942,7 @@ impl FlyTracker {
                         socket.set_write_timeout(Some(timeout))?;
                         info!("UDS connecting to {:?}", uds.filename);
                         socket.connect(&uds.filename)?;
-                        result = Some(DatagramSocket::Uds(socket));
+                        result = Some(CamdataSocket::Uds(socket));
                     }
                     #[cfg(not(feature = "flydra-uds"))]
                     &RealtimePointsDestAddr::UnixDomainSocket(ref _uds) => {
@@ -878,10 +975,14 @@ impl FlyTracker {
                             coord_socket.set_write_timeout(Some(timeout))?;
                             debug!("UDP connecting to {}", dest);
                             coord_socket.connect(&dest)?;
-                            result = Some(DatagramSocket::Udp(coord_socket));
+                            result = Some(CamdataSocket::Udp(coord_socket));
                             break;
                         }
                     }
+                    &RealtimePointsDestAddr::TcpStream(ref dest_ip_addr) => {
+                        let dest = format!("{}:{}", dest_ip_addr.ip(), dest_ip_addr.port());
+                        result = Some(CamdataSocket::Tcp(TcpCamdataSender::connect(dest)?));
+                    }
                 }
                 result
             }
@@ -929,6 +1030,17 @@ impl FlyTracker {
         &mut self,
         frame: &DynamicFrame,
         ufmf_state: UfmfState,
+    ) -> Result<(FlydraRawUdpPacket, UfmfState)> {
+        self.process_new_frame_with_apriltags(frame, ufmf_state, vec![])
+    }
+
+    /// Like [Self::process_new_frame] but also send the April Tags detected in
+    /// `frame` to braid.
+    pub fn process_new_frame_with_apriltags(
+        &mut self,
+        frame: &DynamicFrame,
+        ufmf_state: UfmfState,
+        apriltags: Vec<FlydraRawUdpAprilTag>,
     ) -> Result<(FlydraRawUdpPacket, UfmfState)> {
         let pixel_format = frame.pixel_format();
         let mut saved_bg_image = None;
@@ -962,19 +1074,13 @@ impl FlyTracker {
         let mut do_save_ufmf_bg = false;
         let mut new_ufmf_state = match ufmf_state {
             UfmfState::Starting(dest) => {
-                let path = std::path::Path::new(&dest);
-                info!("saving UFMF to path {}", path.display());
-                let f = std::fs::File::create(&path)?;
-                let ufmf_writer = UFMFWriter::new(
-                    f,
-                    cast::u16(frame.width())?,
-                    cast::u16(frame.height())?,
-                    frame.pixel_format(),
-                    Some(frame),
-                )?;
                 // save current background state when starting ufmf save.
                 do_save_ufmf_bg = true;
-                UfmfState::Saving(ufmf_writer)
+                UfmfState::Saving(start_ufmf(&dest, frame, &[])?)
+            }
+            UfmfState::StartingWithFrames(dest, buffered) => {
+                do_save_ufmf_bg = true;
+                UfmfState::Saving(start_ufmf(&dest, frame, &buffered)?)
             }
             UfmfState::Saving(ufmf_writer) => UfmfState::Saving(ufmf_writer),
             UfmfState::Stopped => {
@@ -1017,6 +1123,8 @@ impl FlyTracker {
             preprocess_stamp,
             image_processing_steps: ImageProcessingSteps::empty(),
             points: vec![],
+            apriltags,
+            seq: None,
         };
 
         sample_vec.push((dur_to_f64(q1.elapsed()), line!()));
@@ -1190,8 +1298,14 @@ impl FlyTracker {
                 sample_vec.push((dur_to_f64(q1.elapsed()), line!()));
 
                 if let Some(ref coord_socket) = self.coord_socket {
-                    let data: Vec<u8> = match self.use_cbor_packets {
-                        true => serde_cbor::ser::to_vec_packed_sd(&packet)?,
+                    packet.seq = Some(self.camdata_seq);
+                    self.camdata_seq += 1;
+                    let use_cbor = self.use_cbor_packets || coord_socket.is_stream();
+                    let data: Vec<u8> = match use_cbor {
+                        true => serde_cbor::ser::to_vec_packed_sd(&CamdataEnvelope {
+                            protocol: self.camdata_protocol.clone(),
+                            packet: &packet,
+                        })?,
                         false => serialize_packet(&packet, self.hack_binning)?,
                     };
                     coord_socket.send_complete(&data)?;
@@ -1227,11 +1341,17 @@ async fn register_node_and_update_image(
     orig_cam_name: flydra_types::RawCamName,
     http_camserver_info: flydra_types::CamHttpServerInfo,
     ros_cam_name: RosCamName,
+    camdata_protocol: CamdataProtocolInfo,
     mut transmit_current_image_rx: mpsc::Receiver<Vec<u8>>,
 ) -> Result<()> {
     let mut mainbrain_session = mainbrain_future_session(api_http_address).await?;
     mainbrain_session
-        .register_flydra_camnode(orig_cam_name, http_camserver_info, ros_cam_name.clone())
+        .register_flydra_camnode(
+            orig_cam_name,
+            http_camserver_info,
+            ros_cam_name.clone(),
+            camdata_protocol,
+        )
         .await?;
     while let Some(image_png_vecu8) = transmit_current_image_rx.next().await {
         mainbrain_session
@@ -27,21 +27,16 @@ pub struct MainbrainSession {
 }
 
 impl MainbrainSession {
-    async fn do_post(&mut self, bytes: Vec<u8>) -> Result<(), hyper::Error> {
+    async fn do_post(
+        &mut self,
+        bytes: Vec<u8>,
+    ) -> Result<hyper::Response<hyper::Body>, hyper::Error> {
         let body = hyper::Body::from(bytes);
 
         let resp = self.inner.post("callback", body).await?;
 
         debug!("called do_post and got response: {:?}", resp);
-        if !resp.status().is_success() {
-            error!(
-                "error: POST response was not a success {}:{}",
-                file!(),
-                line!()
-            );
-            // TODO: return Err(_)?
-        };
-        Ok(())
+        Ok(resp)
     }
 
     pub async fn register_flydra_camnode(
@@ -49,17 +44,30 @@ impl MainbrainSession {
         orig_cam_name: flydra_types::RawCamName,
         http_camserver_info: flydra_types::CamHttpServerInfo,
         ros_cam_name: flydra_types::RosCamName,
-    ) -> Result<(), hyper::Error> {
+        camdata_protocol: flydra_types::CamdataProtocolInfo,
+    ) -> crate::Result<()> {
         let msg = flydra_types::RegisterNewCamera {
             orig_cam_name,
             http_camserver_info,
             ros_cam_name,
+            camdata_protocol: Some(camdata_protocol),
         };
 
         debug!("register_flydra_camnode with message {:?}", msg);
         let msg = flydra_types::HttpApiCallback::NewCamera(msg);
         let bytes = serde_json::to_vec(&msg).unwrap();
-        self.do_post(bytes).await
+        let resp = self.do_post(bytes).await?;
+        let status = resp.status();
+        if !status.is_success() {
+            // Braid rejected the camera, e.g. because of an incompatible camera
+            // data protocol. The body holds the reason.
+            let body = hyper::body::to_bytes(resp.into_body()).await?;
+            return Err(crate::Error::RegistrationRejected {
+                status,
+                reason: String::from_utf8_lossy(&body).into_owned(),
+            });
+        }
+        Ok(())
     }
 
     pub async fn update_image(
@@ -75,6 +83,14 @@ impl MainbrainSession {
         debug!("update_image with message {:?}", msg);
         let msg = flydra_types::HttpApiCallback::UpdateCurrentImage(msg);
         let bytes = serde_json::to_vec(&msg).unwrap();
-        self.do_post(bytes).await
+        let resp = self.do_post(bytes).await?;
+        if !resp.status().is_success() {
+            error!(
+                "error: POST response was not a success {}:{}",
+                file!(),
+                line!()
+            );
+        };
+        Ok(())
     }
 }
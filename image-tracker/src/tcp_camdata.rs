@@ -0,0 +1,147 @@
+use std::io::Write;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+use std::time::{Duration, Instant};
+
+use crossbeam_ok::CrossbeamOk;
+
+use crate::Result;
+
+/// Number of packets waiting to be sent before new packets are dropped.
+const QUEUE_SIZE: usize = 100;
+/// A write blocking longer than this breaks the connection.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(1);
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Sends camera data to braid over TCP.
+///
+/// The packets are written by a separate thread so that a slow or broken
+/// connection never blocks the tracking. If the queue to that thread is full,
+/// packets are dropped and counted. If the connection fails, it is reopened.
+/// Braid accounts for the missing packets with their sequence numbers.
+pub(crate) struct TcpCamdataSender {
+    dest: String,
+    tx: channellib::Sender<Vec<u8>>,
+    n_dropped: Arc<AtomicUsize>,
+}
+
+impl std::fmt::Debug for TcpCamdataSender {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(fmt, "TcpCamdataSender({})", self.dest)
+    }
+}
+
+impl TcpCamdataSender {
+    /// Connect to `dest` and start the sending thread.
+    pub(crate) fn connect(dest: String) -> Result<Self> {
+        debug!("TCP connecting to {}", dest);
+        let stream = connect(&dest)?;
+
+        let (tx, rx) = channellib::bounded::<Vec<u8>>(QUEUE_SIZE);
+        let n_dropped = Arc::new(AtomicUsize::new(0));
+
+        let dest2 = dest.clone();
+        let n_dropped2 = n_dropped.clone();
+        std::thread::Builder::new()
+            .name("tcp-camdata".to_string())
+            .spawn(move || send_loop(dest2, stream, rx, n_dropped2))?;
+
+        Ok(Self {
+            dest,
+            tx,
+            n_dropped,
+        })
+    }
+
+    /// Queue a packet for sending without blocking.
+    pub(crate) fn send(&self, x: &[u8]) {
+        if self.tx.is_full() {
+            count_dropped(&self.n_dropped, &self.dest, "send queue full");
+            return;
+        }
+        // Prefix the length, as expected by braid's `LengthDelimitedCodec`.
+        let mut buf = Vec::with_capacity(4 + x.len());
+        buf.extend_from_slice(&(x.len() as u32).to_be_bytes());
+        buf.extend_from_slice(x);
+        self.tx.send(buf).cb_ok();
+    }
+}
+
+fn connect(dest: &str) -> std::io::Result<TcpStream> {
+    let mut last_err = None;
+    for addr in dest.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT) {
+            Ok(stream) => {
+                // Do not wait to combine packets.
+                stream.set_nodelay(true)?;
+                stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+                return Ok(stream);
+            }
+            Err(e) => {
+                last_err = Some(e);
+            }
+        }
+    }
+    Err(last_err.unwrap_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("no address for {}", dest),
+        )
+    }))
+}
+
+fn count_dropped(n_dropped: &AtomicUsize, dest: &str, reason: &str) {
+    let n = n_dropped.fetch_add(1, Ordering::Relaxed) + 1;
+    // Do not log every packet.
+    if n.is_power_of_two() {
+        warn!(
+            "dropping camera data to {} ({}), {} packets dropped so far",
+            dest, reason, n
+        );
+    }
+}
+
+/// Write the queued packets until the sender is dropped.
+fn send_loop(
+    dest: String,
+    stream: TcpStream,
+    rx: channellib::Receiver<Vec<u8>>,
+    n_dropped: Arc<AtomicUsize>,
+) {
+    let mut stream = Some(stream);
+    let mut last_connect_attempt = Instant::now();
+    while let Ok(buf) = rx.recv() {
+        if stream.is_none() && last_connect_attempt.elapsed() >= RECONNECT_INTERVAL {
+            last_connect_attempt = Instant::now();
+            match connect(&dest) {
+                Ok(s) => {
+                    info!("reconnected camera data stream to {}", dest);
+                    stream = Some(s);
+                }
+                Err(e) => {
+                    debug!("reconnecting camera data stream to {}: {}", dest, e);
+                }
+            }
+        }
+        match &mut stream {
+            Some(s) => {
+                if let Err(e) = s.write_all(&buf) {
+                    // A packet may have been partially written, so the stream
+                    // cannot be used anymore.
+                    error!("sending camera data to {}: {}", dest, e);
+                    stream = None;
+                    last_connect_attempt = Instant::now();
+                    count_dropped(&n_dropped, &dest, "not connected");
+                }
+            }
+            None => {
+                count_dropped(&n_dropped, &dest, "not connected");
+            }
+        }
+    }
+    debug!("camera data stream to {} done", dest);
+}
@@ -0,0 +1,912 @@
+//! Read Matroska (MKV) files saved by `mkv-writer`.
+//!
+//! Besides the video frames, this returns the metadata saved by `mkv-writer`:
+//! the camera framenumber and timestamps of each frame and, for the whole
+//! file, the camera name and attachments such as the camera calibration.
+//!
+//! Frames are returned as encoded by the codec given in [MkvInfo::codec_id]
+//! and must be decoded by the caller.
+#![cfg_attr(feature = "backtrace", feature(backtrace))]
+
+use std::{
+    collections::VecDeque,
+    io::{Read, Seek, SeekFrom},
+};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// Codec ID of the track holding the [FrameMetadata] of each frame.
+pub const FRAME_METADATA_CODEC_ID: &str = "D_STRANDCAM/FRAME_METADATA";
+
+/// Name of the tag holding the camera name.
+pub const CAMERA_NAME_TAG: &str = "CAMERA_NAME";
+
+// EBML element IDs
+const EBML: u32 = 0x1A45_DFA3;
+const DOC_TYPE: u32 = 0x4282;
+
+// Matroska element IDs
+const SEGMENT: u32 = 0x1853_8067;
+const SEEK_HEAD: u32 = 0x114D_9B74;
+const INFO: u32 = 0x1549_A966;
+const TIMESTAMP_SCALE: u32 = 0x2A_D7B1;
+const DURATION: u32 = 0x4489;
+const DATE_UTC: u32 = 0x4461;
+const MUXING_APP: u32 = 0x4D80;
+const WRITING_APP: u32 = 0x5741;
+const TRACKS: u32 = 0x1654_AE6B;
+const TRACK_ENTRY: u32 = 0xAE;
+const TRACK_NUMBER: u32 = 0xD7;
+const TRACK_TYPE: u32 = 0x83;
+const CODEC_ID: u32 = 0x86;
+const VIDEO: u32 = 0xE0;
+const PIXEL_WIDTH: u32 = 0xB0;
+const PIXEL_HEIGHT: u32 = 0xBA;
+const TAGS: u32 = 0x1254_C367;
+const TAG: u32 = 0x7373;
+const SIMPLE_TAG: u32 = 0x67C8;
+const TAG_NAME: u32 = 0x45A3;
+const TAG_STRING: u32 = 0x4487;
+const ATTACHMENTS: u32 = 0x1941_A469;
+const ATTACHED_FILE: u32 = 0x61A7;
+const FILE_DESCRIPTION: u32 = 0x467E;
+const FILE_NAME: u32 = 0x466E;
+const FILE_MIME_TYPE: u32 = 0x4660;
+const FILE_DATA: u32 = 0x465C;
+const CLUSTER: u32 = 0x1F43_B675;
+const TIMESTAMP: u32 = 0xE7;
+const SIMPLE_BLOCK: u32 = 0xA3;
+const BLOCK_GROUP: u32 = 0xA0;
+const BLOCK: u32 = 0xA1;
+const REFERENCE_BLOCK: u32 = 0xFB;
+const CUES: u32 = 0x1C53_BB6B;
+const CHAPTERS: u32 = 0x1043_A770;
+
+/// The elements which can directly follow a Cluster in the Segment.
+const TOP_LEVEL_IDS: &[u32] = &[
+    SEEK_HEAD,
+    INFO,
+    TRACKS,
+    CHAPTERS,
+    CLUSTER,
+    CUES,
+    ATTACHMENTS,
+    TAGS,
+];
+
+const TRACK_TYPE_VIDEO: u64 = 1;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("IO error: {source}")]
+    IoError {
+        #[from]
+        source: std::io::Error,
+        #[cfg(feature = "backtrace")]
+        backtrace: std::backtrace::Backtrace,
+    },
+    #[error("JSON error: {source}")]
+    JsonError {
+        #[from]
+        source: serde_json::Error,
+        #[cfg(feature = "backtrace")]
+        backtrace: std::backtrace::Backtrace,
+    },
+    #[error("not a Matroska file")]
+    NotMatroska,
+    #[error("invalid element at position {0}")]
+    InvalidElement(u64),
+    #[error("no video track")]
+    NoVideoTrack,
+    #[error("laced blocks are not supported")]
+    LacingNotSupported,
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// Metadata saved with each frame.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FrameMetadata {
+    /// The framenumber from the camera.
+    pub framenumber: u64,
+    /// The time the frame was acquired by the host computer.
+    pub host_timestamp: chrono::DateTime<chrono::Utc>,
+    /// The time the frame was triggered (seconds since the UNIX epoch) if the
+    /// camera was synchronized to a trigger box.
+    pub trigger_timestamp: Option<f64>,
+}
+
+/// A file attached to the video, such as the camera calibration.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Attachment {
+    pub filename: String,
+    pub mime_type: String,
+    pub description: Option<String>,
+    pub data: Vec<u8>,
+}
+
+/// Information about the whole file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MkvInfo {
+    pub codec_id: String,
+    pub width: u32,
+    pub height: u32,
+    /// The creation time of the video.
+    pub date_utc: Option<chrono::DateTime<chrono::Utc>>,
+    pub duration: Option<std::time::Duration>,
+    pub muxing_app: Option<String>,
+    pub writing_app: Option<String>,
+    pub camera_name: Option<String>,
+    pub attachments: Vec<Attachment>,
+}
+
+/// A single video frame.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    /// The presentation timestamp relative to the start of the video.
+    pub pts: std::time::Duration,
+    /// The presentation timestamp as absolute time (if the creation time of
+    /// the video is known).
+    pub timestamp: Option<chrono::DateTime<chrono::Utc>>,
+    pub keyframe: bool,
+    /// The encoded frame.
+    pub data: Vec<u8>,
+    pub metadata: Option<FrameMetadata>,
+}
+
+struct Tracks {
+    video: Option<(u64, String, u32, u32)>,
+    metadata: Option<u64>,
+}
+
+/// Reads frames and metadata from an MKV file.
+///
+/// Iterating over the reader returns the frames in the order they are stored
+/// in the file. A file which was not completely written (e.g. because the
+/// recording was interrupted) is read up to the last complete cluster.
+///
+/// Clusters of unknown size, as written by live streaming muxers, end at the
+/// next top level element (e.g. the next Cluster or the Cues) or at the end
+/// of the segment.
+pub struct MkvReader<R>
+where
+    R: Read + Seek,
+{
+    rdr: R,
+    info: MkvInfo,
+    timestamp_scale: u64,
+    video_track: u64,
+    metadata_track: Option<u64>,
+    /// The absolute position of the next element to read.
+    pos: u64,
+    /// The absolute position of the end of the segment.
+    end: u64,
+    queue: VecDeque<Frame>,
+}
+
+impl<R> MkvReader<R>
+where
+    R: Read + Seek,
+{
+    pub fn new(mut rdr: R) -> Result<Self> {
+        let file_len = rdr.seek(SeekFrom::End(0))?;
+
+        let ebml = read_header(&mut rdr, 0, file_len)?.ok_or(Error::NotMatroska)?;
+        if ebml.id != EBML {
+            return Err(Error::NotMatroska);
+        }
+        let ebml_size = ebml.size.ok_or(Error::InvalidElement(0))?;
+        let ebml_data = read_data(&mut rdr, ebml.data_start, ebml_size)?;
+        let doc_type = children(&ebml_data, ebml.data_start)?
+            .into_iter()
+            .find(|(id, _)| *id == DOC_TYPE)
+            .map(|(_, data)| read_string(data));
+        match doc_type.as_deref() {
+            Some("matroska") | Some("webm") => {}
+            _ => return Err(Error::NotMatroska),
+        }
+
+        let segment_pos = ebml.data_start + ebml_size;
+        let segment = read_header(&mut rdr, segment_pos, file_len)?.ok_or(Error::NotMatroska)?;
+        if segment.id != SEGMENT {
+            return Err(Error::NotMatroska);
+        }
+        let end = match segment.size {
+            Some(size) => (segment.data_start + size).min(file_len),
+            None => file_len,
+        };
+
+        let mut timestamp_scale = 1_000_000;
+        let mut date_utc = None;
+        let mut duration = None;
+        let mut muxing_app = None;
+        let mut writing_app = None;
+        let mut tracks = Tracks {
+            video: None,
+            metadata: None,
+        };
+        let mut camera_name = None;
+        let mut attachments = Vec::new();
+
+        // Read the top level elements up to the first cluster.
+        let mut pos = segment.data_start;
+        while let Some(header) = read_header(&mut rdr, pos, end)? {
+            if header.id == CLUSTER {
+                break;
+            }
+            let size = header.size.ok_or(Error::InvalidElement(pos))?;
+            let next = header.data_start + size;
+            if next > end {
+                break;
+            }
+            match header.id {
+                INFO => {
+                    let data = read_data(&mut rdr, header.data_start, size)?;
+                    for (id, value) in children(&data, header.data_start)? {
+                        match id {
+                            TIMESTAMP_SCALE => timestamp_scale = read_uint(value),
+                            DURATION => duration = read_float(value),
+                            DATE_UTC => date_utc = Some(read_date(value)),
+                            MUXING_APP => muxing_app = Some(read_string(value)),
+                            WRITING_APP => writing_app = Some(read_string(value)),
+                            _ => {}
+                        }
+                    }
+                }
+                TRACKS => {
+                    let data = read_data(&mut rdr, header.data_start, size)?;
+                    tracks = parse_tracks(&data, header.data_start)?;
+                }
+                TAGS => {
+                    let data = read_data(&mut rdr, header.data_start, size)?;
+                    for (name, value) in parse_tags(&data, header.data_start)? {
+                        if name == CAMERA_NAME_TAG {
+                            camera_name = Some(value);
+                        }
+                    }
+                }
+                ATTACHMENTS => {
+                    let data = read_data(&mut rdr, header.data_start, size)?;
+                    attachments = parse_attachments(&data, header.data_start)?;
+                }
+                _ => {}
+            }
+            pos = next;
+        }
+
+        let (video_track, codec_id, width, height) = tracks.video.ok_or(Error::NoVideoTrack)?;
+        let duration = duration
+            .map(|d| std::time::Duration::from_nanos((d * timestamp_scale as f64).round() as u64));
+
+        Ok(Self {
+            rdr,
+            info: MkvInfo {
+                codec_id,
+                width,
+                height,
+                date_utc,
+                duration,
+                muxing_app,
+                writing_app,
+                camera_name,
+                attachments,
+            },
+            timestamp_scale,
+            video_track,
+            metadata_track: tracks.metadata,
+            pos,
+            end,
+            queue: VecDeque::new(),
+        })
+    }
+
+    pub fn info(&self) -> &MkvInfo {
+        &self.info
+    }
+
+    /// Read the next cluster into the queue of frames.
+    ///
+    /// Returns `false` if there is no further complete cluster.
+    fn read_cluster(&mut self) -> Result<bool> {
+        loop {
+            let header = match read_header(&mut self.rdr, self.pos, self.end)? {
+                Some(header) => header,
+                None => return Ok(false),
+            };
+            let size = match header.size {
+                Some(size) => size,
+                None if header.id == CLUSTER => self.unknown_cluster_size(header.data_start)?,
+                None => return Err(Error::InvalidElement(self.pos)),
+            };
+            let next = header.data_start + size;
+            if next > self.end {
+                // incomplete element at the end of the file
+                return Ok(false);
+            }
+            self.pos = next;
+            if header.id == CLUSTER {
+                let data = read_data(&mut self.rdr, header.data_start, size)?;
+                self.parse_cluster(&data, header.data_start)?;
+                return Ok(true);
+            }
+        }
+    }
+
+    /// Find the size of the cluster with unknown size whose data starts at
+    /// `data_start` by reading the headers of its children.
+    ///
+    /// An incomplete child at the end of the file is not part of the cluster.
+    fn unknown_cluster_size(&mut self, data_start: u64) -> Result<u64> {
+        let mut pos = data_start;
+        while let Some(header) = read_header(&mut self.rdr, pos, self.end)? {
+            if TOP_LEVEL_IDS.contains(&header.id) {
+                break;
+            }
+            let size = header.size.ok_or(Error::InvalidElement(pos))?;
+            let next = header.data_start + size;
+            if next > self.end {
+                break;
+            }
+            pos = next;
+        }
+        Ok(pos - data_start)
+    }
+
+    fn parse_cluster(&mut self, data: &[u8], pos: u64) -> Result<()> {
+        let mut cluster_timestamp = 0;
+        let mut blocks = Vec::new();
+        for (id, value) in children(data, pos)? {
+            match id {
+                TIMESTAMP => cluster_timestamp = read_uint(value),
+                SIMPLE_BLOCK => {
+                    let keyframe = block_flags(value, pos)? & 0x80 != 0;
+                    blocks.push((value, keyframe));
+                }
+                BLOCK_GROUP => {
+                    let group = children(value, pos)?;
+                    let keyframe = !group.iter().any(|(id, _)| *id == REFERENCE_BLOCK);
+                    if let Some((_, block)) = group.into_iter().find(|(id, _)| *id == BLOCK) {
+                        blocks.push((block, keyframe));
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let mut frames: Vec<Frame> = Vec::new();
+        for (block, keyframe) in blocks {
+            let (track, track_len) =
+                read_vint(block, 0, false).ok_or(Error::InvalidElement(pos))?;
+            if block.len() < track_len + 3 {
+                return Err(Error::InvalidElement(pos));
+            }
+            if block_flags(block, pos)? & 0x06 != 0 {
+                return Err(Error::LacingNotSupported);
+            }
+            let relative = i16::from_be_bytes([block[track_len], block[track_len + 1]]);
+            let payload = &block[track_len + 3..];
+            let ticks = (cluster_timestamp as i64 + relative as i64).max(0) as u64;
+            let pts = std::time::Duration::from_nanos(ticks * self.timestamp_scale);
+
+            if track == self.video_track {
+                let timestamp = self
+                    .info
+                    .date_utc
+                    .map(|d| d + chrono::Duration::from_std(pts).unwrap());
+                frames.push(Frame {
+                    pts,
+                    timestamp,
+                    keyframe,
+                    data: payload.to_vec(),
+                    metadata: None,
+                });
+            } else if Some(track) == self.metadata_track {
+                // The metadata is stored directly after its frame.
+                if let Some(frame) = frames.last_mut() {
+                    if frame.pts == pts && frame.metadata.is_none() {
+                        frame.metadata = Some(serde_json::from_slice(payload)?);
+                    }
+                }
+            }
+        }
+        self.queue.extend(frames);
+        Ok(())
+    }
+}
+
+impl<R> Iterator for MkvReader<R>
+where
+    R: Read + Seek,
+{
+    type Item = Result<Frame>;
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.queue.is_empty() {
+            match self.read_cluster() {
+                Ok(true) => {}
+                Ok(false) => return None,
+                Err(e) => {
+                    // Do not attempt to read further.
+                    self.pos = self.end;
+                    return Some(Err(e));
+                }
+            }
+        }
+        self.queue.pop_front().map(Ok)
+    }
+}
+
+struct ElementHeader {
+    id: u32,
+    data_start: u64,
+    /// The size of the data, `None` if unknown.
+    size: Option<u64>,
+}
+
+/// Read the element header at `pos`.
+///
+/// Returns `None` if the header does not fit before `end`.
+fn read_header<R: Read + Seek>(rdr: &mut R, pos: u64, end: u64) -> Result<Option<ElementHeader>> {
+    if pos + 2 > end {
+        return Ok(None);
+    }
+    rdr.seek(SeekFrom::Start(pos))?;
+    let mut buf = [0u8; 12];
+    let n = ((end - pos) as usize).min(buf.len());
+    rdr.read_exact(&mut buf[..n])?;
+    let buf = &buf[..n];
+
+    let (id, id_len) = match read_vint(buf, 0, true) {
+        Some(x) => x,
+        None => return Ok(None),
+    };
+    if id_len > 4 {
+        return Err(Error::InvalidElement(pos));
+    }
+    let (size, size_len) = match read_vint(buf, id_len, false) {
+        Some(x) => x,
+        None => return Ok(None),
+    };
+    // A size with all bits set means "unknown".
+    let size = if size == (1 << (7 * size_len)) - 1 {
+        None
+    } else {
+        Some(size)
+    };
+    Ok(Some(ElementHeader {
+        id: id as u32,
+        data_start: pos + (id_len + size_len) as u64,
+        size,
+    }))
+}
+
+fn read_data<R: Read + Seek>(rdr: &mut R, pos: u64, size: u64) -> Result<Vec<u8>> {
+    rdr.seek(SeekFrom::Start(pos))?;
+    let mut buf = vec![0; size as usize];
+    rdr.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+/// Read a variable size integer, returning the value and its length.
+///
+/// If `keep_marker` is true (as for element IDs), the length marker bit is
+/// kept in the returned value.
+fn read_vint(buf: &[u8], pos: usize, keep_marker: bool) -> Option<(u64, usize)> {
+    let first = *buf.get(pos)?;
+    let len = first.leading_zeros() as usize + 1;
+    if len > 8 || pos + len > buf.len() {
+        return None;
+    }
+    let mut value = if keep_marker {
+        first as u64
+    } else {
+        (first as u64) & (0xFF >> len)
+    };
+    for b in &buf[pos + 1..pos + len] {
+        value = (value << 8) | *b as u64;
+    }
+    Some((value, len))
+}
+
+/// Split the data of a master element into its children.
+///
+/// `pos` is only used for error messages.
+fn children(buf: &[u8], pos: u64) -> Result<Vec<(u32, &[u8])>> {
+    let mut result = Vec::new();
+    let mut i = 0;
+    while i < buf.len() {
+        let (id, id_len) = read_vint(buf, i, true).ok_or(Error::InvalidElement(pos))?;
+        let (size, size_len) =
+            read_vint(buf, i + id_len, false).ok_or(Error::InvalidElement(pos))?;
+        let start = i + id_len + size_len;
+        let stop = start + size as usize;
+        if stop > buf.len() {
+            return Err(Error::InvalidElement(pos));
+        }
+        result.push((id as u32, &buf[start..stop]));
+        i = stop;
+    }
+    Ok(result)
+}
+
+fn block_flags(block: &[u8], pos: u64) -> Result<u8> {
+    let (_, track_len) = read_vint(block, 0, false).ok_or(Error::InvalidElement(pos))?;
+    block
+        .get(track_len + 2)
+        .copied()
+        .ok_or(Error::InvalidElement(pos))
+}
+
+fn read_uint(buf: &[u8]) -> u64 {
+    buf.iter().fold(0, |acc, b| (acc << 8) | *b as u64)
+}
+
+fn read_float(buf: &[u8]) -> Option<f64> {
+    match buf.len() {
+        4 => Some(f32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]]) as f64),
+        8 => {
+            let mut bytes = [0; 8];
+            bytes.copy_from_slice(buf);
+            Some(f64::from_be_bytes(bytes))
+        }
+        _ => None,
+    }
+}
+
+fn read_string(buf: &[u8]) -> String {
+    // Strings may be padded with zeros.
+    let len = buf.iter().position(|b| *b == 0).unwrap_or(buf.len());
+    String::from_utf8_lossy(&buf[..len]).into_owned()
+}
+
+/// Read a date, saved as nanoseconds since 2001-01-01T00:00:00 UTC.
+fn read_date(buf: &[u8]) -> chrono::DateTime<chrono::Utc> {
+    use chrono::TimeZone;
+    let mut nanos = read_uint(buf) as i64;
+    if buf.len() < 8 && !buf.is_empty() && buf[0] & 0x80 != 0 {
+        // sign extend
+        nanos -= 1 << (8 * buf.len());
+    }
+    chrono::Utc.ymd(2001, 1, 1).and_hms(0, 0, 0) + chrono::Duration::nanoseconds(nanos)
+}
+
+fn parse_tracks(data: &[u8], pos: u64) -> Result<Tracks> {
+    let mut tracks = Tracks {
+        video: None,
+        metadata: None,
+    };
+    for (id, entry) in children(data, pos)? {
+        if id != TRACK_ENTRY {
+            continue;
+        }
+        let mut number = None;
+        let mut track_type = None;
+        let mut codec_id = String::new();
+        let mut width = 0;
+        let mut height = 0;
+        for (id, value) in children(entry, pos)? {
+            match id {
+                TRACK_NUMBER => number = Some(read_uint(value)),
+                TRACK_TYPE => track_type = Some(read_uint(value)),
+                CODEC_ID => codec_id = read_string(value),
+                VIDEO => {
+                    for (id, value) in children(value, pos)? {
+                        match id {
+                            PIXEL_WIDTH => width = read_uint(value) as u32,
+                            PIXEL_HEIGHT => height = read_uint(value) as u32,
+                            _ => {}
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        let number = number.ok_or(Error::InvalidElement(pos))?;
+        if codec_id == FRAME_METADATA_CODEC_ID {
+            tracks.metadata = Some(number);
+        } else if track_type == Some(TRACK_TYPE_VIDEO) && tracks.video.is_none() {
+            tracks.video = Some((number, codec_id, width, height));
+        }
+    }
+    Ok(tracks)
+}
+
+fn parse_tags(data: &[u8], pos: u64) -> Result<Vec<(String, String)>> {
+    let mut result = Vec::new();
+    for (id, tag) in children(data, pos)? {
+        if id != TAG {
+            continue;
+        }
+        for (id, simple_tag) in children(tag, pos)? {
+            if id != SIMPLE_TAG {
+                continue;
+            }
+            let mut name = None;
+            let mut value = None;
+            for (id, x) in children(simple_tag, pos)? {
+                match id {
+                    TAG_NAME => name = Some(read_string(x)),
+                    TAG_STRING => value = Some(read_string(x)),
+                    _ => {}
+                }
+            }
+            if let (Some(name), Some(value)) = (name, value) {
+                result.push((name, value));
+            }
+        }
+    }
+    Ok(result)
+}
+
+fn parse_attachments(data: &[u8], pos: u64) -> Result<Vec<Attachment>> {
+    let mut result = Vec::new();
+    for (id, file) in children(data, pos)? {
+        if id != ATTACHED_FILE {
+            continue;
+        }
+        let mut attachment = Attachment {
+            filename: String::new(),
+            mime_type: String::new(),
+            description: None,
+            data: Vec::new(),
+        };
+        for (id, value) in children(file, pos)? {
+            match id {
+                FILE_NAME => attachment.filename = read_string(value),
+                FILE_MIME_TYPE => attachment.mime_type = read_string(value),
+                FILE_DESCRIPTION => attachment.description = Some(read_string(value)),
+                FILE_DATA => attachment.data = value.to_vec(),
+                _ => {}
+            }
+        }
+        result.push(attachment);
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const ATTACHMENT_FILE_UID: u32 = 0x46AE;
+    const TRACK_TYPE_METADATA: u64 = 0x21;
+
+    fn write_id(buf: &mut Vec<u8>, id: u32) {
+        let bytes = id.to_be_bytes();
+        let n_leading_zeros = bytes.iter().take_while(|b| **b == 0).count();
+        buf.extend_from_slice(&bytes[n_leading_zeros..]);
+    }
+
+    /// Write a size as 8 byte variable size integer.
+    fn write_size8(buf: &mut Vec<u8>, size: u64) {
+        buf.push(0x01);
+        buf.extend_from_slice(&size.to_be_bytes()[1..]);
+    }
+
+    fn elem(buf: &mut Vec<u8>, id: u32, content: &[u8]) {
+        write_id(buf, id);
+        write_size8(buf, content.len() as u64);
+        buf.extend_from_slice(content);
+    }
+
+    /// An element of unknown size.
+    fn unknown_size_elem(buf: &mut Vec<u8>, id: u32, content: &[u8]) {
+        write_id(buf, id);
+        write_size8(buf, (1 << 56) - 1);
+        buf.extend_from_slice(content);
+    }
+
+    fn uint_elem(buf: &mut Vec<u8>, id: u32, value: u64) {
+        elem(buf, id, &value.to_be_bytes());
+    }
+
+    fn str_elem(buf: &mut Vec<u8>, id: u32, value: &str) {
+        elem(buf, id, value.as_bytes());
+    }
+
+    /// The EBML header and the start of a segment.
+    fn file_start(doc_type: &str, segment_size: Option<u64>) -> Vec<u8> {
+        let mut buf = Vec::new();
+        let mut e = Vec::new();
+        str_elem(&mut e, DOC_TYPE, doc_type);
+        elem(&mut buf, EBML, &e);
+        write_id(&mut buf, SEGMENT);
+        write_size8(&mut buf, segment_size.unwrap_or((1 << 56) - 1));
+        buf
+    }
+
+    fn info(duration: Option<f64>) -> Vec<u8> {
+        let mut e = Vec::new();
+        uint_elem(&mut e, TIMESTAMP_SCALE, 1_000_000);
+        if let Some(duration) = duration {
+            elem(&mut e, DURATION, &duration.to_be_bytes());
+        }
+        str_elem(&mut e, MUXING_APP, "test");
+        let mut buf = Vec::new();
+        elem(&mut buf, INFO, &e);
+        buf
+    }
+
+    fn tracks(codec_id: &str, with_metadata: bool) -> Vec<u8> {
+        let mut video = Vec::new();
+        uint_elem(&mut video, PIXEL_WIDTH, 32);
+        uint_elem(&mut video, PIXEL_HEIGHT, 16);
+        let mut entry = Vec::new();
+        uint_elem(&mut entry, TRACK_NUMBER, 1);
+        uint_elem(&mut entry, TRACK_TYPE, TRACK_TYPE_VIDEO);
+        str_elem(&mut entry, CODEC_ID, codec_id);
+        elem(&mut entry, VIDEO, &video);
+
+        let mut e = Vec::new();
+        elem(&mut e, TRACK_ENTRY, &entry);
+        if with_metadata {
+            let mut entry = Vec::new();
+            uint_elem(&mut entry, TRACK_NUMBER, 2);
+            uint_elem(&mut entry, TRACK_TYPE, TRACK_TYPE_METADATA);
+            str_elem(&mut entry, CODEC_ID, FRAME_METADATA_CODEC_ID);
+            elem(&mut e, TRACK_ENTRY, &entry);
+        }
+        let mut buf = Vec::new();
+        elem(&mut buf, TRACKS, &e);
+        buf
+    }
+
+    fn simple_block(buf: &mut Vec<u8>, track: u8, relative: i16, keyframe: bool, data: &[u8]) {
+        let mut block = vec![0x80 | track];
+        block.extend_from_slice(&relative.to_be_bytes());
+        block.push(if keyframe { 0x80 } else { 0x00 });
+        block.extend_from_slice(data);
+        elem(buf, SIMPLE_BLOCK, &block);
+    }
+
+    /// The content of a cluster with one frame `i` at `timestamp`.
+    fn cluster_content(i: u8, timestamp: u64, keyframe: bool) -> Vec<u8> {
+        let mut content = Vec::new();
+        uint_elem(&mut content, TIMESTAMP, timestamp);
+        simple_block(&mut content, 1, 0, keyframe, &[i; 10]);
+        content
+    }
+
+    #[test]
+    fn read_metadata() {
+        let mut body = info(Some(190.0));
+        body.extend(tracks("V_VP9", true));
+        {
+            let mut simple_tag = Vec::new();
+            str_elem(&mut simple_tag, TAG_NAME, CAMERA_NAME_TAG);
+            str_elem(&mut simple_tag, TAG_STRING, "cam1");
+            let mut tag = Vec::new();
+            elem(&mut tag, SIMPLE_TAG, &simple_tag);
+            let mut e = Vec::new();
+            elem(&mut e, TAG, &tag);
+            elem(&mut body, TAGS, &e);
+        }
+        {
+            let mut file = Vec::new();
+            str_elem(&mut file, FILE_DESCRIPTION, "camera calibration");
+            str_elem(&mut file, FILE_NAME, "cal.xml");
+            str_elem(&mut file, FILE_MIME_TYPE, "application/xml");
+            elem(&mut file, FILE_DATA, b"<xml></xml>");
+            uint_elem(&mut file, ATTACHMENT_FILE_UID, 1);
+            let mut e = Vec::new();
+            elem(&mut e, ATTACHED_FILE, &file);
+            elem(&mut body, ATTACHMENTS, &e);
+        }
+        for cluster in 0..4u8 {
+            let mut content = Vec::new();
+            uint_elem(&mut content, TIMESTAMP, cluster as u64 * 50);
+            for j in 0..5u8 {
+                let i = cluster * 5 + j;
+                let metadata = format!(
+                    "{{\"framenumber\":{},\"host_timestamp\":\"2001-01-01T00:00:01Z\",\"trigger_timestamp\":null}}",
+                    i as u64 + 100
+                );
+                let relative = j as i16 * 10;
+                simple_block(&mut content, 1, relative, j == 0, &[i; 10]);
+                simple_block(&mut content, 2, relative, true, metadata.as_bytes());
+            }
+            elem(&mut body, CLUSTER, &content);
+        }
+        let mut buf = file_start("matroska", Some(body.len() as u64));
+        buf.extend(body);
+
+        let reader = MkvReader::new(std::io::Cursor::new(buf)).unwrap();
+        let info = reader.info().clone();
+        assert_eq!(info.codec_id, "V_VP9");
+        assert_eq!((info.width, info.height), (32, 16));
+        assert_eq!(info.muxing_app.as_deref(), Some("test"));
+        assert_eq!(info.camera_name.as_deref(), Some("cam1"));
+        assert_eq!(
+            info.attachments,
+            vec![Attachment {
+                filename: "cal.xml".to_string(),
+                mime_type: "application/xml".to_string(),
+                description: Some("camera calibration".to_string()),
+                data: b"<xml></xml>".to_vec(),
+            }]
+        );
+        assert_eq!(info.duration, Some(std::time::Duration::from_millis(190)));
+
+        let frames: Vec<_> = reader.collect::<Result<_>>().unwrap();
+        assert_eq!(frames.len(), 20);
+        for (i, frame) in frames.iter().enumerate() {
+            assert_eq!(frame.pts, std::time::Duration::from_millis(i as u64 * 10));
+            assert_eq!(frame.keyframe, i % 5 == 0);
+            assert_eq!(frame.data, vec![i as u8; 10]);
+            assert_eq!(frame.metadata.as_ref().unwrap().framenumber, i as u64 + 100);
+        }
+    }
+
+    #[test]
+    fn live_stream() {
+        let mut buf = file_start("webm", None);
+        buf.extend(info(None));
+        buf.extend(tracks("V_VP8", false));
+        for i in 0..10u8 {
+            elem(
+                &mut buf,
+                CLUSTER,
+                &cluster_content(i, i as u64 * 40, i == 0),
+            );
+        }
+
+        let reader = MkvReader::new(std::io::Cursor::new(buf)).unwrap();
+        let info = reader.info().clone();
+        assert_eq!(info.codec_id, "V_VP8");
+        assert_eq!(info.duration, None);
+
+        let frames: Vec<_> = reader.collect::<Result<_>>().unwrap();
+        assert_eq!(frames.len(), 10);
+        for (i, frame) in frames.iter().enumerate() {
+            assert_eq!(frame.pts, std::time::Duration::from_millis(i as u64 * 40));
+            assert_eq!(frame.keyframe, i == 0);
+            assert_eq!(frame.data, vec![i as u8; 10]);
+            assert_eq!(frame.metadata, None);
+        }
+    }
+
+    #[test]
+    fn unknown_size_clusters() {
+        let mut buf = file_start("webm", None);
+        buf.extend(info(None));
+        buf.extend(tracks("V_VP8", false));
+        for i in 0..5u8 {
+            unknown_size_elem(&mut buf, CLUSTER, &cluster_content(i, i as u64 * 40, true));
+        }
+        // Cues end the last unknown size cluster.
+        elem(&mut buf, CUES, &[]);
+        unknown_size_elem(&mut buf, CLUSTER, &cluster_content(5, 200, true));
+        // A cluster which reaches the end of the file and whose last block was
+        // not completely written.
+        let mut content = cluster_content(6, 240, true);
+        let mut partial = Vec::new();
+        simple_block(&mut partial, 1, 40, false, &[7; 10]);
+        content.extend_from_slice(&partial[..partial.len() - 3]);
+        unknown_size_elem(&mut buf, CLUSTER, &content);
+
+        let reader = MkvReader::new(std::io::Cursor::new(buf)).unwrap();
+        let frames: Vec<_> = reader.collect::<Result<_>>().unwrap();
+        assert_eq!(frames.len(), 7);
+        for (i, frame) in frames.iter().enumerate() {
+            assert_eq!(frame.pts, std::time::Duration::from_millis(i as u64 * 40));
+            assert_eq!(frame.data, vec![i as u8; 10]);
+        }
+    }
+
+    #[test]
+    fn truncated_file() {
+        let mut body = info(None);
+        body.extend(tracks("V_VP8", false));
+        for i in 0..3u8 {
+            elem(&mut body, CLUSTER, &cluster_content(i, i as u64 * 40, true));
+        }
+        let mut buf = file_start("webm", Some(body.len() as u64));
+        buf.extend(body);
+        // Cut the last cluster.
+        buf.truncate(buf.len() - 5);
+
+        let reader = MkvReader::new(std::io::Cursor::new(buf)).unwrap();
+        let frames: Vec<_> = reader.collect::<Result<_>>().unwrap();
+        assert_eq!(frames.len(), 2);
+    }
+}
@@ -156,6 +156,7 @@ fn main() -> Result<(), anyhow::Error> {
         codec,
         max_framerate: ci2_remote_control::RecordingFrameRate::Unlimited,
         writing_application: None,
+        crash_safe: false,
     };
 
     let mut my_mkv_writer = mkv_writer::MkvWriter::new(out_fd, cfg, libs_and_nv_enc)?;
@@ -0,0 +1,995 @@
+//! A minimal Matroska muxer for a single video track.
+//!
+//! Besides the video frames, the muxer writes a SeekHead, the Cues (the seek
+//! index) and the Duration of the segment so that the resulting file can be
+//! seeked without being remuxed. In crash-safe mode, the Cues and Duration are
+//! additionally updated at a regular interval while recording so that a file
+//! which is never finished (e.g. after a power cut) remains seekable up to the
+//! last update.
+//!
+//! The Cues are written into a region which is padded with a Void element and
+//! rewritten in place as long as they fit. Once they outgrow it, they move to
+//! the end of the file into a region twice their size, so the total space lost
+//! to abandoned regions stays proportional to the size of the final Cues.
+//!
+//! This is used by `mkv-writer` for files and, through [LiveMuxer], for live
+//! streams which do not need any of the encoders.
+
+use std::io::{Seek, SeekFrom, Write};
+
+use mkv_reader::{Attachment, CAMERA_NAME_TAG, FRAME_METADATA_CODEC_ID};
+
+// EBML element IDs
+const EBML: u32 = 0x1A45_DFA3;
+const EBML_VERSION: u32 = 0x4286;
+const EBML_READ_VERSION: u32 = 0x42F7;
+const EBML_MAX_ID_LENGTH: u32 = 0x42F2;
+const EBML_MAX_SIZE_LENGTH: u32 = 0x42F3;
+const DOC_TYPE: u32 = 0x4282;
+const DOC_TYPE_VERSION: u32 = 0x4287;
+const DOC_TYPE_READ_VERSION: u32 = 0x4285;
+const VOID: u32 = 0xEC;
+
+// Matroska element IDs
+const SEGMENT: u32 = 0x1853_8067;
+const SEEK_HEAD: u32 = 0x114D_9B74;
+const SEEK: u32 = 0x4DBB;
+const SEEK_ID: u32 = 0x53AB;
+const SEEK_POSITION: u32 = 0x53AC;
+const INFO: u32 = 0x1549_A966;
+const TIMESTAMP_SCALE: u32 = 0x2A_D7B1;
+const DURATION: u32 = 0x4489;
+const DATE_UTC: u32 = 0x4461;
+const MUXING_APP: u32 = 0x4D80;
+const WRITING_APP: u32 = 0x5741;
+const TRACKS: u32 = 0x1654_AE6B;
+const TRACK_ENTRY: u32 = 0xAE;
+const TRACK_NUMBER: u32 = 0xD7;
+const TRACK_UID: u32 = 0x73C5;
+const TRACK_TYPE: u32 = 0x83;
+const FLAG_LACING: u32 = 0x9C;
+const CODEC_ID: u32 = 0x86;
+const VIDEO: u32 = 0xE0;
+const PIXEL_WIDTH: u32 = 0xB0;
+const PIXEL_HEIGHT: u32 = 0xBA;
+const CLUSTER: u32 = 0x1F43_B675;
+const TIMESTAMP: u32 = 0xE7;
+const SIMPLE_BLOCK: u32 = 0xA3;
+const CUES: u32 = 0x1C53_BB6B;
+const CUE_POINT: u32 = 0xBB;
+const CUE_TIME: u32 = 0xB3;
+const CUE_TRACK_POSITIONS: u32 = 0xB7;
+const CUE_TRACK: u32 = 0xF7;
+const CUE_CLUSTER_POSITION: u32 = 0xF1;
+const TAGS: u32 = 0x1254_C367;
+const TAG: u32 = 0x7373;
+const TARGETS: u32 = 0x63C0;
+const SIMPLE_TAG: u32 = 0x67C8;
+const TAG_NAME: u32 = 0x45A3;
+const TAG_STRING: u32 = 0x4487;
+const ATTACHMENTS: u32 = 0x1941_A469;
+const ATTACHED_FILE: u32 = 0x61A7;
+const FILE_DESCRIPTION: u32 = 0x467E;
+const FILE_NAME: u32 = 0x466E;
+const FILE_MIME_TYPE: u32 = 0x4660;
+const FILE_DATA: u32 = 0x465C;
+const FILE_UID: u32 = 0x46AE;
+
+/// Timestamps are stored in milliseconds.
+const TIMESTAMP_SCALE_NANOS: u64 = 1_000_000;
+
+/// Bytes reserved at the start of the segment for the SeekHead.
+const SEEK_HEAD_RESERVED: usize = 160;
+
+/// The smallest region reserved for the Cues in crash-safe mode.
+const MIN_CUES_RESERVED: u64 = 4096;
+
+/// The size of a Void element header with an 8 byte size.
+const VOID_HEADER_LEN: u64 = 9;
+
+/// A cluster is closed once it holds this much data, even without a keyframe.
+const MAX_CLUSTER_BYTES: usize = 32 * 1024 * 1024;
+
+/// The number of the video track.
+const VIDEO_TRACK: u64 = 1;
+
+/// The number of the track with the metadata of each frame.
+const METADATA_TRACK: u64 = 2;
+
+const TRACK_TYPE_VIDEO: u64 = 1;
+const TRACK_TYPE_METADATA: u64 = 0x21;
+
+/// The all-ones value of an 8 byte variable size integer, meaning "unknown".
+const UNKNOWN_SIZE: u64 = (1 << 56) - 1;
+
+/// The codec of the video track.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Codec {
+    Vp8,
+    Vp9,
+    H264,
+    Ffv1,
+}
+
+impl Codec {
+    fn codec_id(&self) -> &'static str {
+        match self {
+            Codec::Vp8 => "V_VP8",
+            Codec::Vp9 => "V_VP9",
+            Codec::H264 => "V_MPEG4/ISO/AVC",
+            Codec::Ffv1 => "V_FFV1",
+        }
+    }
+
+    fn doc_type(&self) -> &'static str {
+        match self {
+            Codec::Vp8 | Codec::Vp9 => "webm",
+            Codec::H264 | Codec::Ffv1 => "matroska",
+        }
+    }
+}
+
+/// The contents of the file headers.
+pub struct MuxConfig<'a> {
+    pub width: u32,
+    pub height: u32,
+    pub codec: Codec,
+    /// Nanoseconds since 2001-01-01T00:00:00 UTC.
+    pub date_utc: i64,
+    pub writing_app: &'a str,
+    pub camera_name: Option<&'a str>,
+    pub attachments: &'a [Attachment],
+    /// Add the track with the metadata of each frame. As this track is not
+    /// allowed in WebM, the DocType is then always "matroska".
+    pub metadata_track: bool,
+    /// If set, update the Cues and Duration after this many nanoseconds of
+    /// video.
+    pub crash_safe_interval: Option<u64>,
+}
+
+struct Cluster {
+    timestamp: u64,
+    starts_with_keyframe: bool,
+    blocks: Vec<u8>,
+}
+
+struct CuePoint {
+    time: u64,
+    /// Position of the cluster relative to the start of the segment data.
+    cluster_pos: u64,
+}
+
+/// Writes a seekable Matroska file.
+pub struct Muxer<T>
+where
+    T: Write + Seek,
+{
+    fd: T,
+    /// Absolute position of the (8 byte) size of the segment.
+    segment_size_pos: u64,
+    /// Absolute position of the start of the segment data.
+    segment_data_start: u64,
+    /// ID and position (relative to the segment data) of the elements
+    /// written before the first cluster.
+    header_elements: Vec<(u32, u64)>,
+    /// Absolute position of the Duration value.
+    duration_pos: u64,
+    /// Absolute position of the end of the data written so far.
+    end_pos: u64,
+    cluster: Option<Cluster>,
+    cues: Vec<CuePoint>,
+    /// Absolute position and size of the region holding the Cues.
+    cues_region: Option<(u64, u64)>,
+    metadata_track: bool,
+    /// The largest timestamp (in milliseconds) of all frames.
+    max_timestamp: Option<u64>,
+    crash_safe_interval: Option<u64>,
+    last_index_update: u64,
+}
+
+impl<T> Muxer<T>
+where
+    T: Write + Seek,
+{
+    /// Write the headers of a new file.
+    pub fn new(mut fd: T, cfg: MuxConfig) -> std::io::Result<Self> {
+        let start = fd.stream_position()?;
+
+        let mut header = Vec::new();
+        {
+            let mut e = Vec::new();
+            uint_elem(&mut e, EBML_VERSION, 1);
+            uint_elem(&mut e, EBML_READ_VERSION, 1);
+            uint_elem(&mut e, EBML_MAX_ID_LENGTH, 4);
+            uint_elem(&mut e, EBML_MAX_SIZE_LENGTH, 8);
+            let doc_type = if cfg.metadata_track {
+                "matroska"
+            } else {
+                cfg.codec.doc_type()
+            };
+            str_elem(&mut e, DOC_TYPE, doc_type);
+            uint_elem(&mut e, DOC_TYPE_VERSION, 4);
+            uint_elem(&mut e, DOC_TYPE_READ_VERSION, 2);
+            master_elem(&mut header, EBML, &e);
+        }
+        write_id(&mut header, SEGMENT);
+        let segment_size_pos = start + header.len() as u64;
+        write_unknown_size(&mut header);
+        let segment_data_start = start + header.len() as u64;
+
+        let info_pos = SEEK_HEAD_RESERVED as u64;
+        let mut info = Vec::new();
+        let duration_offset;
+        {
+            let mut e = Vec::new();
+            uint_elem(&mut e, TIMESTAMP_SCALE, TIMESTAMP_SCALE_NANOS);
+            write_id(&mut e, DURATION);
+            write_size(&mut e, 8);
+            duration_offset = e.len();
+            e.extend_from_slice(&0.0f64.to_be_bytes());
+            write_id(&mut e, DATE_UTC);
+            write_size(&mut e, 8);
+            e.extend_from_slice(&cfg.date_utc.to_be_bytes());
+            str_elem(
+                &mut e,
+                MUXING_APP,
+                concat!(env!("CARGO_PKG_NAME"), "-", env!("CARGO_PKG_VERSION")),
+            );
+            str_elem(&mut e, WRITING_APP, cfg.writing_app);
+            write_id(&mut info, INFO);
+            write_size8(&mut info, e.len() as u64)?;
+            info.extend_from_slice(&e);
+        }
+        // The Info element starts with a 4 byte ID and an 8 byte size.
+        let duration_pos = segment_data_start + info_pos + 12 + duration_offset as u64;
+
+        // Unique IDs only need to be unique within the file.
+        let uid_base = (cfg.date_utc as u64) & !0xFF;
+
+        let mut tracks = Vec::new();
+        {
+            let mut video = Vec::new();
+            uint_elem(&mut video, PIXEL_WIDTH, cfg.width.into());
+            uint_elem(&mut video, PIXEL_HEIGHT, cfg.height.into());
+
+            let mut entry = Vec::new();
+            uint_elem(&mut entry, TRACK_NUMBER, VIDEO_TRACK);
+            uint_elem(&mut entry, TRACK_UID, uid_base | VIDEO_TRACK);
+            uint_elem(&mut entry, TRACK_TYPE, TRACK_TYPE_VIDEO);
+            uint_elem(&mut entry, FLAG_LACING, 0);
+            str_elem(&mut entry, CODEC_ID, cfg.codec.codec_id());
+            master_elem(&mut entry, VIDEO, &video);
+
+            let mut e = Vec::new();
+            master_elem(&mut e, TRACK_ENTRY, &entry);
+
+            if cfg.metadata_track {
+                let mut metadata_entry = Vec::new();
+                uint_elem(&mut metadata_entry, TRACK_NUMBER, METADATA_TRACK);
+                uint_elem(&mut metadata_entry, TRACK_UID, uid_base | METADATA_TRACK);
+                uint_elem(&mut metadata_entry, TRACK_TYPE, TRACK_TYPE_METADATA);
+                uint_elem(&mut metadata_entry, FLAG_LACING, 0);
+                str_elem(&mut metadata_entry, CODEC_ID, FRAME_METADATA_CODEC_ID);
+                master_elem(&mut e, TRACK_ENTRY, &metadata_entry);
+            }
+            master_elem(&mut tracks, TRACKS, &e);
+        }
+
+        let mut tags = Vec::new();
+        if let Some(camera_name) = cfg.camera_name {
+            let mut simple_tag = Vec::new();
+            str_elem(&mut simple_tag, TAG_NAME, CAMERA_NAME_TAG);
+            str_elem(&mut simple_tag, TAG_STRING, camera_name);
+            let mut tag = Vec::new();
+            // Empty targets means the tag applies to the whole segment.
+            master_elem(&mut tag, TARGETS, &[]);
+            master_elem(&mut tag, SIMPLE_TAG, &simple_tag);
+            let mut e = Vec::new();
+            master_elem(&mut e, TAG, &tag);
+            master_elem(&mut tags, TAGS, &e);
+        }
+
+        let mut attachments = Vec::new();
+        if !cfg.attachments.is_empty() {
+            let mut e = Vec::new();
+            for (i, attachment) in cfg.attachments.iter().enumerate() {
+                let mut file = Vec::new();
+                if let Some(description) = &attachment.description {
+                    str_elem(&mut file, FILE_DESCRIPTION, description);
+                }
+                str_elem(&mut file, FILE_NAME, &attachment.filename);
+                str_elem(&mut file, FILE_MIME_TYPE, &attachment.mime_type);
+                bytes_elem(&mut file, FILE_DATA, &attachment.data);
+                uint_elem(&mut file, FILE_UID, uid_base | (i as u64 + 1));
+                master_elem(&mut e, ATTACHED_FILE, &file);
+            }
+            master_elem(&mut attachments, ATTACHMENTS, &e);
+        }
+
+        let mut header_elements = Vec::new();
+        let mut pos = info_pos;
+        for (id, buf) in &[
+            (INFO, &info),
+            (TRACKS, &tracks),
+            (TAGS, &tags),
+            (ATTACHMENTS, &attachments),
+        ] {
+            if !buf.is_empty() {
+                header_elements.push((*id, pos));
+                pos += buf.len() as u64;
+            }
+        }
+
+        let mut result = Self {
+            fd,
+            segment_size_pos,
+            segment_data_start,
+            header_elements,
+            duration_pos,
+            end_pos: 0,
+            cluster: None,
+            cues: Vec::new(),
+            cues_region: None,
+            metadata_track: cfg.metadata_track,
+            max_timestamp: None,
+            crash_safe_interval: cfg.crash_safe_interval,
+            last_index_update: 0,
+        };
+
+        header.extend_from_slice(&result.seek_head(None)?);
+        header.extend_from_slice(&info);
+        header.extend_from_slice(&tracks);
+        header.extend_from_slice(&tags);
+        header.extend_from_slice(&attachments);
+        result.fd.write_all(&header)?;
+        result.end_pos = start + header.len() as u64;
+        Ok(result)
+    }
+
+    /// Add an encoded frame with its presentation timestamp in nanoseconds.
+    ///
+    /// The `metadata`, if any, is saved in the metadata track directly after
+    /// the frame. It is ignored if the file has no metadata track.
+    pub fn add_frame(
+        &mut self,
+        data: &[u8],
+        pts_nanos: u64,
+        keyframe: bool,
+        metadata: Option<&[u8]>,
+    ) -> std::io::Result<()> {
+        let timestamp = (pts_nanos as f64 / TIMESTAMP_SCALE_NANOS as f64).round() as u64;
+
+        let need_new_cluster = match &self.cluster {
+            None => true,
+            Some(cluster) => {
+                let relative = timestamp as i64 - cluster.timestamp as i64;
+                (keyframe && !cluster.blocks.is_empty())
+                    || relative > i16::MAX as i64
+                    || relative < i16::MIN as i64
+                    || cluster.blocks.len() > MAX_CLUSTER_BYTES
+            }
+        };
+        if need_new_cluster {
+            self.write_cluster()?;
+            self.cluster = Some(Cluster {
+                timestamp,
+                starts_with_keyframe: keyframe,
+                blocks: Vec::new(),
+            });
+        }
+
+        let cluster = self.cluster.as_mut().unwrap();
+        let relative = (timestamp as i64 - cluster.timestamp as i64) as i16;
+        simple_block(&mut cluster.blocks, VIDEO_TRACK, relative, keyframe, data);
+        if let (true, Some(metadata)) = (self.metadata_track, metadata) {
+            simple_block(
+                &mut cluster.blocks,
+                METADATA_TRACK,
+                relative,
+                true,
+                metadata,
+            );
+        }
+
+        self.max_timestamp = Some(self.max_timestamp.unwrap_or(0).max(timestamp));
+
+        if let Some(interval) = self.crash_safe_interval {
+            let interval = interval / TIMESTAMP_SCALE_NANOS;
+            if timestamp >= self.last_index_update + interval {
+                self.write_cluster()?;
+                self.update_index(true)?;
+                self.last_index_update = timestamp;
+            }
+        }
+        Ok(())
+    }
+
+    /// Write all pending data, the Cues and the final Duration and size.
+    pub fn finish(mut self) -> std::io::Result<T> {
+        self.write_cluster()?;
+        self.update_index(false)?;
+
+        let mut size = Vec::new();
+        write_size8(&mut size, self.end_pos - self.segment_data_start)?;
+        self.write_at(self.segment_size_pos, &size)?;
+        self.fd.seek(SeekFrom::Start(self.end_pos))?;
+        self.fd.flush()?;
+        Ok(self.fd)
+    }
+
+    /// Write the pending cluster (if any) to the end of the file.
+    fn write_cluster(&mut self) -> std::io::Result<()> {
+        let cluster = match self.cluster.take() {
+            Some(cluster) => cluster,
+            None => return Ok(()),
+        };
+
+        let mut content = Vec::new();
+        uint_elem(&mut content, TIMESTAMP, cluster.timestamp);
+        content.extend_from_slice(&cluster.blocks);
+        let mut buf = Vec::new();
+        master_elem(&mut buf, CLUSTER, &content);
+
+        if cluster.starts_with_keyframe {
+            self.cues.push(CuePoint {
+                time: cluster.timestamp,
+                cluster_pos: self.end_pos - self.segment_data_start,
+            });
+        }
+        self.fd.write_all(&buf)?;
+        self.end_pos += buf.len() as u64;
+        Ok(())
+    }
+
+    /// Write the current Cues and update the SeekHead and Duration to match.
+    ///
+    /// The Cues are rewritten in place if they fit into their region.
+    /// Otherwise they are written to the end of the file, with spare room for
+    /// later updates if `reserve` is set, and the previous region is turned
+    /// into a Void element so that only a single, complete index remains in
+    /// the file.
+    fn update_index(&mut self, reserve: bool) -> std::io::Result<()> {
+        if !self.cues.is_empty() {
+            let mut points = Vec::new();
+            for cue in self.cues.iter() {
+                let mut positions = Vec::new();
+                uint_elem(&mut positions, CUE_TRACK, VIDEO_TRACK);
+                uint_elem(&mut positions, CUE_CLUSTER_POSITION, cue.cluster_pos);
+                let mut point = Vec::new();
+                uint_elem(&mut point, CUE_TIME, cue.time);
+                master_elem(&mut point, CUE_TRACK_POSITIONS, &positions);
+                master_elem(&mut points, CUE_POINT, &point);
+            }
+            let mut buf = Vec::new();
+            master_elem(&mut buf, CUES, &points);
+            let cues_len = buf.len() as u64;
+
+            match self.cues_region {
+                Some((pos, len)) if cues_len == len || cues_len + VOID_HEADER_LEN <= len => {
+                    if cues_len < len {
+                        void_header(&mut buf, len - cues_len)?;
+                    }
+                    self.write_at(pos, &buf)?;
+                }
+                previous => {
+                    let len = if reserve {
+                        (2 * cues_len).max(MIN_CUES_RESERVED)
+                    } else {
+                        cues_len
+                    };
+                    if cues_len < len {
+                        void_header(&mut buf, len - cues_len)?;
+                        buf.resize(len as usize, 0);
+                    }
+                    let cues_pos = self.end_pos;
+                    self.write_at(cues_pos, &buf)?;
+                    self.end_pos += len;
+                    self.cues_region = Some((cues_pos, len));
+
+                    let seek_head = self.seek_head(Some(cues_pos - self.segment_data_start))?;
+                    self.write_at(self.segment_data_start, &seek_head)?;
+
+                    if let Some((pos, len)) = previous {
+                        let mut void = Vec::new();
+                        void_header(&mut void, len)?;
+                        self.write_at(pos, &void)?;
+                    }
+                }
+            }
+        }
+
+        let duration = self.max_timestamp.unwrap_or(0) as f64;
+        self.write_at(self.duration_pos, &duration.to_be_bytes())?;
+
+        self.fd.seek(SeekFrom::Start(self.end_pos))?;
+        self.fd.flush()
+    }
+
+    fn write_at(&mut self, pos: u64, buf: &[u8]) -> std::io::Result<()> {
+        self.fd.seek(SeekFrom::Start(pos))?;
+        self.fd.write_all(buf)
+    }
+
+    /// The SeekHead, padded to its reserved size.
+    fn seek_head(&self, cues_pos: Option<u64>) -> std::io::Result<Vec<u8>> {
+        let mut entries = self.header_elements.clone();
+        if let Some(cues_pos) = cues_pos {
+            entries.push((CUES, cues_pos));
+        }
+        let mut content = Vec::new();
+        for (id, pos) in entries {
+            let mut id_buf = Vec::new();
+            write_id(&mut id_buf, id);
+            let mut seek = Vec::new();
+            bytes_elem(&mut seek, SEEK_ID, &id_buf);
+            uint_elem(&mut seek, SEEK_POSITION, pos);
+            master_elem(&mut content, SEEK, &seek);
+        }
+        let mut buf = Vec::new();
+        master_elem(&mut buf, SEEK_HEAD, &content);
+
+        // The padding is a Void element with a 1 byte size.
+        let remaining = SEEK_HEAD_RESERVED.checked_sub(buf.len());
+        let remaining = match remaining {
+            Some(remaining) if remaining >= 2 && remaining - 2 < 0x7F => remaining,
+            _ => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!(
+                        "SeekHead of {} bytes does not fit into {} reserved bytes",
+                        buf.len(),
+                        SEEK_HEAD_RESERVED
+                    ),
+                ));
+            }
+        };
+        write_id(&mut buf, VOID);
+        write_size(&mut buf, (remaining - 2) as u64);
+        buf.resize(SEEK_HEAD_RESERVED, 0);
+        Ok(buf)
+    }
+}
+
+/// A muxer for a live WebM stream which is consumed as it is produced, e.g.
+/// by the Media Source Extensions API of a web browser.
+///
+/// The stream starts with the initialization segment, followed by one cluster
+/// per frame. As nothing is ever written back, the segment has an unknown size
+/// and there is neither a SeekHead nor Cues.
+pub struct LiveMuxer {
+    width: u32,
+    height: u32,
+    codec: Codec,
+}
+
+impl LiveMuxer {
+    /// Create a muxer for a VP8 or VP9 stream.
+    ///
+    /// Panics for any other codec, as WebM does not allow them.
+    pub fn new(width: u32, height: u32, codec: Codec) -> Self {
+        assert!(
+            matches!(codec, Codec::Vp8 | Codec::Vp9),
+            "{:?} is not allowed in WebM",
+            codec
+        );
+        Self {
+            width,
+            height,
+            codec,
+        }
+    }
+
+    /// The MIME type of the stream, including the codecs parameter.
+    pub fn mime_type(&self) -> &'static str {
+        match self.codec {
+            Codec::Vp9 => "video/webm; codecs=\"vp9\"",
+            _ => "video/webm; codecs=\"vp8\"",
+        }
+    }
+
+    /// The EBML header and the start of the segment with its Info and Tracks.
+    pub fn init_segment(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        {
+            let mut e = Vec::new();
+            uint_elem(&mut e, EBML_VERSION, 1);
+            uint_elem(&mut e, EBML_READ_VERSION, 1);
+            uint_elem(&mut e, EBML_MAX_ID_LENGTH, 4);
+            uint_elem(&mut e, EBML_MAX_SIZE_LENGTH, 8);
+            str_elem(&mut e, DOC_TYPE, self.codec.doc_type());
+            uint_elem(&mut e, DOC_TYPE_VERSION, 4);
+            uint_elem(&mut e, DOC_TYPE_READ_VERSION, 2);
+            master_elem(&mut buf, EBML, &e);
+        }
+        write_id(&mut buf, SEGMENT);
+        write_unknown_size(&mut buf);
+        {
+            let app = concat!(env!("CARGO_PKG_NAME"), "-", env!("CARGO_PKG_VERSION"));
+            let mut e = Vec::new();
+            uint_elem(&mut e, TIMESTAMP_SCALE, TIMESTAMP_SCALE_NANOS);
+            str_elem(&mut e, MUXING_APP, app);
+            str_elem(&mut e, WRITING_APP, app);
+            master_elem(&mut buf, INFO, &e);
+        }
+        {
+            let mut video = Vec::new();
+            uint_elem(&mut video, PIXEL_WIDTH, self.width.into());
+            uint_elem(&mut video, PIXEL_HEIGHT, self.height.into());
+
+            let mut entry = Vec::new();
+            uint_elem(&mut entry, TRACK_NUMBER, VIDEO_TRACK);
+            uint_elem(&mut entry, TRACK_UID, VIDEO_TRACK);
+            uint_elem(&mut entry, TRACK_TYPE, TRACK_TYPE_VIDEO);
+            uint_elem(&mut entry, FLAG_LACING, 0);
+            str_elem(&mut entry, CODEC_ID, self.codec.codec_id());
+            master_elem(&mut entry, VIDEO, &video);
+
+            let mut e = Vec::new();
+            master_elem(&mut e, TRACK_ENTRY, &entry);
+            master_elem(&mut buf, TRACKS, &e);
+        }
+        buf
+    }
+
+    /// A cluster holding a single encoded frame with its presentation
+    /// timestamp in milliseconds.
+    pub fn cluster(&self, data: &[u8], pts_millis: u64, keyframe: bool) -> Vec<u8> {
+        let mut content = Vec::new();
+        uint_elem(&mut content, TIMESTAMP, pts_millis);
+        simple_block(&mut content, VIDEO_TRACK, 0, keyframe, data);
+        let mut buf = Vec::new();
+        master_elem(&mut buf, CLUSTER, &content);
+        buf
+    }
+}
+
+fn simple_block(buf: &mut Vec<u8>, track: u64, relative: i16, keyframe: bool, data: &[u8]) {
+    write_id(buf, SIMPLE_BLOCK);
+    write_size(buf, data.len() as u64 + 4);
+    write_size(buf, track);
+    buf.extend_from_slice(&relative.to_be_bytes());
+    buf.push(if keyframe { 0x80 } else { 0x00 });
+    buf.extend_from_slice(data);
+}
+
+fn write_id(buf: &mut Vec<u8>, id: u32) {
+    let bytes = id.to_be_bytes();
+    let n_leading_zeros = bytes.iter().take_while(|b| **b == 0).count();
+    buf.extend_from_slice(&bytes[n_leading_zeros..]);
+}
+
+/// Write a size using the shortest possible variable size integer.
+fn write_size(buf: &mut Vec<u8>, size: u64) {
+    let mut len = 1;
+    // The all-ones value of each length is reserved.
+    while len < 8 && size >= (1 << (7 * len)) - 1 {
+        len += 1;
+    }
+    if len == 8 {
+        buf.push(0x01);
+        buf.extend_from_slice(&size.to_be_bytes()[1..]);
+        return;
+    }
+    let marked = size | (1 << (7 * len));
+    buf.extend_from_slice(&marked.to_be_bytes()[8 - len..]);
+}
+
+/// Write a size as 8 byte variable size integer so it can be overwritten later.
+fn write_size8(buf: &mut Vec<u8>, size: u64) -> std::io::Result<()> {
+    if size >= UNKNOWN_SIZE {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("element size {} too large", size),
+        ));
+    }
+    buf.push(0x01);
+    buf.extend_from_slice(&size.to_be_bytes()[1..]);
+    Ok(())
+}
+
+/// Write the reserved size value meaning "unknown".
+fn write_unknown_size(buf: &mut Vec<u8>) {
+    buf.push(0x01);
+    buf.extend_from_slice(&UNKNOWN_SIZE.to_be_bytes()[1..]);
+}
+
+/// Write the header of a Void element which is `total_len` bytes long,
+/// including the header.
+fn void_header(buf: &mut Vec<u8>, total_len: u64) -> std::io::Result<()> {
+    write_id(buf, VOID);
+    write_size8(buf, total_len - VOID_HEADER_LEN)
+}
+
+fn uint_elem(buf: &mut Vec<u8>, id: u32, value: u64) {
+    let bytes = value.to_be_bytes();
+    let n_leading_zeros = bytes.iter().take_while(|b| **b == 0).count().min(7);
+    bytes_elem(buf, id, &bytes[n_leading_zeros..]);
+}
+
+fn str_elem(buf: &mut Vec<u8>, id: u32, value: &str) {
+    bytes_elem(buf, id, value.as_bytes());
+}
+
+fn bytes_elem(buf: &mut Vec<u8>, id: u32, value: &[u8]) {
+    write_id(buf, id);
+    write_size(buf, value.len() as u64);
+    buf.extend_from_slice(value);
+}
+
+fn master_elem(buf: &mut Vec<u8>, id: u32, content: &[u8]) {
+    bytes_elem(buf, id, content);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Read an ID or size at `pos`, returning the value (with the length
+    /// marker for IDs) and the number of bytes read.
+    fn read_vint(buf: &[u8], pos: usize, keep_marker: bool) -> (u64, usize) {
+        let len = buf[pos].leading_zeros() as usize + 1;
+        let mut value = if keep_marker {
+            buf[pos] as u64
+        } else {
+            (buf[pos] as u64) & (0xFF >> len)
+        };
+        for b in &buf[pos + 1..pos + len] {
+            value = (value << 8) | *b as u64;
+        }
+        (value, len)
+    }
+
+    /// Return ID, data position and data size of the element at `pos`.
+    fn read_header(buf: &[u8], pos: usize) -> (u32, usize, usize) {
+        let (id, id_len) = read_vint(buf, pos, true);
+        let (size, size_len) = read_vint(buf, pos + id_len, false);
+        (id as u32, pos + id_len + size_len, size as usize)
+    }
+
+    fn children(buf: &[u8], start: usize, size: usize) -> Vec<(u32, usize, usize)> {
+        let mut result = Vec::new();
+        let mut pos = start;
+        while pos < start + size {
+            let child = read_header(buf, pos);
+            pos = child.1 + child.2;
+            result.push(child);
+        }
+        result
+    }
+
+    fn read_uint(buf: &[u8], pos: usize, size: usize) -> u64 {
+        buf[pos..pos + size]
+            .iter()
+            .fold(0, |acc, b| (acc << 8) | *b as u64)
+    }
+
+    /// Check the SeekHead and return the Cues time of each cue point and the
+    /// Duration.
+    fn check_index(buf: &[u8]) -> (Vec<u64>, f64) {
+        let (id, ebml_start, ebml_size) = read_header(buf, 0);
+        assert_eq!(id, EBML);
+        let (id, segment_start, _) = read_header(buf, ebml_start + ebml_size);
+        assert_eq!(id, SEGMENT);
+
+        let (id, seek_head_start, seek_head_size) = read_header(buf, segment_start);
+        assert_eq!(id, SEEK_HEAD);
+        let mut cues = None;
+        let mut duration = None;
+        for (id, start, size) in children(buf, seek_head_start, seek_head_size) {
+            assert_eq!(id, SEEK);
+            let seek = children(buf, start, size);
+            let (target_id, _) = read_vint(buf, seek[0].1, true);
+            let pos = segment_start + read_uint(buf, seek[1].1, seek[1].2) as usize;
+            let (found_id, data_start, data_size) = read_header(buf, pos);
+            assert_eq!(found_id as u64, target_id);
+            match found_id {
+                CUES => {
+                    cues = Some(
+                        children(buf, data_start, data_size)
+                            .into_iter()
+                            .map(|(_, start, size)| {
+                                let time = children(buf, start, size)[0];
+                                assert_eq!(time.0, CUE_TIME);
+                                read_uint(buf, time.1, time.2)
+                            })
+                            .collect(),
+                    )
+                }
+                INFO => {
+                    for (id, start, _) in children(buf, data_start, data_size) {
+                        if id == DURATION {
+                            let mut value = [0; 8];
+                            value.copy_from_slice(&buf[start..start + 8]);
+                            duration = Some(f64::from_be_bytes(value));
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        (cues.expect("no cues"), duration.expect("no duration"))
+    }
+
+    #[test]
+    fn cues_and_duration() {
+        let mut fd = std::io::Cursor::new(Vec::new());
+        let cfg = MuxConfig {
+            width: 32,
+            height: 16,
+            codec: Codec::Vp8,
+            date_utc: 0,
+            writing_app: "test",
+            camera_name: None,
+            attachments: &[],
+            metadata_track: false,
+            crash_safe_interval: Some(1_000_000_000),
+        };
+        let mut muxer = Muxer::new(&mut fd, cfg).unwrap();
+        for i in 0..100 {
+            // 10 frames per second, a keyframe each second
+            let keyframe = i % 10 == 0;
+            muxer
+                .add_frame(&[i as u8; 50], i * 100_000_000, keyframe, None)
+                .unwrap();
+        }
+
+        // Before finishing, the index is valid as of the last update.
+        let (cues, duration) = check_index(muxer.fd.get_ref());
+        assert_eq!(cues, (0..10).map(|i| i * 1000).collect::<Vec<_>>());
+        assert_eq!(duration, 9000.0);
+
+        muxer.finish().unwrap();
+        let buf = fd.into_inner();
+        let (cues, duration) = check_index(&buf);
+        assert_eq!(cues, (0..10).map(|i| i * 1000).collect::<Vec<_>>());
+        assert_eq!(duration, 9900.0);
+
+        // The segment size covers the remaining file.
+        let (_, ebml_start, ebml_size) = read_header(&buf, 0);
+        let (_, segment_start, segment_size) = read_header(&buf, ebml_start + ebml_size);
+        assert_eq!(segment_start + segment_size, buf.len());
+    }
+
+    /// The DocType and the top level elements of the segment.
+    fn doc_type_and_elements(buf: &[u8]) -> (String, Vec<(u32, usize, usize)>) {
+        let (_, ebml_start, ebml_size) = read_header(buf, 0);
+        let doc_type = children(buf, ebml_start, ebml_size)
+            .into_iter()
+            .find(|(id, _, _)| *id == DOC_TYPE)
+            .map(|(_, start, size)| String::from_utf8(buf[start..start + size].to_vec()).unwrap())
+            .unwrap();
+        let (_, segment_start, segment_size) = read_header(buf, ebml_start + ebml_size);
+        (doc_type, children(buf, segment_start, segment_size))
+    }
+
+    #[test]
+    fn cues_space_is_bounded() {
+        let mut fd = std::io::Cursor::new(Vec::new());
+        let cfg = MuxConfig {
+            width: 32,
+            height: 16,
+            codec: Codec::Vp8,
+            date_utc: 0,
+            writing_app: "test",
+            camera_name: None,
+            attachments: &[],
+            metadata_track: false,
+            crash_safe_interval: Some(100_000_000),
+        };
+        let mut muxer = Muxer::new(&mut fd, cfg).unwrap();
+        // 10 keyframes per second with an index update after each.
+        let n_frames = 1000;
+        for i in 0..n_frames {
+            muxer
+                .add_frame(&[i as u8; 10], i * 100_000_000, true, None)
+                .unwrap();
+        }
+        muxer.finish().unwrap();
+        let buf = fd.into_inner();
+
+        let (cues, _) = check_index(&buf);
+        assert_eq!(cues.len(), n_frames as usize);
+
+        let (doc_type, elements) = doc_type_and_elements(&buf);
+        assert_eq!(doc_type, "webm");
+        let cues_size: usize = elements
+            .iter()
+            .filter(|(id, _, _)| *id == CUES)
+            .map(|(_, _, size)| *size)
+            .sum();
+        let void_size: usize = elements
+            .iter()
+            .filter(|(id, _, _)| *id == VOID)
+            .map(|(_, _, size)| *size)
+            .sum();
+        // Appending a new copy of the Cues after each update would leave
+        // megabytes of Void elements.
+        assert!(void_size < 4 * cues_size + MIN_CUES_RESERVED as usize);
+    }
+
+    #[test]
+    fn metadata_track() {
+        let attachments = vec![Attachment {
+            filename: "cal.xml".to_string(),
+            mime_type: "application/xml".to_string(),
+            description: None,
+            data: b"<xml></xml>".to_vec(),
+        }];
+        let mut fd = std::io::Cursor::new(Vec::new());
+        let cfg = MuxConfig {
+            width: 32,
+            height: 16,
+            codec: Codec::Vp9,
+            date_utc: 1_000_000_000,
+            writing_app: "test",
+            camera_name: Some("cam1"),
+            attachments: &attachments,
+            metadata_track: true,
+            crash_safe_interval: None,
+        };
+        let mut muxer = Muxer::new(&mut fd, cfg).unwrap();
+        for i in 0..20u64 {
+            muxer
+                .add_frame(&[i as u8; 10], i * 10_000_000, i % 5 == 0, Some(b"{}"))
+                .unwrap();
+        }
+        muxer.finish().unwrap();
+        let buf = fd.into_inner();
+
+        // The metadata track is not allowed in WebM files.
+        let (doc_type, elements) = doc_type_and_elements(&buf);
+        assert_eq!(doc_type, "matroska");
+
+        let ids: Vec<u32> = elements.iter().map(|(id, _, _)| *id).collect();
+        for id in &[SEEK_HEAD, INFO, TRACKS, TAGS, ATTACHMENTS, CLUSTER, CUES] {
+            assert!(ids.contains(id));
+        }
+        let (_, start, size) = elements.iter().find(|(id, _, _)| *id == TRACKS).unwrap();
+        assert_eq!(children(&buf, *start, *size).len(), 2);
+
+        // Each cluster holds 5 frames, each followed by its metadata.
+        let clusters: Vec<_> = elements
+            .iter()
+            .filter(|(id, _, _)| *id == CLUSTER)
+            .collect();
+        assert_eq!(clusters.len(), 4);
+        for (_, start, size) in clusters {
+            let blocks = children(&buf, *start, *size);
+            assert_eq!(blocks.len(), 11);
+        }
+    }
+
+    #[test]
+    fn live_stream() {
+        let muxer = LiveMuxer::new(32, 16, Codec::Vp8);
+        let mut buf = muxer.init_segment();
+        for i in 0..10u64 {
+            buf.extend(muxer.cluster(&[i as u8; 10], i * 40, i == 0));
+        }
+
+        let (_, ebml_start, ebml_size) = read_header(&buf, 0);
+        let (id, segment_start, segment_size) = read_header(&buf, ebml_start + ebml_size);
+        assert_eq!(id, SEGMENT);
+        assert_eq!(segment_size as u64, UNKNOWN_SIZE);
+        let elements = children(&buf, segment_start, buf.len() - segment_start);
+        let ids: Vec<u32> = elements.iter().map(|(id, _, _)| *id).collect();
+        let mut expected = vec![INFO, TRACKS];
+        expected.extend(vec![CLUSTER; 10]);
+        assert_eq!(ids, expected);
+
+        let (_, start, size) = elements[2 + 3];
+        let cluster = children(&buf, start, size);
+        assert_eq!(cluster[0].0, TIMESTAMP);
+        assert_eq!(read_uint(&buf, cluster[0].1, cluster[0].2), 120);
+        assert_eq!(cluster[1].0, SIMPLE_BLOCK);
+    }
+
+    #[test]
+    fn sizes() {
+        let mut buf = Vec::new();
+        write_size(&mut buf, 1);
+        write_size(&mut buf, 126);
+        write_size(&mut buf, 127);
+        assert_eq!(buf, vec![0x81, 0xFE, 0x40, 0x7F]);
+    }
+}
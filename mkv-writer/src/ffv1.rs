@@ -0,0 +1,900 @@
+//! Lossless FFV1 video encoding.
+//!
+//! This implements the subset of FFV1 version 1 (RFC 9043) needed to save
+//! frames losslessly: every frame is a keyframe with a single slice, the
+//! residuals are Golomb-Rice coded and the context quantization tables are
+//! fixed. Gray images with 8 to 16 bits per sample and 8 bit RGB images (using
+//! the reversible color transform) are supported.
+
+use convert_image::{LosslessColorspace, LosslessFrame};
+
+use crate::{Error, Result};
+
+const VERSION: u32 = 1;
+const CODER_TYPE_GOLOMB_RICE: u32 = 0;
+const COLORSPACE_YCBCR: u32 = 0;
+const COLORSPACE_RGB: u32 = 1;
+
+/// Number of states used to code one range coded symbol.
+const CONTEXT_SIZE: usize = 32;
+
+/// Lengths of the runs of equal values in the positive half of each context
+/// quantization table, giving the values 0 to 5 for differences of 0, 1, 2-4,
+/// 5-11, 12-63 and 64-127.
+const QUANT_RUNS: [u8; 6] = [1, 1, 3, 7, 52, 64];
+
+/// Number of values in each (symmetric) quantization table.
+const QUANT_VALUES: i32 = 2 * QUANT_RUNS.len() as i32 - 1;
+
+/// Number of contexts, using the differences L-TL, TL-T and T-TR.
+const CONTEXT_COUNT: usize = ((QUANT_VALUES * QUANT_VALUES * QUANT_VALUES + 1) / 2) as usize;
+
+/// Escape limit of the Golomb-Rice codes.
+const GOLOMB_LIMIT: u32 = 12;
+
+/// Log2 of the run lengths in run mode.
+const LOG2_RUN: [u8; 41] = [
+    0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 9, 10, 11, 12, 13,
+    14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24,
+];
+
+/// Encode one frame as an FFV1 keyframe.
+pub(crate) fn encode_frame(frame: &LosslessFrame) -> Result<Vec<u8>> {
+    let bits = frame.bits_per_sample;
+    let (colorspace, n_channels) = match frame.colorspace {
+        LosslessColorspace::Gray if (8..=16).contains(&bits) => (COLORSPACE_YCBCR, 1),
+        LosslessColorspace::Rgb if bits == 8 => (COLORSPACE_RGB, 3),
+        _ => return Err(Error::UnsupportedLosslessFrame(frame.colorspace, bits)),
+    };
+    let width = frame.width as usize;
+    let height = frame.height as usize;
+    if frame.samples.len() != width * height * n_channels {
+        return Err(Error::InconsistentState);
+    }
+
+    let mut rac = RangeEncoder::new();
+    let mut keyframe_state = 128;
+    rac.put_bit(&mut keyframe_state, true);
+
+    // Parameters
+    let mut state = [128; CONTEXT_SIZE];
+    rac.put_symbol(&mut state, VERSION as i32, false);
+    rac.put_symbol(&mut state, CODER_TYPE_GOLOMB_RICE as i32, false);
+    rac.put_symbol(&mut state, colorspace as i32, false);
+    rac.put_symbol(&mut state, bits as i32, false);
+    rac.put_bit(&mut state[0], colorspace == COLORSPACE_RGB); // chroma planes
+    rac.put_symbol(&mut state, 0, false); // log2 horizontal chroma subsampling
+    rac.put_symbol(&mut state, 0, false); // log2 vertical chroma subsampling
+    rac.put_bit(&mut state[0], false); // no alpha plane
+    for i in 0..5 {
+        let mut state = [128; CONTEXT_SIZE];
+        if i < 3 {
+            for len in QUANT_RUNS.iter() {
+                rac.put_symbol(&mut state, *len as i32 - 1, false);
+            }
+        } else {
+            // These context inputs (LL-L and TT-T) are not used.
+            rac.put_symbol(&mut state, 127, false);
+        }
+    }
+
+    // The Golomb-Rice coded slice follows the range coded header.
+    let mut slice = SliceEncoder {
+        bits: BitWriter::new(rac.terminate()),
+        quant_table: quant_table(),
+        planes: [
+            vec![VlcState::default(); CONTEXT_COUNT],
+            vec![VlcState::default(); CONTEXT_COUNT],
+        ],
+        run_index: 0,
+    };
+
+    match frame.colorspace {
+        LosslessColorspace::Gray => {
+            let mut lines = Lines::new(width);
+            for row in frame.samples.chunks_exact(width) {
+                lines.next_line();
+                for (dest, src) in lines.current().iter_mut().zip(row.iter()) {
+                    // Samples are signed 16 bit integers in the reference
+                    // implementation, which matters for the median prediction
+                    // of 16 bit data.
+                    *dest = *src as i16 as i32;
+                }
+                slice.encode_line(&mut lines, 0, bits.into());
+            }
+        }
+        LosslessColorspace::Rgb => {
+            let offset = 1 << bits;
+            let mut lines = [Lines::new(width), Lines::new(width), Lines::new(width)];
+            for row in frame.samples.chunks_exact(width * 3) {
+                for plane in lines.iter_mut() {
+                    plane.next_line();
+                }
+                for (x, rgb) in row.chunks_exact(3).enumerate() {
+                    let (r, g, b) = (rgb[0] as i32, rgb[1] as i32, rgb[2] as i32);
+                    // Reversible color transform (JPEG 2000 RCT)
+                    let b = b - g;
+                    let r = r - g;
+                    let g = g + ((b + r) >> 2);
+                    lines[0].current()[x] = g;
+                    lines[1].current()[x] = b + offset;
+                    lines[2].current()[x] = r + offset;
+                }
+                // The transformed chroma needs one more bit. Blue and red share
+                // their contexts.
+                for (i, plane) in lines.iter_mut().enumerate() {
+                    slice.encode_line(plane, i.min(1), u32::from(bits) + 1);
+                }
+            }
+        }
+    }
+
+    Ok(slice.bits.finish())
+}
+
+/// Build the full quantization table from [QUANT_RUNS] as done by decoders.
+fn quant_table() -> [i32; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    for (value, len) in QUANT_RUNS.iter().enumerate() {
+        for _ in 0..*len {
+            table[i] = value as i32;
+            i += 1;
+        }
+    }
+    for i in 1..128 {
+        table[256 - i] = -table[i];
+    }
+    table[128] = -table[127];
+    table
+}
+
+/// Map the difference `v` into the range of signed `bits` bit integers.
+fn fold(v: i32, bits: u32) -> i32 {
+    let shift = 32 - bits;
+    (v << shift) >> shift
+}
+
+fn median(a: i32, b: i32, c: i32) -> i32 {
+    a.max(b).min(a.min(b).max(c))
+}
+
+/// The current and previous line of a plane, with margins on both sides.
+struct Lines {
+    width: usize,
+    /// Two lines of `width + 2` samples, alternating between current and
+    /// previous.
+    buf: Vec<i32>,
+    current_is_first: bool,
+}
+
+impl Lines {
+    fn new(width: usize) -> Self {
+        Self {
+            width,
+            buf: vec![0; 2 * (width + 2)],
+            current_is_first: false,
+        }
+    }
+
+    /// Start a new line, keeping the current one as previous line.
+    fn next_line(&mut self) {
+        self.current_is_first = !self.current_is_first;
+    }
+
+    fn split(&mut self) -> (&mut [i32], &mut [i32]) {
+        let (first, second) = self.buf.split_at_mut(self.width + 2);
+        if self.current_is_first {
+            (first, second)
+        } else {
+            (second, first)
+        }
+    }
+
+    fn current(&mut self) -> &mut [i32] {
+        let width = self.width;
+        &mut self.split().0[1..=width]
+    }
+}
+
+#[derive(Clone, Copy)]
+struct VlcState {
+    drift: i32,
+    error_sum: i32,
+    bias: i32,
+    count: i32,
+}
+
+impl Default for VlcState {
+    fn default() -> Self {
+        Self {
+            drift: 0,
+            error_sum: 4,
+            bias: 0,
+            count: 1,
+        }
+    }
+}
+
+impl VlcState {
+    /// The Golomb-Rice parameter for the next symbol.
+    fn k(&self) -> u32 {
+        let mut k = 0;
+        let mut i = self.count;
+        while i < self.error_sum {
+            k += 1;
+            i += i;
+        }
+        k
+    }
+
+    fn update(&mut self, v: i32) {
+        self.error_sum += v.abs();
+        self.drift += v;
+        if self.count == 128 {
+            self.count >>= 1;
+            self.drift >>= 1;
+            self.error_sum >>= 1;
+        }
+        self.count += 1;
+        if self.drift <= -self.count {
+            self.bias = (self.bias - 1).max(-128);
+            self.drift = (self.drift + self.count).max(-self.count + 1);
+        } else if self.drift > 0 {
+            self.bias = (self.bias + 1).min(127);
+            self.drift = (self.drift - self.count).min(0);
+        }
+    }
+}
+
+struct SliceEncoder {
+    bits: BitWriter,
+    quant_table: [i32; 256],
+    /// Contexts of the luma (or green) and chroma planes.
+    planes: [Vec<VlcState>; 2],
+    run_index: usize,
+}
+
+impl SliceEncoder {
+    fn encode_line(&mut self, lines: &mut Lines, plane: usize, bits: u32) {
+        let width = lines.width;
+        let (cur, prev) = lines.split();
+        // Samples left of the line are taken from the line above.
+        cur[0] = prev[1];
+        prev[width + 1] = prev[width];
+
+        let mut run_mode = false;
+        let mut run_count = 0;
+        for x in 1..=width {
+            let (l, tl, t, tr) = (cur[x - 1], prev[x - 1], prev[x], prev[x + 1]);
+            let q = &self.quant_table;
+            let mut context = q[((l - tl) & 0xFF) as usize]
+                + QUANT_VALUES * q[((tl - t) & 0xFF) as usize]
+                + QUANT_VALUES * QUANT_VALUES * q[((t - tr) & 0xFF) as usize];
+            let mut diff = cur[x] - median(l, l + t - tl, t);
+            if context < 0 {
+                context = -context;
+                diff = -diff;
+            }
+            let mut diff = fold(diff, bits);
+
+            if context == 0 {
+                run_mode = true;
+            }
+            if run_mode {
+                if diff != 0 {
+                    while run_count >= 1 << LOG2_RUN[self.run_index] {
+                        run_count -= 1 << LOG2_RUN[self.run_index];
+                        self.run_index += 1;
+                        self.bits.put(1, 1);
+                    }
+                    self.bits
+                        .put(1 + u32::from(LOG2_RUN[self.run_index]), run_count);
+                    self.run_index = self.run_index.saturating_sub(1);
+                    run_count = 0;
+                    run_mode = false;
+                    if diff > 0 {
+                        diff -= 1;
+                    }
+                } else {
+                    run_count += 1;
+                }
+            }
+            if !run_mode {
+                let state = &mut self.planes[plane][context as usize];
+                put_vlc_symbol(&mut self.bits, state, diff, bits);
+            }
+        }
+        if run_mode {
+            while run_count >= 1 << LOG2_RUN[self.run_index] {
+                run_count -= 1 << LOG2_RUN[self.run_index];
+                self.run_index += 1;
+                self.bits.put(1, 1);
+            }
+            if run_count > 0 {
+                self.bits.put(1, 1);
+            }
+        }
+    }
+}
+
+fn put_vlc_symbol(bits: &mut BitWriter, state: &mut VlcState, v: i32, n_bits: u32) {
+    let v = fold(v - state.bias, n_bits);
+    let k = state.k();
+    let code = v ^ ((2 * state.drift + state.count) >> 31);
+
+    // signed to unsigned: 0, -1, 1, -2, ... to 0, 1, 2, 3, ...
+    let code = if code >= 0 {
+        2 * code as u32
+    } else {
+        (-2 * code - 1) as u32
+    };
+    let e = code >> k;
+    if e < GOLOMB_LIMIT {
+        bits.put(e + k + 1, (1 << k) | (code & ((1 << k) - 1)));
+    } else {
+        bits.put(GOLOMB_LIMIT + n_bits, code - GOLOMB_LIMIT + 1);
+    }
+    state.update(v);
+}
+
+/// Writes bits, most significant bit first.
+struct BitWriter {
+    buf: Vec<u8>,
+    acc: u64,
+    n_acc: u32,
+}
+
+impl BitWriter {
+    fn new(buf: Vec<u8>) -> Self {
+        Self {
+            buf,
+            acc: 0,
+            n_acc: 0,
+        }
+    }
+
+    /// Write the lowest `n` (at most 32) bits of `value`.
+    fn put(&mut self, n: u32, value: u32) {
+        debug_assert!(n <= 32);
+        self.acc = (self.acc << n) | (u64::from(value) & ((1 << n) - 1));
+        self.n_acc += n;
+        while self.n_acc >= 8 {
+            self.n_acc -= 8;
+            self.buf.push((self.acc >> self.n_acc) as u8);
+        }
+    }
+
+    /// Pad to a full byte with zeros and return the data.
+    fn finish(mut self) -> Vec<u8> {
+        if self.n_acc > 0 {
+            self.put(8 - self.n_acc, 0);
+        }
+        self.buf
+    }
+}
+
+/// State transition tables of the range coder.
+struct RacStates {
+    one: [u8; 256],
+    zero: [u8; 256],
+}
+
+impl RacStates {
+    /// The default state transition table of FFV1.
+    fn new() -> Self {
+        const ONE: i64 = 1 << 32;
+        // 0.05 * ONE
+        const FACTOR: i64 = 214_748_364;
+        const MAX_P: i64 = 256 - 8;
+
+        let mut one = [0u8; 256];
+        let mut last_p8 = 0;
+        let mut p = ONE / 2;
+        for _ in 0..128 {
+            let mut p8 = (256 * p + ONE / 2) >> 32;
+            if p8 <= last_p8 {
+                p8 = last_p8 + 1;
+            }
+            if last_p8 > 0 && last_p8 < 256 && p8 <= MAX_P {
+                one[last_p8 as usize] = p8 as u8;
+            }
+            p += ((ONE - p) * FACTOR + ONE / 2) >> 32;
+            last_p8 = p8;
+        }
+        for i in (256 - MAX_P)..=MAX_P {
+            if one[i as usize] != 0 {
+                continue;
+            }
+            let mut p = (i * ONE + 128) >> 8;
+            p += ((ONE - p) * FACTOR + ONE / 2) >> 32;
+            let mut p8 = (256 * p + ONE / 2) >> 32;
+            if p8 <= i {
+                p8 = i + 1;
+            }
+            if p8 > MAX_P {
+                p8 = MAX_P;
+            }
+            one[i as usize] = p8 as u8;
+        }
+
+        let mut zero = [0u8; 256];
+        for i in 1..255 {
+            zero[i] = (256 - one[256 - i] as u32) as u8;
+        }
+        Self { one, zero }
+    }
+}
+
+/// Binary range encoder used for the frame header.
+struct RangeEncoder {
+    buf: Vec<u8>,
+    low: u32,
+    range: u32,
+    outstanding_count: usize,
+    outstanding_byte: Option<u32>,
+    states: RacStates,
+}
+
+impl RangeEncoder {
+    fn new() -> Self {
+        Self {
+            buf: Vec::new(),
+            low: 0,
+            range: 0xFF00,
+            outstanding_count: 0,
+            outstanding_byte: None,
+            states: RacStates::new(),
+        }
+    }
+
+    fn renorm(&mut self) {
+        while self.range < 0x100 {
+            match self.outstanding_byte {
+                None => {
+                    self.outstanding_byte = Some(self.low >> 8);
+                }
+                Some(byte) if self.low <= 0xFF00 => {
+                    self.buf.push(byte as u8);
+                    let len = self.buf.len() + self.outstanding_count;
+                    self.buf.resize(len, 0xFF);
+                    self.outstanding_count = 0;
+                    self.outstanding_byte = Some(self.low >> 8);
+                }
+                Some(byte) if self.low >= 0x10000 => {
+                    self.buf.push((byte + 1) as u8);
+                    let len = self.buf.len() + self.outstanding_count;
+                    self.buf.resize(len, 0x00);
+                    self.outstanding_count = 0;
+                    self.outstanding_byte = Some((self.low >> 8) - 0x100);
+                }
+                Some(_) => {
+                    self.outstanding_count += 1;
+                }
+            }
+            self.low = (self.low & 0xFF) << 8;
+            self.range <<= 8;
+        }
+    }
+
+    fn put_bit(&mut self, state: &mut u8, bit: bool) {
+        let range1 = (self.range * u32::from(*state)) >> 8;
+        if bit {
+            self.low += self.range - range1;
+            self.range = range1;
+            *state = self.states.one[*state as usize];
+        } else {
+            self.range -= range1;
+            *state = self.states.zero[*state as usize];
+        }
+        self.renorm();
+    }
+
+    fn put_symbol(&mut self, state: &mut [u8; CONTEXT_SIZE], v: i32, is_signed: bool) {
+        if v == 0 {
+            self.put_bit(&mut state[0], true);
+            return;
+        }
+        let a = v.unsigned_abs();
+        let e = 31 - a.leading_zeros() as usize;
+        self.put_bit(&mut state[0], false);
+        for i in 0..e {
+            self.put_bit(&mut state[1 + i.min(9)], true);
+        }
+        self.put_bit(&mut state[1 + e.min(9)], false);
+        for i in (0..e).rev() {
+            self.put_bit(&mut state[22 + i.min(9)], (a >> i) & 1 == 1);
+        }
+        if is_signed {
+            self.put_bit(&mut state[11 + e.min(10)], v < 0);
+        }
+    }
+
+    /// Finish range coding and return the data.
+    fn terminate(mut self) -> Vec<u8> {
+        self.range = 0xFF;
+        self.low += 0xFF;
+        self.renorm();
+        self.range = 0xFF;
+        self.renorm();
+        self.buf
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn default_state_transitions() {
+        // The first rows of the default state transition table of RFC 9043.
+        let states = RacStates::new();
+        assert_eq!(
+            &states.one[..32],
+            &[
+                0, 0, 0, 0, 0, 0, 0, 0, 20, 21, 22, 23, 24, 25, 26, 27, 28, 29, 30, 31, 32, 33, 34,
+                35, 36, 37, 37, 38, 39, 40, 41, 42
+            ][..]
+        );
+        assert_eq!(
+            &states.one[240..],
+            &[241, 242, 243, 244, 245, 246, 247, 248, 248, 0, 0, 0, 0, 0, 0, 0][..]
+        );
+    }
+
+    fn check_roundtrip(frame: &LosslessFrame) -> usize {
+        let buf = encode_frame(frame).unwrap();
+        let decoded = decoder::decode_frame(&buf, frame.width as usize, frame.height as usize);
+        assert_eq!(decoded.colorspace, frame.colorspace);
+        assert_eq!(decoded.bits_per_sample, frame.bits_per_sample);
+        assert_eq!(decoded.samples, frame.samples);
+        buf.len()
+    }
+
+    /// A smooth image with some noise and a flat region.
+    fn test_image(width: u32, height: u32, n_channels: u32, max: u32) -> Vec<u16> {
+        let mut seed = 1234u32;
+        let mut samples = Vec::new();
+        for y in 0..height {
+            for x in 0..width {
+                for c in 0..n_channels {
+                    seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                    let value = if y < height / 4 {
+                        max / 2
+                    } else {
+                        let noise = (seed >> 16) % 8;
+                        (x * 7 + y * 3 + c * 50 + noise) * (max + 1)
+                            / (width * 7 + height * 3 + 158)
+                    };
+                    samples.push(value.min(max) as u16);
+                }
+            }
+        }
+        samples
+    }
+
+    #[test]
+    fn roundtrip_gray() {
+        for bits in [8, 12, 16].iter() {
+            let (width, height) = (37, 21);
+            let frame = LosslessFrame {
+                width,
+                height,
+                colorspace: LosslessColorspace::Gray,
+                bits_per_sample: *bits,
+                samples: test_image(width, height, 1, (1 << bits) - 1),
+            };
+            let size = check_roundtrip(&frame);
+            if *bits == 8 {
+                assert!(size < frame.samples.len());
+            }
+        }
+    }
+
+    #[test]
+    fn roundtrip_gray_extremes() {
+        // Large jumps between neighbors test the escape codes and the
+        // wrapping 16 bit prediction.
+        let (width, height) = (16, 8);
+        let samples = (0..width * height)
+            .map(|i| {
+                if (i / 3) % 2 == 0 {
+                    0
+                } else {
+                    0xFFFF - i as u16
+                }
+            })
+            .collect();
+        check_roundtrip(&LosslessFrame {
+            width,
+            height,
+            colorspace: LosslessColorspace::Gray,
+            bits_per_sample: 16,
+            samples,
+        });
+    }
+
+    #[test]
+    fn roundtrip_rgb() {
+        let (width, height) = (20, 13);
+        check_roundtrip(&LosslessFrame {
+            width,
+            height,
+            colorspace: LosslessColorspace::Rgb,
+            bits_per_sample: 8,
+            samples: test_image(width, height, 3, 255),
+        });
+    }
+
+    #[test]
+    fn unsupported_bit_depth() {
+        let frame = LosslessFrame {
+            width: 1,
+            height: 1,
+            colorspace: LosslessColorspace::Rgb,
+            bits_per_sample: 12,
+            samples: vec![0; 3],
+        };
+        assert!(encode_frame(&frame).is_err());
+    }
+
+    /// A decoder for the frames written above, following the reference
+    /// decoder of RFC 9043.
+    mod decoder {
+        use super::super::*;
+
+        struct RangeDecoder<'a> {
+            buf: &'a [u8],
+            pos: usize,
+            low: u32,
+            range: u32,
+            states: RacStates,
+        }
+
+        impl<'a> RangeDecoder<'a> {
+            fn new(buf: &'a [u8]) -> Self {
+                let low = u32::from(buf[0]) << 8 | u32::from(buf[1]);
+                Self {
+                    buf,
+                    pos: 2,
+                    low,
+                    range: 0xFF00,
+                    states: RacStates::new(),
+                }
+            }
+
+            fn refill(&mut self) {
+                if self.range < 0x100 {
+                    self.range <<= 8;
+                    self.low <<= 8;
+                    if self.pos < self.buf.len() {
+                        self.low += u32::from(self.buf[self.pos]);
+                    }
+                    self.pos += 1;
+                }
+            }
+
+            fn get_bit(&mut self, state: &mut u8) -> bool {
+                let range1 = (self.range * u32::from(*state)) >> 8;
+                self.range -= range1;
+                if self.low < self.range {
+                    *state = self.states.zero[*state as usize];
+                    self.refill();
+                    false
+                } else {
+                    self.low -= self.range;
+                    self.range = range1;
+                    *state = self.states.one[*state as usize];
+                    self.refill();
+                    true
+                }
+            }
+
+            fn get_symbol(&mut self, state: &mut [u8; CONTEXT_SIZE], is_signed: bool) -> i32 {
+                if self.get_bit(&mut state[0]) {
+                    return 0;
+                }
+                let mut e = 0;
+                while self.get_bit(&mut state[1 + e.min(9)]) {
+                    e += 1;
+                }
+                let mut a = 1;
+                for i in (0..e).rev() {
+                    a = 2 * a + self.get_bit(&mut state[22 + i.min(9)]) as i32;
+                }
+                if is_signed && self.get_bit(&mut state[11 + e.min(10)]) {
+                    -a
+                } else {
+                    a
+                }
+            }
+        }
+
+        struct BitReader<'a> {
+            buf: &'a [u8],
+            pos: usize,
+        }
+
+        impl<'a> BitReader<'a> {
+            fn get(&mut self, n: u32) -> u32 {
+                let mut v = 0;
+                for _ in 0..n {
+                    let byte = self.buf[self.pos / 8];
+                    let bit = (byte >> (7 - self.pos % 8)) & 1;
+                    v = (v << 1) | u32::from(bit);
+                    self.pos += 1;
+                }
+                v
+            }
+
+            fn get_vlc_symbol(&mut self, state: &mut VlcState, bits: u32) -> i32 {
+                let k = state.k();
+                let mut e = 0;
+                while e < GOLOMB_LIMIT && self.get(1) == 0 {
+                    e += 1;
+                }
+                let code = if e < GOLOMB_LIMIT {
+                    (e << k) | self.get(k)
+                } else {
+                    self.get(bits) + GOLOMB_LIMIT - 1
+                };
+                let mut v = if code & 1 == 0 {
+                    (code >> 1) as i32
+                } else {
+                    -((code >> 1) as i32) - 1
+                };
+                v ^= (2 * state.drift + state.count) >> 31;
+                let ret = fold(v + state.bias, bits);
+                state.update(v);
+                ret
+            }
+        }
+
+        pub(super) fn decode_frame(buf: &[u8], width: usize, height: usize) -> LosslessFrame {
+            let mut rac = RangeDecoder::new(buf);
+            let mut keyframe_state = 128;
+            assert!(rac.get_bit(&mut keyframe_state));
+            let mut state = [128; CONTEXT_SIZE];
+            assert_eq!(rac.get_symbol(&mut state, false), 1); // version
+            assert_eq!(rac.get_symbol(&mut state, false), 0); // coder type
+            let colorspace = rac.get_symbol(&mut state, false);
+            let bits = rac.get_symbol(&mut state, false) as u32;
+            let chroma_planes = rac.get_bit(&mut state[0]);
+            assert_eq!(rac.get_symbol(&mut state, false), 0);
+            assert_eq!(rac.get_symbol(&mut state, false), 0);
+            assert!(!rac.get_bit(&mut state[0]));
+
+            let mut tables = Vec::new();
+            let mut scale = 1;
+            for _ in 0..5 {
+                let mut state = [128; CONTEXT_SIZE];
+                let mut table = [0i32; 256];
+                let mut i = 0;
+                let mut v = 0;
+                while i < 128 {
+                    let len = rac.get_symbol(&mut state, false) + 1;
+                    for _ in 0..len {
+                        table[i] = scale * v;
+                        i += 1;
+                    }
+                    v += 1;
+                }
+                for i in 1..128 {
+                    table[256 - i] = -table[i];
+                }
+                table[128] = -table[127];
+                scale *= 2 * v - 1;
+                tables.push(table);
+            }
+            let context_count = ((scale + 1) / 2) as usize;
+
+            let mut reader = BitReader {
+                buf: &buf[rac.pos - 1..],
+                pos: 0,
+            };
+            let mut planes = [
+                vec![VlcState::default(); context_count],
+                vec![VlcState::default(); context_count],
+            ];
+            let mut run_index = 0;
+
+            let mut decode_line = |cur: &mut [i32], prev: &mut [i32], plane: usize, bits: u32| {
+                cur[0] = prev[1];
+                prev[width + 1] = prev[width];
+                let mut run_mode = 0;
+                let mut run_count: i32 = 0;
+                for x in 1..=width {
+                    let (l, tl, t, tr) = (cur[x - 1], prev[x - 1], prev[x], prev[x + 1]);
+                    let mut context = tables[0][((l - tl) & 0xFF) as usize]
+                        + tables[1][((tl - t) & 0xFF) as usize]
+                        + tables[2][((t - tr) & 0xFF) as usize];
+                    let sign = context < 0;
+                    context = context.abs();
+
+                    if context == 0 && run_mode == 0 {
+                        run_mode = 1;
+                    }
+                    let mut diff;
+                    if run_mode != 0 {
+                        if run_count == 0 && run_mode == 1 {
+                            if reader.get(1) == 1 {
+                                run_count = 1 << LOG2_RUN[run_index];
+                                if x - 1 + run_count as usize <= width {
+                                    run_index += 1;
+                                }
+                            } else {
+                                run_count = reader.get(LOG2_RUN[run_index].into()) as i32;
+                                run_index = run_index.saturating_sub(1);
+                                run_mode = 2;
+                            }
+                        }
+                        run_count -= 1;
+                        if run_count < 0 {
+                            run_mode = 0;
+                            run_count = 0;
+                            diff =
+                                reader.get_vlc_symbol(&mut planes[plane][context as usize], bits);
+                            if diff >= 0 {
+                                diff += 1;
+                            }
+                        } else {
+                            diff = 0;
+                        }
+                    } else {
+                        diff = reader.get_vlc_symbol(&mut planes[plane][context as usize], bits);
+                    }
+                    if sign {
+                        diff = -diff;
+                    }
+                    let value = (median(l, l + t - tl, t) + diff) & ((1 << bits) - 1);
+                    cur[x] = if bits == 16 {
+                        value as i16 as i32
+                    } else {
+                        value
+                    };
+                }
+            };
+
+            let n_planes = if chroma_planes { 3 } else { 1 };
+            let mut lines: Vec<_> = (0..n_planes)
+                .map(|_| (vec![0; width + 2], vec![0; width + 2]))
+                .collect();
+            let mut samples = Vec::new();
+            for _ in 0..height {
+                for (cur, prev) in lines.iter_mut() {
+                    std::mem::swap(cur, prev);
+                }
+                if colorspace == 1 {
+                    for (i, (cur, prev)) in lines.iter_mut().enumerate() {
+                        decode_line(cur, prev, i.min(1), bits + 1);
+                    }
+                    let offset = 1 << bits;
+                    for x in 1..=width {
+                        let b = lines[1].0[x] - offset;
+                        let r = lines[2].0[x] - offset;
+                        let g = lines[0].0[x] - ((b + r) >> 2);
+                        for v in [r + g, g, b + g].iter() {
+                            samples.push(*v as u16);
+                        }
+                    }
+                } else {
+                    let (cur, prev) = &mut lines[0];
+                    decode_line(cur, prev, 0, bits);
+                    samples.extend(cur[1..=width].iter().map(|v| *v as u16));
+                }
+            }
+
+            LosslessFrame {
+                width: width as u32,
+                height: height as u32,
+                colorspace: if colorspace == 1 {
+                    LosslessColorspace::Rgb
+                } else {
+                    LosslessColorspace::Gray
+                },
+                bits_per_sample: bits as u8,
+                samples,
+            }
+        }
+    }
+}
@@ -1,18 +1,34 @@
 #![cfg_attr(feature = "backtrace", feature(backtrace))]
 
-use std::rc::Rc;
+use std::{collections::BTreeMap, rc::Rc};
 
 #[macro_use]
 extern crate log;
 
 use ci2_remote_control::MkvRecordingConfig;
-use convert_image::{encode_into_nv12, encode_y4m_frame, Y4MColorspace};
+use convert_image::{
+    encode_into_nv12, encode_lossless_frame, encode_y4m_frame, LosslessColorspace, LosslessFrame,
+    Y4MColorspace,
+};
 
 use machine_vision_formats::{ImageBufferMutRef, ImageStride, PixelFormat};
 use nvenc::{InputBuffer, OutputBuffer, RateControlMode};
 
 use thiserror::Error;
 
+mod ffv1;
+use mkv_mux::{Codec, MuxConfig, Muxer};
+
+pub use mkv_reader::{Attachment, FrameMetadata};
+
+/// In crash-safe mode, the index and duration are updated after this much
+/// video (in nanoseconds).
+const CRASH_SAFE_INTERVAL_NANOS: u64 = 5_000_000_000;
+
+/// Frame metadata is dropped if its frame was not encoded after this long (in
+/// nanoseconds), e.g. because the encoder skipped the frame.
+const MAX_PENDING_METADATA_NANOS: u64 = 10_000_000_000;
+
 #[derive(Error, Debug)]
 pub enum Error {
     #[error("IO error: {source}")]
@@ -46,6 +62,17 @@ pub enum Error {
     ),
     #[error("nvenc libraries not loaded")]
     NvencLibsNotLoaded,
+    #[error("FFV1 does not support {0:?} frames with {1} bits per sample")]
+    UnsupportedLosslessFrame(LosslessColorspace, u8),
+    #[error("lossless frames can only be saved with the FFV1 codec")]
+    LosslessFrameRequiresFfv1,
+    #[error("JSON error: {source}")]
+    JsonError {
+        #[from]
+        source: serde_json::Error,
+        #[cfg(feature = "backtrace")]
+        backtrace: std::backtrace::Backtrace,
+    },
 }
 
 impl From<dynlink_nvidia_encode::NvencError> for Error {
@@ -65,6 +92,16 @@ type Result<T> = std::result::Result<T, Error>;
 enum MyEncoder<'lib> {
     Vpx(vpx_encode::Encoder),
     Nvidia(NvEncoder<'lib>),
+    /// Lossless encoding, with every frame a keyframe.
+    Ffv1,
+}
+
+/// Metadata saved once per file.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FileMetadata {
+    pub camera_name: Option<String>,
+    /// Files to attach, such as the camera calibration.
+    pub attachments: Vec<Attachment>,
 }
 
 pub struct MkvWriter<'lib, T>
@@ -74,6 +111,7 @@ where
     inner: Option<WriteState<'lib, T>>,
     nv_enc: Option<nvenc::NvEnc<'lib>>,
     writing_application: String,
+    file_metadata: FileMetadata,
 }
 
 impl<'lib, T> MkvWriter<'lib, T>
@@ -84,6 +122,16 @@ where
         fd: T,
         config: MkvRecordingConfig,
         nv_enc: Option<nvenc::NvEnc<'lib>>,
+    ) -> Result<Self> {
+        Self::new_with_metadata(fd, config, nv_enc, FileMetadata::default())
+    }
+
+    /// Create a writer which saves `file_metadata` in the file.
+    pub fn new_with_metadata(
+        fd: T,
+        config: MkvRecordingConfig,
+        nv_enc: Option<nvenc::NvEnc<'lib>>,
+        file_metadata: FileMetadata,
     ) -> Result<Self> {
         let writing_application: String = config
             .clone()
@@ -93,6 +141,7 @@ where
             inner: Some(WriteState::Configured((fd, config))),
             nv_enc,
             writing_application,
+            file_metadata,
         })
     }
 
@@ -105,28 +154,85 @@ where
         IM: ImageStride<FMT>,
         FMT: PixelFormat,
     {
-        let inner = self.inner.take();
+        self.write_with_metadata(frame, timestamp, None)
+    }
 
-        match inner {
-            Some(WriteState::Configured((fd, cfg))) => {
-                use webm::mux;
+    /// Write a frame and save its `metadata` in the metadata track.
+    ///
+    /// The file has a metadata track only if the first frame written has
+    /// metadata. The metadata of later frames is otherwise ignored.
+    pub fn write_with_metadata<'a, IM, FMT>(
+        &'a mut self,
+        frame: &IM,
+        timestamp: chrono::DateTime<chrono::Utc>,
+        metadata: Option<FrameMetadata>,
+    ) -> Result<()>
+    where
+        IM: ImageStride<FMT>,
+        FMT: PixelFormat,
+    {
+        self.write_impl(
+            frame.width(),
+            frame.height(),
+            timestamp,
+            metadata,
+            |state, metadata| write_frame(state, frame, timestamp, metadata),
+        )
+    }
 
-                let width = frame.width();
-                let height = frame.height();
+    /// Write a frame which was already converted with
+    /// [convert_image::lossless_frame_from_mono16] or
+    /// [convert_image::encode_lossless_frame].
+    ///
+    /// This allows saving images with up to 16 bits per sample, but requires
+    /// the FFV1 codec.
+    pub fn write_lossless(
+        &mut self,
+        frame: &LosslessFrame,
+        timestamp: chrono::DateTime<chrono::Utc>,
+        metadata: Option<FrameMetadata>,
+    ) -> Result<()> {
+        if let Some(WriteState::Configured((_, cfg))) = &self.inner {
+            if !matches!(cfg.codec, ci2_remote_control::MkvCodec::FFV1) {
+                return Err(Error::LosslessFrameRequiresFfv1);
+            }
+        }
+        self.write_impl(
+            frame.width,
+            frame.height,
+            timestamp,
+            metadata,
+            |state, metadata| write_lossless_frame(state, frame, timestamp, metadata),
+        )
+    }
 
-                let mut mkv_segment =
-                    mux::Segment::new(mux::Writer::new(fd)).expect("mux::Segment::new");
+    /// Start recording if needed and call `write` unless the frame is skipped
+    /// to limit the frame rate.
+    fn write_impl<F>(
+        &mut self,
+        width: u32,
+        height: u32,
+        timestamp: chrono::DateTime<chrono::Utc>,
+        metadata: Option<FrameMetadata>,
+        write: F,
+    ) -> Result<()>
+    where
+        F: FnOnce(&mut RecordingState<'lib, T>, Option<FrameMetadata>) -> Result<()>,
+    {
+        let inner = self.inner.take();
 
+        match inner {
+            Some(WriteState::Configured((fd, cfg))) => {
                 let mut opt_h264_encoder = None;
 
                 let (vpx_tup, mux_codec) = match cfg.codec {
                     ci2_remote_control::MkvCodec::VP8(opts) => (
                         Some((vpx_encode::VideoCodecId::VP8, opts.bitrate)),
-                        webm::mux::VideoCodecId::VP8,
+                        Codec::Vp8,
                     ),
                     ci2_remote_control::MkvCodec::VP9(opts) => (
                         Some((vpx_encode::VideoCodecId::VP9, opts.bitrate)),
-                        webm::mux::VideoCodecId::VP9,
+                        Codec::Vp9,
                     ),
                     ci2_remote_control::MkvCodec::H264(opts) => {
                         // scope for anonymous lifetime of ref
@@ -214,15 +320,14 @@ where
                                     encoder,
                                     vram_queue,
                                 });
-                                (None, webm::mux::VideoCodecId::H264)
+                                (None, Codec::H264)
                             }
                             None => return Err(Error::NvencLibsNotLoaded),
                         }
                     }
+                    ci2_remote_control::MkvCodec::FFV1 => (None, Codec::Ffv1),
                 };
 
-                let vt = mkv_segment.add_video_track(width, height, None, mux_codec);
-
                 let my_encoder = if let Some((vpx_codec, bitrate)) = vpx_tup {
                     debug!("Using codec {:?} in mkv file.", vpx_codec);
                     // Setup the encoder.
@@ -235,9 +340,11 @@ where
                     })?;
 
                     MyEncoder::Vpx(vpx_encoder)
-                } else {
-                    let enc = opt_h264_encoder.unwrap();
+                } else if let Some(enc) = opt_h264_encoder {
                     MyEncoder::Nvidia(enc)
+                } else {
+                    debug!("Using codec FFV1 in mkv file.");
+                    MyEncoder::Ffv1
                 };
 
                 // Set DateUTC metadata
@@ -254,12 +361,32 @@ where
                     "saving DateUTC with value in mkv file: {} (from initial timestamp {})",
                     nanoseconds, timestamp
                 );
-                mkv_segment.set_date_utc(nanoseconds);
-                mkv_segment.set_app_name(&self.writing_application);
+
+                let crash_safe_interval = if cfg.crash_safe {
+                    Some(CRASH_SAFE_INTERVAL_NANOS)
+                } else {
+                    None
+                };
+                let mux = Muxer::new(
+                    fd,
+                    MuxConfig {
+                        width,
+                        height,
+                        codec: mux_codec,
+                        date_utc: nanoseconds,
+                        writing_app: &self.writing_application,
+                        camera_name: self.file_metadata.camera_name.as_deref(),
+                        attachments: &self.file_metadata.attachments,
+                        metadata_track: metadata.is_some(),
+                        crash_safe_interval,
+                    },
+                )?;
 
                 let mut state = RecordingState {
-                    mkv_segment,
-                    vt,
+                    output: Output {
+                        mux,
+                        pending_metadata: BTreeMap::new(),
+                    },
                     my_encoder,
                     first_timestamp: timestamp,
                     previous_timestamp: timestamp,
@@ -267,7 +394,7 @@ where
                         .unwrap(),
                 };
 
-                write_frame(&mut state, frame, timestamp)?;
+                write(&mut state, metadata)?;
 
                 self.inner = Some(WriteState::Recording(state));
 
@@ -277,7 +404,7 @@ where
                 let interval = timestamp.signed_duration_since(state.previous_timestamp);
                 if interval >= state.target_interval {
                     debug!("Saving frame at {}: interval {}", timestamp, interval);
-                    write_frame(&mut state, frame, timestamp)?;
+                    write(&mut state, metadata)?;
                     state.previous_timestamp = timestamp;
                 } else {
                     debug!(
@@ -299,9 +426,11 @@ where
         }
     }
 
+    /// Finish writing the file.
+    ///
+    /// This writes any remaining frames and the seek index (Cues) and
+    /// duration of the video.
     pub fn finish(&mut self) -> Result<()> {
-        use webm::mux::Track;
-
         let inner = self.inner.take();
         match inner {
             Some(WriteState::Configured((_fd, _cfg))) => {
@@ -315,9 +444,11 @@ where
                         let mut frames = vpx_encoder.finish().unwrap();
                         trace!("Finishing vpx encoding.");
                         while let Some(frame) = frames.next().unwrap() {
-                            state
-                                .vt
-                                .add_frame(frame.data, nanos(&frame.pts_dur()), frame.key);
+                            state.output.add_frame(
+                                frame.data,
+                                nanos(&frame.pts_dur()),
+                                frame.key,
+                            )?;
                             trace!(
                                 "got vpx encoded data for final frame(s): {} bytes",
                                 frame.data.len()
@@ -332,20 +463,21 @@ where
                                 Some(iobuf) => {
                                     // scope for locked output buffer
                                     let outbuf = iobuf.out_buf.lock()?;
-                                    state.vt.add_frame(
+                                    state.output.add_frame(
                                         outbuf.mem(),
                                         nanos(outbuf.pts()),
                                         outbuf.is_keyframe(),
-                                    );
+                                    )?;
                                 }
                             }
                         }
                     }
+                    MyEncoder::Ffv1 => {
+                        // Frames are written as soon as they are encoded.
+                    }
                 }
 
-                // If duration is set to `None`, libwebm will set it
-                // automatically.
-                let _ = state.mkv_segment.finalize(None);
+                state.output.mux.finish()?;
 
                 trace!("Finalized mkv.");
                 self.inner = Some(WriteState::Finished);
@@ -392,14 +524,13 @@ fn write_frame<'lib, T, FRAME, FMT>(
     state: &mut RecordingState<'lib, T>,
     raw_frame: &FRAME,
     timestamp: chrono::DateTime<chrono::Utc>,
+    metadata: Option<FrameMetadata>,
 ) -> Result<()>
 where
     T: std::io::Write + std::io::Seek,
     FRAME: ImageStride<FMT>,
     FMT: PixelFormat,
 {
-    use webm::mux::Track;
-
     let elapsed = timestamp.signed_duration_since(state.first_timestamp);
 
     match &mut state.my_encoder {
@@ -408,11 +539,16 @@ where
             trace!("got yuv data for frame. {} bytes.", yuv.len());
 
             let milliseconds = elapsed.num_milliseconds();
+            if let Some(metadata) = metadata {
+                // The encoded frame will have this timestamp.
+                let pts_nanos = milliseconds as u64 * 1_000_000;
+                state.output.pending_metadata.insert(pts_nanos, metadata);
+            }
             for frame in vpx_encoder.encode(milliseconds, &yuv).unwrap() {
                 trace!("got vpx encoded data: {} bytes.", frame.data.len());
                 state
-                    .vt
-                    .add_frame(frame.data, nanos(&frame.pts_dur()), frame.key);
+                    .output
+                    .add_frame(frame.data, nanos(&frame.pts_dur()), frame.key)?;
             }
         }
         MyEncoder::Nvidia(ref mut nv_encoder) => {
@@ -423,9 +559,11 @@ where
                     {
                         // scope for locked output buffer
                         let outbuf = iobuf.out_buf.lock()?;
-                        state
-                            .vt
-                            .add_frame(outbuf.mem(), nanos(outbuf.pts()), outbuf.is_keyframe());
+                        state.output.add_frame(
+                            outbuf.mem(),
+                            nanos(outbuf.pts()),
+                            outbuf.is_keyframe(),
+                        )?;
                     }
                     nv_encoder
                         .vram_queue
@@ -447,17 +585,48 @@ where
                 dest_stride
             };
 
-            nv_encoder.encoder.encode_picture(
-                &vram_buf.in_buf,
-                &vram_buf.out_buf,
-                pitch,
-                elapsed.to_std().unwrap(),
-            )?;
+            let pts = elapsed.to_std().unwrap();
+            if let Some(metadata) = metadata {
+                state.output.pending_metadata.insert(nanos(&pts), metadata);
+            }
+
+            nv_encoder
+                .encoder
+                .encode_picture(&vram_buf.in_buf, &vram_buf.out_buf, pitch, pts)?;
+        }
+        MyEncoder::Ffv1 => {
+            let lossless = encode_lossless_frame(raw_frame)?;
+            write_lossless_frame(state, &lossless, timestamp, metadata)?;
         }
     }
     Ok(())
 }
 
+fn write_lossless_frame<'lib, T>(
+    state: &mut RecordingState<'lib, T>,
+    lossless: &LosslessFrame,
+    timestamp: chrono::DateTime<chrono::Utc>,
+    metadata: Option<FrameMetadata>,
+) -> Result<()>
+where
+    T: std::io::Write + std::io::Seek,
+{
+    if !matches!(state.my_encoder, MyEncoder::Ffv1) {
+        return Err(Error::LosslessFrameRequiresFfv1);
+    }
+    let elapsed = timestamp.signed_duration_since(state.first_timestamp);
+
+    let data = ffv1::encode_frame(lossless)?;
+    trace!("got ffv1 encoded data: {} bytes.", data.len());
+
+    let pts_nanos = nanos(&elapsed.to_std().unwrap());
+    if let Some(metadata) = metadata {
+        state.output.pending_metadata.insert(pts_nanos, metadata);
+    }
+    state.output.add_frame(&data, pts_nanos, true)?;
+    Ok(())
+}
+
 enum WriteState<'lib, T>
 where
     T: std::io::Write + std::io::Seek,
@@ -471,14 +640,43 @@ struct RecordingState<'lib, T>
 where
     T: std::io::Write + std::io::Seek,
 {
-    mkv_segment: webm::mux::Segment<webm::mux::Writer<T>>,
-    vt: webm::mux::VideoTrack,
+    output: Output<T>,
     my_encoder: MyEncoder<'lib>,
     first_timestamp: chrono::DateTime<chrono::Utc>,
     previous_timestamp: chrono::DateTime<chrono::Utc>,
     target_interval: chrono::Duration,
 }
 
+/// The muxer and the metadata of frames not yet returned by the encoder.
+struct Output<T>
+where
+    T: std::io::Write + std::io::Seek,
+{
+    mux: Muxer<T>,
+    /// Frame metadata keyed by presentation timestamp (in nanoseconds).
+    pending_metadata: BTreeMap<u64, FrameMetadata>,
+}
+
+impl<T> Output<T>
+where
+    T: std::io::Write + std::io::Seek,
+{
+    /// Add an encoded frame together with its metadata (if any).
+    fn add_frame(&mut self, data: &[u8], pts_nanos: u64, keyframe: bool) -> Result<()> {
+        let metadata = match self.pending_metadata.remove(&pts_nanos) {
+            Some(metadata) => Some(serde_json::to_vec(&metadata)?),
+            None => None,
+        };
+        self.mux
+            .add_frame(data, pts_nanos, keyframe, metadata.as_deref())?;
+
+        // Forget metadata of frames which will never arrive.
+        let oldest = pts_nanos.saturating_sub(MAX_PENDING_METADATA_NANOS);
+        self.pending_metadata = self.pending_metadata.split_off(&oldest);
+        Ok(())
+    }
+}
+
 struct NvEncoder<'lib> {
     encoder: Rc<nvenc::Encoder<'lib>>,
     vram_queue: nvenc::Queue<IOBuffer<InputBuffer<'lib>, OutputBuffer<'lib>>>,
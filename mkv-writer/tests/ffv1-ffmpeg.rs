@@ -0,0 +1,171 @@
+//! Check that FFV1 files are decoded losslessly by ffmpeg, which is an
+//! independent implementation of the codec.
+//!
+//! The tests are skipped if `ffmpeg` is not installed.
+
+use std::{path::Path, process::Command};
+
+use chrono::TimeZone;
+use ci2_remote_control::{MkvCodec, MkvRecordingConfig, RecordingFrameRate};
+use machine_vision_formats::pixel_format::{Mono8, RGB8};
+use mkv_writer::MkvWriter;
+use simple_frame::SimpleFrame;
+
+const WIDTH: u32 = 37;
+const HEIGHT: u32 = 21;
+const N_FRAMES: usize = 3;
+
+fn ffmpeg_available() -> bool {
+    match Command::new("ffmpeg").arg("-version").output() {
+        Ok(output) => output.status.success(),
+        Err(_) => {
+            eprintln!("ffmpeg not found, skipping test");
+            false
+        }
+    }
+}
+
+/// Decode all frames of the file at `path` into raw video of `pix_fmt`.
+fn ffmpeg_decode(path: &Path, pix_fmt: &str) -> Vec<u8> {
+    let output = Command::new("ffmpeg")
+        .args(&["-v", "error", "-i"])
+        .arg(path)
+        .args(&["-f", "rawvideo", "-pix_fmt", pix_fmt, "-"])
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "ffmpeg failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    output.stdout
+}
+
+fn write_mkv<F>(path: &Path, write: F)
+where
+    F: Fn(&mut MkvWriter<std::fs::File>, chrono::DateTime<chrono::Utc>, usize),
+{
+    let cfg = MkvRecordingConfig {
+        codec: MkvCodec::FFV1,
+        max_framerate: RecordingFrameRate::Unlimited,
+        ..Default::default()
+    };
+    let fd = std::fs::File::create(path).unwrap();
+    let mut writer = MkvWriter::new(fd, cfg, None).unwrap();
+    for i in 0..N_FRAMES {
+        let timestamp = chrono::Utc.timestamp(1_600_000_000 + i as i64, 0);
+        write(&mut writer, timestamp, i);
+    }
+    writer.finish().unwrap();
+}
+
+/// A smooth image with noise, a flat region and some extreme values.
+fn test_samples(n_channels: u32, max: u32, frame: usize) -> Vec<u32> {
+    let mut seed = 1234 + frame as u32;
+    let mut samples = Vec::new();
+    for y in 0..HEIGHT {
+        for x in 0..WIDTH {
+            for c in 0..n_channels {
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                let value = if y < HEIGHT / 4 {
+                    max / 2
+                } else if y == HEIGHT - 1 {
+                    if (x / 3) % 2 == 0 {
+                        0
+                    } else {
+                        max
+                    }
+                } else {
+                    let noise = (seed >> 16) % 8;
+                    (x * 7 + y * 3 + c * 50 + noise) * (max + 1) / (WIDTH * 7 + HEIGHT * 3 + 158)
+                };
+                samples.push(value.min(max));
+            }
+        }
+    }
+    samples
+}
+
+#[test]
+fn ffv1_high_bit_depth_gray() {
+    if !ffmpeg_available() {
+        return;
+    }
+    let tmpdir = tempfile::tempdir().unwrap();
+    for (bits, pix_fmt) in &[(16, "gray16le"), (12, "gray12le"), (10, "gray10le")] {
+        let frames: Vec<Vec<u8>> = (0..N_FRAMES)
+            .map(|i| {
+                test_samples(1, (1 << bits) - 1, i)
+                    .into_iter()
+                    .flat_map(|v| (v as u16).to_le_bytes().to_vec())
+                    .collect()
+            })
+            .collect();
+
+        let path = tmpdir.path().join(format!("gray{}.mkv", bits));
+        write_mkv(&path, |writer, timestamp, i| {
+            let lossless = convert_image::lossless_frame_from_mono16(
+                WIDTH,
+                HEIGHT,
+                WIDTH as usize * 2,
+                &frames[i],
+                *bits,
+            )
+            .unwrap();
+            writer.write_lossless(&lossless, timestamp, None).unwrap();
+        });
+
+        let decoded = ffmpeg_decode(&path, pix_fmt);
+        assert_eq!(decoded, frames.concat(), "{} bits", bits);
+    }
+}
+
+#[test]
+fn ffv1_mono8_and_rgb8() {
+    if !ffmpeg_available() {
+        return;
+    }
+    let tmpdir = tempfile::tempdir().unwrap();
+
+    let frames: Vec<Vec<u8>> = (0..N_FRAMES)
+        .map(|i| {
+            test_samples(1, 255, i)
+                .into_iter()
+                .map(|v| v as u8)
+                .collect()
+        })
+        .collect();
+    let path = tmpdir.path().join("mono8.mkv");
+    write_mkv(&path, |writer, timestamp, i| {
+        let frame: SimpleFrame<Mono8> = SimpleFrame {
+            width: WIDTH,
+            height: HEIGHT,
+            stride: WIDTH,
+            image_data: frames[i].clone(),
+            fmt: std::marker::PhantomData,
+        };
+        writer.write(&frame, timestamp).unwrap();
+    });
+    assert_eq!(ffmpeg_decode(&path, "gray"), frames.concat());
+
+    let frames: Vec<Vec<u8>> = (0..N_FRAMES)
+        .map(|i| {
+            test_samples(3, 255, i)
+                .into_iter()
+                .map(|v| v as u8)
+                .collect()
+        })
+        .collect();
+    let path = tmpdir.path().join("rgb8.mkv");
+    write_mkv(&path, |writer, timestamp, i| {
+        let frame: SimpleFrame<RGB8> = SimpleFrame {
+            width: WIDTH,
+            height: HEIGHT,
+            stride: WIDTH * 3,
+            image_data: frames[i].clone(),
+            fmt: std::marker::PhantomData,
+        };
+        writer.write(&frame, timestamp).unwrap();
+    });
+    assert_eq!(ffmpeg_decode(&path, "rgb24"), frames.concat());
+}
@@ -0,0 +1,660 @@
+//! Refinement of a [MultiCameraSystem] by bundle adjustment
+//!
+//! The cameras and the 3D points observed by them are jointly optimized to
+//! minimize the reprojection error of the observed (distorted) pixels using
+//! the Levenberg-Marquardt algorithm. Because each observation depends only
+//! on a single camera and a single point, the normal equations are reduced to
+//! the camera parameters with the Schur complement, so the number of points
+//! may be large.
+//!
+//! The extrinsic parameters of the first camera (in name order) are held
+//! fixed to remove the freedom of rotating and translating the whole system.
+//! The center of the second camera is kept at its distance from the center of
+//! the first camera to remove the freedom of scaling the whole system.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use na::geometry::{Point3, UnitQuaternion};
+use na::storage::Owned;
+use na::{DMatrix, DVector, Matrix3, RealField, Vector3, U1, U2};
+use nalgebra as na;
+use serde::Serialize;
+
+use crate::{
+    Camera, DistortedPixel, ExtrinsicParameters, MultiCameraSystem, MvgError, PointWorldFrame,
+    Result, RosOpenCvIntrinsics, UndistortedPixel,
+};
+
+/// Which parameters are optimized.
+#[derive(Debug, Clone)]
+pub struct BundleAdjustmentOptions {
+    /// Optimize the focal lengths and principal points.
+    pub optimize_intrinsics: bool,
+    /// Names of the cameras whose focal lengths and principal points are kept
+    /// as is, even with `optimize_intrinsics`.
+    pub fixed_intrinsics: BTreeSet<String>,
+    /// Optimize the radial (k1, k2) and tangential (p1, p2) distortion.
+    ///
+    /// The third radial coefficient is kept as is, because the flydra XML
+    /// calibration format cannot store it.
+    pub optimize_distortion: bool,
+    /// Maximum number of Levenberg-Marquardt iterations.
+    pub max_iterations: usize,
+}
+
+impl Default for BundleAdjustmentOptions {
+    fn default() -> Self {
+        Self {
+            optimize_intrinsics: false,
+            fixed_intrinsics: BTreeSet::new(),
+            optimize_distortion: false,
+            max_iterations: 100,
+        }
+    }
+}
+
+/// Reprojection error statistics of a set of observations (in pixels).
+#[derive(Debug, Clone, Serialize)]
+pub struct ReprojectionErrorStats<R: RealField + Serialize> {
+    pub n_observations: usize,
+    pub mean: R,
+    pub rms: R,
+    pub max: R,
+}
+
+/// Reprojection error statistics of all observations and for each camera.
+#[derive(Debug, Clone, Serialize)]
+pub struct ReprojectionReport<R: RealField + Serialize> {
+    pub all_cameras: ReprojectionErrorStats<R>,
+    pub per_camera: BTreeMap<String, ReprojectionErrorStats<R>>,
+}
+
+/// The result of [bundle_adjust].
+#[derive(Debug, Clone)]
+pub struct BundleAdjustmentResult<R: RealField + Default + Serialize> {
+    /// The refined camera system.
+    pub system: MultiCameraSystem<R>,
+    /// The refined 3D points, in the order of the input points.
+    pub points: Vec<PointWorldFrame<R>>,
+    /// Reprojection errors with the original cameras and triangulated points.
+    pub before: ReprojectionReport<R>,
+    /// Reprojection errors with the refined cameras and points.
+    pub after: ReprojectionReport<R>,
+    /// Number of Levenberg-Marquardt iterations performed.
+    pub n_iterations: usize,
+}
+
+/// Refine `system` from observations of 3D points.
+///
+/// Each element of `points` are the observations of a single 3D point, given
+/// as camera name and distorted pixel coordinates. Points must be observed
+/// by at least two cameras. The initial position of each point is found by
+/// triangulation.
+pub fn bundle_adjust<R>(
+    system: &MultiCameraSystem<R>,
+    points: &[Vec<(String, DistortedPixel<R>)>],
+    opts: &BundleAdjustmentOptions,
+) -> Result<BundleAdjustmentResult<R>>
+where
+    R: RealField + Default + Serialize,
+{
+    let cam_names: Vec<String> = system.cams_by_name().keys().cloned().collect();
+    let orig_cams: Vec<Camera<R>> = system.cams_by_name().values().cloned().collect();
+    let layouts: Vec<ParamLayout<R>> = (0..orig_cams.len())
+        .map(|i| {
+            let extrinsics = match i {
+                0 => ExtrinsicsLayout::Fixed,
+                1 => ExtrinsicsLayout::fixed_distance(
+                    orig_cams[0].extrinsics().camcenter(),
+                    orig_cams[1].extrinsics().camcenter(),
+                ),
+                _ => ExtrinsicsLayout::Free,
+            };
+            ParamLayout::new(extrinsics, &cam_names[i], opts)
+        })
+        .collect();
+    for (cam, layout) in orig_cams.iter().zip(layouts.iter()) {
+        if layout.intrinsics && !cam.intrinsics().rect.is_identity(na::convert(1.0e-7)) {
+            return Err(MvgError::RectificationMatrixNotSupported);
+        }
+    }
+
+    // Index the observations by camera and triangulate the initial points.
+    let mut observations: Vec<Vec<Observation<R>>> = Vec::with_capacity(points.len());
+    let mut point_coords: Vec<Point3<R>> = Vec::with_capacity(points.len());
+    for point_obs in points.iter() {
+        let mut obs = Vec::with_capacity(point_obs.len());
+        let mut undistorted = Vec::with_capacity(point_obs.len());
+        for (cam_name, distorted) in point_obs.iter() {
+            let cam_idx = cam_names
+                .iter()
+                .position(|name| name == cam_name)
+                .ok_or(MvgError::UnknownCamera)?;
+            let pixels: cam_geom::Pixels<R, U1, Owned<R, U1, U2>> = distorted.into();
+            let undist: UndistortedPixel<R> =
+                orig_cams[cam_idx].intrinsics().undistort(&pixels).into();
+            undistorted.push((cam_name.clone(), undist));
+            obs.push(Observation {
+                cam_idx,
+                distorted: distorted.clone(),
+            });
+        }
+        let point = system.find3d(&undistorted)?;
+        observations.push(obs);
+        point_coords.push(point.coords);
+    }
+
+    let mut state = State {
+        cam_params: layouts.iter().map(|l| DVector::zeros(l.len())).collect(),
+        points: point_coords,
+    };
+    let mut cams = state.cameras(&orig_cams, &layouts)?;
+    let before = reprojection_report(&cam_names, &cams, &state.points, &observations);
+    let mut cost = total_cost(&cams, &state.points, &observations);
+
+    let ten: R = na::convert(10.0);
+    let min_lambda: R = na::convert(1e-12);
+    let max_lambda: R = na::convert(1e10);
+    let converged: R = na::convert(1e-10);
+    let mut lambda: R = na::convert(1e-3);
+    let mut n_iterations = 0;
+    while n_iterations < opts.max_iterations {
+        n_iterations += 1;
+        let normal = NormalEquations::new(&orig_cams, &layouts, &state, &cams, &observations)?;
+
+        // Increase the damping until the cost decreases.
+        let mut improved = None;
+        while lambda < max_lambda {
+            if let Some((delta_cams, delta_points)) = normal.solve(lambda) {
+                let candidate = state.step(&delta_cams, &delta_points);
+                let candidate_cams = candidate.cameras(&orig_cams, &layouts)?;
+                let candidate_cost = total_cost(&candidate_cams, &candidate.points, &observations);
+                if candidate_cost < cost {
+                    improved = Some((candidate, candidate_cams, candidate_cost));
+                    break;
+                }
+            }
+            lambda *= ten;
+        }
+
+        match improved {
+            Some((new_state, new_cams, new_cost)) => {
+                let relative_change = (cost - new_cost) / cost;
+                state = new_state;
+                cams = new_cams;
+                cost = new_cost;
+                lambda = (lambda / ten).max(min_lambda);
+                log::debug!("iteration {}: cost {}", n_iterations, cost);
+                if relative_change < converged {
+                    break;
+                }
+            }
+            None => {
+                // No step reduces the cost: we are at a minimum.
+                break;
+            }
+        }
+    }
+
+    let after = reprojection_report(&cam_names, &cams, &state.points, &observations);
+    let cams_by_name = cam_names.into_iter().zip(cams.into_iter()).collect();
+    let system = MultiCameraSystem::new_inner(cams_by_name, system.comment().cloned());
+    let points = state
+        .points
+        .into_iter()
+        .map(|coords| PointWorldFrame { coords })
+        .collect();
+    Ok(BundleAdjustmentResult {
+        system,
+        points,
+        before,
+        after,
+        n_iterations,
+    })
+}
+
+struct Observation<R: RealField> {
+    cam_idx: usize,
+    distorted: DistortedPixel<R>,
+}
+
+/// The free extrinsic parameters of a camera.
+#[derive(Debug, Clone)]
+enum ExtrinsicsLayout<R: RealField> {
+    /// No free parameters.
+    Fixed,
+    /// The rotation (as axis-angle) and the rotation of the camera center
+    /// about `origin` (as axis-angle, with the axis in the plane spanned by
+    /// `axes`), which keeps the distance of the camera center to `origin`.
+    FixedDistance {
+        origin: Point3<R>,
+        axes: [Vector3<R>; 2],
+    },
+    /// The rotation (as axis-angle) and camera center.
+    Free,
+}
+
+impl<R: RealField> ExtrinsicsLayout<R> {
+    /// Keep the distance between `origin` and `camcenter`.
+    fn fixed_distance(origin: &Point3<R>, camcenter: &Point3<R>) -> Self {
+        let axes = match (camcenter - origin).try_normalize(na::convert(1e-12)) {
+            Some(baseline) => {
+                // Any vector not parallel to the baseline.
+                let other = baseline.iamin();
+                let first = baseline.cross(&Vector3::ith(other, R::one())).normalize();
+                let second = baseline.cross(&first);
+                [first, second]
+            }
+            // The camera center cannot move.
+            None => [Vector3::zeros(), Vector3::zeros()],
+        };
+        Self::FixedDistance {
+            origin: *origin,
+            axes,
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Self::Fixed => 0,
+            Self::FixedDistance { .. } => 5,
+            Self::Free => 6,
+        }
+    }
+}
+
+/// The free parameters of a camera.
+///
+/// The parameters are, in order, the extrinsic parameters (see
+/// [ExtrinsicsLayout]), fx, fy, cx and cy (if `intrinsics`) and k1, k2, p1
+/// and p2 (if `distortion`). All are differences to the original camera.
+#[derive(Debug, Clone)]
+struct ParamLayout<R: RealField> {
+    extrinsics: ExtrinsicsLayout<R>,
+    intrinsics: bool,
+    distortion: bool,
+}
+
+impl<R: RealField> ParamLayout<R> {
+    fn new(extrinsics: ExtrinsicsLayout<R>, name: &str, opts: &BundleAdjustmentOptions) -> Self {
+        Self {
+            extrinsics,
+            intrinsics: opts.optimize_intrinsics && !opts.fixed_intrinsics.contains(name),
+            distortion: opts.optimize_distortion,
+        }
+    }
+
+    fn len(&self) -> usize {
+        let mut n = self.extrinsics.len();
+        if self.intrinsics {
+            n += 4;
+        }
+        if self.distortion {
+            n += 4;
+        }
+        n
+    }
+
+    /// The finite difference step for each parameter.
+    fn steps(&self) -> Vec<R> {
+        let mut steps = vec![na::convert(1e-7); self.extrinsics.len()];
+        if self.intrinsics {
+            steps.extend_from_slice(&[na::convert(1e-4); 4]);
+        }
+        if self.distortion {
+            steps.extend_from_slice(&[na::convert(1e-7); 4]);
+        }
+        steps
+    }
+
+    /// Create the camera with parameters `params` relative to `orig`.
+    fn camera(&self, orig: &Camera<R>, params: &DVector<R>) -> Result<Camera<R>> {
+        let orig_extrinsics = orig.extrinsics();
+        let camcenter = match &self.extrinsics {
+            ExtrinsicsLayout::Fixed => None,
+            ExtrinsicsLayout::FixedDistance { origin, axes } => {
+                let axisangle = axes[0] * params[3] + axes[1] * params[4];
+                let baseline = orig_extrinsics.camcenter() - origin;
+                Some(origin + UnitQuaternion::new(axisangle) * baseline)
+            }
+            ExtrinsicsLayout::Free => {
+                Some(orig_extrinsics.camcenter() + Vector3::new(params[3], params[4], params[5]))
+            }
+        };
+        let extrinsics = match camcenter {
+            Some(camcenter) => {
+                let rotation = UnitQuaternion::new(Vector3::new(params[0], params[1], params[2]))
+                    * UnitQuaternion::from_rotation_matrix(orig_extrinsics.rotation());
+                ExtrinsicParameters::from_rotation_and_camcenter(rotation, camcenter)
+            }
+            None => orig_extrinsics.clone(),
+        };
+
+        let orig_intrinsics = orig.intrinsics();
+        let mut i = self.extrinsics.len();
+        let mut p = orig_intrinsics.p;
+        let mut k = orig_intrinsics.k;
+        let mut distortion = orig_intrinsics.distortion.clone();
+        if self.intrinsics {
+            for (row, col) in [(0, 0), (1, 1), (0, 2), (1, 2)].iter() {
+                k[(*row, *col)] += params[i];
+                p[(*row, *col)] += params[i];
+                i += 1;
+            }
+        }
+        if self.distortion {
+            *distortion.radial1_mut() += params[i];
+            *distortion.radial2_mut() += params[i + 1];
+            *distortion.tangential1_mut() += params[i + 2];
+            *distortion.tangential2_mut() += params[i + 3];
+        }
+        let intrinsics = if self.intrinsics || self.distortion {
+            RosOpenCvIntrinsics::from_components(p, k, distortion, orig_intrinsics.rect)?
+        } else {
+            orig_intrinsics.clone()
+        };
+
+        Camera::new(orig.width(), orig.height(), extrinsics, intrinsics)
+    }
+}
+
+/// The current estimate of all parameters.
+#[derive(Clone)]
+struct State<R: RealField> {
+    cam_params: Vec<DVector<R>>,
+    points: Vec<Point3<R>>,
+}
+
+impl<R: RealField> State<R> {
+    fn cameras(
+        &self,
+        orig_cams: &[Camera<R>],
+        layouts: &[ParamLayout<R>],
+    ) -> Result<Vec<Camera<R>>> {
+        orig_cams
+            .iter()
+            .zip(layouts.iter())
+            .zip(self.cam_params.iter())
+            .map(|((orig, layout), params)| layout.camera(orig, params))
+            .collect()
+    }
+
+    fn step(&self, delta_cams: &[DVector<R>], delta_points: &[Vector3<R>]) -> Self {
+        Self {
+            cam_params: self
+                .cam_params
+                .iter()
+                .zip(delta_cams.iter())
+                .map(|(p, d)| p + d)
+                .collect(),
+            points: self
+                .points
+                .iter()
+                .zip(delta_points.iter())
+                .map(|(p, d)| p + d)
+                .collect(),
+        }
+    }
+}
+
+fn residual<R: RealField>(cam: &Camera<R>, point: &Point3<R>, obs: &Observation<R>) -> [R; 2] {
+    let projected = cam.project_3d_to_distorted_pixel(&PointWorldFrame { coords: *point });
+    [
+        projected.coords.x - obs.distorted.coords.x,
+        projected.coords.y - obs.distorted.coords.y,
+    ]
+}
+
+fn total_cost<R: RealField>(
+    cams: &[Camera<R>],
+    points: &[Point3<R>],
+    observations: &[Vec<Observation<R>>],
+) -> R {
+    let mut cost = R::zero();
+    for (point, point_obs) in points.iter().zip(observations.iter()) {
+        for obs in point_obs.iter() {
+            let r = residual(&cams[obs.cam_idx], point, obs);
+            cost += r[0] * r[0] + r[1] * r[1];
+        }
+    }
+    cost
+}
+
+fn reprojection_report<R: RealField + Serialize>(
+    cam_names: &[String],
+    cams: &[Camera<R>],
+    points: &[Point3<R>],
+    observations: &[Vec<Observation<R>>],
+) -> ReprojectionReport<R> {
+    let mut dists: Vec<Vec<R>> = vec![Vec::new(); cams.len()];
+    for (point, point_obs) in points.iter().zip(observations.iter()) {
+        for obs in point_obs.iter() {
+            let r = residual(&cams[obs.cam_idx], point, obs);
+            dists[obs.cam_idx].push((r[0] * r[0] + r[1] * r[1]).sqrt());
+        }
+    }
+    let all: Vec<R> = dists.iter().flatten().copied().collect();
+    ReprojectionReport {
+        all_cameras: stats(&all),
+        per_camera: cam_names
+            .iter()
+            .cloned()
+            .zip(dists.iter().map(|d| stats(d.as_slice())))
+            .collect(),
+    }
+}
+
+fn stats<R: RealField + Serialize>(dists: &[R]) -> ReprojectionErrorStats<R> {
+    let n: R = na::convert(dists.len().max(1) as f64);
+    let sum = dists.iter().fold(R::zero(), |acc, d| acc + *d);
+    let sum_sq = dists.iter().fold(R::zero(), |acc, d| acc + *d * *d);
+    ReprojectionErrorStats {
+        n_observations: dists.len(),
+        mean: sum / n,
+        rms: (sum_sq / n).sqrt(),
+        max: dists.iter().fold(R::zero(), |acc, d| acc.max(*d)),
+    }
+}
+
+/// The normal equations, split into camera and point blocks.
+struct NormalEquations<R: RealField> {
+    /// offset of the parameters of each camera in the reduced system
+    offsets: Vec<usize>,
+    n_cam_params: usize,
+    /// J^T J of the parameters of each camera
+    u: Vec<DMatrix<R>>,
+    /// J^T r of the parameters of each camera
+    g_cams: Vec<DVector<R>>,
+    /// J^T J of each point
+    v: Vec<Matrix3<R>>,
+    /// J^T r of each point
+    g_points: Vec<Vector3<R>>,
+    /// J_cam^T J_point of each observation of each point, with the camera index
+    w: Vec<Vec<(usize, DMatrix<R>)>>,
+}
+
+impl<R: RealField> NormalEquations<R> {
+    fn new(
+        orig_cams: &[Camera<R>],
+        layouts: &[ParamLayout<R>],
+        state: &State<R>,
+        cams: &[Camera<R>],
+        observations: &[Vec<Observation<R>>],
+    ) -> Result<Self> {
+        let two: R = na::convert(2.0);
+
+        // Cameras with each parameter perturbed, for central differences.
+        let mut perturbed: Vec<Vec<(Camera<R>, Camera<R>, R)>> = Vec::with_capacity(cams.len());
+        for ((orig, layout), params) in orig_cams
+            .iter()
+            .zip(layouts.iter())
+            .zip(state.cam_params.iter())
+        {
+            let mut this_cam = Vec::with_capacity(layout.len());
+            for (j, step) in layout.steps().into_iter().enumerate() {
+                let mut plus = params.clone();
+                plus[j] += step;
+                let mut minus = params.clone();
+                minus[j] -= step;
+                this_cam.push((
+                    layout.camera(orig, &plus)?,
+                    layout.camera(orig, &minus)?,
+                    step,
+                ));
+            }
+            perturbed.push(this_cam);
+        }
+
+        let mut offsets = Vec::with_capacity(layouts.len());
+        let mut n_cam_params = 0;
+        for layout in layouts.iter() {
+            offsets.push(n_cam_params);
+            n_cam_params += layout.len();
+        }
+
+        let mut u: Vec<DMatrix<R>> = layouts
+            .iter()
+            .map(|l| DMatrix::zeros(l.len(), l.len()))
+            .collect();
+        let mut g_cams: Vec<DVector<R>> = layouts.iter().map(|l| DVector::zeros(l.len())).collect();
+        let mut v = Vec::with_capacity(state.points.len());
+        let mut g_points = Vec::with_capacity(state.points.len());
+        let mut w = Vec::with_capacity(state.points.len());
+
+        let point_step: R = na::convert(1e-6);
+        for (point, point_obs) in state.points.iter().zip(observations.iter()) {
+            let mut v_p = Matrix3::zeros();
+            let mut g_p = Vector3::zeros();
+            let mut w_p = Vec::with_capacity(point_obs.len());
+            for obs in point_obs.iter() {
+                let c = obs.cam_idx;
+                let r = residual(&cams[c], point, obs);
+                let r = na::Vector2::new(r[0], r[1]);
+
+                let mut j_point = na::Matrix2x3::zeros();
+                for k in 0..3 {
+                    let mut plus = *point;
+                    plus[k] += point_step;
+                    let mut minus = *point;
+                    minus[k] -= point_step;
+                    let rp = residual(&cams[c], &plus, obs);
+                    let rm = residual(&cams[c], &minus, obs);
+                    j_point[(0, k)] = (rp[0] - rm[0]) / (two * point_step);
+                    j_point[(1, k)] = (rp[1] - rm[1]) / (two * point_step);
+                }
+
+                let n = perturbed[c].len();
+                let mut j_cam = DMatrix::zeros(2, n);
+                for (k, (plus, minus, step)) in perturbed[c].iter().enumerate() {
+                    let rp = residual(plus, point, obs);
+                    let rm = residual(minus, point, obs);
+                    j_cam[(0, k)] = (rp[0] - rm[0]) / (two * *step);
+                    j_cam[(1, k)] = (rp[1] - rm[1]) / (two * *step);
+                }
+
+                v_p += j_point.transpose() * j_point;
+                g_p += j_point.transpose() * r;
+                if n > 0 {
+                    u[c] += j_cam.transpose() * &j_cam;
+                    g_cams[c] += j_cam.transpose() * r;
+                    let j_point_dyn = DMatrix::from_iterator(2, 3, j_point.iter().copied());
+                    w_p.push((c, j_cam.transpose() * j_point_dyn));
+                }
+            }
+            v.push(v_p);
+            g_points.push(g_p);
+            w.push(w_p);
+        }
+
+        Ok(Self {
+            offsets,
+            n_cam_params,
+            u,
+            g_cams,
+            v,
+            g_points,
+            w,
+        })
+    }
+
+    /// Solve the damped normal equations for the parameter updates.
+    ///
+    /// Returns `None` if the system cannot be solved.
+    fn solve(&self, lambda: R) -> Option<(Vec<DVector<R>>, Vec<Vector3<R>>)> {
+        let one = R::one();
+        let eps: R = na::convert(1e-9);
+
+        // Marquardt damping of the diagonal.
+        let v_inv: Vec<Matrix3<R>> = self
+            .v
+            .iter()
+            .map(|v| {
+                let mut damped = *v;
+                for i in 0..3 {
+                    damped[(i, i)] = v[(i, i)] * (one + lambda) + eps;
+                }
+                damped.try_inverse()
+            })
+            .collect::<Option<_>>()?;
+
+        // The reduced camera system (Schur complement of the point blocks).
+        let n = self.n_cam_params;
+        let mut s = DMatrix::zeros(n, n);
+        let mut rhs = DVector::zeros(n);
+        for (c, (u, g)) in self.u.iter().zip(self.g_cams.iter()).enumerate() {
+            let o = self.offsets[c];
+            let len = u.nrows();
+            let mut damped = u.clone();
+            for i in 0..len {
+                damped[(i, i)] = u[(i, i)] * (one + lambda) + eps;
+            }
+            s.slice_mut((o, o), (len, len)).copy_from(&damped);
+            rhs.rows_mut(o, len).copy_from(&(-g));
+        }
+        for ((w_p, v_inv), g_p) in self.w.iter().zip(v_inv.iter()).zip(self.g_points.iter()) {
+            let v_inv_dyn = DMatrix::from_iterator(3, 3, v_inv.iter().copied());
+            let g_p_dyn = DVector::from_iterator(3, g_p.iter().copied());
+            for (c1, w1) in w_p.iter() {
+                let w1_v_inv = w1 * &v_inv_dyn;
+                let o1 = self.offsets[*c1];
+                let mut rhs_block = rhs.rows_mut(o1, w1.nrows());
+                rhs_block += &w1_v_inv * &g_p_dyn;
+                for (c2, w2) in w_p.iter() {
+                    let o2 = self.offsets[*c2];
+                    let mut block = s.slice_mut((o1, o2), (w1.nrows(), w2.nrows()));
+                    block -= &w1_v_inv * w2.transpose();
+                }
+            }
+        }
+
+        let delta = if n > 0 {
+            s.cholesky()?.solve(&rhs)
+        } else {
+            DVector::zeros(0)
+        };
+
+        let delta_cams: Vec<DVector<R>> = self
+            .u
+            .iter()
+            .enumerate()
+            .map(|(c, u)| delta.rows(self.offsets[c], u.nrows()).into_owned())
+            .collect();
+
+        let delta_points = self
+            .w
+            .iter()
+            .zip(v_inv.iter())
+            .zip(self.g_points.iter())
+            .map(|((w_p, v_inv), g_p)| {
+                let mut b = -g_p;
+                for (c, w) in w_p.iter() {
+                    let wt_delta = w.transpose() * &delta_cams[*c];
+                    b -= Vector3::new(wt_delta[0], wt_delta[1], wt_delta[2]);
+                }
+                v_inv * b
+            })
+            .collect();
+
+        Some((delta_cams, delta_points))
+    }
+}
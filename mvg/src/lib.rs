@@ -40,6 +40,10 @@ pub enum MvgError {
     NotImplemented,
     #[error("cannot convert to flydra xml")]
     CannotConvertToFlydraXml,
+    #[error("camera {name} shares too few points with the other cameras")]
+    CameraNotConnected { name: String },
+    #[error("self-calibration failed: {}", error)]
+    SelfCalibrationFailed { error: &'static str },
     #[error("IO error: {source}")]
     Io {
         #[from]
@@ -106,6 +110,10 @@ pub use crate::camera::{rq_decomposition, Camera};
 mod multi_cam_system;
 pub use crate::multi_cam_system::MultiCameraSystem;
 
+pub mod bundle_adjustment;
+
+pub mod self_calibration;
+
 #[derive(Debug, Clone)]
 pub struct DistortedPixel<R: RealField> {
     pub coords: Point2<R>,
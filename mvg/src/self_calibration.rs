@@ -0,0 +1,860 @@
+//! Calibration of a [MultiCameraSystem] from scratch
+//!
+//! A single point, such as an LED, is moved through the volume seen by the
+//! cameras. From the 2D observations of this point alone, a projective
+//! reconstruction is found and refined by iterative factorization of the
+//! measurement matrix. This is upgraded to a metric reconstruction by
+//! assuming zero skew, square pixels and a principal point near the image
+//! center (or known intrinsic parameters), and finally refined by
+//! [bundle_adjust]. The approach follows the MultiCamSelfCal toolbox of
+//! Svoboda et al.
+//!
+//! The calibrated system has an arbitrary position, orientation and scale.
+//! Use [align_to_known_points] to move it into a world coordinate frame.
+
+use std::collections::BTreeMap;
+
+use na::geometry::{Point3, Rotation3, UnitQuaternion};
+use na::storage::Owned;
+use na::{
+    DMatrix, DVector, Matrix3, Matrix3x4, Matrix4, RealField, Vector2, Vector3, Vector4, U1, U2,
+};
+use nalgebra as na;
+use serde::Serialize;
+
+use crate::bundle_adjustment::{bundle_adjust, BundleAdjustmentOptions, ReprojectionReport};
+use crate::{
+    Camera, DistortedPixel, ExtrinsicParameters, MultiCameraSystem, MvgError, PointWorldFrame,
+    Result, RosOpenCvIntrinsics, UndistortedPixel,
+};
+
+/// Minimum number of points shared by the first two cameras.
+const MIN_PAIR_POINTS: usize = 8;
+
+/// Minimum number of reconstructed points seen by each further camera.
+const MIN_RESECTION_POINTS: usize = 6;
+
+/// A camera to be calibrated.
+#[derive(Debug, Clone)]
+pub struct SelfCalibrationCamera<R: RealField> {
+    pub name: String,
+    pub width: usize,
+    pub height: usize,
+    /// The intrinsic parameters, if known.
+    ///
+    /// Known intrinsic parameters are kept as is. Otherwise, the focal
+    /// lengths and principal point are estimated and lens distortion is
+    /// ignored.
+    pub intrinsics: Option<RosOpenCvIntrinsics<R>>,
+}
+
+/// Parameters of [self_calibrate].
+#[derive(Debug, Clone)]
+pub struct SelfCalibrationOptions {
+    /// Minimum number of cameras which must observe a point for it to be used.
+    pub min_views: usize,
+    /// Maximum number of iterations of the projective factorization.
+    pub factorization_iterations: usize,
+    /// Maximum number of bundle adjustment iterations.
+    pub max_iterations: usize,
+    /// Observations with a larger reprojection error (in pixels) after
+    /// bundle adjustment are discarded and bundle adjustment is repeated.
+    pub outlier_threshold: f64,
+}
+
+impl Default for SelfCalibrationOptions {
+    fn default() -> Self {
+        Self {
+            min_views: 2,
+            factorization_iterations: 50,
+            max_iterations: 100,
+            outlier_threshold: 5.0,
+        }
+    }
+}
+
+/// The result of [self_calibrate].
+#[derive(Debug, Clone)]
+pub struct SelfCalibrationResult<R: RealField + Default + Serialize> {
+    /// The calibrated camera system.
+    pub system: MultiCameraSystem<R>,
+    /// Reprojection errors of the observations used.
+    pub report: ReprojectionReport<R>,
+    /// Number of points used.
+    pub n_points: usize,
+    /// Number of observations discarded as outliers.
+    pub n_outliers: usize,
+}
+
+/// Calibrate `cameras` from observations of a single moving point.
+///
+/// Each element of `points` are the simultaneous observations of the point,
+/// given as camera name and distorted pixel coordinates. Unknown intrinsic
+/// parameters are refined by bundle adjustment, known ones are kept as is.
+pub fn self_calibrate<R>(
+    cameras: &[SelfCalibrationCamera<R>],
+    points: &[Vec<(String, DistortedPixel<R>)>],
+    opts: &SelfCalibrationOptions,
+) -> Result<SelfCalibrationResult<R>>
+where
+    R: RealField + Default + Serialize,
+{
+    if cameras.len() < 2 {
+        return Err(MvgError::SelfCalibrationFailed {
+            error: "at least two cameras are required",
+        });
+    }
+    let normalizations = cameras
+        .iter()
+        .map(Normalization::new)
+        .collect::<Result<Vec<_>>>()?;
+
+    // Keep the points seen by enough cameras, with their observations in
+    // normalized image coordinates.
+    let min_views = opts.min_views.max(2);
+    let mut used: Vec<Vec<(String, DistortedPixel<R>)>> = Vec::new();
+    let mut visible: Vec<Vec<(usize, Vector2<R>)>> = Vec::new();
+    for point_obs in points.iter() {
+        let mut obs = Vec::with_capacity(point_obs.len());
+        let mut vis = Vec::with_capacity(point_obs.len());
+        for (cam_name, distorted) in point_obs.iter() {
+            let cam_idx = cameras
+                .iter()
+                .position(|cam| &cam.name == cam_name)
+                .ok_or(MvgError::UnknownCamera)?;
+            if vis.iter().any(|(i, _)| *i == cam_idx) {
+                continue;
+            }
+            obs.push((cam_name.clone(), distorted.clone()));
+            vis.push((cam_idx, normalizations[cam_idx].normalize(distorted)));
+        }
+        if vis.len() >= min_views {
+            used.push(obs);
+            visible.push(vis);
+        }
+    }
+
+    let (mut pmats, mut xs) = projective_reconstruction(cameras, &visible)?;
+    factorize(&mut pmats, &mut xs, &visible, opts.factorization_iterations)?;
+
+    let known: Vec<bool> = cameras.iter().map(|cam| cam.intrinsics.is_some()).collect();
+    let h = metric_upgrade(&pmats, &known)?;
+    let h_inv = h.try_inverse().ok_or(MvgError::SelfCalibrationFailed {
+        error: "metric upgrade failed",
+    })?;
+    let mut pmats: Vec<Matrix3x4<R>> = pmats.iter().map(|p| p * h).collect();
+    let mut xs: Vec<Vector4<R>> = xs.iter().map(|x| h_inv * x).collect();
+    fix_cheirality(&mut pmats, &mut xs, &visible);
+    normalize_frame(&mut pmats, &xs)?;
+
+    let mut cams_by_name = BTreeMap::new();
+    for ((cam, normalization), pmat) in cameras.iter().zip(normalizations.iter()).zip(pmats.iter())
+    {
+        let pmat = normalization.to_pixels * pmat;
+        let mut camera = Camera::from_pmat(cam.width, cam.height, &pmat)?;
+        if let Some(intrinsics) = &cam.intrinsics {
+            camera = Camera::new(
+                cam.width,
+                cam.height,
+                camera.extrinsics().clone(),
+                intrinsics.clone(),
+            )?;
+        }
+        cams_by_name.insert(cam.name.clone(), camera);
+    }
+    let system = MultiCameraSystem::new(cams_by_name);
+
+    // Refine, discard the outliers and refine again.
+    let ba_opts = BundleAdjustmentOptions {
+        optimize_intrinsics: true,
+        fixed_intrinsics: cameras
+            .iter()
+            .filter(|cam| cam.intrinsics.is_some())
+            .map(|cam| cam.name.clone())
+            .collect(),
+        optimize_distortion: false,
+        max_iterations: opts.max_iterations,
+    };
+    let result = bundle_adjust(&system, &used, &ba_opts)?;
+
+    let threshold: R = na::convert(opts.outlier_threshold);
+    let mut inliers = Vec::with_capacity(used.len());
+    let mut n_outliers = 0;
+    for (point_obs, point) in used.iter().zip(result.points.iter()) {
+        let mut kept = Vec::with_capacity(point_obs.len());
+        for (cam_name, distorted) in point_obs.iter() {
+            let cam = result
+                .system
+                .cam_by_name(cam_name)
+                .ok_or(MvgError::UnknownCamera)?;
+            let projected = cam.project_3d_to_distorted_pixel(point);
+            if (projected.coords - distorted.coords).norm() <= threshold {
+                kept.push((cam_name.clone(), distorted.clone()));
+            }
+        }
+        if kept.len() >= min_views {
+            n_outliers += point_obs.len() - kept.len();
+            inliers.push(kept);
+        } else {
+            n_outliers += point_obs.len();
+        }
+    }
+    let result = if n_outliers > 0 {
+        bundle_adjust(&result.system, &inliers, &ba_opts)?
+    } else {
+        result
+    };
+
+    Ok(SelfCalibrationResult {
+        system: result.system,
+        report: result.after,
+        n_points: inliers.len(),
+        n_outliers,
+    })
+}
+
+/// Move `system` such that points are at known world coordinates.
+///
+/// Each element of `known` is the world coordinate of a point and its
+/// observations, given as camera name and distorted pixel coordinates. The
+/// similarity transform (rotation, translation and scale) which best maps
+/// the triangulated points onto the world coordinates is applied to all
+/// cameras. At least three points are required.
+pub fn align_to_known_points<R>(
+    system: &MultiCameraSystem<R>,
+    known: &[(PointWorldFrame<R>, Vec<(String, DistortedPixel<R>)>)],
+) -> Result<MultiCameraSystem<R>>
+where
+    R: RealField + Default + Serialize,
+{
+    if known.len() < 3 {
+        return Err(MvgError::NotEnoughPoints);
+    }
+    let mut src = Vec::with_capacity(known.len());
+    let mut dst = Vec::with_capacity(known.len());
+    for (world, point_obs) in known.iter() {
+        let mut undistorted = Vec::with_capacity(point_obs.len());
+        for (cam_name, distorted) in point_obs.iter() {
+            let cam = system
+                .cam_by_name(cam_name)
+                .ok_or(MvgError::UnknownCamera)?;
+            let pixels: cam_geom::Pixels<R, U1, Owned<R, U1, U2>> = distorted.into();
+            let undist: UndistortedPixel<R> = cam.intrinsics().undistort(&pixels).into();
+            undistorted.push((cam_name.clone(), undist));
+        }
+        src.push(system.find3d(&undistorted)?.coords.coords);
+        dst.push(world.coords.coords);
+    }
+
+    // The least squares similarity transform (Umeyama, 1991).
+    let n: R = na::convert(known.len() as f64);
+    let mean_src = src.iter().fold(Vector3::zeros(), |acc, x| acc + x) / n;
+    let mean_dst = dst.iter().fold(Vector3::zeros(), |acc, x| acc + x) / n;
+    let mut covariance = Matrix3::zeros();
+    let mut var_src = R::zero();
+    for (s, d) in src.iter().zip(dst.iter()) {
+        let s = s - mean_src;
+        let d = d - mean_dst;
+        covariance += d * s.transpose();
+        var_src += s.norm_squared();
+    }
+    covariance /= n;
+    var_src /= n;
+    if var_src <= na::convert(1e-12) {
+        return Err(MvgError::NotEnoughPoints);
+    }
+
+    let svd = covariance.svd(true, true);
+    let u = svd.u.ok_or(MvgError::SvdFailed)?;
+    let v_t = svd.v_t.ok_or(MvgError::SvdFailed)?;
+    let mut signs = Vector3::repeat(R::one());
+    if u.determinant() * v_t.determinant() < R::zero() {
+        signs[argmin(svd.singular_values.as_slice())] = -R::one();
+    }
+    let rotation = u * Matrix3::from_diagonal(&signs) * v_t;
+    let scale = svd.singular_values.component_mul(&signs).sum() / var_src;
+    let translation = mean_dst - rotation * mean_src * scale;
+
+    transform_system(
+        system,
+        scale,
+        &Rotation3::from_matrix_unchecked(rotation),
+        &translation,
+    )
+}
+
+/// Apply the similarity transform `x -> scale * rotation * x + translation`.
+fn transform_system<R>(
+    system: &MultiCameraSystem<R>,
+    scale: R,
+    rotation: &Rotation3<R>,
+    translation: &Vector3<R>,
+) -> Result<MultiCameraSystem<R>>
+where
+    R: RealField + Default + Serialize,
+{
+    let mut cams_by_name = BTreeMap::new();
+    for (name, cam) in system.cams_by_name().iter() {
+        let extrinsics = cam.extrinsics();
+        let cam_rotation = extrinsics.rotation() * rotation.inverse();
+        let camcenter =
+            Point3::from(rotation * extrinsics.camcenter().coords * scale + translation);
+        let extrinsics = ExtrinsicParameters::from_rotation_and_camcenter(
+            UnitQuaternion::from_rotation_matrix(&cam_rotation),
+            camcenter,
+        );
+        let cam = Camera::new(
+            cam.width(),
+            cam.height(),
+            extrinsics,
+            cam.intrinsics().clone(),
+        )?;
+        cams_by_name.insert(name.clone(), cam);
+    }
+    Ok(MultiCameraSystem::new_inner(
+        cams_by_name,
+        system.comment().cloned(),
+    ))
+}
+
+/// Conversion between pixel and normalized image coordinates of a camera.
+///
+/// With known intrinsic parameters, normalized coordinates are undistorted
+/// and have unit focal length. Otherwise, the image center is moved to the
+/// origin and the image scaled to a size of about two.
+struct Normalization<R: RealField> {
+    intrinsics: Option<RosOpenCvIntrinsics<R>>,
+    from_pixels: Matrix3<R>,
+    to_pixels: Matrix3<R>,
+}
+
+impl<R: RealField> Normalization<R> {
+    fn new(cam: &SelfCalibrationCamera<R>) -> Result<Self> {
+        let to_pixels = match &cam.intrinsics {
+            Some(intrinsics) => {
+                if !intrinsics.rect.is_identity(na::convert(1.0e-7)) {
+                    return Err(MvgError::RectificationMatrixNotSupported);
+                }
+                intrinsics.p.fixed_slice::<3, 3>(0, 0).into_owned()
+            }
+            None => {
+                let zero = R::zero();
+                let two: R = na::convert(2.0);
+                let width: R = na::convert(cam.width as f64);
+                let height: R = na::convert(cam.height as f64);
+                let s = (width + height) / two;
+                Matrix3::new(
+                    s,
+                    zero,
+                    width / two,
+                    zero,
+                    s,
+                    height / two,
+                    zero,
+                    zero,
+                    R::one(),
+                )
+            }
+        };
+        let from_pixels = to_pixels
+            .try_inverse()
+            .ok_or(MvgError::SelfCalibrationFailed {
+                error: "singular intrinsic parameters",
+            })?;
+        Ok(Self {
+            intrinsics: cam.intrinsics.clone(),
+            from_pixels,
+            to_pixels,
+        })
+    }
+
+    fn normalize(&self, distorted: &DistortedPixel<R>) -> Vector2<R> {
+        let coords = match &self.intrinsics {
+            Some(intrinsics) => {
+                let pixels: cam_geom::Pixels<R, U1, Owned<R, U1, U2>> = distorted.into();
+                let undistorted: UndistortedPixel<R> = intrinsics.undistort(&pixels).into();
+                undistorted.coords
+            }
+            None => distorted.coords,
+        };
+        let x = self.from_pixels * Vector3::new(coords.x, coords.y, R::one());
+        Vector2::new(x[0] / x[2], x[1] / x[2])
+    }
+}
+
+/// Find a projective reconstruction of all cameras and points.
+///
+/// The two cameras sharing the most points are reconstructed from their
+/// fundamental matrix. Then, the camera seeing the most reconstructed points
+/// is added by resection and the points it shares with the reconstructed
+/// cameras are triangulated, until all cameras are reconstructed.
+fn projective_reconstruction<R: RealField>(
+    cameras: &[SelfCalibrationCamera<R>],
+    visible: &[Vec<(usize, Vector2<R>)>],
+) -> Result<(Vec<Matrix3x4<R>>, Vec<Vector4<R>>)> {
+    let n_cams = cameras.len();
+    let mut shared = vec![vec![0usize; n_cams]; n_cams];
+    for vis in visible.iter() {
+        for (a, _) in vis.iter() {
+            for (b, _) in vis.iter() {
+                shared[*a][*b] += 1;
+            }
+        }
+    }
+    let mut best = (0, 1, 0);
+    for a in 0..n_cams {
+        for b in (a + 1)..n_cams {
+            if shared[a][b] > best.2 {
+                best = (a, b, shared[a][b]);
+            }
+        }
+    }
+    let (cam_a, cam_b, n_shared) = best;
+    if n_shared < MIN_PAIR_POINTS {
+        return Err(MvgError::NotEnoughPoints);
+    }
+
+    let pairs: Vec<(Vector2<R>, Vector2<R>)> = visible
+        .iter()
+        .filter_map(|vis| {
+            let (_, xa) = vis.iter().find(|(i, _)| *i == cam_a)?;
+            let (_, xb) = vis.iter().find(|(i, _)| *i == cam_b)?;
+            Some((*xa, *xb))
+        })
+        .collect();
+    let f = fundamental_matrix(&pairs)?;
+    let e = null_vector(DMatrix::from_column_slice(3, 3, f.transpose().as_slice()))?;
+    let e = Vector3::new(e[0], e[1], e[2]);
+
+    // The canonical camera pair of the fundamental matrix.
+    let mut pmats: Vec<Option<Matrix3x4<R>>> = vec![None; n_cams];
+    pmats[cam_a] = Some(Matrix3x4::identity());
+    let mut p_b = Matrix3x4::zeros();
+    p_b.fixed_slice_mut::<3, 3>(0, 0)
+        .copy_from(&(e.cross_matrix() * f));
+    p_b.set_column(3, &e);
+    pmats[cam_b] = Some(p_b);
+
+    let mut xs: Vec<Option<Vector4<R>>> = vec![None; visible.len()];
+    triangulate_missing(&pmats, &mut xs, visible)?;
+
+    loop {
+        let mut next: Option<(usize, usize)> = None;
+        for (cam_idx, pmat) in pmats.iter().enumerate() {
+            if pmat.is_some() {
+                continue;
+            }
+            let n = visible
+                .iter()
+                .zip(xs.iter())
+                .filter(|(vis, x)| x.is_some() && vis.iter().any(|(i, _)| *i == cam_idx))
+                .count();
+            let is_better = match next {
+                Some((_, best_n)) => n > best_n,
+                None => true,
+            };
+            if is_better {
+                next = Some((cam_idx, n));
+            }
+        }
+        let (cam_idx, n) = match next {
+            Some(next) => next,
+            None => break,
+        };
+        if n < MIN_RESECTION_POINTS {
+            return Err(MvgError::CameraNotConnected {
+                name: cameras[cam_idx].name.clone(),
+            });
+        }
+        let correspondences: Vec<(Vector4<R>, Vector2<R>)> = visible
+            .iter()
+            .zip(xs.iter())
+            .filter_map(|(vis, x)| {
+                let (_, u) = vis.iter().find(|(i, _)| *i == cam_idx)?;
+                Some(((*x)?, *u))
+            })
+            .collect();
+        pmats[cam_idx] = Some(resection(&correspondences)?);
+        triangulate_missing(&pmats, &mut xs, visible)?;
+    }
+
+    // Now all cameras are reconstructed and thus all points, which are each
+    // seen by at least two cameras, are triangulated.
+    Ok((
+        pmats.into_iter().flatten().collect(),
+        xs.into_iter().flatten().collect(),
+    ))
+}
+
+/// Refine a projective reconstruction by iterative factorization.
+///
+/// The measurement matrix is built from the observations scaled by their
+/// projective depths, with unobserved entries filled in by reprojection. Its
+/// best rank 4 approximation yields new cameras and points. This is repeated
+/// while the reprojection error decreases.
+fn factorize<R: RealField>(
+    pmats: &mut [Matrix3x4<R>],
+    xs: &mut [Vector4<R>],
+    visible: &[Vec<(usize, Vector2<R>)>],
+    max_iterations: usize,
+) -> Result<()> {
+    let n_cams = pmats.len();
+    let tolerance: R = na::convert(1e-6);
+    let mut error = projective_error(pmats, xs, visible);
+    for _ in 0..max_iterations {
+        let mut w = DMatrix::zeros(3 * n_cams, xs.len());
+        for (j, (x, vis)) in xs.iter().zip(visible.iter()).enumerate() {
+            for (i, p) in pmats.iter().enumerate() {
+                let projected = p * x;
+                let entry = match vis.iter().find(|(cam_idx, _)| *cam_idx == i) {
+                    Some((_, u)) => Vector3::new(u.x, u.y, R::one()) * projected[2],
+                    None => projected,
+                };
+                w.fixed_slice_mut::<3, 1>(3 * i, j).copy_from(&entry);
+            }
+        }
+
+        let svd = w.svd(true, true);
+        let u = svd.u.ok_or(MvgError::SvdFailed)?;
+        let v_t = svd.v_t.ok_or(MvgError::SvdFailed)?;
+        let sigma = &svd.singular_values;
+        let mut order: Vec<usize> = (0..sigma.len()).collect();
+        order.sort_by(|a, b| {
+            sigma[*b]
+                .partial_cmp(&sigma[*a])
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+
+        let new_pmats: Vec<Matrix3x4<R>> = (0..n_cams)
+            .map(|i| {
+                let p = Matrix3x4::from_fn(|r, c| u[(3 * i + r, order[c])] * sigma[order[c]]);
+                p / p.norm()
+            })
+            .collect();
+        let new_xs: Vec<Vector4<R>> = (0..xs.len())
+            .map(|j| Vector4::from_fn(|r, _| v_t[(order[r], j)]).normalize())
+            .collect();
+
+        let new_error = projective_error(&new_pmats, &new_xs, visible);
+        let improved = new_error < error;
+        if !improved {
+            break;
+        }
+        let converged = error - new_error < tolerance * error;
+        pmats.copy_from_slice(&new_pmats);
+        xs.copy_from_slice(&new_xs);
+        error = new_error;
+        if converged {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// The mean reprojection error in normalized image coordinates.
+fn projective_error<R: RealField>(
+    pmats: &[Matrix3x4<R>],
+    xs: &[Vector4<R>],
+    visible: &[Vec<(usize, Vector2<R>)>],
+) -> R {
+    let mut sum = R::zero();
+    let mut n = 0;
+    for (x, vis) in xs.iter().zip(visible.iter()) {
+        for (i, u) in vis.iter() {
+            let p = pmats[*i] * x;
+            sum += Vector2::new(p[0] / p[2] - u.x, p[1] / p[2] - u.y).norm();
+            n += 1;
+        }
+    }
+    sum / na::convert(n.max(1) as f64)
+}
+
+/// The upper triangle of a symmetric 4x4 matrix, in parameter order.
+const QUADRIC_ELEMENTS: [(usize, usize); 10] = [
+    (0, 0),
+    (0, 1),
+    (0, 2),
+    (0, 3),
+    (1, 1),
+    (1, 2),
+    (1, 3),
+    (2, 2),
+    (2, 3),
+    (3, 3),
+];
+
+/// The coefficients of element `(a, b)` of `p * q * p^T` in the parameters
+/// of the symmetric matrix `q`.
+fn omega_coefficients<R: RealField>(p: &Matrix3x4<R>, a: usize, b: usize) -> [R; 10] {
+    let mut result = [R::zero(); 10];
+    for (e, (k, l)) in QUADRIC_ELEMENTS.iter().enumerate() {
+        result[e] = if k == l {
+            p[(a, *k)] * p[(b, *k)]
+        } else {
+            p[(a, *k)] * p[(b, *l)] + p[(a, *l)] * p[(b, *k)]
+        };
+    }
+    result
+}
+
+/// Find the transform `h` from the projective to a metric reconstruction.
+///
+/// The absolute dual quadric `q` is estimated linearly from the image of the
+/// absolute conic `p * q * p^T` of each camera, which is proportional to
+/// `k * k^T` of its intrinsic parameters `k`. In normalized image
+/// coordinates, skew and principal point are about zero and the aspect ratio
+/// about one. Focal lengths are about one only if the intrinsic parameters
+/// are known. The constraints are weighted by how well these assumptions
+/// hold (Pollefeys et al., 2004) and the estimate is refined once by
+/// reweighting with its scale of each camera. Then, `q = h * diag(1, 1, 1, 0)
+/// * h^T`.
+fn metric_upgrade<R: RealField>(pmats: &[Matrix3x4<R>], known: &[bool]) -> Result<Matrix4<R>> {
+    let zero = R::zero();
+    let aspect_weight: R = na::convert(1.0 / 0.2);
+    let skew_weight: R = na::convert(1.0 / 0.01);
+    let principal_point_weight: R = na::convert(1.0 / 0.1);
+
+    let mut scales = vec![R::one(); pmats.len()];
+    let mut q = Matrix4::zeros();
+    for _ in 0..2 {
+        let mut a = DMatrix::zeros(6 * pmats.len(), 10);
+        for (i, p) in pmats.iter().enumerate() {
+            let focal_weight: R = if known[i] {
+                na::convert(1.0 / 0.1)
+            } else {
+                na::convert(1.0 / 9.0)
+            };
+            let c00 = omega_coefficients(p, 0, 0);
+            let c11 = omega_coefficients(p, 1, 1);
+            let c22 = omega_coefficients(p, 2, 2);
+            let equations = [
+                (focal_weight, c00, Some(c22)),
+                (focal_weight, c11, Some(c22)),
+                (aspect_weight, c00, Some(c11)),
+                (skew_weight, omega_coefficients(p, 0, 1), None),
+                (principal_point_weight, omega_coefficients(p, 0, 2), None),
+                (principal_point_weight, omega_coefficients(p, 1, 2), None),
+            ];
+            for (k, (weight, plus, minus)) in equations.iter().enumerate() {
+                for (e, plus) in plus.iter().enumerate() {
+                    let mut value = *plus;
+                    if let Some(minus) = minus {
+                        value -= minus[e];
+                    }
+                    a[(6 * i + k, e)] = value * *weight / scales[i];
+                }
+            }
+        }
+        let params = null_vector(a)?;
+        for (e, (k, l)) in QUADRIC_ELEMENTS.iter().enumerate() {
+            q[(*k, *l)] = params[e];
+            q[(*l, *k)] = params[e];
+        }
+        let tiny: R = na::convert(1e-12);
+        for (scale, p) in scales.iter_mut().zip(pmats.iter()) {
+            *scale = (p * q * p.transpose())[(2, 2)].abs().max(tiny);
+        }
+    }
+
+    let eigen = na::SymmetricEigen::new(q);
+    let mut values = eigen.eigenvalues;
+    // The sign of the estimate is arbitrary.
+    if values.iter().filter(|v| **v > zero).count() < 2 {
+        values = -values;
+    }
+    let mut order: Vec<usize> = (0..4).collect();
+    order.sort_by(|a, b| {
+        values[*b]
+            .partial_cmp(&values[*a])
+            .unwrap_or(std::cmp::Ordering::Equal)
+    });
+    if values[order[2]] <= zero {
+        return Err(MvgError::SelfCalibrationFailed {
+            error: "metric upgrade failed",
+        });
+    }
+    let mut h = Matrix4::zeros();
+    for (c, idx) in order.iter().enumerate() {
+        let scale = if c < 3 { values[*idx].sqrt() } else { R::one() };
+        h.set_column(c, &(eigen.eigenvectors.column(*idx) * scale));
+    }
+    Ok(h)
+}
+
+/// Put the points in front of the cameras.
+///
+/// A metric reconstruction and its point reflection explain the
+/// observations equally well, but only one has the points in front of the
+/// cameras. Afterwards, the sign of each camera matrix is chosen to give a
+/// rotation (rather than a reflection).
+fn fix_cheirality<R: RealField>(
+    pmats: &mut [Matrix3x4<R>],
+    xs: &mut [Vector4<R>],
+    visible: &[Vec<(usize, Vector2<R>)>],
+) {
+    let zero = R::zero();
+    let one = R::one();
+    let mut n_front = 0;
+    let mut n_total = 0;
+    for (x, vis) in xs.iter().zip(visible.iter()) {
+        for (i, _) in vis.iter() {
+            let p = &pmats[*i];
+            let depth = p.fixed_slice::<3, 3>(0, 0).determinant() * (p * x)[2] * x[3];
+            if depth > zero {
+                n_front += 1;
+            }
+            n_total += 1;
+        }
+    }
+    if 2 * n_front < n_total {
+        let reflection = Matrix4::from_diagonal(&Vector4::new(-one, -one, -one, one));
+        for p in pmats.iter_mut() {
+            *p *= reflection;
+        }
+        for x in xs.iter_mut() {
+            *x = reflection * *x;
+        }
+    }
+    for p in pmats.iter_mut() {
+        if p.fixed_slice::<3, 3>(0, 0).determinant() < zero {
+            *p = -*p;
+        }
+    }
+}
+
+/// Move the centroid of the points to the origin and scale their RMS
+/// distance from it to one.
+fn normalize_frame<R: RealField>(pmats: &mut [Matrix3x4<R>], xs: &[Vector4<R>]) -> Result<()> {
+    let eps: R = na::convert(1e-9);
+    let finite: Vec<Vector3<R>> = xs
+        .iter()
+        .filter(|x| x[3].abs() > eps * x.norm())
+        .map(|x| x.xyz() / x[3])
+        .collect();
+    if finite.is_empty() {
+        return Err(MvgError::SelfCalibrationFailed {
+            error: "all points at infinity",
+        });
+    }
+    let n: R = na::convert(finite.len() as f64);
+    let centroid = finite.iter().fold(Vector3::zeros(), |acc, x| acc + x) / n;
+    let rms = (finite
+        .iter()
+        .fold(R::zero(), |acc, x| acc + (x - centroid).norm_squared())
+        / n)
+        .sqrt();
+
+    // The points are transformed by `[I/rms, -centroid/rms; 0, 1]`, so the
+    // cameras by its inverse.
+    let mut t_inv = Matrix4::identity() * rms;
+    t_inv[(3, 3)] = R::one();
+    t_inv.fixed_slice_mut::<3, 1>(0, 3).copy_from(&centroid);
+    for p in pmats.iter_mut() {
+        *p *= t_inv;
+    }
+    Ok(())
+}
+
+/// The fundamental matrix `f` with `x2^T * f * x1 = 0` for all `(x1, x2)`.
+fn fundamental_matrix<R: RealField>(pairs: &[(Vector2<R>, Vector2<R>)]) -> Result<Matrix3<R>> {
+    let mut a = DMatrix::zeros(pairs.len(), 9);
+    for (k, (x1, x2)) in pairs.iter().enumerate() {
+        let row = [
+            x2.x * x1.x,
+            x2.x * x1.y,
+            x2.x,
+            x2.y * x1.x,
+            x2.y * x1.y,
+            x2.y,
+            x1.x,
+            x1.y,
+            R::one(),
+        ];
+        for (c, value) in row.iter().enumerate() {
+            a[(k, c)] = *value;
+        }
+    }
+    let f = null_vector(a)?;
+    let f = Matrix3::from_row_slice(f.as_slice());
+
+    // Enforce rank 2.
+    let mut svd = f.svd(true, true);
+    let imin = argmin(svd.singular_values.as_slice());
+    svd.singular_values[imin] = R::zero();
+    svd.recompose().map_err(|_| MvgError::SvdFailed)
+}
+
+/// Triangulate the points seen by at least two reconstructed cameras.
+fn triangulate_missing<R: RealField>(
+    pmats: &[Option<Matrix3x4<R>>],
+    xs: &mut [Option<Vector4<R>>],
+    visible: &[Vec<(usize, Vector2<R>)>],
+) -> Result<()> {
+    for (x, vis) in xs.iter_mut().zip(visible.iter()) {
+        if x.is_some() {
+            continue;
+        }
+        let views: Vec<(&Matrix3x4<R>, &Vector2<R>)> = vis
+            .iter()
+            .filter_map(|(i, u)| pmats[*i].as_ref().map(|p| (p, u)))
+            .collect();
+        if views.len() >= 2 {
+            *x = Some(triangulate(&views)?);
+        }
+    }
+    Ok(())
+}
+
+/// The point seen at `u` by each camera `p`, by the direct linear transform.
+fn triangulate<R: RealField>(views: &[(&Matrix3x4<R>, &Vector2<R>)]) -> Result<Vector4<R>> {
+    let mut a = DMatrix::zeros(2 * views.len(), 4);
+    for (k, (p, u)) in views.iter().enumerate() {
+        for c in 0..4 {
+            a[(2 * k, c)] = u.x * p[(2, c)] - p[(0, c)];
+            a[(2 * k + 1, c)] = u.y * p[(2, c)] - p[(1, c)];
+        }
+    }
+    let x = null_vector(a)?;
+    Ok(Vector4::new(x[0], x[1], x[2], x[3]).normalize())
+}
+
+/// The camera seeing each point `x` at `u`, by the direct linear transform.
+fn resection<R: RealField>(correspondences: &[(Vector4<R>, Vector2<R>)]) -> Result<Matrix3x4<R>> {
+    let mut a = DMatrix::zeros(2 * correspondences.len(), 12);
+    for (k, (x, u)) in correspondences.iter().enumerate() {
+        for (c, xc) in x.iter().enumerate() {
+            a[(2 * k, c)] = *xc;
+            a[(2 * k, 8 + c)] = -u.x * *xc;
+            a[(2 * k + 1, 4 + c)] = *xc;
+            a[(2 * k + 1, 8 + c)] = -u.y * *xc;
+        }
+    }
+    let p = null_vector(a)?;
+    Ok(Matrix3x4::from_row_slice(p.as_slice()))
+}
+
+/// The unit vector `x` minimizing `|a * x|`.
+fn null_vector<R: RealField>(a: DMatrix<R>) -> Result<DVector<R>> {
+    let ncols = a.ncols();
+    // Pad with zero rows such that the SVD has all right singular vectors.
+    let a = if a.nrows() < ncols {
+        a.resize_vertically(ncols, R::zero())
+    } else {
+        a
+    };
+    let svd = a.svd(false, true);
+    let v_t = svd.v_t.ok_or(MvgError::SvdFailed)?;
+    let imin = argmin(svd.singular_values.as_slice());
+    Ok(v_t.row(imin).transpose())
+}
+
+/// The index of the smallest value.
+fn argmin<R: RealField>(values: &[R]) -> usize {
+    let mut best = 0;
+    for (i, value) in values.iter().enumerate() {
+        if *value < values[best] {
+            best = i;
+        }
+    }
+    best
+}
@@ -153,3 +153,207 @@ fn test_dlt_mvg() {
         approx::assert_relative_eq!(orig_uv.coords[1], new_uv.coords[1], epsilon = epsilon);
     }
 }
+
+#[test]
+fn test_bundle_adjustment() {
+    use mvg::bundle_adjustment::{bundle_adjust, BundleAdjustmentOptions};
+    use nalgebra::{UnitQuaternion, Vector3};
+    use std::collections::BTreeMap;
+
+    let up = Unit::new_normalize(Vector3::new(0.0, 0.0, 1.0));
+    let lookat = Vector3::new(0.0, 0.0, 0.0);
+    let camcenters = [
+        Vector3::new(2.0, 0.0, 0.5),
+        Vector3::new(0.0, 2.0, 0.5),
+        Vector3::new(-2.0, 0.2, 0.8),
+    ];
+
+    let mut true_cams = BTreeMap::new();
+    let mut perturbed_cams = BTreeMap::new();
+    for (i, cc) in camcenters.iter().enumerate() {
+        let name = format!("cam{}", i);
+        let extrinsics = cam_geom::ExtrinsicParameters::from_view(cc, &lookat, &up);
+        let intrinsics = mvg::make_default_intrinsics();
+        let cam = Camera::new(640, 480, extrinsics.clone(), intrinsics.clone()).unwrap();
+        true_cams.insert(name.clone(), cam);
+
+        // All but the first camera are moved a bit.
+        let perturbed = if i == 0 {
+            extrinsics
+        } else {
+            let rotation = UnitQuaternion::from_euler_angles(0.01, -0.005, 0.003)
+                * UnitQuaternion::from_rotation_matrix(extrinsics.rotation());
+            let camcenter = extrinsics.camcenter() + Vector3::new(0.01, -0.02, 0.005);
+            cam_geom::ExtrinsicParameters::from_rotation_and_camcenter(rotation, camcenter)
+        };
+        let cam = Camera::new(640, 480, perturbed, intrinsics).unwrap();
+        perturbed_cams.insert(name, cam);
+    }
+    let true_system = mvg::MultiCameraSystem::new(true_cams);
+    let perturbed_system = mvg::MultiCameraSystem::new(perturbed_cams);
+
+    let mut points = Vec::new();
+    for x in [-0.2, 0.0, 0.2].iter() {
+        for y in [-0.2, 0.0, 0.2].iter() {
+            for z in [-0.1, 0.1, 0.3].iter() {
+                let pt = PointWorldFrame {
+                    coords: Point3::new(*x, *y, *z),
+                };
+                let obs: Vec<(String, DistortedPixel<f64>)> = true_system
+                    .cams_by_name()
+                    .iter()
+                    .map(|(name, cam)| (name.clone(), cam.project_3d_to_distorted_pixel(&pt)))
+                    .collect();
+                points.push(obs);
+            }
+        }
+    }
+
+    let result = bundle_adjust(
+        &perturbed_system,
+        &points,
+        &BundleAdjustmentOptions::default(),
+    )
+    .unwrap();
+    assert!(result.before.all_cameras.mean > 1.0);
+    assert!(result.after.all_cameras.mean < 1e-3);
+    assert_eq!(result.after.all_cameras.n_observations, 27 * 3);
+    assert_eq!(result.points.len(), 27);
+
+    // The first camera is not changed.
+    let cam0 = result.system.cam_by_name("cam0").unwrap();
+    assert_relative_eq!(
+        cam0.extrinsics().camcenter(),
+        perturbed_system
+            .cam_by_name("cam0")
+            .unwrap()
+            .extrinsics()
+            .camcenter()
+    );
+
+    // The scale is not changed: the distance between the first and the second
+    // camera is kept.
+    let baseline = |system: &mvg::MultiCameraSystem<f64>| {
+        let cc0 = system.cam_by_name("cam0").unwrap().extrinsics().camcenter();
+        let cc1 = system.cam_by_name("cam1").unwrap().extrinsics().camcenter();
+        (cc1 - cc0).norm()
+    };
+    assert_relative_eq!(
+        baseline(&result.system),
+        baseline(&perturbed_system),
+        epsilon = 1e-9
+    );
+}
+
+#[test]
+fn test_self_calibration() {
+    use mvg::self_calibration::{
+        align_to_known_points, self_calibrate, SelfCalibrationCamera, SelfCalibrationOptions,
+    };
+    use nalgebra::Vector3;
+    use std::collections::BTreeMap;
+
+    let up = Unit::new_normalize(Vector3::new(0.0, 0.0, 1.0));
+    let lookat = Vector3::new(0.0, 0.0, 0.0);
+    let camcenters = [
+        Vector3::new(2.0, 0.0, 0.5),
+        Vector3::new(0.0, 2.0, 0.5),
+        Vector3::new(-2.0, 0.2, 0.8),
+        Vector3::new(0.3, -2.0, 1.2),
+    ];
+
+    let mut true_cams = BTreeMap::new();
+    for (i, cc) in camcenters.iter().enumerate() {
+        let extrinsics = cam_geom::ExtrinsicParameters::from_view(cc, &lookat, &up);
+        let cam = Camera::new(640, 480, extrinsics, mvg::make_default_intrinsics()).unwrap();
+        true_cams.insert(format!("cam{}", i), cam);
+    }
+    let true_system = mvg::MultiCameraSystem::new(true_cams);
+
+    let mut world_points = Vec::new();
+    let mut points = Vec::new();
+    for x in [-0.2, -0.1, 0.0, 0.1, 0.2].iter() {
+        for y in [-0.2, -0.1, 0.0, 0.1, 0.2].iter() {
+            for z in [-0.1, 0.0, 0.1, 0.2, 0.3].iter() {
+                let pt = PointWorldFrame {
+                    coords: Point3::new(*x, *y + 0.3 * *z, *z + 0.2 * *x),
+                };
+                let obs: Vec<(String, DistortedPixel<f64>)> = true_system
+                    .cams_by_name()
+                    .iter()
+                    .map(|(name, cam)| (name.clone(), cam.project_3d_to_distorted_pixel(&pt)))
+                    .collect();
+                world_points.push(pt);
+                points.push(obs);
+            }
+        }
+    }
+
+    let cameras: Vec<SelfCalibrationCamera<f64>> = true_system
+        .cams_by_name()
+        .keys()
+        .map(|name| SelfCalibrationCamera {
+            name: name.clone(),
+            width: 640,
+            height: 480,
+            intrinsics: None,
+        })
+        .collect();
+    let result = self_calibrate(&cameras, &points, &SelfCalibrationOptions::default()).unwrap();
+    assert_eq!(result.n_points, 125);
+    assert_eq!(result.n_outliers, 0);
+    assert!(result.report.all_cameras.mean < 1e-2);
+
+    let known: Vec<_> = world_points
+        .iter()
+        .cloned()
+        .zip(points.iter().cloned())
+        .step_by(10)
+        .collect();
+    let aligned = align_to_known_points(&result.system, &known).unwrap();
+    for (name, cam) in true_system.cams_by_name().iter() {
+        let cam2 = aligned.cam_by_name(name).unwrap();
+        assert_relative_eq!(
+            cam.extrinsics().camcenter(),
+            cam2.extrinsics().camcenter(),
+            epsilon = 1e-3
+        );
+        assert_relative_eq!(
+            cam.intrinsics().p[(0, 0)],
+            cam2.intrinsics().p[(0, 0)],
+            epsilon = 1.0
+        );
+    }
+    // Known intrinsic parameters are kept, unknown ones are refined.
+    let cameras: Vec<SelfCalibrationCamera<f64>> = true_system
+        .cams_by_name()
+        .iter()
+        .enumerate()
+        .map(|(i, (name, cam))| SelfCalibrationCamera {
+            name: name.clone(),
+            width: 640,
+            height: 480,
+            intrinsics: if i < 2 {
+                Some(cam.intrinsics().clone())
+            } else {
+                None
+            },
+        })
+        .collect();
+    let result = self_calibrate(&cameras, &points, &SelfCalibrationOptions::default()).unwrap();
+    assert!(result.report.all_cameras.mean < 1e-2);
+    for cam in cameras.iter() {
+        let calibrated = result.system.cam_by_name(&cam.name).unwrap();
+        match &cam.intrinsics {
+            Some(intrinsics) => {
+                assert_eq!(calibrated.intrinsics().k, intrinsics.k);
+                assert_eq!(calibrated.intrinsics().p, intrinsics.p);
+            }
+            None => assert_relative_eq!(
+                calibrated.intrinsics().k[(0, 0)],
+                true_system.cam_by_name(&cam.name).unwrap().intrinsics().k[(0, 0)],
+                max_relative = 0.05
+            ),
+        }
+    }
+}
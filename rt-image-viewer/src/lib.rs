@@ -292,6 +292,7 @@ async fn new_rt_image_viewer_bui_app(
                 firehose_callback_rx,
                 true,
                 &*EVENTS_PREFIX,
+                http_video_streaming::StreamEncoding::Jpeg,
                 flag,
             )?)
         });
@@ -78,3 +78,41 @@ pub fn obj_parse(
     }
     Ok(results)
 }
+
+/// Load the triangles of each object in an .obj file.
+///
+/// Unlike [obj_parse], this does not require texture coordinates. Returns the
+/// name and the vertices of each triangle for every object.
+pub fn obj_parse_triangles(
+    buf: &[u8]
+) -> Result<Vec<(String, Vec<[Point3<f64>; 3]>)>, crate::Error> {
+
+    let mut reader = std::io::BufReader::new(buf);
+
+    let obj = obj::ObjData::load_buf(&mut reader)?;
+
+    let mut results = Vec::new();
+    for o in &obj.objects {
+        let mut triangles = Vec::new();
+
+        for g in &o.groups {
+            for poly in g.polys.iter(){
+                let mesh: genmesh::Polygon<_> = poly.clone().try_into()?;
+                mesh.emit_triangles(|tri|
+                    {
+                        triangles.push([
+                            to_point3(obj.position[tri.x.0]),
+                            to_point3(obj.position[tri.y.0]),
+                            to_point3(obj.position[tri.z.0]),
+                        ]);
+                    }
+                );
+            }
+        }
+
+        results.push(
+            (o.name.clone(), triangles)
+        )
+    }
+    Ok(results)
+}
@@ -17,3 +17,19 @@ fn test_read_tetrahedron() {
     let buf = include_bytes!("tetrahedron.obj");
     simple_obj_parse::obj_parse(buf).expect("parsed");
 }
+
+#[test]
+fn test_read_tetrahedron_triangles() {
+    let buf = include_bytes!("tetrahedron.obj");
+    let objects = simple_obj_parse::obj_parse_triangles(buf).expect("parsed");
+    assert_eq!(objects.len(), 1);
+    assert_eq!(objects[0].0, "tetrahedron");
+    assert_eq!(objects[0].1.len(), 4);
+
+    // Texture coordinates are only required by `obj_parse`.
+    let buf = include_bytes!("tetrahedron-no-uv.obj");
+    assert!(simple_obj_parse::obj_parse(buf).is_err());
+    let objects = simple_obj_parse::obj_parse_triangles(buf).expect("parsed");
+    assert_eq!(objects[0].1.len(), 4);
+    assert_eq!(objects[0].1[0][1].x, 1.0);
+}
@@ -19,6 +19,9 @@ fn split_path<P: AsRef<std::path::Path>>(path: P) -> (std::path::PathBuf, std::p
 }
 
 /// Fix .mkv file so that seek works in VLC.
+///
+/// This is only required for files saved by older versions of `mkv-writer`,
+/// which did not write a seek index.
 pub fn mkv_fix<P: AsRef<std::path::Path> + AsRef<std::ffi::OsStr>>(
     orig_path: P,
 ) -> Result<(), Error> {
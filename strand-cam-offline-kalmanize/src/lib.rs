@@ -437,6 +437,17 @@ fn convert_row(
         timestamp: None, //flydra_types::FlydraFloatTimestampLocal::from_dt(&dt),
         x: strand_cam_row.x_px,
         y: strand_cam_row.y_px,
+        ellipse_major: std::f64::NAN,
+        ellipse_minor: std::f64::NAN,
+        ellipse_angle: std::f64::NAN,
+        bbox_left: None,
+        bbox_bottom: None,
+        bbox_right: None,
+        bbox_top: None,
+        contour: String::new(),
+        patch_left: None,
+        patch_bottom: None,
+        patch: String::new(),
     }
 }
 
@@ -7,7 +7,7 @@ use http_video_streaming_types::FirehoseCallbackInner;
 #[cfg(feature = "flydratrax")]
 use http_video_streaming_types::{CircleParams, Shape};
 
-use ci2_remote_control::{MkvRecordingConfig, RecordingFrameRate, TagFamily};
+use ci2_remote_control::{MkvRecordingConfig, PostTriggerConfig, RecordingFrameRate, TagFamily};
 use image_tracker_types::ImPtDetectCfg;
 
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
@@ -88,7 +88,7 @@ pub struct StoreType {
     /// Path where debug data is being saved.
     #[cfg(feature = "checkercal")]
     pub checkerboard_save_debug: Option<String>,
-    pub post_trigger_buffer_size: usize,
+    pub post_trigger_config: PostTriggerConfig,
     pub cuda_devices: Vec<String>,
     /// This is None if no apriltag support is compiled in. Otherwise Some(_).
     pub apriltag_state: Option<ApriltagState>,
@@ -102,6 +102,12 @@ pub struct ApriltagState {
     pub do_detection: bool,
     pub april_family: TagFamily,
     pub is_recording_csv: Option<RecordingPath>,
+    /// The edge length of the tags (between the detected corners) in meters.
+    pub tag_size_meters: f64,
+    /// True if a camera calibration is loaded so that tag poses are estimated.
+    pub has_calibration: bool,
+    /// The most recently estimated tag poses.
+    pub latest_poses: Vec<AprilTagPose>,
 }
 
 impl Default for ApriltagState {
@@ -110,10 +116,28 @@ impl Default for ApriltagState {
             do_detection: false,
             april_family: TagFamily::default(),
             is_recording_csv: None,
+            tag_size_meters: 0.1,
+            has_calibration: false,
+            latest_poses: Vec::new(),
         }
     }
 }
 
+/// The pose of a detected April Tag.
+///
+/// Rotations are quaternions `[w, x, y, z]` rotating from the tag frame.
+/// Positions are of the tag center in meters.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct AprilTagPose {
+    pub id: i32,
+    pub camera_rotation: [f64; 4],
+    pub camera_position: [f64; 3],
+    pub world_rotation: [f64; 4],
+    pub world_position: [f64; 3],
+    /// The root mean square reprojection error of the tag corners in pixels.
+    pub reprojection_error: f64,
+}
+
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub struct ImOpsState {
     pub do_detection: bool,
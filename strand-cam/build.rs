@@ -32,12 +32,20 @@ const BACKEND: &str = "flycap2";
 #[cfg(feature = "backend_aravis")]
 const BACKEND: &str = "aravis";
 
+#[cfg(feature = "backend_sim")]
+const BACKEND: &str = "sim";
+
+#[cfg(feature = "backend_playback")]
+const BACKEND: &str = "playback";
+
 fn main() {
     #[cfg(not(any(
         feature = "backend_dc1394",
         feature = "backend_flycap2",
         feature = "backend_pyloncxx",
-        feature = "backend_aravis"
+        feature = "backend_aravis",
+        feature = "backend_sim",
+        feature = "backend_playback"
     )))]
     compile_error!("no backend selected.");
 
@@ -200,6 +200,18 @@ fn parse_args() -> std::result::Result<StrandCamArgs, anyhow::Error> {
                     .long("pixel-format")
                     .help("The desired pixel format.")
                     .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("live_view_bitrate")
+                    .long("live-view-bitrate")
+                    .help("Stream the live view as VP8 video with this bitrate (in kbps) rather than as JPEG images.")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("post_trigger_gpio")
+                    .long("post-trigger-gpio")
+                    .help("GPIO input value file (e.g. /sys/class/gpio/gpio17/value). A rising edge triggers an MKV recording.")
+                    .takes_value(true),
             );
 
         #[cfg(feature = "posix_sched_fifo")]
@@ -236,6 +248,15 @@ fn parse_args() -> std::result::Result<StrandCamArgs, anyhow::Error> {
             )
         }
 
+        parser = parser.arg(
+            Arg::with_name("camera_calibration")
+                .long("camera-calibration")
+                // The name of this option before calibrations were also saved in MKV files.
+                .alias("apriltag-calibration")
+                .help("Filename of flydra .xml or pymvg .json camera calibration. Saved in MKV recordings and used to estimate April Tag poses.")
+                .takes_value(true),
+        );
+
         #[cfg(feature = "flydratrax")]
         {
             parser = parser
@@ -391,6 +412,19 @@ fn parse_args() -> std::result::Result<StrandCamArgs, anyhow::Error> {
     let apriltag_csv_filename_template =
         strand_cam_storetype::APRILTAG_CSV_TEMPLATE_DEFAULT.to_string();
 
+    let camera_calibration_fname = matches
+        .value_of("camera_calibration")
+        .map(std::path::PathBuf::from);
+
+    let live_view_bitrate = match matches.value_of("live_view_bitrate") {
+        Some(bitrate) => Some(bitrate.parse()?),
+        None => None,
+    };
+
+    let post_trigger_gpio = matches
+        .value_of("post_trigger_gpio")
+        .map(std::path::PathBuf::from);
+
     let defaults = StrandCamArgs::default();
 
     Ok(StrandCamArgs {
@@ -424,8 +458,11 @@ fn parse_args() -> std::result::Result<StrandCamArgs, anyhow::Error> {
         model_server_addr,
         #[cfg(feature = "fiducial")]
         apriltag_csv_filename_template,
+        camera_calibration_fname,
         force_camera_sync_mode,
         software_limit_framerate: strand_cam::StartSoftwareFrameRateLimit::NoChange,
+        live_view_bitrate,
+        post_trigger_gpio,
         ..defaults
     })
 }
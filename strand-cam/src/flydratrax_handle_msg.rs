@@ -97,7 +97,9 @@ pub fn flydratrax_handle_msg(
                 };
                 cur_pos2d = next;
             }
-            SendType::EndOfFrame(_fno) => {}
+            SendType::EndOfFrame(_fno)
+            | SendType::TriggerVolumeEnter(_)
+            | SendType::TriggerVolumeLeave(_) => {}
         }
 
         #[cfg(feature = "with_camtrig")]
@@ -1,39 +1,401 @@
 use std::collections::VecDeque;
+#[cfg(feature = "post-trigger-compression")]
+use std::io::{Read, Write};
 
+#[cfg(feature = "post-trigger-compression")]
+use basic_frame::BasicExtra;
 use basic_frame::DynamicFrame;
+use ci2_remote_control::{PostTriggerConfig, PostTriggerFormat};
+#[cfg(feature = "post-trigger-compression")]
+use machine_vision_formats::{PixFmt, Stride};
+use timestamped_frame::ExtraTimeData;
 
+/// A frame kept in the buffer, optionally compressed.
+enum BufferedFrame {
+    Raw(DynamicFrame),
+    #[cfg(feature = "post-trigger-compression")]
+    Compressed {
+        width: u32,
+        height: u32,
+        stride: u32,
+        pixel_format: PixFmt,
+        extra: BasicExtra,
+        /// The image data compressed with deflate.
+        data: Vec<u8>,
+    },
+}
+
+impl BufferedFrame {
+    #[cfg(feature = "post-trigger-compression")]
+    fn new(frame: &DynamicFrame, compress: bool) -> std::io::Result<Self> {
+        if !compress {
+            return Ok(BufferedFrame::Raw(frame.clone()));
+        }
+        let mut encoder = libflate::deflate::Encoder::new(Vec::new());
+        encoder.write_all(frame.image_data_without_format())?;
+        let data = encoder.finish().into_result()?;
+        Ok(BufferedFrame::Compressed {
+            width: frame.width(),
+            height: frame.height(),
+            stride: frame.stride() as u32,
+            pixel_format: frame.pixel_format(),
+            extra: BasicExtra {
+                host_timestamp: frame.extra().host_timestamp(),
+                host_framenumber: frame.extra().host_framenumber(),
+            },
+            data,
+        })
+    }
+
+    #[cfg(not(feature = "post-trigger-compression"))]
+    fn new(frame: &DynamicFrame, _compress: bool) -> std::io::Result<Self> {
+        Ok(BufferedFrame::Raw(frame.clone()))
+    }
+
+    fn host_timestamp(&self) -> chrono::DateTime<chrono::Utc> {
+        match self {
+            BufferedFrame::Raw(frame) => frame.extra().host_timestamp(),
+            #[cfg(feature = "post-trigger-compression")]
+            BufferedFrame::Compressed { extra, .. } => extra.host_timestamp,
+        }
+    }
+
+    /// The number of bytes of image data held.
+    fn n_bytes(&self) -> usize {
+        match self {
+            BufferedFrame::Raw(frame) => frame.image_data_without_format().len(),
+            #[cfg(feature = "post-trigger-compression")]
+            BufferedFrame::Compressed { data, .. } => data.len(),
+        }
+    }
+
+    fn into_frame(self) -> std::io::Result<DynamicFrame> {
+        match self {
+            BufferedFrame::Raw(frame) => Ok(frame),
+            #[cfg(feature = "post-trigger-compression")]
+            BufferedFrame::Compressed {
+                width,
+                height,
+                stride,
+                pixel_format,
+                extra,
+                data,
+            } => {
+                let mut image_data = Vec::with_capacity(stride as usize * height as usize);
+                libflate::deflate::Decoder::new(&data[..]).read_to_end(&mut image_data)?;
+                Ok(DynamicFrame::new(
+                    width,
+                    height,
+                    stride,
+                    Box::new(extra),
+                    image_data,
+                    pixel_format,
+                ))
+            }
+        }
+    }
+}
+
+/// A recording started by a trigger which stops after `post_trigger_secs`.
+struct StopTimer {
+    format: PostTriggerFormat,
+    duration: chrono::Duration,
+    /// The host timestamp at which to stop. Unknown until a frame arrived.
+    stop: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+/// The most recent frames, to be saved when a recording is triggered.
+///
+/// The buffer holds the frames of the last `pre_trigger_secs` seconds (or the
+/// last `max_frames` frames, if set), but no more than `max_buffer_mbytes`
+/// megabytes.
+///
+/// It also keeps track of when recordings started by a trigger should stop.
+/// As this is compared with the host timestamps of the frames, the time of the
+/// trigger is the host timestamp of the most recent frame.
 pub(crate) struct PostTriggerBuffer {
-    size: usize,
-    inner: VecDeque<DynamicFrame>,
+    cfg: PostTriggerConfig,
+    inner: VecDeque<BufferedFrame>,
+    n_bytes: usize,
+    /// The host timestamp of the most recent frame.
+    newest: Option<chrono::DateTime<chrono::Utc>>,
+    stop_timers: Vec<StopTimer>,
 }
 
 impl PostTriggerBuffer {
     pub(crate) fn new() -> Self {
         Self {
-            size: 0,
+            cfg: PostTriggerConfig::default(),
             inner: VecDeque::new(),
+            n_bytes: 0,
+            newest: None,
+            stop_timers: Vec::new(),
+        }
+    }
+
+    fn is_buffering(&self) -> bool {
+        match self.cfg.max_frames {
+            Some(max_frames) => max_frames > 0,
+            None => self.cfg.pre_trigger_secs > 0.0,
         }
     }
 
     fn trim(&mut self) {
-        while self.inner.len() > self.size {
-            self.inner.pop_front();
+        let max_bytes = self.cfg.max_buffer_mbytes as usize * 1024 * 1024;
+        let newest = match self.inner.back() {
+            Some(frame) => frame.host_timestamp(),
+            None => return,
+        };
+        while let Some(oldest) = self.inner.front() {
+            let is_within_limit = match self.cfg.max_frames {
+                Some(max_frames) => self.inner.len() <= max_frames,
+                None => {
+                    let age = newest.signed_duration_since(oldest.host_timestamp());
+                    let age_secs = age.num_microseconds().unwrap_or(i64::MAX) as f64 * 1e-6;
+                    age_secs <= self.cfg.pre_trigger_secs
+                }
+            };
+            if is_within_limit && self.n_bytes <= max_bytes {
+                break;
+            }
+            let oldest = self.inner.pop_front().unwrap();
+            self.n_bytes -= oldest.n_bytes();
         }
     }
 
-    pub(crate) fn set_size(&mut self, size: usize) {
-        self.size = size;
+    pub(crate) fn set_config(&mut self, cfg: PostTriggerConfig) {
+        #[cfg(not(feature = "post-trigger-compression"))]
+        if cfg.compress {
+            warn!("post trigger buffer compression not compiled in, keeping frames uncompressed");
+        }
+        self.cfg = cfg;
+        if !self.is_buffering() {
+            self.inner.clear();
+            self.n_bytes = 0;
+        }
         self.trim();
     }
 
-    pub(crate) fn push(&mut self, frame: &DynamicFrame) {
-        if self.size > 0 {
-            self.inner.push_back(frame.clone());
+    pub(crate) fn push(&mut self, frame: &DynamicFrame) -> std::io::Result<()> {
+        let host_timestamp = frame.extra().host_timestamp();
+        self.newest = Some(host_timestamp);
+        for timer in self.stop_timers.iter_mut() {
+            if timer.stop.is_none() {
+                timer.stop = Some(host_timestamp + timer.duration);
+            }
+        }
+
+        if self.is_buffering() {
+            // Copies (and possibly compresses) the data.
+            let frame = BufferedFrame::new(frame, self.cfg.compress)?;
+            self.n_bytes += frame.n_bytes();
+            self.inner.push_back(frame);
         }
         self.trim();
+        Ok(())
     }
 
-    pub(crate) fn get_and_clear(&mut self) -> VecDeque<DynamicFrame> {
+    pub(crate) fn get_and_clear(&mut self) -> std::io::Result<Vec<DynamicFrame>> {
+        self.n_bytes = 0;
         std::mem::replace(&mut self.inner, VecDeque::new())
+            .into_iter()
+            .map(BufferedFrame::into_frame)
+            .collect()
+    }
+
+    /// Stop a recording started by a trigger at the most recent frame after
+    /// `post_trigger_secs`, if set.
+    pub(crate) fn start_stop_timer(&mut self, format: PostTriggerFormat) {
+        self.cancel_stop_timer(format);
+        if self.cfg.post_trigger_secs > 0.0 {
+            let micros = (self.cfg.post_trigger_secs * 1e6).round() as i64;
+            let duration = chrono::Duration::microseconds(micros);
+            self.stop_timers.push(StopTimer {
+                format,
+                duration,
+                stop: self.newest.map(|newest| newest + duration),
+            });
+        }
+    }
+
+    /// Forget when to stop the recording, e.g. because it was stopped.
+    pub(crate) fn cancel_stop_timer(&mut self, format: PostTriggerFormat) {
+        self.stop_timers.retain(|timer| timer.format != format);
+    }
+
+    /// Remove and return the recordings which should be stopped at the most
+    /// recent frame.
+    pub(crate) fn take_expired(&mut self) -> Vec<PostTriggerFormat> {
+        let newest = match self.newest {
+            Some(newest) => newest,
+            None => return Vec::new(),
+        };
+        let (expired, pending) = std::mem::replace(&mut self.stop_timers, Vec::new())
+            .into_iter()
+            .partition(|timer| timer.stop.map_or(false, |stop| stop <= newest));
+        self.stop_timers = pending;
+        expired.into_iter().map(|timer| timer.format).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use basic_frame::BasicExtra;
+    use machine_vision_formats::PixFmt;
+
+    fn frame_at(
+        t0: chrono::DateTime<chrono::Utc>,
+        millis: i64,
+        width: u32,
+        height: u32,
+    ) -> DynamicFrame {
+        let image_data = (0..width * height).map(|i| (i / 64) as u8).collect();
+        let extra = Box::new(BasicExtra {
+            host_timestamp: t0 + chrono::Duration::milliseconds(millis),
+            host_framenumber: millis as usize,
+        });
+        DynamicFrame::new(width, height, width, extra, image_data, PixFmt::Mono8)
+    }
+
+    fn config(pre_trigger_secs: f64) -> PostTriggerConfig {
+        PostTriggerConfig {
+            pre_trigger_secs,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn trims_to_duration() {
+        let t0 = chrono::Utc::now();
+        let mut buf = PostTriggerBuffer::new();
+        buf.set_config(config(1.0));
+        for i in 0..30 {
+            buf.push(&frame_at(t0, i * 100, 8, 8)).unwrap();
+        }
+        let frames = buf.get_and_clear().unwrap();
+        // The frames from 1.9 to 2.9 seconds.
+        assert_eq!(frames.len(), 11);
+        assert_eq!(
+            frames[0].extra().host_timestamp(),
+            t0 + chrono::Duration::milliseconds(1900)
+        );
+        assert!(buf.get_and_clear().unwrap().is_empty());
+    }
+
+    #[test]
+    fn trims_to_memory_budget() {
+        let t0 = chrono::Utc::now();
+        let mut buf = PostTriggerBuffer::new();
+        buf.set_config(PostTriggerConfig {
+            max_buffer_mbytes: 3,
+            ..config(100.0)
+        });
+        // Each frame holds 1 MB.
+        for i in 0..10 {
+            buf.push(&frame_at(t0, i * 100, 1024, 1024)).unwrap();
+        }
+        let frames = buf.get_and_clear().unwrap();
+        assert_eq!(frames.len(), 3);
+        assert_eq!(frames[2].extra().host_framenumber(), 900);
+    }
+
+    #[test]
+    fn trims_to_frame_count() {
+        let t0 = chrono::Utc::now();
+        let mut buf = PostTriggerBuffer::new();
+        buf.set_config(PostTriggerConfig {
+            max_frames: Some(5),
+            ..config(0.0)
+        });
+        for i in 0..30 {
+            buf.push(&frame_at(t0, i * 100, 8, 8)).unwrap();
+        }
+        assert_eq!(buf.get_and_clear().unwrap().len(), 5);
+
+        // Turning off buffering drops the frames.
+        buf.push(&frame_at(t0, 3000, 8, 8)).unwrap();
+        buf.set_config(config(0.0));
+        assert!(buf.get_and_clear().unwrap().is_empty());
+    }
+
+    #[cfg(feature = "post-trigger-compression")]
+    #[test]
+    fn compression_round_trip() {
+        use machine_vision_formats::Stride;
+
+        let t0 = chrono::Utc::now();
+        let mut buf = PostTriggerBuffer::new();
+        buf.set_config(PostTriggerConfig {
+            compress: true,
+            ..config(10.0)
+        });
+        let originals: Vec<DynamicFrame> = (0..3).map(|i| frame_at(t0, i * 100, 64, 32)).collect();
+        for frame in originals.iter() {
+            buf.push(frame).unwrap();
+        }
+        // The image data compresses well.
+        assert!(buf.n_bytes < 3 * 64 * 32 / 4);
+
+        let frames = buf.get_and_clear().unwrap();
+        assert_eq!(frames.len(), originals.len());
+        for (frame, orig) in frames.iter().zip(originals.iter()) {
+            assert_eq!(frame.width(), orig.width());
+            assert_eq!(frame.height(), orig.height());
+            assert_eq!(frame.stride(), orig.stride());
+            assert_eq!(frame.pixel_format(), orig.pixel_format());
+            assert_eq!(
+                frame.extra().host_timestamp(),
+                orig.extra().host_timestamp()
+            );
+            assert_eq!(
+                frame.extra().host_framenumber(),
+                orig.extra().host_framenumber()
+            );
+            assert_eq!(
+                frame.image_data_without_format(),
+                orig.image_data_without_format()
+            );
+        }
+    }
+
+    #[test]
+    fn stop_timers_use_frame_timestamps() {
+        // The host clock is far from the current time.
+        let t0 = chrono::Utc::now() - chrono::Duration::days(1);
+        let mut buf = PostTriggerBuffer::new();
+        buf.set_config(PostTriggerConfig {
+            post_trigger_secs: 2.0,
+            ..config(0.0)
+        });
+
+        buf.push(&frame_at(t0, 0, 8, 8)).unwrap();
+        buf.start_stop_timer(PostTriggerFormat::Mkv);
+        buf.push(&frame_at(t0, 1900, 8, 8)).unwrap();
+        assert!(buf.take_expired().is_empty());
+        buf.push(&frame_at(t0, 2000, 8, 8)).unwrap();
+        assert_eq!(buf.take_expired(), vec![PostTriggerFormat::Mkv]);
+        assert!(buf.take_expired().is_empty());
+
+        // A cancelled timer never expires.
+        buf.start_stop_timer(PostTriggerFormat::Fmf);
+        buf.cancel_stop_timer(PostTriggerFormat::Fmf);
+        buf.push(&frame_at(t0, 10_000, 8, 8)).unwrap();
+        assert!(buf.take_expired().is_empty());
+    }
+
+    #[test]
+    fn stop_timer_before_first_frame() {
+        let t0 = chrono::Utc::now();
+        let mut buf = PostTriggerBuffer::new();
+        buf.set_config(PostTriggerConfig {
+            post_trigger_secs: 1.0,
+            ..config(0.0)
+        });
+        buf.start_stop_timer(PostTriggerFormat::Ufmf);
+        buf.push(&frame_at(t0, 5000, 8, 8)).unwrap();
+        assert!(buf.take_expired().is_empty());
+        buf.push(&frame_at(t0, 6000, 8, 8)).unwrap();
+        assert_eq!(buf.take_expired(), vec![PostTriggerFormat::Ufmf]);
     }
 }
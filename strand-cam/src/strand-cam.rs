@@ -19,8 +19,12 @@ use ci2_aravis as backend;
 use ci2_dc1394 as backend;
 #[cfg(feature = "backend_flycap2")]
 use ci2_flycap2 as backend;
+#[cfg(feature = "backend_playback")]
+extern crate ci2_playback as backend;
 #[cfg(feature = "backend_pyloncxx")]
 extern crate ci2_pyloncxx as backend;
+#[cfg(feature = "backend_sim")]
+extern crate ci2_sim as backend;
 
 #[cfg(feature = "fiducial")]
 use ads_apriltag as apriltag;
@@ -69,7 +73,9 @@ use std::path::Path;
 
 #[cfg(feature = "image_tracker")]
 use ci2_remote_control::CsvSaveConfig;
-use ci2_remote_control::{CamArg, MkvRecordingConfig, RecordingFrameRate};
+use ci2_remote_control::{
+    CamArg, MkvRecordingConfig, PostTriggerConfig, PostTriggerFormat, RecordingFrameRate,
+};
 use flydra_types::{
     BuiServerInfo, CamHttpServerInfo, MainbrainBuiLocation, RawCamName, RealtimePointsDestAddr,
     RosCamName,
@@ -352,7 +358,10 @@ pub(crate) enum Msg {
     #[cfg(feature = "image_tracker")]
     SetTracking(bool),
     PostTriggerStartMkv((String, MkvRecordingConfig)),
-    SetPostTriggerBufferSize(usize),
+    PostTriggerStartFmf((String, RecordingFrameRate)),
+    #[cfg(feature = "image_tracker")]
+    PostTriggerStartUfmf(String),
+    SetPostTriggerConfig(PostTriggerConfig),
     Mframe(DynamicFrame),
     #[cfg(feature = "image_tracker")]
     SetIsSavingObjDetectionCsv(CsvSaveConfig),
@@ -469,6 +478,22 @@ struct DetectionSerializer {
     h21: f64,
     // no h22 because it is always 1.0
     family: String,
+    // The pose columns are empty if no pose was estimated.
+    qw: Option<f64>,
+    qx: Option<f64>,
+    qy: Option<f64>,
+    qz: Option<f64>,
+    x: Option<f64>,
+    y: Option<f64>,
+    z: Option<f64>,
+    world_qw: Option<f64>,
+    world_qx: Option<f64>,
+    world_qy: Option<f64>,
+    world_qz: Option<f64>,
+    world_x: Option<f64>,
+    world_y: Option<f64>,
+    world_z: Option<f64>,
+    reproj_err: Option<f64>,
 }
 
 #[cfg(feature = "fiducial")]
@@ -480,6 +505,7 @@ fn my_round(a: f32) -> f32 {
 #[cfg(feature = "fiducial")]
 fn to_serializer(
     orig: &apriltag::Detection,
+    pose: Option<&strand_cam_storetype::AprilTagPose>,
     frame: usize,
     time_microseconds: i64,
 ) -> DetectionSerializer {
@@ -487,6 +513,7 @@ fn to_serializer(
     // We are not going to save h22, so (in debug builds) let's check it meets
     // our expectations.
     debug_assert!((h[8] - 1.0).abs() < 1e-16);
+    let q = |f: fn(&strand_cam_storetype::AprilTagPose) -> f64| pose.map(f);
     DetectionSerializer {
         frame,
         time_microseconds,
@@ -502,6 +529,99 @@ fn to_serializer(
         h20: h[6],
         h21: h[7],
         family: orig.family_type().to_str().to_string(),
+        qw: q(|p| p.camera_rotation[0]),
+        qx: q(|p| p.camera_rotation[1]),
+        qy: q(|p| p.camera_rotation[2]),
+        qz: q(|p| p.camera_rotation[3]),
+        x: q(|p| p.camera_position[0]),
+        y: q(|p| p.camera_position[1]),
+        z: q(|p| p.camera_position[2]),
+        world_qw: q(|p| p.world_rotation[0]),
+        world_qx: q(|p| p.world_rotation[1]),
+        world_qy: q(|p| p.world_rotation[2]),
+        world_qz: q(|p| p.world_rotation[3]),
+        world_x: q(|p| p.world_position[0]),
+        world_y: q(|p| p.world_position[1]),
+        world_z: q(|p| p.world_position[2]),
+        reproj_err: q(|p| p.reprojection_error),
+    }
+}
+
+/// Estimates the pose of April Tags seen by a calibrated camera.
+#[cfg(feature = "fiducial")]
+struct TagPoseEstimator {
+    cam: flydra_mvg::MultiCamera<f64>,
+    intrinsics: apriltag::pose::CameraIntrinsics,
+    calibration_fname: std::path::PathBuf,
+}
+
+#[cfg(feature = "fiducial")]
+impl TagPoseEstimator {
+    /// Load the calibration of a camera from a flydra .xml or pymvg .json file.
+    ///
+    /// If the calibration contains a single camera, it is used regardless of
+    /// its name.
+    fn from_file(fname: &std::path::Path, ros_cam_name: &RosCamName) -> anyhow::Result<Self> {
+        let rdr = std::fs::File::open(fname)?;
+        let is_xml = fname.extension().map(|ext| ext == "xml").unwrap_or(false);
+        let system = if is_xml {
+            flydra_mvg::FlydraMultiCameraSystem::<f64>::from_flydra_xml(rdr)?
+        } else {
+            let sys = mvg::MultiCameraSystem::from_pymvg_file_json(rdr)?;
+            flydra_mvg::FlydraMultiCameraSystem::from_system(sys, None)
+        };
+        let cam = match system.cam_by_name(ros_cam_name.as_str()) {
+            Some(cam) => cam,
+            None if system.len() == 1 => system.cameras().next().unwrap(),
+            None => anyhow::bail!(
+                "camera \"{}\" not in calibration {}",
+                ros_cam_name.as_str(),
+                fname.display()
+            ),
+        };
+        let p = cam.clone().to_cam().intrinsics().p;
+        let intrinsics = apriltag::pose::CameraIntrinsics {
+            fx: p[(0, 0)],
+            fy: p[(1, 1)],
+            cx: p[(0, 2)],
+            cy: p[(1, 2)],
+        };
+        Ok(Self {
+            cam,
+            intrinsics,
+            calibration_fname: fname.to_path_buf(),
+        })
+    }
+
+    fn estimate(
+        &self,
+        det: &apriltag::Detection,
+        tag_size_meters: f64,
+    ) -> Option<strand_cam_storetype::AprilTagPose> {
+        let mut corners = [[0.0; 2]; 4];
+        for (undistorted, distorted) in corners.iter_mut().zip(det.corners().iter()) {
+            let pt = self.cam.undistort(&mvg::DistortedPixel {
+                coords: nalgebra::Point2::new(distorted[0], distorted[1]),
+            });
+            *undistorted = [pt.coords.x, pt.coords.y];
+        }
+        let pose = apriltag::pose::estimate_tag_pose(&corners, tag_size_meters, &self.intrinsics)?;
+
+        // The extrinsic rotation is from the world to the camera frame.
+        let extrinsics = self.cam.extrinsics();
+        let cam2world = extrinsics.rotation().inverse();
+        let world_rotation = cam2world * pose.rotation;
+        let world_position = cam2world * pose.translation + extrinsics.camcenter().coords;
+
+        let quat = |q: &nalgebra::UnitQuaternion<f64>| [q.w, q.i, q.j, q.k];
+        Some(strand_cam_storetype::AprilTagPose {
+            id: det.id(),
+            camera_rotation: quat(&pose.rotation),
+            camera_position: pose.translation.into(),
+            world_rotation: quat(&world_rotation),
+            world_position: world_position.into(),
+            reprojection_error: pose.reprojection_error,
+        })
     }
 }
 
@@ -509,6 +629,9 @@ fn to_serializer(
 struct AprilConfig {
     created_at: chrono::DateTime<chrono::Local>,
     camera_name: String,
+    tag_size_meters: f64,
+    /// The calibration used to estimate the tag poses.
+    calibration_fname: Option<String>,
 }
 
 #[cfg(feature = "fiducial")]
@@ -519,7 +642,12 @@ struct AprilTagWriter {
 
 #[cfg(feature = "fiducial")]
 impl AprilTagWriter {
-    fn new(template: String, camera_name: &str) -> Result<Self> {
+    fn new(
+        template: String,
+        camera_name: &str,
+        tag_size_meters: f64,
+        calibration_fname: Option<String>,
+    ) -> Result<Self> {
         let now: chrono::DateTime<chrono::Utc> = chrono::Utc::now();
         let local = now.with_timezone(&chrono::Local);
         let fname = local.format(&template).to_string();
@@ -530,6 +658,8 @@ impl AprilTagWriter {
         let april_config = AprilConfig {
             created_at: local,
             camera_name: camera_name.to_string(),
+            tag_size_meters,
+            calibration_fname,
         };
         let cfg_yaml = serde_yaml::to_string(&april_config).unwrap();
         writeln!(
@@ -544,6 +674,22 @@ impl AprilTagWriter {
             fd,
             "# it always has value 1. The center pixel of the detection is (h02,h12)."
         )?;
+        writeln!(
+            fd,
+            "# If a calibration is given, the pose of each tag is saved as quaternion"
+        )?;
+        writeln!(
+            fd,
+            "# (qw,qx,qy,qz) and position (x,y,z) in meters in the camera frame and as"
+        )?;
+        writeln!(
+            fd,
+            "# (world_qw,...,world_z) in the calibration frame. The root mean square"
+        )?;
+        writeln!(
+            fd,
+            "# reprojection error of the tag corners in pixels is reproj_err."
+        )?;
         writeln!(fd, "# -- start of yaml config --")?;
         for line in cfg_yaml.lines() {
             writeln!(fd, "# {}", line)?;
@@ -557,6 +703,7 @@ impl AprilTagWriter {
     fn save(
         &mut self,
         detections: &apriltag::Zarray<apriltag::Detection>,
+        poses: &[Option<strand_cam_storetype::AprilTagPose>],
         frame: usize,
         ts: chrono::DateTime<chrono::Utc>,
     ) -> Result<()> {
@@ -564,8 +711,9 @@ impl AprilTagWriter {
             .signed_duration_since(self.t0)
             .num_microseconds()
             .unwrap();
-        for det in detections.as_slice().iter() {
-            let atd: DetectionSerializer = to_serializer(det, frame, time_microseconds);
+        for (det, pose) in detections.as_slice().iter().zip(poses.iter()) {
+            let atd: DetectionSerializer =
+                to_serializer(det, pose.as_ref(), frame, time_microseconds);
             self.wtr.serialize(atd)?;
         }
         Ok(())
@@ -593,6 +741,9 @@ fn frame_process_thread(
     model_server: flydra2::ModelServer,
     #[cfg(feature="flydratrax")]
     flydratrax_calibration_source: CalSource,
+    #[cfg(feature="fiducial")]
+    apriltag_pose_estimator: Option<TagPoseEstimator>,
+    mkv_file_metadata: mkv_writer::FileMetadata,
     cam_name: RawCamName,
     camera_cfg: CameraCfgFview2_0_26,
     width: u32,
@@ -684,7 +835,8 @@ fn frame_process_thread(
     #[cfg(feature="image_tracker")]
     #[allow(unused_assignments)]
     let mut is_doing_object_detection = false;
-    let version_str = env!("CARGO_PKG_VERSION").to_string();
+    let version_str = format!("{}-{} ({})", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"),
+        env!("GIT_HASH"));
 
     #[allow(unused_mut)]
     #[allow(unused_assignments)]
@@ -750,6 +902,12 @@ fn frame_process_thread(
     #[cfg(feature="fiducial")]
     april_td.add_family(april_tf);
 
+    // The poses shown in the browser are updated at most this often.
+    #[cfg(feature="fiducial")]
+    let pose_update_interval = std::time::Duration::from_millis(500);
+    #[cfg(feature="fiducial")]
+    let mut last_pose_update = std::time::Instant::now();
+
     #[cfg(feature = "checkercal")]
     let mut last_checkerboard_detection = std::time::Instant::now();
 
@@ -984,7 +1142,7 @@ fn frame_process_thread(
                 {
                     let tracker = stor.read();
                     let shared = tracker.as_ref();
-                    post_trig_buffer.set_size(shared.post_trigger_buffer_size);
+                    post_trig_buffer.set_config(shared.post_trigger_config.clone());
                 }
                 shared_store_arc = Some(stor);
             }
@@ -994,15 +1152,15 @@ fn frame_process_thread(
                 fmf_writer = Some(FmfWriteInfo::new(FMFWriter::new(f)?, recording_framerate));
             }
             Msg::StartMkv((format_str_mkv,mkv_recording_config)) => {
-                my_mkv_writer = Some(bg_movie_writer::BgMovieWriter::new_webm_writer(format_str_mkv, mkv_recording_config, 100));
+                my_mkv_writer = Some(bg_movie_writer::BgMovieWriter::new_webm_writer(format_str_mkv, mkv_recording_config, 100, mkv_file_metadata.clone()));
             }
             #[cfg(feature="image_tracker")]
             Msg::StartUFMF(dest) => {
                 ufmf_state = Some(UfmfState::Starting(dest));
             }
             Msg::PostTriggerStartMkv((format_str_mkv,mkv_recording_config)) => {
-                let frames = post_trig_buffer.get_and_clear();
-                let mut raw = bg_movie_writer::BgMovieWriter::new_webm_writer(format_str_mkv, mkv_recording_config, frames.len()+100);
+                let frames = post_trig_buffer.get_and_clear()?;
+                let mut raw = bg_movie_writer::BgMovieWriter::new_webm_writer(format_str_mkv, mkv_recording_config, frames.len()+100, mkv_file_metadata.clone());
                 for mut frame in frames.into_iter() {
                     // Force frame width to be power of 2.
                     let val = 2;
@@ -1010,16 +1168,43 @@ fn frame_process_thread(
                     match_all_dynamic_fmts!(&mut frame, x, {x.width = clipped_width});
                     // frame.width = clipped_width;
                     let ts = frame.extra().host_timestamp();
-                    raw.write(frame, ts)?;
+                    // Frame metadata is not kept in the post-trigger buffer.
+                    raw.write(frame, ts, None)?;
                 }
                 my_mkv_writer = Some(raw);
+                post_trig_buffer.start_stop_timer(PostTriggerFormat::Mkv);
+            }
+            Msg::PostTriggerStartFmf((dest,recording_framerate)) => {
+                let frames = post_trig_buffer.get_and_clear()?;
+                let f = std::fs::File::create(&dest)?;
+                let mut inner = FmfWriteInfo::new(FMFWriter::new(f)?, recording_framerate);
+                for frame in frames.iter() {
+                    // The buffered frames are saved regardless of the
+                    // recording framerate.
+                    match_all_dynamic_fmts!(frame, x, {
+                        inner.writer.write(x, frame.extra().host_timestamp())?
+                    });
+                }
+                fmf_writer = Some(inner);
+                post_trig_buffer.start_stop_timer(PostTriggerFormat::Fmf);
+            }
+            #[cfg(feature="image_tracker")]
+            Msg::PostTriggerStartUfmf(dest) => {
+                // The buffered frames are saved first, then the frames
+                // processed by the tracker.
+                let frames = post_trig_buffer.get_and_clear()?;
+                ufmf_state = Some(UfmfState::StartingWithFrames(dest, frames));
+                post_trig_buffer.start_stop_timer(PostTriggerFormat::Ufmf);
             }
             Msg::StartAprilTagRec(format_str_apriltags_csv) => {
                 #[cfg(feature="fiducial")]
                 {
                     if let Some(x) = store_cache.as_ref() {
                         if let Some(apriltag_state) = &x.apriltag_state {
-                            apriltag_writer = Some(AprilTagWriter::new(format_str_apriltags_csv, &x.camera_name)?);
+                            let calibration_fname = apriltag_pose_estimator.as_ref()
+                                .map(|e| e.calibration_fname.display().to_string());
+                            apriltag_writer = Some(AprilTagWriter::new(format_str_apriltags_csv, &x.camera_name,
+                                apriltag_state.tag_size_meters, calibration_fname)?);
                         }
                     }
                 }
@@ -1030,12 +1215,12 @@ fn frame_process_thread(
                     apriltag_writer = None;
                 }
             }
-            Msg::SetPostTriggerBufferSize(size) => {
-                post_trig_buffer.set_size(size);
+            Msg::SetPostTriggerConfig(cfg) => {
+                post_trig_buffer.set_config(cfg.clone());
                 if let Some(ref mut store) = shared_store_arc {
                     let mut tracker = store.write();
                     tracker.modify(|tracker| {
-                        tracker.post_trigger_buffer_size = size;
+                        tracker.post_trigger_config = cfg;
                     });
                 }
             }
@@ -1056,7 +1241,7 @@ fn frame_process_thread(
                     }
                 }
 
-                post_trig_buffer.push(&frame); // If buffer size larger than 0, copies data.
+                post_trig_buffer.push(&frame)?; // If buffer duration larger than 0, copies data.
 
                 #[cfg(feature="checkercal")]
                 let checkercal_tmp = store_cache.as_ref().and_then(|x|
@@ -1252,6 +1437,15 @@ fn frame_process_thread(
                         }
                     }
 
+                    // April Tag detections to send to braid with the tracked points.
+                    #[cfg(feature="image_tracker")]
+                    #[allow(unused_mut)]
+                    let mut udp_apriltags = Vec::new();
+
+                    // Trigger timestamp of this frame, if known, to save in MKV files.
+                    #[allow(unused_mut)]
+                    let mut trigger_timestamp: Option<f64> = None;
+
                     #[cfg(feature="fiducial")]
                     {
 
@@ -1272,10 +1466,34 @@ fn frame_process_thread(
                                     if let Some(mut im) = frame2april(&frame) {
                                         let detections = april_td.detect(im.inner_mut());
 
+                                        let poses: Vec<_> = detections.as_slice().iter().map(|det| {
+                                            apriltag_pose_estimator.as_ref()
+                                                .and_then(|e| e.estimate(det, ts.tag_size_meters))
+                                        }).collect();
+
                                         if let Some(ref mut wtr) = apriltag_writer {
-                                            wtr.save(&detections, frame.extra().host_framenumber(), frame.extra().host_timestamp())?;
+                                            wtr.save(&detections, &poses, frame.extra().host_framenumber(), frame.extra().host_timestamp())?;
+                                        }
+
+                                        if apriltag_pose_estimator.is_some() && last_pose_update.elapsed() >= pose_update_interval {
+                                            let latest_poses: Vec<_> = poses.into_iter().flatten().collect();
+                                            if latest_poses != ts.latest_poses {
+                                                if let Some(ref ssa) = shared_store_arc {
+                                                    // scope for write lock on ssa
+                                                    let mut tracker = ssa.write();
+                                                    tracker.modify(|shared| {
+                                                        if let Some(ref mut ts) = shared.apriltag_state {
+                                                            ts.latest_poses = latest_poses;
+                                                        }
+                                                    });
+                                                }
+                                            }
+                                            last_pose_update = std::time::Instant::now();
                                         }
 
+                                        #[cfg(feature="image_tracker")]
+                                        udp_apriltags.extend(detections.as_slice().iter().map(det2udp));
+
                                         let tag_points = detections.as_slice().iter().map(det2display);
                                         all_points.extend(tag_points);
                                     }
@@ -1288,8 +1506,9 @@ fn frame_process_thread(
                     {
                     if is_doing_object_detection {
                         let inner_ufmf_state = ufmf_state.take().unwrap();
-                        let (tracker_annotation, new_ufmf_state) = im_tracker.process_new_frame(&frame, inner_ufmf_state)?;
+                        let (tracker_annotation, new_ufmf_state) = im_tracker.process_new_frame_with_apriltags(&frame, inner_ufmf_state, udp_apriltags)?;
                         ufmf_state.get_or_insert(new_ufmf_state);
+                        trigger_timestamp = tracker_annotation.timestamp.as_ref().map(|t| t.as_f64());
 
                         #[cfg(feature="flydratrax")]
                         {
@@ -1327,6 +1546,7 @@ fn frame_process_thread(
                                 let fdp = flydra2::FrameDataAndPoints{
                                     frame_data,
                                     points,
+                                    apriltags: vec![],
                                 };
                                 let si = StreamItem::Packet(fdp);
 
@@ -1495,7 +1715,12 @@ fn frame_process_thread(
 
                 if let Some(ref mut inner) = my_mkv_writer {
                     let data = frame.clone(); // copy entire frame data
-                    inner.write(data, frame.extra().host_timestamp())?;
+                    let metadata = mkv_writer::FrameMetadata {
+                        framenumber: frame.extra().host_framenumber() as u64,
+                        host_timestamp: frame.extra().host_timestamp(),
+                        trigger_timestamp,
+                    };
+                    inner.write(data, frame.extra().host_timestamp(), Some(metadata))?;
                 }
 
                 if let Some(ref mut inner) = fmf_writer {
@@ -1511,6 +1736,38 @@ fn frame_process_thread(
                     }
                 }
 
+                // Stop recordings started by a trigger once their post-trigger
+                // duration is over.
+                for format in post_trig_buffer.take_expired().into_iter() {
+                    info!("post-trigger duration over, stopping {:?} recording", format);
+                    match format {
+                        PostTriggerFormat::Mkv => {
+                            if let Some(mut inner) = my_mkv_writer.take() {
+                                inner.finish()?;
+                            }
+                        }
+                        PostTriggerFormat::Fmf => {
+                            fmf_writer = None;
+                        }
+                        PostTriggerFormat::Ufmf => {
+                            #[cfg(feature="image_tracker")]
+                            {
+                                ufmf_state = Some(UfmfState::Stopped);
+                            }
+                        }
+                    }
+                    if let Some(ref mut store) = shared_store_arc {
+                        let mut tracker = store.write();
+                        tracker.modify(|shared| {
+                            match format {
+                                PostTriggerFormat::Mkv => shared.is_recording_mkv = None,
+                                PostTriggerFormat::Fmf => shared.is_recording_fmf = None,
+                                PostTriggerFormat::Ufmf => shared.is_recording_ufmf = None,
+                            }
+                        });
+                    }
+                }
+
                 #[cfg(feature="plugin-process-frame")]
                 {
                     // Do FFI image processing with lowest latency possible
@@ -1701,13 +1958,16 @@ fn frame_process_thread(
                 if let Some(mut inner) = my_mkv_writer.take() {
                     inner.finish()?;
                 }
+                post_trig_buffer.cancel_stop_timer(PostTriggerFormat::Mkv);
             }
             Msg::StopFMF => {
                 fmf_writer = None;
+                post_trig_buffer.cancel_stop_timer(PostTriggerFormat::Fmf);
             }
             #[cfg(feature="image_tracker")]
             Msg::StopUFMF => {
                 ufmf_state = Some(UfmfState::Stopped);
+                post_trig_buffer.cancel_stop_timer(PostTriggerFormat::Ufmf);
             }
             #[cfg(feature="image_tracker")]
             Msg::SetTracking(value) => {
@@ -1722,6 +1982,50 @@ fn frame_process_thread(
     Ok(())
 }
 
+/// Trigger an MKV recording on each rising edge of a GPIO input.
+///
+/// Returns once the app quits.
+fn watch_post_trigger_gpio(path: &std::path::Path, mut cam_args_tx: mpsc::Sender<CamArg>) {
+    let mut previous = None;
+    let mut read_failed = false;
+    loop {
+        let is_high = match std::fs::read_to_string(path) {
+            Ok(value) => {
+                if read_failed {
+                    info!("reading GPIO {} again", path.display());
+                    read_failed = false;
+                }
+                value.trim() == "1"
+            }
+            Err(e) => {
+                // Keep trying, e.g. if the GPIO is exported later. The error
+                // is only logged once.
+                if !read_failed {
+                    error!("could not read GPIO {}, will retry: {}", path.display(), e);
+                    read_failed = true;
+                }
+                previous = None;
+                if cam_args_tx.is_closed() {
+                    return;
+                }
+                std::thread::sleep(std::time::Duration::from_secs(1));
+                continue;
+            }
+        };
+        if is_high && previous == Some(false) {
+            info!("post trigger from GPIO {}", path.display());
+            if let Err(e) = cam_args_tx.try_send(CamArg::PostTrigger(PostTriggerFormat::Mkv)) {
+                if e.is_disconnected() {
+                    return;
+                }
+                error!("could not send post trigger: {}", e);
+            }
+        }
+        previous = Some(is_high);
+        std::thread::sleep(std::time::Duration::from_millis(5));
+    }
+}
+
 #[cfg(feature = "with_camtrig")]
 fn get_intensity(device_state: &camtrig_comms::DeviceState, chan_num: u8) -> u16 {
     let ch: &camtrig_comms::ChannelState = match chan_num {
@@ -1978,6 +2282,17 @@ fn det2display(det: &apriltag::Detection) -> http_video_streaming_types::Point {
     }
 }
 
+#[cfg(all(feature = "fiducial", feature = "image_tracker"))]
+fn det2udp(det: &apriltag::Detection) -> flydra_types::FlydraRawUdpAprilTag {
+    let center = det.center();
+    flydra_types::FlydraRawUdpAprilTag {
+        family: det.family_type().to_str().to_string(),
+        id: det.id(),
+        center: [center[0], center[1]],
+        corners: *det.corners(),
+    }
+}
+
 #[cfg(feature = "fiducial")]
 fn frame2april(frame: &DynamicFrame) -> Option<apriltag::ImageU8Borrowed> {
     use machine_vision_formats::{ImageData, Stride};
@@ -2000,7 +2315,6 @@ fn run_camtrig(
     camtrig_heartbeat_update_arc: Arc<RwLock<std::time::Instant>>,
     tx_cam_arg: mpsc::Sender<CamArg>,
 ) -> Result<SerialJoinHandles> {
-    use camtrig::CamtrigCodec;
     use camtrig_comms::{ChannelState, DeviceState, OnState, Running, TriggerState};
 
     fn make_chan(num: u8, on_state: OnState) -> ChannelState {
@@ -2058,8 +2372,8 @@ fn run_camtrig(
     };
 
     // separate reader and writer
-    let mut reader_port = port.try_clone()?;
-    let mut writer_port = port;
+    let reader_port = port.try_clone()?;
+    let writer_port = port;
 
     let (flag, control) = thread_control::make_pair();
     let tx_cam_arg2 = tx_cam_arg.clone();
@@ -2070,35 +2384,16 @@ fn run_camtrig(
 
             let thread_closer = CloseAppOnThreadExit::new(tx_cam_arg2, file!(), line!());
 
-            let mut codec = CamtrigCodec::new();
-            let mut buf = bytes::BytesMut::with_capacity(1000);
-            let mut read_buf = [0; 100];
-
-            while flag.is_alive() {
-                // blocking read from serial port
-                match reader_port.read(&mut read_buf[..]) {
-                    Ok(n_bytes) => {
-                        buf.extend_from_slice(&read_buf[..n_bytes]);
-                        use tokio_util::codec::Decoder;
-                        if let Some(item) = thread_closer.check(codec.decode(&mut buf)) {
-                            info!("read from camtrig device: {:?}", item);
+            thread_closer.check(camtrig::read_device_messages(reader_port, || flag.is_alive(), |item| {
+                info!("read from camtrig device: {:?}", item);
 
-                            {
-                                // elsewhere check if this happens every CAMTRIG_HEARTBEAT_INTERVAL_MSEC or so.
-                                let mut camtrig_heartbeat_update =
-                                    camtrig_heartbeat_update_arc.write();
-                                *camtrig_heartbeat_update = std::time::Instant::now();
-                            }
-                        }
-                    }
-                    Err(e) => match e.kind() {
-                        std::io::ErrorKind::TimedOut => continue,
-                        _ => {
-                            thread_closer.fail(e.into());
-                        }
-                    },
+                {
+                    // elsewhere check if this happens every CAMTRIG_HEARTBEAT_INTERVAL_MSEC or so.
+                    let mut camtrig_heartbeat_update =
+                        camtrig_heartbeat_update_arc.write();
+                    *camtrig_heartbeat_update = std::time::Instant::now();
                 }
-            }
+            }));
             thread_closer.success();
         })?
         .into();
@@ -2114,10 +2409,11 @@ fn run_camtrig(
         .spawn(move || {
             // camtrig ignore for now
             let thread_closer = CloseAppOnThreadExit::new(tx_cam_arg2, file!(), line!());
-            let mut codec = CamtrigCodec::new();
-            let mut buf = bytes::BytesMut::with_capacity(1000);
 
-            while flag.is_alive() {
+            let next_msg = || {
+                if !flag.is_alive() {
+                    return None;
+                }
                 let mut msgs = Vec::new();
                 loop {
                     match camtrig_rx.try_recv() {
@@ -2153,13 +2449,9 @@ fn run_camtrig(
                     });
                 }
 
-                info!("sending message to camtrig device: {:?}", msg);
-                use bytes::buf::Buf;
-                use tokio_util::codec::Encoder;
-                thread_closer.check(codec.encode(msg, &mut buf));
-                let n_bytes = thread_closer.check(writer_port.write(&buf));
-                buf.advance(n_bytes);
-            }
+                Some(msg)
+            };
+            thread_closer.check(camtrig::write_device_messages(writer_port, next_msg));
             thread_closer.success();
         })?
         .into();
@@ -2273,6 +2565,27 @@ fn get_mkv_writing_application(is_braid: bool) -> String {
     }
 }
 
+/// Read a camera calibration file to attach to MKV recordings.
+fn calibration_attachment(fname: &Path) -> anyhow::Result<mkv_writer::Attachment> {
+    use anyhow::Context;
+    let data = std::fs::read(fname)
+        .with_context(|| format!("reading camera calibration {}", fname.display()))?;
+    let mime_type = match fname.extension().and_then(|ext| ext.to_str()) {
+        Some("json") => "application/json",
+        _ => "application/xml",
+    };
+    let filename = fname
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| "calibration.xml".to_string());
+    Ok(mkv_writer::Attachment {
+        filename,
+        mime_type: mime_type.to_string(),
+        description: Some("camera calibration".to_string()),
+        data,
+    })
+}
+
 fn display_qr_url(url: &str) {
     use qrcodegen::{QrCode, QrCodeEcc};
     use std::io::stdout;
@@ -2374,12 +2687,21 @@ pub struct StrandCamArgs {
     pub flydratrax_calibration_source: CalSource,
     #[cfg(feature = "fiducial")]
     pub apriltag_csv_filename_template: String,
+    /// Camera calibration (flydra .xml or pymvg .json). Attached to MKV
+    /// recordings and used to estimate the pose of April Tags.
+    pub camera_calibration_fname: Option<std::path::PathBuf>,
 
     /// If set, camera acquisition will external trigger.
     pub force_camera_sync_mode: bool,
 
     /// If not Enable, limit framerate (FPS) at startup.
     pub software_limit_framerate: StartSoftwareFrameRateLimit,
+    /// If set, the live view is streamed as VP8 video with this bitrate (in
+    /// kilobits per second) rather than as individual JPEG images.
+    pub live_view_bitrate: Option<u32>,
+    /// GPIO input value file (e.g. `/sys/class/gpio/gpio17/value`). A rising
+    /// edge triggers an MKV recording with the post-trigger configuration.
+    pub post_trigger_gpio: Option<std::path::PathBuf>,
 }
 
 pub type SaveEmptyData2dType = bool;
@@ -2418,6 +2740,7 @@ impl Default for StrandCamArgs {
             #[cfg(feature = "fiducial")]
             apriltag_csv_filename_template: strand_cam_storetype::APRILTAG_CSV_TEMPLATE_DEFAULT
                 .to_string(),
+            camera_calibration_fname: None,
             #[cfg(feature = "image_tracker")]
             tracker_cfg_src: ImPtDetectCfgSource::ChangesNotSavedToDisk(default_im_pt_detect()),
             csv_save_dir: "/dev/null".to_string(),
@@ -2438,6 +2761,8 @@ impl Default for StrandCamArgs {
             plugin_wait_dur: std::time::Duration::from_millis(5),
             force_camera_sync_mode: false,
             software_limit_framerate: StartSoftwareFrameRateLimit::NoChange,
+            live_view_bitrate: None,
+            post_trigger_gpio: None,
             #[cfg(feature = "flydratrax")]
             flydratrax_calibration_source: CalSource::PseudoCal,
             #[cfg(feature = "flydratrax")]
@@ -2783,11 +3108,32 @@ pub async fn setup_app(
         }
     };
 
+    let mkv_file_metadata = mkv_writer::FileMetadata {
+        camera_name: Some(cam_name.as_str().to_string()),
+        attachments: match &args.camera_calibration_fname {
+            Some(fname) => vec![calibration_attachment(fname)?],
+            None => Vec::new(),
+        },
+    };
+
     #[cfg(not(feature="fiducial"))]
     let apriltag_state = None;
 
     #[cfg(feature="fiducial")]
-    let apriltag_state = Some(ApriltagState::default());
+    let apriltag_pose_estimator = match &args.camera_calibration_fname {
+        Some(fname) => {
+            let estimator = TagPoseEstimator::from_file(fname, &cam_name.to_ros())?;
+            info!("estimating April Tag poses with calibration {}", fname.display());
+            Some(estimator)
+        }
+        None => None,
+    };
+
+    #[cfg(feature="fiducial")]
+    let apriltag_state = Some(ApriltagState {
+        has_calibration: apriltag_pose_estimator.is_some(),
+        ..Default::default()
+    });
 
     let im_ops_state = ImOpsState::default();
 
@@ -2812,6 +3158,8 @@ pub async fn setup_app(
 
     let is_braid = args.is_braid;
     mkv_recording_config.writing_application = Some(get_mkv_writing_application(is_braid));
+    // Keep recordings seekable even if strand-cam does not exit cleanly.
+    mkv_recording_config.crash_safe = true;
 
     let shared_store = ChangeTracker::new(StoreType {
         is_braid,
@@ -2853,7 +3201,7 @@ pub async fn setup_app(
         checkerboard_data: strand_cam_storetype::CheckerboardCalState::new(),
         #[cfg(feature="checkercal")]
         checkerboard_save_debug: None,
-        post_trigger_buffer_size: 0,
+        post_trigger_config: PostTriggerConfig::default(),
         cuda_devices,
         apriltag_state,
         im_ops_state,
@@ -2977,6 +3325,9 @@ pub async fn setup_app(
                     model_server,
                     #[cfg(feature="flydratrax")]
                     flydratrax_calibration_source,
+                    #[cfg(feature="fiducial")]
+                    apriltag_pose_estimator,
+                    mkv_file_metadata,
                     cam_name,
                     camera_cfg,
                     image_width,
@@ -3409,6 +3760,22 @@ pub async fn setup_app(
                         }
                     });
                 }
+                CamArg::SetAprilTagSize(tag_size_meters) => {
+                    let mut tracker = shared_store_arc.write();
+                    tracker.modify(|shared| {
+                        if let Some(ref mut ts) = shared.apriltag_state {
+                            if ts.is_recording_csv.is_some() {
+                                error!("will not change tag size while recording CSV");
+                            } else if tag_size_meters > 0.0 {
+                                ts.tag_size_meters = tag_size_meters;
+                            } else {
+                                error!("invalid tag size {}", tag_size_meters);
+                            }
+                        } else {
+                            error!("no apriltag support, not switching state");
+                        }
+                    });
+                }
                 CamArg::ToggleImOpsDetection(do_detection) => {
                     let mut tracker = shared_store_arc.write();
                     tracker.modify(|shared| {
@@ -3467,21 +3834,65 @@ pub async fn setup_app(
                     });
                 }
 
-                CamArg::PostTrigger(mkv_recording_config) => {
-                    let format_str_mkv = {
+                CamArg::PostTrigger(format) => {
+                    info!("post trigger {:?} recording", format);
+                    let mut tracker = shared_store_arc.write();
+                    tracker.modify(|shared| {
+                        match format {
+                            PostTriggerFormat::Mkv => {
+                                if shared.is_recording_mkv.is_some() {
+                                    error!("Already recording MKV, ignoring trigger.");
+                                    return;
+                                }
+                                let mkv_recording_config = shared.mkv_recording_config.clone();
+                                tx_frame2.send(Msg::PostTriggerStartMkv((shared.format_str_mkv.clone(), mkv_recording_config))).cb_ok();
+                                shared.is_recording_mkv = Some(RecordingPath::new(shared.format_str_mkv.clone()));
+                            }
+                            PostTriggerFormat::Fmf => {
+                                if shared.is_recording_fmf.is_some() {
+                                    error!("Already recording FMF, ignoring trigger.");
+                                    return;
+                                }
+                                let local: chrono::DateTime<chrono::Local> = chrono::Local::now();
+                                let filename = local.format(&shared.format_str).to_string();
+                                let recording_framerate = shared.recording_framerate.clone();
+                                tx_frame2.send(Msg::PostTriggerStartFmf((filename.clone(), recording_framerate))).cb_ok();
+                                shared.is_recording_fmf = Some(RecordingPath::new(filename));
+                            }
+                            PostTriggerFormat::Ufmf => {
+                                #[cfg(feature="image_tracker")]
+                                {
+                                    if shared.is_recording_ufmf.is_some() {
+                                        error!("Already recording UFMF, ignoring trigger.");
+                                        return;
+                                    }
+                                    if !shared.is_doing_object_detection {
+                                        error!("Not doing object detection, ignoring trigger to save data to UFMF.");
+                                        return;
+                                    }
+                                    let local: chrono::DateTime<chrono::Local> = chrono::Local::now();
+                                    let filename = local.format(&shared.format_str_ufmf).to_string();
+                                    tx_frame2.send(Msg::PostTriggerStartUfmf(filename.clone())).cb_ok();
+                                    shared.is_recording_ufmf = Some(RecordingPath::new(filename));
+                                }
+                                #[cfg(not(feature="image_tracker"))]
+                                error!("no image tracker support, ignoring trigger to save data to UFMF.");
+                            }
+                        }
+                    });
+                }
+                CamArg::SetPostTriggerBufferSize(size) => {
+                    let cfg = {
                         let tracker = shared_store_arc.read();
-                        tracker.as_ref().format_str_mkv.clone()
+                        PostTriggerConfig {
+                            max_frames: Some(size),
+                            ..tracker.as_ref().post_trigger_config.clone()
+                        }
                     };
-                    tx_frame2.send(Msg::PostTriggerStartMkv((format_str_mkv.clone(), mkv_recording_config))).cb_ok();
-                    {
-                        let mut tracker = shared_store_arc.write();
-                        tracker.modify(|shared| {
-                            shared.is_recording_mkv = Some(RecordingPath::new(format_str_mkv));
-                        })
-                    }
+                    tx_frame2.send(Msg::SetPostTriggerConfig(cfg)).cb_ok();
                 }
-                CamArg::SetPostTriggerBufferSize(size) => {
-                    tx_frame2.send(Msg::SetPostTriggerBufferSize(size)).cb_ok();
+                CamArg::SetPostTriggerConfig(cfg) => {
+                    tx_frame2.send(Msg::SetPostTriggerConfig(cfg)).cb_ok();
                 }
                 CamArg::SetIsRecordingFmf(do_recording) => {
                     let mut tracker = shared_store_arc.write();
@@ -3883,6 +4294,10 @@ pub async fn setup_app(
     }
 
     let sender_table = my_app.txers.clone();
+    let live_view_encoding = match args.live_view_bitrate {
+        Some(bitrate) => video_streaming::StreamEncoding::Vp8 { bitrate },
+        None => video_streaming::StreamEncoding::Jpeg,
+    };
 
     let (flag, control) = thread_control::make_pair();
     let cam_args_tx3 = cam_args_tx2.clone();
@@ -3894,11 +4309,20 @@ pub async fn setup_app(
             firehose_callback_rx,
             false,
             &strand_cam_storetype::STRAND_CAM_EVENTS_URL_PATH,
+            live_view_encoding,
             flag,
         ).map_err(|e| anyhow::Error::from(e)));
     })?.into();
     let video_streaming_cjh = ControlledJoinHandle { control, join_handle };
 
+    if let Some(gpio_path) = args.post_trigger_gpio.clone() {
+        let cam_args_tx3 = cam_args_tx.clone();
+        // Errors reading the GPIO are logged, so this does not close the app.
+        std::thread::Builder::new().name("post_trigger_gpio".to_string()).spawn(move || { // closes when app quits
+            watch_post_trigger_gpio(&gpio_path, cam_args_tx3);
+        })?;
+    }
+
     #[cfg(feature="plugin-process-frame")]
     let plugin_streaming_cjh = {
         let (flag, control) = thread_control::make_pair();
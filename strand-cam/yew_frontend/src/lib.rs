@@ -18,13 +18,14 @@ use yew::prelude::*;
 
 use ads_webasm::components::{EnumToggle, VecToggle};
 use http_video_streaming_types::ToClient as FirehoseImageData;
+use strand_cam_storetype::ApriltagState;
 use strand_cam_storetype::CallbackType;
 use strand_cam_storetype::StoreType as ServerState;
 #[cfg(feature = "flydratrax")]
 use strand_cam_storetype::{KalmanTrackingConfig, LedProgramConfig};
 use yew_tincture::components::CheckboxLabel;
 
-use ci2_remote_control::{RecordingFrameRate, TagFamily};
+use ci2_remote_control::{PostTriggerConfig, PostTriggerFormat, RecordingFrameRate, TagFamily};
 use ci2_types::AutoMode;
 
 use image_tracker_types::ImPtDetectCfg;
@@ -82,6 +83,7 @@ enum Msg {
     ToggleTagFamily(TagFamily),
     ToggleAprilTagDetection(bool),
     ToggleAprilTagDetectionSaveCsv(bool),
+    SetAprilTagSize(f64),
 
     ToggleImOpsDetection(bool),
     SetImOpsDestination(SocketAddr),
@@ -128,8 +130,11 @@ enum Msg {
     #[cfg(feature = "checkercal")]
     ClearCheckerboards,
 
-    SetPostTriggerBufferSize(usize),
-    PostTriggerMkvRecording,
+    SetPreTriggerSecs(f64),
+    SetPostTriggerSecs(f64),
+    SetPostTriggerMaxMbytes(u32),
+    TogglePostTriggerCompress(bool),
+    PostTrigger(PostTriggerFormat),
 
     // UpdateConnectionState(ReadyState),
     Ignore,
@@ -152,7 +157,11 @@ struct Model {
     checkerboard_width: TypedInputStorage<u32>,
     #[cfg(feature = "checkercal")]
     checkerboard_height: TypedInputStorage<u32>,
-    post_trigger_buffer_size_local: TypedInputStorage<usize>,
+    pre_trigger_secs_local: TypedInputStorage<f64>,
+    post_trigger_secs_local: TypedInputStorage<f64>,
+    post_trigger_max_mbytes_local: TypedInputStorage<u32>,
+
+    apriltag_size_local: TypedInputStorage<f64>,
 
     im_ops_destination_local: TypedInputStorage<SocketAddr>,
     im_ops_source_local: TypedInputStorage<IpAddr>,
@@ -217,7 +226,10 @@ impl Component for Model {
             checkerboard_width: TypedInputStorage::empty(),
             #[cfg(feature = "checkercal")]
             checkerboard_height: TypedInputStorage::empty(),
-            post_trigger_buffer_size_local: TypedInputStorage::empty(),
+            pre_trigger_secs_local: TypedInputStorage::empty(),
+            post_trigger_secs_local: TypedInputStorage::empty(),
+            post_trigger_max_mbytes_local: TypedInputStorage::empty(),
+            apriltag_size_local: TypedInputStorage::empty(),
 
             im_ops_destination_local: TypedInputStorage::empty(),
             im_ops_source_local: TypedInputStorage::empty(),
@@ -263,8 +275,18 @@ impl Component for Model {
                         .set_if_not_focused(response.checkerboard_data.height);
                 }
 
-                self.post_trigger_buffer_size_local
-                    .set_if_not_focused(response.post_trigger_buffer_size);
+                let post_trigger_config = &response.post_trigger_config;
+                self.pre_trigger_secs_local
+                    .set_if_not_focused(post_trigger_config.pre_trigger_secs);
+                self.post_trigger_secs_local
+                    .set_if_not_focused(post_trigger_config.post_trigger_secs);
+                self.post_trigger_max_mbytes_local
+                    .set_if_not_focused(post_trigger_config.max_buffer_mbytes);
+
+                if let Some(ref ts) = response.apriltag_state {
+                    self.apriltag_size_local
+                        .set_if_not_focused(ts.tag_size_meters);
+                }
 
                 self.im_ops_destination_local
                     .set_if_not_focused(response.im_ops_state.destination);
@@ -376,6 +398,10 @@ impl Component for Model {
                 self.ft = send_cam_message(CamArg::SetIsRecordingAprilTagCsv(v), self);
                 return false; // don't update DOM, do that on return
             }
+            Msg::SetAprilTagSize(v) => {
+                self.ft = send_cam_message(CamArg::SetAprilTagSize(v), self);
+                return false; // don't update DOM, do that on return
+            }
             Msg::ToggleImOpsDetection(v) => {
                 self.ft = send_cam_message(CamArg::ToggleImOpsDetection(v), self);
                 return false; // don't update DOM, do that on return
@@ -418,6 +444,7 @@ impl Component for Model {
                     ci2_remote_control::MkvCodec::VP8(ref mut o) => o.bitrate = v.to_u32(),
                     ci2_remote_control::MkvCodec::VP9(ref mut o) => o.bitrate = v.to_u32(),
                     ci2_remote_control::MkvCodec::H264(ref mut o) => o.bitrate = v.to_u32(),
+                    ci2_remote_control::MkvCodec::FFV1 => {}
                 }
                 self.ft = send_cam_message(CamArg::SetMkvRecordingConfig(old_config), self);
                 return false; // don't update DOM, do that on return
@@ -438,6 +465,7 @@ impl Component for Model {
                         codec: v.get_codec(&old_config.codec),
                         max_framerate: old_config.max_framerate.clone(),
                         writing_application: None,
+                        crash_safe: old_config.crash_safe,
                     };
                     self.ft = send_cam_message(CamArg::SetMkvRecordingConfig(cfg), self);
                 }
@@ -518,19 +546,33 @@ impl Component for Model {
                 return false;
             }
 
-            Msg::SetPostTriggerBufferSize(val) => {
-                self.ft = send_cam_message(CamArg::SetPostTriggerBufferSize(val), self);
+            Msg::SetPreTriggerSecs(val) => {
+                let mut cfg = self.post_trigger_config();
+                cfg.pre_trigger_secs = val;
+                self.ft = send_cam_message(CamArg::SetPostTriggerConfig(cfg), self);
+                return false;
+            }
+            Msg::SetPostTriggerSecs(val) => {
+                let mut cfg = self.post_trigger_config();
+                cfg.post_trigger_secs = val;
+                self.ft = send_cam_message(CamArg::SetPostTriggerConfig(cfg), self);
+                return false;
+            }
+            Msg::SetPostTriggerMaxMbytes(val) => {
+                let mut cfg = self.post_trigger_config();
+                cfg.max_buffer_mbytes = val;
+                self.ft = send_cam_message(CamArg::SetPostTriggerConfig(cfg), self);
+                return false;
+            }
+            Msg::TogglePostTriggerCompress(val) => {
+                let mut cfg = self.post_trigger_config();
+                cfg.compress = val;
+                self.ft = send_cam_message(CamArg::SetPostTriggerConfig(cfg), self);
                 return false;
             }
 
-            Msg::PostTriggerMkvRecording => {
-                let mkv_recording_config = if let Some(ref state) = self.server_state {
-                    state.mkv_recording_config.clone()
-                } else {
-                    ci2_remote_control::MkvRecordingConfig::default()
-                };
-
-                self.ft = send_cam_message(CamArg::PostTrigger(mkv_recording_config), self);
+            Msg::PostTrigger(format) => {
+                self.ft = send_cam_message(CamArg::PostTrigger(format), self);
                 return false; // don't update DOM, do that on return
             }
 
@@ -731,10 +773,22 @@ impl Model {
         if let Some(ref shared) = self.server_state {
             let available_codecs = shared.available_codecs();
 
-            let selected_idx = match shared.mkv_recording_config.codec {
-                ci2_remote_control::MkvCodec::VP8(_) => 0,
-                ci2_remote_control::MkvCodec::VP9(_) => 1,
-                ci2_remote_control::MkvCodec::H264(_) => 2,
+            let selected = CodecSelection::from_codec(&shared.mkv_recording_config.codec);
+            let selected_idx = available_codecs
+                .iter()
+                .position(|c| *c == selected)
+                .unwrap_or(0);
+
+            // A lossless codec has no bitrate.
+            let bitrate_div = match get_bitrate(&shared.mkv_recording_config.codec) {
+                Ok(bitrate) => html! {<div>
+                    <h5>{"MKV Bitrate"}</h5>
+                    <EnumToggle<BitrateSelection>
+                        value=bitrate
+                        onsignal=self.link.callback(|variant| Msg::ToggleMkvBitrate(variant))
+                    />
+                </div>},
+                Err(()) => html! {<div></div>},
             };
 
             // TODO: should we bother showing devices if only 1?
@@ -789,13 +843,7 @@ impl Model {
                             />
                         </div>
 
-                        <div>
-                            <h5>{"MKV Bitrate"}</h5>
-                            <EnumToggle<BitrateSelection>
-                                value=get_bitrate(&shared.mkv_recording_config.codec).unwrap()
-                                onsignal=self.link.callback(|variant| Msg::ToggleMkvBitrate(variant))
-                            />
-                        </div>
+                        { bitrate_div }
 
                         { cuda_select_div }
 
@@ -809,7 +857,24 @@ impl Model {
         }
     }
 
+    fn post_trigger_config(&self) -> PostTriggerConfig {
+        if let Some(ref state) = self.server_state {
+            state.post_trigger_config.clone()
+        } else {
+            PostTriggerConfig::default()
+        }
+    }
+
     fn view_post_trigger_options(&self) -> Html {
+        let compress = self.post_trigger_config().compress;
+        let ufmf_button = match self.server_state {
+            Some(ref shared) if shared.has_image_tracker_compiled => {
+                html! {
+                    <Button title="Post Trigger µFMF Recording" onsignal=self.link.callback(|_| Msg::PostTrigger(PostTriggerFormat::Ufmf))/>
+                }
+            }
+            _ => html! {},
+        };
         html! {
             <div class="wrap-collapsible">
                 <CheckboxLabel label="Post Triggering" initially_checked=true />
@@ -818,15 +883,34 @@ impl Model {
                     that were acquired prior to the Post Trigger occurring."}</p>
                 </div>
                 <div>
-                    <label>{"buffer size (number of frames) "}
-                        <TypedInput<usize>
-                            storage=self.post_trigger_buffer_size_local.clone()
-                            on_send_valid=self.link.callback(|v| Msg::SetPostTriggerBufferSize(v))
+                    <label>{"pre-trigger duration (seconds) "}
+                        <TypedInput<f64>
+                            storage=self.pre_trigger_secs_local.clone()
+                            on_send_valid=self.link.callback(|v| Msg::SetPreTriggerSecs(v))
                             />
                     </label>
+                    <label>{"post-trigger duration (seconds, 0 records until stopped) "}
+                        <TypedInput<f64>
+                            storage=self.post_trigger_secs_local.clone()
+                            on_send_valid=self.link.callback(|v| Msg::SetPostTriggerSecs(v))
+                            />
+                    </label>
+                    <label>{"maximum buffer size (MB) "}
+                        <TypedInput<u32>
+                            storage=self.post_trigger_max_mbytes_local.clone()
+                            on_send_valid=self.link.callback(|v| Msg::SetPostTriggerMaxMbytes(v))
+                            />
+                    </label>
+                    <Toggle
+                        label="Compress buffered frames"
+                        value=compress
+                        ontoggle=self.link.callback(|checked| {Msg::TogglePostTriggerCompress(checked)})
+                        />
 
-                    <Button title="Post Trigger MKV Recording" onsignal=self.link.callback(|_| Msg::PostTriggerMkvRecording)/>
-                    {"(Initiates MKV recording as set above. MKV recording must be manually stopped.)"}
+                    <Button title="Post Trigger MKV Recording" onsignal=self.link.callback(|_| Msg::PostTrigger(PostTriggerFormat::Mkv))/>
+                    <Button title="Post Trigger FMF Recording" onsignal=self.link.callback(|_| Msg::PostTrigger(PostTriggerFormat::Fmf))/>
+                    {ufmf_button}
+                    {"(Initiates recording with the formats set above. µFMF recordings contain no frames from before the trigger.)"}
 
                 </div>
             </div>
@@ -916,7 +1000,17 @@ impl Model {
                                     ontoggle=self.link.callback(|checked| {Msg::ToggleAprilTagDetectionSaveCsv(checked)})
                                     />
                             </div>
+
+                            <div>
+                                <label>{"Tag size (meters)"}
+                                    <TypedInput<f64>
+                                        storage=self.apriltag_size_local.clone()
+                                        on_send_valid=self.link.callback(|v| Msg::SetAprilTagSize(v))
+                                        />
+                                </label>
+                            </div>
                         </div>
+                        {self.apriltag_pose_ui(ts)}
 
                     </div>
                 }
@@ -928,6 +1022,41 @@ impl Model {
         }
     }
 
+    fn apriltag_pose_ui(&self, ts: &ApriltagState) -> Html {
+        if !ts.has_calibration {
+            return html! {
+                <div>
+                    <p>{"No camera calibration loaded, tag poses are not estimated."}</p>
+                </div>
+            };
+        }
+        let fmt3 = |v: &[f64; 3]| format!("{:.3}, {:.3}, {:.3}", v[0], v[1], v[2]);
+        let rows = ts.latest_poses.iter().map(|pose| {
+            html! {
+                <tr>
+                    <td>{pose.id}</td>
+                    <td>{fmt3(&pose.camera_position)}</td>
+                    <td>{fmt3(&pose.world_position)}</td>
+                    <td>{format!("{:.2}", pose.reprojection_error)}</td>
+                </tr>
+            }
+        });
+        html! {
+            <div>
+                <h5>{"Tag Poses"}</h5>
+                <table>
+                    <tr>
+                        <th>{"id"}</th>
+                        <th>{"camera frame x, y, z (m)"}</th>
+                        <th>{"world frame x, y, z (m)"}</th>
+                        <th>{"reprojection error (px)"}</th>
+                    </tr>
+                    {for rows}
+                </table>
+            </div>
+        }
+    }
+
     fn im_ops_ui(&self) -> Html {
         let empty = html! {
             <div>
@@ -1392,6 +1521,7 @@ fn get_bitrate(bitrate: &ci2_remote_control::MkvCodec) -> Result<BitrateSelectio
         ci2_remote_control::MkvCodec::VP8(c) => c.bitrate,
         ci2_remote_control::MkvCodec::VP9(c) => c.bitrate,
         ci2_remote_control::MkvCodec::H264(c) => c.bitrate,
+        ci2_remote_control::MkvCodec::FFV1 => return Err(()),
     };
     let x = match bitrate {
         500 => Bitrate500,
@@ -1465,15 +1595,26 @@ enum CodecSelection {
     VP8,
     VP9,
     H264,
+    FFV1,
 }
 
 impl CodecSelection {
+    fn from_codec(codec: &ci2_remote_control::MkvCodec) -> Self {
+        match codec {
+            ci2_remote_control::MkvCodec::VP8(_) => CodecSelection::VP8,
+            ci2_remote_control::MkvCodec::VP9(_) => CodecSelection::VP9,
+            ci2_remote_control::MkvCodec::H264(_) => CodecSelection::H264,
+            ci2_remote_control::MkvCodec::FFV1 => CodecSelection::FFV1,
+        }
+    }
+
     fn get_codec(&self, old: &ci2_remote_control::MkvCodec) -> ci2_remote_control::MkvCodec {
         use crate::CodecSelection::*;
         let bitrate = match old {
             ci2_remote_control::MkvCodec::VP8(c) => c.bitrate,
             ci2_remote_control::MkvCodec::VP9(c) => c.bitrate,
             ci2_remote_control::MkvCodec::H264(c) => c.bitrate,
+            ci2_remote_control::MkvCodec::FFV1 => BitrateSelection::default().to_u32(),
         };
         match self {
             VP8 => ci2_remote_control::MkvCodec::VP8(ci2_remote_control::VP8Options { bitrate }),
@@ -1482,6 +1623,7 @@ impl CodecSelection {
                 bitrate,
                 cuda_device: 0,
             }),
+            FFV1 => ci2_remote_control::MkvCodec::FFV1,
         }
     }
 }
@@ -1498,6 +1640,7 @@ impl std::fmt::Display for CodecSelection {
             CodecSelection::VP8 => "VP8",
             CodecSelection::VP9 => "VP9",
             CodecSelection::H264 => "H264",
+            CodecSelection::FFV1 => "FFV1 (lossless)",
         };
         write!(f, "{}", x)
     }
@@ -1509,6 +1652,7 @@ impl enum_iter::EnumIter for CodecSelection {
             CodecSelection::VP8,
             CodecSelection::VP9,
             CodecSelection::H264,
+            CodecSelection::FFV1,
         ]
     }
 }
@@ -1524,9 +1668,14 @@ impl HasAvail for ServerState {
                 CodecSelection::VP8,
                 CodecSelection::VP9,
                 CodecSelection::H264,
+                CodecSelection::FFV1,
             ]
         } else {
-            vec![CodecSelection::VP8, CodecSelection::VP9]
+            vec![
+                CodecSelection::VP8,
+                CodecSelection::VP9,
+                CodecSelection::FFV1,
+            ]
         }
     }
 }
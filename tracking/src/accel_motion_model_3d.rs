@@ -0,0 +1,143 @@
+use num_traits::One;
+
+use nalgebra::allocator::Allocator;
+use nalgebra::core::dimension::{U6, U9};
+use nalgebra::{DefaultAllocator, OMatrix, RealField};
+
+use adskalman::TransitionModelLinearNoControl;
+
+use crate::motion_model_3d_fixed_dt::MotionModel3DFixedDt;
+
+/// 3D motion model with acceleration for fixed dt
+///
+/// The state vector is [x y z xvel yvel zvel xaccel yaccel zaccel]
+#[derive(Debug, Clone)]
+pub struct MotionModelAccel3DFixedDt<R: RealField>
+where
+    DefaultAllocator: Allocator<R, U9, U9>,
+    DefaultAllocator: Allocator<R, U9>,
+{
+    pub transition_model: OMatrix<R, U9, U9>,
+    pub transition_model_transpose: OMatrix<R, U9, U9>,
+    pub transition_noise_covariance: OMatrix<R, U9, U9>,
+}
+
+impl<R: RealField> TransitionModelLinearNoControl<R, U9> for MotionModelAccel3DFixedDt<R>
+where
+    DefaultAllocator: Allocator<R, U9, U9>,
+    DefaultAllocator: Allocator<R, U9>,
+{
+    fn F(&self) -> &OMatrix<R, U9, U9> {
+        &self.transition_model
+    }
+    fn FT(&self) -> &OMatrix<R, U9, U9> {
+        &self.transition_model_transpose
+    }
+    fn Q(&self) -> &OMatrix<R, U9, U9> {
+        &self.transition_noise_covariance
+    }
+}
+
+/// Embed a model of position and velocity into the state with acceleration.
+///
+/// The acceleration is set to zero (with zero variance) at each time step, so
+/// the position and velocity evolve exactly as in the original model.
+impl<R: RealField> From<MotionModel3DFixedDt<R>> for MotionModelAccel3DFixedDt<R>
+where
+    DefaultAllocator: Allocator<R, U6, U6>,
+    DefaultAllocator: Allocator<R, U6>,
+    DefaultAllocator: Allocator<R, U9, U9>,
+    DefaultAllocator: Allocator<R, U9>,
+{
+    fn from(orig: MotionModel3DFixedDt<R>) -> Self {
+        let mut transition_model = OMatrix::<R, U9, U9>::zeros();
+        transition_model
+            .fixed_slice_mut::<6, 6>(0, 0)
+            .copy_from(&orig.transition_model);
+        let transition_model_transpose = transition_model.transpose();
+
+        let mut transition_noise_covariance = OMatrix::<R, U9, U9>::zeros();
+        transition_noise_covariance
+            .fixed_slice_mut::<6, 6>(0, 0)
+            .copy_from(&orig.transition_noise_covariance);
+
+        Self {
+            transition_model,
+            transition_model_transpose,
+            transition_noise_covariance,
+        }
+    }
+}
+
+/// constant acceleration 3D motion model parameterized by `dt`
+///
+/// The important method is `calc_for_dt()`. Calling this
+/// returns a motion model for a specific `dt`.
+///
+/// The state vector is [x y z xvel yvel zvel xaccel yaccel zaccel]. The
+/// acceleration changes by (white noise) jerk scaled by `motion_noise_scale`.
+#[derive(Debug, Clone)]
+pub struct ConstantAcceleration3DModel<R: RealField>
+where
+    DefaultAllocator: Allocator<R, U9, U9>,
+    DefaultAllocator: Allocator<R, U9>,
+{
+    motion_noise_scale: R,
+}
+
+impl<R: RealField> ConstantAcceleration3DModel<R>
+where
+    DefaultAllocator: Allocator<R, U9, U9>,
+    DefaultAllocator: Allocator<R, U9>,
+{
+    pub fn new(motion_noise_scale: R) -> Self {
+        Self { motion_noise_scale }
+    }
+
+    /// For a given `dt`, create a new instance of the motion model.
+    pub fn calc_for_dt(&self, dt: R) -> MotionModelAccel3DFixedDt<R> {
+        let one: R = One::one();
+        let two: R = one + one;
+        let three: R = two + one;
+        let six: R = three + three;
+        let eight: R = six + two;
+        let twenty: R = nalgebra::convert(20.0);
+
+        let dt2 = dt * dt;
+        let dt3 = dt2 * dt;
+        let dt4 = dt3 * dt;
+        let dt5 = dt4 * dt;
+
+        // Create transition model. 3D position, 3D velocity and 3D
+        // acceleration. This is "A" in most Kalman filter descriptions.
+        let mut transition_model = OMatrix::<R, U9, U9>::identity();
+        // This is "Q" in most Kalman filter descriptions.
+        let mut transition_noise_covariance = OMatrix::<R, U9, U9>::zeros();
+        for i in 0..3 {
+            let (pos, vel, accel) = (i, i + 3, i + 6);
+
+            transition_model[(pos, vel)] = dt;
+            transition_model[(pos, accel)] = dt2 / two;
+            transition_model[(vel, accel)] = dt;
+
+            let q = &mut transition_noise_covariance;
+            q[(pos, pos)] = dt5 / twenty;
+            q[(pos, vel)] = dt4 / eight;
+            q[(pos, accel)] = dt3 / six;
+            q[(vel, pos)] = dt4 / eight;
+            q[(vel, vel)] = dt3 / three;
+            q[(vel, accel)] = dt2 / two;
+            q[(accel, pos)] = dt3 / six;
+            q[(accel, vel)] = dt2 / two;
+            q[(accel, accel)] = dt;
+        }
+        let transition_model_transpose = transition_model.transpose();
+        let transition_noise_covariance = transition_noise_covariance * self.motion_noise_scale;
+
+        MotionModelAccel3DFixedDt {
+            transition_model,
+            transition_model_transpose,
+            transition_noise_covariance,
+        }
+    }
+}
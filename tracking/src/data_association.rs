@@ -0,0 +1,335 @@
+//! Association of observations with tracked objects
+//!
+//! All functions take a matrix of likelihoods with one row per object and one
+//! column per observation. An observation can only be associated with an
+//! object if its likelihood exceeds `min_likelihood` ("gating").
+
+use nalgebra::DMatrix;
+
+/// The maximum number of joint association events enumerated for a single
+/// cluster of objects by [jpda_probabilities].
+pub const MAX_JPDA_EVENTS: usize = 10_000;
+
+/// Each object, in turn, takes its most likely remaining observation.
+///
+/// Objects are visited in row order, so the result depends on the order of
+/// the objects. Returns the index of the associated observation (if any) for
+/// each object.
+pub fn greedy_assignment(likelihoods: &DMatrix<f64>, min_likelihood: f64) -> Vec<Option<usize>> {
+    let mut remaining = likelihoods.clone();
+    let mut result = Vec::with_capacity(likelihoods.nrows());
+    for row_idx in 0..remaining.nrows() {
+        let row = remaining.row(row_idx);
+        let best =
+            row.iter()
+                .enumerate()
+                .fold(
+                    None,
+                    |best: Option<(usize, f64)>, (col_idx, &value)| match best {
+                        Some((_, best_value)) if value > best_value => Some((col_idx, value)),
+                        None => Some((col_idx, value)),
+                        _ => best,
+                    },
+                );
+        match best {
+            Some((col_idx, value)) if value > min_likelihood => {
+                // Once an observation is used, no other object may use it.
+                remaining.column_mut(col_idx).fill(0.0);
+                result.push(Some(col_idx));
+            }
+            _ => result.push(None),
+        }
+    }
+    result
+}
+
+/// Global nearest neighbour: the assignment with the highest joint likelihood.
+///
+/// Leaving an object without observation is scored as if the object had an
+/// observation with likelihood `min_likelihood`. The assignment is found with
+/// the Hungarian algorithm. Returns the index of the associated observation
+/// (if any) for each object.
+pub fn gnn_assignment(likelihoods: &DMatrix<f64>, min_likelihood: f64) -> Vec<Option<usize>> {
+    let n_rows = likelihoods.nrows();
+    let n_obs = likelihoods.ncols();
+
+    // Costs of impossible associations. Every object can always remain
+    // unassigned, so an assignment using one of these is never optimal.
+    let forbidden = 1e9;
+    let miss_cost = -min_likelihood.max(f64::MIN_POSITIVE).ln();
+
+    // Columns beyond the observations are one "unassigned" column per object.
+    let costs = DMatrix::from_fn(n_rows, n_obs + n_rows, |i, j| {
+        if j < n_obs {
+            let likelihood = likelihoods[(i, j)];
+            if likelihood > min_likelihood {
+                -likelihood.ln()
+            } else {
+                forbidden
+            }
+        } else if j - n_obs == i {
+            miss_cost
+        } else {
+            forbidden
+        }
+    });
+
+    hungarian(&costs)
+        .into_iter()
+        .map(|col_idx| if col_idx < n_obs { Some(col_idx) } else { None })
+        .collect()
+}
+
+/// Joint probabilistic data association (JPDA)
+///
+/// Returns the probability that observation `j` originates from object `i`
+/// as element `(i, j)`. The probability that object `i` had no observation
+/// is one minus the sum of row `i`.
+///
+/// Objects sharing gated observations are grouped into clusters and, within
+/// each cluster, all feasible joint association events are enumerated. An
+/// event is weighted by the product of `likelihood / min_likelihood` over its
+/// associations. Returns `None` if a cluster has more than
+/// [MAX_JPDA_EVENTS] events.
+pub fn jpda_probabilities(likelihoods: &DMatrix<f64>, min_likelihood: f64) -> Option<DMatrix<f64>> {
+    let n_rows = likelihoods.nrows();
+    let n_obs = likelihoods.ncols();
+    let clutter_density = min_likelihood.max(f64::MIN_POSITIVE);
+
+    let gated: Vec<Vec<usize>> = (0..n_rows)
+        .map(|i| {
+            (0..n_obs)
+                .filter(|&j| likelihoods[(i, j)] > min_likelihood)
+                .collect()
+        })
+        .collect();
+
+    let mut result = DMatrix::zeros(n_rows, n_obs);
+    for cluster in clusters(&gated, n_obs) {
+        // Enumerate all events of this cluster with their log weight.
+        let mut events = Vec::new();
+        let mut current = Vec::with_capacity(cluster.len());
+        let mut used = vec![false; n_obs];
+        if !enumerate_events(
+            &cluster,
+            &gated,
+            likelihoods,
+            clutter_density,
+            0.0,
+            &mut current,
+            &mut used,
+            &mut events,
+        ) {
+            return None;
+        }
+
+        let max_log_weight = events
+            .iter()
+            .fold(f64::NEG_INFINITY, |acc, (w, _)| acc.max(*w));
+        let mut total = 0.0;
+        for (log_weight, assignment) in events.iter() {
+            let weight = (log_weight - max_log_weight).exp();
+            total += weight;
+            for (&row_idx, col_idx) in cluster.iter().zip(assignment.iter()) {
+                if let Some(col_idx) = col_idx {
+                    result[(row_idx, *col_idx)] += weight;
+                }
+            }
+        }
+        for &row_idx in cluster.iter() {
+            for col_idx in 0..n_obs {
+                result[(row_idx, col_idx)] /= total;
+            }
+        }
+    }
+    Some(result)
+}
+
+/// The observations which originate from any object with a non-zero
+/// probability, given the result of [jpda_probabilities].
+///
+/// These observations contribute to the update of an object, even if their
+/// association probability is small, so they must not also start new objects.
+/// Only the remaining observations are available for that.
+pub fn jpda_consumed_observations(probabilities: &DMatrix<f64>) -> Vec<usize> {
+    (0..probabilities.ncols())
+        .filter(|col_idx| probabilities.column(*col_idx).sum() > 0.0)
+        .collect()
+}
+
+/// Group objects which (transitively) share gated observations.
+fn clusters(gated: &[Vec<usize>], n_obs: usize) -> Vec<Vec<usize>> {
+    let mut cluster_of_obs: Vec<Option<usize>> = vec![None; n_obs];
+    let mut cluster_of_row: Vec<usize> = (0..gated.len()).collect();
+
+    fn root(cluster_of_row: &mut [usize], mut i: usize) -> usize {
+        while cluster_of_row[i] != i {
+            cluster_of_row[i] = cluster_of_row[cluster_of_row[i]];
+            i = cluster_of_row[i];
+        }
+        i
+    }
+
+    for (row_idx, cols) in gated.iter().enumerate() {
+        for &col_idx in cols.iter() {
+            match cluster_of_obs[col_idx] {
+                Some(other) => {
+                    let a = root(&mut cluster_of_row, row_idx);
+                    let b = root(&mut cluster_of_row, other);
+                    cluster_of_row[a] = b;
+                }
+                None => cluster_of_obs[col_idx] = Some(row_idx),
+            }
+        }
+    }
+
+    let mut result: Vec<Vec<usize>> = Vec::new();
+    let mut index_of_root: Vec<Option<usize>> = vec![None; gated.len()];
+    for row_idx in 0..gated.len() {
+        let r = root(&mut cluster_of_row, row_idx);
+        match index_of_root[r] {
+            Some(idx) => result[idx].push(row_idx),
+            None => {
+                index_of_root[r] = Some(result.len());
+                result.push(vec![row_idx]);
+            }
+        }
+    }
+    result
+}
+
+/// Recursively enumerate the joint events of the rows in `cluster`.
+///
+/// Returns `false` if there are more than [MAX_JPDA_EVENTS] events.
+#[allow(clippy::too_many_arguments)]
+fn enumerate_events(
+    cluster: &[usize],
+    gated: &[Vec<usize>],
+    likelihoods: &DMatrix<f64>,
+    clutter_density: f64,
+    log_weight: f64,
+    current: &mut Vec<Option<usize>>,
+    used: &mut [bool],
+    events: &mut Vec<(f64, Vec<Option<usize>>)>,
+) -> bool {
+    let depth = current.len();
+    if depth == cluster.len() {
+        if events.len() >= MAX_JPDA_EVENTS {
+            return false;
+        }
+        events.push((log_weight, current.clone()));
+        return true;
+    }
+    let row_idx = cluster[depth];
+
+    // The object has no observation.
+    current.push(None);
+    let ok = enumerate_events(
+        cluster,
+        gated,
+        likelihoods,
+        clutter_density,
+        log_weight,
+        current,
+        used,
+        events,
+    );
+    current.pop();
+    if !ok {
+        return false;
+    }
+
+    for &col_idx in gated[row_idx].iter() {
+        if used[col_idx] {
+            continue;
+        }
+        used[col_idx] = true;
+        current.push(Some(col_idx));
+        let ok = enumerate_events(
+            cluster,
+            gated,
+            likelihoods,
+            clutter_density,
+            log_weight + (likelihoods[(row_idx, col_idx)] / clutter_density).ln(),
+            current,
+            used,
+            events,
+        );
+        current.pop();
+        used[col_idx] = false;
+        if !ok {
+            return false;
+        }
+    }
+    true
+}
+
+/// Solve the rectangular assignment problem with the Hungarian algorithm.
+///
+/// `costs` must have at least as many columns as rows. Returns the column
+/// assigned to each row such that the total cost is minimal.
+fn hungarian(costs: &DMatrix<f64>) -> Vec<usize> {
+    let n = costs.nrows();
+    let m = costs.ncols();
+    assert!(n <= m);
+
+    // Potentials and matching use 1-based indices, with 0 as a sentinel.
+    let mut u = vec![0.0; n + 1];
+    let mut v = vec![0.0; m + 1];
+    let mut row_of_col = vec![0usize; m + 1];
+    let mut way = vec![0usize; m + 1];
+
+    for i in 1..=n {
+        row_of_col[0] = i;
+        let mut j0 = 0;
+        let mut min_v = vec![f64::INFINITY; m + 1];
+        let mut used = vec![false; m + 1];
+        loop {
+            used[j0] = true;
+            let i0 = row_of_col[j0];
+            let mut delta = f64::INFINITY;
+            let mut j1 = 0;
+            for j in 1..=m {
+                if !used[j] {
+                    let cur = costs[(i0 - 1, j - 1)] - u[i0] - v[j];
+                    if cur < min_v[j] {
+                        min_v[j] = cur;
+                        way[j] = j0;
+                    }
+                    if min_v[j] < delta {
+                        delta = min_v[j];
+                        j1 = j;
+                    }
+                }
+            }
+            for j in 0..=m {
+                if used[j] {
+                    u[row_of_col[j]] += delta;
+                    v[j] -= delta;
+                } else {
+                    min_v[j] -= delta;
+                }
+            }
+            j0 = j1;
+            if row_of_col[j0] == 0 {
+                break;
+            }
+        }
+        loop {
+            let j1 = way[j0];
+            row_of_col[j0] = row_of_col[j1];
+            j0 = j1;
+            if j0 == 0 {
+                break;
+            }
+        }
+    }
+
+    let mut result = vec![0; n];
+    for j in 1..=m {
+        if row_of_col[j] != 0 {
+            result[row_of_col[j] - 1] = j - 1;
+        }
+    }
+    result
+}
@@ -0,0 +1,276 @@
+use nalgebra::allocator::Allocator;
+use nalgebra::core::dimension::{DimName, U1, U2, U9};
+use nalgebra::{DMatrix, DefaultAllocator, OMatrix, OVector, RealField};
+
+use adskalman::{
+    CovarianceUpdateMethod, ObservationModel, StateAndCovariance, TransitionModelLinearNoControl,
+};
+
+use crate::accel_motion_model_3d::MotionModelAccel3DFixedDt;
+
+/// interacting multiple model (IMM) mixer over 3D motion models for fixed dt
+///
+/// Each motion model ("mode") runs its own Kalman filter. The probability of
+/// each mode is updated from how well the mode predicted the observations.
+/// Before each prediction, the estimates of all modes are mixed according to
+/// the probabilities of switching between modes. With a single mode, this is
+/// an ordinary Kalman filter.
+///
+/// The state vector is [x y z xvel yvel zvel xaccel yaccel zaccel].
+#[derive(Debug, Clone)]
+pub struct ImmMotionModel<R: RealField> {
+    models: Vec<MotionModelAccel3DFixedDt<R>>,
+    /// Element `(i, j)` is the probability of switching from mode `i` to `j`.
+    switch_matrix: DMatrix<R>,
+}
+
+impl<R: RealField> ImmMotionModel<R> {
+    /// Create a new mixer over `models`.
+    ///
+    /// On each time step, a mode switches to one of the other modes with
+    /// probability `switch_probability` (with equal probability for each of
+    /// them). Panics if `models` is empty.
+    pub fn new(models: Vec<MotionModelAccel3DFixedDt<R>>, switch_probability: R) -> Self {
+        assert!(!models.is_empty(), "IMM requires at least one model");
+        let n = models.len();
+        let one = R::one();
+        let switch_matrix = if n == 1 {
+            DMatrix::from_element(1, 1, one)
+        } else {
+            let other: R = switch_probability / nalgebra::convert((n - 1) as f64);
+            DMatrix::from_fn(n, n, |i, j| {
+                if i == j {
+                    one - switch_probability
+                } else {
+                    other
+                }
+            })
+        };
+        Self {
+            models,
+            switch_matrix,
+        }
+    }
+
+    /// The motion models of the modes.
+    pub fn models(&self) -> &[MotionModelAccel3DFixedDt<R>] {
+        &self.models
+    }
+
+    /// Create an estimate with the same state in all modes.
+    ///
+    /// All modes are equally likely.
+    pub fn initial_estimate(&self, estimate: StateAndCovariance<R, U9>) -> ImmEstimate<R> {
+        let n = self.models.len();
+        let probability = R::one() / nalgebra::convert(n as f64);
+        ImmEstimate {
+            estimates: vec![estimate; n],
+            probabilities: vec![probability; n],
+        }
+    }
+
+    /// Mix the estimates of all modes and predict them forward by `dt`.
+    pub fn predict(&self, previous: &ImmEstimate<R>) -> ImmEstimate<R> {
+        let n = self.models.len();
+        assert_eq!(previous.estimates.len(), n);
+
+        if n == 1 {
+            return ImmEstimate {
+                estimates: vec![self.models[0].predict(&previous.estimates[0])],
+                probabilities: previous.probabilities.clone(),
+            };
+        }
+
+        let mut estimates = Vec::with_capacity(n);
+        let mut probabilities = Vec::with_capacity(n);
+        for (j, model) in self.models.iter().enumerate() {
+            // The predicted probability of mode `j`.
+            let c_j = (0..n).fold(R::zero(), |acc, i| {
+                acc + self.switch_matrix[(i, j)] * previous.probabilities[i]
+            });
+            // The probability of having been in mode `i` given mode `j` now.
+            let weights: Vec<R> = (0..n)
+                .map(|i| {
+                    if c_j > R::zero() {
+                        self.switch_matrix[(i, j)] * previous.probabilities[i] / c_j
+                    } else {
+                        previous.probabilities[i]
+                    }
+                })
+                .collect();
+            let mixed = moment_match(&previous.estimates, &weights);
+            estimates.push(model.predict(&mixed));
+            probabilities.push(c_j);
+        }
+        ImmEstimate {
+            estimates,
+            probabilities,
+        }
+    }
+}
+
+/// The state of an [ImmMotionModel]: one estimate and probability per mode
+#[derive(Debug, Clone)]
+pub struct ImmEstimate<R: RealField> {
+    estimates: Vec<StateAndCovariance<R, U9>>,
+    probabilities: Vec<R>,
+}
+
+impl<R: RealField> ImmEstimate<R> {
+    /// The estimates of each mode.
+    pub fn estimates(&self) -> &[StateAndCovariance<R, U9>] {
+        &self.estimates
+    }
+
+    /// The probabilities of each mode.
+    pub fn probabilities(&self) -> &[R] {
+        &self.probabilities
+    }
+
+    /// The combined estimate over all modes.
+    pub fn combined(&self) -> StateAndCovariance<R, U9> {
+        if self.estimates.len() == 1 {
+            self.estimates[0].clone()
+        } else {
+            moment_match(&self.estimates, &self.probabilities)
+        }
+    }
+
+    /// Reduce a weighted mixture of estimates to a single estimate.
+    ///
+    /// All `components` must have the same number of modes. Within each mode,
+    /// the estimates are moment matched. The weights need not be normalized.
+    /// Panics if `components` is empty.
+    pub fn mixture(components: &[(R, ImmEstimate<R>)]) -> Self {
+        assert!(
+            !components.is_empty(),
+            "mixture requires at least one component"
+        );
+        let n = components[0].1.estimates.len();
+
+        let mut estimates = Vec::with_capacity(n);
+        let mut probabilities = Vec::with_capacity(n);
+        for mode in 0..n {
+            let mode_estimates: Vec<StateAndCovariance<R, U9>> = components
+                .iter()
+                .map(|(_, c)| c.estimates[mode].clone())
+                .collect();
+            let weights: Vec<R> = components
+                .iter()
+                .map(|(w, c)| *w * c.probabilities[mode])
+                .collect();
+            let total = weights.iter().fold(R::zero(), |acc, w| acc + *w);
+            let weights: Vec<R> = if total > R::zero() {
+                weights.into_iter().map(|w| w / total).collect()
+            } else {
+                let n_components: R = nalgebra::convert(components.len() as f64);
+                vec![R::one() / n_components; components.len()]
+            };
+            estimates.push(moment_match(&mode_estimates, &weights));
+            probabilities.push(total);
+        }
+
+        let total = probabilities.iter().fold(R::zero(), |acc, p| acc + *p);
+        let probabilities = if total > R::zero() {
+            probabilities.into_iter().map(|p| p / total).collect()
+        } else {
+            components[0].1.probabilities.clone()
+        };
+
+        Self {
+            estimates,
+            probabilities,
+        }
+    }
+
+    /// Update the estimate of each mode from a 2D observation.
+    ///
+    /// The covariance is updated using the Joseph form. The mode
+    /// probabilities are updated with the likelihood of the observation given
+    /// the estimate of each mode.
+    pub fn update<O>(
+        &self,
+        observation_model: &O,
+        observation: &OVector<R, U2>,
+    ) -> Result<Self, adskalman::Error>
+    where
+        O: ObservationModel<R, U9, U2>,
+    {
+        if self.estimates.len() == 1 {
+            let estimate = observation_model.update(
+                &self.estimates[0],
+                observation,
+                CovarianceUpdateMethod::JosephForm,
+            )?;
+            return Ok(Self {
+                estimates: vec![estimate],
+                probabilities: self.probabilities.clone(),
+            });
+        }
+
+        let mut estimates = Vec::with_capacity(self.estimates.len());
+        let mut probabilities = Vec::with_capacity(self.estimates.len());
+        for (prior, probability) in self.estimates.iter().zip(self.probabilities.iter()) {
+            let residual = observation - observation_model.predict_observation(prior.state());
+            let residual_covariance =
+                observation_model.H() * prior.covariance() * observation_model.HT()
+                    + observation_model.R();
+            probabilities.push(*probability * gaussian_pdf(&residual, &residual_covariance));
+            estimates.push(observation_model.update(
+                prior,
+                observation,
+                CovarianceUpdateMethod::JosephForm,
+            )?);
+        }
+
+        let total = probabilities.iter().fold(R::zero(), |acc, p| acc + *p);
+        let probabilities = if total > R::zero() {
+            probabilities.into_iter().map(|p| p / total).collect()
+        } else {
+            // The observation is (numerically) impossible in all modes, so it
+            // does not tell us which mode is more likely.
+            self.probabilities.clone()
+        };
+
+        Ok(Self {
+            estimates,
+            probabilities,
+        })
+    }
+}
+
+/// A single Gaussian with the same mean and covariance as a weighted mixture.
+pub(crate) fn moment_match<R, SS>(
+    estimates: &[StateAndCovariance<R, SS>],
+    weights: &[R],
+) -> StateAndCovariance<R, SS>
+where
+    R: RealField,
+    SS: DimName,
+    DefaultAllocator: Allocator<R, SS, SS>,
+    DefaultAllocator: Allocator<R, SS>,
+    DefaultAllocator: Allocator<R, U1, SS>,
+{
+    let mut state = OVector::<R, SS>::zeros();
+    for (est, w) in estimates.iter().zip(weights.iter()) {
+        state += est.state() * *w;
+    }
+    let mut covariance = OMatrix::<R, SS, SS>::zeros();
+    for (est, w) in estimates.iter().zip(weights.iter()) {
+        let diff = est.state() - &state;
+        covariance += (est.covariance() + &diff * diff.transpose()) * *w;
+    }
+    StateAndCovariance::new(state, covariance)
+}
+
+/// The probability density of a zero-mean 2D Gaussian at `x`.
+fn gaussian_pdf<R: RealField>(x: &OVector<R, U2>, covariance: &OMatrix<R, U2, U2>) -> R {
+    let det = covariance.determinant();
+    let inv = match covariance.clone().try_inverse() {
+        Some(inv) if det > R::zero() => inv,
+        _ => return R::zero(),
+    };
+    let two: R = nalgebra::convert(2.0);
+    let mahalanobis_sq = (x.transpose() * inv * x)[(0, 0)];
+    (-mahalanobis_sq / two).exp() / (R::two_pi() * det.sqrt())
+}
@@ -1,7 +1,12 @@
 extern crate nalgebra as na;
 
+pub mod accel_motion_model_3d;
+pub mod data_association;
 pub mod flat_motion_model_3d;
+pub mod imm;
 pub mod motion_model_2d;
 pub mod motion_model_3d;
 pub mod motion_model_3d_fixed_dt;
+pub mod motion_model_registry;
 pub mod observation_model_2d;
+pub mod rts_smoother;
@@ -0,0 +1,349 @@
+//! 3D motion models selectable by name
+//!
+//! A single model is filtered in its own state space: [x y z xvel yvel zvel]
+//! for models without acceleration and [x y z xvel yvel zvel xaccel yaccel
+//! zaccel] for models with acceleration. An IMM mixer uses the state with
+//! acceleration for all its models, where models without acceleration keep the
+//! acceleration at zero.
+
+use nalgebra::core::dimension::{U2, U6, U9};
+use nalgebra::{OMatrix, OVector, RealField};
+
+use adskalman::{
+    CovarianceUpdateMethod, ObservationModel, StateAndCovariance, TransitionModelLinearNoControl,
+};
+
+use crate::accel_motion_model_3d::{ConstantAcceleration3DModel, MotionModelAccel3DFixedDt};
+use crate::flat_motion_model_3d::FlatZZero3DModel;
+use crate::imm::{moment_match, ImmEstimate, ImmMotionModel};
+use crate::motion_model_3d::ConstantVelocity3DModel;
+use crate::motion_model_3d_fixed_dt::{MotionModel3D, MotionModel3DFixedDt};
+
+/// name of [ConstantVelocity3DModel]
+pub const CONSTANT_VELOCITY: &str = "constant-velocity";
+/// name of [FlatZZero3DModel]
+pub const FLAT_Z_ZERO: &str = "flat-z-zero";
+/// name of [ConstantAcceleration3DModel]
+pub const CONSTANT_ACCELERATION: &str = "constant-acceleration";
+/// name of the interacting multiple model mixer, see [ImmMotionModel]
+pub const IMM: &str = "imm";
+
+/// The names of all motion models in the registry.
+pub const MOTION_MODEL_NAMES: [&str; 4] =
+    [CONSTANT_VELOCITY, FLAT_Z_ZERO, CONSTANT_ACCELERATION, IMM];
+
+#[derive(thiserror::Error, Debug)]
+pub enum MotionModelError {
+    #[error("unknown motion model \"{0}\" (expected one of: constant-velocity, flat-z-zero, constant-acceleration, imm)")]
+    UnknownMotionModel(String),
+    #[error("the \"imm\" motion model requires a list of models to mix")]
+    ImmModelsRequired,
+    #[error("the \"imm\" motion model cannot mix \"imm\" models")]
+    NestedImm,
+    #[error("IMM switch probability must be between 0 and 1")]
+    InvalidSwitchProbability,
+    #[error("smoothing is not implemented for the \"imm\" motion model mixing several models")]
+    SmoothingNotImplemented,
+}
+
+/// A motion model from the registry, parameterized by `dt`
+///
+/// The important method is `calc_for_dt()`. Calling this returns a motion
+/// model for a specific `dt`.
+#[derive(Debug, Clone)]
+pub enum RegisteredMotionModel3D<R: RealField> {
+    ConstantVelocity(ConstantVelocity3DModel<R>),
+    FlatZZero(FlatZZero3DModel<R>),
+    ConstantAcceleration(ConstantAcceleration3DModel<R>),
+    Imm {
+        models: Vec<RegisteredMotionModel3D<R>>,
+        switch_probability: R,
+    },
+}
+
+impl<R: RealField> RegisteredMotionModel3D<R> {
+    /// Create the motion model with name `name`.
+    ///
+    /// The "imm" model mixes other models and must be created with
+    /// [RegisteredMotionModel3D::new_imm].
+    pub fn new(name: &str, motion_noise_scale: R) -> Result<Self, MotionModelError> {
+        match name {
+            CONSTANT_VELOCITY => Ok(Self::ConstantVelocity(ConstantVelocity3DModel::new(
+                motion_noise_scale,
+            ))),
+            FLAT_Z_ZERO => Ok(Self::FlatZZero(FlatZZero3DModel::new(motion_noise_scale))),
+            CONSTANT_ACCELERATION => Ok(Self::ConstantAcceleration(
+                ConstantAcceleration3DModel::new(motion_noise_scale),
+            )),
+            IMM => Err(MotionModelError::ImmModelsRequired),
+            _ => Err(MotionModelError::UnknownMotionModel(name.to_string())),
+        }
+    }
+
+    /// Create an interacting multiple model (IMM) mixer over `models`.
+    ///
+    /// See [ImmMotionModel::new] for the meaning of `switch_probability`.
+    pub fn new_imm(models: Vec<Self>, switch_probability: R) -> Result<Self, MotionModelError> {
+        if models.is_empty() {
+            return Err(MotionModelError::ImmModelsRequired);
+        }
+        if models.iter().any(|m| matches!(m, Self::Imm { .. })) {
+            return Err(MotionModelError::NestedImm);
+        }
+        if !(switch_probability >= R::zero() && switch_probability <= R::one()) {
+            return Err(MotionModelError::InvalidSwitchProbability);
+        }
+        Ok(Self::Imm {
+            models,
+            switch_probability,
+        })
+    }
+
+    /// The name of this model in the registry.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::ConstantVelocity(_) => CONSTANT_VELOCITY,
+            Self::FlatZZero(_) => FLAT_Z_ZERO,
+            Self::ConstantAcceleration(_) => CONSTANT_ACCELERATION,
+            Self::Imm { .. } => IMM,
+        }
+    }
+
+    /// Return an error if trajectories cannot be smoothed with this model.
+    ///
+    /// Smoothing is implemented for single models but not for an IMM mixer of
+    /// several models, see [MotionModelFixedDt::rts_smooth].
+    pub fn check_smoothing(&self) -> Result<(), MotionModelError> {
+        match self {
+            Self::Imm { models, .. } if models.len() > 1 => {
+                Err(MotionModelError::SmoothingNotImplemented)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// For a given `dt`, create a new instance of the motion model.
+    ///
+    /// An "imm" model mixing a single model results in that model alone.
+    pub fn calc_for_dt(&self, dt: R) -> MotionModelFixedDt<R> {
+        match self {
+            Self::ConstantVelocity(m) => MotionModelFixedDt::Velocity(m.calc_for_dt(dt)),
+            Self::FlatZZero(m) => MotionModelFixedDt::Velocity(m.calc_for_dt(dt)),
+            Self::ConstantAcceleration(m) => MotionModelFixedDt::Acceleration(m.calc_for_dt(dt)),
+            Self::Imm {
+                models,
+                switch_probability,
+            } => match models.as_slice() {
+                [single] => single.calc_for_dt(dt),
+                models => {
+                    let models = models.iter().map(|m| m.calc_accel_for_dt(dt)).collect();
+                    MotionModelFixedDt::Imm(ImmMotionModel::new(models, *switch_probability))
+                }
+            },
+        }
+    }
+
+    fn calc_accel_for_dt(&self, dt: R) -> MotionModelAccel3DFixedDt<R> {
+        match self {
+            Self::ConstantVelocity(m) => m.calc_for_dt(dt).into(),
+            Self::FlatZZero(m) => m.calc_for_dt(dt).into(),
+            Self::ConstantAcceleration(m) => m.calc_for_dt(dt),
+            Self::Imm { .. } => unreachable!("nested IMM models are not allowed"),
+        }
+    }
+}
+
+/// A motion model from the registry for a fixed dt
+#[derive(Debug)]
+pub enum MotionModelFixedDt<R: RealField> {
+    /// A single model with the state [x y z xvel yvel zvel].
+    Velocity(MotionModel3DFixedDt<R>),
+    /// A single model with the state [x y z xvel yvel zvel xaccel yaccel zaccel].
+    Acceleration(MotionModelAccel3DFixedDt<R>),
+    /// Several models mixed by an IMM.
+    Imm(ImmMotionModel<R>),
+}
+
+impl<R: RealField> MotionModelFixedDt<R> {
+    /// Create an estimate for this model from an estimate of the position and
+    /// velocity.
+    ///
+    /// The acceleration, if part of the state, is known to be zero initially
+    /// and only becomes uncertain through the motion model.
+    pub fn initial_estimate(&self, estimate: StateAndCovariance<R, U6>) -> MotionModelEstimate<R> {
+        match self {
+            Self::Velocity(_) => MotionModelEstimate::Velocity(estimate),
+            Self::Acceleration(_) => MotionModelEstimate::Acceleration(with_acceleration(estimate)),
+            Self::Imm(m) => {
+                MotionModelEstimate::Imm(m.initial_estimate(with_acceleration(estimate)))
+            }
+        }
+    }
+
+    /// Predict `previous` forward by `dt`.
+    ///
+    /// Panics if `previous` was not created by this model.
+    pub fn predict(&self, previous: &MotionModelEstimate<R>) -> MotionModelEstimate<R> {
+        use MotionModelEstimate as E;
+        match (self, previous) {
+            (Self::Velocity(m), E::Velocity(est)) => E::Velocity(m.predict(est)),
+            (Self::Acceleration(m), E::Acceleration(est)) => E::Acceleration(m.predict(est)),
+            (Self::Imm(m), E::Imm(est)) => E::Imm(m.predict(est)),
+            _ => panic!("estimate does not belong to this motion model"),
+        }
+    }
+
+    /// Smooth the estimates of a forward pass with a Rauch-Tung-Striebel
+    /// smoother.
+    ///
+    /// See [crate::rts_smoother::rts_smooth]. Returns the position and
+    /// velocity of each smoothed estimate, or `None` if smoothing failed or the
+    /// model is an IMM mixer (for which smoothing is not implemented).
+    pub fn rts_smooth(
+        &self,
+        filtered: &[MotionModelEstimate<R>],
+    ) -> Option<Vec<StateAndCovariance<R, U6>>> {
+        use MotionModelEstimate as E;
+        match self {
+            Self::Velocity(m) => {
+                let filtered = filtered
+                    .iter()
+                    .map(|est| match est {
+                        E::Velocity(est) => est.clone(),
+                        _ => panic!("estimate does not belong to this motion model"),
+                    })
+                    .collect::<Vec<_>>();
+                crate::rts_smoother::rts_smooth(&filtered, m)
+            }
+            Self::Acceleration(m) => {
+                let filtered = filtered
+                    .iter()
+                    .map(|est| match est {
+                        E::Acceleration(est) => est.clone(),
+                        _ => panic!("estimate does not belong to this motion model"),
+                    })
+                    .collect::<Vec<_>>();
+                let smoothed = crate::rts_smoother::rts_smooth(&filtered, m)?;
+                Some(smoothed.iter().map(position_velocity).collect())
+            }
+            Self::Imm(_) => None,
+        }
+    }
+}
+
+/// The estimate of a [MotionModelFixedDt]
+#[derive(Debug, Clone)]
+pub enum MotionModelEstimate<R: RealField> {
+    Velocity(StateAndCovariance<R, U6>),
+    Acceleration(StateAndCovariance<R, U9>),
+    Imm(ImmEstimate<R>),
+}
+
+impl<R: RealField> MotionModelEstimate<R> {
+    /// The estimate of the position and velocity, [x y z xvel yvel zvel].
+    pub fn position_velocity(&self) -> StateAndCovariance<R, U6> {
+        match self {
+            Self::Velocity(est) => est.clone(),
+            Self::Acceleration(est) => position_velocity(est),
+            Self::Imm(est) => position_velocity(&est.combined()),
+        }
+    }
+
+    /// Update the estimate from a 2D observation.
+    ///
+    /// The covariance is updated using the Joseph form. See
+    /// [ImmEstimate::update] for the update of an IMM estimate.
+    pub fn update<O>(
+        &self,
+        observation_model: &O,
+        observation: &OVector<R, U2>,
+    ) -> Result<Self, adskalman::Error>
+    where
+        O: ObservationModel<R, U6, U2> + ObservationModel<R, U9, U2>,
+    {
+        let form = CovarianceUpdateMethod::JosephForm;
+        Ok(match self {
+            Self::Velocity(est) => {
+                Self::Velocity(observation_model.update(est, observation, form)?)
+            }
+            Self::Acceleration(est) => {
+                Self::Acceleration(observation_model.update(est, observation, form)?)
+            }
+            Self::Imm(est) => Self::Imm(est.update(observation_model, observation)?),
+        })
+    }
+
+    /// Reduce a weighted mixture of estimates to a single estimate.
+    ///
+    /// All `components` must be estimates of the same model. The weights need
+    /// not be normalized. Panics if `components` is empty.
+    pub fn mixture(components: &[(R, Self)]) -> Self {
+        assert!(
+            !components.is_empty(),
+            "mixture requires at least one component"
+        );
+        let total = components.iter().fold(R::zero(), |acc, (w, _)| acc + *w);
+        let weights: Vec<R> = if total > R::zero() {
+            components.iter().map(|(w, _)| *w / total).collect()
+        } else {
+            let n: R = nalgebra::convert(components.len() as f64);
+            vec![R::one() / n; components.len()]
+        };
+        match &components[0].1 {
+            Self::Velocity(_) => {
+                let estimates: Vec<_> = components
+                    .iter()
+                    .map(|(_, c)| match c {
+                        Self::Velocity(est) => est.clone(),
+                        _ => panic!("mixture of estimates of different models"),
+                    })
+                    .collect();
+                Self::Velocity(moment_match(&estimates, &weights))
+            }
+            Self::Acceleration(_) => {
+                let estimates: Vec<_> = components
+                    .iter()
+                    .map(|(_, c)| match c {
+                        Self::Acceleration(est) => est.clone(),
+                        _ => panic!("mixture of estimates of different models"),
+                    })
+                    .collect();
+                Self::Acceleration(moment_match(&estimates, &weights))
+            }
+            Self::Imm(_) => {
+                let components: Vec<_> = components
+                    .iter()
+                    .map(|(w, c)| match c {
+                        Self::Imm(est) => (*w, est.clone()),
+                        _ => panic!("mixture of estimates of different models"),
+                    })
+                    .collect();
+                Self::Imm(ImmEstimate::mixture(&components))
+            }
+        }
+    }
+}
+
+/// Extend an estimate of position and velocity with zero acceleration.
+fn with_acceleration<R: RealField>(
+    estimate: StateAndCovariance<R, U6>,
+) -> StateAndCovariance<R, U9> {
+    let (state6, covariance6) = estimate.inner();
+    let mut state = OVector::<R, U9>::zeros();
+    state.fixed_rows_mut::<6>(0).copy_from(&state6);
+    let mut covariance = OMatrix::<R, U9, U9>::zeros();
+    covariance
+        .fixed_slice_mut::<6, 6>(0, 0)
+        .copy_from(&covariance6);
+    StateAndCovariance::new(state, covariance)
+}
+
+/// The marginal estimate of position and velocity.
+fn position_velocity<R: RealField>(
+    estimate: &StateAndCovariance<R, U9>,
+) -> StateAndCovariance<R, U6> {
+    StateAndCovariance::new(
+        estimate.state().fixed_rows::<6>(0).into_owned(),
+        estimate.covariance().fixed_slice::<6, 6>(0, 0).into_owned(),
+    )
+}
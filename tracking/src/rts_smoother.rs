@@ -0,0 +1,66 @@
+use nalgebra::allocator::Allocator;
+use nalgebra::core::dimension::DimName;
+use nalgebra::{DefaultAllocator, OMatrix, RealField};
+
+use adskalman::{StateAndCovariance, TransitionModelLinearNoControl};
+
+/// Rauch-Tung-Striebel smoother
+///
+/// `filtered` are the posterior estimates of the (causal) forward Kalman
+/// filter, one per time step, where each prior was computed from the previous
+/// posterior with `motion_model`. The smoothed estimates, which incorporate
+/// all observations (including those made later), are returned in the same
+/// order. The last smoothed estimate is identical to the last filtered one.
+///
+/// Returns `None` if a predicted covariance matrix cannot be inverted.
+pub fn rts_smooth<R, SS, M>(
+    filtered: &[StateAndCovariance<R, SS>],
+    motion_model: &M,
+) -> Option<Vec<StateAndCovariance<R, SS>>>
+where
+    R: RealField,
+    SS: DimName,
+    M: TransitionModelLinearNoControl<R, SS>,
+    DefaultAllocator: Allocator<R, SS, SS>,
+    DefaultAllocator: Allocator<R, SS>,
+{
+    let mut smoothed: Vec<StateAndCovariance<R, SS>> = Vec::with_capacity(filtered.len());
+    let last = match filtered.last() {
+        Some(last) => last,
+        None => return Some(smoothed),
+    };
+    smoothed.push(last.clone());
+
+    // Run backwards in time, from the second-to-last estimate to the first.
+    for est in filtered.iter().rev().skip(1) {
+        let next_smoothed = smoothed.last().unwrap();
+
+        // The prior for the next time step, as computed by the forward filter.
+        let prior = motion_model.predict(est);
+        let prior_covariance_inv = match prior.covariance().clone().try_inverse() {
+            Some(inv) => inv,
+            None => {
+                // Degenerate models such as `FlatZZero3DModel` have zero
+                // variance in some dimensions. These dimensions are not
+                // propagated by the transition model, so regularizing them
+                // does not change the smoother gain.
+                let cov = prior.covariance();
+                let max_variance = (0..SS::dim()).fold(R::zero(), |acc, i| acc.max(cov[(i, i)]));
+                let eps = max_variance * nalgebra::convert(1e-12);
+                (cov + OMatrix::<R, SS, SS>::identity() * eps).try_inverse()?
+            }
+        };
+
+        // The smoother gain.
+        let gain = est.covariance() * motion_model.FT() * prior_covariance_inv;
+        let gain_transpose = gain.transpose();
+
+        let state = est.state() + &gain * (next_smoothed.state() - prior.state());
+        let covariance = est.covariance()
+            + &gain * (next_smoothed.covariance() - prior.covariance()) * gain_transpose;
+        smoothed.push(StateAndCovariance::new(state, covariance));
+    }
+
+    smoothed.reverse();
+    Some(smoothed)
+}
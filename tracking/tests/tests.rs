@@ -92,3 +92,391 @@ fn test_missing_frames_via_large_dt_flat3d() {
     assert_relative_eq!(est1_2.state(), est2_2.state());
     assert_relative_eq!(est1_2.covariance(), est2_2.covariance());
 }
+
+/// Test that smoothing a noisy constant velocity trajectory gives estimates
+/// closer to the truth than the forward filter.
+#[test]
+fn test_rts_smoother_3d() {
+    use adskalman::{CovarianceUpdateMethod, ObservationModel};
+    use nalgebra::core::dimension::{U3, U6};
+    use nalgebra::{Matrix3, OMatrix, OVector, Vector3};
+    use tracking::motion_model_3d::ConstantVelocity3DModel;
+    use tracking::rts_smoother::rts_smooth;
+
+    /// Observe the position directly.
+    struct PositionObservationModel {
+        observation_matrix: OMatrix<f64, U3, U6>,
+        observation_matrix_transpose: OMatrix<f64, U6, U3>,
+        observation_noise_covariance: OMatrix<f64, U3, U3>,
+    }
+
+    impl ObservationModel<f64, U6, U3> for PositionObservationModel {
+        fn H(&self) -> &OMatrix<f64, U3, U6> {
+            &self.observation_matrix
+        }
+        fn HT(&self) -> &OMatrix<f64, U6, U3> {
+            &self.observation_matrix_transpose
+        }
+        fn R(&self) -> &OMatrix<f64, U3, U3> {
+            &self.observation_noise_covariance
+        }
+        fn predict_observation(&self, state: &OVector<f64, U6>) -> OVector<f64, U3> {
+            &self.observation_matrix * state
+        }
+    }
+
+    let mut observation_matrix = OMatrix::<f64, U3, U6>::zeros();
+    observation_matrix
+        .fixed_columns_mut::<3>(0)
+        .copy_from(&Matrix3::identity());
+    let obs_model = PositionObservationModel {
+        observation_matrix,
+        observation_matrix_transpose: observation_matrix.transpose(),
+        observation_noise_covariance: 0.01 * Matrix3::identity(),
+    };
+
+    let dt = 0.01;
+    let motion_model = ConstantVelocity3DModel::new(1.0).calc_for_dt(dt);
+
+    let start = Vector3::new(0.1, 0.2, 0.3);
+    let vel = Vector3::new(1.0, -0.5, 0.25);
+    let truth: Vec<Vector3<f64>> = (0..200).map(|i| start + vel * (i as f64 * dt)).collect();
+
+    // Deterministic noise in the range [-0.1, 0.1).
+    let noise = |i: usize| ((i as f64 * 12.9898).sin() * 43758.5453).fract() * 0.1;
+
+    let mut estimate = StateAndCovariance::new(Vector6::zeros(), Matrix6::identity());
+    let mut filtered = Vec::with_capacity(truth.len());
+    for (i, pos) in truth.iter().enumerate() {
+        if i > 0 {
+            estimate = motion_model.predict(&estimate);
+        }
+        let observation = pos + Vector3::new(noise(3 * i), noise(3 * i + 1), noise(3 * i + 2));
+        estimate = obs_model
+            .update(&estimate, &observation, CovarianceUpdateMethod::JosephForm)
+            .unwrap();
+        filtered.push(estimate.clone());
+    }
+
+    let smoothed = rts_smooth(&filtered, &motion_model).unwrap();
+    assert_eq!(smoothed.len(), filtered.len());
+    assert_relative_eq!(
+        smoothed.last().unwrap().state(),
+        filtered.last().unwrap().state()
+    );
+
+    let sum_sq_err = |estimates: &[StateAndCovariance<f64, U6>]| -> f64 {
+        estimates
+            .iter()
+            .zip(truth.iter())
+            .map(|(est, pos)| (est.state().fixed_rows::<3>(0) - pos).norm_squared())
+            .sum()
+    };
+    assert!(sum_sq_err(&smoothed) < sum_sq_err(&filtered));
+    // Smoothing also reduces the uncertainty.
+    assert!(smoothed[0].covariance()[(0, 0)] < filtered[0].covariance()[(0, 0)]);
+}
+
+/// Test that doing updates every frame without observations
+/// is equal to doing an update with a longer dt.
+#[test]
+fn test_missing_frames_via_large_dt_accel3d() {
+    use nalgebra::{OMatrix, OVector, U9};
+    use tracking::accel_motion_model_3d::ConstantAcceleration3DModel;
+
+    let motion_noise_scale = 1.234;
+    let model = ConstantAcceleration3DModel::new(motion_noise_scale);
+
+    let dt1 = 5.678;
+    let state0 =
+        OVector::<f64, U9>::from_column_slice(&[1.2, 3.4, 5.6, 7.8, 9.10, 11.12, 0.1, 0.2, 0.3]);
+    let covar0 = 42.0 * OMatrix::<f64, U9, U9>::identity();
+
+    let est0 = StateAndCovariance::new(state0, covar0);
+
+    // Run two time steps of duration dt.
+    let mm1 = model.calc_for_dt(dt1);
+    let est1_1 = mm1.predict(&est0);
+    let est1_2 = mm1.predict(&est1_1);
+
+    // Run one time step of duration 2*dt.
+    let mm2 = model.calc_for_dt(2.0 * dt1);
+    let est2_2 = mm2.predict(&est0);
+
+    assert_relative_eq!(est1_2.state(), est2_2.state(), max_relative = 1e-12);
+    assert_relative_eq!(
+        est1_2.covariance(),
+        est2_2.covariance(),
+        max_relative = 1e-12
+    );
+}
+
+/// Test that the registry filters a single constant velocity model, also when
+/// given as the only model of an IMM mixer, in the original 6-D state.
+#[test]
+fn test_registry_constant_velocity_is_6d() {
+    use tracking::motion_model_3d::ConstantVelocity3DModel;
+    use tracking::motion_model_registry::{
+        MotionModelEstimate, MotionModelFixedDt, RegisteredMotionModel3D, CONSTANT_VELOCITY,
+    };
+
+    let dt = 0.01;
+    let model6 = ConstantVelocity3DModel::new(1.234).calc_for_dt(dt);
+    let registered = RegisteredMotionModel3D::new(CONSTANT_VELOCITY, 1.234).unwrap();
+    assert_eq!(registered.name(), CONSTANT_VELOCITY);
+    let single_imm = RegisteredMotionModel3D::new_imm(vec![registered.clone()], 0.1).unwrap();
+
+    let state6 = Vector6::new(1.2, 3.4, 5.6, 7.8, 9.10, 11.12);
+    let covar6 = 0.5 * Matrix6::<f64>::identity();
+    let mut expected = StateAndCovariance::new(state6, covar6);
+    for _ in 0..10 {
+        expected = model6.predict(&expected);
+    }
+
+    for model in [registered, single_imm].iter() {
+        let model = model.calc_for_dt(dt);
+        assert!(matches!(model, MotionModelFixedDt::Velocity(_)));
+        let mut est = model.initial_estimate(StateAndCovariance::new(state6, covar6));
+        for _ in 0..10 {
+            est = model.predict(&est);
+        }
+        match est {
+            MotionModelEstimate::Velocity(est) => {
+                assert_relative_eq!(est.state(), expected.state());
+                assert_relative_eq!(est.covariance(), expected.covariance());
+            }
+            other => panic!("unexpected estimate {:?}", other),
+        }
+    }
+}
+
+/// Test that a constant velocity model in the state with acceleration
+/// predicts the same position and velocity as the original model.
+#[test]
+fn test_constant_velocity_with_acceleration_matches_6d() {
+    use nalgebra::{OMatrix, OVector, U9};
+    use tracking::accel_motion_model_3d::MotionModelAccel3DFixedDt;
+    use tracking::motion_model_3d::ConstantVelocity3DModel;
+
+    let dt = 0.01;
+    let model6 = ConstantVelocity3DModel::new(1.234).calc_for_dt(dt);
+    let model9 =
+        MotionModelAccel3DFixedDt::from(ConstantVelocity3DModel::new(1.234).calc_for_dt(dt));
+
+    let state6 = Vector6::new(1.2, 3.4, 5.6, 7.8, 9.10, 11.12);
+    let mut covar6 = 0.5 * Matrix6::<f64>::identity();
+    covar6[(0, 3)] = 0.1;
+    covar6[(3, 0)] = 0.1;
+    let mut state9 = OVector::<f64, U9>::zeros();
+    state9.fixed_rows_mut::<6>(0).copy_from(&state6);
+    let mut covar9 = OMatrix::<f64, U9, U9>::zeros();
+    covar9.fixed_slice_mut::<6, 6>(0, 0).copy_from(&covar6);
+
+    let mut est6 = StateAndCovariance::new(state6, covar6);
+    let mut est9 = StateAndCovariance::new(state9, covar9);
+    for _ in 0..10 {
+        est6 = model6.predict(&est6);
+        est9 = model9.predict(&est9);
+    }
+    assert_relative_eq!(
+        est9.state().fixed_rows::<6>(0).into_owned(),
+        est6.state().clone()
+    );
+    assert_relative_eq!(
+        est9.covariance().fixed_slice::<6, 6>(0, 0).into_owned(),
+        est6.covariance().clone()
+    );
+    // The acceleration stays zero.
+    assert_eq!(est9.state()[6], 0.0);
+    assert_eq!(est9.covariance()[(6, 6)], 0.0);
+}
+
+/// Test that an IMM mixer over a constant velocity and a constant
+/// acceleration model assigns most probability to the constant acceleration
+/// model for an accelerating trajectory.
+#[test]
+fn test_imm_accelerating() {
+    use adskalman::ObservationModel;
+    use nalgebra::{OMatrix, OVector, Vector3, U2, U9};
+    use tracking::motion_model_registry::{
+        MotionModelFixedDt, RegisteredMotionModel3D, CONSTANT_ACCELERATION, CONSTANT_VELOCITY,
+    };
+
+    /// Observe two coordinates of the position directly.
+    struct TwoCoordObservationModel {
+        observation_matrix: OMatrix<f64, U2, U9>,
+        observation_matrix_transpose: OMatrix<f64, U9, U2>,
+        observation_noise_covariance: OMatrix<f64, U2, U2>,
+    }
+
+    impl TwoCoordObservationModel {
+        fn new(i: usize, j: usize) -> Self {
+            let mut observation_matrix = OMatrix::<f64, U2, U9>::zeros();
+            observation_matrix[(0, i)] = 1.0;
+            observation_matrix[(1, j)] = 1.0;
+            Self {
+                observation_matrix,
+                observation_matrix_transpose: observation_matrix.transpose(),
+                observation_noise_covariance: 1e-6 * OMatrix::<f64, U2, U2>::identity(),
+            }
+        }
+    }
+
+    impl ObservationModel<f64, U9, U2> for TwoCoordObservationModel {
+        fn H(&self) -> &OMatrix<f64, U2, U9> {
+            &self.observation_matrix
+        }
+        fn HT(&self) -> &OMatrix<f64, U9, U2> {
+            &self.observation_matrix_transpose
+        }
+        fn R(&self) -> &OMatrix<f64, U2, U2> {
+            &self.observation_noise_covariance
+        }
+        fn predict_observation(&self, state: &OVector<f64, U9>) -> OVector<f64, U2> {
+            &self.observation_matrix * state
+        }
+    }
+
+    let obs_xy = TwoCoordObservationModel::new(0, 1);
+    let obs_zx = TwoCoordObservationModel::new(2, 0);
+
+    let models = vec![
+        RegisteredMotionModel3D::new(CONSTANT_VELOCITY, 1e-4).unwrap(),
+        RegisteredMotionModel3D::new(CONSTANT_ACCELERATION, 1.0).unwrap(),
+    ];
+    let dt = 0.01;
+    let imm = match RegisteredMotionModel3D::new_imm(models, 0.05)
+        .unwrap()
+        .calc_for_dt(dt)
+    {
+        MotionModelFixedDt::Imm(imm) => imm,
+        other => panic!("unexpected model {:?}", other),
+    };
+
+    let start = Vector3::new(0.1, 0.2, 0.3);
+    let vel = Vector3::new(0.5, 0.0, -0.5);
+    let accel = Vector3::new(2.0, -1.0, 0.5);
+
+    let mut state0 = OVector::<f64, U9>::zeros();
+    state0.fixed_rows_mut::<3>(0).copy_from(&start);
+    let mut estimate = imm.initial_estimate(StateAndCovariance::new(
+        state0,
+        OMatrix::<f64, U9, U9>::identity(),
+    ));
+    assert_relative_eq!(estimate.probabilities()[0], 0.5);
+
+    for i in 0..200 {
+        let t = i as f64 * dt;
+        if i > 0 {
+            estimate = imm.predict(&estimate);
+        }
+        let pos = start + vel * t + accel * (0.5 * t * t);
+        estimate = estimate
+            .update(&obs_xy, &OVector::<f64, U2>::new(pos.x, pos.y))
+            .unwrap();
+        estimate = estimate
+            .update(&obs_zx, &OVector::<f64, U2>::new(pos.z, pos.x))
+            .unwrap();
+    }
+
+    let probabilities = estimate.probabilities();
+    assert_relative_eq!(probabilities[0] + probabilities[1], 1.0, epsilon = 1e-9);
+    assert!(probabilities[1] > 0.5);
+
+    let t = 199.0 * dt;
+    let combined = estimate.combined();
+    let pos = start + vel * t + accel * (0.5 * t * t);
+    assert!((combined.state().fixed_rows::<3>(0) - pos).norm() < 0.01);
+}
+
+#[test]
+fn test_registry_errors() {
+    use tracking::motion_model_registry::{
+        MotionModelError, RegisteredMotionModel3D, CONSTANT_VELOCITY, IMM, MOTION_MODEL_NAMES,
+    };
+
+    for name in MOTION_MODEL_NAMES.iter().filter(|name| **name != IMM) {
+        let model = RegisteredMotionModel3D::<f64>::new(name, 1.0).unwrap();
+        assert_eq!(model.name(), *name);
+    }
+
+    match RegisteredMotionModel3D::<f64>::new("no-such-model", 1.0) {
+        Err(MotionModelError::UnknownMotionModel(name)) => assert_eq!(name, "no-such-model"),
+        other => panic!("unexpected result {:?}", other),
+    }
+    assert!(RegisteredMotionModel3D::<f64>::new(IMM, 1.0).is_err());
+    assert!(RegisteredMotionModel3D::<f64>::new_imm(vec![], 0.1).is_err());
+
+    let cv = RegisteredMotionModel3D::<f64>::new(CONSTANT_VELOCITY, 1.0).unwrap();
+    assert!(RegisteredMotionModel3D::new_imm(vec![cv.clone()], 1.5).is_err());
+    let imm = RegisteredMotionModel3D::new_imm(vec![cv.clone()], 0.1).unwrap();
+    assert_eq!(imm.name(), IMM);
+    // A single mixed model is used alone and can be smoothed.
+    assert!(imm.check_smoothing().is_ok());
+    assert!(RegisteredMotionModel3D::new_imm(vec![cv.clone(), imm], 0.1).is_err());
+
+    let imm2 = RegisteredMotionModel3D::new_imm(vec![cv.clone(), cv.clone()], 0.1).unwrap();
+    match imm2.check_smoothing() {
+        Err(MotionModelError::SmoothingNotImplemented) => {}
+        other => panic!("unexpected result {:?}", other),
+    }
+    assert!(cv.check_smoothing().is_ok());
+}
+
+#[test]
+fn test_data_association_methods() {
+    use tracking::data_association::{
+        gnn_assignment, greedy_assignment, jpda_consumed_observations, jpda_probabilities,
+    };
+
+    let min_likelihood = 1e-8;
+    // Object 0 slightly prefers point 0, but point 0 is the only point for
+    // object 1. Object 2 is far from everything.
+    let likelihoods = na::DMatrix::from_row_slice(
+        3,
+        3,
+        &[
+            0.5, 0.4, 0.0, //
+            0.45, 1e-10, 0.0, //
+            0.0, 0.0, 1e-9,
+        ],
+    );
+
+    let greedy = greedy_assignment(&likelihoods, min_likelihood);
+    assert_eq!(greedy, vec![Some(0), None, None]);
+
+    let gnn = gnn_assignment(&likelihoods, min_likelihood);
+    assert_eq!(gnn, vec![Some(1), Some(0), None]);
+
+    let probs = jpda_probabilities(&likelihoods, min_likelihood).unwrap();
+    assert!(probs[(0, 1)] > 0.99);
+    assert!(probs[(1, 0)] > 0.99);
+    assert_relative_eq!(probs.row(2).sum(), 0.0);
+    for i in 0..3 {
+        assert!(probs.row(i).sum() <= 1.0 + 1e-12);
+        assert!(probs.column(i).sum() <= 1.0 + 1e-12);
+    }
+    assert_eq!(jpda_consumed_observations(&probs), vec![0, 1]);
+
+    // A single object with a single point: the point is either from the
+    // object or clutter.
+    let likelihoods = na::DMatrix::from_element(1, 1, 3e-8);
+    let probs = jpda_probabilities(&likelihoods, min_likelihood).unwrap();
+    assert_relative_eq!(probs[(0, 0)], 0.75, epsilon = 1e-12);
+    assert_eq!(jpda_consumed_observations(&probs), vec![0]);
+
+    // A single object with a likely and an unlikely point: the unlikely point
+    // is probably clutter, but it still contributes to the update of the
+    // object and thus cannot start a new object.
+    let likelihoods = na::DMatrix::from_row_slice(1, 2, &[1e-6, 1.01e-8]);
+    let probs = jpda_probabilities(&likelihoods, min_likelihood).unwrap();
+    assert!(probs[(0, 1)] > 0.0);
+    assert!(probs[(0, 1)] < 0.1);
+    assert_eq!(jpda_consumed_observations(&probs), vec![0, 1]);
+
+    // A point outside the gate of all objects remains available.
+    let likelihoods = na::DMatrix::from_row_slice(1, 2, &[1e-6, 1e-9]);
+    let probs = jpda_probabilities(&likelihoods, min_likelihood).unwrap();
+    assert_relative_eq!(probs[(0, 1)], 0.0);
+    assert_eq!(jpda_consumed_observations(&probs), vec![0]);
+}
@@ -23,6 +23,9 @@ pub type UFMFResult<M> = std::result::Result<M, UFMFError>;
 
 mod save_indices;
 
+pub mod reader;
+pub use crate::reader::UFMFReader;
+
 #[derive(Debug, thiserror::Error)]
 pub enum UFMFError {
     #[error("unimplemented pixel_format {0}")]
@@ -34,6 +37,30 @@ pub enum UFMFError {
     #[error("the pixel format changed")]
     FormatChanged,
 
+    #[error("not a ufmf file")]
+    NotUFMF,
+    #[error("unimplemented version {0}")]
+    UnimplementedVersion(u32),
+    #[error("unknown format {0}")]
+    UnknownFormat(String),
+    #[error("unknown dtype {0}")]
+    UnknownDtype(u8),
+    #[error("unexpected chunk id {0}")]
+    UnexpectedChunk(u8),
+    #[error("invalid index")]
+    InvalidIndex,
+    #[error("frame {0} out of range")]
+    FrameOutOfRange(usize),
+
+    #[error("From {path}: {source}")]
+    IoPath {
+        path: String,
+        #[source]
+        source: std::io::Error,
+        #[cfg(feature = "backtrace")]
+        backtrace: std::backtrace::Backtrace,
+    },
+
     #[error("{source}")]
     Io {
         #[from]
@@ -169,6 +196,7 @@ impl RectFromCenter {
 }
 
 /// Specifies a rectangular region, drawn from lower-left.
+#[derive(Debug, Clone, PartialEq)]
 pub struct RectFromCorner {
     /// x lower left of region
     x0: u16,
@@ -180,6 +208,25 @@ pub struct RectFromCorner {
     h: u16,
 }
 
+impl RectFromCorner {
+    /// x lower left of region
+    pub fn x0(&self) -> u16 {
+        self.x0
+    }
+    /// y lower left of region
+    pub fn y0(&self) -> u16 {
+        self.y0
+    }
+    /// width of region
+    pub fn w(&self) -> u16 {
+        self.w
+    }
+    /// height of region
+    pub fn h(&self) -> u16 {
+        self.h
+    }
+}
+
 struct Region<'a> {
     origframe: &'a DynamicFrame,
     rect: &'a RectFromCorner,
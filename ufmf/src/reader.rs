@@ -0,0 +1,875 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use byteorder::{LittleEndian, ReadBytesExt};
+
+use basic_frame::{BasicExtra, DynamicFrame};
+use datetime_conversion::f64_to_datetime;
+use formats::pixel_format::PixFmt;
+
+use crate::{RectFromCorner, UFMFError, UFMFResult, FRAME_CHUNK, INDEX_DICT_CHUNK, KEYFRAME_CHUNK};
+
+/// The keyframe types searched, in order, for a background image when
+/// reconstructing full frames.
+const DEFAULT_BACKGROUND_TYPES: &[&[u8]] = &[b"mean", b"frame0"];
+
+/// Location and timestamp of one chunk, as listed in the file index.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IndexEntry {
+    /// timestamp of the chunk
+    pub timestamp: f64,
+    /// byte offset of the chunk from the start of the file
+    pub loc: u64,
+}
+
+/// The image data of a single region saved with a frame.
+#[derive(Debug, Clone)]
+pub struct RegionImage {
+    /// location of the region within the full frame
+    pub rect: RectFromCorner,
+    /// raw image data with stride `rect.w() * bytes_per_pixel`
+    pub image_data: Vec<u8>,
+}
+
+/// All regions saved for one frame.
+#[derive(Debug, Clone)]
+pub struct FrameRegions {
+    /// timestamp of the frame
+    pub timestamp: f64,
+    /// the saved regions
+    pub regions: Vec<RegionImage>,
+}
+
+/// Reads UFMF (micro fly movie format) movie files.
+///
+/// The file index written by [crate::UFMFWriter::close] is parsed when opening.
+/// If the index is missing (e.g. because the writer was never closed), the
+/// chunks of the file are scanned to rebuild it.
+///
+/// Iterating over the reader returns full frames, which are reconstructed by
+/// pasting the saved regions onto the most recent background keyframe. Errors
+/// while reading a frame are returned, so a corrupt file can be distinguished
+/// from the end of the file.
+pub struct UFMFReader<R: Read + Seek> {
+    f: R,
+    pixel_format: PixFmt,
+    max_width: u16,
+    max_height: u16,
+    bytes_per_pixel: usize,
+    index_frame: Vec<IndexEntry>,
+    index_keyframes: BTreeMap<Vec<u8>, Vec<IndexEntry>>,
+    background_type: Option<Vec<u8>>,
+    /// Cache of the most recently decoded background keyframe, keyed by its
+    /// location in the file.
+    background_cache: Option<(u64, Vec<u8>)>,
+    count: usize,
+}
+
+impl<R: Read + Seek> std::fmt::Debug for UFMFReader<R> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "UFMFReader {{ pixel_format: {}, max_width: {}, max_height: {}, n_frames: {} }}",
+            self.pixel_format,
+            self.max_width,
+            self.max_height,
+            self.index_frame.len()
+        )
+    }
+}
+
+impl UFMFReader<BufReader<File>> {
+    /// Open the file at `path` for reading.
+    pub fn open<P: AsRef<Path>>(path: P) -> UFMFResult<Self> {
+        let f = File::open(&path).map_err(|e| UFMFError::IoPath {
+            source: e,
+            path: path.as_ref().display().to_string(),
+            #[cfg(feature = "backtrace")]
+            backtrace: std::backtrace::Backtrace::capture(),
+        })?;
+        Self::new(BufReader::new(f))
+    }
+}
+
+impl<R: Read + Seek> UFMFReader<R> {
+    /// Create a reader from an already-opened file.
+    pub fn new(mut f: R) -> UFMFResult<Self> {
+        f.seek(SeekFrom::Start(0))?;
+
+        let mut magic = [0u8; 4];
+        f.read_exact(&mut magic)?;
+        if &magic != b"ufmf" {
+            return Err(UFMFError::NotUFMF);
+        }
+        let version = f.read_u32::<LittleEndian>()?;
+        if version != 3 {
+            return Err(UFMFError::UnimplementedVersion(version));
+        }
+        let index_loc = f.read_u64::<LittleEndian>()?;
+        let max_width = f.read_u16::<LittleEndian>()?;
+        let max_height = f.read_u16::<LittleEndian>()?;
+        let coding_len = f.read_u8()?;
+        let mut coding = vec![0; coding_len as usize];
+        f.read_exact(&mut coding)?;
+        let pixel_format = get_pixel_format(&coding)?;
+        let header_end = f.seek(SeekFrom::Current(0))?;
+
+        let bytes_per_pixel = (pixel_format.bits_per_pixel() / 8) as usize;
+
+        let (index_frame, index_keyframes) = if index_loc == 0 {
+            scan_chunks(&mut f, header_end, bytes_per_pixel)?
+        } else {
+            f.seek(SeekFrom::Start(index_loc))?;
+            read_index(&mut f)?
+        };
+
+        Ok(Self {
+            f,
+            pixel_format,
+            max_width,
+            max_height,
+            bytes_per_pixel,
+            index_frame,
+            index_keyframes,
+            background_type: None,
+            background_cache: None,
+            count: 0,
+        })
+    }
+
+    #[inline]
+    pub fn width(&self) -> u32 {
+        self.max_width as u32
+    }
+
+    #[inline]
+    pub fn height(&self) -> u32 {
+        self.max_height as u32
+    }
+
+    #[inline]
+    pub fn format(&self) -> PixFmt {
+        self.pixel_format
+    }
+
+    /// The number of frames in the file.
+    #[inline]
+    pub fn frame_count(&self) -> usize {
+        self.index_frame.len()
+    }
+
+    /// The index of all frames in the file.
+    pub fn frame_index(&self) -> &[IndexEntry] {
+        &self.index_frame
+    }
+
+    /// The names of all keyframe types saved in the file (e.g. `b"mean"`).
+    pub fn keyframe_types(&self) -> impl Iterator<Item = &[u8]> {
+        self.index_keyframes.keys().map(|k| k.as_slice())
+    }
+
+    /// The index of all keyframes of type `keyframe_type`.
+    pub fn keyframe_index(&self, keyframe_type: &[u8]) -> &[IndexEntry] {
+        match self.index_keyframes.get(keyframe_type) {
+            Some(idx) => idx.as_slice(),
+            None => &[],
+        }
+    }
+
+    /// Set the keyframe type used as background for full frame reconstruction.
+    ///
+    /// By default, `mean` keyframes are used if present, otherwise `frame0`.
+    pub fn set_background_type(&mut self, keyframe_type: Option<&[u8]>) {
+        self.background_type = keyframe_type.map(|x| x.to_vec());
+        self.background_cache = None;
+    }
+
+    /// Read keyframe number `n` of type `keyframe_type`.
+    ///
+    /// Keyframes saved as floating point (e.g. the running mean from
+    /// image-tracker) are returned with the matching `32f` pixel format.
+    pub fn get_keyframe(&mut self, keyframe_type: &[u8], n: usize) -> UFMFResult<DynamicFrame> {
+        let entry = *self
+            .keyframe_index(keyframe_type)
+            .get(n)
+            .ok_or(UFMFError::FrameOutOfRange(n))?;
+        let keyframe = self.read_keyframe_at(entry.loc)?;
+        let (pixel_format, bytes_per_element) = match keyframe.dtype {
+            b'B' => (self.pixel_format, self.bytes_per_pixel),
+            b'f' => (float_pixel_format(self.pixel_format)?, 4),
+            dtype => return Err(UFMFError::UnknownDtype(dtype)),
+        };
+        let stride = (keyframe.width as usize * bytes_per_element) as u32;
+        let extra = Box::new(BasicExtra {
+            host_timestamp: f64_to_datetime(keyframe.timestamp).with_timezone(&chrono::Utc),
+            host_framenumber: n,
+        });
+        Ok(DynamicFrame::new(
+            keyframe.width as u32,
+            keyframe.height as u32,
+            stride,
+            extra,
+            keyframe.image_data,
+            pixel_format,
+        ))
+    }
+
+    /// Read the regions saved with frame number `n`.
+    pub fn get_frame_regions(&mut self, n: usize) -> UFMFResult<FrameRegions> {
+        let entry = *self
+            .index_frame
+            .get(n)
+            .ok_or(UFMFError::FrameOutOfRange(n))?;
+        self.f.seek(SeekFrom::Start(entry.loc))?;
+        let chunk_id = self.f.read_u8()?;
+        if chunk_id != FRAME_CHUNK {
+            return Err(UFMFError::UnexpectedChunk(chunk_id));
+        }
+        read_frame_chunk(&mut self.f, self.bytes_per_pixel)
+    }
+
+    /// Read frame number `n` as a full frame.
+    ///
+    /// The regions saved with the frame are pasted onto the latest background
+    /// keyframe which precedes the frame in the file. If no background
+    /// keyframe exists, the regions are pasted onto a black image. Parts of
+    /// regions outside the frame are ignored.
+    pub fn get_frame(&mut self, n: usize) -> UFMFResult<DynamicFrame> {
+        let entry = *self
+            .index_frame
+            .get(n)
+            .ok_or(UFMFError::FrameOutOfRange(n))?;
+        let frame_regions = self.get_frame_regions(n)?;
+
+        let width = self.max_width as usize;
+        let height = self.max_height as usize;
+        let stride = width * self.bytes_per_pixel;
+
+        let mut image_data = match self.background_before(entry.loc)? {
+            Some(bg) => bg,
+            None => vec![0; stride * height],
+        };
+
+        for region in frame_regions.regions.iter() {
+            let rect = &region.rect;
+            let (x0, y0) = (rect.x0 as usize, rect.y0 as usize);
+            // Clip the region to the frame.
+            let w = (rect.w as usize).min(width.saturating_sub(x0));
+            let h = (rect.h as usize).min(height.saturating_sub(y0));
+            if w == 0 || h == 0 {
+                continue;
+            }
+            let src_stride = rect.w as usize * self.bytes_per_pixel;
+            let row_bytes = w * self.bytes_per_pixel;
+            let x_offset = x0 * self.bytes_per_pixel;
+            for (i, src_row) in region
+                .image_data
+                .chunks_exact(src_stride)
+                .take(h)
+                .enumerate()
+            {
+                let start = (y0 + i) * stride + x_offset;
+                image_data[start..start + row_bytes].copy_from_slice(&src_row[..row_bytes]);
+            }
+        }
+
+        let extra = Box::new(BasicExtra {
+            host_timestamp: f64_to_datetime(frame_regions.timestamp).with_timezone(&chrono::Utc),
+            host_framenumber: n,
+        });
+        Ok(DynamicFrame::new(
+            width as u32,
+            height as u32,
+            stride as u32,
+            extra,
+            image_data,
+            self.pixel_format,
+        ))
+    }
+
+    /// Position the reader such that the next frame returned is frame `n`.
+    pub fn seek_to_frame(&mut self, n: usize) -> UFMFResult<()> {
+        if n > self.index_frame.len() {
+            return Err(UFMFError::FrameOutOfRange(n));
+        }
+        self.count = n;
+        Ok(())
+    }
+
+    /// Find the frame with the latest timestamp not after `timestamp`.
+    pub fn frame_at_timestamp(&self, timestamp: f64) -> Option<usize> {
+        let n_before = self
+            .index_frame
+            .partition_point(|entry| entry.timestamp <= timestamp);
+        n_before.checked_sub(1)
+    }
+
+    /// Find the frame with the earliest timestamp not before `timestamp`.
+    pub fn frame_from_timestamp(&self, timestamp: f64) -> Option<usize> {
+        let n_before = self
+            .index_frame
+            .partition_point(|entry| entry.timestamp < timestamp);
+        if n_before < self.index_frame.len() {
+            Some(n_before)
+        } else {
+            None
+        }
+    }
+
+    /// Return the background image (converted to the file's pixel format)
+    /// from the last background keyframe located before `loc`.
+    fn background_before(&mut self, loc: u64) -> UFMFResult<Option<Vec<u8>>> {
+        let keyframe_type: Vec<u8> = match &self.background_type {
+            Some(t) => t.clone(),
+            None => match DEFAULT_BACKGROUND_TYPES
+                .iter()
+                .find(|t| self.index_keyframes.contains_key(**t))
+            {
+                Some(t) => t.to_vec(),
+                None => return Ok(None),
+            },
+        };
+
+        let idx = self.keyframe_index(&keyframe_type);
+        let entry = match idx.iter().rev().find(|e| e.loc < loc) {
+            Some(e) => *e,
+            None => match idx.first() {
+                Some(e) => *e,
+                None => return Ok(None),
+            },
+        };
+
+        if let Some((cached_loc, data)) = &self.background_cache {
+            if *cached_loc == entry.loc {
+                return Ok(Some(data.clone()));
+            }
+        }
+
+        let keyframe = self.read_keyframe_at(entry.loc)?;
+        if keyframe.width != self.max_width || keyframe.height != self.max_height {
+            return Err(UFMFError::FormatChanged);
+        }
+        let data = match keyframe.dtype {
+            b'B' => keyframe.image_data,
+            b'f' => {
+                if self.bytes_per_pixel != 1 {
+                    return Err(UFMFError::UnimplementedPixelFormat(self.pixel_format));
+                }
+                keyframe
+                    .image_data
+                    .chunks_exact(4)
+                    .map(|b| {
+                        let v = f32::from_le_bytes([b[0], b[1], b[2], b[3]]);
+                        v.round().max(0.0).min(255.0) as u8
+                    })
+                    .collect()
+            }
+            dtype => return Err(UFMFError::UnknownDtype(dtype)),
+        };
+        self.background_cache = Some((entry.loc, data.clone()));
+        Ok(Some(data))
+    }
+
+    fn read_keyframe_at(&mut self, loc: u64) -> UFMFResult<RawKeyframe> {
+        self.f.seek(SeekFrom::Start(loc))?;
+        let chunk_id = self.f.read_u8()?;
+        if chunk_id != KEYFRAME_CHUNK {
+            return Err(UFMFError::UnexpectedChunk(chunk_id));
+        }
+        read_keyframe_chunk(&mut self.f, self.bytes_per_pixel)
+    }
+}
+
+impl<R: Read + Seek> Iterator for UFMFReader<R> {
+    type Item = UFMFResult<DynamicFrame>;
+    fn next(&mut self) -> Option<Self::Item> {
+        let n = self.count;
+        if n >= self.index_frame.len() {
+            return None;
+        }
+        self.count += 1;
+        Some(self.get_frame(n))
+    }
+}
+
+struct RawKeyframe {
+    keyframe_type: Vec<u8>,
+    dtype: u8,
+    width: u16,
+    height: u16,
+    timestamp: f64,
+    image_data: Vec<u8>,
+}
+
+/// Read a keyframe chunk. The chunk id must already have been read.
+fn read_keyframe_chunk<R: Read>(f: &mut R, bytes_per_pixel: usize) -> UFMFResult<RawKeyframe> {
+    let type_len = f.read_u8()?;
+    let mut keyframe_type = vec![0; type_len as usize];
+    f.read_exact(&mut keyframe_type)?;
+    let dtype = f.read_u8()?;
+    let width = f.read_u16::<LittleEndian>()?;
+    let height = f.read_u16::<LittleEndian>()?;
+    let timestamp = f.read_f64::<LittleEndian>()?;
+    let bytes_per_element = match dtype {
+        b'B' => bytes_per_pixel,
+        b'f' => 4,
+        dtype => return Err(UFMFError::UnknownDtype(dtype)),
+    };
+    let mut image_data = vec![0; width as usize * height as usize * bytes_per_element];
+    f.read_exact(&mut image_data)?;
+    Ok(RawKeyframe {
+        keyframe_type,
+        dtype,
+        width,
+        height,
+        timestamp,
+        image_data,
+    })
+}
+
+/// Read a frame chunk. The chunk id must already have been read.
+fn read_frame_chunk<R: Read>(f: &mut R, bytes_per_pixel: usize) -> UFMFResult<FrameRegions> {
+    let timestamp = f.read_f64::<LittleEndian>()?;
+    let n_pts = f.read_u16::<LittleEndian>()?;
+    let mut regions = Vec::with_capacity(n_pts as usize);
+    for _ in 0..n_pts {
+        let x0 = f.read_u16::<LittleEndian>()?;
+        let y0 = f.read_u16::<LittleEndian>()?;
+        let w = f.read_u16::<LittleEndian>()?;
+        let h = f.read_u16::<LittleEndian>()?;
+        let mut image_data = vec![0; w as usize * h as usize * bytes_per_pixel];
+        f.read_exact(&mut image_data)?;
+        regions.push(RegionImage {
+            rect: RectFromCorner { x0, y0, w, h },
+            image_data,
+        });
+    }
+    Ok(FrameRegions { timestamp, regions })
+}
+
+type Indices = (Vec<IndexEntry>, BTreeMap<Vec<u8>, Vec<IndexEntry>>);
+
+/// Rebuild the index by reading every chunk, starting at `pos`.
+///
+/// This is used for files in which the index was never written.
+fn scan_chunks<R: Read + Seek>(f: &mut R, pos: u64, bytes_per_pixel: usize) -> UFMFResult<Indices> {
+    let mut index_frame = Vec::new();
+    let mut index_keyframes: BTreeMap<Vec<u8>, Vec<IndexEntry>> = BTreeMap::new();
+    let mut loc = f.seek(SeekFrom::Start(pos))?;
+    loop {
+        let chunk_id = match f.read_u8() {
+            Ok(chunk_id) => chunk_id,
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e.into()),
+        };
+        // A truncated final chunk is not an error: the file was likely not
+        // closed properly, so we keep everything before it.
+        match chunk_id {
+            FRAME_CHUNK => match read_frame_chunk(f, bytes_per_pixel) {
+                Ok(regions) => index_frame.push(IndexEntry {
+                    timestamp: regions.timestamp,
+                    loc,
+                }),
+                Err(UFMFError::Io { ref source, .. })
+                    if source.kind() == std::io::ErrorKind::UnexpectedEof =>
+                {
+                    break
+                }
+                Err(e) => return Err(e),
+            },
+            KEYFRAME_CHUNK => match read_keyframe_chunk(f, bytes_per_pixel) {
+                Ok(keyframe) => index_keyframes
+                    .entry(keyframe.keyframe_type)
+                    .or_insert_with(Vec::new)
+                    .push(IndexEntry {
+                        timestamp: keyframe.timestamp,
+                        loc,
+                    }),
+                Err(UFMFError::Io { ref source, .. })
+                    if source.kind() == std::io::ErrorKind::UnexpectedEof =>
+                {
+                    break
+                }
+                Err(e) => return Err(e),
+            },
+            INDEX_DICT_CHUNK => break,
+            other => return Err(UFMFError::UnexpectedChunk(other)),
+        }
+        loc = f.seek(SeekFrom::Current(0))?;
+    }
+    Ok((index_frame, index_keyframes))
+}
+
+/// A value in the index dictionary saved at the end of the file.
+enum IndexValue {
+    Dict(BTreeMap<Vec<u8>, IndexValue>),
+    Locs(Vec<u64>),
+    Timestamps(Vec<f64>),
+}
+
+/// Read the index. `f` must be positioned just after the index chunk id, which
+/// is the index location saved in the header.
+fn read_index<R: Read>(f: &mut R) -> UFMFResult<Indices> {
+    let mut index = match read_index_value(f)? {
+        IndexValue::Dict(d) => d,
+        _ => return Err(UFMFError::InvalidIndex),
+    };
+
+    let index_frame = match index.remove(&b"frame"[..]) {
+        Some(value) => index_entries(value)?,
+        None => Vec::new(),
+    };
+
+    let mut index_keyframes = BTreeMap::new();
+    match index.remove(&b"keyframe"[..]) {
+        Some(IndexValue::Dict(d)) => {
+            for (keyframe_type, value) in d.into_iter() {
+                index_keyframes.insert(keyframe_type, index_entries(value)?);
+            }
+        }
+        Some(_) => return Err(UFMFError::InvalidIndex),
+        None => {}
+    }
+
+    Ok((index_frame, index_keyframes))
+}
+
+/// Convert a dict with `loc` and `timestamp` arrays into index entries.
+fn index_entries(value: IndexValue) -> UFMFResult<Vec<IndexEntry>> {
+    let mut d = match value {
+        IndexValue::Dict(d) => d,
+        _ => return Err(UFMFError::InvalidIndex),
+    };
+    if d.is_empty() {
+        return Ok(Vec::new());
+    }
+    let locs = match d.remove(&b"loc"[..]) {
+        Some(IndexValue::Locs(x)) => x,
+        _ => return Err(UFMFError::InvalidIndex),
+    };
+    let timestamps = match d.remove(&b"timestamp"[..]) {
+        Some(IndexValue::Timestamps(x)) => x,
+        _ => return Err(UFMFError::InvalidIndex),
+    };
+    if locs.len() != timestamps.len() {
+        return Err(UFMFError::InvalidIndex);
+    }
+    Ok(locs
+        .into_iter()
+        .zip(timestamps.into_iter())
+        .map(|(loc, timestamp)| IndexEntry { timestamp, loc })
+        .collect())
+}
+
+fn read_index_value<R: Read>(f: &mut R) -> UFMFResult<IndexValue> {
+    match f.read_u8()? {
+        b'd' => {
+            let n_keys = f.read_u8()?;
+            let mut d = BTreeMap::new();
+            for _ in 0..n_keys {
+                let key_len = f.read_u16::<LittleEndian>()?;
+                let mut key = vec![0; key_len as usize];
+                f.read_exact(&mut key)?;
+                let value = read_index_value(f)?;
+                d.insert(key, value);
+            }
+            Ok(IndexValue::Dict(d))
+        }
+        b'a' => {
+            let dtype_char = f.read_u8()?;
+            let n_bytes = f.read_u32::<LittleEndian>()? as usize;
+            match dtype_char {
+                // Python may save locations as any integer type.
+                b'l' | b'L' | b'q' | b'Q' => {
+                    let mut locs = Vec::with_capacity(n_bytes / 8);
+                    for _ in 0..n_bytes / 8 {
+                        locs.push(f.read_u64::<LittleEndian>()?);
+                    }
+                    Ok(IndexValue::Locs(locs))
+                }
+                b'i' | b'I' => {
+                    let mut locs = Vec::with_capacity(n_bytes / 4);
+                    for _ in 0..n_bytes / 4 {
+                        locs.push(f.read_u32::<LittleEndian>()? as u64);
+                    }
+                    Ok(IndexValue::Locs(locs))
+                }
+                b'd' => {
+                    let mut timestamps = Vec::with_capacity(n_bytes / 8);
+                    for _ in 0..n_bytes / 8 {
+                        timestamps.push(f.read_f64::<LittleEndian>()?);
+                    }
+                    Ok(IndexValue::Timestamps(timestamps))
+                }
+                dtype => Err(UFMFError::UnknownDtype(dtype)),
+            }
+        }
+        _ => Err(UFMFError::InvalidIndex),
+    }
+}
+
+fn get_pixel_format(coding: &[u8]) -> UFMFResult<PixFmt> {
+    use PixFmt::*;
+    let r = match coding {
+        b"MONO8" => Mono8,
+        b"RAW8:RGGB" => BayerRG8,
+        b"RAW8:GBRG" => BayerGB8,
+        b"RAW8:GRBG" => BayerGR8,
+        b"RAW8:BGGR" => BayerBG8,
+        b"YUV422" => YUV422,
+        b"RGB8" => RGB8,
+        f => {
+            return Err(UFMFError::UnknownFormat(
+                String::from_utf8_lossy(f).into_owned(),
+            ));
+        }
+    };
+    Ok(r)
+}
+
+/// Get the floating point pixel format used for keyframes saved as `f` dtype.
+fn float_pixel_format(pixel_format: PixFmt) -> UFMFResult<PixFmt> {
+    use PixFmt::*;
+    let r = match pixel_format {
+        Mono8 => Mono32f,
+        BayerRG8 => BayerRG32f,
+        BayerGB8 => BayerGB32f,
+        BayerGR8 => BayerGR32f,
+        BayerBG8 => BayerBG32f,
+        f => {
+            return Err(UFMFError::UnimplementedPixelFormat(f));
+        }
+    };
+    Ok(r)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{RectFromCenter, UFMFWriter};
+    use basic_frame::BasicFrame;
+    use formats::{ImageData, Stride};
+    use machine_vision_formats::pixel_format::{Mono32f, Mono8};
+    use timestamped_frame::ExtraTimeData;
+
+    fn arange(start: u8, timestamp: f64) -> DynamicFrame {
+        let image_data = (0..100).map(|i| start + i as u8).collect();
+        let extra = Box::new(BasicExtra {
+            host_timestamp: f64_to_datetime(timestamp).with_timezone(&chrono::Utc),
+            host_framenumber: 0,
+        });
+        DynamicFrame::Mono8(BasicFrame {
+            width: 10,
+            height: 10,
+            stride: 10,
+            image_data,
+            pixel_format: std::marker::PhantomData,
+            extra,
+        })
+    }
+
+    fn small_ufmf() -> Vec<u8> {
+        let frame0 = arange(0, 123.456);
+        let f = std::io::Cursor::new(Vec::new());
+        let mut writer = UFMFWriter::new(f, 10, 10, PixFmt::Mono8, Some(&frame0)).unwrap();
+        let point_data = vec![
+            RectFromCenter::from_xy_wh(0, 0, 4, 4),
+            RectFromCenter::from_xy_wh(4, 4, 4, 4),
+            RectFromCenter::from_xy_wh(9, 9, 4, 4),
+        ];
+        writer.add_frame(&arange(100, 124.0), &point_data).unwrap();
+        writer.add_frame(&arange(100, 125.0), &vec![]).unwrap();
+        writer.close().unwrap().into_inner()
+    }
+
+    fn expected_pixel(row: usize, col: usize) -> u8 {
+        let in_rect = |x0: usize, y0: usize| col >= x0 && col < x0 + 4 && row >= y0 && row < y0 + 4;
+        let base = (row * 10 + col) as u8;
+        if in_rect(0, 0) || in_rect(2, 2) || in_rect(6, 6) {
+            base + 100
+        } else {
+            base
+        }
+    }
+
+    #[test]
+    fn test_read_index() {
+        let reader = UFMFReader::new(std::io::Cursor::new(small_ufmf())).unwrap();
+        assert_eq!(reader.width(), 10);
+        assert_eq!(reader.height(), 10);
+        assert_eq!(reader.format(), PixFmt::Mono8);
+        assert_eq!(reader.frame_count(), 2);
+        let types: Vec<&[u8]> = reader.keyframe_types().collect();
+        assert_eq!(types, vec![&b"frame0"[..]]);
+        assert_eq!(reader.keyframe_index(b"frame0")[0].timestamp, 123.456);
+        assert_eq!(reader.frame_at_timestamp(124.5), Some(0));
+        assert_eq!(reader.frame_at_timestamp(1.0), None);
+        assert_eq!(reader.frame_from_timestamp(124.0), Some(0));
+        assert_eq!(reader.frame_from_timestamp(124.5), Some(1));
+        assert_eq!(reader.frame_from_timestamp(125.5), None);
+    }
+
+    #[test]
+    fn test_read_regions() {
+        let mut reader = UFMFReader::new(std::io::Cursor::new(small_ufmf())).unwrap();
+        let frame_regions = reader.get_frame_regions(0).unwrap();
+        assert_eq!(frame_regions.timestamp, 124.0);
+        assert_eq!(frame_regions.regions.len(), 3);
+        let region = &frame_regions.regions[1];
+        assert_eq!(region.rect.x0(), 2);
+        assert_eq!(region.rect.y0(), 2);
+        assert_eq!(region.image_data[0], 122);
+
+        let frame_regions = reader.get_frame_regions(1).unwrap();
+        assert_eq!(frame_regions.regions.len(), 0);
+        assert!(reader.get_frame_regions(2).is_err());
+    }
+
+    #[test]
+    fn test_reconstruct_frames() {
+        let reader = UFMFReader::new(std::io::Cursor::new(small_ufmf())).unwrap();
+        let frames: Vec<DynamicFrame> = reader.collect::<UFMFResult<_>>().unwrap();
+        assert_eq!(frames.len(), 2);
+
+        let frame = frames[0].clone().into_basic::<Mono8>().unwrap();
+        assert_eq!(frame.extra().host_framenumber(), 0);
+        for row in 0..10 {
+            for col in 0..10 {
+                let actual = frame.image_data()[row * frame.stride() + col];
+                assert_eq!(actual, expected_pixel(row, col), "at {},{}", row, col);
+            }
+        }
+
+        // The second frame has no regions, so it is the background.
+        let frame = frames[1].clone().into_basic::<Mono8>().unwrap();
+        let expected: Vec<u8> = (0..100).collect();
+        assert_eq!(frame.image_data(), &expected[..]);
+    }
+
+    #[test]
+    fn test_float_keyframe() {
+        let running_mean = BasicFrame::<Mono32f> {
+            width: 10,
+            height: 10,
+            stride: 40,
+            image_data: (0..100)
+                .flat_map(|i| (i as f32 + 0.4).to_le_bytes().to_vec())
+                .collect(),
+            pixel_format: std::marker::PhantomData,
+            extra: Box::new(BasicExtra {
+                host_timestamp: f64_to_datetime(123.0).with_timezone(&chrono::Utc),
+                host_framenumber: 0,
+            }),
+        };
+
+        let f = std::io::Cursor::new(Vec::new());
+        let mut writer = UFMFWriter::new(f, 10, 10, PixFmt::Mono8, None).unwrap();
+        writer.add_keyframe(b"mean", &running_mean).unwrap();
+        writer.add_frame(&arange(100, 124.0), &vec![]).unwrap();
+        let buf = writer.close().unwrap().into_inner();
+
+        let mut reader = UFMFReader::new(std::io::Cursor::new(buf)).unwrap();
+        let mean = reader.get_keyframe(b"mean", 0).unwrap();
+        assert_eq!(mean.pixel_format(), PixFmt::Mono32f);
+
+        // The float background is rounded when reconstructing a frame.
+        let frame = reader.get_frame(0).unwrap().into_basic::<Mono8>().unwrap();
+        let expected: Vec<u8> = (0..100).collect();
+        assert_eq!(frame.image_data(), &expected[..]);
+    }
+
+    #[test]
+    fn test_region_past_edge() {
+        let mut buf = small_ufmf();
+        let reader = UFMFReader::new(std::io::Cursor::new(buf.clone())).unwrap();
+        let loc = reader.frame_index()[0].loc as usize;
+        // Skip the chunk id, timestamp, number of regions and the first two
+        // regions (4x4 pixels each) to get to the header of the last region.
+        let region_loc = loc + 1 + 8 + 2 + 2 * (8 + 16);
+        let rect: Vec<u16> = buf[region_loc..region_loc + 8]
+            .chunks_exact(2)
+            .map(|b| u16::from_le_bytes([b[0], b[1]]))
+            .collect();
+        assert_eq!(rect, vec![6, 6, 4, 4]);
+        // Move the region such that it extends 2 pixels past the right and
+        // bottom edges.
+        buf[region_loc..region_loc + 2].copy_from_slice(&8u16.to_le_bytes());
+        buf[region_loc + 2..region_loc + 4].copy_from_slice(&8u16.to_le_bytes());
+
+        let mut reader = UFMFReader::new(std::io::Cursor::new(buf)).unwrap();
+        let frame = reader.get_frame(0).unwrap().into_basic::<Mono8>().unwrap();
+        let data = frame.image_data();
+        // The top-left 2x2 pixels of the region are within the frame.
+        assert_eq!(data[8 * 10 + 8], 166);
+        assert_eq!(data[9 * 10 + 9], 177);
+        assert_eq!(data[9 * 10 + 7], 97);
+    }
+
+    #[test]
+    fn test_corrupt_frame() {
+        let mut buf = small_ufmf();
+        let reader = UFMFReader::new(std::io::Cursor::new(buf.clone())).unwrap();
+        let loc = reader.frame_index()[1].loc as usize;
+        // Overwrite the chunk id of the second frame.
+        buf[loc] = 99;
+
+        let reader = UFMFReader::new(std::io::Cursor::new(buf)).unwrap();
+        let frames: Vec<UFMFResult<DynamicFrame>> = reader.collect();
+        assert_eq!(frames.len(), 2);
+        assert!(frames[0].is_ok());
+        match frames[1] {
+            Err(UFMFError::UnexpectedChunk(99)) => {}
+            ref other => panic!("unexpected result {:?}", other.as_ref().map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn test_file_round_trip() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let path = tmpdir.path().join("round_trip.ufmf");
+        {
+            let frame0 = arange(0, 10.0);
+            let f = std::fs::File::create(&path).unwrap();
+            let mut writer = UFMFWriter::new(f, 10, 10, PixFmt::Mono8, Some(&frame0)).unwrap();
+            for i in 1..4 {
+                let regions = vec![RectFromCenter::from_xy_wh(4, 4, 4, 4)];
+                writer
+                    .add_frame(&arange(100, 10.0 + i as f64), &regions)
+                    .unwrap();
+            }
+            writer.close().unwrap();
+        }
+
+        let reader = UFMFReader::open(&path).unwrap();
+        assert_eq!(reader.frame_count(), 3);
+        assert_eq!(reader.keyframe_index(b"frame0").len(), 1);
+        let timestamps: Vec<f64> = reader.frame_index().iter().map(|e| e.timestamp).collect();
+        assert_eq!(timestamps, vec![11.0, 12.0, 13.0]);
+        let frames: Vec<DynamicFrame> = reader.collect::<UFMFResult<_>>().unwrap();
+        assert_eq!(frames.len(), 3);
+        for frame in frames.into_iter() {
+            let frame = frame.into_basic::<Mono8>().unwrap();
+            // Inside the region, the pixels are from the added frame.
+            assert_eq!(frame.image_data()[3 * 10 + 3], 133);
+            // Outside, they are from the background.
+            assert_eq!(frame.image_data()[0], 0);
+        }
+    }
+
+    #[test]
+    fn test_missing_index() {
+        let mut buf = small_ufmf();
+        // Zero the index location in the header to simulate a file which was
+        // never closed.
+        for b in buf[8..16].iter_mut() {
+            *b = 0;
+        }
+        let mut reader = UFMFReader::new(std::io::Cursor::new(buf)).unwrap();
+        assert_eq!(reader.frame_count(), 2);
+        assert_eq!(reader.keyframe_index(b"frame0").len(), 1);
+        let frame = reader.get_frame(0).unwrap().into_basic::<Mono8>().unwrap();
+        assert_eq!(frame.image_data()[0], expected_pixel(0, 0));
+    }
+}